@@ -0,0 +1,209 @@
+//! Playing back VMD motions on a model.
+//!
+//! [`Animator`] pairs the bone and morph keyframes of a [`Vmd`] with the bones and morphs of a
+//! [`Pmx`] by name and samples them at any point in time. Between two keyframes each channel
+//! follows the bezier curve stored with the later one: the translation per axis, the rotation as
+//! a whole. Morph weights are interpolated linearly, as VMD stores no curves for them.
+//!
+//! Time is measured in frames, MMD plays motions at 30 frames per second. Before the first
+//! keyframe of a track it holds the first value, after the last one the last value.
+
+use std::collections::HashMap;
+
+use glam::{Quat, Vec3};
+
+use crate::{
+    pmx::Pmx,
+    skin::Pose,
+    types::MorphIndex,
+    util::{decode_shift_jis, encode_shift_jis},
+    vmd::{BONE_NAME_LEN, BoneInterpolation, MORPH_NAME_LEN, Vmd},
+};
+
+/// The keyframes of a motion bound to the bones and morphs of a model, see the
+/// [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct Animator {
+    bones: Vec<(usize, Vec<BoneKey>)>,
+    morphs: Vec<(MorphIndex, Vec<MorphKey>)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BoneKey {
+    frame: u32,
+    translation: Vec3,
+    rotation: Quat,
+    interpolation: BoneInterpolation,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MorphKey {
+    frame: u32,
+    weight: f32,
+}
+
+impl Animator {
+    /// Binds the keyframes of `vmd` to the bones and morphs of `pmx`.
+    ///
+    /// VMD names are cut off after 15 bytes of Shift-JIS, so model names are compared the same
+    /// way. Keyframes for bones and morphs the model does not have are skipped, and of several
+    /// keyframes on the same frame the last one is kept.
+    pub fn new(pmx: &Pmx, vmd: &Vmd) -> Self {
+        let bone_names = names(
+            pmx.bones.inner.iter().map(|b| b.name.local.as_str()),
+            BONE_NAME_LEN,
+        );
+        let morph_names = names(
+            pmx.morphs.inner.iter().map(|m| m.name.local.as_str()),
+            MORPH_NAME_LEN,
+        );
+
+        let mut bones: HashMap<usize, Vec<BoneKey>> = HashMap::new();
+        for frame in vmd.bone_frames() {
+            if let Some(&bone) = bone_names.get(frame.bone()) {
+                bones.entry(bone).or_default().push(BoneKey {
+                    frame: frame.frame(),
+                    translation: frame.translation(),
+                    rotation: Quat::from_vec4(frame.rotation()).normalize(),
+                    interpolation: frame.interpolation(),
+                });
+            }
+        }
+
+        let mut morphs: HashMap<usize, Vec<MorphKey>> = HashMap::new();
+        for frame in vmd.morph_frames() {
+            if let Some(&morph) = morph_names.get(frame.morph()) {
+                morphs.entry(morph).or_default().push(MorphKey {
+                    frame: frame.frame(),
+                    weight: frame.weight(),
+                });
+            }
+        }
+
+        let mut bones: Vec<_> = bones
+            .into_iter()
+            .map(|(bone, keys)| (bone, into_track(keys, |k| k.frame)))
+            .collect();
+        bones.sort_by_key(|(bone, _)| *bone);
+
+        let mut morphs: Vec<_> = morphs
+            .into_iter()
+            .map(|(morph, keys)| (morph, into_track(keys, |k| k.frame)))
+            .collect();
+        morphs.sort_by_key(|(morph, _)| *morph);
+        let morphs = morphs
+            .into_iter()
+            .map(|(morph, keys)| (MorphIndex::new(morph as i32), keys))
+            .collect();
+
+        Self { bones, morphs }
+    }
+
+    /// The bone pose at `time`, in frames.
+    ///
+    /// Bones without keyframes stay in the bind pose. IK chains still have to be solved into the
+    /// result with [`Skeleton::solve_ik`](crate::skeleton::Skeleton::solve_ik).
+    pub fn sample(&self, time: f32) -> Pose {
+        let mut pose = Pose::new();
+
+        for (bone, keys) in &self.bones {
+            let (translation, rotation) = match segment(keys, time, |k| k.frame) {
+                Segment::Hold(key) => (key.translation, key.rotation),
+                Segment::Between(from, to, x) => {
+                    let curves = &to.interpolation;
+                    let progress = Vec3::new(
+                        curves.x.evaluate(x),
+                        curves.y.evaluate(x),
+                        curves.z.evaluate(x),
+                    );
+                    let translation =
+                        from.translation + (to.translation - from.translation) * progress;
+                    let rotation = from
+                        .rotation
+                        .slerp(to.rotation, curves.rotation.evaluate(x));
+
+                    (translation, rotation)
+                }
+            };
+
+            pose.set_translation(*bone, translation);
+            pose.set_rotation(*bone, rotation);
+        }
+
+        pose
+    }
+
+    /// The weight of every morph with keyframes at `time`, in frames, ready for
+    /// [`Pmx::apply_morphs`].
+    pub fn sample_morphs(&self, time: f32) -> Vec<(MorphIndex, f32)> {
+        self.morphs
+            .iter()
+            .map(|(morph, keys)| {
+                let weight = match segment(keys, time, |k| k.frame) {
+                    Segment::Hold(key) => key.weight,
+                    Segment::Between(from, to, x) => from.weight + (to.weight - from.weight) * x,
+                };
+
+                (*morph, weight)
+            })
+            .collect()
+    }
+
+    /// The last frame any bound keyframe is placed on, 0 if there are none.
+    pub fn last_frame(&self) -> u32 {
+        let bones = self
+            .bones
+            .iter()
+            .filter_map(|(_, keys)| keys.last())
+            .map(|k| k.frame);
+        let morphs = self
+            .morphs
+            .iter()
+            .filter_map(|(_, keys)| keys.last())
+            .map(|k| k.frame);
+
+        bones.chain(morphs).max().unwrap_or(0)
+    }
+}
+
+/// Maps each name, cut off the way VMD stores it, to the first index using it.
+fn names<'a>(names: impl Iterator<Item = &'a str>, len: usize) -> HashMap<String, usize> {
+    let mut indices = HashMap::new();
+    for (i, name) in names.enumerate() {
+        indices
+            .entry(decode_shift_jis(&encode_shift_jis(name, len)))
+            .or_insert(i);
+    }
+
+    indices
+}
+
+/// Sorts keyframes by frame, keeping the last of several on the same frame.
+fn into_track<K>(mut keys: Vec<K>, frame: impl Fn(&K) -> u32) -> Vec<K> {
+    keys.reverse();
+    // the sort is stable, so within a frame the originally last keyframe now comes first
+    keys.sort_by_key(&frame);
+    keys.dedup_by_key(|k| frame(k));
+
+    keys
+}
+
+enum Segment<'k, K> {
+    Hold(&'k K),
+    /// The keyframes around the time and how far along it is from the first to the second.
+    Between(&'k K, &'k K, f32),
+}
+
+/// Finds where `time` falls in a non-empty, sorted track.
+fn segment<K>(keys: &[K], time: f32, frame: impl Fn(&K) -> u32) -> Segment<'_, K> {
+    let next = keys.partition_point(|k| frame(k) as f32 <= time);
+
+    match (next.checked_sub(1).map(|i| &keys[i]), keys.get(next)) {
+        (Some(from), Some(to)) => {
+            let (start, end) = (frame(from) as f32, frame(to) as f32);
+            Segment::Between(from, to, (time - start) / (end - start))
+        }
+        (Some(key), None) | (None, Some(key)) => Segment::Hold(key),
+        (None, None) => unreachable!("tracks are never empty"),
+    }
+}
@@ -0,0 +1,295 @@
+//! Reading models straight out of zip and 7z archives.
+//!
+//! Models are distributed as archives holding the `.pmx` with its textures. [`Archive`] lists the
+//! entries, parses a model from one and finds the entries its texture paths refer to, all in
+//! memory. Texture paths are resolved relative to the model's entry, case-insensitively where the
+//! exact name is missing, as they were written on Windows.
+//!
+//! Zip archives made on Japanese Windows store their names in Shift-JIS without marking it, names
+//! that are not valid UTF-8 are decoded as Shift-JIS. 7z archives are solid, reading an entry
+//! decompresses everything before it, so [`Archive::read_textures`] reads all textures in one pass.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Component, Path},
+};
+
+use sevenz_rust::{Password, SevenZReader};
+use thiserror::Error;
+use zip::{ZipArchive, result::ZipError};
+
+use crate::{
+    pmx::{self, Pmx},
+    texture::normalize_path,
+    util::decode_shift_jis,
+};
+
+const ZIP_SIGNATURE: &[u8] = b"PK";
+const SEVEN_Z_SIGNATURE: &[u8] = &[b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Zip(#[from] ZipError),
+    #[error(transparent)]
+    SevenZ(#[from] sevenz_rust::Error),
+    #[error(transparent)]
+    Pmx(#[from] pmx::Error),
+    #[error("The file is neither a zip nor a 7z archive")]
+    UnknownFormat,
+    #[error("The archive contains no .pmx file")]
+    NoModel,
+    #[error("Entry {0} was not found in the archive")]
+    EntryNotFound(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A zip or 7z archive, see the [module docs](self).
+pub struct Archive<R: Read + Seek> {
+    inner: Inner<R>,
+    /// The names of the file entries, with `/` separators.
+    entries: Vec<String>,
+}
+
+enum Inner<R: Read + Seek> {
+    Zip {
+        zip: ZipArchive<R>,
+        /// The index of each entry in the zip.
+        indices: Vec<usize>,
+    },
+    SevenZ(Box<SevenZReader<R>>),
+}
+
+/// A model parsed from an archive, with the archive entries of its textures.
+#[derive(Debug)]
+pub struct ArchivedModel {
+    pub(crate) entry: String,
+    pub(crate) pmx: Pmx,
+    pub(crate) textures: Vec<Option<String>>,
+}
+
+impl Archive<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Reads the entry list of a zip or 7z archive, told apart by their signatures.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut signature = [0; 6];
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut signature)?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        if signature.starts_with(ZIP_SIGNATURE) {
+            let mut zip = ZipArchive::new(reader)?;
+            let mut entries = Vec::new();
+            let mut indices = Vec::new();
+
+            for index in 0..zip.len() {
+                let file = zip.by_index_raw(index)?;
+                if file.is_dir() {
+                    continue;
+                }
+
+                let name = match std::str::from_utf8(file.name_raw()) {
+                    Ok(name) => name.to_string(),
+                    Err(_) => decode_shift_jis(file.name_raw()),
+                };
+                entries.push(name.replace('\\', "/"));
+                indices.push(index);
+            }
+
+            Ok(Self {
+                inner: Inner::Zip { zip, indices },
+                entries,
+            })
+        } else if signature == SEVEN_Z_SIGNATURE {
+            let reader = SevenZReader::new(reader, len, Password::empty())?;
+            let entries = reader
+                .archive()
+                .files
+                .iter()
+                .filter(|file| !file.is_directory)
+                .map(|file| file.name.replace('\\', "/"))
+                .collect();
+
+            Ok(Self {
+                inner: Inner::SevenZ(Box::new(reader)),
+                entries,
+            })
+        } else {
+            Err(Error::UnknownFormat)
+        }
+    }
+
+    /// The names of the files in the archive, with `/` separators.
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// The entries with a `.pmx` extension.
+    pub fn models(&self) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .map(String::as_str)
+            .filter(|entry| entry.to_ascii_lowercase().ends_with(".pmx"))
+    }
+
+    /// Reads the contents of an entry.
+    pub fn read(&mut self, entry: &str) -> Result<Vec<u8>> {
+        self.read_all(&[entry])?
+            .remove(entry)
+            .ok_or_else(|| Error::EntryNotFound(entry.to_string()))
+    }
+
+    /// Parses the model of the archive.
+    ///
+    /// Archives often hold variants of a model, the one least deep in the directory tree is
+    /// picked, the first in the archive of those at the same depth.
+    pub fn open_model(&mut self) -> Result<ArchivedModel> {
+        let entry = self
+            .models()
+            .min_by_key(|entry| entry.matches('/').count())
+            .ok_or(Error::NoModel)?
+            .to_string();
+
+        self.open_model_at(&entry)
+    }
+
+    /// Parses the model stored at `entry`.
+    pub fn open_model_at(&mut self, entry: &str) -> Result<ArchivedModel> {
+        let bytes = self.read(entry)?;
+        let pmx = Pmx::parse(&mut Cursor::new(bytes))?;
+
+        let textures = pmx
+            .textures
+            .inner
+            .iter()
+            .map(|texture| {
+                self.resolve(entry, texture.path.as_str())
+                    .map(str::to_string)
+            })
+            .collect();
+
+        Ok(ArchivedModel {
+            entry: entry.to_string(),
+            pmx,
+            textures,
+        })
+    }
+
+    /// Finds the entry a texture path of the model at `model` refers to.
+    pub fn resolve(&self, model: &str, path: &str) -> Option<&str> {
+        let mut parts: Vec<String> = model.split('/').map(str::to_string).collect();
+        // the file name of the model
+        parts.pop();
+
+        for component in normalize_path(path).components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+                Component::ParentDir => {
+                    parts.pop()?;
+                }
+                Component::CurDir => {}
+                Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+
+        let path = parts.join("/");
+
+        self.entries
+            .iter()
+            .find(|entry| **entry == path)
+            .or_else(|| {
+                let path = path.to_lowercase();
+                self.entries
+                    .iter()
+                    .find(|entry| entry.to_lowercase() == path)
+            })
+            .map(String::as_str)
+    }
+
+    /// Reads the file of every texture of `model` that was found, keyed by texture index.
+    pub fn read_textures(&mut self, model: &ArchivedModel) -> Result<HashMap<usize, Vec<u8>>> {
+        let entries: Vec<&str> = model
+            .textures
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        let contents = self.read_all(&entries)?;
+
+        Ok(model
+            .textures
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| Some((index, contents.get(entry.as_deref()?)?.clone())))
+            .collect())
+    }
+
+    /// Reads several entries, in a single pass over a 7z archive.
+    fn read_all(&mut self, wanted: &[&str]) -> Result<HashMap<String, Vec<u8>>> {
+        let wanted: HashSet<&str> = wanted.iter().copied().collect();
+        let mut contents = HashMap::new();
+
+        match &mut self.inner {
+            Inner::Zip { zip, indices } => {
+                for (entry, &index) in self.entries.iter().zip(indices.iter()) {
+                    if !wanted.contains(entry.as_str()) {
+                        continue;
+                    }
+
+                    let mut bytes = Vec::new();
+                    zip.by_index(index)?.read_to_end(&mut bytes)?;
+                    contents.insert(entry.clone(), bytes);
+                }
+            }
+            Inner::SevenZ(reader) => {
+                reader.for_each_entries(|file, data| {
+                    let name = file.name.replace('\\', "/");
+
+                    if wanted.contains(name.as_str()) {
+                        let mut bytes = Vec::new();
+                        data.read_to_end(&mut bytes)?;
+                        contents.insert(name, bytes);
+                    } else {
+                        // the entries share one stream, so skipped ones still have to be read
+                        std::io::copy(data, &mut std::io::sink())?;
+                    }
+
+                    Ok(contents.len() < wanted.len())
+                })?;
+            }
+        }
+
+        Ok(contents)
+    }
+}
+
+impl ArchivedModel {
+    /// The entry the model was read from.
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    pub fn pmx(&self) -> &Pmx {
+        &self.pmx
+    }
+
+    pub fn into_pmx(self) -> Pmx {
+        self.pmx
+    }
+
+    /// The entry of each texture, `None` for textures missing from the archive.
+    pub fn texture_entries(&self) -> &[Option<String>] {
+        &self.textures
+    }
+}
@@ -0,0 +1,238 @@
+//! Mapping facial morphs to the ARKit blend shapes.
+//!
+//! Face tracking apps drive avatars through the 52 blend shapes of ARKit face tracking, VTuber
+//! software calls models with all of them "Perfect Sync" models. [`Pmx::arkit_mapping`] finds the
+//! morph for each blend shape, by its ARKit name (local or universal, ignoring case) as Perfect
+//! Sync models name them, or else by the usual MMD name for the same expression, like `あ` for
+//! `jawOpen` or `ウィンク` for `eyeBlinkLeft`. MMD's left and right are the model's own, as in
+//! ARKit.
+//!
+//! The standard MMD morphs cover only a few of the blend shapes, the eye direction ones never
+//! match as MMD moves the eyes with bones. The facial morphs mapped to no blend shape are listed
+//! as well, to map by hand.
+//!
+//! The mapping refers to morphs by name, which is also what the glTF and VRM exporters name the
+//! morph targets and expressions after.
+
+use std::fmt;
+
+use crate::{
+    morph::{Offsets, Panel},
+    pmx::Pmx,
+    standard::fold,
+};
+
+/// The ARKit face tracking blend shapes.
+pub const BLENDSHAPES: [&str; 52] = [
+    "eyeBlinkLeft",
+    "eyeLookDownLeft",
+    "eyeLookInLeft",
+    "eyeLookOutLeft",
+    "eyeLookUpLeft",
+    "eyeSquintLeft",
+    "eyeWideLeft",
+    "eyeBlinkRight",
+    "eyeLookDownRight",
+    "eyeLookInRight",
+    "eyeLookOutRight",
+    "eyeLookUpRight",
+    "eyeSquintRight",
+    "eyeWideRight",
+    "jawForward",
+    "jawLeft",
+    "jawRight",
+    "jawOpen",
+    "mouthClose",
+    "mouthFunnel",
+    "mouthPucker",
+    "mouthLeft",
+    "mouthRight",
+    "mouthSmileLeft",
+    "mouthSmileRight",
+    "mouthFrownLeft",
+    "mouthFrownRight",
+    "mouthDimpleLeft",
+    "mouthDimpleRight",
+    "mouthStretchLeft",
+    "mouthStretchRight",
+    "mouthRollLower",
+    "mouthRollUpper",
+    "mouthShrugLower",
+    "mouthShrugUpper",
+    "mouthPressLeft",
+    "mouthPressRight",
+    "mouthLowerDownLeft",
+    "mouthLowerDownRight",
+    "mouthUpperUpLeft",
+    "mouthUpperUpRight",
+    "browDownLeft",
+    "browDownRight",
+    "browInnerUp",
+    "browOuterUpLeft",
+    "browOuterUpRight",
+    "cheekPuff",
+    "cheekSquintLeft",
+    "cheekSquintRight",
+    "noseSneerLeft",
+    "noseSneerRight",
+    "tongueOut",
+];
+
+/// MMD morph names for a blend shape, in order of preference.
+const CONVENTIONS: &[(&str, &[&str])] = &[
+    ("eyeBlinkLeft", &["ウィンク", "まばたき左", "ウィンク左"]),
+    ("eyeBlinkRight", &["ウィンク右", "まばたき右"]),
+    ("eyeWideLeft", &["びっくり左"]),
+    ("eyeWideRight", &["びっくり右"]),
+    ("jawForward", &["顎前"]),
+    ("jawLeft", &["顎左"]),
+    ("jawRight", &["顎右"]),
+    ("jawOpen", &["あ", "あ２"]),
+    ("mouthClose", &["ん"]),
+    ("mouthFunnel", &["お"]),
+    ("mouthPucker", &["う"]),
+    ("mouthLeft", &["口左"]),
+    ("mouthRight", &["口右"]),
+    ("mouthSmileLeft", &["口角上げ左"]),
+    ("mouthSmileRight", &["口角上げ右"]),
+    ("mouthFrownLeft", &["口角下げ左"]),
+    ("mouthFrownRight", &["口角下げ右"]),
+    ("mouthStretchLeft", &["口横広げ左"]),
+    ("mouthStretchRight", &["口横広げ右"]),
+    ("browDownLeft", &["怒り左"]),
+    ("browDownRight", &["怒り右"]),
+    ("browInnerUp", &["困る"]),
+    ("cheekPuff", &["ぷくー", "頬膨らまし"]),
+    ("tongueOut", &["ぺろっ", "べー", "舌出し"]),
+];
+
+/// A blend shape and the morph driving it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArkitBinding {
+    pub(crate) shape: &'static str,
+    pub(crate) morph: usize,
+    pub(crate) name: String,
+}
+
+impl ArkitBinding {
+    /// The ARKit name of the blend shape.
+    pub fn shape(&self) -> &'static str {
+        self.shape
+    }
+
+    /// The index of the morph.
+    pub fn morph(&self) -> usize {
+        self.morph
+    }
+
+    /// The local name of the morph.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The morphs found for the blend shapes, see [`Pmx::arkit_mapping`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArkitMapping {
+    pub(crate) bindings: Vec<ArkitBinding>,
+    pub(crate) unmapped: Vec<usize>,
+}
+
+impl ArkitMapping {
+    /// The mapped blend shapes, in the order of [`BLENDSHAPES`].
+    pub fn bindings(&self) -> &[ArkitBinding] {
+        &self.bindings
+    }
+
+    /// The binding of a blend shape, by its ARKit name.
+    pub fn get(&self, shape: &str) -> Option<&ArkitBinding> {
+        self.bindings.iter().find(|binding| binding.shape == shape)
+    }
+
+    /// The blend shapes no morph was found for.
+    pub fn missing(&self) -> impl Iterator<Item = &'static str> + '_ {
+        BLENDSHAPES
+            .into_iter()
+            .filter(|&shape| self.get(shape).is_none())
+    }
+
+    /// The eyebrow, eye and mouth morphs no blend shape is mapped to.
+    pub fn unmapped(&self) -> &[usize] {
+        &self.unmapped
+    }
+
+    /// The mapping as a JSON object from blend shape to morph name.
+    #[cfg(feature = "gltf")]
+    pub fn to_json(&self) -> serde_json::Value {
+        (self.bindings.iter())
+            .map(|binding| (binding.shape.to_string(), binding.name.clone().into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+impl fmt::Display for ArkitMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for binding in &self.bindings {
+            writeln!(f, "{}: {}", binding.shape, binding.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pmx {
+    /// Maps the morphs to the ARKit blend shapes, see the [module docs](crate::arkit).
+    ///
+    /// Of several morphs with a name for a blend shape the first one is used. Only vertex, UV,
+    /// group and flip morphs are mapped.
+    pub fn arkit_mapping(&self) -> ArkitMapping {
+        let morphs = &self.morphs.inner;
+        let mappable = |i: &usize| {
+            !matches!(
+                morphs[*i].offsets,
+                Offsets::Bone(_) | Offsets::Material(_) | Offsets::Impulse(_)
+            )
+        };
+        let find = |matches: &dyn Fn(usize) -> bool| {
+            (0..morphs.len()).filter(mappable).find(|&i| matches(i))
+        };
+
+        let mut bindings = Vec::new();
+        for shape in BLENDSHAPES {
+            let by_shape = find(&|i| {
+                let name = &morphs[i].name;
+                [&name.local, &name.universal]
+                    .iter()
+                    .any(|n| n.as_str().trim().eq_ignore_ascii_case(shape))
+            });
+            let conventions = CONVENTIONS
+                .iter()
+                .find(|(s, _)| *s == shape)
+                .map_or(&[][..], |(_, names)| *names);
+            let by_convention = || {
+                conventions.iter().find_map(|convention| {
+                    let convention = fold(convention);
+                    find(&|i| fold(morphs[i].name.local.as_str()) == convention)
+                })
+            };
+
+            if let Some(morph) = by_shape.or_else(by_convention) {
+                bindings.push(ArkitBinding {
+                    shape,
+                    morph,
+                    name: morphs[morph].name.local.as_str().to_owned(),
+                });
+            }
+        }
+
+        let unmapped = (0..morphs.len())
+            .filter(|&i| matches!(morphs[i].panel, Panel::Eyebrow | Panel::Eye | Panel::Mouth))
+            .filter(|&i| !bindings.iter().any(|binding| binding.morph == i))
+            .collect();
+
+        ArkitMapping { bindings, unmapped }
+    }
+}
@@ -0,0 +1,273 @@
+//! Conversion into [bevy](https://bevyengine.org) meshes.
+//!
+//! [`BevyModel`] holds one [`Mesh`] per material with only the vertices that material uses, a
+//! description of each material to build a bevy material from, and the joints and inverse bind
+//! poses for a `SkinnedMesh`. Like the glTF export, coordinates are converted from MMD's
+//! left-handed space to bevy's right-handed one by mirroring Z. Units are left as they are, scale
+//! the transform of the model's root entity to size it.
+//!
+//! SDEF and QDEF have no equivalent in bevy's skinning and are blended linearly.
+
+use bevy_asset::RenderAssetUsages;
+use bevy_mesh::{
+    Indices, Mesh, PrimitiveTopology, VertexAttributeValues, skinning::SkinnedMeshInverseBindposes,
+};
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::pmx::Pmx;
+
+/// A model ready to be added to bevy's assets, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct BevyModel {
+    pub(crate) meshes: Vec<Mesh>,
+    pub(crate) materials: Vec<MaterialDescription>,
+    pub(crate) skin: BevySkin,
+}
+
+/// The parameters of a material, in the terms of bevy's `StandardMaterial`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialDescription {
+    pub(crate) name: String,
+    /// The index of the PMX material.
+    pub(crate) index: usize,
+    pub(crate) base_color: Vec4,
+    pub(crate) texture: Option<String>,
+    pub(crate) double_sided: bool,
+    pub(crate) blend: bool,
+}
+
+/// The joints of the skinned meshes, one per bone in bone order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BevySkin {
+    pub(crate) names: Vec<String>,
+    pub(crate) parents: Vec<Option<usize>>,
+    pub(crate) local_transforms: Vec<Mat4>,
+    pub(crate) inverse_bindposes: Vec<Mat4>,
+}
+
+impl BevyModel {
+    pub fn from_pmx(pmx: &Pmx) -> Self {
+        let vertices = &pmx.vertices.inner;
+        let bone_count = pmx.bones.inner.len();
+        let surfaces = &pmx.surfaces.inner;
+
+        let mut meshes = Vec::new();
+        let mut materials = Vec::new();
+        // the position of each model vertex in the mesh being built
+        let mut remap = vec![u32::MAX; vertices.len()];
+        let mut start = 0;
+
+        for (index, material) in pmx.materials.inner.iter().enumerate() {
+            let count = (material.surface_count.max(0) as usize).min(surfaces.len() - start);
+            let range = &surfaces[start..start + count];
+            start += count;
+
+            let mut used = Vec::new();
+            let mut indices = Vec::with_capacity(range.len());
+            // mirroring an axis flips the handedness, the winding is reversed to keep faces outward
+            for triangle in range.chunks_exact(3) {
+                let corners = [&triangle[0], &triangle[2], &triangle[1]];
+                let Some(corners) = corners
+                    .map(|surface| surface.index.as_usize().filter(|&i| i < vertices.len()))
+                    .into_iter()
+                    .collect::<Option<Vec<usize>>>()
+                else {
+                    continue;
+                };
+
+                for vertex in corners {
+                    if remap[vertex] == u32::MAX {
+                        remap[vertex] = used.len() as u32;
+                        used.push(vertex);
+                    }
+                    indices.push(remap[vertex]);
+                }
+            }
+
+            if indices.is_empty() {
+                continue;
+            }
+
+            let mut positions = Vec::with_capacity(used.len());
+            let mut normals = Vec::with_capacity(used.len());
+            let mut uvs = Vec::with_capacity(used.len());
+            let mut joints = Vec::with_capacity(used.len());
+            let mut weights = Vec::with_capacity(used.len());
+
+            for &vertex in &used {
+                let vertex = &vertices[vertex];
+                positions.push(mirror(vertex.pos).to_array());
+                normals.push(mirror(vertex.normal).normalize_or(Vec3::Y).to_array());
+                uvs.push(vertex.uv.to_array());
+
+                let (joint, weight) = vertex.weight_deform.skin_weights(bone_count);
+                joints.push(joint);
+                weights.push(weight);
+            }
+
+            for &vertex in &used {
+                remap[vertex] = u32::MAX;
+            }
+
+            let mut mesh = Mesh::new(
+                PrimitiveTopology::TriangleList,
+                RenderAssetUsages::default(),
+            )
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+            .with_inserted_indices(Indices::U32(indices));
+            if bone_count > 0 {
+                mesh = mesh
+                    .with_inserted_attribute(
+                        Mesh::ATTRIBUTE_JOINT_INDEX,
+                        VertexAttributeValues::Uint16x4(joints),
+                    )
+                    .with_inserted_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, weights);
+            }
+
+            let texture = material
+                .tex_idx
+                .as_usize()
+                .and_then(|texture| pmx.textures.inner.get(texture))
+                // PMX paths use Windows separators
+                .map(|texture| texture.path.as_str().replace('\\', "/"));
+
+            meshes.push(mesh);
+            materials.push(MaterialDescription {
+                name: material.name.local.as_str().to_string(),
+                index,
+                base_color: material.diffuse,
+                texture,
+                double_sided: material.flags.no_cull(),
+                blend: material.diffuse.w < 1.0,
+            });
+        }
+
+        Self {
+            meshes,
+            materials,
+            skin: BevySkin::from_pmx(pmx),
+        }
+    }
+
+    /// The meshes, each drawn with the material at the same position in
+    /// [`materials`](Self::materials). Materials without surfaces have no mesh.
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn materials(&self) -> &[MaterialDescription] {
+        &self.materials
+    }
+
+    pub fn skin(&self) -> &BevySkin {
+        &self.skin
+    }
+
+    pub fn into_parts(self) -> (Vec<Mesh>, Vec<MaterialDescription>, BevySkin) {
+        (self.meshes, self.materials, self.skin)
+    }
+}
+
+impl MaterialDescription {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The index of the PMX material this was made from.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The diffuse colour, including its alpha.
+    pub fn base_color(&self) -> Vec4 {
+        self.base_color
+    }
+
+    /// The path of the texture relative to the model, with `/` separators.
+    pub fn texture(&self) -> Option<&str> {
+        self.texture.as_deref()
+    }
+
+    /// Returns true if back faces are drawn, `cull_mode: None` in bevy.
+    pub fn double_sided(&self) -> bool {
+        self.double_sided
+    }
+
+    /// Returns true if the material is translucent and needs `AlphaMode::Blend`.
+    pub fn blend(&self) -> bool {
+        self.blend
+    }
+}
+
+impl BevySkin {
+    pub fn from_pmx(pmx: &Pmx) -> Self {
+        let bones = &pmx.bones.inner;
+        let skeleton = pmx.skeleton();
+
+        // bones looping back onto themselves become roots
+        let parents: Vec<Option<usize>> = (0..bones.len())
+            .map(|i| skeleton.parent(i).filter(|_| skeleton.is_rooted(i)))
+            .collect();
+
+        // bones have no rotation in the bind pose, so every transform is a translation
+        let local_transforms = bones
+            .iter()
+            .zip(&parents)
+            .map(|(bone, parent)| {
+                let parent = parent.map_or(Vec3::ZERO, |p| mirror(bones[p].position));
+                Mat4::from_translation(mirror(bone.position) - parent)
+            })
+            .collect();
+        let inverse_bindposes = bones
+            .iter()
+            .map(|bone| Mat4::from_translation(-mirror(bone.position)))
+            .collect();
+
+        Self {
+            names: bones
+                .iter()
+                .map(|bone| bone.name.local.as_str().to_string())
+                .collect(),
+            parents,
+            local_transforms,
+            inverse_bindposes,
+        }
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The parent of each joint, `None` for roots.
+    pub fn parents(&self) -> &[Option<usize>] {
+        &self.parents
+    }
+
+    /// The transform of each joint relative to its parent, for the joint entities.
+    pub fn local_transforms(&self) -> &[Mat4] {
+        &self.local_transforms
+    }
+
+    pub fn inverse_bindposes(&self) -> &[Mat4] {
+        &self.inverse_bindposes
+    }
+
+    /// The inverse bind poses as the asset a `SkinnedMesh` refers to, its joint entities have to
+    /// be listed in the same order.
+    pub fn inverse_bindposes_asset(&self) -> SkinnedMeshInverseBindposes {
+        self.inverse_bindposes.clone().into()
+    }
+}
+
+impl Pmx {
+    /// Converts the model into bevy meshes, see [`BevyModel`].
+    pub fn to_bevy(&self) -> BevyModel {
+        BevyModel::from_pmx(self)
+    }
+}
+
+fn mirror(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, v.y, -v.z)
+}
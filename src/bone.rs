@@ -0,0 +1,633 @@
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+use crate::types::{
+    BoneIndex, IndexSize, Name, ParseContext, TextEncoding, Vec3, from_array, read_f32, read_i32,
+    read_u8, read_u16, vec_from_bytes, vec_to_bytes, write_count, write_f32, write_i32, write_u8,
+    write_u16,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Negative size encountered where positive expected")]
+    NegativeSize,
+    #[error(transparent)]
+    Type(#[from] crate::types::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Bone flags do not match the optional bone data present")]
+    FlagMismatch,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct Bones {
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<Bone>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(Bones, Bone, len);
+
+impl From<Vec<Bone>> for Bones {
+    fn from(inner: Vec<Bone>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
+}
+
+impl Bones {
+    pub fn len(&self) -> usize {
+        let len = self.inner.len();
+        debug_assert!(self.len == len);
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bones(&self) -> &[Bone] {
+        &self.inner
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size = read_i32(reader)?;
+
+        if size.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // name, position, parent, layer, flags and a tail index
+        let size = context.check_count(size as usize, 26 + 2 * index_size as u64)?;
+
+        let mut inner_vec = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let bone = Bone::parse(reader, index_size, encoding, context)?;
+            inner_vec.push(bone);
+        }
+
+        Ok(Self {
+            len: size,
+            inner: inner_vec,
+        })
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for bone in &self.inner {
+            bone.write(writer, index_size, encoding)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The 16-bit flag field of a bone.
+///
+/// Several flags decide which optional fields follow in the bone record.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoneFlags {
+    pub(crate) raw: u16,
+}
+
+impl BoneFlags {
+    pub fn from_raw(raw: u16) -> Self {
+        Self { raw }
+    }
+
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+
+    /// The tail is stored as a bone index instead of a position offset.
+    pub const INDEXED_TAIL: u16 = 0x0001;
+    pub const ROTATABLE: u16 = 0x0002;
+    pub const TRANSLATABLE: u16 = 0x0004;
+    pub const VISIBLE: u16 = 0x0008;
+    pub const ENABLED: u16 = 0x0010;
+    pub const IK: u16 = 0x0020;
+    /// Inherit (append) from the parent's local transform instead of its deformed transform.
+    pub const INHERIT_LOCAL: u16 = 0x0080;
+    pub const INHERIT_ROTATION: u16 = 0x0100;
+    pub const INHERIT_TRANSLATION: u16 = 0x0200;
+    pub const FIXED_AXIS: u16 = 0x0400;
+    pub const LOCAL_AXES: u16 = 0x0800;
+    pub const PHYSICS_AFTER_DEFORM: u16 = 0x1000;
+    pub const EXTERNAL_PARENT_DEFORM: u16 = 0x2000;
+
+    /// Returns true if every bit of `flag` is set.
+    pub fn contains(&self, flag: u16) -> bool {
+        self.raw & flag == flag
+    }
+
+    pub fn indexed_tail(&self) -> bool {
+        self.contains(Self::INDEXED_TAIL)
+    }
+
+    pub fn rotatable(&self) -> bool {
+        self.contains(Self::ROTATABLE)
+    }
+
+    pub fn translatable(&self) -> bool {
+        self.contains(Self::TRANSLATABLE)
+    }
+
+    pub fn visible(&self) -> bool {
+        self.contains(Self::VISIBLE)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.contains(Self::ENABLED)
+    }
+
+    pub fn ik(&self) -> bool {
+        self.contains(Self::IK)
+    }
+
+    pub fn inherit_local(&self) -> bool {
+        self.contains(Self::INHERIT_LOCAL)
+    }
+
+    pub fn inherit_rotation(&self) -> bool {
+        self.contains(Self::INHERIT_ROTATION)
+    }
+
+    pub fn inherit_translation(&self) -> bool {
+        self.contains(Self::INHERIT_TRANSLATION)
+    }
+
+    pub fn fixed_axis(&self) -> bool {
+        self.contains(Self::FIXED_AXIS)
+    }
+
+    pub fn local_axes(&self) -> bool {
+        self.contains(Self::LOCAL_AXES)
+    }
+
+    pub fn physics_after_deform(&self) -> bool {
+        self.contains(Self::PHYSICS_AFTER_DEFORM)
+    }
+
+    pub fn external_parent_deform(&self) -> bool {
+        self.contains(Self::EXTERNAL_PARENT_DEFORM)
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            raw: read_u16(reader)?,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_u16(writer, self.raw)?;
+
+        Ok(())
+    }
+}
+
+/// Where the bone's tail (the visual end of the bone) points to.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Tail {
+    /// Offset relative to the bone's position.
+    Position(Vec3),
+    /// Another bone whose position is the tail.
+    Bone(BoneIndex),
+}
+
+/// Inherit (append) data, present when the bone inherits rotation and/or translation.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Inherit {
+    pub(crate) parent: BoneIndex,
+    pub(crate) weight: f32,
+}
+
+impl Inherit {
+    pub fn parent(&self) -> &BoneIndex {
+        &self.parent
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// Local coordinate axes, present when the `LOCAL_AXES` flag is set.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LocalAxes {
+    pub(crate) x: Vec3,
+    pub(crate) z: Vec3,
+}
+
+impl LocalAxes {
+    pub fn x(&self) -> Vec3 {
+        self.x
+    }
+
+    pub fn z(&self) -> Vec3 {
+        self.z
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ik {
+    pub(crate) target: BoneIndex,
+    pub(crate) loop_count: i32,
+    /// Maximum rotation per iteration in radians.
+    pub(crate) limit_angle: f32,
+    pub(crate) links: Vec<IkLink>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IkLink {
+    pub(crate) bone: BoneIndex,
+    pub(crate) limits: Option<IkAngleLimit>,
+}
+
+impl IkLink {
+    pub fn bone(&self) -> &BoneIndex {
+        &self.bone
+    }
+
+    pub fn limits(&self) -> Option<&IkAngleLimit> {
+        self.limits.as_ref()
+    }
+}
+
+/// Per-axis rotation limits of an IK link in radians.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IkAngleLimit {
+    pub(crate) min: Vec3,
+    pub(crate) max: Vec3,
+}
+
+impl IkAngleLimit {
+    pub fn min(&self) -> Vec3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Vec3 {
+        self.max
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bone {
+    pub(crate) name: Name,
+    pub(crate) position: Vec3,
+    pub(crate) parent: BoneIndex,
+    pub(crate) layer: i32,
+    pub(crate) flags: BoneFlags,
+    pub(crate) tail: Tail,
+    pub(crate) inherit: Option<Inherit>,
+    pub(crate) fixed_axis: Option<Vec3>,
+    pub(crate) local_axes: Option<LocalAxes>,
+    pub(crate) external_parent: Option<i32>,
+    pub(crate) ik: Option<Ik>,
+}
+
+impl Bone {
+    /// Creates a visible, rotatable root bone with a zero tail offset.
+    pub fn new(name: impl Into<String>, position: Vec3) -> Self {
+        Self {
+            name: Name::new(name, "", TextEncoding::UTF16LE),
+            position,
+            parent: BoneIndex::nil(),
+            layer: 0,
+            flags: BoneFlags::from_raw(
+                BoneFlags::ROTATABLE | BoneFlags::VISIBLE | BoneFlags::ENABLED,
+            ),
+            tail: Tail::Position(from_array([0.0; 3])),
+            inherit: None,
+            fixed_axis: None,
+            local_axes: None,
+            external_parent: None,
+            ik: None,
+        }
+    }
+
+    pub fn set_parent(&mut self, parent: BoneIndex) {
+        self.parent = parent;
+    }
+
+    /// Sets the flags, except for `INDEXED_TAIL`, which always follows the tail.
+    pub fn set_flags(&mut self, flags: BoneFlags) {
+        let indexed = self.flags.raw() & BoneFlags::INDEXED_TAIL;
+        self.flags = BoneFlags::from_raw(flags.raw() & !BoneFlags::INDEXED_TAIL | indexed);
+    }
+
+    /// Sets the tail, updating the `INDEXED_TAIL` flag to match.
+    pub fn set_tail(&mut self, tail: Tail) {
+        let mut raw = self.flags.raw() & !BoneFlags::INDEXED_TAIL;
+        if matches!(tail, Tail::Bone(_)) {
+            raw |= BoneFlags::INDEXED_TAIL;
+        }
+
+        self.flags = BoneFlags::from_raw(raw);
+        self.tail = tail;
+    }
+
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn parent(&self) -> &BoneIndex {
+        &self.parent
+    }
+
+    /// Deform layer, bones are transformed in ascending layer order.
+    pub fn layer(&self) -> i32 {
+        self.layer
+    }
+
+    pub fn flags(&self) -> BoneFlags {
+        self.flags
+    }
+
+    pub fn tail(&self) -> &Tail {
+        &self.tail
+    }
+
+    pub fn inherit(&self) -> Option<&Inherit> {
+        self.inherit.as_ref()
+    }
+
+    pub fn fixed_axis(&self) -> Option<Vec3> {
+        self.fixed_axis
+    }
+
+    pub fn local_axes(&self) -> Option<&LocalAxes> {
+        self.local_axes.as_ref()
+    }
+
+    /// The external parent key, used to parent the bone to another model.
+    pub fn external_parent(&self) -> Option<i32> {
+        self.external_parent
+    }
+
+    pub fn ik(&self) -> Option<&Ik> {
+        self.ik.as_ref()
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size: IndexSize = index_size.try_into()?;
+
+        let name = Name::parse(reader, encoding, context)?;
+
+        let position: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        let parent = BoneIndex::parse(reader, size)?;
+
+        let layer = read_i32(reader)?;
+
+        let flags = BoneFlags::parse(reader)?;
+
+        let tail = if flags.contains(BoneFlags::INDEXED_TAIL) {
+            Tail::Bone(BoneIndex::parse(reader, size)?)
+        } else {
+            Tail::Position(vec_from_bytes!(Vec3, reader))
+        };
+
+        let inherit = if flags.contains(BoneFlags::INHERIT_ROTATION)
+            || flags.contains(BoneFlags::INHERIT_TRANSLATION)
+        {
+            let parent = BoneIndex::parse(reader, size)?;
+            let weight = read_f32(reader)?;
+
+            Some(Inherit { parent, weight })
+        } else {
+            None
+        };
+
+        let fixed_axis = if flags.contains(BoneFlags::FIXED_AXIS) {
+            Some(vec_from_bytes!(Vec3, reader))
+        } else {
+            None
+        };
+
+        let local_axes = if flags.contains(BoneFlags::LOCAL_AXES) {
+            let x: Vec3 = vec_from_bytes!(Vec3, reader);
+            let z: Vec3 = vec_from_bytes!(Vec3, reader);
+
+            Some(LocalAxes { x, z })
+        } else {
+            None
+        };
+
+        let external_parent = if flags.contains(BoneFlags::EXTERNAL_PARENT_DEFORM) {
+            Some(read_i32(reader)?)
+        } else {
+            None
+        };
+
+        let ik = if flags.contains(BoneFlags::IK) {
+            Some(Ik::parse(reader, size, context)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name,
+            position,
+            parent,
+            layer,
+            flags,
+            tail,
+            inherit,
+            fixed_axis,
+            local_axes,
+            external_parent,
+            ik,
+        })
+    }
+
+    /// Writes the bone record.
+    ///
+    /// The optional fields are written based on the flags, so they have to agree with each other.
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        let size: IndexSize = index_size.try_into()?;
+
+        self.name.write(writer, encoding)?;
+
+        vec_to_bytes!(Vec3, self.position, writer);
+
+        self.parent.write(writer, size)?;
+
+        write_i32(writer, self.layer)?;
+
+        self.flags.write(writer)?;
+
+        match (&self.tail, self.flags.contains(BoneFlags::INDEXED_TAIL)) {
+            (Tail::Bone(index), true) => index.write(writer, size)?,
+            (Tail::Position(position), false) => vec_to_bytes!(Vec3, *position, writer),
+            _ => Err(Error::FlagMismatch)?,
+        }
+
+        let inherits = self.flags.contains(BoneFlags::INHERIT_ROTATION)
+            || self.flags.contains(BoneFlags::INHERIT_TRANSLATION);
+
+        match (&self.inherit, inherits) {
+            (Some(inherit), true) => {
+                inherit.parent.write(writer, size)?;
+                write_f32(writer, inherit.weight)?;
+            }
+            (None, false) => {}
+            _ => Err(Error::FlagMismatch)?,
+        }
+
+        match (&self.fixed_axis, self.flags.contains(BoneFlags::FIXED_AXIS)) {
+            (Some(axis), true) => vec_to_bytes!(Vec3, *axis, writer),
+            (None, false) => {}
+            _ => Err(Error::FlagMismatch)?,
+        }
+
+        match (&self.local_axes, self.flags.contains(BoneFlags::LOCAL_AXES)) {
+            (Some(axes), true) => {
+                vec_to_bytes!(Vec3, axes.x, writer);
+                vec_to_bytes!(Vec3, axes.z, writer);
+            }
+            (None, false) => {}
+            _ => Err(Error::FlagMismatch)?,
+        }
+
+        match (
+            &self.external_parent,
+            self.flags.contains(BoneFlags::EXTERNAL_PARENT_DEFORM),
+        ) {
+            (Some(key), true) => write_i32(writer, *key)?,
+            (None, false) => {}
+            _ => Err(Error::FlagMismatch)?,
+        }
+
+        match (&self.ik, self.flags.contains(BoneFlags::IK)) {
+            (Some(ik), true) => ik.write(writer, size)?,
+            (None, false) => {}
+            _ => Err(Error::FlagMismatch)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl Ik {
+    pub fn target(&self) -> &BoneIndex {
+        &self.target
+    }
+
+    pub fn loop_count(&self) -> i32 {
+        self.loop_count
+    }
+
+    pub fn limit_angle(&self) -> f32 {
+        self.limit_angle
+    }
+
+    pub fn links(&self) -> &[IkLink] {
+        &self.links
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize, context: ParseContext) -> Result<Self> {
+        let target = BoneIndex::parse(reader, size)?;
+
+        let loop_count = read_i32(reader)?;
+
+        let limit_angle = read_f32(reader)?;
+
+        let link_count = read_i32(reader)?;
+
+        if link_count.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // a bone index and the angle limit flag
+        let link_count = context.check_count(link_count as usize, 2)?;
+
+        let mut links = Vec::with_capacity(link_count);
+
+        for _ in 0..link_count {
+            let bone = BoneIndex::parse(reader, size)?;
+
+            // a single byte deciding whether angle limits follow
+            let limits = if read_u8(reader)? != 0 {
+                let min: Vec3 = vec_from_bytes!(Vec3, reader);
+                let max: Vec3 = vec_from_bytes!(Vec3, reader);
+
+                Some(IkAngleLimit { min, max })
+            } else {
+                None
+            };
+
+            links.push(IkLink { bone, limits });
+        }
+
+        Ok(Self {
+            target,
+            loop_count,
+            limit_angle,
+            links,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.target.write(writer, size)?;
+
+        write_i32(writer, self.loop_count)?;
+
+        write_f32(writer, self.limit_angle)?;
+
+        write_count(writer, self.links.len())?;
+
+        for link in &self.links {
+            link.bone.write(writer, size)?;
+
+            match &link.limits {
+                Some(limits) => {
+                    write_u8(writer, 1)?;
+                    vec_to_bytes!(Vec3, limits.min, writer);
+                    vec_to_bytes!(Vec3, limits.max, writer);
+                }
+                None => write_u8(writer, 0)?,
+            }
+        }
+
+        Ok(())
+    }
+}
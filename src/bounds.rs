@@ -0,0 +1,161 @@
+//! Bounding volumes of the bind pose.
+//!
+//! [`Pmx::aabb`] and [`Pmx::bounding_sphere`] cover every vertex, the `material_` variants only
+//! the vertices of the triangles of one material. Vertices no triangle refers to count for the
+//! whole model, models rarely have any.
+//!
+//! The bounding sphere is found with Ritter's algorithm, a single pass that is not the smallest
+//! sphere but stays within a few percent of it, which is enough for framing and culling.
+
+use crate::{
+    pmx::Pmx,
+    types::{Vec3, from_array, to_array},
+};
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub(crate) min: Vec3,
+    pub(crate) max: Vec3,
+}
+
+impl Aabb {
+    /// The box around `points`, `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter().map(to_array);
+        let first = points.next()?;
+
+        let (min, max) = points.fold((first, first), |(min, max), point| {
+            (
+                std::array::from_fn(|i| min[i].min(point[i])),
+                std::array::from_fn(|i| max[i].max(point[i])),
+            )
+        });
+
+        Some(Self {
+            min: from_array(min),
+            max: from_array(max),
+        })
+    }
+
+    pub fn min(&self) -> Vec3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Vec3 {
+        self.max
+    }
+
+    pub fn center(&self) -> Vec3 {
+        let (min, max): ([f32; 3], [f32; 3]) = (to_array(self.min), to_array(self.max));
+        from_array(std::array::from_fn(|i| (min[i] + max[i]) / 2.0))
+    }
+
+    /// The extent along each axis.
+    pub fn size(&self) -> Vec3 {
+        let (min, max): ([f32; 3], [f32; 3]) = (to_array(self.min), to_array(self.max));
+        from_array(std::array::from_fn(|i| max[i] - min[i]))
+    }
+}
+
+/// A sphere containing a set of points, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub(crate) center: Vec3,
+    pub(crate) radius: f32,
+}
+
+impl BoundingSphere {
+    /// A sphere around `points`, `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let points: Vec<[f32; 3]> = points.into_iter().map(to_array).collect();
+        let first = *points.first()?;
+
+        // the initial diameter spans two points far apart, found from the first one
+        let farthest = |from: [f32; 3]| {
+            points
+                .iter()
+                .copied()
+                .max_by(|a, b| distance(from, *a).total_cmp(&distance(from, *b)))
+                .unwrap_or(from)
+        };
+        let a = farthest(first);
+        let b = farthest(a);
+
+        let mut center: [f32; 3] = std::array::from_fn(|i| (a[i] + b[i]) / 2.0);
+        let mut radius = distance(a, b) / 2.0;
+
+        // grows the sphere just enough to take in each point outside of it
+        for &point in &points {
+            let d = distance(center, point);
+            if d > radius {
+                let grown = (radius + d) / 2.0;
+                let shift = (grown - radius) / d;
+                center = std::array::from_fn(|i| center[i] + (point[i] - center[i]) * shift);
+                radius = grown;
+            }
+        }
+
+        Some(Self {
+            center: from_array(center),
+            radius,
+        })
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.center
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+impl Pmx {
+    /// The box around every vertex, `None` for a model without vertices.
+    pub fn aabb(&self) -> Option<Aabb> {
+        Aabb::from_points(self.vertices.inner.iter().map(|vertex| vertex.pos))
+    }
+
+    /// A sphere around every vertex, `None` for a model without vertices.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        BoundingSphere::from_points(self.vertices.inner.iter().map(|vertex| vertex.pos))
+    }
+
+    /// The box around the triangles of the material at `index`, `None` if it has none or there
+    /// is no such material.
+    pub fn material_aabb(&self, index: usize) -> Option<Aabb> {
+        Aabb::from_points(self.material_positions(index))
+    }
+
+    /// A sphere around the triangles of the material at `index`, `None` if it has none or there
+    /// is no such material.
+    pub fn material_bounding_sphere(&self, index: usize) -> Option<BoundingSphere> {
+        BoundingSphere::from_points(self.material_positions(index))
+    }
+
+    /// The positions of the vertices the surfaces of a material refer to, skipping broken
+    /// references.
+    fn material_positions(&self, index: usize) -> impl Iterator<Item = Vec3> + '_ {
+        let surfaces = &self.surfaces.inner;
+        let materials = self.materials.inner.get(..=index).unwrap_or_default();
+
+        let start: usize = (materials.iter().rev().skip(1))
+            .map(|material| material.surface_count.max(0) as usize)
+            .sum();
+        let start = start.min(surfaces.len());
+        let count = materials
+            .last()
+            .map_or(0, |m| m.surface_count.max(0) as usize);
+        let end = (start + count).min(surfaces.len());
+
+        surfaces[start..end].iter().filter_map(|surface| {
+            let vertex = surface.index.as_usize()?;
+            Some(self.vertices.inner.get(vertex)?.pos)
+        })
+    }
+}
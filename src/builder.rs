@@ -0,0 +1,209 @@
+//! Assembling models from scratch.
+//!
+//! [`PmxBuilder`] collects the elements of a model and fills in everything derived from them:
+//! the index sizes in the globals, the surface count of each material and the two display
+//! frames every model is expected to have. The result is checked with [`Pmx::validate`], so a
+//! builder never hands out a model that would trip up other tools.
+
+use crate::{
+    bone::Bone,
+    display_frame::{DisplayFrame, FrameEntry},
+    material::Material,
+    pmx::{self, Comment, Error, Globals, Header, ModelName, Pmx, Result, Version},
+    surface::Surface,
+    texture::Texture,
+    types::{BoneIndex, IndexSize, Name, PmxText, TextEncoding, VertexIndex},
+    vertex::Vertex,
+};
+
+/// Collects the elements of a new model, see the [module docs](self).
+///
+/// Elements refer to each other by their position in the order they were added in, e.g. the
+/// first texture added is texture 0.
+pub struct PmxBuilder {
+    version: Version,
+    encoding: TextEncoding,
+    name: (String, String),
+    comment: (String, String),
+    vertices: Vec<Vertex>,
+    surfaces: Vec<Surface>,
+    textures: Vec<Texture>,
+    materials: Vec<Material>,
+    bones: Vec<Bone>,
+}
+
+impl Default for PmxBuilder {
+    fn default() -> Self {
+        Self {
+            version: Version::V2_0,
+            encoding: TextEncoding::UTF16LE,
+            name: Default::default(),
+            comment: Default::default(),
+            vertices: Vec::new(),
+            surfaces: Vec::new(),
+            textures: Vec::new(),
+            materials: Vec::new(),
+            bones: Vec::new(),
+        }
+    }
+}
+
+impl PmxBuilder {
+    /// Starts an empty PMX 2.0 model with UTF-16 texts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The encoding every text of the model is written with.
+    pub fn encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn name(mut self, local: impl Into<String>) -> Self {
+        self.name.0 = local.into();
+        self
+    }
+
+    pub fn universal_name(mut self, universal: impl Into<String>) -> Self {
+        self.name.1 = universal.into();
+        self
+    }
+
+    pub fn comment(mut self, local: impl Into<String>) -> Self {
+        self.comment.0 = local.into();
+        self
+    }
+
+    pub fn universal_comment(mut self, universal: impl Into<String>) -> Self {
+        self.comment.1 = universal.into();
+        self
+    }
+
+    pub fn add_vertex(mut self, vertex: Vertex) -> Self {
+        self.vertices.push(vertex);
+        self
+    }
+
+    pub fn add_vertices(mut self, vertices: impl IntoIterator<Item = Vertex>) -> Self {
+        self.vertices.extend(vertices);
+        self
+    }
+
+    /// Adds a texture path, relative to the model file.
+    pub fn add_texture(mut self, path: impl Into<String>) -> Self {
+        self.textures.push(Texture {
+            path: PmxText::new(path, self.encoding),
+        });
+        self
+    }
+
+    /// Adds a material along with the triangles drawn with it, given as vertex indices.
+    ///
+    /// The surface count of the material is overwritten with the number of indices.
+    pub fn add_material(
+        mut self,
+        mut material: Material,
+        triangles: impl IntoIterator<Item = [u32; 3]>,
+    ) -> Self {
+        let start = self.surfaces.len();
+
+        for triangle in triangles {
+            self.surfaces.extend(triangle.map(|index| Surface {
+                // out of range values turn negative and get reported by validation
+                index: VertexIndex::new(index as i32),
+            }));
+        }
+
+        material.surface_count = (self.surfaces.len() - start) as i32;
+        self.materials.push(material);
+        self
+    }
+
+    pub fn add_bone(mut self, bone: Bone) -> Self {
+        self.bones.push(bone);
+        self
+    }
+
+    /// Assembles the model, picking the smallest index sizes that fit.
+    ///
+    /// A `Root` display frame holding the first bone and an empty expression frame are added.
+    /// Fails if an element needs a newer version than the one set, or with [`Error::Invalid`] if
+    /// any reference is out of bounds.
+    pub fn build(self) -> Result<Pmx> {
+        let encoding = self.encoding;
+        let text = |text: &str| PmxText::new(text, encoding);
+
+        let root_entries = if self.bones.is_empty() {
+            Vec::new()
+        } else {
+            vec![FrameEntry::Bone(BoneIndex::new(0))]
+        };
+        let display_frames = vec![
+            DisplayFrame {
+                name: Name::new("Root", "Root", encoding),
+                special: true,
+                entries: root_entries,
+            },
+            DisplayFrame {
+                name: Name::new("表情", "Exp", encoding),
+                special: true,
+                entries: Vec::new(),
+            },
+        ];
+
+        let header = Header {
+            tag: *b"PMX ",
+            version: self.version,
+            raw_version: self.version.as_f32(),
+            globals: Globals {
+                encoding,
+                vec4_additional: 0,
+                vert_idx_size: IndexSize::smallest_for(self.vertices.len(), false),
+                tex_idx_size: IndexSize::smallest_for(self.textures.len(), true),
+                material_idx_size: IndexSize::smallest_for(self.materials.len(), true),
+                bone_idx_size: IndexSize::smallest_for(self.bones.len(), true),
+                morph_idx_size: 1,
+                rb_idx_size: 1,
+                additional: None,
+            },
+            name: ModelName {
+                local: text(&self.name.0),
+                universal: text(&self.name.1),
+            },
+            comment: Comment {
+                local: text(&self.comment.0),
+                universal: text(&self.comment.1),
+            },
+        };
+
+        let pmx = Pmx {
+            header,
+            vertices: self.vertices.into(),
+            surfaces: self.surfaces.into(),
+            textures: self.textures.into(),
+            materials: self.materials.into(),
+            bones: self.bones.into(),
+            morphs: Vec::new().into(),
+            display_frames: display_frames.into(),
+            rigid_bodies: Vec::new().into(),
+            joints: Vec::new().into(),
+            soft_bodies: (self.version >= Version::V2_1).then(|| Vec::new().into()),
+            trailing: None,
+        };
+
+        pmx::check_vertices(pmx.header.version, &pmx.vertices)?;
+
+        let report = pmx.validate();
+        if !report.is_valid() {
+            Err(Error::Invalid(report))?
+        }
+
+        Ok(pmx)
+    }
+}
@@ -0,0 +1,151 @@
+//! Packing a model and its textures into a single zip archive.
+//!
+//! [`Pmx::bundle`] stores the model at the root of the archive and every texture file it
+//! references next to it, at its path relative to the model with `/` separators. The texture
+//! section of the stored model is rewritten to those paths, so the archive can be extracted
+//! anywhere. Textures outside the model directory are moved into `textures/`.
+//!
+//! Texture files are found with [`resolve_path`](crate::texture::resolve_path), ones that do not exist are left out of the
+//! archive and reported.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use thiserror::Error;
+use zip::{CompressionMethod, ZipWriter, result::ZipError, write::SimpleFileOptions};
+
+use crate::{
+    pmx::{self, Pmx},
+    texture::{Texture, Textures, normalize_path},
+    types::PmxText,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Zip(#[from] ZipError),
+    #[error(transparent)]
+    Pmx(#[from] pmx::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl Pmx {
+    /// Writes the model and its textures, found relative to `model_dir`, into a zip archive at
+    /// `path`, see the [module docs](crate::bundle).
+    ///
+    /// The model is stored under the file name of `path` with a `.pmx` extension. Returns the
+    /// indices of the textures whose files were not found.
+    pub fn bundle(
+        &self,
+        model_dir: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<usize>> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map_or("model".into(), |stem| stem.to_string_lossy());
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        let missing = self.bundle_to(model_dir, &mut writer, &format!("{name}.pmx"))?;
+        writer.flush()?;
+
+        Ok(missing)
+    }
+
+    /// Writes the model and its textures into a zip archive, the model stored as `model_name`.
+    ///
+    /// Returns the indices of the textures whose files were not found.
+    pub fn bundle_to(
+        &self,
+        model_dir: impl AsRef<Path>,
+        writer: impl Write + Seek,
+        model_name: &str,
+    ) -> Result<Vec<usize>> {
+        let model_dir = model_dir.as_ref();
+        let encoding = self.header.globals.encoding;
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        let mut zip = ZipWriter::new(writer);
+        let mut missing = Vec::new();
+        let mut paths = Vec::with_capacity(self.textures.inner.len());
+        // the entry of every file written so far, and the entry names taken, lowercased as
+        // archives are often extracted on case-insensitive file systems
+        let mut entries: HashMap<PathBuf, String> = HashMap::new();
+        let mut taken = HashSet::from([model_name.to_lowercase()]);
+
+        for (index, texture) in self.textures.inner.iter().enumerate() {
+            let relative = entry_name(texture.path.as_str());
+
+            let Some(source) = texture.resolve(model_dir) else {
+                missing.push(index);
+                paths.push(relative.unwrap_or_else(|| fallback_name(index, texture)));
+                continue;
+            };
+
+            if let Some(entry) = entries.get(&source) {
+                paths.push(entry.clone());
+                continue;
+            }
+
+            let entry = relative
+                .filter(|entry| !taken.contains(&entry.to_lowercase()))
+                .unwrap_or_else(|| fallback_name(index, texture));
+            taken.insert(entry.to_lowercase());
+
+            zip.start_file(entry.as_str(), options)?;
+            std::io::copy(&mut File::open(&source)?, &mut zip)?;
+
+            entries.insert(source, entry.clone());
+            paths.push(entry);
+        }
+
+        let textures = Textures::from(
+            paths
+                .into_iter()
+                .map(|path| Texture {
+                    path: PmxText::new(path, encoding),
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        zip.start_file(model_name, options)?;
+        self.write_with_textures(&mut zip, &textures)?;
+
+        zip.finish()?;
+
+        Ok(missing)
+    }
+}
+
+/// The path of a texture inside the archive, `None` if it leaves the model directory.
+fn entry_name(path: &str) -> Option<String> {
+    let path = normalize_path(path);
+    let mut parts = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) => parts.push(part.to_string_lossy()),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// A unique path in `textures/` keeping the file name.
+fn fallback_name(index: usize, texture: &Texture) -> String {
+    let path = normalize_path(texture.path.as_str());
+    let file_name = path
+        .file_name()
+        .map_or("texture".into(), |name| name.to_string_lossy());
+
+    format!("textures/{index}_{file_name}")
+}
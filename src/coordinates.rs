@@ -0,0 +1,276 @@
+//! Converting a model out of MMD's left-handed, Y-up coordinate system.
+//!
+//! [`Pmx::convert_coordinates`] mirrors the whole model into a right-handed system. Positions,
+//! directions and offsets are mapped, the winding of every triangle is reversed so faces keep
+//! pointing outwards, and rotations are mirrored along with the space they live in: quaternions
+//! and IK limits around each axis turn the other way where the axis is not mirrored itself.
+//!
+//! Rigid bodies and joints carry a local frame with their shape and limits in it. Mirroring the
+//! world would make that frame left-handed, so its local Z axis is mirrored too, which leaves boxes,
+//! spheres and capsules as they were and keeps their rotations proper. Their Euler angles are
+//! recomputed in MMD's order, around Y, then X, then Z, and the limits along and around local axes
+//! are mirrored to match.
+//!
+//! Every [`Target`] is its own inverse, converting a converted model again restores it.
+//!
+//! [`Pmx::mirror_x`] uses the same mapping to mirror the model across the YZ plane within MMD's
+//! own system, like PMX Editor's mirror tool. Left and right parts trade places, so the names of
+//! bones, morphs, rigid bodies and joints have their left and right swapped to match.
+
+use glam::{EulerRot, Mat3, Quat, Vec3, Vec4};
+
+use crate::{bone::Tail, morph::Offsets, pmx::Pmx, types::PmxText, vertex::WeightDeform};
+
+/// The coordinate system to convert a model into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Y up with Z mirrored, like glTF, three.js and bevy.
+    RightHandedYUp,
+    /// Z up with Y and Z swapped, like Blender, the model facing -Y.
+    RightHandedZUp,
+}
+
+/// A mirroring, each component taken from a component of the input and maybe negated.
+#[derive(Clone, Copy)]
+struct Mirror {
+    source: [usize; 3],
+    sign: [f32; 3],
+}
+
+impl Mirror {
+    /// Mirrors Z in the local frames of rigid bodies and joints.
+    const LOCAL: Mirror = Mirror {
+        source: [0, 1, 2],
+        sign: [1.0, 1.0, -1.0],
+    };
+
+    /// Mirrors X, the model's left and right.
+    const X: Mirror = Mirror {
+        source: [0, 1, 2],
+        sign: [-1.0, 1.0, 1.0],
+    };
+
+    fn of(target: Target) -> Self {
+        match target {
+            Target::RightHandedYUp => Mirror::LOCAL,
+            Target::RightHandedZUp => Mirror {
+                source: [0, 2, 1],
+                sign: [1.0; 3],
+            },
+        }
+    }
+
+    fn point(&self, v: Vec3) -> Vec3 {
+        Vec3::from_array(std::array::from_fn(|i| self.sign[i] * v[self.source[i]]))
+    }
+
+    /// Maps a rotation vector like a torque, which turns the other way in a mirrored space.
+    fn axial(&self, v: Vec3) -> Vec3 {
+        -self.point(v)
+    }
+
+    /// Maps a quaternion stored as XYZW.
+    fn quat(&self, q: Vec4) -> Vec4 {
+        self.axial(q.truncate()).extend(q.w)
+    }
+
+    /// Maps per-axis ranges of a translation, keeping min below max.
+    fn range(&self, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+        let (a, b) = (self.point(min), self.point(max));
+        (a.min(b), a.max(b))
+    }
+
+    /// Maps per-axis ranges of rotation angles, keeping min below max.
+    fn angle_range(&self, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+        let (a, b) = (self.axial(min), self.axial(max));
+        (a.min(b), a.max(b))
+    }
+
+    fn matrix(&self) -> Mat3 {
+        Mat3::from_cols(
+            self.point(Vec3::X),
+            self.point(Vec3::Y),
+            self.point(Vec3::Z),
+        )
+    }
+
+    /// Maps MMD Euler angles of a local frame, the frame's own Z mirrored with [`Mirror::LOCAL`].
+    fn euler(&self, rotation: Vec3) -> Vec3 {
+        let matrix = Mat3::from_quat(Quat::from_euler(
+            EulerRot::YXZ,
+            rotation.y,
+            rotation.x,
+            rotation.z,
+        ));
+        let mirrored = self.matrix() * matrix * Mirror::LOCAL.matrix();
+        let (y, x, z) = Quat::from_mat3(&mirrored).to_euler(EulerRot::YXZ);
+
+        Vec3::new(x, y, z)
+    }
+}
+
+impl Pmx {
+    /// Mirrors the model into `target`, see the [module docs](crate::coordinates).
+    pub fn convert_coordinates(&mut self, target: Target) {
+        self.mirror(Mirror::of(target));
+    }
+
+    /// Mirrors the model across the YZ plane and swaps left and right in names, see the
+    /// [module docs](crate::coordinates).
+    ///
+    /// `左` and `右` are swapped in names, leaving `左右` alone, as are `Left`, `LEFT`, a `left`
+    /// that does not continue a word and `_L` or `.L` ending one, and their right counterparts.
+    /// Mirroring twice restores the model.
+    pub fn mirror_x(&mut self) {
+        self.mirror(Mirror::X);
+
+        let names = (self.bones.inner.iter_mut().map(|b| &mut b.name))
+            .chain(self.morphs.inner.iter_mut().map(|m| &mut m.name))
+            .chain(self.rigid_bodies.inner.iter_mut().map(|r| &mut r.name))
+            .chain(self.joints.inner.iter_mut().map(|j| &mut j.name));
+
+        for name in names {
+            for text in [&mut name.local, &mut name.universal] {
+                if let Some(swapped) = swap_sides(text.as_str()) {
+                    *text = PmxText::new(swapped, text.encoding);
+                }
+            }
+        }
+    }
+
+    fn mirror(&mut self, mirror: Mirror) {
+        let local = Mirror::LOCAL;
+
+        for vertex in &mut self.vertices.inner {
+            vertex.pos = mirror.point(vertex.pos);
+            vertex.normal = mirror.point(vertex.normal);
+
+            if let WeightDeform::Sdef { c, r0, r1, .. } = &mut vertex.weight_deform {
+                *c = mirror.point(*c);
+                *r0 = mirror.point(*r0);
+                *r1 = mirror.point(*r1);
+            }
+        }
+
+        for triangle in self.surfaces.inner.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+
+        for bone in &mut self.bones.inner {
+            bone.position = mirror.point(bone.position);
+
+            if let Tail::Position(offset) = &mut bone.tail {
+                *offset = mirror.point(*offset);
+            }
+            if let Some(axis) = &mut bone.fixed_axis {
+                *axis = mirror.point(*axis);
+            }
+            if let Some(axes) = &mut bone.local_axes {
+                axes.x = mirror.point(axes.x);
+                axes.z = mirror.point(axes.z);
+            }
+
+            let links = bone.ik.iter_mut().flat_map(|ik| &mut ik.links);
+            for limits in links.filter_map(|link| link.limits.as_mut()) {
+                (limits.min, limits.max) = mirror.angle_range(limits.min, limits.max);
+            }
+        }
+
+        for morph in &mut self.morphs.inner {
+            match &mut morph.offsets {
+                Offsets::Vertex(offsets) => {
+                    for offset in offsets {
+                        offset.translation = mirror.point(offset.translation);
+                    }
+                }
+                Offsets::Bone(offsets) => {
+                    for offset in offsets {
+                        offset.translation = mirror.point(offset.translation);
+                        offset.rotation = mirror.quat(offset.rotation);
+                    }
+                }
+                Offsets::Impulse(offsets) => {
+                    for offset in offsets {
+                        // local impulses are in the body's frame
+                        let mirror = if offset.local { local } else { mirror };
+                        offset.velocity = mirror.point(offset.velocity);
+                        offset.torque = mirror.axial(offset.torque);
+                    }
+                }
+                Offsets::Group(_)
+                | Offsets::Uv(_)
+                | Offsets::AdditionalUv(..)
+                | Offsets::Material(_)
+                | Offsets::Flip(_) => {}
+            }
+        }
+
+        for body in &mut self.rigid_bodies.inner {
+            body.position = mirror.point(body.position);
+            body.rotation = mirror.euler(body.rotation);
+        }
+
+        for joint in &mut self.joints.inner {
+            joint.position = mirror.point(joint.position);
+            joint.rotation = mirror.euler(joint.rotation);
+            (joint.position_min, joint.position_max) =
+                local.range(joint.position_min, joint.position_max);
+            (joint.rotation_min, joint.rotation_max) =
+                local.angle_range(joint.rotation_min, joint.rotation_max);
+        }
+    }
+}
+
+/// The side words and what they turn into, the longer words first.
+const SIDES: [(&str, &str); 14] = [
+    ("左右", "左右"),
+    ("右左", "右左"),
+    ("左", "右"),
+    ("右", "左"),
+    ("Left", "Right"),
+    ("Right", "Left"),
+    ("LEFT", "RIGHT"),
+    ("RIGHT", "LEFT"),
+    ("left", "right"),
+    ("right", "left"),
+    ("_L", "_R"),
+    ("_R", "_L"),
+    (".L", ".R"),
+    (".R", ".L"),
+];
+
+/// `text` with left and right swapped, `None` if it names neither.
+fn swap_sides(text: &str) -> Option<String> {
+    let mut swapped = String::with_capacity(text.len());
+    let mut changed = false;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        // lowercase words only at the start of a word, `bright` is no side, and suffixes only
+        // at the end of one, `_Lower` is none either
+        let word_start = !swapped.ends_with(|c: char| c.is_ascii_alphabetic());
+        let side = SIDES.iter().find(|(from, _)| {
+            let Some(after) = rest.strip_prefix(from) else {
+                return false;
+            };
+            let word_end = !after.starts_with(|c: char| c.is_ascii_alphabetic());
+
+            (word_start || !from.starts_with(|c: char| c.is_lowercase()))
+                && (word_end || !from.starts_with(['_', '.']))
+        });
+
+        match side {
+            Some((from, to)) => {
+                swapped.push_str(to);
+                changed |= from != to;
+                rest = &rest[from.len()..];
+            }
+            None => {
+                swapped.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    changed.then_some(swapped)
+}
@@ -0,0 +1,1274 @@
+//! PMX Editor compatible CSV export and import.
+//!
+//! PMX Editor can write model sections as CSV, one row per element where the first column names
+//! the kind of row (`PmxVertex`, `PmxMaterial`, `PmxBone`, `PmxMorph`, ...) and lines starting
+//! with `;` are column headers. Bones, materials, morphs, textures and rigid bodies are referenced
+//! by name, vertices by index, and angles are written in degrees.
+//!
+//! Importing merges the rows into the model instead of replacing whole sections: vertices are
+//! matched by index and everything else by local name, matched elements are overwritten and new
+//! ones appended, so indices held by other sections stay valid. Bones and morphs that have a row
+//! get their IK links and offsets from the link and offset rows of the same file. Rows of kinds
+//! not handled here, like `PmxFace` or `PmxBody`, are skipped.
+//!
+//! Files are written as UTF-8 with a byte order mark, which both PMX Editor and spreadsheet
+//! software detect. Files without one are read as UTF-8 if valid and as Shift-JIS otherwise.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{
+    bone::{Bone, BoneFlags, Ik, IkAngleLimit, IkLink, Inherit, LocalAxes, Tail},
+    material::{EnvironmentBlend, Material, MaterialFlags, Toon},
+    morph::{
+        BoneOffset, FlipOffset, GroupOffset, ImpulseOffset, MaterialOffset, MaterialOperation,
+        Morph, Offsets, Panel, UvOffset, VertexOffset,
+    },
+    pmx::Pmx,
+    texture::Texture,
+    types::{
+        BoneIndex, IndexSize, MaterialIndex, MorphIndex, Name, PmxText, RigidBodyIndex,
+        TextureIndex, Vec3, Vec4, VertexIndex, from_array, to_array,
+    },
+    util::decode_shift_jis_text,
+    vertex::{Vertex, WeightDeform},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Line {line}: unterminated quoted field")]
+    UnterminatedQuote { line: usize },
+    #[error("Line {line}: {kind} row has {found} columns, expected at least {expected}")]
+    MissingColumns {
+        line: usize,
+        kind: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Line {line}, column {column}: invalid value \"{value}\"")]
+    InvalidValue {
+        line: usize,
+        column: usize,
+        value: String,
+    },
+    #[error("Line {line}: unknown {kind} \"{name}\"")]
+    UnknownReference {
+        line: usize,
+        kind: &'static str,
+        name: String,
+    },
+    #[error("Line {line}: {message}")]
+    Malformed { line: usize, message: &'static str },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A section that can be exported as CSV.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Section {
+    Vertices,
+    Materials,
+    Bones,
+    Morphs,
+}
+
+impl Section {
+    pub const ALL: [Section; 4] = [
+        Section::Vertices,
+        Section::Materials,
+        Section::Bones,
+        Section::Morphs,
+    ];
+}
+
+const BOM: &str = "\u{feff}";
+
+const VERTEX_HEADER: &str = ";PmxVertex,頂点Index,位置_x,位置_y,位置_z,法線_x,法線_y,法線_z,エッジ倍率,UV_u,UV_v,追加UV1_x,追加UV1_y,追加UV1_z,追加UV1_w,追加UV2_x,追加UV2_y,追加UV2_z,追加UV2_w,追加UV3_x,追加UV3_y,追加UV3_z,追加UV3_w,追加UV4_x,追加UV4_y,追加UV4_z,追加UV4_w,ウェイト変形タイプ(0:BDEF1 1:BDEF2 2:BDEF4 3:SDEF 4:QDEF),ウェイト1_ボーン名,ウェイト1_ウェイト値,ウェイト2_ボーン名,ウェイト2_ウェイト値,ウェイト3_ボーン名,ウェイト3_ウェイト値,ウェイト4_ボーン名,ウェイト4_ウェイト値,C_x,C_y,C_z,R0_x,R0_y,R0_z,R1_x,R1_y,R1_z";
+const MATERIAL_HEADER: &str = ";PmxMaterial,材質名,材質名(英),拡散色_R,拡散色_G,拡散色_B,拡散色_A(非透過度),反射色_R,反射色_G,反射色_B,反射強度,環境色_R,環境色_G,環境色_B,両面描画(0/1),地面影(0/1),セルフ影マップ(0/1),セルフ影(0/1),頂点色(0/1),描画(0:Tri/1:Point/2:Line),エッジ(0/1),エッジサイズ,エッジ色_R,エッジ色_G,エッジ色_B,エッジ色_A,基本テクスチャ,スフィアテクスチャ,スフィアモード(0:無効/1:乗算/2:加算/3:サブテクスチャ),Toonテクスチャ,メモ";
+const BONE_HEADER: &str = ";PmxBone,ボーン名,ボーン名(英),変形階層,物理後(0/1),位置_x,位置_y,位置_z,回転(0/1),移動(0/1),IK(0/1),表示(0/1),操作(0/1),親ボーン名,表示先(0:オフセット/1:ボーン),表示先ボーン名,オフセット_x,オフセット_y,オフセット_z,ローカル付与(0/1),回転付与(0/1),移動付与(0/1),付与率,付与親名,軸制限(0/1),制限軸_x,制限軸_y,制限軸_z,ローカル軸(0/1),ローカルX軸_x,ローカルX軸_y,ローカルX軸_z,ローカルZ軸_x,ローカルZ軸_y,ローカルZ軸_z,外部親(0/1),外部親Key,IKTarget名,IKLoop,IK単位角[deg]";
+const IK_LINK_HEADER: &str = ";PmxIKLink,親ボーン名,Linkボーン名,角度制限(0/1),XL[deg],XH[deg],YL[deg],YH[deg],ZL[deg],ZH[deg]";
+const MORPH_HEADER: &str = ";PmxMorph,モーフ名,モーフ名(英),パネル(0:無効/1:眉(左下)/2:目(左上)/3:口(右上)/4:その他(右下)),モーフ種類(0:グループモーフ/1:頂点モーフ/2:ボーンモーフ/3:UV(Tex)モーフ/4:追加UV1モーフ/5:追加UV2モーフ/6:追加UV3モーフ/7:追加UV4モーフ/8:材質モーフ/9:フリップモーフ/10:インパルスモーフ)";
+const GROUP_MORPH_HEADER: &str = ";PmxGroupMorph,親モーフ名,モーフ名,影響度";
+const VERTEX_MORPH_HEADER: &str =
+    ";PmxVertexMorph,親モーフ名,頂点Index,オフセット_x,オフセット_y,オフセット_z";
+const BONE_MORPH_HEADER: &str = ";PmxBoneMorph,親モーフ名,ボーン名,移動量_x,移動量_y,移動量_z,回転量_x[deg],回転量_y[deg],回転量_z[deg]";
+const UV_MORPH_HEADER: &str =
+    ";PmxUVMorph,親モーフ名,頂点Index,オフセット_x,オフセット_y,オフセット_z,オフセット_w";
+const MATERIAL_MORPH_HEADER: &str = ";PmxMaterialMorph,親モーフ名,材質名,演算形式(0:乗算/1:加算),拡散色_R,拡散色_G,拡散色_B,拡散色_A,反射色_R,反射色_G,反射色_B,反射強度,環境色_R,環境色_G,環境色_B,エッジ色_R,エッジ色_G,エッジ色_B,エッジ色_A,エッジサイズ,テクスチャ係数_R,テクスチャ係数_G,テクスチャ係数_B,テクスチャ係数_A,スフィアテクスチャ係数_R,スフィアテクスチャ係数_G,スフィアテクスチャ係数_B,スフィアテクスチャ係数_A,Toonテクスチャ係数_R,Toonテクスチャ係数_G,Toonテクスチャ係数_B,Toonテクスチャ係数_A";
+const FLIP_MORPH_HEADER: &str = ";PmxFlipMorph,親モーフ名,モーフ名,影響度";
+const IMPULSE_MORPH_HEADER: &str = ";PmxImpulseMorph,親モーフ名,剛体名,ローカル(0/1),移動速度_x,移動速度_y,移動速度_z,回転トルク_x,回転トルク_y,回転トルク_z";
+
+/// One output row, strings are always quoted like PMX Editor does.
+struct Row(Vec<String>);
+
+impl Row {
+    fn new(kind: &str) -> Self {
+        Self(vec![kind.to_string()])
+    }
+
+    fn text(mut self, text: &str) -> Self {
+        self.0.push(format!("\"{}\"", text.replace('"', "\"\"")));
+        self
+    }
+
+    fn num(mut self, value: impl Display) -> Self {
+        self.0.push(value.to_string());
+        self
+    }
+
+    fn flag(self, value: bool) -> Self {
+        self.num(u8::from(value))
+    }
+
+    fn floats(mut self, values: &[f32]) -> Self {
+        self.0.extend(values.iter().map(f32::to_string));
+        self
+    }
+
+    fn write(self, writer: &mut impl Write) -> Result<()> {
+        writeln!(writer, "{}", self.0.join(","))?;
+
+        Ok(())
+    }
+}
+
+impl Pmx {
+    /// Writes the given sections to a CSV file at `path`.
+    pub fn export_csv(&self, path: &Path, sections: &[Section]) -> Result<()> {
+        let fh = std::fs::File::create(path)?;
+
+        let mut writer = BufWriter::new(fh);
+
+        self.write_csv(&mut writer, sections)?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the given sections as CSV, in the order they are listed.
+    pub fn write_csv(&self, writer: &mut impl Write, sections: &[Section]) -> Result<()> {
+        write!(writer, "{BOM}")?;
+
+        for section in sections {
+            match section {
+                Section::Vertices => self.write_vertices_csv(writer)?,
+                Section::Materials => self.write_materials_csv(writer)?,
+                Section::Bones => self.write_bones_csv(writer)?,
+                Section::Morphs => self.write_morphs_csv(writer)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merges the rows of a CSV file into the model.
+    pub fn import_csv(&mut self, path: &Path) -> Result<()> {
+        let fh = std::fs::File::open(path)?;
+
+        self.read_csv(&mut std::io::BufReader::new(fh))
+    }
+
+    /// Merges CSV rows into the model, see the module documentation for how rows are matched.
+    ///
+    /// Materials keep their surface count when overwritten, new materials start without
+    /// surfaces. Index sizes in the globals are widened when a section outgrows them.
+    pub fn read_csv(&mut self, reader: &mut impl Read) -> Result<()> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) => text.strip_prefix(BOM).unwrap_or(text).to_string(),
+            Err(_) => decode_shift_jis_text(&bytes),
+        };
+
+        let records = parse_records(&text)?;
+        let rows = |kind: &'static str| records.iter().filter(move |r| r.fields[0] == kind);
+
+        let mut textures = first_indices(self.textures.inner.iter().map(|t| t.path.as_str()));
+
+        for record in rows("PmxMaterial") {
+            self.import_material(record, &mut textures)?;
+        }
+
+        self.import_bones(rows("PmxBone").collect(), rows("PmxIKLink"))?;
+
+        let bones = first_indices(self.bones.inner.iter().map(|b| b.name.local.as_str()));
+        for record in rows("PmxVertex") {
+            self.import_vertex(record, &bones)?;
+        }
+
+        self.import_morphs(
+            rows("PmxMorph").collect(),
+            records
+                .iter()
+                .filter(|r| OFFSET_KINDS.contains(&r.fields[0].as_str())),
+        )?;
+
+        self.textures.len = self.textures.inner.len();
+        self.materials.len = self.materials.inner.len();
+        self.bones.len = self.bones.inner.len();
+        self.vertices.size = self.vertices.inner.len();
+        self.morphs.len = self.morphs.inner.len();
+
+        let globals = &mut self.header.globals;
+        let widen = |size: &mut u8, count: usize, signed: bool| {
+            *size = (*size).max(IndexSize::smallest_for(count, signed));
+        };
+        widen(&mut globals.vert_idx_size, self.vertices.inner.len(), false);
+        widen(&mut globals.tex_idx_size, self.textures.inner.len(), true);
+        widen(
+            &mut globals.material_idx_size,
+            self.materials.inner.len(),
+            true,
+        );
+        widen(&mut globals.bone_idx_size, self.bones.inner.len(), true);
+        widen(&mut globals.morph_idx_size, self.morphs.inner.len(), true);
+
+        Ok(())
+    }
+
+    fn write_vertices_csv(&self, writer: &mut impl Write) -> Result<()> {
+        writeln!(writer, "{VERTEX_HEADER}")?;
+
+        for (i, vertex) in self.vertices.inner.iter().enumerate() {
+            let position: [f32; 3] = to_array(vertex.pos);
+            let normal: [f32; 3] = to_array(vertex.normal);
+            let uv: [f32; 2] = to_array(vertex.uv);
+
+            let mut row = Row::new("PmxVertex")
+                .num(i)
+                .floats(&position)
+                .floats(&normal)
+                .num(vertex.edge_scale)
+                .floats(&uv);
+
+            let additional = vertex.additional_vec4s();
+            for channel in 0..4 {
+                let value: [f32; 4] = additional
+                    .get(channel)
+                    .map(|&v| to_array(v))
+                    .unwrap_or_default();
+                row = row.floats(&value);
+            }
+
+            let (indices, weights): (&[BoneIndex], [f32; 4]) = match &vertex.weight_deform {
+                WeightDeform::Bdef1 { index } => {
+                    (std::slice::from_ref(index), [1.0, 0.0, 0.0, 0.0])
+                }
+                WeightDeform::Bdef2 { indices, weights }
+                | WeightDeform::Sdef {
+                    indices, weights, ..
+                } => (indices.as_slice(), [weights[0], weights[1], 0.0, 0.0]),
+                WeightDeform::Bdef4 { indices, weights }
+                | WeightDeform::Qdef { indices, weights } => (indices.as_slice(), *weights),
+            };
+
+            row = row.num(weight_deform_type(&vertex.weight_deform));
+            for (slot, weight) in weights.iter().enumerate() {
+                let name = indices.get(slot).map_or("", |index| self.bone_name(index));
+                row = row.text(name).num(weight);
+            }
+
+            let sdef: [[f32; 3]; 3] = match &vertex.weight_deform {
+                WeightDeform::Sdef { c, r0, r1, .. } => {
+                    [to_array(*c), to_array(*r0), to_array(*r1)]
+                }
+                _ => Default::default(),
+            };
+            for value in &sdef {
+                row = row.floats(value);
+            }
+
+            row.write(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_materials_csv(&self, writer: &mut impl Write) -> Result<()> {
+        writeln!(writer, "{MATERIAL_HEADER}")?;
+
+        for material in &self.materials.inner {
+            let diffuse: [f32; 4] = to_array(material.diffuse);
+            let specular: [f32; 3] = to_array(material.specular);
+            let ambient: [f32; 3] = to_array(material.ambient);
+            let edge_color: [f32; 4] = to_array(material.edge_color);
+            let flags = material.flags;
+
+            let draw_mode = if flags.point_draw() {
+                1
+            } else if flags.line_draw() {
+                2
+            } else {
+                0
+            };
+
+            let toon = match &material.toon {
+                Toon::Internal(index) => Toon::internal_file_name(*index),
+                Toon::Texture(index) => self.texture_path(index).to_string(),
+            };
+
+            Row::new("PmxMaterial")
+                .text(material.name.local.as_str())
+                .text(material.name.universal.as_str())
+                .floats(&diffuse)
+                .floats(&specular)
+                .num(material.specular_strength)
+                .floats(&ambient)
+                .flag(flags.no_cull())
+                .flag(flags.ground_shadow())
+                .flag(flags.draw_shadow())
+                .flag(flags.receive_shadow())
+                .flag(flags.vertex_color())
+                .num(draw_mode)
+                .flag(flags.edge())
+                .num(material.edge_scale)
+                .floats(&edge_color)
+                .text(self.texture_path(&material.tex_idx))
+                .text(self.texture_path(&material.env_idx))
+                .num(u8::from(&material.env_blend))
+                .text(&toon)
+                .text(material.meta.as_str())
+                .write(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_bones_csv(&self, writer: &mut impl Write) -> Result<()> {
+        writeln!(writer, "{BONE_HEADER}")?;
+
+        for bone in &self.bones.inner {
+            let flags = bone.flags;
+            let position: [f32; 3] = to_array(bone.position);
+
+            let (tail_bone, tail_offset): (&str, [f32; 3]) = match &bone.tail {
+                Tail::Bone(index) => (self.bone_name(index), [0.0; 3]),
+                Tail::Position(offset) => ("", to_array(*offset)),
+            };
+
+            let (inherit_parent, inherit_weight) = match &bone.inherit {
+                Some(inherit) => (self.bone_name(&inherit.parent), inherit.weight),
+                None => ("", 0.0),
+            };
+
+            let fixed_axis: [f32; 3] = bone.fixed_axis.map(to_array).unwrap_or_default();
+            let (local_x, local_z): ([f32; 3], [f32; 3]) = match &bone.local_axes {
+                Some(axes) => (to_array(axes.x), to_array(axes.z)),
+                None => ([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            };
+
+            let (ik_target, ik_loop, ik_angle) = match &bone.ik {
+                Some(ik) => (
+                    self.bone_name(&ik.target),
+                    ik.loop_count,
+                    ik.limit_angle.to_degrees(),
+                ),
+                None => ("", 0, 0.0),
+            };
+
+            Row::new("PmxBone")
+                .text(bone.name.local.as_str())
+                .text(bone.name.universal.as_str())
+                .num(bone.layer)
+                .flag(flags.physics_after_deform())
+                .floats(&position)
+                .flag(flags.rotatable())
+                .flag(flags.translatable())
+                .flag(flags.ik())
+                .flag(flags.visible())
+                .flag(flags.enabled())
+                .text(self.bone_name(&bone.parent))
+                .flag(flags.indexed_tail())
+                .text(tail_bone)
+                .floats(&tail_offset)
+                .flag(flags.inherit_local())
+                .flag(flags.inherit_rotation())
+                .flag(flags.inherit_translation())
+                .num(inherit_weight)
+                .text(inherit_parent)
+                .flag(flags.fixed_axis())
+                .floats(&fixed_axis)
+                .flag(flags.local_axes())
+                .floats(&local_x)
+                .floats(&local_z)
+                .flag(flags.external_parent_deform())
+                .num(bone.external_parent.unwrap_or(0))
+                .text(ik_target)
+                .num(ik_loop)
+                .num(ik_angle)
+                .write(writer)?;
+        }
+
+        writeln!(writer, "{IK_LINK_HEADER}")?;
+
+        for bone in &self.bones.inner {
+            let Some(ik) = &bone.ik else {
+                continue;
+            };
+
+            for link in &ik.links {
+                let (min, max): ([f32; 3], [f32; 3]) = match &link.limits {
+                    Some(limits) => (to_array(limits.min), to_array(limits.max)),
+                    None => ([0.0; 3], [0.0; 3]),
+                };
+
+                Row::new("PmxIKLink")
+                    .text(bone.name.local.as_str())
+                    .text(self.bone_name(&link.bone))
+                    .flag(link.limits.is_some())
+                    .floats(&[
+                        min[0].to_degrees(),
+                        max[0].to_degrees(),
+                        min[1].to_degrees(),
+                        max[1].to_degrees(),
+                        min[2].to_degrees(),
+                        max[2].to_degrees(),
+                    ])
+                    .write(writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_morphs_csv(&self, writer: &mut impl Write) -> Result<()> {
+        writeln!(writer, "{MORPH_HEADER}")?;
+
+        for morph in &self.morphs.inner {
+            Row::new("PmxMorph")
+                .text(morph.name.local.as_str())
+                .text(morph.name.universal.as_str())
+                .num(u8::from(morph.panel))
+                .num(morph.offsets.typ())
+                .write(writer)?;
+        }
+
+        let kinds = [
+            (GROUP_MORPH_HEADER, 0..=0),
+            (VERTEX_MORPH_HEADER, 1..=1),
+            (BONE_MORPH_HEADER, 2..=2),
+            (UV_MORPH_HEADER, 3..=7),
+            (MATERIAL_MORPH_HEADER, 8..=8),
+            (FLIP_MORPH_HEADER, 9..=9),
+            (IMPULSE_MORPH_HEADER, 10..=10),
+        ];
+
+        for (header, types) in kinds {
+            writeln!(writer, "{header}")?;
+
+            for morph in &self.morphs.inner {
+                if types.contains(&morph.offsets.typ()) {
+                    self.write_offsets_csv(writer, morph)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_offsets_csv(&self, writer: &mut impl Write, morph: &Morph) -> Result<()> {
+        let parent = morph.name.local.as_str();
+
+        match &morph.offsets {
+            Offsets::Group(offsets) => {
+                for offset in offsets {
+                    Row::new("PmxGroupMorph")
+                        .text(parent)
+                        .text(self.morph_name(&offset.morph))
+                        .num(offset.weight)
+                        .write(writer)?;
+                }
+            }
+            Offsets::Vertex(offsets) => {
+                for offset in offsets {
+                    let translation: [f32; 3] = to_array(offset.translation);
+
+                    Row::new("PmxVertexMorph")
+                        .text(parent)
+                        .num(offset.vertex.value())
+                        .floats(&translation)
+                        .write(writer)?;
+                }
+            }
+            Offsets::Bone(offsets) => {
+                for offset in offsets {
+                    let translation: [f32; 3] = to_array(offset.translation);
+                    let rotation = quaternion_to_euler(to_array(offset.rotation));
+
+                    Row::new("PmxBoneMorph")
+                        .text(parent)
+                        .text(self.bone_name(&offset.bone))
+                        .floats(&translation)
+                        .floats(&rotation.map(f32::to_degrees))
+                        .write(writer)?;
+                }
+            }
+            Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => {
+                for offset in offsets {
+                    let value: [f32; 4] = to_array(offset.offset);
+
+                    Row::new("PmxUVMorph")
+                        .text(parent)
+                        .num(offset.vertex.value())
+                        .floats(&value)
+                        .write(writer)?;
+                }
+            }
+            Offsets::Material(offsets) => {
+                for offset in offsets {
+                    let material = offset
+                        .material
+                        .get(&self.materials.inner)
+                        .map_or("", |material| material.name.local.as_str());
+
+                    let diffuse: [f32; 4] = to_array(offset.diffuse);
+                    let specular: [f32; 3] = to_array(offset.specular);
+                    let ambient: [f32; 3] = to_array(offset.ambient);
+                    let edge_color: [f32; 4] = to_array(offset.edge_color);
+                    let texture_tint: [f32; 4] = to_array(offset.texture_tint);
+                    let environment_tint: [f32; 4] = to_array(offset.environment_tint);
+                    let toon_tint: [f32; 4] = to_array(offset.toon_tint);
+
+                    Row::new("PmxMaterialMorph")
+                        .text(parent)
+                        .text(material)
+                        .num(u8::from(offset.operation))
+                        .floats(&diffuse)
+                        .floats(&specular)
+                        .num(offset.specular_strength)
+                        .floats(&ambient)
+                        .floats(&edge_color)
+                        .num(offset.edge_scale)
+                        .floats(&texture_tint)
+                        .floats(&environment_tint)
+                        .floats(&toon_tint)
+                        .write(writer)?;
+                }
+            }
+            Offsets::Flip(offsets) => {
+                for offset in offsets {
+                    Row::new("PmxFlipMorph")
+                        .text(parent)
+                        .text(self.morph_name(&offset.morph))
+                        .num(offset.weight)
+                        .write(writer)?;
+                }
+            }
+            Offsets::Impulse(offsets) => {
+                for offset in offsets {
+                    let rigid_body = offset
+                        .rigid_body
+                        .get(&self.rigid_bodies.inner)
+                        .map_or("", |body| body.name.local.as_str());
+                    let velocity: [f32; 3] = to_array(offset.velocity);
+                    let torque: [f32; 3] = to_array(offset.torque);
+
+                    Row::new("PmxImpulseMorph")
+                        .text(parent)
+                        .text(rigid_body)
+                        .flag(offset.local)
+                        .floats(&velocity)
+                        .floats(&torque)
+                        .write(writer)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn bone_name(&self, index: &BoneIndex) -> &str {
+        index
+            .get(&self.bones.inner)
+            .map_or("", |bone| bone.name.local.as_str())
+    }
+
+    fn morph_name(&self, index: &MorphIndex) -> &str {
+        index
+            .get(&self.morphs.inner)
+            .map_or("", |morph| morph.name.local.as_str())
+    }
+
+    fn texture_path(&self, index: &TextureIndex) -> &str {
+        index
+            .get(&self.textures.inner)
+            .map_or("", |texture| texture.path.as_str())
+    }
+
+    fn text(&self, text: &str) -> PmxText {
+        PmxText::new(text, self.header.globals.encoding)
+    }
+
+    fn import_material(
+        &mut self,
+        record: &Record,
+        textures: &mut HashMap<String, usize>,
+    ) -> Result<()> {
+        record.expect("PmxMaterial", 30)?;
+
+        let mut texture = |column: usize| -> TextureIndex {
+            let path = record.text(column);
+
+            if path.is_empty() {
+                return TextureIndex::nil();
+            }
+
+            let index = *textures.entry(path.to_string()).or_insert_with(|| {
+                self.textures.inner.push(Texture {
+                    path: PmxText::new(path, self.header.globals.encoding),
+                });
+                self.textures.inner.len() - 1
+            });
+
+            TextureIndex::new(index as i32)
+        };
+
+        let tex_idx = texture(26);
+        let env_idx = texture(27);
+
+        let toon = match Toon::internal_index_of(record.text(29)) {
+            Some(index) => Toon::Internal(index),
+            None => Toon::Texture(texture(29)),
+        };
+
+        let mut flags = 0;
+        for (column, flag) in [
+            (14, MaterialFlags::NO_CULL),
+            (15, MaterialFlags::GROUND_SHADOW),
+            (16, MaterialFlags::DRAW_SHADOW),
+            (17, MaterialFlags::RECEIVE_SHADOW),
+            (18, MaterialFlags::VERTEX_COLOR),
+            (20, MaterialFlags::EDGE),
+        ] {
+            if record.flag(column)? {
+                flags |= flag;
+            }
+        }
+        flags |= match record.int(19)? {
+            0 => 0,
+            1 => MaterialFlags::POINT_DRAW,
+            2 => MaterialFlags::LINE_DRAW,
+            _ => Err(record.invalid(19))?,
+        };
+
+        let env_blend = u8::try_from(record.int(28)?)
+            .ok()
+            .and_then(|blend| EnvironmentBlend::try_from(blend).ok())
+            .ok_or_else(|| record.invalid(28))?;
+
+        let name = record.text(1);
+        let existing = self
+            .materials
+            .inner
+            .iter()
+            .position(|material| material.name.local.as_str() == name);
+
+        let material = Material {
+            name: Name {
+                local: self.text(name),
+                universal: self.text(record.text(2)),
+            },
+            diffuse: record.vec4(3)?,
+            specular: record.vec3(7)?,
+            specular_strength: record.float(10)?,
+            ambient: record.vec3(11)?,
+            flags: MaterialFlags::from_raw(flags),
+            edge_color: record.vec4(22)?,
+            edge_scale: record.float(21)?,
+            tex_idx,
+            env_idx,
+            env_blend,
+            toon,
+            meta: self.text(record.text(30)),
+            surface_count: existing.map_or(0, |i| self.materials.inner[i].surface_count),
+        };
+
+        match existing {
+            Some(i) => self.materials.inner[i] = material,
+            None => self.materials.inner.push(material),
+        }
+
+        Ok(())
+    }
+
+    fn import_bones<'a>(
+        &mut self,
+        records: Vec<&Record>,
+        links: impl Iterator<Item = &'a Record>,
+    ) -> Result<()> {
+        // every bone row gets its index up front so references can point forward
+        let mut names = first_indices(self.bones.inner.iter().map(|b| b.name.local.as_str()));
+        let mut next = self.bones.inner.len();
+        for record in &records {
+            record.expect("PmxBone", 39)?;
+
+            names.entry(record.text(1).to_string()).or_insert_with(|| {
+                next += 1;
+                next - 1
+            });
+        }
+
+        for record in &records {
+            let bone_ref =
+                |column: usize| resolve(&names, record, column, "bone").map(BoneIndex::new);
+
+            let mut flags = 0;
+            for (column, flag) in [
+                (4, BoneFlags::PHYSICS_AFTER_DEFORM),
+                (8, BoneFlags::ROTATABLE),
+                (9, BoneFlags::TRANSLATABLE),
+                (10, BoneFlags::IK),
+                (11, BoneFlags::VISIBLE),
+                (12, BoneFlags::ENABLED),
+                (14, BoneFlags::INDEXED_TAIL),
+                (19, BoneFlags::INHERIT_LOCAL),
+                (20, BoneFlags::INHERIT_ROTATION),
+                (21, BoneFlags::INHERIT_TRANSLATION),
+                (24, BoneFlags::FIXED_AXIS),
+                (28, BoneFlags::LOCAL_AXES),
+                (35, BoneFlags::EXTERNAL_PARENT_DEFORM),
+            ] {
+                if record.flag(column)? {
+                    flags |= flag;
+                }
+            }
+            let flags = BoneFlags::from_raw(flags);
+
+            let tail = if flags.indexed_tail() {
+                Tail::Bone(bone_ref(15)?)
+            } else {
+                Tail::Position(record.vec3(16)?)
+            };
+
+            let inherit = (flags.inherit_rotation() || flags.inherit_translation())
+                .then(|| -> Result<Inherit> {
+                    Ok(Inherit {
+                        parent: bone_ref(23)?,
+                        weight: record.float(22)?,
+                    })
+                })
+                .transpose()?;
+
+            let local_axes = flags
+                .local_axes()
+                .then(|| -> Result<LocalAxes> {
+                    Ok(LocalAxes {
+                        x: record.vec3(29)?,
+                        z: record.vec3(32)?,
+                    })
+                })
+                .transpose()?;
+
+            let ik = flags
+                .ik()
+                .then(|| -> Result<Ik> {
+                    Ok(Ik {
+                        target: bone_ref(37)?,
+                        loop_count: record.int(38)?,
+                        limit_angle: record.float(39)?.to_radians(),
+                        links: Vec::new(),
+                    })
+                })
+                .transpose()?;
+
+            let bone = Bone {
+                name: Name {
+                    local: self.text(record.text(1)),
+                    universal: self.text(record.text(2)),
+                },
+                position: record.vec3(5)?,
+                parent: bone_ref(13)?,
+                layer: record.int(3)?,
+                flags,
+                tail,
+                inherit,
+                fixed_axis: flags.fixed_axis().then(|| record.vec3(25)).transpose()?,
+                local_axes,
+                external_parent: flags
+                    .external_parent_deform()
+                    .then(|| record.int(36))
+                    .transpose()?,
+                ik,
+            };
+
+            let index = names[record.text(1)];
+            if index < self.bones.inner.len() {
+                self.bones.inner[index] = bone;
+            } else {
+                self.bones.inner.push(bone);
+            }
+        }
+
+        for record in links {
+            record.expect("PmxIKLink", 9)?;
+
+            let bone = resolve(&names, record, 1, "bone")?;
+            let link = IkLink {
+                bone: BoneIndex::new(resolve(&names, record, 2, "bone")?),
+                limits: record
+                    .flag(3)?
+                    .then(|| -> Result<IkAngleLimit> {
+                        let [xl, xh, yl, yh, zl, zh] = record.floats::<6>(4)?;
+
+                        Ok(IkAngleLimit {
+                            min: from_array([xl, yl, zl].map(f32::to_radians)),
+                            max: from_array([xh, yh, zh].map(f32::to_radians)),
+                        })
+                    })
+                    .transpose()?,
+            };
+
+            let ik = usize::try_from(bone)
+                .ok()
+                .and_then(|bone| self.bones.inner.get_mut(bone))
+                .and_then(|bone| bone.ik.as_mut())
+                .ok_or(Error::Malformed {
+                    line: record.line,
+                    message: "IK link for a bone without IK",
+                })?;
+            ik.links.push(link);
+        }
+
+        Ok(())
+    }
+
+    fn import_vertex(&mut self, record: &Record, bones: &HashMap<String, usize>) -> Result<()> {
+        record.expect("PmxVertex", 44)?;
+
+        let bone_ref = |column: usize| resolve(bones, record, column, "bone").map(BoneIndex::new);
+
+        let indices = [bone_ref(28)?, bone_ref(30)?, bone_ref(32)?, bone_ref(34)?];
+        let weights = [
+            record.float(29)?,
+            record.float(31)?,
+            record.float(33)?,
+            record.float(35)?,
+        ];
+
+        let weight_deform = match record.int(27)? {
+            0 => WeightDeform::Bdef1 { index: indices[0] },
+            1 => WeightDeform::Bdef2 {
+                indices: [indices[0], indices[1]],
+                weights: [weights[0], 1.0 - weights[0]],
+            },
+            2 => WeightDeform::Bdef4 { indices, weights },
+            3 => WeightDeform::Sdef {
+                indices: [indices[0], indices[1]],
+                weights: [weights[0], 1.0 - weights[0]],
+                c: record.vec3(36)?,
+                r0: record.vec3(39)?,
+                r1: record.vec3(42)?,
+            },
+            4 => WeightDeform::Qdef { indices, weights },
+            _ => Err(record.invalid(27))?,
+        };
+
+        let additional_count = self.header.globals.vec4_additional as usize;
+        let extra_vec4 = (additional_count != 0)
+            .then(|| {
+                (0..additional_count)
+                    .map(|channel| record.vec4(11 + channel * 4))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?;
+
+        let vertex = Vertex {
+            pos: record.vec3(2)?,
+            normal: record.vec3(5)?,
+            uv: from_array(record.floats::<2>(9)?),
+            extra_vec4,
+            weight_deform,
+            edge_scale: record.float(8)?,
+        };
+
+        let index = usize::try_from(record.int(1)?).map_err(|_| record.invalid(1))?;
+        match index.cmp(&self.vertices.inner.len()) {
+            std::cmp::Ordering::Less => self.vertices.inner[index] = vertex,
+            std::cmp::Ordering::Equal => self.vertices.inner.push(vertex),
+            std::cmp::Ordering::Greater => Err(Error::Malformed {
+                line: record.line,
+                message: "vertex index past the end of the vertex section",
+            })?,
+        }
+
+        Ok(())
+    }
+
+    fn import_morphs<'a>(
+        &mut self,
+        records: Vec<&Record>,
+        offsets: impl Iterator<Item = &'a Record>,
+    ) -> Result<()> {
+        let mut names = first_indices(self.morphs.inner.iter().map(|m| m.name.local.as_str()));
+
+        for record in records {
+            record.expect("PmxMorph", 4)?;
+
+            let panel = u8::try_from(record.int(3)?)
+                .ok()
+                .and_then(|panel| Panel::try_from(panel).ok())
+                .ok_or_else(|| record.invalid(3))?;
+
+            let offsets = match record.int(4)? {
+                0 => Offsets::Group(Vec::new()),
+                1 => Offsets::Vertex(Vec::new()),
+                2 => Offsets::Bone(Vec::new()),
+                3 => Offsets::Uv(Vec::new()),
+                typ @ 4..=7 => Offsets::AdditionalUv(typ as u8 - 4, Vec::new()),
+                8 => Offsets::Material(Vec::new()),
+                9 => Offsets::Flip(Vec::new()),
+                10 => Offsets::Impulse(Vec::new()),
+                _ => Err(record.invalid(4))?,
+            };
+
+            let morph = Morph {
+                name: Name {
+                    local: self.text(record.text(1)),
+                    universal: self.text(record.text(2)),
+                },
+                panel,
+                offsets,
+            };
+
+            match names.get(record.text(1)) {
+                Some(&i) => self.morphs.inner[i] = morph,
+                None => {
+                    names.insert(record.text(1).to_string(), self.morphs.inner.len());
+                    self.morphs.inner.push(morph);
+                }
+            }
+        }
+
+        let bones = first_indices(self.bones.inner.iter().map(|b| b.name.local.as_str()));
+        let materials = first_indices(self.materials.inner.iter().map(|m| m.name.local.as_str()));
+        let rigid_bodies = first_indices(
+            self.rigid_bodies
+                .inner
+                .iter()
+                .map(|r| r.name.local.as_str()),
+        );
+
+        for record in offsets {
+            let kind = OFFSET_KINDS
+                .iter()
+                .find(|&&kind| kind == record.fields[0])
+                .copied()
+                .unwrap_or_default();
+
+            let morph = usize::try_from(resolve(&names, record, 1, "morph")?).map_err(|_| {
+                Error::Malformed {
+                    line: record.line,
+                    message: "offset row without a morph name",
+                }
+            })?;
+            let mismatch = Error::Malformed {
+                line: record.line,
+                message: "offset row does not match the type of its morph",
+            };
+
+            match (kind, &mut self.morphs.inner[morph].offsets) {
+                ("PmxGroupMorph", Offsets::Group(offsets)) => {
+                    record.expect(kind, 3)?;
+                    offsets.push(GroupOffset {
+                        morph: MorphIndex::new(resolve(&names, record, 2, "morph")?),
+                        weight: record.float(3)?,
+                    });
+                }
+                ("PmxFlipMorph", Offsets::Flip(offsets)) => {
+                    record.expect(kind, 3)?;
+                    offsets.push(FlipOffset {
+                        morph: MorphIndex::new(resolve(&names, record, 2, "morph")?),
+                        weight: record.float(3)?,
+                    });
+                }
+                ("PmxVertexMorph", Offsets::Vertex(offsets)) => {
+                    record.expect(kind, 5)?;
+                    offsets.push(VertexOffset {
+                        vertex: VertexIndex::new(record.int(2)?),
+                        translation: record.vec3(3)?,
+                    });
+                }
+                ("PmxUVMorph", Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets)) => {
+                    record.expect(kind, 6)?;
+                    offsets.push(UvOffset {
+                        vertex: VertexIndex::new(record.int(2)?),
+                        offset: record.vec4(3)?,
+                    });
+                }
+                ("PmxBoneMorph", Offsets::Bone(offsets)) => {
+                    record.expect(kind, 8)?;
+                    let rotation = record.floats::<3>(6)?.map(f32::to_radians);
+                    offsets.push(BoneOffset {
+                        bone: BoneIndex::new(resolve(&bones, record, 2, "bone")?),
+                        translation: record.vec3(3)?,
+                        rotation: from_array(euler_to_quaternion(rotation)),
+                    });
+                }
+                ("PmxMaterialMorph", Offsets::Material(offsets)) => {
+                    record.expect(kind, 31)?;
+                    let operation = u8::try_from(record.int(3)?)
+                        .ok()
+                        .and_then(|operation| MaterialOperation::try_from(operation).ok())
+                        .ok_or_else(|| record.invalid(3))?;
+                    offsets.push(MaterialOffset {
+                        material: MaterialIndex::new(resolve(&materials, record, 2, "material")?),
+                        operation,
+                        diffuse: record.vec4(4)?,
+                        specular: record.vec3(8)?,
+                        specular_strength: record.float(11)?,
+                        ambient: record.vec3(12)?,
+                        edge_color: record.vec4(15)?,
+                        edge_scale: record.float(19)?,
+                        texture_tint: record.vec4(20)?,
+                        environment_tint: record.vec4(24)?,
+                        toon_tint: record.vec4(28)?,
+                    });
+                }
+                ("PmxImpulseMorph", Offsets::Impulse(offsets)) => {
+                    record.expect(kind, 9)?;
+                    offsets.push(ImpulseOffset {
+                        rigid_body: RigidBodyIndex::new(resolve(
+                            &rigid_bodies,
+                            record,
+                            2,
+                            "rigid body",
+                        )?),
+                        local: record.flag(3)?,
+                        velocity: record.vec3(4)?,
+                        torque: record.vec3(7)?,
+                    });
+                }
+                _ => Err(mismatch)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const OFFSET_KINDS: [&str; 7] = [
+    "PmxGroupMorph",
+    "PmxVertexMorph",
+    "PmxBoneMorph",
+    "PmxUVMorph",
+    "PmxMaterialMorph",
+    "PmxFlipMorph",
+    "PmxImpulseMorph",
+];
+
+fn weight_deform_type(weight_deform: &WeightDeform) -> u8 {
+    match weight_deform {
+        WeightDeform::Bdef1 { .. } => 0,
+        WeightDeform::Bdef2 { .. } => 1,
+        WeightDeform::Bdef4 { .. } => 2,
+        WeightDeform::Sdef { .. } => 3,
+        WeightDeform::Qdef { .. } => 4,
+    }
+}
+
+/// Maps names to the index of their first occurrence.
+fn first_indices<'a>(names: impl Iterator<Item = &'a str>) -> HashMap<String, usize> {
+    let mut indices = HashMap::new();
+
+    for (i, name) in names.enumerate() {
+        indices.entry(name.to_string()).or_insert(i);
+    }
+
+    indices
+}
+
+/// Looks up the name in a column, an empty name is the nil index.
+fn resolve(
+    names: &HashMap<String, usize>,
+    record: &Record,
+    column: usize,
+    kind: &'static str,
+) -> Result<i32> {
+    let name = record.text(column);
+
+    if name.is_empty() {
+        return Ok(-1);
+    }
+
+    names
+        .get(name)
+        .map(|&i| i as i32)
+        .ok_or_else(|| Error::UnknownReference {
+            line: record.line,
+            kind,
+            name: name.to_string(),
+        })
+}
+
+/// Euler angles in radians to a quaternion (XYZW), rotating around Z, then X, then Y.
+///
+/// This is the yaw-pitch-roll order PMX Editor uses for angles it shows in degrees.
+fn euler_to_quaternion([x, y, z]: [f32; 3]) -> [f32; 4] {
+    let (sx, cx) = (x / 2.0).sin_cos();
+    let (sy, cy) = (y / 2.0).sin_cos();
+    let (sz, cz) = (z / 2.0).sin_cos();
+
+    [
+        cy * sx * cz + sy * cx * sz,
+        sy * cx * cz - cy * sx * sz,
+        cy * cx * sz - sy * sx * cz,
+        cy * cx * cz + sy * sx * sz,
+    ]
+}
+
+/// Inverse of [`euler_to_quaternion`].
+fn quaternion_to_euler([x, y, z, w]: [f32; 4]) -> [f32; 3] {
+    let sin_x = (2.0 * (w * x - y * z)).clamp(-1.0, 1.0);
+
+    [
+        sin_x.asin(),
+        (2.0 * (x * z + w * y)).atan2(1.0 - 2.0 * (x * x + y * y)),
+        (2.0 * (x * y + w * z)).atan2(1.0 - 2.0 * (x * x + z * z)),
+    ]
+}
+
+/// A parsed CSV row and the line it started on.
+struct Record {
+    line: usize,
+    fields: Vec<String>,
+}
+
+impl Record {
+    /// Checks that the row has the columns up to and including `last`.
+    fn expect(&self, kind: &'static str, last: usize) -> Result<()> {
+        if self.fields.len() <= last {
+            Err(Error::MissingColumns {
+                line: self.line,
+                kind,
+                expected: last + 1,
+                found: self.fields.len(),
+            })?
+        }
+
+        Ok(())
+    }
+
+    fn invalid(&self, column: usize) -> Error {
+        Error::InvalidValue {
+            line: self.line,
+            column: column + 1,
+            value: self.text(column).to_string(),
+        }
+    }
+
+    fn text(&self, column: usize) -> &str {
+        self.fields.get(column).map_or("", String::as_str)
+    }
+
+    fn float(&self, column: usize) -> Result<f32> {
+        self.text(column)
+            .trim()
+            .parse()
+            .map_err(|_| self.invalid(column))
+    }
+
+    fn int(&self, column: usize) -> Result<i32> {
+        self.text(column)
+            .trim()
+            .parse()
+            .map_err(|_| self.invalid(column))
+    }
+
+    fn flag(&self, column: usize) -> Result<bool> {
+        Ok(self.int(column)? != 0)
+    }
+
+    fn floats<const N: usize>(&self, first: usize) -> Result<[f32; N]> {
+        let mut values = [0.0; N];
+
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.float(first + i)?;
+        }
+
+        Ok(values)
+    }
+
+    fn vec3(&self, first: usize) -> Result<Vec3> {
+        Ok(from_array(self.floats::<3>(first)?))
+    }
+
+    fn vec4(&self, first: usize) -> Result<Vec4> {
+        Ok(from_array(self.floats::<4>(first)?))
+    }
+}
+
+/// Splits CSV text into rows, skipping empty lines and `;` comment lines.
+///
+/// Quoted fields may contain commas, newlines and `""` escaped quotes.
+fn parse_records(text: &str) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while chars.peek().is_some() {
+        let start = line;
+
+        if chars.peek() == Some(&';') {
+            for c in chars.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+            }
+            line += 1;
+            continue;
+        }
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+
+        loop {
+            match chars.next() {
+                None if quoted => Err(Error::UnterminatedQuote { line: start })?,
+                None => break,
+                Some('"') if quoted => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                }
+                Some('"') if field.is_empty() => quoted = true,
+                Some(c) if quoted => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+                Some(',') => fields.push(std::mem::take(&mut field)),
+                Some('\r') => {}
+                Some('\n') => {
+                    line += 1;
+                    break;
+                }
+                Some(c) => field.push(c),
+            }
+        }
+        fields.push(field);
+
+        if fields.len() > 1 || !fields[0].is_empty() {
+            records.push(Record {
+                line: start,
+                fields,
+            });
+        }
+    }
+
+    Ok(records)
+}
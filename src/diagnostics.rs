@@ -0,0 +1,369 @@
+//! Non-fatal anomalies found while parsing.
+//!
+//! Plenty of models in the wild contain values that are technically readable but almost certainly
+//! mistakes: weights that do not add up, nil bones on vertices, NaN coordinates, flag bits the
+//! format does not define. Parsing accepts them as they are, [`Pmx::parse_with_diagnostics`]
+//! additionally reports them to a [`ParseDiagnostics`] so tools can point them out.
+
+use core::fmt;
+
+use std::{
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use crate::{
+    bone::{BoneFlags, Tail},
+    display_frame::FrameEntry,
+    morph::Offsets,
+    pmx::{Pmx, Result, Version},
+    soft_body::SoftBodyFlags,
+    types::{Index, to_array},
+    vertex::WeightDeform,
+    visit::Section,
+};
+
+/// How far the weights of a vertex may be off from 1 before it gets reported.
+const WEIGHT_TOLERANCE: f32 = 1e-3;
+
+/// A single anomaly, located by section and element index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub(crate) section: Section,
+    pub(crate) index: usize,
+    pub(crate) kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn kind(&self) -> &DiagnosticKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.section, self.index, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// A nil index in a field where it leaves the element without effect.
+    UnexpectedNil { field: &'static str },
+    /// The bone weights of a vertex do not add up to 1.
+    WeightSum { sum: f32 },
+    /// A NaN or infinite value.
+    NonFinite { field: &'static str },
+    /// Flag bits that have no meaning in the file's version.
+    UnknownFlags { field: &'static str, bits: u16 },
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticKind::UnexpectedNil { field } => write!(f, "{field} is nil"),
+            DiagnosticKind::WeightSum { sum } => write!(f, "weights add up to {sum}"),
+            DiagnosticKind::NonFinite { field } => write!(f, "{field} is not finite"),
+            DiagnosticKind::UnknownFlags { field, bits } => {
+                write!(f, "{field} has unknown bits {bits:#06x}")
+            }
+        }
+    }
+}
+
+/// Collects the diagnostics of a parse, in section order.
+#[derive(Debug, Clone, Default)]
+pub struct ParseDiagnostics {
+    pub(crate) diagnostics: Vec<Diagnostic>,
+}
+
+impl ParseDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.diagnostics.clear();
+    }
+
+    fn push(&mut self, section: Section, index: usize, kind: DiagnosticKind) {
+        self.diagnostics.push(Diagnostic {
+            section,
+            index,
+            kind,
+        });
+    }
+
+    fn nil(
+        &mut self,
+        section: Section,
+        index: usize,
+        field: &'static str,
+        value: impl Into<Index>,
+    ) {
+        if value.into().is_nil() {
+            self.push(section, index, DiagnosticKind::UnexpectedNil { field });
+        }
+    }
+
+    fn finite(&mut self, section: Section, index: usize, field: &'static str, values: &[f32]) {
+        if !values.iter().all(|value| value.is_finite()) {
+            self.push(section, index, DiagnosticKind::NonFinite { field });
+        }
+    }
+
+    fn flags(&mut self, section: Section, index: usize, field: &'static str, raw: u16, known: u16) {
+        if raw & !known != 0 {
+            self.push(
+                section,
+                index,
+                DiagnosticKind::UnknownFlags {
+                    field,
+                    bits: raw & !known,
+                },
+            );
+        }
+    }
+}
+
+impl fmt::Display for ParseDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{diagnostic}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pmx {
+    /// Opens a `.pmx` file like [`Pmx::open`], reporting anomalies to `diagnostics`.
+    pub fn open_with_diagnostics(path: &Path, diagnostics: &mut ParseDiagnostics) -> Result<Self> {
+        let fh = std::fs::File::open(path)?;
+
+        let mut reader = BufReader::new(fh);
+
+        Self::parse_with_diagnostics(&mut reader, diagnostics)
+    }
+
+    /// Parses a model like [`Pmx::parse`], reporting anomalies to `diagnostics`.
+    ///
+    /// Only malformed files fail, the diagnostics never change what gets parsed.
+    pub fn parse_with_diagnostics(
+        reader: &mut impl Read,
+        diagnostics: &mut ParseDiagnostics,
+    ) -> Result<Self> {
+        let pmx = Self::parse(reader)?;
+
+        pmx.diagnose(diagnostics);
+
+        Ok(pmx)
+    }
+
+    fn diagnose(&self, d: &mut ParseDiagnostics) {
+        let version = self.header.version;
+
+        for (i, vertex) in self.vertices.inner.iter().enumerate() {
+            let s = Section::Vertices;
+            let position: [f32; 3] = to_array(vertex.pos);
+            let normal: [f32; 3] = to_array(vertex.normal);
+            let uv: [f32; 2] = to_array(vertex.uv);
+
+            d.finite(s, i, "position", &position);
+            d.finite(s, i, "normal", &normal);
+            d.finite(s, i, "UV", &uv);
+            d.finite(s, i, "edge scale", &[vertex.edge_scale]);
+            for additional in vertex.additional_vec4s() {
+                let additional: [f32; 4] = to_array(*additional);
+                d.finite(s, i, "additional vec4", &additional);
+            }
+
+            let (indices, weights) = vertex.weight_deform.bone_influences();
+            d.finite(s, i, "weights", &weights);
+            for (index, weight) in indices.into_iter().zip(weights) {
+                if weight != 0.0 {
+                    d.nil(s, i, "weighted bone", Index::new(index, true));
+                }
+            }
+            if let WeightDeform::Bdef4 { weights, .. } | WeightDeform::Qdef { weights, .. } =
+                &vertex.weight_deform
+            {
+                let sum: f32 = weights.iter().sum();
+                if (sum - 1.0).abs() > WEIGHT_TOLERANCE {
+                    d.push(s, i, DiagnosticKind::WeightSum { sum });
+                }
+            }
+        }
+
+        for (i, surface) in self.surfaces.inner.iter().enumerate() {
+            d.nil(Section::Surfaces, i, "vertex", surface.index);
+        }
+
+        // the last three material flags were added in 2.1
+        let material_flags = match version {
+            Version::V2_0 => 0x1F,
+            Version::V2_1 => u8::MAX,
+        };
+        for (i, material) in self.materials.inner.iter().enumerate() {
+            let s = Section::Materials;
+            let diffuse: [f32; 4] = to_array(material.diffuse);
+            let specular: [f32; 3] = to_array(material.specular);
+            let ambient: [f32; 3] = to_array(material.ambient);
+            let edge_color: [f32; 4] = to_array(material.edge_color);
+
+            d.finite(s, i, "diffuse", &diffuse);
+            d.finite(s, i, "specular", &specular);
+            d.finite(s, i, "specular strength", &[material.specular_strength]);
+            d.finite(s, i, "ambient", &ambient);
+            d.finite(s, i, "edge color", &edge_color);
+            d.finite(s, i, "edge scale", &[material.edge_scale]);
+            d.flags(
+                s,
+                i,
+                "flags",
+                u16::from(material.flags.raw),
+                u16::from(material_flags),
+            );
+        }
+
+        let bone_flags = BoneFlags::INDEXED_TAIL
+            | BoneFlags::ROTATABLE
+            | BoneFlags::TRANSLATABLE
+            | BoneFlags::VISIBLE
+            | BoneFlags::ENABLED
+            | BoneFlags::IK
+            | BoneFlags::INHERIT_LOCAL
+            | BoneFlags::INHERIT_ROTATION
+            | BoneFlags::INHERIT_TRANSLATION
+            | BoneFlags::FIXED_AXIS
+            | BoneFlags::LOCAL_AXES
+            | BoneFlags::PHYSICS_AFTER_DEFORM
+            | BoneFlags::EXTERNAL_PARENT_DEFORM;
+        for (i, bone) in self.bones.inner.iter().enumerate() {
+            let s = Section::Bones;
+            let position: [f32; 3] = to_array(bone.position);
+
+            d.finite(s, i, "position", &position);
+            if let Tail::Position(offset) = &bone.tail {
+                let offset: [f32; 3] = to_array(*offset);
+                d.finite(s, i, "tail", &offset);
+            }
+            if let Some(inherit) = &bone.inherit {
+                d.nil(s, i, "inherit parent", inherit.parent);
+                d.finite(s, i, "inherit weight", &[inherit.weight]);
+            }
+            if let Some(ik) = &bone.ik {
+                d.nil(s, i, "IK target", ik.target);
+                for link in &ik.links {
+                    d.nil(s, i, "IK link", link.bone);
+                }
+            }
+            d.flags(s, i, "flags", bone.flags.raw, bone_flags);
+        }
+
+        for (i, morph) in self.morphs.inner.iter().enumerate() {
+            let s = Section::Morphs;
+
+            match &morph.offsets {
+                Offsets::Group(offsets) => offsets.iter().for_each(|o| {
+                    d.nil(s, i, "group offset", o.morph);
+                    d.finite(s, i, "group offset", &[o.weight]);
+                }),
+                Offsets::Flip(offsets) => offsets.iter().for_each(|o| {
+                    d.nil(s, i, "flip offset", o.morph);
+                    d.finite(s, i, "flip offset", &[o.weight]);
+                }),
+                Offsets::Vertex(offsets) => offsets.iter().for_each(|o| {
+                    let translation: [f32; 3] = to_array(o.translation);
+                    d.nil(s, i, "vertex offset", o.vertex);
+                    d.finite(s, i, "vertex offset", &translation);
+                }),
+                Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => {
+                    offsets.iter().for_each(|o| {
+                        let offset: [f32; 4] = to_array(o.offset);
+                        d.nil(s, i, "UV offset", o.vertex);
+                        d.finite(s, i, "UV offset", &offset);
+                    })
+                }
+                Offsets::Bone(offsets) => offsets.iter().for_each(|o| {
+                    let translation: [f32; 3] = to_array(o.translation);
+                    let rotation: [f32; 4] = to_array(o.rotation);
+                    d.nil(s, i, "bone offset", o.bone);
+                    d.finite(s, i, "bone offset", &translation);
+                    d.finite(s, i, "bone offset", &rotation);
+                }),
+                Offsets::Material(_) => {}
+                Offsets::Impulse(offsets) => offsets.iter().for_each(|o| {
+                    d.nil(s, i, "impulse offset", o.rigid_body);
+                }),
+            }
+        }
+
+        for (i, frame) in self.display_frames.inner.iter().enumerate() {
+            for entry in &frame.entries {
+                match entry {
+                    FrameEntry::Bone(bone) => d.nil(Section::DisplayFrames, i, "bone entry", *bone),
+                    FrameEntry::Morph(morph) => {
+                        d.nil(Section::DisplayFrames, i, "morph entry", *morph)
+                    }
+                }
+            }
+        }
+
+        for (i, rigid_body) in self.rigid_bodies.inner.iter().enumerate() {
+            let s = Section::RigidBodies;
+            let size: [f32; 3] = to_array(rigid_body.size);
+            let position: [f32; 3] = to_array(rigid_body.position);
+            let rotation: [f32; 3] = to_array(rigid_body.rotation);
+
+            d.finite(s, i, "size", &size);
+            d.finite(s, i, "position", &position);
+            d.finite(s, i, "rotation", &rotation);
+            d.finite(s, i, "mass", &[rigid_body.mass]);
+        }
+
+        for (i, joint) in self.joints.inner.iter().enumerate() {
+            let s = Section::Joints;
+            let position: [f32; 3] = to_array(joint.position);
+            let rotation: [f32; 3] = to_array(joint.rotation);
+
+            d.nil(s, i, "rigid body A", joint.rigid_body_a);
+            d.nil(s, i, "rigid body B", joint.rigid_body_b);
+            d.finite(s, i, "position", &position);
+            d.finite(s, i, "rotation", &rotation);
+        }
+
+        let soft_body_flags =
+            SoftBodyFlags::B_LINK | SoftBodyFlags::CLUSTER_CREATION | SoftBodyFlags::LINK_CROSSING;
+        let soft_bodies = self.soft_bodies.as_ref().map_or(&[][..], |s| &s.inner);
+        for (i, soft_body) in soft_bodies.iter().enumerate() {
+            d.flags(
+                Section::SoftBodies,
+                i,
+                "flags",
+                u16::from(soft_body.flags.raw),
+                u16::from(soft_body_flags),
+            );
+        }
+    }
+}
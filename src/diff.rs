@@ -0,0 +1,607 @@
+//! Comparing two models element by element.
+//!
+//! [`Pmx::diff`] reports the vertices, materials, bones and morphs that were added, removed or
+//! modified between two versions of a model. Vertices are paired by index. Materials, bones and
+//! morphs are paired by name, so reordering them in PMX Editor is not reported as a change of
+//! every element after the moved one. Of several elements with the same name, the first is paired
+//! with the first and so on.
+//!
+//! References to bones, materials and morphs are compared by the name of the element they refer
+//! to for the same reason, and floats are compared with a tolerance, as a file saved again by an
+//! editor often differs in the last bits.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::{
+    bone::{Bone, Tail},
+    material::{Material, Toon},
+    morph::{Morph, Offsets},
+    pmx::Pmx,
+    types::{BoneIndex, MaterialIndex, MorphIndex, TextureIndex, to_array},
+    vertex::{Vertex, WeightDeform},
+    visit::Section,
+};
+
+/// Settings for [`Pmx::diff`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffOptions {
+    pub(crate) tolerance: f32,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self { tolerance: 1e-5 }
+    }
+}
+
+impl DiffOptions {
+    /// Options with a tolerance of `1e-5`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tolerance(&self) -> f32 {
+        self.tolerance
+    }
+
+    /// The largest difference between two floats that still counts as equal.
+    pub fn set_tolerance(&mut self, tolerance: f32) {
+        self.tolerance = tolerance;
+    }
+}
+
+/// The differences between two models, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelDiff {
+    pub(crate) changes: Vec<Change>,
+}
+
+/// A single difference between two models.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub(crate) section: Section,
+    pub(crate) name: String,
+    pub(crate) kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    /// The element only exists in the second model, at `index`.
+    Added { index: usize },
+    /// The element only exists in the first model, at `index`.
+    Removed { index: usize },
+    /// The element exists in both models with different values.
+    Modified {
+        old: usize,
+        new: usize,
+        /// The names of the fields that differ.
+        fields: Vec<&'static str>,
+    },
+}
+
+impl ModelDiff {
+    /// Returns true if no differences were found.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes, by section in file order, then removed, modified and added elements.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// The changes in one section.
+    pub fn section(&self, section: Section) -> impl Iterator<Item = &Change> {
+        self.changes
+            .iter()
+            .filter(move |change| change.section == section)
+    }
+}
+
+impl Change {
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    /// The name of the element, empty for vertices.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> &ChangeKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for ModelDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = if self.name.is_empty() {
+            String::new()
+        } else {
+            format!(" \"{}\"", self.name)
+        };
+
+        match &self.kind {
+            ChangeKind::Added { index } => write!(f, "+ {} {index}{name}", self.section),
+            ChangeKind::Removed { index } => write!(f, "- {} {index}{name}", self.section),
+            ChangeKind::Modified { old, new, fields } => {
+                let index = if old == new {
+                    old.to_string()
+                } else {
+                    format!("{old} -> {new}")
+                };
+
+                write!(f, "~ {} {index}{name}: {}", self.section, fields.join(", "))
+            }
+        }
+    }
+}
+
+impl Pmx {
+    /// Compares the model with `other`, see [`ModelDiff`].
+    pub fn diff(&self, other: &Pmx, options: &DiffOptions) -> ModelDiff {
+        let mut differ = Differ {
+            old: Names { pmx: self },
+            new: Names { pmx: other },
+            tolerance: options.tolerance,
+            changes: Vec::new(),
+        };
+
+        let (vertices, other_vertices) = (&self.vertices.inner, &other.vertices.inner);
+        let paired = vertices.len().min(other_vertices.len());
+        for index in paired..vertices.len() {
+            differ.push(Section::Vertices, "", ChangeKind::Removed { index });
+        }
+        for (index, (a, b)) in vertices.iter().zip(other_vertices).enumerate() {
+            if let Some(kind) = differ.compare(a, b, index, index, vertex_fields) {
+                differ.push(Section::Vertices, "", kind);
+            }
+        }
+        for index in paired..other_vertices.len() {
+            differ.push(Section::Vertices, "", ChangeKind::Added { index });
+        }
+
+        differ.named(
+            Section::Materials,
+            &self.materials.inner,
+            &other.materials.inner,
+            |m| m.name.local.as_str(),
+            material_fields,
+        );
+        differ.named(
+            Section::Bones,
+            &self.bones.inner,
+            &other.bones.inner,
+            |b| b.name.local.as_str(),
+            bone_fields,
+        );
+        differ.named(
+            Section::Morphs,
+            &self.morphs.inner,
+            &other.morphs.inner,
+            |m| m.name.local.as_str(),
+            morph_fields,
+        );
+
+        ModelDiff {
+            changes: differ.changes,
+        }
+    }
+}
+
+/// Collects the changes between two models.
+struct Differ<'a> {
+    old: Names<'a>,
+    new: Names<'a>,
+    tolerance: f32,
+    changes: Vec<Change>,
+}
+
+impl Differ<'_> {
+    fn push(&mut self, section: Section, name: &str, kind: ChangeKind) {
+        self.changes.push(Change {
+            section,
+            name: name.to_string(),
+            kind,
+        });
+    }
+
+    /// The modification between two paired elements, `None` if they are equal.
+    fn compare<T>(
+        &self,
+        a: &T,
+        b: &T,
+        old: usize,
+        new: usize,
+        fields: impl Fn(&T, &Names) -> Fields,
+    ) -> Option<ChangeKind> {
+        let fields = fields(a, &self.old).changed(&fields(b, &self.new), self.tolerance);
+
+        (!fields.is_empty()).then_some(ChangeKind::Modified { old, new, fields })
+    }
+
+    /// Pairs the elements of a section by name and reports the differences.
+    fn named<T>(
+        &mut self,
+        section: Section,
+        old: &[T],
+        new: &[T],
+        name: impl Fn(&T) -> &str,
+        fields: impl Fn(&T, &Names) -> Fields,
+    ) {
+        // the indices of each name in the new model, the next one to pair last
+        let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, element) in new.iter().enumerate().rev() {
+            by_name.entry(name(element)).or_default().push(index);
+        }
+
+        let mut paired = vec![false; new.len()];
+        let mut modified = Vec::new();
+
+        for (index, element) in old.iter().enumerate() {
+            let element_name = name(element);
+            let Some(other) = by_name.get_mut(element_name).and_then(Vec::pop) else {
+                self.push(section, element_name, ChangeKind::Removed { index });
+                continue;
+            };
+            paired[other] = true;
+
+            if let Some(kind) = self.compare(element, &new[other], index, other, &fields) {
+                modified.push((element_name, kind));
+            }
+        }
+
+        for (element_name, kind) in modified {
+            self.push(section, element_name, kind);
+        }
+        for (index, element) in new.iter().enumerate() {
+            if !paired[index] {
+                self.push(section, name(element), ChangeKind::Added { index });
+            }
+        }
+    }
+}
+
+/// The names references are compared by.
+struct Names<'a> {
+    pmx: &'a Pmx,
+}
+
+impl Names<'_> {
+    fn bone(&self, index: &BoneIndex) -> String {
+        match index.as_usize() {
+            None => "none".to_string(),
+            Some(i) => self
+                .pmx
+                .bones
+                .inner
+                .get(i)
+                .map_or_else(|| format!("#{i}"), |b| b.name.local.as_str().to_string()),
+        }
+    }
+
+    fn material(&self, index: &MaterialIndex) -> String {
+        match index.as_usize() {
+            None => "none".to_string(),
+            Some(i) => self
+                .pmx
+                .materials
+                .inner
+                .get(i)
+                .map_or_else(|| format!("#{i}"), |m| m.name.local.as_str().to_string()),
+        }
+    }
+
+    fn morph(&self, index: &MorphIndex) -> String {
+        match index.as_usize() {
+            None => "none".to_string(),
+            Some(i) => self
+                .pmx
+                .morphs
+                .inner
+                .get(i)
+                .map_or_else(|| format!("#{i}"), |m| m.name.local.as_str().to_string()),
+        }
+    }
+
+    fn texture(&self, index: &TextureIndex) -> String {
+        match index.as_usize() {
+            None => "none".to_string(),
+            Some(i) => self
+                .pmx
+                .textures
+                .inner
+                .get(i)
+                .map_or_else(|| format!("#{i}"), |t| t.path.as_str().to_string()),
+        }
+    }
+}
+
+/// The values of an element, in the same order for every element of a section.
+#[derive(Default)]
+struct Fields {
+    fields: Vec<(&'static str, Field)>,
+}
+
+enum Field {
+    Floats(Vec<f32>),
+    Exact(String),
+}
+
+impl Fields {
+    fn floats(mut self, name: &'static str, values: impl IntoIterator<Item = f32>) -> Self {
+        self.fields
+            .push((name, Field::Floats(values.into_iter().collect())));
+        self
+    }
+
+    fn exact(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.fields.push((name, Field::Exact(value.to_string())));
+        self
+    }
+
+    /// The names of the fields that differ from `other`.
+    fn changed(&self, other: &Fields, tolerance: f32) -> Vec<&'static str> {
+        let mut changed = self
+            .fields
+            .iter()
+            .zip(&other.fields)
+            .filter(|((_, a), (_, b))| match (a, b) {
+                (Field::Floats(a), Field::Floats(b)) => {
+                    a.len() != b.len() || a.iter().zip(b).any(|(a, b)| (a - b).abs() > tolerance)
+                }
+                (Field::Exact(a), Field::Exact(b)) => a != b,
+                _ => true,
+            })
+            .map(|((name, _), _)| *name)
+            .collect::<Vec<_>>();
+
+        // a field split into an exact and a float part is reported once
+        changed.dedup();
+        changed
+    }
+}
+
+fn vertex_fields(vertex: &Vertex, names: &Names) -> Fields {
+    let pos: [f32; 3] = to_array(vertex.pos);
+    let normal: [f32; 3] = to_array(vertex.normal);
+    let uv: [f32; 2] = to_array(vertex.uv);
+    let additional = vertex.extra_vec4.iter().flatten().flat_map(|v| {
+        let v: [f32; 4] = to_array(*v);
+        v
+    });
+
+    let deform = &vertex.weight_deform;
+    let (kind, weights, sdef): (_, &[f32], _) = match deform {
+        WeightDeform::Bdef1 { .. } => ("BDEF1", &[], None),
+        WeightDeform::Bdef2 { weights, .. } => ("BDEF2", weights, None),
+        WeightDeform::Bdef4 { weights, .. } => ("BDEF4", weights, None),
+        WeightDeform::Sdef {
+            weights, c, r0, r1, ..
+        } => ("SDEF", weights, Some([*c, *r0, *r1])),
+        WeightDeform::Qdef { weights, .. } => ("QDEF", weights, None),
+    };
+    let bones: Vec<String> = deform
+        .bone_indices()
+        .iter()
+        .map(|b| names.bone(b))
+        .collect();
+    let sdef = sdef.into_iter().flatten().flat_map(|v| {
+        let v: [f32; 3] = to_array(v);
+        v
+    });
+
+    Fields::default()
+        .floats("position", pos)
+        .floats("normal", normal)
+        .floats("uv", uv)
+        .floats("additional uv", additional)
+        .exact("deform", kind)
+        .exact("bones", bones.join(", "))
+        .floats("weights", weights.iter().copied())
+        .floats("sdef", sdef)
+        .floats("edge scale", [vertex.edge_scale])
+}
+
+fn material_fields(material: &Material, names: &Names) -> Fields {
+    let diffuse: [f32; 4] = to_array(material.diffuse);
+    let specular: [f32; 3] = to_array(material.specular);
+    let ambient: [f32; 3] = to_array(material.ambient);
+    let edge_color: [f32; 4] = to_array(material.edge_color);
+    let toon = match &material.toon {
+        Toon::Internal(index) => Toon::internal_file_name(*index),
+        Toon::Texture(index) => names.texture(index),
+    };
+
+    Fields::default()
+        .exact("english name", material.name.universal.as_str())
+        .floats("diffuse", diffuse)
+        .floats(
+            "specular",
+            specular.into_iter().chain([material.specular_strength]),
+        )
+        .floats("ambient", ambient)
+        .exact("flags", material.flags.raw())
+        .floats("edge", edge_color.into_iter().chain([material.edge_scale]))
+        .exact("texture", names.texture(&material.tex_idx))
+        .exact(
+            "environment",
+            format!(
+                "{} {:?}",
+                names.texture(&material.env_idx),
+                material.env_blend
+            ),
+        )
+        .exact("toon", toon)
+        .exact("memo", material.meta.as_str())
+        .exact("surface count", material.surface_count)
+}
+
+fn bone_fields(bone: &Bone, names: &Names) -> Fields {
+    let position: [f32; 3] = to_array(bone.position);
+    let (tail, tail_offset) = match &bone.tail {
+        Tail::Position(offset) => ("offset".to_string(), Some(*offset)),
+        Tail::Bone(index) => (names.bone(index), None),
+    };
+    let tail_offset = tail_offset.into_iter().flat_map(|v| {
+        let v: [f32; 3] = to_array(v);
+        v
+    });
+    let axis = |v: Option<_>| {
+        v.into_iter().flat_map(|v: crate::types::Vec3| {
+            let v: [f32; 3] = to_array(v);
+            v
+        })
+    };
+    let local_axes = bone
+        .local_axes
+        .iter()
+        .flat_map(|axes| axis(Some(axes.x)).chain(axis(Some(axes.z))));
+
+    let ik = bone.ik.as_ref().map(|ik| {
+        let links: Vec<String> = ik.links.iter().map(|l| names.bone(&l.bone)).collect();
+        format!(
+            "{} {} [{}]",
+            names.bone(&ik.target),
+            ik.loop_count,
+            links.join(", ")
+        )
+    });
+    let ik_floats = bone.ik.iter().flat_map(|ik| {
+        let limits = ik.links.iter().flat_map(|link| {
+            link.limits
+                .iter()
+                .flat_map(|limit| axis(Some(limit.min)).chain(axis(Some(limit.max))))
+        });
+        [ik.limit_angle]
+            .into_iter()
+            .chain(limits)
+            .collect::<Vec<_>>()
+    });
+
+    Fields::default()
+        .exact("english name", bone.name.universal.as_str())
+        .floats("position", position)
+        .exact("parent", names.bone(&bone.parent))
+        .exact("layer", bone.layer)
+        .exact("flags", bone.flags.raw())
+        .exact("tail", tail)
+        .floats("tail", tail_offset)
+        .exact(
+            "inherit",
+            bone.inherit
+                .as_ref()
+                .map_or("none".to_string(), |i| names.bone(&i.parent)),
+        )
+        .floats("inherit", bone.inherit.iter().map(|i| i.weight))
+        .floats("fixed axis", axis(bone.fixed_axis))
+        .floats("local axes", local_axes)
+        .exact("external parent", format!("{:?}", bone.external_parent))
+        .exact("ik", ik.unwrap_or_default())
+        .floats("ik", ik_floats)
+}
+
+fn morph_fields(morph: &Morph, names: &Names) -> Fields {
+    let mut targets: Vec<String> = Vec::new();
+    let mut values: Vec<f32> = Vec::new();
+    let mut push = |target: String, floats: &[&[f32]]| {
+        targets.push(target);
+        values.extend(floats.iter().flat_map(|f| f.iter().copied()));
+    };
+
+    let kind = match &morph.offsets {
+        Offsets::Group(offsets) => {
+            for o in offsets {
+                push(names.morph(&o.morph), &[&[o.weight]]);
+            }
+            "group".to_string()
+        }
+        Offsets::Flip(offsets) => {
+            for o in offsets {
+                push(names.morph(&o.morph), &[&[o.weight]]);
+            }
+            "flip".to_string()
+        }
+        Offsets::Vertex(offsets) => {
+            for o in offsets {
+                let t: [f32; 3] = to_array(o.translation);
+                push(o.vertex.value().to_string(), &[&t]);
+            }
+            "vertex".to_string()
+        }
+        Offsets::Bone(offsets) => {
+            for o in offsets {
+                let t: [f32; 3] = to_array(o.translation);
+                let r: [f32; 4] = to_array(o.rotation);
+                push(names.bone(&o.bone), &[&t, &r]);
+            }
+            "bone".to_string()
+        }
+        Offsets::Uv(offsets) => {
+            for o in offsets {
+                let v: [f32; 4] = to_array(o.offset);
+                push(o.vertex.value().to_string(), &[&v]);
+            }
+            "uv".to_string()
+        }
+        Offsets::AdditionalUv(channel, offsets) => {
+            for o in offsets {
+                let v: [f32; 4] = to_array(o.offset);
+                push(o.vertex.value().to_string(), &[&v]);
+            }
+            format!("uv{}", channel + 1)
+        }
+        Offsets::Material(offsets) => {
+            for o in offsets {
+                let diffuse: [f32; 4] = to_array(o.diffuse);
+                let specular: [f32; 3] = to_array(o.specular);
+                let ambient: [f32; 3] = to_array(o.ambient);
+                let edge: [f32; 4] = to_array(o.edge_color);
+                let texture: [f32; 4] = to_array(o.texture_tint);
+                let environment: [f32; 4] = to_array(o.environment_tint);
+                let toon: [f32; 4] = to_array(o.toon_tint);
+                push(
+                    format!("{} {:?}", names.material(&o.material), o.operation),
+                    &[
+                        &diffuse,
+                        &specular,
+                        &[o.specular_strength],
+                        &ambient,
+                        &edge,
+                        &[o.edge_scale],
+                        &texture,
+                        &environment,
+                        &toon,
+                    ],
+                );
+            }
+            "material".to_string()
+        }
+        Offsets::Impulse(offsets) => {
+            for o in offsets {
+                let v: [f32; 3] = to_array(o.velocity);
+                let t: [f32; 3] = to_array(o.torque);
+                push(format!("{} {}", o.rigid_body.value(), o.local), &[&v, &t]);
+            }
+            "impulse".to_string()
+        }
+    };
+
+    Fields::default()
+        .exact("english name", morph.name.universal.as_str())
+        .exact("panel", format!("{:?}", morph.panel))
+        .exact("kind", kind)
+        .exact("targets", targets.join(", "))
+        .floats("offsets", values)
+}
@@ -0,0 +1,186 @@
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+use crate::{
+    pmx::Globals,
+    types::{
+        BoneIndex, IndexSize, MorphIndex, Name, ParseContext, read_i32, read_u8, write_count,
+        write_u8,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Negative size encountered where positive expected")]
+    NegativeSize,
+    #[error(transparent)]
+    Type(#[from] crate::types::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid display frame entry type encountered")]
+    InvalidEntryType,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct DisplayFrames {
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<DisplayFrame>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(DisplayFrames, DisplayFrame, len);
+
+impl From<Vec<DisplayFrame>> for DisplayFrames {
+    fn from(inner: Vec<DisplayFrame>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
+}
+
+impl DisplayFrames {
+    pub fn len(&self) -> usize {
+        let len = self.inner.len();
+        debug_assert!(self.len == len);
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn display_frames(&self) -> &[DisplayFrame] {
+        &self.inner
+    }
+
+    pub fn parse(reader: &mut impl Read, globals: &Globals, context: ParseContext) -> Result<Self> {
+        let size = read_i32(reader)?;
+
+        if size.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // name, special flag and entry count
+        let size = context.check_count(size as usize, 13)?;
+
+        let mut inner_vec = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let frame = DisplayFrame::parse(reader, globals, context)?;
+            inner_vec.push(frame);
+        }
+
+        Ok(Self {
+            len: size,
+            inner: inner_vec,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, globals: &Globals) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for frame in &self.inner {
+            frame.write(writer, globals)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A display frame groups bones and morphs into the panels shown by editors.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayFrame {
+    pub(crate) name: Name,
+    /// Special frames are the "Root" and "表情" (expressions) frames which editors don't allow to be modified.
+    pub(crate) special: bool,
+    pub(crate) entries: Vec<FrameEntry>,
+}
+
+impl DisplayFrame {
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn is_special(&self) -> bool {
+        self.special
+    }
+
+    pub fn entries(&self) -> &[FrameEntry] {
+        &self.entries
+    }
+
+    pub fn parse(reader: &mut impl Read, globals: &Globals, context: ParseContext) -> Result<Self> {
+        let name = Name::parse(reader, globals.encoding, context)?;
+
+        let special = read_u8(reader)? != 0;
+
+        let count = read_i32(reader)?;
+
+        if count.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        let bone_size: IndexSize = globals.bone_idx_size.try_into()?;
+        let morph_size: IndexSize = globals.morph_idx_size.try_into()?;
+
+        // a type byte and an index
+        let count = context.check_count(count as usize, 2)?;
+
+        let mut entries = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let entry = match read_u8(reader)? {
+                0 => FrameEntry::Bone(BoneIndex::parse(reader, bone_size)?),
+                1 => FrameEntry::Morph(MorphIndex::parse(reader, morph_size)?),
+                _ => Err(Error::InvalidEntryType)?,
+            };
+
+            entries.push(entry);
+        }
+
+        Ok(Self {
+            name,
+            special,
+            entries,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, globals: &Globals) -> Result<()> {
+        self.name.write(writer, globals.encoding)?;
+
+        write_u8(writer, self.special as u8)?;
+
+        write_count(writer, self.entries.len())?;
+
+        let bone_size: IndexSize = globals.bone_idx_size.try_into()?;
+        let morph_size: IndexSize = globals.morph_idx_size.try_into()?;
+
+        for entry in &self.entries {
+            match entry {
+                FrameEntry::Bone(index) => {
+                    write_u8(writer, 0)?;
+                    index.write(writer, bone_size)?;
+                }
+                FrameEntry::Morph(index) => {
+                    write_u8(writer, 1)?;
+                    index.write(writer, morph_size)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An entry of a display frame, frames can mix bones and morphs.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrameEntry {
+    Bone(BoneIndex),
+    Morph(MorphIndex),
+}
@@ -0,0 +1,316 @@
+//! Sorting materials for drawing.
+//!
+//! MMD draws materials in file order with blending always on, so models put their translucent
+//! parts last by hand and renderers with separate opaque and blended passes have to tell them
+//! apart. [`Pmx::material_alpha`] classifies every material by its diffuse alpha, materials with
+//! an alpha of `0` being hidden as in MMD. With the `image` feature,
+//! [`Pmx::material_alpha_with_textures`] also looks at the alpha channel of the loaded textures:
+//! textures with only fully opaque and fully transparent pixels can be drawn opaque with an alpha
+//! test, ones with anything in between need blending.
+//!
+//! The edge of a material is drawn with the edge color, so a material with the
+//! [`EDGE`](MaterialFlags::EDGE) flag and a translucent edge color needs blending for its edge
+//! pass even if its body is opaque.
+//!
+//! [`Pmx::draw_order`] puts the opaque materials first and the blended ones after them, each in
+//! file order, which keeps the order the author tuned the translucent parts in.
+//! [`Pmx::draw_calls`] turns the order into ranges of the index buffer with the render state each
+//! needs, so an engine can submit them as they are.
+
+use std::ops::Range;
+
+use crate::{
+    material::{Material, MaterialFlags},
+    pmx::Pmx,
+    submesh::Submesh,
+    types::to_array,
+};
+
+/// How a material needs to be drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlphaMode {
+    /// Fully opaque.
+    Opaque,
+    /// Opaque where the texture is not fully transparent, drawn in the opaque pass with an alpha
+    /// test.
+    Cutout,
+    /// Translucent, drawn after the opaque materials.
+    Blended,
+    /// A diffuse alpha of `0`, not drawn at all.
+    Hidden,
+}
+
+impl AlphaMode {
+    /// Returns true if the material is drawn in the opaque pass.
+    pub fn is_opaque(self) -> bool {
+        matches!(self, AlphaMode::Opaque | AlphaMode::Cutout)
+    }
+}
+
+/// The alpha mode of one material, see [`Pmx::material_alpha`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaterialAlpha {
+    pub(crate) material: usize,
+    pub(crate) mode: AlphaMode,
+    pub(crate) blended_edge: bool,
+}
+
+impl MaterialAlpha {
+    /// The index of the material.
+    pub fn material(&self) -> usize {
+        self.material
+    }
+
+    pub fn mode(&self) -> AlphaMode {
+        self.mode
+    }
+
+    /// Returns true if the material draws an edge with a translucent edge color.
+    pub fn blended_edge(&self) -> bool {
+        self.blended_edge
+    }
+}
+
+/// The render state a draw call needs, see [`DrawCall`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PipelineHints {
+    pub(crate) two_sided: bool,
+    pub(crate) blend: bool,
+    pub(crate) edge: bool,
+}
+
+impl PipelineHints {
+    /// Returns true if back faces are drawn, from the [`NO_CULL`](MaterialFlags::NO_CULL) flag.
+    pub fn two_sided(&self) -> bool {
+        self.two_sided
+    }
+
+    /// Returns true if the material is drawn in the blended pass.
+    pub fn blend(&self) -> bool {
+        self.blend
+    }
+
+    /// Returns true if an edge is drawn around the material, it has the
+    /// [`EDGE`](MaterialFlags::EDGE) flag and a visible edge.
+    pub fn edge(&self) -> bool {
+        self.edge
+    }
+}
+
+/// One material's part of an index buffer holding the surfaces in file order, like the one of
+/// `Pmx::index_data` with the `gpu` feature, see [`Pmx::draw_calls`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawCall {
+    pub(crate) index_offset: u32,
+    pub(crate) index_count: u32,
+    pub(crate) material: usize,
+    pub(crate) hints: PipelineHints,
+}
+
+impl DrawCall {
+    /// The first index drawn, indices being surfaces in file order.
+    pub fn index_offset(&self) -> u32 {
+        self.index_offset
+    }
+
+    /// The number of indices drawn, three per triangle.
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    /// The index of the material.
+    pub fn material(&self) -> usize {
+        self.material
+    }
+
+    pub fn hints(&self) -> PipelineHints {
+        self.hints
+    }
+}
+
+/// The recommended order to draw the materials in, see [`Pmx::draw_order`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DrawOrder {
+    pub(crate) submeshes: Vec<Submesh>,
+    pub(crate) opaque: usize,
+    pub(crate) hidden: Vec<usize>,
+}
+
+impl DrawOrder {
+    /// The submeshes of the drawn materials, in drawing order. Their ranges still index the
+    /// surface section as it is in the file.
+    pub fn submeshes(&self) -> &[Submesh] {
+        &self.submeshes
+    }
+
+    /// The materials in drawing order.
+    pub fn materials(&self) -> impl Iterator<Item = usize> + '_ {
+        self.submeshes.iter().map(|submesh| submesh.material)
+    }
+
+    /// The submeshes drawn in the opaque pass.
+    pub fn opaque(&self) -> &[Submesh] {
+        &self.submeshes[..self.opaque]
+    }
+
+    /// The submeshes drawn in the blended pass.
+    pub fn blended(&self) -> &[Submesh] {
+        &self.submeshes[self.opaque..]
+    }
+
+    /// The hidden materials, which are not drawn.
+    pub fn hidden(&self) -> &[usize] {
+        &self.hidden
+    }
+
+    /// A draw call for every submesh, in drawing order. Submeshes without surfaces are skipped.
+    pub fn draw_calls(&self, pmx: &Pmx) -> Vec<DrawCall> {
+        (self.submeshes.iter().enumerate())
+            .filter(|(_, submesh)| !submesh.surfaces.is_empty())
+            .filter_map(|(i, submesh)| {
+                let material = pmx.materials.inner.get(submesh.material)?;
+                let [.., edge_alpha]: [f32; 4] = to_array(material.edge_color);
+
+                Some(DrawCall {
+                    index_offset: submesh.surfaces.start as u32,
+                    index_count: submesh.surfaces.len() as u32,
+                    material: submesh.material,
+                    hints: PipelineHints {
+                        two_sided: material.flags.contains(MaterialFlags::NO_CULL),
+                        blend: i >= self.opaque,
+                        edge: material.flags.contains(MaterialFlags::EDGE)
+                            && material.edge_scale > 0.0
+                            && edge_alpha > 0.0,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// The surface ranges the submeshes would cover if the surface section was rewritten in
+    /// drawing order, one per submesh.
+    pub fn reordered_ranges(&self) -> Vec<Range<usize>> {
+        let mut start = 0;
+
+        (self.submeshes.iter())
+            .map(|submesh| {
+                let range = start..start + submesh.surfaces.len();
+                start = range.end;
+                range
+            })
+            .collect()
+    }
+}
+
+impl Pmx {
+    /// Classifies every material by its diffuse alpha, see the [module docs](crate::draw_order).
+    pub fn material_alpha(&self) -> Vec<MaterialAlpha> {
+        (self.materials.inner.iter().enumerate())
+            .map(|(i, material)| classify(i, material, None))
+            .collect()
+    }
+
+    /// Classifies every material by its diffuse alpha and the alpha of its texture, see the
+    /// [module docs](crate::draw_order).
+    ///
+    /// Materials whose texture is not loaded are classified by their diffuse alpha alone.
+    #[cfg(feature = "image")]
+    pub fn material_alpha_with_textures(
+        &self,
+        textures: &crate::image::LoadedTextures,
+    ) -> Vec<MaterialAlpha> {
+        let mut texture_modes = std::collections::HashMap::new();
+
+        (self.materials.inner.iter().enumerate())
+            .map(|(i, material)| {
+                let texture = (material.tex_idx.as_usize()).and_then(|texture| {
+                    let image = textures.get(texture)?;
+                    Some(
+                        *texture_modes
+                            .entry(texture)
+                            .or_insert_with(|| texture_mode(image)),
+                    )
+                });
+                classify(i, material, texture)
+            })
+            .collect()
+    }
+
+    /// The materials in the order to draw them in, given their alpha modes from
+    /// [`Pmx::material_alpha`], see the [module docs](crate::draw_order).
+    ///
+    /// Materials with blended edges stay in the opaque pass when their body is opaque, renderers
+    /// drawing edges in a pass of their own should blend it for them.
+    pub fn draw_order(&self, alpha: &[MaterialAlpha]) -> DrawOrder {
+        let submeshes = self.submeshes();
+        let mode = |submesh: &Submesh| {
+            (alpha.iter())
+                .find(|a| a.material == submesh.material)
+                .map_or(AlphaMode::Opaque, |a| a.mode)
+        };
+
+        let (mut drawn, hidden): (Vec<Submesh>, Vec<Submesh>) =
+            (submeshes.into_iter()).partition(|submesh| mode(submesh) != AlphaMode::Hidden);
+        // the sort is stable, so file order is kept within each pass
+        drawn.sort_by_key(|submesh| !mode(submesh).is_opaque());
+        let opaque = drawn.iter().filter(|s| mode(s).is_opaque()).count();
+
+        DrawOrder {
+            submeshes: drawn,
+            opaque,
+            hidden: hidden.into_iter().map(|submesh| submesh.material).collect(),
+        }
+    }
+
+    /// The draw calls of the model in the recommended order, the opaque materials before the
+    /// blended ones, see the [module docs](crate::draw_order). Hidden materials are left out.
+    ///
+    /// Materials are classified by their diffuse alpha, [`DrawOrder::draw_calls`] makes the draw
+    /// calls of an order worked out from textures as well.
+    pub fn draw_calls(&self) -> Vec<DrawCall> {
+        self.draw_order(&self.material_alpha()).draw_calls(self)
+    }
+}
+
+fn classify(index: usize, material: &Material, texture: Option<AlphaMode>) -> MaterialAlpha {
+    let [.., alpha]: [f32; 4] = to_array(material.diffuse);
+    let [.., edge_alpha]: [f32; 4] = to_array(material.edge_color);
+
+    let mode = if alpha <= 0.0 {
+        AlphaMode::Hidden
+    } else if alpha < 1.0 {
+        AlphaMode::Blended
+    } else {
+        texture.unwrap_or(AlphaMode::Opaque)
+    };
+
+    MaterialAlpha {
+        material: index,
+        mode,
+        blended_edge: mode != AlphaMode::Hidden
+            && material.flags.contains(MaterialFlags::EDGE)
+            && edge_alpha < 1.0,
+    }
+}
+
+/// Opaque without transparent pixels, cutout with only fully opaque and fully transparent ones,
+/// allowing for some compression noise, and blended otherwise.
+#[cfg(feature = "image")]
+fn texture_mode(image: &::image::RgbaImage) -> AlphaMode {
+    const NOISE: u8 = 8;
+
+    let mut transparent = false;
+    for pixel in image.pixels() {
+        match pixel.0[3] {
+            a if a >= u8::MAX - NOISE => {}
+            a if a <= NOISE => transparent = true,
+            _ => return AlphaMode::Blended,
+        }
+    }
+
+    if transparent {
+        AlphaMode::Cutout
+    } else {
+        AlphaMode::Opaque
+    }
+}
@@ -0,0 +1,1204 @@
+//! A curated, human-readable text representation of a model, meant for diffing and version control.
+//!
+//! References to bones, morphs, materials, textures and rigid bodies are written as the local name
+//! (or path, for textures) of the element they point to, as long as that name is unique within its
+//! section. Ambiguous references and references to unnamed elements like vertices stay plain
+//! indices, nil references are `null`.
+//!
+//! The schema is independent of the in-memory types, so it stays stable when those change.
+//! [`Dump`] implements serde, so formats other than JSON can be used through it as well.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    bone::{self, Bone, BoneFlags, IkAngleLimit, LocalAxes},
+    display_frame::{DisplayFrame, FrameEntry},
+    joint::{Joint, JointType},
+    material::{EnvironmentBlend, Material, MaterialFlags, Toon},
+    morph::{self, MaterialOperation, Morph, Offsets, Panel, UvOffset, VertexOffset},
+    pmx::{self, Comment, Globals, Header, ModelName, Pmx, Version},
+    rigid_body::{CollisionGroup, CollisionMask, PhysicsMode, RigidBody, Shape},
+    surface::Surface,
+    texture::Texture,
+    types::{
+        BoneIndex, MaterialIndex, MorphIndex, Name, PmxText, RigidBodyIndex, TextEncoding,
+        TextureIndex, Vec2, Vec3, Vec4, VertexIndex,
+    },
+    vertex::{Vertex, WeightDeform},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("PMX error: {0}")]
+    Pmx(#[from] pmx::Error),
+    #[error("Unknown {kind} reference \"{name}\"")]
+    UnknownReference { kind: &'static str, name: String },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Dumps a model as pretty-printed JSON.
+pub fn to_json(pmx: &Pmx) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&Dump::from_pmx(pmx))?)
+}
+
+/// Loads a model from JSON written by [`to_json`].
+pub fn from_json(json: &str) -> Result<Pmx> {
+    serde_json::from_str::<Dump>(json)?.into_pmx()
+}
+
+/// A reference to another element, by name where possible.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Ref {
+    Index(i32),
+    Name(String),
+}
+
+/// Maps between indices and unique names of a section.
+struct Names<'a> {
+    kind: &'static str,
+    by_index: Vec<Option<&'a str>>,
+    by_name: HashMap<&'a str, i32>,
+}
+
+impl<'a> Names<'a> {
+    fn new(kind: &'static str, names: impl Iterator<Item = &'a str>) -> Self {
+        let names: Vec<&str> = names.collect();
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for name in &names {
+            *counts.entry(name).or_default() += 1;
+        }
+
+        let by_index = names
+            .iter()
+            .map(|name| (!name.is_empty() && counts[name] == 1).then_some(*name))
+            .collect();
+
+        let by_name = names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| counts[*name] == 1)
+            .map(|(i, name)| (*name, i as i32))
+            .collect();
+
+        Self {
+            kind,
+            by_index,
+            by_name,
+        }
+    }
+
+    fn to_ref(&self, value: i32) -> Option<Ref> {
+        if value == -1 {
+            return None;
+        }
+
+        let name = usize::try_from(value)
+            .ok()
+            .and_then(|i| self.by_index.get(i).copied().flatten());
+
+        Some(match name {
+            Some(name) => Ref::Name(name.to_string()),
+            None => Ref::Index(value),
+        })
+    }
+
+    fn resolve(&self, r: &Option<Ref>) -> Result<i32> {
+        match r {
+            None => Ok(-1),
+            Some(Ref::Index(i)) => Ok(*i),
+            Some(Ref::Name(name)) => {
+                self.by_name
+                    .get(name.as_str())
+                    .copied()
+                    .ok_or_else(|| Error::UnknownReference {
+                        kind: self.kind,
+                        name: name.clone(),
+                    })
+            }
+        }
+    }
+}
+
+/// The name tables of every section that can be referenced by name.
+struct Tables<'a> {
+    textures: Names<'a>,
+    materials: Names<'a>,
+    bones: Names<'a>,
+    morphs: Names<'a>,
+    rigid_bodies: Names<'a>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NamePair {
+    local: String,
+    universal: String,
+}
+
+impl NamePair {
+    fn from_texts(local: &PmxText, universal: &PmxText) -> Self {
+        Self {
+            local: local.as_str().to_string(),
+            universal: universal.as_str().to_string(),
+        }
+    }
+
+    fn to_name(&self, encoding: TextEncoding) -> Name {
+        Name {
+            local: PmxText::new(self.local.as_str(), encoding),
+            universal: PmxText::new(self.universal.as_str(), encoding),
+        }
+    }
+}
+
+impl From<&Name> for NamePair {
+    fn from(name: &Name) -> Self {
+        Self::from_texts(&name.local, &name.universal)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexSizes {
+    vertex: u8,
+    texture: u8,
+    material: u8,
+    bone: u8,
+    morph: u8,
+    rigid_body: u8,
+}
+
+/// A whole model in the dump schema.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Dump {
+    version: f32,
+    encoding: TextEncoding,
+    additional_vec4_count: u8,
+    index_sizes: IndexSizes,
+    name: NamePair,
+    comment: NamePair,
+    vertices: Vec<DumpVertex>,
+    /// Flat vertex index buffer, every 3 indices form a triangle.
+    indices: Vec<i32>,
+    /// Texture paths.
+    textures: Vec<String>,
+    materials: Vec<DumpMaterial>,
+    bones: Vec<DumpBone>,
+    morphs: Vec<DumpMorph>,
+    display_frames: Vec<DumpDisplayFrame>,
+    rigid_bodies: Vec<DumpRigidBody>,
+    joints: Vec<DumpJoint>,
+    /// Kept in the raw layout, with plain indices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    soft_bodies: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpVertex {
+    position: Vec3,
+    normal: Vec3,
+    uv: Vec2,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    additional: Vec<Vec4>,
+    deform: DumpDeform,
+    edge_scale: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DumpDeform {
+    Bdef1 {
+        bone: Option<Ref>,
+    },
+    /// `weight` is the weight of the first bone, the second gets the rest.
+    Bdef2 {
+        bones: [Option<Ref>; 2],
+        weight: f32,
+    },
+    Bdef4 {
+        bones: [Option<Ref>; 4],
+        weights: [f32; 4],
+    },
+    Sdef {
+        bones: [Option<Ref>; 2],
+        weight: f32,
+        c: Vec3,
+        r0: Vec3,
+        r1: Vec3,
+    },
+    Qdef {
+        bones: [Option<Ref>; 4],
+        weights: [f32; 4],
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DumpToon {
+    Texture(Option<Ref>),
+    /// 0-based index of the built-in toon texture.
+    Internal(u8),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpMaterial {
+    name: NamePair,
+    diffuse: Vec4,
+    specular: Vec3,
+    specular_strength: f32,
+    ambient: Vec3,
+    flags: u8,
+    edge_color: Vec4,
+    edge_scale: f32,
+    texture: Option<Ref>,
+    environment: Option<Ref>,
+    environment_blend: EnvironmentBlend,
+    toon: DumpToon,
+    meta: String,
+    surface_count: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DumpTail {
+    Position(Vec3),
+    Bone(Option<Ref>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpInherit {
+    parent: Option<Ref>,
+    weight: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpIk {
+    target: Option<Ref>,
+    loop_count: i32,
+    limit_angle: f32,
+    links: Vec<DumpIkLink>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpIkLink {
+    bone: Option<Ref>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limits: Option<IkAngleLimit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpBone {
+    name: NamePair,
+    position: Vec3,
+    parent: Option<Ref>,
+    layer: i32,
+    flags: u16,
+    tail: DumpTail,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inherit: Option<DumpInherit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fixed_axis: Option<Vec3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_axes: Option<LocalAxes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    external_parent: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ik: Option<DumpIk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpMorphWeight {
+    morph: Option<Ref>,
+    weight: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpBoneOffset {
+    bone: Option<Ref>,
+    translation: Vec3,
+    rotation: Vec4,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpMaterialOffset {
+    /// `null` applies the offset to every material.
+    material: Option<Ref>,
+    operation: MaterialOperation,
+    diffuse: Vec4,
+    specular: Vec3,
+    specular_strength: f32,
+    ambient: Vec3,
+    edge_color: Vec4,
+    edge_scale: f32,
+    texture_tint: Vec4,
+    environment_tint: Vec4,
+    toon_tint: Vec4,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpImpulseOffset {
+    rigid_body: Option<Ref>,
+    local: bool,
+    velocity: Vec3,
+    torque: Vec3,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DumpOffsets {
+    Group { offsets: Vec<DumpMorphWeight> },
+    Vertex { offsets: Vec<VertexOffset> },
+    Bone { offsets: Vec<DumpBoneOffset> },
+    Uv { offsets: Vec<UvOffset> },
+    AdditionalUv { channel: u8, offsets: Vec<UvOffset> },
+    Material { offsets: Vec<DumpMaterialOffset> },
+    Flip { offsets: Vec<DumpMorphWeight> },
+    Impulse { offsets: Vec<DumpImpulseOffset> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpMorph {
+    name: NamePair,
+    panel: Panel,
+    #[serde(flatten)]
+    offsets: DumpOffsets,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DumpFrameEntry {
+    Bone(Option<Ref>),
+    Morph(Option<Ref>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpDisplayFrame {
+    name: NamePair,
+    special: bool,
+    entries: Vec<DumpFrameEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpRigidBody {
+    name: NamePair,
+    bone: Option<Ref>,
+    group: u8,
+    non_collision_mask: u16,
+    shape: Shape,
+    size: Vec3,
+    position: Vec3,
+    rotation: Vec3,
+    mass: f32,
+    linear_damping: f32,
+    angular_damping: f32,
+    repulsion: f32,
+    friction: f32,
+    physics_mode: PhysicsMode,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpJoint {
+    name: NamePair,
+    #[serde(rename = "type")]
+    typ: JointType,
+    rigid_body_a: Option<Ref>,
+    rigid_body_b: Option<Ref>,
+    position: Vec3,
+    rotation: Vec3,
+    position_min: Vec3,
+    position_max: Vec3,
+    rotation_min: Vec3,
+    rotation_max: Vec3,
+    position_spring: Vec3,
+    rotation_spring: Vec3,
+}
+
+impl Dump {
+    pub fn from_pmx(pmx: &Pmx) -> Self {
+        let header = &pmx.header;
+        let globals = &header.globals;
+
+        let tables = Tables {
+            textures: Names::new(
+                "texture",
+                pmx.textures.inner.iter().map(|t| t.path.as_str()),
+            ),
+            materials: Names::new(
+                "material",
+                pmx.materials.inner.iter().map(|m| m.name.local.as_str()),
+            ),
+            bones: Names::new(
+                "bone",
+                pmx.bones.inner.iter().map(|b| b.name.local.as_str()),
+            ),
+            morphs: Names::new(
+                "morph",
+                pmx.morphs.inner.iter().map(|m| m.name.local.as_str()),
+            ),
+            rigid_bodies: Names::new(
+                "rigid body",
+                pmx.rigid_bodies.inner.iter().map(|r| r.name.local.as_str()),
+            ),
+        };
+
+        Self {
+            version: header.raw_version,
+            encoding: globals.encoding,
+            additional_vec4_count: globals.vec4_additional,
+            index_sizes: IndexSizes {
+                vertex: globals.vert_idx_size,
+                texture: globals.tex_idx_size,
+                material: globals.material_idx_size,
+                bone: globals.bone_idx_size,
+                morph: globals.morph_idx_size,
+                rigid_body: globals.rb_idx_size,
+            },
+            name: NamePair::from_texts(&header.name.local, &header.name.universal),
+            comment: NamePair::from_texts(&header.comment.local, &header.comment.universal),
+            vertices: pmx
+                .vertices
+                .inner
+                .iter()
+                .map(|v| dump_vertex(v, &tables))
+                .collect(),
+            indices: pmx.surfaces.inner.iter().map(|s| s.index.value()).collect(),
+            textures: pmx
+                .textures
+                .inner
+                .iter()
+                .map(|t| t.path.as_str().to_string())
+                .collect(),
+            materials: pmx
+                .materials
+                .inner
+                .iter()
+                .map(|m| dump_material(m, &tables))
+                .collect(),
+            bones: pmx
+                .bones
+                .inner
+                .iter()
+                .map(|b| dump_bone(b, &tables))
+                .collect(),
+            morphs: pmx
+                .morphs
+                .inner
+                .iter()
+                .map(|m| dump_morph(m, &tables))
+                .collect(),
+            display_frames: pmx
+                .display_frames
+                .inner
+                .iter()
+                .map(|d| dump_display_frame(d, &tables))
+                .collect(),
+            rigid_bodies: pmx
+                .rigid_bodies
+                .inner
+                .iter()
+                .map(|r| dump_rigid_body(r, &tables))
+                .collect(),
+            joints: pmx
+                .joints
+                .inner
+                .iter()
+                .map(|j| dump_joint(j, &tables))
+                .collect(),
+            soft_bodies: pmx
+                .soft_bodies
+                .as_ref()
+                .map(|s| serde_json::to_value(s).expect("soft bodies only hold plain data")),
+        }
+    }
+
+    /// Builds the model, resolving named references back into indices.
+    pub fn into_pmx(self) -> Result<Pmx> {
+        let encoding = self.encoding;
+
+        let tables = Tables {
+            textures: Names::new("texture", self.textures.iter().map(String::as_str)),
+            materials: Names::new(
+                "material",
+                self.materials.iter().map(|m| m.name.local.as_str()),
+            ),
+            bones: Names::new("bone", self.bones.iter().map(|b| b.name.local.as_str())),
+            morphs: Names::new("morph", self.morphs.iter().map(|m| m.name.local.as_str())),
+            rigid_bodies: Names::new(
+                "rigid body",
+                self.rigid_bodies.iter().map(|r| r.name.local.as_str()),
+            ),
+        };
+
+        let header = Header {
+            tag: *b"PMX ",
+            version: Version::try_from(self.version)?,
+            raw_version: self.version,
+            globals: Globals {
+                encoding,
+                vec4_additional: self.additional_vec4_count,
+                vert_idx_size: self.index_sizes.vertex,
+                tex_idx_size: self.index_sizes.texture,
+                material_idx_size: self.index_sizes.material,
+                bone_idx_size: self.index_sizes.bone,
+                morph_idx_size: self.index_sizes.morph,
+                rb_idx_size: self.index_sizes.rigid_body,
+                additional: None,
+            },
+            name: ModelName {
+                local: PmxText::new(self.name.local.as_str(), encoding),
+                universal: PmxText::new(self.name.universal.as_str(), encoding),
+            },
+            comment: Comment {
+                local: PmxText::new(self.comment.local.as_str(), encoding),
+                universal: PmxText::new(self.comment.universal.as_str(), encoding),
+            },
+        };
+
+        let vertices = self
+            .vertices
+            .iter()
+            .map(|v| load_vertex(v, &tables))
+            .collect::<Result<Vec<_>>>()?;
+
+        let surfaces = self
+            .indices
+            .iter()
+            .map(|&i| Surface {
+                index: VertexIndex::new(i),
+            })
+            .collect::<Vec<_>>();
+
+        let textures = self
+            .textures
+            .iter()
+            .map(|path| Texture {
+                path: PmxText::new(path.as_str(), encoding),
+            })
+            .collect::<Vec<_>>();
+
+        let materials = self
+            .materials
+            .iter()
+            .map(|m| load_material(m, &tables, encoding))
+            .collect::<Result<Vec<_>>>()?;
+
+        let bones = self
+            .bones
+            .iter()
+            .map(|b| load_bone(b, &tables, encoding))
+            .collect::<Result<Vec<_>>>()?;
+
+        let morphs = self
+            .morphs
+            .iter()
+            .map(|m| load_morph(m, &tables, encoding))
+            .collect::<Result<Vec<_>>>()?;
+
+        let display_frames = self
+            .display_frames
+            .iter()
+            .map(|d| load_display_frame(d, &tables, encoding))
+            .collect::<Result<Vec<_>>>()?;
+
+        let rigid_bodies = self
+            .rigid_bodies
+            .iter()
+            .map(|r| load_rigid_body(r, &tables, encoding))
+            .collect::<Result<Vec<_>>>()?;
+
+        let joints = self
+            .joints
+            .iter()
+            .map(|j| load_joint(j, &tables, encoding))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Pmx {
+            header,
+            vertices: vertices.into(),
+            surfaces: surfaces.into(),
+            textures: textures.into(),
+            materials: materials.into(),
+            bones: bones.into(),
+            morphs: morphs.into(),
+            display_frames: display_frames.into(),
+            rigid_bodies: rigid_bodies.into(),
+            joints: joints.into(),
+            soft_bodies: self.soft_bodies.map(serde_json::from_value).transpose()?,
+            trailing: None,
+        })
+    }
+}
+
+fn bone_refs<const N: usize>(indices: &[BoneIndex; N], tables: &Tables) -> [Option<Ref>; N] {
+    indices.each_ref().map(|i| tables.bones.to_ref(i.value()))
+}
+
+fn load_bone_refs<const N: usize>(
+    refs: &[Option<Ref>; N],
+    tables: &Tables,
+) -> Result<[BoneIndex; N]> {
+    let mut indices = [BoneIndex::nil(); N];
+
+    for (index, r) in indices.iter_mut().zip(refs) {
+        *index = BoneIndex::new(tables.bones.resolve(r)?);
+    }
+
+    Ok(indices)
+}
+
+fn dump_vertex(vertex: &Vertex, tables: &Tables) -> DumpVertex {
+    let deform = match &vertex.weight_deform {
+        WeightDeform::Bdef1 { index } => DumpDeform::Bdef1 {
+            bone: tables.bones.to_ref(index.value()),
+        },
+        WeightDeform::Bdef2 { indices, weights } => DumpDeform::Bdef2 {
+            bones: bone_refs(indices, tables),
+            weight: weights[0],
+        },
+        WeightDeform::Bdef4 { indices, weights } => DumpDeform::Bdef4 {
+            bones: bone_refs(indices, tables),
+            weights: *weights,
+        },
+        WeightDeform::Sdef {
+            indices,
+            weights,
+            c,
+            r0,
+            r1,
+        } => DumpDeform::Sdef {
+            bones: bone_refs(indices, tables),
+            weight: weights[0],
+            c: *c,
+            r0: *r0,
+            r1: *r1,
+        },
+        WeightDeform::Qdef { indices, weights } => DumpDeform::Qdef {
+            bones: bone_refs(indices, tables),
+            weights: *weights,
+        },
+    };
+
+    DumpVertex {
+        position: vertex.pos,
+        normal: vertex.normal,
+        uv: vertex.uv,
+        additional: vertex.additional_vec4s().to_vec(),
+        deform,
+        edge_scale: vertex.edge_scale,
+    }
+}
+
+fn load_vertex(vertex: &DumpVertex, tables: &Tables) -> Result<Vertex> {
+    let weight_deform = match &vertex.deform {
+        DumpDeform::Bdef1 { bone } => WeightDeform::Bdef1 {
+            index: BoneIndex::new(tables.bones.resolve(bone)?),
+        },
+        DumpDeform::Bdef2 { bones, weight } => WeightDeform::Bdef2 {
+            indices: load_bone_refs(bones, tables)?,
+            weights: [*weight, 1.0 - weight],
+        },
+        DumpDeform::Bdef4 { bones, weights } => WeightDeform::Bdef4 {
+            indices: load_bone_refs(bones, tables)?,
+            weights: *weights,
+        },
+        DumpDeform::Sdef {
+            bones,
+            weight,
+            c,
+            r0,
+            r1,
+        } => WeightDeform::Sdef {
+            indices: load_bone_refs(bones, tables)?,
+            weights: [*weight, 1.0 - weight],
+            c: *c,
+            r0: *r0,
+            r1: *r1,
+        },
+        DumpDeform::Qdef { bones, weights } => WeightDeform::Qdef {
+            indices: load_bone_refs(bones, tables)?,
+            weights: *weights,
+        },
+    };
+
+    Ok(Vertex {
+        pos: vertex.position,
+        normal: vertex.normal,
+        uv: vertex.uv,
+        extra_vec4: (!vertex.additional.is_empty()).then(|| vertex.additional.clone()),
+        weight_deform,
+        edge_scale: vertex.edge_scale,
+    })
+}
+
+fn dump_material(material: &Material, tables: &Tables) -> DumpMaterial {
+    DumpMaterial {
+        name: (&material.name).into(),
+        diffuse: material.diffuse,
+        specular: material.specular,
+        specular_strength: material.specular_strength,
+        ambient: material.ambient,
+        flags: material.flags.raw(),
+        edge_color: material.edge_color,
+        edge_scale: material.edge_scale,
+        texture: tables.textures.to_ref(material.tex_idx.value()),
+        environment: tables.textures.to_ref(material.env_idx.value()),
+        environment_blend: material.env_blend,
+        toon: match &material.toon {
+            Toon::Texture(index) => DumpToon::Texture(tables.textures.to_ref(index.value())),
+            Toon::Internal(n) => DumpToon::Internal(*n),
+        },
+        meta: material.meta.as_str().to_string(),
+        surface_count: material.surface_count,
+    }
+}
+
+fn load_material(
+    material: &DumpMaterial,
+    tables: &Tables,
+    encoding: TextEncoding,
+) -> Result<Material> {
+    Ok(Material {
+        name: material.name.to_name(encoding),
+        diffuse: material.diffuse,
+        specular: material.specular,
+        specular_strength: material.specular_strength,
+        ambient: material.ambient,
+        flags: MaterialFlags::from_raw(material.flags),
+        edge_color: material.edge_color,
+        edge_scale: material.edge_scale,
+        tex_idx: TextureIndex::new(tables.textures.resolve(&material.texture)?),
+        env_idx: TextureIndex::new(tables.textures.resolve(&material.environment)?),
+        env_blend: material.environment_blend,
+        toon: match &material.toon {
+            DumpToon::Texture(r) => Toon::Texture(TextureIndex::new(tables.textures.resolve(r)?)),
+            DumpToon::Internal(n) => Toon::Internal(*n),
+        },
+        meta: PmxText::new(material.meta.as_str(), encoding),
+        surface_count: material.surface_count,
+    })
+}
+
+fn dump_bone(bone: &Bone, tables: &Tables) -> DumpBone {
+    let to_ref = |index: &BoneIndex| tables.bones.to_ref(index.value());
+
+    DumpBone {
+        name: (&bone.name).into(),
+        position: bone.position,
+        parent: to_ref(&bone.parent),
+        layer: bone.layer,
+        flags: bone.flags.raw(),
+        tail: match &bone.tail {
+            bone::Tail::Position(offset) => DumpTail::Position(*offset),
+            bone::Tail::Bone(index) => DumpTail::Bone(to_ref(index)),
+        },
+        inherit: bone.inherit.as_ref().map(|inherit| DumpInherit {
+            parent: to_ref(&inherit.parent),
+            weight: inherit.weight,
+        }),
+        fixed_axis: bone.fixed_axis,
+        local_axes: bone.local_axes.as_ref().map(|axes| LocalAxes {
+            x: axes.x,
+            z: axes.z,
+        }),
+        external_parent: bone.external_parent,
+        ik: bone.ik.as_ref().map(|ik| DumpIk {
+            target: to_ref(&ik.target),
+            loop_count: ik.loop_count,
+            limit_angle: ik.limit_angle,
+            links: ik
+                .links
+                .iter()
+                .map(|link| DumpIkLink {
+                    bone: to_ref(&link.bone),
+                    limits: link.limits.as_ref().map(|limits| IkAngleLimit {
+                        min: limits.min,
+                        max: limits.max,
+                    }),
+                })
+                .collect(),
+        }),
+    }
+}
+
+fn load_bone(bone: &DumpBone, tables: &Tables, encoding: TextEncoding) -> Result<Bone> {
+    let resolve = |r: &Option<Ref>| Ok::<_, Error>(BoneIndex::new(tables.bones.resolve(r)?));
+
+    let ik = match &bone.ik {
+        Some(ik) => Some(bone::Ik {
+            target: resolve(&ik.target)?,
+            loop_count: ik.loop_count,
+            limit_angle: ik.limit_angle,
+            links: ik
+                .links
+                .iter()
+                .map(|link| {
+                    Ok(bone::IkLink {
+                        bone: resolve(&link.bone)?,
+                        limits: link.limits.as_ref().map(|limits| IkAngleLimit {
+                            min: limits.min,
+                            max: limits.max,
+                        }),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+        }),
+        None => None,
+    };
+
+    Ok(Bone {
+        name: bone.name.to_name(encoding),
+        position: bone.position,
+        parent: resolve(&bone.parent)?,
+        layer: bone.layer,
+        flags: BoneFlags::from_raw(bone.flags),
+        tail: match &bone.tail {
+            DumpTail::Position(offset) => bone::Tail::Position(*offset),
+            DumpTail::Bone(r) => bone::Tail::Bone(resolve(r)?),
+        },
+        inherit: match &bone.inherit {
+            Some(inherit) => Some(bone::Inherit {
+                parent: resolve(&inherit.parent)?,
+                weight: inherit.weight,
+            }),
+            None => None,
+        },
+        fixed_axis: bone.fixed_axis,
+        local_axes: bone.local_axes.as_ref().map(|axes| LocalAxes {
+            x: axes.x,
+            z: axes.z,
+        }),
+        external_parent: bone.external_parent,
+        ik,
+    })
+}
+
+fn dump_morph(morph: &Morph, tables: &Tables) -> DumpMorph {
+    let morph_weights = |offsets: &[(MorphIndex, f32)]| {
+        offsets
+            .iter()
+            .map(|(index, weight)| DumpMorphWeight {
+                morph: tables.morphs.to_ref(index.value()),
+                weight: *weight,
+            })
+            .collect()
+    };
+
+    let uv_offsets = |offsets: &[UvOffset]| {
+        offsets
+            .iter()
+            .map(|o| UvOffset {
+                vertex: o.vertex,
+                offset: o.offset,
+            })
+            .collect()
+    };
+
+    let offsets = match &morph.offsets {
+        Offsets::Group(offsets) => DumpOffsets::Group {
+            offsets: morph_weights(
+                &offsets
+                    .iter()
+                    .map(|o| (o.morph, o.weight))
+                    .collect::<Vec<_>>(),
+            ),
+        },
+        Offsets::Vertex(offsets) => DumpOffsets::Vertex {
+            offsets: offsets
+                .iter()
+                .map(|o| VertexOffset {
+                    vertex: o.vertex,
+                    translation: o.translation,
+                })
+                .collect(),
+        },
+        Offsets::Bone(offsets) => DumpOffsets::Bone {
+            offsets: offsets
+                .iter()
+                .map(|o| DumpBoneOffset {
+                    bone: tables.bones.to_ref(o.bone.value()),
+                    translation: o.translation,
+                    rotation: o.rotation,
+                })
+                .collect(),
+        },
+        Offsets::Uv(offsets) => DumpOffsets::Uv {
+            offsets: uv_offsets(offsets),
+        },
+        Offsets::AdditionalUv(channel, offsets) => DumpOffsets::AdditionalUv {
+            channel: *channel,
+            offsets: uv_offsets(offsets),
+        },
+        Offsets::Material(offsets) => DumpOffsets::Material {
+            offsets: offsets
+                .iter()
+                .map(|o| DumpMaterialOffset {
+                    material: tables.materials.to_ref(o.material.value()),
+                    operation: o.operation,
+                    diffuse: o.diffuse,
+                    specular: o.specular,
+                    specular_strength: o.specular_strength,
+                    ambient: o.ambient,
+                    edge_color: o.edge_color,
+                    edge_scale: o.edge_scale,
+                    texture_tint: o.texture_tint,
+                    environment_tint: o.environment_tint,
+                    toon_tint: o.toon_tint,
+                })
+                .collect(),
+        },
+        Offsets::Flip(offsets) => DumpOffsets::Flip {
+            offsets: morph_weights(
+                &offsets
+                    .iter()
+                    .map(|o| (o.morph, o.weight))
+                    .collect::<Vec<_>>(),
+            ),
+        },
+        Offsets::Impulse(offsets) => DumpOffsets::Impulse {
+            offsets: offsets
+                .iter()
+                .map(|o| DumpImpulseOffset {
+                    rigid_body: tables.rigid_bodies.to_ref(o.rigid_body.value()),
+                    local: o.local,
+                    velocity: o.velocity,
+                    torque: o.torque,
+                })
+                .collect(),
+        },
+    };
+
+    DumpMorph {
+        name: (&morph.name).into(),
+        panel: morph.panel,
+        offsets,
+    }
+}
+
+fn load_morph(morph: &DumpMorph, tables: &Tables, encoding: TextEncoding) -> Result<Morph> {
+    let morph_index = |r: &Option<Ref>| Ok::<_, Error>(MorphIndex::new(tables.morphs.resolve(r)?));
+
+    let uv_offsets = |offsets: &[UvOffset]| {
+        offsets
+            .iter()
+            .map(|o| UvOffset {
+                vertex: o.vertex,
+                offset: o.offset,
+            })
+            .collect()
+    };
+
+    let offsets = match &morph.offsets {
+        DumpOffsets::Group { offsets } => Offsets::Group(
+            offsets
+                .iter()
+                .map(|o| {
+                    Ok(morph::GroupOffset {
+                        morph: morph_index(&o.morph)?,
+                        weight: o.weight,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        DumpOffsets::Vertex { offsets } => Offsets::Vertex(
+            offsets
+                .iter()
+                .map(|o| VertexOffset {
+                    vertex: o.vertex,
+                    translation: o.translation,
+                })
+                .collect(),
+        ),
+        DumpOffsets::Bone { offsets } => Offsets::Bone(
+            offsets
+                .iter()
+                .map(|o| {
+                    Ok(morph::BoneOffset {
+                        bone: BoneIndex::new(tables.bones.resolve(&o.bone)?),
+                        translation: o.translation,
+                        rotation: o.rotation,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        DumpOffsets::Uv { offsets } => Offsets::Uv(uv_offsets(offsets)),
+        DumpOffsets::AdditionalUv { channel, offsets } => {
+            Offsets::AdditionalUv(*channel, uv_offsets(offsets))
+        }
+        DumpOffsets::Material { offsets } => Offsets::Material(
+            offsets
+                .iter()
+                .map(|o| {
+                    Ok(morph::MaterialOffset {
+                        material: MaterialIndex::new(tables.materials.resolve(&o.material)?),
+                        operation: o.operation,
+                        diffuse: o.diffuse,
+                        specular: o.specular,
+                        specular_strength: o.specular_strength,
+                        ambient: o.ambient,
+                        edge_color: o.edge_color,
+                        edge_scale: o.edge_scale,
+                        texture_tint: o.texture_tint,
+                        environment_tint: o.environment_tint,
+                        toon_tint: o.toon_tint,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        DumpOffsets::Flip { offsets } => Offsets::Flip(
+            offsets
+                .iter()
+                .map(|o| {
+                    Ok(morph::FlipOffset {
+                        morph: morph_index(&o.morph)?,
+                        weight: o.weight,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        DumpOffsets::Impulse { offsets } => Offsets::Impulse(
+            offsets
+                .iter()
+                .map(|o| {
+                    Ok(morph::ImpulseOffset {
+                        rigid_body: RigidBodyIndex::new(
+                            tables.rigid_bodies.resolve(&o.rigid_body)?,
+                        ),
+                        local: o.local,
+                        velocity: o.velocity,
+                        torque: o.torque,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+    };
+
+    Ok(Morph {
+        name: morph.name.to_name(encoding),
+        panel: morph.panel,
+        offsets,
+    })
+}
+
+fn dump_display_frame(frame: &DisplayFrame, tables: &Tables) -> DumpDisplayFrame {
+    DumpDisplayFrame {
+        name: (&frame.name).into(),
+        special: frame.special,
+        entries: frame
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                FrameEntry::Bone(index) => DumpFrameEntry::Bone(tables.bones.to_ref(index.value())),
+                FrameEntry::Morph(index) => {
+                    DumpFrameEntry::Morph(tables.morphs.to_ref(index.value()))
+                }
+            })
+            .collect(),
+    }
+}
+
+fn load_display_frame(
+    frame: &DumpDisplayFrame,
+    tables: &Tables,
+    encoding: TextEncoding,
+) -> Result<DisplayFrame> {
+    Ok(DisplayFrame {
+        name: frame.name.to_name(encoding),
+        special: frame.special,
+        entries: frame
+            .entries
+            .iter()
+            .map(|entry| {
+                Ok(match entry {
+                    DumpFrameEntry::Bone(r) => {
+                        FrameEntry::Bone(BoneIndex::new(tables.bones.resolve(r)?))
+                    }
+                    DumpFrameEntry::Morph(r) => {
+                        FrameEntry::Morph(MorphIndex::new(tables.morphs.resolve(r)?))
+                    }
+                })
+            })
+            .collect::<Result<_>>()?,
+    })
+}
+
+fn dump_rigid_body(rigid_body: &RigidBody, tables: &Tables) -> DumpRigidBody {
+    DumpRigidBody {
+        name: (&rigid_body.name).into(),
+        bone: tables.bones.to_ref(rigid_body.bone.value()),
+        group: rigid_body.group.raw,
+        non_collision_mask: rigid_body.non_collision_mask.raw,
+        shape: rigid_body.shape,
+        size: rigid_body.size,
+        position: rigid_body.position,
+        rotation: rigid_body.rotation,
+        mass: rigid_body.mass,
+        linear_damping: rigid_body.linear_damping,
+        angular_damping: rigid_body.angular_damping,
+        repulsion: rigid_body.repulsion,
+        friction: rigid_body.friction,
+        physics_mode: rigid_body.physics_mode,
+    }
+}
+
+fn load_rigid_body(
+    rigid_body: &DumpRigidBody,
+    tables: &Tables,
+    encoding: TextEncoding,
+) -> Result<RigidBody> {
+    Ok(RigidBody {
+        name: rigid_body.name.to_name(encoding),
+        bone: BoneIndex::new(tables.bones.resolve(&rigid_body.bone)?),
+        group: CollisionGroup::from_raw(rigid_body.group),
+        non_collision_mask: CollisionMask::from_raw(rigid_body.non_collision_mask),
+        shape: rigid_body.shape,
+        size: rigid_body.size,
+        position: rigid_body.position,
+        rotation: rigid_body.rotation,
+        mass: rigid_body.mass,
+        linear_damping: rigid_body.linear_damping,
+        angular_damping: rigid_body.angular_damping,
+        repulsion: rigid_body.repulsion,
+        friction: rigid_body.friction,
+        physics_mode: rigid_body.physics_mode,
+    })
+}
+
+fn dump_joint(joint: &Joint, tables: &Tables) -> DumpJoint {
+    DumpJoint {
+        name: (&joint.name).into(),
+        typ: joint.typ,
+        rigid_body_a: tables.rigid_bodies.to_ref(joint.rigid_body_a.value()),
+        rigid_body_b: tables.rigid_bodies.to_ref(joint.rigid_body_b.value()),
+        position: joint.position,
+        rotation: joint.rotation,
+        position_min: joint.position_min,
+        position_max: joint.position_max,
+        rotation_min: joint.rotation_min,
+        rotation_max: joint.rotation_max,
+        position_spring: joint.position_spring,
+        rotation_spring: joint.rotation_spring,
+    }
+}
+
+fn load_joint(joint: &DumpJoint, tables: &Tables, encoding: TextEncoding) -> Result<Joint> {
+    Ok(Joint {
+        name: joint.name.to_name(encoding),
+        typ: joint.typ,
+        rigid_body_a: RigidBodyIndex::new(tables.rigid_bodies.resolve(&joint.rigid_body_a)?),
+        rigid_body_b: RigidBodyIndex::new(tables.rigid_bodies.resolve(&joint.rigid_body_b)?),
+        position: joint.position,
+        rotation: joint.rotation,
+        position_min: joint.position_min,
+        position_max: joint.position_max,
+        rotation_min: joint.rotation_min,
+        rotation_max: joint.rotation_max,
+        position_spring: joint.position_spring,
+        rotation_spring: joint.rotation_spring,
+    })
+}
@@ -0,0 +1,412 @@
+//! Edits that keep the references between sections consistent.
+//!
+//! Removing an element shifts every element after it down by one, so each index pointing past
+//! it is decremented, and the references to the removed element itself are dropped, nilled or
+//! redirected depending on what makes sense for the field.
+
+use crate::{
+    bone::{Bone, Tail},
+    display_frame::FrameEntry,
+    material::Material,
+    morph::{Morph, Offsets},
+    pmx::Pmx,
+    types::{BoneIndex, IndexSize, MaterialIndex, MorphIndex},
+};
+
+/// Where references to a removed index go.
+#[derive(Clone, Copy)]
+struct Removal {
+    removed: i32,
+    /// The already shifted index references to the removed element are redirected to.
+    replacement: i32,
+}
+
+impl Removal {
+    fn new(removed: usize, replacement: i32) -> Self {
+        let removed = removed as i32;
+        let replacement = match replacement {
+            r if r == removed => -1,
+            r if r > removed => r - 1,
+            r => r,
+        };
+
+        Self {
+            removed,
+            replacement,
+        }
+    }
+
+    /// The value `value` becomes after the removal.
+    fn remap(self, value: i32) -> i32 {
+        match value {
+            v if v == self.removed => self.replacement,
+            v if v > self.removed => v - 1,
+            v => v,
+        }
+    }
+
+    /// Returns false if `value` referred to the removed element.
+    fn keeps(self, value: i32) -> bool {
+        value != self.removed
+    }
+
+    fn bone(self, index: &mut BoneIndex) {
+        *index = BoneIndex::new(self.remap(index.value()));
+    }
+
+    fn material(self, index: &mut MaterialIndex) {
+        *index = MaterialIndex::new(self.remap(index.value()));
+    }
+
+    fn morph(self, index: &mut MorphIndex) {
+        *index = MorphIndex::new(self.remap(index.value()));
+    }
+}
+
+/// Where indices go when an element is inserted.
+#[derive(Clone, Copy)]
+struct Insertion {
+    at: i32,
+}
+
+impl Insertion {
+    fn bone(self, index: &mut BoneIndex) {
+        if index.value() >= self.at {
+            *index = BoneIndex::new(index.value() + 1);
+        }
+    }
+}
+
+impl Pmx {
+    /// Removes a material along with the surfaces it covers.
+    ///
+    /// Material morph offsets targeting it are dropped and soft bodies using it get a nil
+    /// material. Vertices only used by the removed surfaces are kept. Returns `None` if there is
+    /// no such material.
+    pub fn remove_material(&mut self, index: usize) -> Option<Material> {
+        if index >= self.materials.inner.len() {
+            return None;
+        }
+
+        let start: usize = self.materials.inner[..index]
+            .iter()
+            .map(|material| material.surface_count.max(0) as usize)
+            .sum();
+        let start = start.min(self.surfaces.inner.len());
+        let end = (start + self.materials.inner[index].surface_count.max(0) as usize)
+            .min(self.surfaces.inner.len());
+
+        self.surfaces.inner.drain(start..end);
+        self.surfaces.len = self.surfaces.inner.len();
+
+        let material = self.materials.inner.remove(index);
+        self.materials.len = self.materials.inner.len();
+
+        let removal = Removal::new(index, -1);
+
+        for morph in &mut self.morphs.inner {
+            if let Offsets::Material(offsets) = &mut morph.offsets {
+                // -1 targets every material and stays as it is
+                offsets.retain(|o| removal.keeps(o.material.value()));
+                offsets
+                    .iter_mut()
+                    .for_each(|o| removal.material(&mut o.material));
+            }
+        }
+
+        for soft_body in self.soft_bodies.iter_mut().flat_map(|s| &mut s.inner) {
+            removal.material(&mut soft_body.material);
+        }
+
+        Some(material)
+    }
+
+    /// Removes a bone and updates every reference to the bones after it.
+    ///
+    /// References to the removed bone are handled as follows:
+    /// - vertex weights and the parents of its children move to its parent, or become nil if it
+    ///   has none
+    /// - tails, inherit parents, IK targets and rigid bodies become nil
+    /// - IK links, bone morph offsets and display frame entries are dropped
+    ///
+    /// Returns `None` if there is no such bone.
+    pub fn remove_bone(&mut self, index: usize) -> Option<Bone> {
+        if index >= self.bones.inner.len() {
+            return None;
+        }
+
+        let bone = self.bones.inner.remove(index);
+        self.bones.len = self.bones.inner.len();
+
+        let reparent = Removal::new(index, bone.parent.value());
+        let nil = Removal::new(index, -1);
+
+        for vertex in &mut self.vertices.inner {
+            for bone in vertex.weight_deform.bone_indices_mut() {
+                reparent.bone(bone);
+            }
+        }
+
+        for bone in &mut self.bones.inner {
+            reparent.bone(&mut bone.parent);
+            if let Tail::Bone(tail) = &mut bone.tail {
+                nil.bone(tail);
+            }
+            if let Some(inherit) = &mut bone.inherit {
+                nil.bone(&mut inherit.parent);
+            }
+            if let Some(ik) = &mut bone.ik {
+                nil.bone(&mut ik.target);
+                ik.links.retain(|link| nil.keeps(link.bone.value()));
+                ik.links
+                    .iter_mut()
+                    .for_each(|link| nil.bone(&mut link.bone));
+            }
+        }
+
+        for morph in &mut self.morphs.inner {
+            if let Offsets::Bone(offsets) = &mut morph.offsets {
+                offsets.retain(|o| nil.keeps(o.bone.value()));
+                offsets.iter_mut().for_each(|o| nil.bone(&mut o.bone));
+            }
+        }
+
+        for frame in &mut self.display_frames.inner {
+            frame.entries.retain(|entry| match entry {
+                FrameEntry::Bone(bone) => nil.keeps(bone.value()),
+                FrameEntry::Morph(_) => true,
+            });
+            for entry in &mut frame.entries {
+                if let FrameEntry::Bone(bone) = entry {
+                    nil.bone(bone);
+                }
+            }
+        }
+
+        for rigid_body in &mut self.rigid_bodies.inner {
+            nil.bone(&mut rigid_body.bone);
+        }
+
+        Some(bone)
+    }
+
+    /// Removes a morph and updates every reference to the morphs after it.
+    ///
+    /// Group and flip morph offsets and display frame entries referring to the removed morph are
+    /// dropped. Returns `None` if there is no such morph.
+    pub fn remove_morph(&mut self, index: usize) -> Option<Morph> {
+        if index >= self.morphs.inner.len() {
+            return None;
+        }
+
+        let morph = self.morphs.inner.remove(index);
+        self.morphs.len = self.morphs.inner.len();
+
+        let nil = Removal::new(index, -1);
+
+        for morph in &mut self.morphs.inner {
+            match &mut morph.offsets {
+                Offsets::Group(offsets) => {
+                    offsets.retain(|o| nil.keeps(o.morph.value()));
+                    offsets.iter_mut().for_each(|o| nil.morph(&mut o.morph));
+                }
+                Offsets::Flip(offsets) => {
+                    offsets.retain(|o| nil.keeps(o.morph.value()));
+                    offsets.iter_mut().for_each(|o| nil.morph(&mut o.morph));
+                }
+                _ => {}
+            }
+        }
+
+        for frame in &mut self.display_frames.inner {
+            frame.entries.retain(|entry| match entry {
+                FrameEntry::Morph(morph) => nil.keeps(morph.value()),
+                FrameEntry::Bone(_) => true,
+            });
+            for entry in &mut frame.entries {
+                if let FrameEntry::Morph(morph) = entry {
+                    nil.morph(morph);
+                }
+            }
+        }
+
+        Some(morph)
+    }
+
+    /// Inserts a bone at `index`, updating every reference to the bones after it.
+    ///
+    /// The references of the inserted bone itself are taken as they are, so they refer to the
+    /// bones after the insertion. An index past the end appends the bone. Returns the index the
+    /// bone ended up at.
+    pub fn insert_bone(&mut self, index: usize, bone: Bone) -> usize {
+        let index = index.min(self.bones.inner.len());
+        let shift = Insertion { at: index as i32 };
+
+        for vertex in &mut self.vertices.inner {
+            for bone in vertex.weight_deform.bone_indices_mut() {
+                shift.bone(bone);
+            }
+        }
+
+        for bone in &mut self.bones.inner {
+            shift.bone(&mut bone.parent);
+            if let Tail::Bone(tail) = &mut bone.tail {
+                shift.bone(tail);
+            }
+            if let Some(inherit) = &mut bone.inherit {
+                shift.bone(&mut inherit.parent);
+            }
+            if let Some(ik) = &mut bone.ik {
+                shift.bone(&mut ik.target);
+                ik.links
+                    .iter_mut()
+                    .for_each(|link| shift.bone(&mut link.bone));
+            }
+        }
+
+        for morph in &mut self.morphs.inner {
+            if let Offsets::Bone(offsets) = &mut morph.offsets {
+                offsets.iter_mut().for_each(|o| shift.bone(&mut o.bone));
+            }
+        }
+
+        for frame in &mut self.display_frames.inner {
+            for entry in &mut frame.entries {
+                if let FrameEntry::Bone(bone) = entry {
+                    shift.bone(bone);
+                }
+            }
+        }
+
+        for rigid_body in &mut self.rigid_bodies.inner {
+            shift.bone(&mut rigid_body.bone);
+        }
+
+        self.bones.inner.insert(index, bone);
+        self.bones.len = self.bones.inner.len();
+
+        let size = &mut self.header.globals.bone_idx_size;
+        *size = (*size).max(IndexSize::smallest_for(self.bones.len, true));
+
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::PmxBuilder,
+        morph::{BoneOffset, GroupOffset, Panel},
+        types::{Name, TextEncoding, from_array},
+        vertex::{Vertex, WeightDeform},
+    };
+
+    /// Bones 0 to 2 in a chain, a vertex weighted to bones 1 and 2, a bone morph moving both and
+    /// a group morph of the two morphs before it. The model goes through a write and a parse, so
+    /// the indices are the parsed kind.
+    fn model() -> Pmx {
+        let bone = |name: &str, parent: i32| {
+            let mut bone = Bone::new(name, from_array([0.0; 3]));
+            bone.set_parent(BoneIndex::new(parent));
+            bone
+        };
+        let vertex = Vertex::new(
+            from_array([0.0; 3]),
+            from_array([0.0, 1.0, 0.0]),
+            from_array([0.0; 2]),
+            WeightDeform::Bdef2 {
+                indices: [BoneIndex::new(1), BoneIndex::new(2)],
+                weights: [0.5, 0.5],
+            },
+        );
+        let mut pmx = PmxBuilder::new()
+            .add_bone(bone("a", -1))
+            .add_bone(bone("b", 0))
+            .add_bone(bone("c", 1))
+            .add_vertex(vertex)
+            .build()
+            .unwrap();
+
+        let morph = |name: &str, offsets| Morph {
+            name: Name::new(name, name, TextEncoding::UTF16LE),
+            panel: Panel::Other,
+            offsets,
+        };
+        let bone_offset = |bone: i32| BoneOffset {
+            bone: BoneIndex::new(bone),
+            translation: from_array([1.0, 0.0, 0.0]),
+            rotation: from_array([0.0, 0.0, 0.0, 1.0]),
+        };
+        let group_offset = |morph: i32| GroupOffset {
+            morph: MorphIndex::new(morph),
+            weight: 1.0,
+        };
+        pmx.morphs = vec![
+            morph("move", Offsets::Bone(vec![bone_offset(1), bone_offset(2)])),
+            morph("other", Offsets::Bone(vec![bone_offset(0)])),
+            morph(
+                "group",
+                Offsets::Group(vec![group_offset(0), group_offset(1)]),
+            ),
+        ]
+        .into();
+
+        let mut bytes = Vec::new();
+        pmx.write_to(&mut bytes).unwrap();
+        Pmx::parse(&mut &bytes[..]).unwrap()
+    }
+
+    fn bone_offsets(morph: &Morph) -> Vec<BoneIndex> {
+        match &morph.offsets {
+            Offsets::Bone(offsets) => offsets.iter().map(|o| o.bone).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn remove_bone_remaps_references() {
+        let mut pmx = model();
+        pmx.remove_bone(1).unwrap();
+
+        let bones = &pmx.bones.inner;
+        assert_eq!(bones.len(), 2);
+        assert_eq!(bones[1].parent, BoneIndex::new(0));
+
+        let weights = pmx.vertices.inner[0].weight_deform.bone_indices();
+        assert_eq!(weights, [BoneIndex::new(0), BoneIndex::new(1)]);
+
+        assert_eq!(bone_offsets(&pmx.morphs.inner[0]), [BoneIndex::new(1)]);
+        assert_eq!(bone_offsets(&pmx.morphs.inner[1]), [BoneIndex::new(0)]);
+    }
+
+    #[test]
+    fn remove_morph_remaps_references() {
+        let mut pmx = model();
+        pmx.remove_morph(0).unwrap();
+
+        let Offsets::Group(offsets) = &pmx.morphs.inner[1].offsets else {
+            panic!("not a group morph");
+        };
+        let targets: Vec<_> = offsets.iter().map(|o| o.morph).collect();
+        assert_eq!(targets, [MorphIndex::new(0)]);
+    }
+
+    #[test]
+    fn insert_bone_shifts_references() {
+        let mut pmx = model();
+        let index = pmx.insert_bone(1, Bone::new("new", from_array([0.0; 3])));
+        assert_eq!(index, 1);
+
+        let parents: Vec<_> = pmx.bones.inner.iter().map(|b| b.parent).collect();
+        let expected = [-1, -1, 0, 2].map(BoneIndex::new);
+        assert_eq!(parents, expected);
+
+        let weights = pmx.vertices.inner[0].weight_deform.bone_indices();
+        assert_eq!(weights, [BoneIndex::new(2), BoneIndex::new(3)]);
+
+        let offsets = bone_offsets(&pmx.morphs.inner[0]);
+        assert_eq!(offsets, [BoneIndex::new(2), BoneIndex::new(3)]);
+        assert_eq!(bone_offsets(&pmx.morphs.inner[1]), [BoneIndex::new(0)]);
+    }
+}
@@ -0,0 +1,1836 @@
+//! glTF 2.0 export and import.
+//!
+//! On export the model becomes a single skinned mesh with one primitive per material. Every bone becomes a
+//! joint node, vertex and UV morphs become morph targets named after the morphs (in
+//! `mesh.extras.targetNames`). The additional UV channels become `TEXCOORD_1` to `TEXCOORD_4`
+//! with the first two of their components, and so do their morphs. Coordinates are converted
+//! from MMD's left-handed space to glTF's right-handed one by mirroring Z, and scaled from MMD
+//! units to meters. Import does the reverse, reading only `TEXCOORD_0`, see [`Gltf::to_pmx`].
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Value, json};
+use thiserror::Error;
+
+use crate::{
+    bone::{Bone, BoneFlags, Tail},
+    display_frame::{DisplayFrame, FrameEntry},
+    material::{EnvironmentBlend, Material as PmxMaterial, MaterialFlags, Toon},
+    morph::{Morph, Offsets, Panel, VertexOffset},
+    pmx::{Comment, Globals, Header, ModelName, Pmx, Version},
+    surface::Surface,
+    texture::Texture,
+    types::{
+        BoneIndex, IndexSize, MorphIndex, Name, PmxText, TextEncoding, TextureIndex, Vec3,
+        VertexIndex, from_array, to_array,
+    },
+    vertex::{UvChannel, Vertex, WeightDeform},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("File is not a valid GLB container")]
+    InvalidGlb,
+    #[error(
+        "Buffer \"{0}\" is an external file, which needs the document to be opened from a path"
+    )]
+    ExternalBuffer(String),
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+    #[error("Could not read texture {path:?}: {source}")]
+    Texture {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// How textures end up in the exported document.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureMode {
+    /// Images reference the texture paths of the model as relative URIs.
+    Reference,
+    /// Like [`TextureMode::Reference`], the files are copied next to the document when saving.
+    Copy,
+    /// PNG and JPEG textures are stored in the binary buffer, other formats stay references since
+    /// glTF does not support them.
+    Embed,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// Meters per MMD unit.
+    pub(crate) scale: f32,
+    pub(crate) textures: TextureMode,
+    /// The directory of the model, texture paths are relative to it.
+    pub(crate) base_dir: Option<PathBuf>,
+    /// Mirror X instead of Z, which also turns the model around to face -Z as VRM 0.x expects.
+    pub(crate) mirror_x: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            // the common convention of 1 unit being 8 cm
+            scale: 0.08,
+            textures: TextureMode::Reference,
+            base_dir: None,
+            mirror_x: false,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn textures(&self) -> TextureMode {
+        self.textures
+    }
+
+    pub fn base_dir(&self) -> Option<&Path> {
+        self.base_dir.as_deref()
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn set_textures(&mut self, textures: TextureMode) {
+        self.textures = textures;
+    }
+
+    pub fn set_base_dir(&mut self, base_dir: impl Into<PathBuf>) {
+        self.base_dir = Some(base_dir.into());
+    }
+
+    fn texture_path(&self, path: &str) -> PathBuf {
+        // PMX paths use Windows separators
+        let path = path.replace('\\', "/");
+
+        match &self.base_dir {
+            Some(base) => base.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+/// Collects the binary buffer together with its buffer views and accessors.
+#[derive(Default)]
+struct Builder {
+    buffer: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Builder {
+    fn view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // accessors need their data aligned to the component size
+        while !self.buffer.len().is_multiple_of(4) {
+            self.buffer.push(0);
+        }
+
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+
+        self.buffer.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    fn accessor(&mut self, accessor: Value) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn floats<const N: usize>(
+        &mut self,
+        items: &[[f32; N]],
+        bounds: bool,
+        target: Option<u32>,
+    ) -> usize {
+        let bytes: Vec<u8> = items
+            .iter()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        let view = self.view(&bytes, target);
+
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": items.len(),
+            "type": accessor_type(N),
+        });
+        if bounds {
+            let (min, max) = bounds_of(items.iter());
+            accessor["min"] = json!(min.as_slice());
+            accessor["max"] = json!(max.as_slice());
+        }
+
+        self.accessor(accessor)
+    }
+
+    /// An accessor that is zero except for the given, strictly increasing, indices.
+    fn sparse<const N: usize>(
+        &mut self,
+        count: usize,
+        indices: &[u32],
+        values: &[[f32; N]],
+        bounds: bool,
+    ) -> usize {
+        let mut accessor = json!({
+            "componentType": FLOAT,
+            "count": count,
+            "type": accessor_type(N),
+        });
+
+        if bounds {
+            let zero = [0.0; N];
+            let (min, max) = bounds_of(values.iter().chain(std::iter::once(&zero)));
+            accessor["min"] = json!(min.as_slice());
+            accessor["max"] = json!(max.as_slice());
+        }
+
+        if !indices.is_empty() {
+            let index_bytes: Vec<u8> = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+            let value_bytes: Vec<u8> = values
+                .iter()
+                .flatten()
+                .flat_map(|f| f.to_le_bytes())
+                .collect();
+
+            let index_view = self.view(&index_bytes, None);
+            let value_view = self.view(&value_bytes, None);
+
+            accessor["sparse"] = json!({
+                "count": indices.len(),
+                "indices": { "bufferView": index_view, "componentType": UNSIGNED_INT },
+                "values": { "bufferView": value_view },
+            });
+        }
+
+        self.accessor(accessor)
+    }
+
+    fn joints(&mut self, items: &[[u16; 4]]) -> usize {
+        let bytes: Vec<u8> = items
+            .iter()
+            .flatten()
+            .flat_map(|j| j.to_le_bytes())
+            .collect();
+        let view = self.view(&bytes, Some(ARRAY_BUFFER));
+
+        self.accessor(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_SHORT,
+            "count": items.len(),
+            "type": "VEC4",
+        }))
+    }
+
+    fn indices(&mut self, indices: &[u32]) -> usize {
+        let bytes: Vec<u8> = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let view = self.view(&bytes, Some(ELEMENT_ARRAY_BUFFER));
+
+        self.accessor(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }))
+    }
+}
+
+fn accessor_type(components: usize) -> &'static str {
+    match components {
+        1 => "SCALAR",
+        2 => "VEC2",
+        3 => "VEC3",
+        4 => "VEC4",
+        16 => "MAT4",
+        _ => unreachable!("no accessor type with {components} components"),
+    }
+}
+
+fn bounds_of<'a, const N: usize>(
+    items: impl Iterator<Item = &'a [f32; N]>,
+) -> ([f32; N], [f32; N]) {
+    let mut min = [f32::INFINITY; N];
+    let mut max = [f32::NEG_INFINITY; N];
+
+    for item in items {
+        for i in 0..N {
+            min[i] = min[i].min(item[i]);
+            max[i] = max[i].max(item[i]);
+        }
+    }
+
+    if min[0] > max[0] {
+        return ([0.0; N], [0.0; N]);
+    }
+
+    (min, max)
+}
+
+/// A glTF document with its binary buffers, either exported from a model and ready to be saved as
+/// `.gltf` + `.bin` or `.glb`, or loaded from disk to be converted into a model.
+#[derive(Debug)]
+pub struct Gltf {
+    pub(crate) json: Value,
+    pub(crate) buffers: Vec<Vec<u8>>,
+    /// Texture files to copy next to the document when saving, as (source, relative target).
+    pub(crate) copies: Vec<(PathBuf, String)>,
+}
+
+impl Gltf {
+    /// The document, exported documents lack the `uri` of the binary buffer, which depends on how
+    /// it is saved.
+    pub fn json(&self) -> &Value {
+        &self.json
+    }
+
+    pub fn buffers(&self) -> &[Vec<u8>] {
+        &self.buffers
+    }
+
+    pub fn from_pmx(pmx: &Pmx, options: &ExportOptions) -> Result<Self> {
+        let scale = options.scale;
+        let mirror = |[x, y, z]: [f32; 3]| -> [f32; 3] {
+            if options.mirror_x {
+                [-x, y, z]
+            } else {
+                [x, y, -z]
+            }
+        };
+        let position = |v: Vec3| -> [f32; 3] {
+            let [x, y, z] = mirror(to_array(v));
+            [x * scale, y * scale, z * scale]
+        };
+
+        let mut builder = Builder::default();
+
+        let bones = &pmx.bones.inner;
+        let vertices = &pmx.vertices.inner;
+        let globals = &pmx.header.globals;
+
+        // skeleton
+
+        // bones looping back onto themselves are exported as roots
+        let skeleton = pmx.skeleton();
+        let parents: Vec<Option<usize>> = (0..bones.len())
+            .map(|i| skeleton.parent(i).filter(|_| skeleton.is_rooted(i)))
+            .collect();
+
+        let mut nodes: Vec<Value> = bones
+            .iter()
+            .enumerate()
+            .map(|(i, bone)| {
+                let world = position(bone.position);
+                let translation = match parents[i] {
+                    Some(parent) => {
+                        let parent = position(bones[parent].position);
+                        [
+                            world[0] - parent[0],
+                            world[1] - parent[1],
+                            world[2] - parent[2],
+                        ]
+                    }
+                    None => world,
+                };
+
+                json!({
+                    "name": bone.name.local.as_str(),
+                    "translation": translation,
+                })
+            })
+            .collect();
+
+        for (child, parent) in parents.iter().enumerate() {
+            if let Some(parent) = parent {
+                let node = &mut nodes[*parent];
+                if node.get("children").is_none() {
+                    node["children"] = json!([]);
+                }
+                node["children"].as_array_mut().unwrap().push(json!(child));
+            }
+        }
+
+        // bones have no rotation in the bind pose, so the inverse bind matrix is a translation
+        let inverse_binds: Vec<[f32; 16]> = bones
+            .iter()
+            .map(|bone| {
+                let [x, y, z] = position(bone.position);
+                [
+                    1.0, 0.0, 0.0, 0.0, //
+                    0.0, 1.0, 0.0, 0.0, //
+                    0.0, 0.0, 1.0, 0.0, //
+                    -x, -y, -z, 1.0,
+                ]
+            })
+            .collect();
+
+        // vertex attributes
+
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| position(v.pos)).collect();
+        let normals: Vec<[f32; 3]> = vertices
+            .iter()
+            .map(|v| {
+                let [x, y, z]: [f32; 3] = to_array(v.normal);
+                let length = (x * x + y * y + z * z).sqrt();
+                if length > 0.0 {
+                    mirror([x / length, y / length, z / length])
+                } else {
+                    [0.0, 1.0, 0.0]
+                }
+            })
+            .collect();
+        let uvs: Vec<[f32; 2]> = vertices.iter().map(|v| to_array(v.uv)).collect();
+
+        let mut attributes = json!({
+            "POSITION": builder.floats(&positions, true, Some(ARRAY_BUFFER)),
+            "NORMAL": builder.floats(&normals, false, Some(ARRAY_BUFFER)),
+            "TEXCOORD_0": builder.floats(&uvs, false, Some(ARRAY_BUFFER)),
+        });
+
+        // glTF texture coordinates only have two components, the others are dropped
+        for &channel in globals.additional_uv_channels() {
+            let uvs: Vec<[f32; 2]> = vertices
+                .iter()
+                .map(|v| {
+                    let [u, v, _, _] = v.additional_uv(channel).map_or([0.0; 4], Into::into);
+                    [u, v]
+                })
+                .collect();
+            attributes[texcoord(Some(channel))] =
+                json!(builder.floats(&uvs, false, Some(ARRAY_BUFFER)));
+        }
+
+        if !bones.is_empty() {
+            let (joints, weights): (Vec<[u16; 4]>, Vec<[f32; 4]>) = vertices
+                .iter()
+                .map(|v| v.weight_deform.skin_weights(bones.len()))
+                .unzip();
+
+            attributes["JOINTS_0"] = json!(builder.joints(&joints));
+            attributes["WEIGHTS_0"] = json!(builder.floats(&weights, false, Some(ARRAY_BUFFER)));
+        }
+
+        // morph targets, only vertex and (additional) UV morphs have a glTF equivalent
+
+        let mut targets = Vec::new();
+        let mut target_names = Vec::new();
+
+        for morph in &pmx.morphs.inner {
+            if !is_morph_target(&morph.offsets, globals) {
+                continue;
+            }
+
+            let target = match &morph.offsets {
+                Offsets::Vertex(offsets) => {
+                    let merged = merge_offsets(
+                        offsets
+                            .iter()
+                            .map(|o| (o.vertex.value(), position(o.translation))),
+                    );
+                    let (indices, values): (Vec<u32>, Vec<[f32; 3]>) =
+                        filter_in_range(merged, vertices.len());
+
+                    json!({
+                        "POSITION": builder.sparse(vertices.len(), &indices, &values, true),
+                    })
+                }
+                Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => {
+                    let merged = merge_offsets(offsets.iter().map(|o| {
+                        let [u, v, _, _]: [f32; 4] = to_array(o.offset);
+                        (o.vertex.value(), [u, v])
+                    }));
+                    let (indices, values): (Vec<u32>, Vec<[f32; 2]>) =
+                        filter_in_range(merged, vertices.len());
+
+                    let mut target = json!({});
+                    target[texcoord(morph.offsets.uv_channel())] =
+                        json!(builder.sparse(vertices.len(), &indices, &values, false));
+                    target
+                }
+                _ => continue,
+            };
+
+            targets.push(target);
+            target_names.push(morph.name.local.as_str());
+        }
+
+        // textures
+
+        let mut images = Vec::new();
+        let mut textures = Vec::new();
+        let mut copies = Vec::new();
+        let mut texture_map: HashMap<usize, usize> = HashMap::new();
+
+        for material in &pmx.materials.inner {
+            let Some(index) = material.tex_idx.as_usize() else {
+                continue;
+            };
+            let Some(texture) = pmx.textures.inner.get(index) else {
+                continue;
+            };
+            if texture_map.contains_key(&index) {
+                continue;
+            }
+
+            let path = texture.path.as_str();
+            let uri = path.replace('\\', "/");
+            let source = options.texture_path(path);
+
+            let mime_type = match uri.rsplit('.').next().map(str::to_ascii_lowercase) {
+                Some(ext) if ext == "png" => Some("image/png"),
+                Some(ext) if ext == "jpg" || ext == "jpeg" => Some("image/jpeg"),
+                _ => None,
+            };
+
+            let image = match (options.textures, mime_type) {
+                (TextureMode::Embed, Some(mime_type)) => {
+                    let bytes = std::fs::read(&source).map_err(|source_error| Error::Texture {
+                        path: source.clone(),
+                        source: source_error,
+                    })?;
+                    let view = builder.view(&bytes, None);
+
+                    json!({ "name": path, "bufferView": view, "mimeType": mime_type })
+                }
+                (mode, _) => {
+                    if mode == TextureMode::Copy {
+                        copies.push((source, uri.clone()));
+                    }
+
+                    json!({ "name": path, "uri": percent_encode(&uri) })
+                }
+            };
+
+            images.push(image);
+            textures.push(json!({ "sampler": 0, "source": images.len() - 1 }));
+            texture_map.insert(index, textures.len() - 1);
+        }
+
+        // materials and primitives
+
+        let mut materials = Vec::new();
+        let mut primitives = Vec::new();
+        let surfaces = &pmx.surfaces.inner;
+        let mut start = 0;
+
+        for material in &pmx.materials.inner {
+            let count = (material.surface_count.max(0) as usize).min(surfaces.len() - start);
+            let range = &surfaces[start..start + count];
+            start += count;
+
+            let diffuse: [f32; 4] = to_array(material.diffuse);
+            let mut pbr = json!({
+                "baseColorFactor": diffuse,
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            });
+            if let Some(texture) = material
+                .tex_idx
+                .as_usize()
+                .and_then(|index| texture_map.get(&index))
+            {
+                pbr["baseColorTexture"] = json!({ "index": texture });
+            }
+
+            materials.push(json!({
+                "name": material.name.local.as_str(),
+                "pbrMetallicRoughness": pbr,
+                "doubleSided": material.flags.no_cull(),
+                "alphaMode": if diffuse[3] < 1.0 { "BLEND" } else { "OPAQUE" },
+            }));
+
+            // mirroring an axis flips the handedness, the winding is reversed to keep faces outward
+            let indices: Vec<u32> = range
+                .chunks_exact(3)
+                .flat_map(|tri| {
+                    [
+                        tri[0].index.value(),
+                        tri[2].index.value(),
+                        tri[1].index.value(),
+                    ]
+                })
+                .map(|i| i as u32)
+                .collect();
+
+            if indices.is_empty() {
+                continue;
+            }
+
+            let mut primitive = json!({
+                "attributes": attributes,
+                "indices": builder.indices(&indices),
+                "material": materials.len() - 1,
+            });
+            if !targets.is_empty() {
+                primitive["targets"] = json!(targets);
+            }
+
+            primitives.push(primitive);
+        }
+
+        // assembly
+
+        let model_name = pmx.header.name.local.as_str();
+
+        let mut mesh = json!({ "name": model_name, "primitives": primitives });
+        if !targets.is_empty() {
+            mesh["weights"] = json!(vec![0.0; targets.len()]);
+            mesh["extras"] = json!({ "targetNames": target_names });
+        }
+
+        let mut scene_nodes: Vec<usize> =
+            (0..bones.len()).filter(|&i| parents[i].is_none()).collect();
+
+        let mut mesh_node = json!({ "name": model_name });
+        if !primitives.is_empty() {
+            mesh_node["mesh"] = json!(0);
+        }
+
+        let mut skins = Vec::new();
+        if !bones.is_empty() {
+            let inverse_bind_matrices = builder.floats(&inverse_binds, false, None);
+            skins.push(json!({
+                "joints": (0..bones.len()).collect::<Vec<_>>(),
+                "inverseBindMatrices": inverse_bind_matrices,
+            }));
+            if !primitives.is_empty() {
+                mesh_node["skin"] = json!(0);
+            }
+        }
+
+        nodes.push(mesh_node);
+        scene_nodes.push(nodes.len() - 1);
+
+        let mut json = json!({
+            "asset": { "version": "2.0", "generator": "sermmde" },
+            "scene": 0,
+            "scenes": [{ "name": model_name, "nodes": scene_nodes }],
+            "nodes": nodes,
+            "materials": materials,
+        });
+
+        if !primitives.is_empty() {
+            json["meshes"] = json!([mesh]);
+        }
+        if !skins.is_empty() {
+            json["skins"] = json!(skins);
+        }
+        if !images.is_empty() {
+            json["images"] = json!(images);
+            json["textures"] = json!(textures);
+            json["samplers"] = json!([{}]);
+        }
+        if !builder.buffer.is_empty() {
+            json["buffers"] = json!([{ "byteLength": builder.buffer.len() }]);
+            json["bufferViews"] = json!(builder.views);
+            json["accessors"] = json!(builder.accessors);
+        }
+
+        let buffers = if builder.buffer.is_empty() {
+            Vec::new()
+        } else {
+            vec![builder.buffer]
+        };
+
+        Ok(Self {
+            json,
+            buffers,
+            copies,
+        })
+    }
+
+    /// Encodes the document as a binary `.glb` container, the first buffer becomes the binary chunk.
+    pub fn to_glb(&self) -> Result<Vec<u8>> {
+        let mut json = self.json.clone();
+        if let Some(buffer) = json["buffers"].get_mut(0).and_then(Value::as_object_mut) {
+            buffer.remove("uri");
+        }
+
+        let mut json = serde_json::to_vec(&json)?;
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+
+        let mut bin = self.buffers.first().cloned().unwrap_or_default();
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+
+        let mut length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            length += 8 + bin.len();
+        }
+
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+
+        if !bin.is_empty() {
+            glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            glb.extend_from_slice(b"BIN\0");
+            glb.extend_from_slice(&bin);
+        }
+
+        Ok(glb)
+    }
+
+    pub fn save_glb(&self, path: &Path) -> Result<()> {
+        let fh = std::fs::File::create(path)?;
+
+        let mut writer = BufWriter::new(fh);
+
+        writer.write_all(&self.to_glb()?)?;
+
+        writer.flush()?;
+
+        self.copy_textures(path)?;
+
+        Ok(())
+    }
+
+    /// Saves the document as `.gltf` with the buffers in `.bin` files of the same name next to it.
+    pub fn save_gltf(&self, path: &Path) -> Result<()> {
+        let mut json = self.json.clone();
+
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            let bin_name = match i {
+                0 => format!("{stem}.bin"),
+                i => format!("{stem}_{i}.bin"),
+            };
+
+            json["buffers"][i]["uri"] = json!(percent_encode(&bin_name));
+
+            std::fs::write(path.with_file_name(&bin_name), buffer)?;
+        }
+
+        let fh = std::fs::File::create(path)?;
+
+        let mut writer = BufWriter::new(fh);
+
+        serde_json::to_writer_pretty(&mut writer, &json)?;
+
+        writer.flush()?;
+
+        self.copy_textures(path)?;
+
+        Ok(())
+    }
+
+    fn copy_textures(&self, document: &Path) -> Result<()> {
+        let dir = document.parent().unwrap_or(Path::new(""));
+
+        for (source, target) in &self.copies {
+            let target = dir.join(target);
+
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::copy(source, &target).map_err(|error| Error::Texture {
+                path: source.clone(),
+                source: error,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a morph becomes a morph target: vertex, UV and additional UV morphs of a channel the
+/// vertices have.
+pub(crate) fn is_morph_target(offsets: &Offsets, globals: &Globals) -> bool {
+    match offsets {
+        Offsets::Vertex(_) | Offsets::Uv(_) => true,
+        Offsets::AdditionalUv(channel, _) => *channel < globals.additional_vec4_count(),
+        _ => false,
+    }
+}
+
+/// The attribute of the main UV, or of an additional UV channel.
+fn texcoord(channel: Option<UvChannel>) -> String {
+    format!(
+        "TEXCOORD_{}",
+        channel.map_or(0, |channel| channel.index() + 1)
+    )
+}
+
+/// Sums offsets of the same vertex and sorts them by vertex, as sparse accessors require.
+fn merge_offsets<const N: usize>(
+    offsets: impl Iterator<Item = (i32, [f32; N])>,
+) -> BTreeMap<i32, [f32; N]> {
+    let mut merged: BTreeMap<i32, [f32; N]> = BTreeMap::new();
+
+    for (vertex, offset) in offsets {
+        let entry = merged.entry(vertex).or_insert([0.0; N]);
+        for i in 0..N {
+            entry[i] += offset[i];
+        }
+    }
+
+    merged
+}
+
+fn filter_in_range<const N: usize>(
+    merged: BTreeMap<i32, [f32; N]>,
+    vertex_count: usize,
+) -> (Vec<u32>, Vec<[f32; N]>) {
+    merged
+        .into_iter()
+        .filter(|&(vertex, _)| vertex >= 0 && (vertex as usize) < vertex_count)
+        .map(|(vertex, offset)| (vertex as u32, offset))
+        .unzip()
+}
+
+/// Percent-encodes everything but unreserved characters and path separators.
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~' | b'/') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+
+    encoded
+}
+
+/// Column-major 4x4 matrix, as stored in glTF.
+type Matrix = [f32; 16];
+
+const IDENTITY: Matrix = [
+    1.0, 0.0, 0.0, 0.0, //
+    0.0, 1.0, 0.0, 0.0, //
+    0.0, 0.0, 1.0, 0.0, //
+    0.0, 0.0, 0.0, 1.0,
+];
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|i| {
+        let (col, row) = (i / 4, i % 4);
+        (0..4).map(|k| a[k * 4 + row] * b[col * 4 + k]).sum()
+    })
+}
+
+fn transform_point(m: &Matrix, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    [
+        m[0] * x + m[4] * y + m[8] * z + m[12],
+        m[1] * x + m[5] * y + m[9] * z + m[13],
+        m[2] * x + m[6] * y + m[10] * z + m[14],
+    ]
+}
+
+fn transform_vector(m: &Matrix, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    [
+        m[0] * x + m[4] * y + m[8] * z,
+        m[1] * x + m[5] * y + m[9] * z,
+        m[2] * x + m[6] * y + m[10] * z,
+    ]
+}
+
+fn normalize([x, y, z]: [f32; 3]) -> [f32; 3] {
+    let length = (x * x + y * y + z * z).sqrt();
+    if length > 0.0 {
+        [x / length, y / length, z / length]
+    } else {
+        [x, y, z]
+    }
+}
+
+fn floats<const N: usize>(value: &Value, default: [f32; N]) -> [f32; N] {
+    match value.as_array() {
+        Some(array) if array.len() == N => {
+            std::array::from_fn(|i| array[i].as_f64().unwrap_or(default[i] as f64) as f32)
+        }
+        _ => default,
+    }
+}
+
+fn node_matrix(node: &Value) -> Matrix {
+    if node.get("matrix").is_some() {
+        return floats(&node["matrix"], IDENTITY);
+    }
+
+    let [tx, ty, tz] = floats(&node["translation"], [0.0; 3]);
+    let [x, y, z, w] = floats(&node["rotation"], [0.0, 0.0, 0.0, 1.0]);
+    let [sx, sy, sz] = floats(&node["scale"], [1.0; 3]);
+
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * sx,
+        (2.0 * (x * y + z * w)) * sx,
+        (2.0 * (x * z - y * w)) * sx,
+        0.0,
+        (2.0 * (x * y - z * w)) * sy,
+        (1.0 - 2.0 * (x * x + z * z)) * sy,
+        (2.0 * (y * z + x * w)) * sy,
+        0.0,
+        (2.0 * (x * z + y * w)) * sz,
+        (2.0 * (y * z - x * w)) * sz,
+        (1.0 - 2.0 * (x * x + y * y)) * sz,
+        0.0,
+        tx,
+        ty,
+        tz,
+        1.0,
+    ]
+}
+
+fn index_of(value: &Value) -> Option<usize> {
+    value.as_u64().map(|index| index as usize)
+}
+
+fn array_of(value: &Value) -> &[Value] {
+    value.as_array().map_or(&[], Vec::as_slice)
+}
+
+impl Gltf {
+    /// Loads a `.gltf` or `.glb` file, external buffers are resolved relative to the file.
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+
+        Self::load(&bytes, Some(path.parent().unwrap_or(Path::new(""))))
+    }
+
+    /// Parses a `.glb` or a self-contained `.gltf` from any reader, the reader is consumed until
+    /// EOF.
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        Self::load(&bytes, None)
+    }
+
+    fn load(bytes: &[u8], base_dir: Option<&Path>) -> Result<Self> {
+        let (json, mut bin): (Value, Option<Vec<u8>>) = if bytes.starts_with(b"glTF") {
+            parse_glb(bytes)?
+        } else {
+            (serde_json::from_slice(bytes)?, None)
+        };
+
+        let buffers = array_of(&json["buffers"])
+            .iter()
+            .map(|buffer| match buffer["uri"].as_str() {
+                None => bin.take().ok_or(Error::Malformed("buffer without data")),
+                Some(uri) if uri.starts_with("data:") => decode_data_uri(uri),
+                Some(uri) => {
+                    let dir = base_dir.ok_or_else(|| Error::ExternalBuffer(uri.to_string()))?;
+                    Ok(std::fs::read(dir.join(percent_decode(uri)))?)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            json,
+            buffers,
+            copies: Vec::new(),
+        })
+    }
+
+    fn view(&self, index: usize) -> Result<(&[u8], Option<usize>)> {
+        let view = &self.json["bufferViews"][index];
+
+        let buffer = index_of(&view["buffer"])
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or(Error::Malformed("buffer view"))?;
+        let offset = index_of(&view["byteOffset"]).unwrap_or(0);
+        let length = index_of(&view["byteLength"]).ok_or(Error::Malformed("buffer view"))?;
+
+        let bytes = buffer
+            .get(offset..offset + length)
+            .ok_or(Error::Malformed("buffer view"))?;
+
+        Ok((bytes, index_of(&view["byteStride"])))
+    }
+
+    /// Reads an accessor as flat `f64`s, applying normalization and sparse substitution.
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize)> {
+        let accessor = &self.json["accessors"][index];
+
+        let count = index_of(&accessor["count"]).ok_or(Error::Malformed("accessor"))?;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => Err(Error::Malformed("accessor type"))?,
+        };
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0) as u32;
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+        let size = component_size(component_type)?;
+
+        let mut values = vec![0.0; count * components];
+
+        if let Some(view) = index_of(&accessor["bufferView"]) {
+            let (bytes, stride) = self.view(view)?;
+            let stride = stride.unwrap_or(size * components);
+            let offset = index_of(&accessor["byteOffset"]).unwrap_or(0);
+
+            for (i, value) in values.iter_mut().enumerate() {
+                let at = offset + (i / components) * stride + (i % components) * size;
+                *value = read_component(bytes, at, component_type, normalized)?;
+            }
+        }
+
+        let sparse = &accessor["sparse"];
+        if let Some(sparse_count) = index_of(&sparse["count"]) {
+            let indices = &sparse["indices"];
+            let (index_bytes, _) = self.view(
+                index_of(&indices["bufferView"]).ok_or(Error::Malformed("sparse accessor"))?,
+            )?;
+            let index_type = indices["componentType"].as_u64().unwrap_or(0) as u32;
+            let index_offset = index_of(&indices["byteOffset"]).unwrap_or(0);
+            let index_size = component_size(index_type)?;
+
+            let sparse_values = &sparse["values"];
+            let (value_bytes, _) = self.view(
+                index_of(&sparse_values["bufferView"])
+                    .ok_or(Error::Malformed("sparse accessor"))?,
+            )?;
+            let value_offset = index_of(&sparse_values["byteOffset"]).unwrap_or(0);
+
+            for i in 0..sparse_count {
+                let target = read_component(
+                    index_bytes,
+                    index_offset + i * index_size,
+                    index_type,
+                    false,
+                )? as usize;
+
+                for c in 0..components {
+                    let at = value_offset + (i * components + c) * size;
+                    let value = read_component(value_bytes, at, component_type, normalized)?;
+                    *values
+                        .get_mut(target * components + c)
+                        .ok_or(Error::Malformed("sparse accessor"))? = value;
+                }
+            }
+        }
+
+        Ok((values, components))
+    }
+
+    fn vec3s(&self, index: usize) -> Result<Vec<[f32; 3]>> {
+        let (values, components) = self.accessor(index)?;
+        if components != 3 {
+            Err(Error::Malformed("accessor type"))?
+        }
+
+        Ok(values
+            .chunks_exact(3)
+            .map(|v| [v[0] as f32, v[1] as f32, v[2] as f32])
+            .collect())
+    }
+
+    /// World matrices of all nodes in their rest pose.
+    fn world_matrices(&self, parents: &[Option<usize>]) -> Vec<Matrix> {
+        let nodes = array_of(&self.json["nodes"]);
+
+        let mut worlds: Vec<Option<Matrix>> = vec![None; nodes.len()];
+
+        for node in 0..nodes.len() {
+            // walk up to the first ancestor with a known world matrix, then back down
+            let mut chain = vec![node];
+            while let Some(parent) = parents[*chain.last().unwrap()] {
+                if worlds[parent].is_some() || chain.contains(&parent) {
+                    chain.push(parent);
+                    break;
+                }
+                chain.push(parent);
+            }
+
+            let mut world = IDENTITY;
+            for &n in chain.iter().rev() {
+                world = match worlds[n] {
+                    Some(known) => known,
+                    None => {
+                        let world = mul(&world, &node_matrix(&nodes[n]));
+                        worlds[n] = Some(world);
+                        world
+                    }
+                };
+            }
+        }
+
+        worlds.into_iter().map(|w| w.unwrap_or(IDENTITY)).collect()
+    }
+
+    /// The path an image gets in the converted model, embedded images get a made up path under
+    /// `textures/`, their data is available through [`Gltf::embedded_images`].
+    fn image_path(&self, index: usize) -> String {
+        let image = &self.json["images"][index];
+
+        match image["uri"].as_str() {
+            Some(uri) if !uri.starts_with("data:") => percent_decode(uri),
+            uri => {
+                let mime_type = image["mimeType"]
+                    .as_str()
+                    .or_else(|| uri.and_then(|uri| uri.strip_prefix("data:")?.split(';').next()));
+                let extension = match mime_type {
+                    Some("image/jpeg") => "jpg",
+                    _ => "png",
+                };
+
+                let name: String = image["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .chars()
+                    .filter(|c| !matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+                    .collect();
+
+                if name.is_empty() {
+                    format!("textures/image{index}.{extension}")
+                } else {
+                    format!("textures/{name}.{extension}")
+                }
+            }
+        }
+    }
+
+    /// The images stored inside the document, as the path [`Gltf::to_pmx`] references them by and
+    /// their encoded data, so they can be written next to the converted model.
+    pub fn embedded_images(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut images = Vec::new();
+
+        for (i, image) in array_of(&self.json["images"]).iter().enumerate() {
+            let bytes = match (image["uri"].as_str(), index_of(&image["bufferView"])) {
+                (Some(uri), _) if uri.starts_with("data:") => decode_data_uri(uri)?,
+                (None, Some(view)) => self.view(view)?.0.to_vec(),
+                _ => continue,
+            };
+
+            images.push((self.image_path(i), bytes));
+        }
+
+        Ok(images)
+    }
+
+    /// Converts the skinned meshes of the document into a model.
+    ///
+    /// Skin joints become bones, meshes without a skin are bound to the closest ancestor bone (or
+    /// a root bone when there are no joints). Every primitive becomes a material with the base
+    /// color as its diffuse color and texture, and position morph targets become vertex morphs,
+    /// merged by name across primitives. `scale` is meters per MMD unit like for export.
+    pub fn to_pmx(&self, scale: f32) -> Result<Pmx> {
+        let encoding = TextEncoding::UTF16LE;
+        let text = |text: &str| PmxText::new(text, encoding);
+
+        // `0.0 - z` rather than `-z` keeps zeros from turning into negative zeros
+        let to_mmd =
+            |[x, y, z]: [f32; 3]| -> Vec3 { from_array([x / scale, y / scale, (0.0 - z) / scale]) };
+        let to_mmd_direction = |[x, y, z]: [f32; 3]| -> [f32; 3] { [x, y, 0.0 - z] };
+
+        let nodes = array_of(&self.json["nodes"]);
+
+        let mut parents: Vec<Option<usize>> = vec![None; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            for child in array_of(&node["children"]).iter().filter_map(index_of) {
+                if let Some(parent) = parents.get_mut(child) {
+                    *parent = Some(i);
+                }
+            }
+        }
+
+        let worlds = self.world_matrices(&parents);
+
+        // skeleton
+
+        let skins = array_of(&self.json["skins"]);
+
+        let joint_nodes: std::collections::BTreeSet<usize> = skins
+            .iter()
+            .flat_map(|skin| array_of(&skin["joints"]).iter().filter_map(index_of))
+            .filter(|&node| node < nodes.len())
+            .collect();
+
+        let mut bone_of_node: Vec<Option<usize>> = vec![None; nodes.len()];
+        for (bone, &node) in joint_nodes.iter().enumerate() {
+            bone_of_node[node] = Some(bone);
+        }
+
+        let ancestor_bone = |mut node: usize| -> Option<usize> {
+            for _ in 0..nodes.len() {
+                node = parents[node]?;
+                if let Some(bone) = bone_of_node[node] {
+                    return Some(bone);
+                }
+            }
+            None
+        };
+
+        let mut bones: Vec<Bone> = joint_nodes
+            .iter()
+            .map(|&node| {
+                let name = nodes[node]["name"]
+                    .as_str()
+                    .map_or_else(|| format!("bone{node}"), str::to_string);
+
+                let parent = ancestor_bone(node);
+
+                let tail = array_of(&nodes[node]["children"])
+                    .iter()
+                    .filter_map(index_of)
+                    .find_map(|child| bone_of_node.get(child).copied().flatten());
+
+                let mut flags = BoneFlags::ROTATABLE | BoneFlags::VISIBLE | BoneFlags::ENABLED;
+                if parent.is_none() {
+                    flags |= BoneFlags::TRANSLATABLE;
+                }
+                if tail.is_some() {
+                    flags |= BoneFlags::INDEXED_TAIL;
+                }
+
+                let world = &worlds[node];
+
+                Bone {
+                    name: Name {
+                        local: text(&name),
+                        universal: text(&name),
+                    },
+                    position: to_mmd([world[12], world[13], world[14]]),
+                    parent: parent.map_or_else(BoneIndex::nil, |p| BoneIndex::new(p as i32)),
+                    layer: 0,
+                    flags: BoneFlags::from_raw(flags),
+                    tail: match tail {
+                        Some(tail) => Tail::Bone(BoneIndex::new(tail as i32)),
+                        None => Tail::Position(from_array([0.0; 3])),
+                    },
+                    inherit: None,
+                    fixed_axis: None,
+                    local_axes: None,
+                    external_parent: None,
+                    ik: None,
+                }
+            })
+            .collect();
+
+        if bones.is_empty() {
+            bones.push(Bone {
+                name: Name {
+                    local: text("センター"),
+                    universal: text("center"),
+                },
+                position: from_array([0.0; 3]),
+                parent: BoneIndex::nil(),
+                layer: 0,
+                flags: BoneFlags::from_raw(
+                    BoneFlags::ROTATABLE
+                        | BoneFlags::TRANSLATABLE
+                        | BoneFlags::VISIBLE
+                        | BoneFlags::ENABLED,
+                ),
+                tail: Tail::Position(from_array([0.0; 3])),
+                inherit: None,
+                fixed_axis: None,
+                local_axes: None,
+                external_parent: None,
+                ik: None,
+            });
+        }
+
+        // meshes
+
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut surfaces: Vec<Surface> = Vec::new();
+        let mut materials: Vec<PmxMaterial> = Vec::new();
+        let mut textures: Vec<String> = Vec::new();
+        let mut morphs: Vec<(String, Vec<VertexOffset>)> = Vec::new();
+        let mut morph_by_name: HashMap<String, usize> = HashMap::new();
+        let mut shared_vertices: HashMap<(usize, String, String), (usize, usize)> = HashMap::new();
+
+        for (node_index, node) in nodes.iter().enumerate() {
+            let Some(mesh) = index_of(&node["mesh"]).map(|mesh| &self.json["meshes"][mesh]) else {
+                continue;
+            };
+
+            // (bone, skin matrix) per joint of the skin, if the mesh is skinned
+            let skin = index_of(&node["skin"])
+                .and_then(|skin| skins.get(skin))
+                .map(|skin| -> Result<Vec<(usize, Matrix)>> {
+                    let inverse_binds = match index_of(&skin["inverseBindMatrices"]) {
+                        Some(accessor) => self.accessor(accessor)?.0,
+                        None => Vec::new(),
+                    };
+
+                    Ok(array_of(&skin["joints"])
+                        .iter()
+                        .enumerate()
+                        .map(|(j, joint)| {
+                            let node = index_of(joint).filter(|&n| n < nodes.len());
+                            let inverse_bind = match inverse_binds.get(j * 16..j * 16 + 16) {
+                                Some(m) => std::array::from_fn(|i| m[i] as f32),
+                                None => IDENTITY,
+                            };
+
+                            let bone = node.and_then(|n| bone_of_node[n]).unwrap_or(0);
+                            let world = node.map_or(IDENTITY, |n| worlds[n]);
+
+                            (bone, mul(&world, &inverse_bind))
+                        })
+                        .collect())
+                })
+                .transpose()?;
+
+            let node_bone = bone_of_node[node_index]
+                .or_else(|| ancestor_bone(node_index))
+                .unwrap_or(0);
+
+            let target_names = array_of(&mesh["extras"]["targetNames"]);
+
+            for primitive in array_of(&mesh["primitives"]) {
+                // only triangle lists have a PMX equivalent
+                if primitive["mode"].as_u64().unwrap_or(4) != 4 {
+                    continue;
+                }
+
+                let attributes = &primitive["attributes"];
+                let Some(position_accessor) = index_of(&attributes["POSITION"]) else {
+                    continue;
+                };
+
+                // primitives of a mesh often share their attributes, those share their vertices
+                let key = (
+                    node_index,
+                    attributes.to_string(),
+                    primitive["targets"].to_string(),
+                );
+
+                let (base, vertex_count) = match shared_vertices.get(&key) {
+                    Some(&shared) => shared,
+                    None => {
+                        let positions = self.vec3s(position_accessor)?;
+                        let normals = match index_of(&attributes["NORMAL"]) {
+                            Some(normals) => Some(self.vec3s(normals)?),
+                            None => None,
+                        };
+                        let uvs = match index_of(&attributes["TEXCOORD_0"]) {
+                            Some(uvs) => Some(self.accessor(uvs)?.0),
+                            None => None,
+                        };
+
+                        let skinning = match (
+                            &skin,
+                            index_of(&attributes["JOINTS_0"]),
+                            index_of(&attributes["WEIGHTS_0"]),
+                        ) {
+                            (Some(skin), Some(joints), Some(weights)) => {
+                                Some((skin, self.accessor(joints)?.0, self.accessor(weights)?.0))
+                            }
+                            _ => None,
+                        };
+
+                        let base = vertices.len();
+                        let mut matrices = Vec::with_capacity(positions.len());
+
+                        for (i, &position) in positions.iter().enumerate() {
+                            let (matrix, weight_deform) = match &skinning {
+                                Some((skin, joints, weights)) => {
+                                    let mut influences: Vec<(usize, f32, &Matrix)> = (0..4)
+                                        .filter_map(|k| {
+                                            let weight = *weights.get(i * 4 + k)? as f32;
+                                            let joint = *joints.get(i * 4 + k)? as usize;
+                                            let (bone, matrix) = skin.get(joint)?;
+                                            (weight > 0.0).then_some((*bone, weight, matrix))
+                                        })
+                                        .collect();
+                                    influences.sort_by(|a, b| {
+                                        b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal)
+                                    });
+
+                                    let sum: f32 = influences.iter().map(|(_, w, _)| w).sum();
+
+                                    let mut matrix = [0.0; 16];
+                                    for (_, weight, m) in &influences {
+                                        for (out, value) in matrix.iter_mut().zip(m.iter()) {
+                                            *out += value * weight / sum;
+                                        }
+                                    }
+
+                                    (matrix, weight_deform_of(&influences, sum, node_bone))
+                                }
+                                None => (
+                                    worlds[node_index],
+                                    WeightDeform::Bdef1 {
+                                        index: BoneIndex::new(node_bone as i32),
+                                    },
+                                ),
+                            };
+
+                            let normal = normals
+                                .as_ref()
+                                .and_then(|normals| normals.get(i))
+                                .map_or([0.0, 1.0, 0.0], |&n| {
+                                    normalize(to_mmd_direction(transform_vector(&matrix, n)))
+                                });
+                            let uv = uvs
+                                .as_ref()
+                                .and_then(|uvs| uvs.get(i * 2..i * 2 + 2))
+                                .map_or([0.0; 2], |uv| [uv[0] as f32, uv[1] as f32]);
+
+                            vertices.push(Vertex {
+                                pos: to_mmd(transform_point(&matrix, position)),
+                                normal: from_array(normal),
+                                uv: from_array(uv),
+                                extra_vec4: None,
+                                weight_deform,
+                                edge_scale: 1.0,
+                            });
+
+                            matrices.push(matrix);
+                        }
+
+                        for (k, target) in array_of(&primitive["targets"]).iter().enumerate() {
+                            let name = target_names
+                                .get(k)
+                                .and_then(Value::as_str)
+                                .map_or_else(|| format!("morph{k}"), str::to_string);
+
+                            let morph = *morph_by_name.entry(name.clone()).or_insert_with(|| {
+                                morphs.push((name, Vec::new()));
+                                morphs.len() - 1
+                            });
+
+                            let Some(deltas) = index_of(&target["POSITION"]) else {
+                                continue;
+                            };
+
+                            for (i, delta) in self.vec3s(deltas)?.into_iter().enumerate() {
+                                if delta == [0.0; 3] || i >= matrices.len() {
+                                    continue;
+                                }
+
+                                let [x, y, z] =
+                                    to_mmd_direction(transform_vector(&matrices[i], delta));
+
+                                morphs[morph].1.push(VertexOffset {
+                                    vertex: VertexIndex::new((base + i) as i32),
+                                    translation: from_array([x / scale, y / scale, z / scale]),
+                                });
+                            }
+                        }
+
+                        shared_vertices.insert(key, (base, positions.len()));
+                        (base, positions.len())
+                    }
+                };
+
+                let indices: Vec<usize> = match index_of(&primitive["indices"]) {
+                    Some(indices) => self
+                        .accessor(indices)?
+                        .0
+                        .into_iter()
+                        .map(|i| i as usize)
+                        .collect(),
+                    None => (0..vertex_count).collect(),
+                };
+
+                if indices.iter().any(|&i| i >= vertex_count) {
+                    Err(Error::Malformed("primitive indices"))?
+                }
+
+                let surface_start = surfaces.len();
+
+                // mirroring Z flips the handedness, so the winding is reversed to keep faces outward
+                for tri in indices.chunks_exact(3) {
+                    for corner in [tri[0], tri[2], tri[1]] {
+                        surfaces.push(Surface {
+                            index: VertexIndex::new((base + corner) as i32),
+                        });
+                    }
+                }
+
+                materials.push(self.material(
+                    primitive,
+                    materials.len(),
+                    (surfaces.len() - surface_start) as i32,
+                    &mut textures,
+                ));
+            }
+        }
+
+        // display frames
+
+        let root = bones
+            .iter()
+            .position(|bone| bone.parent.is_nil())
+            .unwrap_or(0);
+
+        let mut display_frames = vec![
+            DisplayFrame {
+                name: Name {
+                    local: text("Root"),
+                    universal: text("Root"),
+                },
+                special: true,
+                entries: vec![FrameEntry::Bone(BoneIndex::new(root as i32))],
+            },
+            DisplayFrame {
+                name: Name {
+                    local: text("表情"),
+                    universal: text("Exp"),
+                },
+                special: true,
+                entries: (0..morphs.len())
+                    .map(|i| FrameEntry::Morph(MorphIndex::new(i as i32)))
+                    .collect(),
+            },
+        ];
+
+        if bones.len() > 1 {
+            display_frames.push(DisplayFrame {
+                name: Name {
+                    local: text("ボーン"),
+                    universal: text("Bones"),
+                },
+                special: false,
+                entries: (0..bones.len())
+                    .filter(|&i| i != root)
+                    .map(|i| FrameEntry::Bone(BoneIndex::new(i as i32)))
+                    .collect(),
+            });
+        }
+
+        let morphs: Vec<Morph> = morphs
+            .into_iter()
+            .map(|(name, offsets)| Morph {
+                name: Name {
+                    local: text(&name),
+                    universal: text(&name),
+                },
+                panel: Panel::Other,
+                offsets: Offsets::Vertex(offsets),
+            })
+            .collect();
+
+        let textures: Vec<Texture> = textures
+            .iter()
+            .map(|path| Texture { path: text(path) })
+            .collect();
+
+        let scene = index_of(&self.json["scene"]).unwrap_or(0);
+        let name = self.json["scenes"][scene]["name"]
+            .as_str()
+            .unwrap_or("model");
+
+        let header = Header {
+            tag: *b"PMX ",
+            version: Version::V2_0,
+            raw_version: Version::V2_0.as_f32(),
+            globals: Globals {
+                encoding,
+                vec4_additional: 0,
+                vert_idx_size: IndexSize::smallest_for(vertices.len(), false),
+                tex_idx_size: IndexSize::smallest_for(textures.len(), true),
+                material_idx_size: IndexSize::smallest_for(materials.len(), true),
+                bone_idx_size: IndexSize::smallest_for(bones.len(), true),
+                morph_idx_size: IndexSize::smallest_for(morphs.len(), true),
+                rb_idx_size: 1,
+                additional: None,
+            },
+            name: ModelName {
+                local: text(name),
+                universal: text(name),
+            },
+            comment: Comment {
+                local: text(""),
+                universal: text(""),
+            },
+        };
+
+        Ok(Pmx {
+            header,
+            vertices: vertices.into(),
+            surfaces: surfaces.into(),
+            textures: textures.into(),
+            materials: materials.into(),
+            bones: bones.into(),
+            morphs: morphs.into(),
+            display_frames: display_frames.into(),
+            rigid_bodies: Vec::new().into(),
+            joints: Vec::new().into(),
+            soft_bodies: None,
+            trailing: None,
+        })
+    }
+
+    fn material(
+        &self,
+        primitive: &Value,
+        index: usize,
+        surface_count: i32,
+        textures: &mut Vec<String>,
+    ) -> PmxMaterial {
+        let text = |text: &str| PmxText::new(text, TextEncoding::UTF16LE);
+
+        let material = match index_of(&primitive["material"]) {
+            Some(material) => &self.json["materials"][material],
+            None => &Value::Null,
+        };
+        let pbr = &material["pbrMetallicRoughness"];
+
+        let name = material["name"]
+            .as_str()
+            .map_or_else(|| format!("材質{}", index + 1), str::to_string);
+
+        let diffuse = floats(&pbr["baseColorFactor"], [1.0; 4]);
+
+        let texture = index_of(&pbr["baseColorTexture"]["index"])
+            .and_then(|texture| index_of(&self.json["textures"][texture]["source"]))
+            .map(|image| {
+                let path = self.image_path(image);
+                let index = textures.iter().position(|t| *t == path).unwrap_or_else(|| {
+                    textures.push(path);
+                    textures.len() - 1
+                });
+                TextureIndex::new(index as i32)
+            })
+            .unwrap_or_else(TextureIndex::nil);
+
+        let mut flags = MaterialFlags::GROUND_SHADOW
+            | MaterialFlags::DRAW_SHADOW
+            | MaterialFlags::RECEIVE_SHADOW;
+        if material["doubleSided"].as_bool().unwrap_or(false) {
+            flags |= MaterialFlags::NO_CULL;
+        }
+
+        PmxMaterial {
+            name: Name {
+                local: text(&name),
+                universal: text(&name),
+            },
+            diffuse: from_array(diffuse),
+            specular: from_array([0.0; 3]),
+            specular_strength: 1.0,
+            ambient: from_array([diffuse[0] * 0.5, diffuse[1] * 0.5, diffuse[2] * 0.5]),
+            flags: MaterialFlags::from_raw(flags),
+            edge_color: from_array([0.0, 0.0, 0.0, 1.0]),
+            edge_scale: 1.0,
+            tex_idx: texture,
+            env_idx: TextureIndex::nil(),
+            env_blend: EnvironmentBlend::None,
+            toon: Toon::Texture(TextureIndex::nil()),
+            meta: text(""),
+            surface_count,
+        }
+    }
+}
+
+/// The PMX deform for up to four influences sorted by descending weight.
+fn weight_deform_of(
+    influences: &[(usize, f32, &Matrix)],
+    sum: f32,
+    fallback: usize,
+) -> WeightDeform {
+    let bone = |k: usize| {
+        BoneIndex::new(
+            influences
+                .get(k)
+                .map_or(influences[0].0, |influence| influence.0) as i32,
+        )
+    };
+    let weight = |k: usize| influences.get(k).map_or(0.0, |influence| influence.1 / sum);
+
+    match influences.len() {
+        0 => WeightDeform::Bdef1 {
+            index: BoneIndex::new(fallback as i32),
+        },
+        1 => WeightDeform::Bdef1 { index: bone(0) },
+        2 => WeightDeform::Bdef2 {
+            indices: [bone(0), bone(1)],
+            weights: [weight(0), 1.0 - weight(0)],
+        },
+        _ => WeightDeform::Bdef4 {
+            indices: [bone(0), bone(1), bone(2), bone(3)],
+            weights: [weight(0), weight(1), weight(2), weight(3)],
+        },
+    }
+}
+
+fn component_size(component_type: u32) -> Result<usize> {
+    match component_type {
+        5120 | 5121 => Ok(1),
+        5122 | 5123 => Ok(2),
+        5125 | 5126 => Ok(4),
+        _ => Err(Error::Malformed("accessor component type")),
+    }
+}
+
+fn read_component(bytes: &[u8], at: usize, component_type: u32, normalized: bool) -> Result<f64> {
+    let size = component_size(component_type)?;
+    let b = bytes
+        .get(at..at + size)
+        .ok_or(Error::Malformed("accessor out of bounds"))?;
+
+    let value = match component_type {
+        5120 => {
+            let v = b[0] as i8 as f64;
+            if normalized { (v / 127.0).max(-1.0) } else { v }
+        }
+        5121 => {
+            let v = b[0] as f64;
+            if normalized { v / 255.0 } else { v }
+        }
+        5122 => {
+            let v = i16::from_le_bytes([b[0], b[1]]) as f64;
+            if normalized {
+                (v / 32767.0).max(-1.0)
+            } else {
+                v
+            }
+        }
+        5123 => {
+            let v = u16::from_le_bytes([b[0], b[1]]) as f64;
+            if normalized { v / 65535.0 } else { v }
+        }
+        5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+    };
+
+    Ok(value)
+}
+
+/// Splits a `.glb` container into its JSON and optional binary chunk.
+fn parse_glb(bytes: &[u8]) -> Result<(Value, Option<Vec<u8>>)> {
+    let u32_at = |at: usize| -> Result<usize> {
+        let b = bytes.get(at..at + 4).ok_or(Error::InvalidGlb)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+
+    if u32_at(4)? != 2 {
+        Err(Error::InvalidGlb)?
+    }
+
+    let length = u32_at(8)?.min(bytes.len());
+
+    let mut json = None;
+    let mut bin = None;
+    let mut at = 12;
+
+    while at + 8 <= length {
+        let chunk_length = u32_at(at)?;
+        let chunk = bytes
+            .get(at + 8..at + 8 + chunk_length)
+            .ok_or(Error::InvalidGlb)?;
+
+        match &bytes[at + 4..at + 8] {
+            b"JSON" => json = Some(serde_json::from_slice(chunk)?),
+            b"BIN\0" if bin.is_none() => bin = Some(chunk.to_vec()),
+            _ => {}
+        }
+
+        at += 8 + chunk_length;
+    }
+
+    Ok((json.ok_or(Error::InvalidGlb)?, bin))
+}
+
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>> {
+    let (header, data) = uri.split_once(',').ok_or(Error::Malformed("data URI"))?;
+
+    if header.ends_with(";base64") {
+        decode_base64(data).ok_or(Error::Malformed("base64 data"))
+    } else {
+        Ok(percent_decode(data).into_bytes())
+    }
+}
+
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+
+    for byte in data.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' | b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return None,
+        };
+
+        accumulator = (accumulator << 6) | value as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((accumulator >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
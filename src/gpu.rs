@@ -0,0 +1,106 @@
+//! Interleaved vertex data ready for upload to the GPU.
+
+use std::ops::Range;
+
+use bytemuck::{Pod, Zeroable};
+use thiserror::Error;
+
+use crate::{pmx::Pmx, types::to_array, vertex::Vertex};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Bone index {0} does not fit in 16 bits")]
+    BoneIndexOverflow(i32),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A vertex with every weight deform flattened to 4 bones.
+///
+/// Unused bone slots have index 0 and weight 0, the weights of a vertex sum to 1.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Pod, Zeroable)]
+pub struct PackedVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub bone_indices: [u16; 4],
+    pub bone_weights: [f32; 4],
+}
+
+impl PackedVertex {
+    pub fn pack(vertex: &Vertex) -> Result<Self> {
+        let (indices, weights) = vertex.weight_deform().bone_influences();
+
+        let mut bone_indices = [0; 4];
+        let mut bone_weights = [0.0; 4];
+
+        for i in 0..4 {
+            // nil and unused slots
+            if indices[i] < 0 {
+                continue;
+            }
+
+            bone_indices[i] =
+                u16::try_from(indices[i]).map_err(|_| Error::BoneIndexOverflow(indices[i]))?;
+            bone_weights[i] = weights[i];
+        }
+
+        let sum: f32 = bone_weights.iter().sum();
+
+        if sum > 0.0 {
+            bone_weights.iter_mut().for_each(|w| *w /= sum);
+        }
+
+        Ok(Self {
+            position: to_array(vertex.position()),
+            normal: to_array(vertex.normal()),
+            uv: to_array(vertex.uv()),
+            bone_indices,
+            bone_weights,
+        })
+    }
+
+    /// The weights quantized for a `unorm8x4` vertex attribute.
+    pub fn weights_unorm8(&self) -> [u8; 4] {
+        self.bone_weights
+            .map(|w| (w.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+}
+
+/// The part of the index buffer drawn with one material.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrawRange {
+    pub material: usize,
+    pub indices: Range<u32>,
+}
+
+impl Pmx {
+    /// The vertex index of every surface, for an index buffer with the triangles in material
+    /// order.
+    pub fn index_data(&self) -> Vec<u32> {
+        self.surfaces.as_u32_indices()
+    }
+
+    /// The range of [`index_data`](Pmx::index_data) each material covers, in material order.
+    ///
+    /// Materials without surfaces get an empty range, materials reaching past the last surface
+    /// are cut off.
+    pub fn draw_ranges(&self) -> Vec<DrawRange> {
+        (self.submeshes().into_iter())
+            .map(|submesh| DrawRange {
+                material: submesh.material,
+                indices: submesh.surfaces.start as u32..submesh.surfaces.end as u32,
+            })
+            .collect()
+    }
+
+    /// Packs every vertex into the interleaved GPU layout.
+    pub fn pack_vertices(&self) -> Result<Vec<PackedVertex>> {
+        self.vertices()
+            .vertices()
+            .iter()
+            .map(PackedVertex::pack)
+            .collect()
+    }
+}
@@ -0,0 +1,104 @@
+//! Inverse kinematics with cyclic coordinate descent, the way MMD solves IK bones.
+//!
+//! Each IK bone names a target bone that should end up at the IK bone's position, and a chain of
+//! link bones that may rotate to get it there. Every iteration turns each link, starting from
+//! the one closest to the target, so that the target points at the goal as seen from the link,
+//! capped at the IK bone's angle limit and clamped to the link's axis limits.
+
+use glam::{EulerRot, Quat, Vec3};
+
+use crate::{
+    bone::{Ik, IkAngleLimit},
+    skeleton::Skeleton,
+    skin::Pose,
+};
+
+/// Targets closer to their goal than this count as reached.
+const REACHED: f32 = 1e-5;
+/// Rotations smaller than this are not worth applying.
+const MIN_ANGLE: f32 = 1e-3;
+
+impl Skeleton<'_> {
+    /// Solves every IK bone, rotating the link bones in `pose` so the targets reach their IK
+    /// bones.
+    ///
+    /// IK bones are solved in [deform order](Skeleton::deform_order), so a chain can build on
+    /// the result of an earlier one like a toe IK after a leg IK.
+    pub fn solve_ik(&self, pose: &mut Pose) {
+        for bone in self.deform_order() {
+            if self.ik(bone).is_some() {
+                self.solve_chain(bone, pose);
+            }
+        }
+    }
+
+    fn ik(&self, bone: usize) -> Option<&Ik> {
+        self.bone(bone)?.ik.as_ref()
+    }
+
+    fn solve_chain(&self, bone: usize, pose: &mut Pose) {
+        let Some(ik) = self.ik(bone) else {
+            return;
+        };
+        let Some(target) = ik.target.as_usize().filter(|&t| t < self.len()) else {
+            return;
+        };
+
+        let links: Vec<(usize, Option<&IkAngleLimit>)> = ik
+            .links
+            .iter()
+            .filter_map(|link| {
+                let index = link.bone.as_usize().filter(|&b| b < self.len())?;
+                Some((index, link.limits.as_ref()))
+            })
+            .collect();
+
+        let position = |world: &[glam::Mat4], bone: usize| world[bone].w_axis.truncate();
+        let goal = position(&self.pose_transforms(pose), bone);
+
+        for _ in 0..ik.loop_count.max(0) {
+            for &(link, limits) in &links {
+                let world = self.pose_transforms(pose);
+                let effector = position(&world, target);
+
+                if effector.distance(goal) < REACHED {
+                    return;
+                }
+
+                // both directions in the link's own space, where its rotation is applied
+                let inverse = world[link].inverse();
+                let to_effector = inverse.transform_point3(effector).normalize_or_zero();
+                let to_goal = inverse.transform_point3(goal).normalize_or_zero();
+
+                let mut angle = to_effector.dot(to_goal).clamp(-1.0, 1.0).acos();
+                if ik.limit_angle > 0.0 {
+                    angle = angle.min(ik.limit_angle);
+                }
+
+                let axis = to_effector.cross(to_goal);
+                if angle < MIN_ANGLE || axis.length_squared() < f32::EPSILON {
+                    continue;
+                }
+
+                let mut rotation =
+                    pose.rotation(link) * Quat::from_axis_angle(axis.normalize(), angle);
+                if let Some(limits) = limits {
+                    rotation = clamp_rotation(rotation, limits);
+                }
+
+                pose.set_rotation(link, rotation.normalize());
+            }
+        }
+    }
+}
+
+/// Clamps each Euler angle of `rotation` into the link's limits.
+fn clamp_rotation(rotation: Quat, limits: &IkAngleLimit) -> Quat {
+    let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+    let angles = Vec3::new(x, y, z);
+
+    // written as max/min rather than clamp, some models have their limits the wrong way round
+    let [x, y, z] = angles.max(limits.min).min(limits.max).to_array();
+
+    Quat::from_euler(EulerRot::XYZ, x, y, z)
+}
@@ -0,0 +1,109 @@
+//! Loading the textures of a model with the [image](https://docs.rs/image) crate.
+//!
+//! Texture paths are stored relative to the model file, with Windows separators, and were written
+//! on a case-insensitive file system. They are found with [`resolve_path`], which matches path
+//! components case-insensitively where the exact name is missing.
+//!
+//! Files are decoded by their contents rather than their extension, models often ship PNGs named
+//! `.bmp` and the like. TGA has no signature, so the extension is used for it. BMP, PNG, TGA,
+//! JPEG and DDS are supported.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use ::image::{RgbaImage, io::Reader};
+use thiserror::Error;
+
+pub use crate::texture::resolve_path;
+use crate::{pmx::Pmx, texture::normalize_path};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Texture file {0} was not found")]
+    NotFound(PathBuf),
+    #[error("IO error reading {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to decode {path}: {source}")]
+    Decode {
+        path: PathBuf,
+        source: ::image::ImageError,
+    },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The decoded textures of a model, and the errors of the ones that could not be loaded.
+#[derive(Debug, Default)]
+pub struct LoadedTextures {
+    pub(crate) images: HashMap<usize, RgbaImage>,
+    pub(crate) errors: Vec<(usize, Error)>,
+}
+
+impl LoadedTextures {
+    /// The image of the texture at `index`, `None` if it failed to load.
+    pub fn get(&self, index: usize) -> Option<&RgbaImage> {
+        self.images.get(&index)
+    }
+
+    /// The images keyed by texture index.
+    pub fn images(&self) -> &HashMap<usize, RgbaImage> {
+        &self.images
+    }
+
+    /// The textures that failed to load, in texture order.
+    pub fn errors(&self) -> &[(usize, Error)] {
+        &self.errors
+    }
+
+    pub fn into_images(self) -> HashMap<usize, RgbaImage> {
+        self.images
+    }
+}
+
+impl Pmx {
+    /// Loads and decodes every texture of the model, relative to `model_dir`.
+    ///
+    /// A texture failing to load does not stop the others, its error is kept in
+    /// [`LoadedTextures::errors`].
+    pub fn load_textures(&self, model_dir: impl AsRef<Path>) -> LoadedTextures {
+        let model_dir = model_dir.as_ref();
+        let mut loaded = LoadedTextures::default();
+
+        for (index, texture) in self.textures.inner.iter().enumerate() {
+            match load_texture(model_dir, texture.path.as_str()) {
+                Ok(image) => {
+                    loaded.images.insert(index, image);
+                }
+                Err(error) => loaded.errors.push((index, error)),
+            }
+        }
+
+        loaded
+    }
+}
+
+/// Loads and decodes a single texture path relative to `model_dir`.
+pub fn load_texture(model_dir: &Path, path: &str) -> Result<RgbaImage> {
+    let path =
+        resolve_path(model_dir, path).ok_or_else(|| Error::NotFound(normalize_path(path)))?;
+
+    let io = |source| Error::Io {
+        path: path.clone(),
+        source,
+    };
+    let reader = Reader::open(&path).map_err(io)?;
+    // keeps the format of the extension if the contents have no known signature
+    let reader = reader.with_guessed_format().map_err(io)?;
+
+    let image = reader.decode().map_err(|source| Error::Decode {
+        path: path.clone(),
+        source,
+    })?;
+
+    Ok(image.into_rgba8())
+}
@@ -0,0 +1,281 @@
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+use crate::types::{
+    IndexSize, Name, ParseContext, RigidBodyIndex, TextEncoding, Vec3, read_i32, read_u8,
+    vec_from_bytes, vec_to_bytes, write_count, write_u8,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Negative size encountered where positive expected")]
+    NegativeSize,
+    #[error(transparent)]
+    Type(#[from] crate::types::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid joint type encountered")]
+    InvalidJointType,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct Joints {
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<Joint>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(Joints, Joint, len);
+
+impl From<Vec<Joint>> for Joints {
+    fn from(inner: Vec<Joint>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
+}
+
+impl Joints {
+    pub fn len(&self) -> usize {
+        let len = self.inner.len();
+        debug_assert!(self.len == len);
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.inner
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size = read_i32(reader)?;
+
+        if size.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // name, type, two rigid body indices and 8 vectors
+        let size = context.check_count(size as usize, 105 + 2 * index_size as u64)?;
+
+        let mut inner_vec = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let joint = Joint::parse(reader, index_size, encoding, context)?;
+            inner_vec.push(joint);
+        }
+
+        Ok(Self {
+            len: size,
+            inner: inner_vec,
+        })
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for joint in &self.inner {
+            joint.write(writer, index_size, encoding)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The constraint type of a joint, all types share the same record layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JointType {
+    // ver 2.0
+    Spring6Dof,
+    // ver 2.1
+    SixDof,
+    // ver 2.1
+    PointToPoint,
+    // ver 2.1
+    ConeTwist,
+    // ver 2.1
+    Slider,
+    // ver 2.1
+    Hinge,
+}
+
+impl TryFrom<u8> for JointType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Spring6Dof),
+            1 => Ok(Self::SixDof),
+            2 => Ok(Self::PointToPoint),
+            3 => Ok(Self::ConeTwist),
+            4 => Ok(Self::Slider),
+            5 => Ok(Self::Hinge),
+            _ => Err(Error::InvalidJointType),
+        }
+    }
+}
+
+impl From<JointType> for u8 {
+    fn from(value: JointType) -> Self {
+        match value {
+            JointType::Spring6Dof => 0,
+            JointType::SixDof => 1,
+            JointType::PointToPoint => 2,
+            JointType::ConeTwist => 3,
+            JointType::Slider => 4,
+            JointType::Hinge => 5,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joint {
+    pub(crate) name: Name,
+    pub(crate) typ: JointType,
+    pub(crate) rigid_body_a: RigidBodyIndex,
+    pub(crate) rigid_body_b: RigidBodyIndex,
+    pub(crate) position: Vec3,
+    /// Euler angles in radians.
+    pub(crate) rotation: Vec3,
+    pub(crate) position_min: Vec3,
+    pub(crate) position_max: Vec3,
+    pub(crate) rotation_min: Vec3,
+    pub(crate) rotation_max: Vec3,
+    pub(crate) position_spring: Vec3,
+    pub(crate) rotation_spring: Vec3,
+}
+
+impl Joint {
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn joint_type(&self) -> JointType {
+        self.typ
+    }
+
+    pub fn rigid_body_a(&self) -> &RigidBodyIndex {
+        &self.rigid_body_a
+    }
+
+    pub fn rigid_body_b(&self) -> &RigidBodyIndex {
+        &self.rigid_body_b
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Vec3 {
+        self.rotation
+    }
+
+    pub fn position_min(&self) -> Vec3 {
+        self.position_min
+    }
+
+    pub fn position_max(&self) -> Vec3 {
+        self.position_max
+    }
+
+    pub fn rotation_min(&self) -> Vec3 {
+        self.rotation_min
+    }
+
+    pub fn rotation_max(&self) -> Vec3 {
+        self.rotation_max
+    }
+
+    pub fn position_spring(&self) -> Vec3 {
+        self.position_spring
+    }
+
+    pub fn rotation_spring(&self) -> Vec3 {
+        self.rotation_spring
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size: IndexSize = index_size.try_into()?;
+
+        let name = Name::parse(reader, encoding, context)?;
+
+        let typ = read_u8(reader)?.try_into()?;
+
+        let rigid_body_a = RigidBodyIndex::parse(reader, size)?;
+        let rigid_body_b = RigidBodyIndex::parse(reader, size)?;
+
+        let position: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation: Vec3 = vec_from_bytes!(Vec3, reader);
+        let position_min: Vec3 = vec_from_bytes!(Vec3, reader);
+        let position_max: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation_min: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation_max: Vec3 = vec_from_bytes!(Vec3, reader);
+        let position_spring: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation_spring: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        Ok(Self {
+            name,
+            typ,
+            rigid_body_a,
+            rigid_body_b,
+            position,
+            rotation,
+            position_min,
+            position_max,
+            rotation_min,
+            rotation_max,
+            position_spring,
+            rotation_spring,
+        })
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        let size: IndexSize = index_size.try_into()?;
+
+        self.name.write(writer, encoding)?;
+
+        write_u8(writer, self.typ.into())?;
+
+        self.rigid_body_a.write(writer, size)?;
+        self.rigid_body_b.write(writer, size)?;
+
+        vec_to_bytes!(Vec3, self.position, writer);
+        vec_to_bytes!(Vec3, self.rotation, writer);
+        vec_to_bytes!(Vec3, self.position_min, writer);
+        vec_to_bytes!(Vec3, self.position_max, writer);
+        vec_to_bytes!(Vec3, self.rotation_min, writer);
+        vec_to_bytes!(Vec3, self.rotation_max, writer);
+        vec_to_bytes!(Vec3, self.position_spring, writer);
+        vec_to_bytes!(Vec3, self.rotation_spring, writer);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,504 @@
+//! On-demand parsing of individual sections.
+//!
+//! [`LazyPmx`] walks the file once to find where each section starts, skipping over the records
+//! without decoding them, and only parses a section the first time it is accessed.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    bone::{self, BoneFlags},
+    display_frame, joint, material, morph,
+    pmx::{Error, Globals, Header, Result, Version, check_joints, check_morphs, check_vertices},
+    rigid_body, soft_body, surface, texture,
+    types::{ParseContext, read_i32, read_u8, read_u16},
+    vertex,
+    visit::Section,
+};
+
+/// A PMX model whose sections are parsed when first accessed.
+pub struct LazyPmx<R> {
+    reader: R,
+    header: Header,
+    offsets: HashMap<Section, u64>,
+    vertices: Option<vertex::Vertices>,
+    surfaces: Option<surface::Surfaces>,
+    textures: Option<texture::Textures>,
+    materials: Option<material::Materials>,
+    bones: Option<bone::Bones>,
+    morphs: Option<morph::Morphs>,
+    display_frames: Option<display_frame::DisplayFrames>,
+    rigid_bodies: Option<rigid_body::RigidBodies>,
+    joints: Option<joint::Joints>,
+    soft_bodies: Option<soft_body::SoftBodies>,
+}
+
+impl LazyPmx<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let fh = File::open(path)?;
+
+        Self::new(BufReader::new(fh))
+    }
+}
+
+impl<R: Read + Seek> LazyPmx<R> {
+    /// Parses the header and scans the section boundaries, leaving every section undecoded.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let header = Header::parse(&mut reader)?;
+
+        let offsets = scan(&mut reader, &header)?;
+
+        Ok(Self {
+            reader,
+            header,
+            offsets,
+            vertices: None,
+            surfaces: None,
+            textures: None,
+            materials: None,
+            bones: None,
+            morphs: None,
+            display_frames: None,
+            rigid_bodies: None,
+            joints: None,
+            soft_bodies: None,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Byte offset of a section's element count, `None` for soft bodies in 2.0 files.
+    pub fn section_offset(&self, section: Section) -> Option<u64> {
+        self.offsets.get(&section).copied()
+    }
+
+    pub fn vertices(&mut self) -> Result<&vertex::Vertices> {
+        let globals = self.header.globals();
+        let version = self.header.version();
+
+        load(
+            &mut self.vertices,
+            &mut self.reader,
+            self.offsets[&Section::Vertices],
+            |r| {
+                let vertices = vertex::Vertices::parse(
+                    r,
+                    globals.vec4_additional,
+                    globals.bone_idx_size,
+                    ParseContext::UNCHECKED,
+                )?;
+                check_vertices(version, &vertices)?;
+                Ok(vertices)
+            },
+        )
+    }
+
+    pub fn surfaces(&mut self) -> Result<&surface::Surfaces> {
+        let globals = self.header.globals();
+
+        load(
+            &mut self.surfaces,
+            &mut self.reader,
+            self.offsets[&Section::Surfaces],
+            |r| {
+                Ok(surface::Surfaces::parse(
+                    r,
+                    globals.vert_idx_size,
+                    ParseContext::UNCHECKED,
+                )?)
+            },
+        )
+    }
+
+    pub fn textures(&mut self) -> Result<&texture::Textures> {
+        let globals = self.header.globals();
+
+        load(
+            &mut self.textures,
+            &mut self.reader,
+            self.offsets[&Section::Textures],
+            |r| {
+                Ok(texture::Textures::parse(
+                    r,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                )?)
+            },
+        )
+    }
+
+    pub fn materials(&mut self) -> Result<&material::Materials> {
+        let globals = self.header.globals();
+
+        load(
+            &mut self.materials,
+            &mut self.reader,
+            self.offsets[&Section::Materials],
+            |r| {
+                Ok(material::Materials::parse(
+                    r,
+                    globals.tex_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                )?)
+            },
+        )
+    }
+
+    pub fn bones(&mut self) -> Result<&bone::Bones> {
+        let globals = self.header.globals();
+
+        load(
+            &mut self.bones,
+            &mut self.reader,
+            self.offsets[&Section::Bones],
+            |r| {
+                Ok(bone::Bones::parse(
+                    r,
+                    globals.bone_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                )?)
+            },
+        )
+    }
+
+    pub fn morphs(&mut self) -> Result<&morph::Morphs> {
+        let globals = self.header.globals();
+        let version = self.header.version();
+
+        load(
+            &mut self.morphs,
+            &mut self.reader,
+            self.offsets[&Section::Morphs],
+            |r| {
+                let morphs = morph::Morphs::parse(r, globals, ParseContext::UNCHECKED)?;
+                check_morphs(version, &morphs)?;
+                Ok(morphs)
+            },
+        )
+    }
+
+    pub fn display_frames(&mut self) -> Result<&display_frame::DisplayFrames> {
+        let globals = self.header.globals();
+
+        load(
+            &mut self.display_frames,
+            &mut self.reader,
+            self.offsets[&Section::DisplayFrames],
+            |r| {
+                Ok(display_frame::DisplayFrames::parse(
+                    r,
+                    globals,
+                    ParseContext::UNCHECKED,
+                )?)
+            },
+        )
+    }
+
+    pub fn rigid_bodies(&mut self) -> Result<&rigid_body::RigidBodies> {
+        let globals = self.header.globals();
+
+        load(
+            &mut self.rigid_bodies,
+            &mut self.reader,
+            self.offsets[&Section::RigidBodies],
+            |r| {
+                Ok(rigid_body::RigidBodies::parse(
+                    r,
+                    globals.bone_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                )?)
+            },
+        )
+    }
+
+    pub fn joints(&mut self) -> Result<&joint::Joints> {
+        let globals = self.header.globals();
+        let version = self.header.version();
+
+        load(
+            &mut self.joints,
+            &mut self.reader,
+            self.offsets[&Section::Joints],
+            |r| {
+                let joints = joint::Joints::parse(
+                    r,
+                    globals.rb_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                )?;
+                check_joints(version, &joints)?;
+                Ok(joints)
+            },
+        )
+    }
+
+    /// The soft bodies, `None` for 2.0 files which have no soft body section.
+    pub fn soft_bodies(&mut self) -> Result<Option<&soft_body::SoftBodies>> {
+        let globals = self.header.globals();
+
+        let Some(&offset) = self.offsets.get(&Section::SoftBodies) else {
+            return Ok(None);
+        };
+
+        load(&mut self.soft_bodies, &mut self.reader, offset, |r| {
+            Ok(soft_body::SoftBodies::parse(
+                r,
+                globals,
+                ParseContext::UNCHECKED,
+            )?)
+        })
+        .map(Some)
+    }
+}
+
+/// Returns the cached section, parsing it from `offset` first if needed.
+fn load<'a, T, R: Read + Seek>(
+    slot: &'a mut Option<T>,
+    reader: &mut R,
+    offset: u64,
+    parse: impl FnOnce(&mut R) -> Result<T>,
+) -> Result<&'a T> {
+    match slot {
+        Some(section) => Ok(section),
+        None => {
+            reader.seek(SeekFrom::Start(offset))?;
+
+            Ok(slot.insert(parse(reader)?))
+        }
+    }
+}
+
+/// Records the start of every section, skipping over their contents.
+pub(crate) fn scan(
+    reader: &mut (impl Read + Seek),
+    header: &Header,
+) -> Result<HashMap<Section, u64>> {
+    let globals = header.globals();
+
+    let mut offsets = HashMap::new();
+
+    let mut mark = |reader: &mut _, section| -> Result<usize> {
+        offsets.insert(section, Seek::stream_position(reader)?);
+        read_count(reader)
+    };
+
+    let count = mark(reader, Section::Vertices)?;
+    for _ in 0..count {
+        skip_vertex(reader, globals)?;
+    }
+
+    let count = mark(reader, Section::Surfaces)?;
+    skip(reader, count as i64 * globals.vert_idx_size as i64)?;
+
+    let count = mark(reader, Section::Textures)?;
+    for _ in 0..count {
+        skip_text(reader)?;
+    }
+
+    let count = mark(reader, Section::Materials)?;
+    for _ in 0..count {
+        skip_material(reader, globals)?;
+    }
+
+    let count = mark(reader, Section::Bones)?;
+    for _ in 0..count {
+        skip_bone(reader, globals)?;
+    }
+
+    let count = mark(reader, Section::Morphs)?;
+    for _ in 0..count {
+        skip_morph(reader, globals)?;
+    }
+
+    let count = mark(reader, Section::DisplayFrames)?;
+    for _ in 0..count {
+        skip_display_frame(reader, globals)?;
+    }
+
+    let count = mark(reader, Section::RigidBodies)?;
+    for _ in 0..count {
+        skip_name(reader)?;
+        // bone index, group, mask, shape, size, position, rotation, 5 floats, physics mode
+        skip(
+            reader,
+            globals.bone_idx_size as i64 + 1 + 2 + 1 + 36 + 20 + 1,
+        )?;
+    }
+
+    let count = mark(reader, Section::Joints)?;
+    for _ in 0..count {
+        skip_name(reader)?;
+        // type, two rigid body indices, 8 vec3s
+        skip(reader, 1 + 2 * globals.rb_idx_size as i64 + 96)?;
+    }
+
+    // the soft body section is last, so only its start is needed
+    if header.version() >= Version::V2_1 {
+        offsets.insert(Section::SoftBodies, reader.stream_position()?);
+    }
+
+    Ok(offsets)
+}
+
+fn read_count(reader: &mut impl Read) -> Result<usize> {
+    let count = read_i32(reader)?;
+
+    if count.is_negative() {
+        Err(Error::NegativeSize)?
+    }
+
+    Ok(count as usize)
+}
+
+fn skip(reader: &mut impl Seek, len: i64) -> Result<()> {
+    reader.seek_relative(len)?;
+
+    Ok(())
+}
+
+fn skip_text(reader: &mut (impl Read + Seek)) -> Result<()> {
+    let len = read_count(reader)?;
+
+    skip(reader, len as i64)
+}
+
+fn skip_name(reader: &mut (impl Read + Seek)) -> Result<()> {
+    skip_text(reader)?;
+    skip_text(reader)
+}
+
+fn skip_vertex(reader: &mut (impl Read + Seek), globals: &Globals) -> Result<()> {
+    let b = globals.bone_idx_size as i64;
+
+    // position, normal, uv and the additional vec4s
+    skip(reader, 32 + 16 * globals.vec4_additional as i64)?;
+
+    let deform = match read_u8(reader)? {
+        0 => b,
+        1 => 2 * b + 4,
+        2 | 4 => 4 * b + 16,
+        3 => 2 * b + 4 + 36,
+        _ => Err(vertex::Error::InvalidWeightDeformType)?,
+    };
+
+    // deform data and edge scale
+    skip(reader, deform + 4)
+}
+
+fn skip_material(reader: &mut (impl Read + Seek), globals: &Globals) -> Result<()> {
+    let t = globals.tex_idx_size as i64;
+
+    skip_name(reader)?;
+
+    // colors, flags, edge, texture indices and environment blend
+    skip(reader, 65 + 2 * t + 1)?;
+
+    let toon = match read_u8(reader)? {
+        0 => t,
+        _ => 1,
+    };
+    skip(reader, toon)?;
+
+    skip_text(reader)?;
+
+    // surface count
+    skip(reader, 4)
+}
+
+fn skip_bone(reader: &mut (impl Read + Seek), globals: &Globals) -> Result<()> {
+    let b = globals.bone_idx_size as i64;
+
+    skip_name(reader)?;
+
+    // position, parent, layer
+    skip(reader, 12 + b + 4)?;
+
+    let flags = BoneFlags::from_raw(read_u16(reader)?);
+
+    let mut len = if flags.indexed_tail() { b } else { 12 };
+
+    if flags.inherit_rotation() || flags.inherit_translation() {
+        len += b + 4;
+    }
+    if flags.fixed_axis() {
+        len += 12;
+    }
+    if flags.local_axes() {
+        len += 24;
+    }
+    if flags.external_parent_deform() {
+        len += 4;
+    }
+
+    skip(reader, len)?;
+
+    if flags.ik() {
+        // target, loop count, limit angle
+        skip(reader, b + 8)?;
+
+        let links = read_count(reader)?;
+
+        for _ in 0..links {
+            skip(reader, b)?;
+
+            if read_u8(reader)? != 0 {
+                skip(reader, 24)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn skip_morph(reader: &mut (impl Read + Seek), globals: &Globals) -> Result<()> {
+    skip_name(reader)?;
+
+    // panel
+    skip(reader, 1)?;
+
+    let typ = read_u8(reader)?;
+
+    let count = read_count(reader)? as i64;
+
+    let offset = match typ {
+        0 | 9 => globals.morph_idx_size as i64 + 4,
+        1 => globals.vert_idx_size as i64 + 12,
+        2 => globals.bone_idx_size as i64 + 28,
+        3..=7 => globals.vert_idx_size as i64 + 16,
+        8 => globals.material_idx_size as i64 + 113,
+        10 => globals.rb_idx_size as i64 + 25,
+        _ => Err(morph::Error::InvalidMorphType)?,
+    };
+
+    skip(reader, count * offset)
+}
+
+fn skip_display_frame(reader: &mut (impl Read + Seek), globals: &Globals) -> Result<()> {
+    skip_name(reader)?;
+
+    // special flag
+    skip(reader, 1)?;
+
+    let count = read_count(reader)?;
+
+    for _ in 0..count {
+        let index = match read_u8(reader)? {
+            0 => globals.bone_idx_size,
+            1 => globals.morph_idx_size,
+            _ => Err(display_frame::Error::InvalidEntryType)?,
+        };
+
+        skip(reader, index as i64)?;
+    }
+
+    Ok(())
+}
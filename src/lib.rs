@@ -1,7 +1,83 @@
-mod material;
+#[cfg(feature = "math_glam")]
+pub mod animation;
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod arkit;
+#[cfg(feature = "bevy")]
+pub mod bevy;
+pub mod bone;
+pub mod bounds;
+pub mod builder;
+#[cfg(feature = "bundle")]
+pub mod bundle;
+#[cfg(feature = "math_glam")]
+pub mod coordinates;
+pub mod csv;
+pub mod diagnostics;
+pub mod diff;
+pub mod display_frame;
+pub mod draw_order;
+#[cfg(feature = "dump")]
+pub mod dump;
+pub mod edit;
+#[cfg(feature = "gltf")]
+pub mod gltf;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+#[cfg(feature = "math_glam")]
+pub mod ik;
+#[cfg(feature = "image")]
+pub mod image;
+pub mod joint;
+pub mod lazy;
+pub mod lightmap;
+#[cfg(feature = "mmap")]
+pub mod mapped;
+pub mod material;
+pub mod merge;
+pub mod morph;
+#[cfg(feature = "math_glam")]
+pub mod morphing;
+pub mod names;
+pub mod normals;
+pub mod obj;
+#[cfg(feature = "rapier")]
+pub mod physics;
+pub mod physics_chains;
+pub mod physics_debug;
+pub mod pmd;
 pub mod pmx;
-mod surface;
-mod texture;
-mod types;
+pub mod prune;
+pub mod resolve;
+pub mod rigid_body;
+pub mod skeleton;
+#[cfg(feature = "math_glam")]
+pub mod skin;
+pub mod soft_body;
+pub mod spring;
+pub mod standard;
+pub mod submesh;
+pub mod surface;
+pub mod texture;
+#[cfg(feature = "thumbnail")]
+pub mod thumbnail;
+#[cfg(feature = "math_glam")]
+pub mod transform;
+pub mod translate;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod types;
 mod util;
-mod vertex;
+pub mod uv;
+pub mod validate;
+pub mod vertex;
+pub mod visit;
+pub mod vmd;
+pub mod vpd;
+#[cfg(feature = "gltf")]
+pub mod vrm;
+pub mod weights;
+pub mod weld;
+#[cfg(feature = "wgpu")]
+pub mod wgpu;
+pub mod xfile;
@@ -0,0 +1,351 @@
+//! Generating lightmap UVs.
+//!
+//! Baked lighting needs a second UV layout in which no two triangles share texels, which the
+//! texture UVs of MMD models rarely are, mirrored halves and repeated parts share texture space.
+//! [`Pmx::generate_lightmap_uvs`] projects the mesh onto the sides of a box: triangles facing the
+//! same way along the same axis that share vertices form a chart, each chart is projected flat
+//! along its axis and the charts are packed into the unit square, keeping their relative sizes
+//! so texel density is even. The layout goes into the `xy` of an additional UV channel.
+//!
+//! A vertex only has one UV per channel, so vertices used by several charts are split, the copies
+//! keep every attribute and get the offsets of the original in vertex and UV morphs. Soft bodies
+//! keep referring to the original vertices. The projection is flat, surfaces bending back onto
+//! themselves without turning past 45° can still overlap within their chart.
+
+use std::collections::{HashMap, hash_map::Entry};
+
+use crate::{
+    morph::{Offsets, UvOffset, VertexOffset},
+    pmx::Pmx,
+    types::{IndexSize, VertexIndex, from_array, to_array},
+    vertex::UvChannel,
+};
+
+/// Settings for [`Pmx::generate_lightmap_uvs`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightmapOptions {
+    pub(crate) padding: f32,
+    pub(crate) channel: Option<UvChannel>,
+}
+
+impl Default for LightmapOptions {
+    fn default() -> Self {
+        Self {
+            padding: 1.0 / 256.0,
+            channel: None,
+        }
+    }
+}
+
+impl LightmapOptions {
+    /// Options with a padding of 1/256, writing to the first unused channel.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn padding(&self) -> f32 {
+        self.padding
+    }
+
+    /// The gap between charts and around the layout in UV units, clamped to `0.0..=0.25`. It
+    /// should span a few texels of the lightmap to keep filtering from bleeding across charts.
+    pub fn set_padding(&mut self, padding: f32) {
+        self.padding = padding.clamp(0.0, 0.25);
+    }
+
+    pub fn channel(&self) -> Option<UvChannel> {
+        self.channel
+    }
+
+    /// The channel to write to, `None` for the first one the model does not use. A channel the
+    /// model has is overwritten, its morphs are left alone.
+    pub fn set_channel(&mut self, channel: Option<UvChannel>) {
+        self.channel = channel;
+    }
+}
+
+/// What [`Pmx::generate_lightmap_uvs`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightmapLayout {
+    pub(crate) channel: UvChannel,
+    pub(crate) charts: usize,
+    pub(crate) added_vertices: usize,
+}
+
+impl LightmapLayout {
+    /// The channel the layout was written to.
+    pub fn channel(&self) -> UvChannel {
+        self.channel
+    }
+
+    pub fn charts(&self) -> usize {
+        self.charts
+    }
+
+    /// The number of vertices split off, appended after the existing ones.
+    pub fn added_vertices(&self) -> usize {
+        self.added_vertices
+    }
+}
+
+impl Pmx {
+    /// Generates a lightmap UV layout, see the [module docs](crate::lightmap).
+    ///
+    /// Returns `None` and leaves the model alone if no channel is given and all four are in use.
+    /// Vertices no triangle uses get a zero UV in a new channel and keep theirs in an existing one.
+    pub fn generate_lightmap_uvs(&mut self, options: &LightmapOptions) -> Option<LightmapLayout> {
+        let used = self.header.globals.vec4_additional.min(4);
+        let channel = match options.channel {
+            Some(channel) => channel,
+            None => UvChannel::from_index(used)?,
+        };
+
+        let vertex_count = self.vertices.inner.len();
+        let positions: Vec<[f32; 3]> = (self.vertices.inner.iter())
+            .map(|v| to_array(v.pos))
+            .collect();
+
+        // triangles with valid corners, by their first surface
+        let triangles: Vec<(usize, [usize; 3])> = (self.surfaces.inner.chunks_exact(3).enumerate())
+            .filter_map(|(i, triangle)| {
+                let corner = |k: usize| triangle[k].index.as_usize().filter(|&v| v < vertex_count);
+                Some((i * 3, [corner(0)?, corner(1)?, corner(2)?]))
+            })
+            .collect();
+        let sides: Vec<usize> = (triangles.iter())
+            .map(|(_, [a, b, c])| side(positions[*a], positions[*b], positions[*c]))
+            .collect();
+
+        // charts, triangles of the same side joined through their vertices
+        let mut parents: Vec<usize> = (0..triangles.len()).collect();
+        let mut first: HashMap<(usize, usize), usize> = HashMap::new();
+        for (t, (_, corners)) in triangles.iter().enumerate() {
+            for &vertex in corners {
+                match first.entry((vertex, sides[t])) {
+                    Entry::Occupied(entry) => union(&mut parents, *entry.get(), t),
+                    Entry::Vacant(entry) => {
+                        entry.insert(t);
+                    }
+                }
+            }
+        }
+
+        let mut roots: HashMap<usize, usize> = HashMap::new();
+        let mut chart_sides = Vec::new();
+        let charts: Vec<usize> = (0..triangles.len())
+            .map(|t| {
+                let root = find(&mut parents, t);
+                *roots.entry(root).or_insert_with(|| {
+                    chart_sides.push(sides[t]);
+                    chart_sides.len() - 1
+                })
+            })
+            .collect();
+
+        // the first chart using a vertex keeps it, the others get a copy
+        let mut vertex_charts: Vec<Option<usize>> = vec![None; vertex_count];
+        let mut copies: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut added: Vec<(usize, usize)> = Vec::new();
+        for (t, (start, corners)) in triangles.iter().enumerate() {
+            let chart = charts[t];
+            for (k, &vertex) in corners.iter().enumerate() {
+                let target = match vertex_charts[vertex] {
+                    None => {
+                        vertex_charts[vertex] = Some(chart);
+                        vertex
+                    }
+                    Some(owner) if owner == chart => vertex,
+                    Some(_) => *copies.entry((vertex, chart)).or_insert_with(|| {
+                        added.push((vertex, chart));
+                        vertex_count + added.len() - 1
+                    }),
+                };
+                self.surfaces.inner[start + k].index = VertexIndex::new(target as i32);
+            }
+        }
+
+        let originals: Vec<usize> = added.iter().map(|&(original, _)| original).collect();
+        for &(original, chart) in &added {
+            let copy = self.vertices.inner[original].clone();
+            self.vertices.inner.push(copy);
+            vertex_charts.push(Some(chart));
+        }
+        self.vertices.size = self.vertices.inner.len();
+        self.split_morph_offsets(vertex_count, &originals);
+
+        let globals = &mut self.header.globals;
+        let size = &mut globals.vert_idx_size;
+        *size = (*size).max(IndexSize::smallest_for(self.vertices.inner.len(), false));
+        if channel.index() >= used {
+            globals.vec4_additional = channel.index() + 1;
+            let count = globals.vec4_additional as usize;
+            for vertex in &mut self.vertices.inner {
+                let additional = vertex.extra_vec4.get_or_insert_with(Vec::new);
+                additional.resize(count, from_array([0.0; 4]));
+            }
+        }
+
+        // each chart projected along its axis, then packed
+        let projected: Vec<Option<[f32; 2]>> = (self.vertices.inner.iter().zip(&vertex_charts))
+            .map(|(vertex, chart)| {
+                chart.map(|chart| project(to_array(vertex.pos), chart_sides[chart]))
+            })
+            .collect();
+
+        let mut bounds = vec![[f32::MAX, f32::MAX, f32::MIN, f32::MIN]; chart_sides.len()];
+        for (chart, point) in vertex_charts.iter().zip(&projected) {
+            if let (Some(chart), Some([x, y])) = (chart, point) {
+                let b = &mut bounds[*chart];
+                *b = [b[0].min(*x), b[1].min(*y), b[2].max(*x), b[3].max(*y)];
+            }
+        }
+        let sizes: Vec<[f32; 2]> = bounds.iter().map(|b| [b[2] - b[0], b[3] - b[1]]).collect();
+
+        let area: f32 = sizes.iter().map(|[w, h]| w * h).sum();
+        let mut gap = options.padding * area.sqrt();
+        let (mut corners, mut extent) = pack(&sizes, gap);
+        // the gap is in model units, grow it until it spans the padding of the packed square
+        for _ in 0..8 {
+            let wanted = options.padding * extent;
+            if gap >= wanted * 0.999 {
+                break;
+            }
+            gap = wanted;
+            (corners, extent) = pack(&sizes, gap);
+        }
+        let extent = if extent > 0.0 { extent } else { 1.0 };
+
+        for (i, vertex) in self.vertices.inner.iter_mut().enumerate() {
+            let (Some(chart), Some([x, y])) = (vertex_charts[i], projected[i]) else {
+                continue;
+            };
+            let [corner_x, corner_y] = corners[chart];
+            let u = (x - bounds[chart][0] + corner_x) / extent;
+            let v = (y - bounds[chart][1] + corner_y) / extent;
+
+            let additional = vertex.extra_vec4.get_or_insert_with(Vec::new);
+            if let Some(slot) = additional.get_mut(channel.index() as usize) {
+                let [_, _, z, w]: [f32; 4] = to_array(*slot);
+                *slot = from_array([u, v, z, w]);
+            }
+        }
+
+        Some(LightmapLayout {
+            channel,
+            charts: chart_sides.len(),
+            added_vertices: added.len(),
+        })
+    }
+
+    /// Gives the vertices from `first_copy` on the vertex and UV morph offsets of the vertex they
+    /// were copied from.
+    fn split_morph_offsets(&mut self, first_copy: usize, originals: &[usize]) {
+        let mut copies: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, &original) in originals.iter().enumerate() {
+            copies.entry(original).or_default().push(first_copy + i);
+        }
+        let copies_of = |vertex: &VertexIndex| {
+            let copies = vertex.as_usize().and_then(|v| copies.get(&v));
+            copies
+                .into_iter()
+                .flatten()
+                .map(|&c| VertexIndex::new(c as i32))
+        };
+
+        for morph in &mut self.morphs.inner {
+            match &mut morph.offsets {
+                Offsets::Vertex(offsets) => {
+                    let added: Vec<VertexOffset> = (offsets.iter())
+                        .flat_map(|o| {
+                            copies_of(&o.vertex).map(|vertex| VertexOffset {
+                                vertex,
+                                translation: o.translation,
+                            })
+                        })
+                        .collect();
+                    offsets.extend(added);
+                }
+                Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => {
+                    let added: Vec<UvOffset> = (offsets.iter())
+                        .flat_map(|o| {
+                            copies_of(&o.vertex).map(|vertex| UvOffset {
+                                vertex,
+                                offset: o.offset,
+                            })
+                        })
+                        .collect();
+                    offsets.extend(added);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Which way a triangle faces, `0..3` for the positive X, Y and Z axes and `3..6` for the negative
+/// ones, by the largest component of its normal.
+fn side(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> usize {
+    let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    let normal = [
+        ab[1] * ac[2] - ab[2] * ac[1],
+        ab[2] * ac[0] - ab[0] * ac[2],
+        ab[0] * ac[1] - ab[1] * ac[0],
+    ];
+
+    let axis = (0..3)
+        .max_by(|&i, &j| normal[i].abs().total_cmp(&normal[j].abs()))
+        .unwrap();
+    if normal[axis] < 0.0 { axis + 3 } else { axis }
+}
+
+/// The position on the plane of a side.
+fn project([x, y, z]: [f32; 3], side: usize) -> [f32; 2] {
+    match side % 3 {
+        0 => [z, y],
+        1 => [x, z],
+        _ => [x, y],
+    }
+}
+
+/// Places the charts in rows, tallest first, `gap` apart and from the edges. Returns the corner
+/// of every chart and the side of the square they fit in.
+fn pack(sizes: &[[f32; 2]], gap: f32) -> (Vec<[f32; 2]>, f32) {
+    let area: f32 = sizes.iter().map(|[w, h]| (w + gap) * (h + gap)).sum();
+    let widest = sizes.iter().map(|[w, _]| *w).fold(0.0, f32::max);
+    let width = area.sqrt().max(widest + 2.0 * gap);
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| sizes[b][1].total_cmp(&sizes[a][1]));
+
+    let mut corners = vec![[0.0; 2]; sizes.len()];
+    let (mut x, mut y, mut row, mut right) = (gap, gap, 0.0f32, 0.0f32);
+    for i in order {
+        let [w, h] = sizes[i];
+        if x + w + gap > width && x > gap {
+            x = gap;
+            y += row + gap;
+            row = 0.0;
+        }
+
+        corners[i] = [x, y];
+        x += w + gap;
+        row = row.max(h);
+        right = right.max(x);
+    }
+
+    (corners, right.max(y + row + gap))
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[b] = a;
+}
@@ -1,13 +1,534 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
 
-use sermmde::pmx::Pmx;
+use sermmde::{diff::DiffOptions, pmd::Pmd, pmx::Pmx, types::TextEncoding};
 
-fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
+const USAGE: &str = "\
+Usage: sermmde <command> [options]
 
-    let path = PathBuf::from(args[1].clone());
+Commands:
+  info <model>              Print the version, names and section sizes of a model
+  validate <model>          Check the references between sections, fails if any are broken
+  dump --json <model>       Print the model as JSON
+  convert <input> <output>  Convert by extension: .pmd or .pmx to .pmx, .gltf or .glb
+  diff <old> <new>          List the vertices, materials, bones and morphs that differ, fails if
+                            any do. Floats within --tolerance <value> (1e-5) count as equal
+  batch <dir> --op <op>     Run an operation on every .pmx and .pmd below a directory in
+                            parallel and print a summary, fails if any model does:
+                              validate   check the references of each model
+                              convert    write each model as --to <pmx|gltf|glb>, next to it
+                                         or in the same place below --out <dir>
+                              re-encode  write each model as .pmx with --encoding <utf8|utf16>
+                                         (utf8) below --out <dir>
+  thumbnail <model> -o <png>  Render a preview of the bind pose in the material colors, without a
+                            GPU, --size <px|WxH> (256)
+  tui <model>               Browse the header, bone tree, materials and morphs in the terminal,
+                            `/` searches by name
+  help                      Print this message
 
-    let pmx = Pmx::open(&path).unwrap();
+Exit codes: 0 on success, 1 if a command fails, 2 for invalid arguments.";
 
-    dbg!(&pmx);
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Invalid command line arguments.
+#[derive(Debug)]
+struct Usage(String);
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Usage {}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => ("help", &[][..]),
+    };
+
+    let result = match command {
+        "info" => info(args),
+        "validate" => validate(args),
+        "dump" => dump(args),
+        "convert" => convert(args),
+        "diff" => diff(args),
+        "batch" => batch(args),
+        "thumbnail" => thumbnail(args),
+        "tui" => tui(args),
+        "help" | "-h" | "--help" => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(Usage(format!("unknown command `{command}`")).into()),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(error) if error.is::<Usage>() => {
+            eprintln!("error: {error}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The positional arguments of a command, `count` of them, with the flags in `flags` allowed.
+fn positional<'a>(args: &'a [String], count: usize, flags: &[&str]) -> Result<Vec<&'a str>> {
+    let mut positional = Vec::new();
+
+    for arg in args {
+        if arg.starts_with('-') && arg.len() > 1 {
+            if !flags.contains(&arg.as_str()) {
+                Err(Usage(format!("unknown option `{arg}`")))?
+            }
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+
+    if positional.len() != count {
+        Err(Usage(format!(
+            "expected {count} argument(s), got {}",
+            positional.len()
+        )))?
+    }
+
+    Ok(positional)
+}
+
+/// Takes the options in `names` and their values out of `args`, the last occurrence wins.
+fn values(args: &[String], names: &[&str]) -> Result<(HashMap<String, String>, Vec<String>)> {
+    let mut values = HashMap::new();
+    let mut rest = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if names.contains(&arg.as_str()) {
+            let value = args
+                .next()
+                .ok_or_else(|| Usage(format!("{arg} needs a value")))?;
+            values.insert(arg.clone(), value.clone());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+
+    Ok((values, rest))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Opens a `.pmx` model, or a `.pmd` one converted to PMX.
+fn open(path: &Path) -> Result<Pmx> {
+    let context = |error: &dyn Error| format!("{}: {error}", path.display());
+
+    match extension(path).as_str() {
+        "pmd" => Ok(Pmx::from_pmd(&Pmd::open(path).map_err(|e| context(&e))?)),
+        _ => Ok(Pmx::open(path).map_err(|e| context(&e))?),
+    }
+}
+
+fn info(args: &[String]) -> Result<ExitCode> {
+    let [path] = positional(args, 1, &[])?[..] else {
+        unreachable!()
+    };
+    let pmx = open(Path::new(path))?;
+
+    let header = pmx.header();
+    let name = header.name();
+    println!("version:       {}", header.version());
+    println!("encoding:      {:?}", header.globals().encoding());
+    println!("name:          {}", name.local);
+    println!("english name:  {}", name.universal);
+
+    println!("vertices:      {}", pmx.vertices().len());
+    println!("faces:         {}", pmx.surfaces().len() / 3);
+    println!("textures:      {}", pmx.textures().len());
+    println!("materials:     {}", pmx.materials().len());
+    println!("bones:         {}", pmx.bones().len());
+    println!("morphs:        {}", pmx.morphs().len());
+    println!("frames:        {}", pmx.display_frames().len());
+    println!("rigid bodies:  {}", pmx.rigid_bodies().len());
+    println!("joints:        {}", pmx.joints().len());
+    if let Some(soft_bodies) = pmx.soft_bodies() {
+        println!("soft bodies:   {}", soft_bodies.len());
+    }
+
+    for (i, material) in pmx.materials().materials().iter().enumerate() {
+        let label = format!("material {i}:");
+        println!("{label:<15}{}", material.name().local);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn validate(args: &[String]) -> Result<ExitCode> {
+    let [path] = positional(args, 1, &[])?[..] else {
+        unreachable!()
+    };
+    let report = open(Path::new(path))?.validate();
+
+    if report.is_valid() {
+        println!("no problems found");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        print!("{report}");
+        println!("{} problem(s) found", report.violations().len());
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn dump(args: &[String]) -> Result<ExitCode> {
+    let [path] = positional(args, 1, &["--json"])?[..] else {
+        unreachable!()
+    };
+    if !args.iter().any(|arg| arg == "--json") {
+        Err(Usage("dump needs an output format, `--json`".into()))?
+    }
+
+    #[cfg(feature = "dump")]
+    {
+        let pmx = open(Path::new(path))?;
+        println!("{}", sermmde::dump::to_json(&pmx)?);
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    #[cfg(not(feature = "dump"))]
+    {
+        let _ = path;
+        Err("JSON output needs the `dump` feature".into())
+    }
+}
+
+fn convert(args: &[String]) -> Result<ExitCode> {
+    let [input, output] = positional(args, 2, &[])?[..] else {
+        unreachable!()
+    };
+    let (input, output) = (PathBuf::from(input), PathBuf::from(output));
+
+    if !matches!(extension(&input).as_str(), "pmd" | "pmx") {
+        Err(Usage(format!(
+            "cannot convert from `{}`, expected a .pmd or .pmx file",
+            input.display()
+        )))?
+    }
+
+    match extension(&output).as_str() {
+        "pmx" => open(&input)?.save(&output)?,
+        "gltf" | "glb" => gltf(&input, &output)?,
+        _ => Err(Usage(format!(
+            "cannot convert to `{}`, expected a .pmx, .gltf or .glb file",
+            output.display()
+        )))?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn diff(args: &[String]) -> Result<ExitCode> {
+    let (values, rest) = values(args, &["--tolerance"])?;
+    let mut options = DiffOptions::new();
+    if let Some(tolerance) = values.get("--tolerance") {
+        let tolerance = tolerance
+            .parse()
+            .map_err(|_| Usage("--tolerance needs a number".into()))?;
+        options.set_tolerance(tolerance);
+    }
+
+    let [old, new] = positional(&rest, 2, &[])?[..] else {
+        unreachable!()
+    };
+    let diff = open(Path::new(old))?.diff(&open(Path::new(new))?, &options);
+
+    if diff.is_empty() {
+        println!("no differences found");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        print!("{diff}");
+        println!("{} difference(s) found", diff.changes().len());
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Validate,
+    Convert,
+    ReEncode,
+}
+
+/// How processing one model of a batch went.
+enum Outcome {
+    Ok(String),
+    Invalid(String),
+    Skipped(String),
+    Failed(String),
+}
+
+fn batch(args: &[String]) -> Result<ExitCode> {
+    let (values, rest) = values(args, &["--op", "--to", "--encoding", "--out"])?;
+    let [dir] = positional(&rest, 1, &[])?[..] else {
+        unreachable!()
+    };
+    let dir = Path::new(dir);
+
+    let operation = match values.get("--op").map(String::as_str) {
+        Some("validate") => Operation::Validate,
+        Some("convert") => Operation::Convert,
+        Some("re-encode") => Operation::ReEncode,
+        Some(op) => Err(Usage(format!("unknown operation `{op}`")))?,
+        None => Err(Usage("batch needs an operation, `--op`".into()))?,
+    };
+    let target = match (operation, values.get("--to").map(String::as_str)) {
+        (Operation::Convert, Some(to @ ("pmx" | "gltf" | "glb"))) => to,
+        (Operation::Convert, Some(to)) => Err(Usage(format!("cannot convert to `{to}`")))?,
+        (Operation::Convert, None) => Err(Usage("convert needs a format, `--to`".into()))?,
+        _ => "pmx",
+    };
+    let encoding = match values.get("--encoding").map(String::as_str) {
+        None | Some("utf8") => TextEncoding::UTF8,
+        Some("utf16") => TextEncoding::UTF16LE,
+        Some(encoding) => Err(Usage(format!("unknown encoding `{encoding}`")))?,
+    };
+    let out = values.get("--out").map(PathBuf::from);
+    if operation == Operation::ReEncode && out.is_none() {
+        Err(Usage("re-encode needs an output directory, `--out`".into()))?
+    }
+
+    let mut models = Vec::new();
+    collect_models(dir, out.as_deref(), &mut models)?;
+    models.sort();
+
+    let next = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<Outcome>>> =
+        Mutex::new((0..models.len()).map(|_| None).collect());
+    let jobs = thread::available_parallelism().map_or(1, usize::from);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(models.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = models.get(index) else {
+                        break;
+                    };
+
+                    let output = |extension: &str| {
+                        let relative = path.strip_prefix(dir).unwrap_or(path);
+                        let output = match &out {
+                            Some(out) => out.join(relative),
+                            None => path.clone(),
+                        };
+                        output.with_extension(extension)
+                    };
+
+                    let outcome = match operation {
+                        Operation::Validate => batch_validate(path),
+                        Operation::Convert => batch_convert(path, &output(target)),
+                        Operation::ReEncode => batch_re_encode(path, &output("pmx"), encoding),
+                    }
+                    .unwrap_or_else(|error| Outcome::Failed(error.to_string()));
+
+                    outcomes.lock().unwrap()[index] = Some(outcome);
+                }
+            });
+        }
+    });
+
+    let outcomes = outcomes.into_inner().unwrap();
+    let width = models
+        .iter()
+        .map(|path| path.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut counts = [0; 4];
+
+    for (path, outcome) in models.iter().zip(outcomes.iter().flatten()) {
+        let (status, detail, count) = match outcome {
+            Outcome::Ok(detail) => ("ok", detail, &mut counts[0]),
+            Outcome::Invalid(detail) => ("invalid", detail, &mut counts[1]),
+            Outcome::Skipped(detail) => ("skipped", detail, &mut counts[2]),
+            Outcome::Failed(detail) => ("failed", detail, &mut counts[3]),
+        };
+        *count += 1;
+
+        let path = path.display().to_string();
+        let padding = width - path.chars().count();
+        let line = format!("{path}{}  {status:<7}  {detail}", " ".repeat(padding));
+        println!("{}", line.trim_end());
+    }
+
+    let [ok, invalid, skipped, failed] = counts;
+    println!(
+        "{} model(s): {ok} ok, {invalid} invalid, {skipped} skipped, {failed} failed",
+        models.len()
+    );
+
+    if invalid + failed > 0 {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Collects the `.pmx` and `.pmd` files below `dir`, except those in `exclude`.
+fn collect_models(dir: &Path, exclude: Option<&Path>, models: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|e| format!("{}: {e}", dir.display()))? {
+        let path = entry?.path();
+
+        if exclude.is_some_and(|exclude| path.starts_with(exclude)) {
+            continue;
+        }
+
+        if path.is_dir() {
+            collect_models(&path, exclude, models)?;
+        } else if matches!(extension(&path).as_str(), "pmx" | "pmd") {
+            models.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn batch_validate(path: &Path) -> Result<Outcome> {
+    let report = open(path)?.validate();
+
+    Ok(match report.violations() {
+        [] => Outcome::Ok(String::new()),
+        [first, ..] => Outcome::Invalid(format!(
+            "{} problem(s), first: {first}",
+            report.violations().len()
+        )),
+    })
+}
+
+fn batch_convert(path: &Path, output: &Path) -> Result<Outcome> {
+    if output == path {
+        return Ok(Outcome::Skipped("already in the target format".into()));
+    }
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if extension(output) == "pmx" {
+        open(path)?.save(output)?;
+    } else {
+        gltf(path, output)?;
+    }
+
+    Ok(Outcome::Ok(output.display().to_string()))
+}
+
+fn batch_re_encode(path: &Path, output: &Path, encoding: TextEncoding) -> Result<Outcome> {
+    let mut pmx = open(path)?;
+    let previous = pmx.header().globals().encoding();
+    pmx.header_mut().globals_mut().set_encoding(encoding);
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    pmx.save(output)?;
+
+    Ok(Outcome::Ok(format!("{previous:?} -> {encoding:?}")))
+}
+
+fn thumbnail(args: &[String]) -> Result<ExitCode> {
+    let (values, rest) = values(args, &["-o", "--output", "--size"])?;
+    let [path] = positional(&rest, 1, &[])?[..] else {
+        unreachable!()
+    };
+    let output = (values.get("-o").or(values.get("--output")))
+        .ok_or_else(|| Usage("thumbnail needs an output file, `-o <png>`".into()))?;
+
+    let mut size = (256, 256);
+    if let Some(value) = values.get("--size") {
+        let parse = |n: &str| n.parse().ok().filter(|&n: &u32| n > 0);
+        size = match value.split_once('x') {
+            Some((width, height)) => parse(width).zip(parse(height)),
+            None => parse(value).map(|n| (n, n)),
+        }
+        .ok_or_else(|| Usage("--size needs a size in pixels like 256 or 320x240".into()))?;
+    }
+
+    #[cfg(feature = "thumbnail")]
+    {
+        let mut options = sermmde::thumbnail::ThumbnailOptions::new();
+        options.set_size(size.0, size.1);
+
+        let image = open(Path::new(path))?.thumbnail(&options);
+        image.save(output)?;
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    #[cfg(not(feature = "thumbnail"))]
+    {
+        let _ = (path, output, size);
+        Err("thumbnails need the `thumbnail` feature".into())
+    }
+}
+
+fn tui(args: &[String]) -> Result<ExitCode> {
+    let [path] = positional(args, 1, &[])?[..] else {
+        unreachable!()
+    };
+
+    #[cfg(feature = "tui")]
+    {
+        sermmde::tui::run(&open(Path::new(path))?)?;
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    #[cfg(not(feature = "tui"))]
+    {
+        let _ = path;
+        Err("the inspector needs the `tui` feature".into())
+    }
+}
+
+#[cfg(feature = "gltf")]
+fn gltf(input: &Path, output: &Path) -> Result<()> {
+    use sermmde::gltf::{ExportOptions, Gltf};
+
+    let pmx = open(input)?;
+    let mut options = ExportOptions::new();
+    if let Some(dir) = input.parent() {
+        options.set_base_dir(dir);
+    }
+
+    let gltf = Gltf::from_pmx(&pmx, &options)?;
+    if extension(output) == "glb" {
+        gltf.save_glb(output)?;
+    } else {
+        gltf.save_gltf(output)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "gltf"))]
+fn gltf(_input: &Path, _output: &Path) -> Result<()> {
+    Err("glTF output needs the `gltf` feature".into())
 }
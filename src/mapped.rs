@@ -0,0 +1,405 @@
+//! Memory-mapped parsing.
+//!
+//! [`MappedPmx`] maps the file and hands out views that read the surface and vertex data straight
+//! from the mapping, so the bulk of a large model never gets copied into owned buffers. The
+//! remaining, comparatively tiny sections are parsed from the mapping on request.
+
+use std::{collections::HashMap, fs::File, io::Cursor, path::Path};
+
+use memmap2::Mmap;
+
+use crate::{
+    bone, display_frame, joint,
+    lazy::scan,
+    material, morph,
+    pmx::{Error, Header, Result, Version, check_joints, check_morphs},
+    rigid_body, soft_body, texture,
+    types::{IndexSize, ParseContext, Vec2, Vec3, Vec4, VertexIndex, from_array},
+    vertex::{self, Vertex},
+    visit::Section,
+};
+
+/// A memory-mapped PMX file.
+pub struct MappedPmx {
+    map: Mmap,
+    header: Header,
+    offsets: HashMap<Section, u64>,
+}
+
+impl MappedPmx {
+    /// Maps the file and scans its section boundaries.
+    ///
+    /// The file must not be modified while it is mapped, doing so is undefined behavior.
+    pub fn open(path: &Path) -> Result<Self> {
+        let fh = File::open(path)?;
+
+        // SAFETY: the caller is responsible for not truncating or modifying the file while mapped,
+        // as documented above
+        let map = unsafe { Mmap::map(&fh)? };
+
+        let mut cursor = Cursor::new(&map[..]);
+
+        let header = Header::parse(&mut cursor)?;
+
+        let offsets = scan(&mut cursor, &header)?;
+
+        Ok(Self {
+            map,
+            header,
+            offsets,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The raw bytes of the whole file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// The bytes from the start of a section (its element count) to the end of the file.
+    fn section(&self, section: Section) -> &[u8] {
+        &self.map[self.offsets[&section] as usize..]
+    }
+
+    /// Element count of a section and the bytes of its elements, up to the next section.
+    fn section_elements(&self, section: Section, next: Section) -> (usize, &[u8]) {
+        let start = self.offsets[&section] as usize;
+        let end = self.offsets[&next] as usize;
+
+        let count = i32::from_le_bytes(self.map[start..start + 4].try_into().unwrap());
+
+        // the scan already rejected negative counts
+        (count as usize, &self.map[start + 4..end])
+    }
+
+    /// A view of the surface index buffer, read directly from the mapping.
+    pub fn surfaces(&self) -> SurfaceView<'_> {
+        let (len, bytes) = self.section_elements(Section::Surfaces, Section::Textures);
+
+        SurfaceView {
+            bytes,
+            len,
+            index_size: self.header.globals().vert_idx_size,
+        }
+    }
+
+    /// A view of the vertices, read directly from the mapping.
+    pub fn vertices(&self) -> Result<VertexView<'_>> {
+        let globals = self.header.globals();
+        let version = self.header.version();
+
+        let (len, bytes) = self.section_elements(Section::Vertices, Section::Surfaces);
+
+        let base = 32 + 16 * globals.vec4_additional as usize;
+        let b = globals.bone_idx_size as usize;
+
+        let mut runs: Vec<Run> = Vec::new();
+        let mut offset = 0;
+
+        for i in 0..len {
+            let typ = bytes[offset + base];
+
+            if typ == 4 && version < Version::V2_1 {
+                Err(Error::VersionMismatch {
+                    version,
+                    feature: "QDEF weight deform",
+                })?
+            }
+
+            let deform = match typ {
+                0 => b,
+                1 => 2 * b + 4,
+                2 | 4 => 4 * b + 16,
+                3 => 2 * b + 4 + 36,
+                _ => Err(vertex::Error::InvalidWeightDeformType)?,
+            };
+
+            let stride = base + 1 + deform + 4;
+
+            match runs.last() {
+                Some(run) if run.stride == stride => {}
+                _ => runs.push(Run {
+                    first: i,
+                    offset,
+                    stride,
+                }),
+            }
+
+            offset += stride;
+        }
+
+        Ok(VertexView {
+            bytes,
+            len,
+            runs,
+            vec4_count: globals.vec4_additional,
+            bone_idx_size: globals.bone_idx_size,
+        })
+    }
+
+    pub fn textures(&self) -> Result<texture::Textures> {
+        let globals = self.header.globals();
+
+        Ok(texture::Textures::parse(
+            &mut self.section(Section::Textures),
+            globals.encoding,
+            ParseContext::UNCHECKED,
+        )?)
+    }
+
+    pub fn materials(&self) -> Result<material::Materials> {
+        let globals = self.header.globals();
+
+        Ok(material::Materials::parse(
+            &mut self.section(Section::Materials),
+            globals.tex_idx_size,
+            globals.encoding,
+            ParseContext::UNCHECKED,
+        )?)
+    }
+
+    pub fn bones(&self) -> Result<bone::Bones> {
+        let globals = self.header.globals();
+
+        Ok(bone::Bones::parse(
+            &mut self.section(Section::Bones),
+            globals.bone_idx_size,
+            globals.encoding,
+            ParseContext::UNCHECKED,
+        )?)
+    }
+
+    pub fn morphs(&self) -> Result<morph::Morphs> {
+        let morphs = morph::Morphs::parse(
+            &mut self.section(Section::Morphs),
+            self.header.globals(),
+            ParseContext::UNCHECKED,
+        )?;
+
+        check_morphs(self.header.version(), &morphs)?;
+
+        Ok(morphs)
+    }
+
+    pub fn display_frames(&self) -> Result<display_frame::DisplayFrames> {
+        Ok(display_frame::DisplayFrames::parse(
+            &mut self.section(Section::DisplayFrames),
+            self.header.globals(),
+            ParseContext::UNCHECKED,
+        )?)
+    }
+
+    pub fn rigid_bodies(&self) -> Result<rigid_body::RigidBodies> {
+        let globals = self.header.globals();
+
+        Ok(rigid_body::RigidBodies::parse(
+            &mut self.section(Section::RigidBodies),
+            globals.bone_idx_size,
+            globals.encoding,
+            ParseContext::UNCHECKED,
+        )?)
+    }
+
+    pub fn joints(&self) -> Result<joint::Joints> {
+        let globals = self.header.globals();
+
+        let joints = joint::Joints::parse(
+            &mut self.section(Section::Joints),
+            globals.rb_idx_size,
+            globals.encoding,
+            ParseContext::UNCHECKED,
+        )?;
+
+        check_joints(self.header.version(), &joints)?;
+
+        Ok(joints)
+    }
+
+    /// The soft bodies, `None` for 2.0 files which have no soft body section.
+    pub fn soft_bodies(&self) -> Result<Option<soft_body::SoftBodies>> {
+        if !self.offsets.contains_key(&Section::SoftBodies) {
+            return Ok(None);
+        }
+
+        Ok(Some(soft_body::SoftBodies::parse(
+            &mut self.section(Section::SoftBodies),
+            self.header.globals(),
+            ParseContext::UNCHECKED,
+        )?))
+    }
+}
+
+/// The surface index buffer, borrowed from the mapping.
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceView<'a> {
+    bytes: &'a [u8],
+    len: usize,
+    index_size: u8,
+}
+
+impl<'a> SurfaceView<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of a single index in bytes.
+    pub fn index_size(&self) -> u8 {
+        self.index_size
+    }
+
+    /// The little-endian index buffer as stored in the file.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn get(&self, index: usize) -> Option<VertexIndex> {
+        if index >= self.len {
+            return None;
+        }
+
+        let size = self.index_size as usize;
+
+        let mut bytes = &self.bytes[index * size..(index + 1) * size];
+
+        // the size was validated when the header was parsed
+        let size: IndexSize = self.index_size.try_into().ok()?;
+
+        VertexIndex::parse(&mut bytes, size).ok()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = VertexIndex> + 'a {
+        let view = *self;
+
+        (0..self.len).filter_map(move |i| view.get(i))
+    }
+
+    /// Iterates over the triangles, ignoring a trailing incomplete one.
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + 'a {
+        let view = *self;
+
+        (0..self.len / 3).map(move |t| {
+            std::array::from_fn(|i| view.get(t * 3 + i).map_or(0, |v| v.value() as u32))
+        })
+    }
+}
+
+/// A run of consecutive vertices sharing the same record size.
+#[derive(Debug)]
+struct Run {
+    first: usize,
+    offset: usize,
+    stride: usize,
+}
+
+/// The vertex section, borrowed from the mapping.
+///
+/// Vertex records differ in size depending on their weight deform, so the view keeps the start of
+/// every run of equally sized records for random access.
+#[derive(Debug)]
+pub struct VertexView<'a> {
+    bytes: &'a [u8],
+    len: usize,
+    runs: Vec<Run>,
+    vec4_count: u8,
+    bone_idx_size: u8,
+}
+
+impl<'a> VertexView<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<VertexRef<'a>> {
+        if index >= self.len {
+            return None;
+        }
+
+        let run = match self.runs.binary_search_by_key(&index, |run| run.first) {
+            Ok(i) => &self.runs[i],
+            Err(i) => &self.runs[i - 1],
+        };
+
+        let start = run.offset + (index - run.first) * run.stride;
+
+        Some(VertexRef {
+            bytes: &self.bytes[start..start + run.stride],
+            vec4_count: self.vec4_count,
+            bone_idx_size: self.bone_idx_size,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = VertexRef<'a>> + '_ {
+        (0..self.len).filter_map(|i| self.get(i))
+    }
+}
+
+/// A single vertex record borrowed from the mapping, decoded on access.
+#[derive(Debug, Copy, Clone)]
+pub struct VertexRef<'a> {
+    bytes: &'a [u8],
+    vec4_count: u8,
+    bone_idx_size: u8,
+}
+
+impl<'a> VertexRef<'a> {
+    /// The raw vertex record.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn position(&self) -> Vec3 {
+        from_array(floats::<3>(&self.bytes[0..]))
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        from_array(floats::<3>(&self.bytes[12..]))
+    }
+
+    pub fn uv(&self) -> Vec2 {
+        from_array(floats::<2>(&self.bytes[24..]))
+    }
+
+    /// The `n`-th additional vec4, `None` if the model has fewer.
+    pub fn additional_vec4(&self, n: usize) -> Option<Vec4> {
+        if n >= self.vec4_count as usize {
+            return None;
+        }
+
+        Some(from_array(floats::<4>(&self.bytes[32 + 16 * n..])))
+    }
+
+    /// The weight deform type byte.
+    pub fn weight_deform_type(&self) -> u8 {
+        self.bytes[32 + 16 * self.vec4_count as usize]
+    }
+
+    pub fn edge_scale(&self) -> f32 {
+        floats::<1>(&self.bytes[self.bytes.len() - 4..])[0]
+    }
+
+    /// Decodes the full vertex into an owned [`Vertex`].
+    pub fn to_vertex(&self) -> Result<Vertex> {
+        let mut bytes = self.bytes;
+
+        Ok(Vertex::parse(
+            &mut bytes,
+            self.vec4_count,
+            self.bone_idx_size,
+        )?)
+    }
+}
+
+fn floats<const N: usize>(bytes: &[u8]) -> [f32; N] {
+    std::array::from_fn(|i| f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()))
+}
@@ -1,8 +1,15 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use thiserror::Error;
 
-use crate::types::{Flag, Index, PmxText, TextEncoding, Vec3, Vec4, vec_from_bytes};
+use crate::{
+    types::{
+        Name, ParseContext, PmxText, TextEncoding, TextureIndex, Vec3, Vec4, from_array, read_f32,
+        read_i32, read_u8, to_array, vec_from_bytes, vec_to_bytes, write_count, write_f32,
+        write_i32, write_u8,
+    },
+    vertex::UvChannel,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -12,14 +19,39 @@ pub enum Error {
     Type(#[from] crate::types::Error),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Invalid environment blend mode encountered")]
+    InvalidEnvironmentBlend,
+    #[error("Invalid toon reference type encountered")]
+    InvalidToonReference,
+    #[error("{source} in element {index}")]
+    Element { index: usize, source: Box<Error> },
+    #[error("{source} in field {field}")]
+    Field {
+        field: &'static str,
+        source: Box<Error>,
+    },
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+crate::types::error_context!();
+
 #[derive(Debug)]
 pub struct Materials {
-    len: usize,
-    inner: Vec<Material>,
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<Material>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(Materials, Material, len);
+
+impl From<Vec<Material>> for Materials {
+    fn from(inner: Vec<Material>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
 }
 
 impl Materials {
@@ -29,23 +61,35 @@ impl Materials {
         len
     }
 
-    pub fn parse(reader: &mut impl Read, index_size: u8, encoding: TextEncoding) -> Result<Self> {
-        let mut size_bytes = [0; 4];
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        reader.read_exact(&mut size_bytes)?;
+    pub fn materials(&self) -> &[Material] {
+        &self.inner
+    }
 
-        let size = i32::from_le_bytes(size_bytes);
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size = field("count", || {
+            let size = read_i32(reader)?;
 
-        if size.is_negative() {
-            Err(Error::NegativeSize)?
-        }
+            if size.is_negative() {
+                Err(Error::NegativeSize)?
+            }
 
-        let size = size as usize;
+            // the fixed fields, two texture indices and an internal toon
+            Ok(context.check_count(size as usize, 84 + 2 * index_size as u64)?)
+        })?;
 
         let mut inner_vec = Vec::with_capacity(size);
 
-        for _ in 0..size {
-            let mat = Material::parse(reader, index_size, encoding)?;
+        for i in 0..size {
+            let mat = element(i, Material::parse(reader, index_size, encoding, context))?;
             inner_vec.push(mat);
         }
 
@@ -54,57 +98,538 @@ impl Materials {
             inner: inner_vec,
         })
     }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for mat in &self.inner {
+            mat.write(writer, index_size, encoding)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Material drawing flags.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaterialFlags {
+    pub(crate) raw: u8,
+}
+
+impl MaterialFlags {
+    pub fn from_raw(raw: u8) -> Self {
+        Self { raw }
+    }
+
+    pub fn raw(&self) -> u8 {
+        self.raw
+    }
+
+    /// Disables back-face culling.
+    pub const NO_CULL: u8 = 0x01;
+    pub const GROUND_SHADOW: u8 = 0x02;
+    /// Casts shadows onto the self shadow map.
+    pub const DRAW_SHADOW: u8 = 0x04;
+    pub const RECEIVE_SHADOW: u8 = 0x08;
+    pub const EDGE: u8 = 0x10;
+    /// Use the first additional vec4 as vertex color (ver 2.1).
+    pub const VERTEX_COLOR: u8 = 0x20;
+    /// Draw the vertices as points (ver 2.1).
+    pub const POINT_DRAW: u8 = 0x40;
+    /// Draw the surfaces as lines (ver 2.1).
+    pub const LINE_DRAW: u8 = 0x80;
+
+    /// Returns true if every bit of `flag` is set.
+    pub fn contains(&self, flag: u8) -> bool {
+        self.raw & flag == flag
+    }
+
+    pub fn no_cull(&self) -> bool {
+        self.contains(Self::NO_CULL)
+    }
+
+    pub fn ground_shadow(&self) -> bool {
+        self.contains(Self::GROUND_SHADOW)
+    }
+
+    pub fn draw_shadow(&self) -> bool {
+        self.contains(Self::DRAW_SHADOW)
+    }
+
+    pub fn receive_shadow(&self) -> bool {
+        self.contains(Self::RECEIVE_SHADOW)
+    }
+
+    pub fn edge(&self) -> bool {
+        self.contains(Self::EDGE)
+    }
+
+    pub fn vertex_color(&self) -> bool {
+        self.contains(Self::VERTEX_COLOR)
+    }
+
+    pub fn point_draw(&self) -> bool {
+        self.contains(Self::POINT_DRAW)
+    }
+
+    pub fn line_draw(&self) -> bool {
+        self.contains(Self::LINE_DRAW)
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            raw: read_u8(reader)?,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_u8(writer, self.raw)?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Material {
-    name: Name,
-    diffuse: Vec4,
-    specular: Vec3,
-    specular_strength: f32,
-    ambient: Vec3,
-    flags: Flag,
-    edge_color: Vec4,
-    edge_scale: f32,
-    tex_idx: Index,
-    env_idx: Index,
-    env_blend: EnvironmentBlend,
-    toon: Toon,
-    meta: PmxText,
-    surface_count: i32,
+    pub(crate) name: Name,
+    pub(crate) diffuse: Vec4,
+    pub(crate) specular: Vec3,
+    pub(crate) specular_strength: f32,
+    pub(crate) ambient: Vec3,
+    pub(crate) flags: MaterialFlags,
+    pub(crate) edge_color: Vec4,
+    pub(crate) edge_scale: f32,
+    pub(crate) tex_idx: TextureIndex,
+    pub(crate) env_idx: TextureIndex,
+    pub(crate) env_blend: EnvironmentBlend,
+    pub(crate) toon: Toon,
+    pub(crate) meta: PmxText,
+    pub(crate) surface_count: i32,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Toon {
-    Texture(Index),
+    /// Index into the texture section.
+    Texture(TextureIndex),
+    /// One of the built-in toon textures (toon01.bmp - toon10.bmp), stored 0-based.
     Internal(u8),
 }
 
-#[derive(Debug)]
+/// The number of built-in toon textures.
+pub const INTERNAL_TOON_COUNT: u8 = 10;
+
+/// The height of the [fallback gradients](Toon::internal_gradient) of the built-in toons.
+pub const TOON_GRADIENT_LEN: usize = 32;
+
+/// The shade at the bottom of each built-in toon texture, approximated.
+const INTERNAL_TOON_SHADES: [[u8; 3]; INTERNAL_TOON_COUNT as usize] = [
+    [205, 205, 205],
+    [252, 222, 206],
+    [160, 160, 160],
+    [250, 208, 208],
+    [212, 226, 250],
+    [255, 236, 206],
+    [224, 224, 224],
+    [238, 214, 204],
+    [254, 238, 238],
+    [246, 228, 222],
+];
+
+impl Toon {
+    /// The 0-based index of the built-in toon texture, `None` for texture references.
+    pub fn internal(&self) -> Option<u8> {
+        match self {
+            Toon::Internal(index) => Some(*index),
+            Toon::Texture(_) => None,
+        }
+    }
+
+    /// The index into the texture section, `None` for built-in toon references.
+    pub fn texture_index(&self) -> Option<TextureIndex> {
+        match self {
+            Toon::Texture(index) => Some(*index),
+            Toon::Internal(_) => None,
+        }
+    }
+
+    /// The file name of the built-in toon texture at the 0-based `index`, like `toon01.bmp`.
+    pub fn internal_file_name(index: u8) -> String {
+        format!("toon{:02}.bmp", u16::from(index) + 1)
+    }
+
+    /// The 0-based index of a built-in toon texture name like `toon01.bmp`, ignoring case.
+    ///
+    /// Models often list the shared toons in their texture section as well, MMD then uses the
+    /// built-in texture unless the model ships a file of the same name.
+    pub fn internal_index_of(name: &str) -> Option<u8> {
+        let name = name.to_ascii_lowercase();
+        let number: u8 = name
+            .strip_prefix("toon")?
+            .strip_suffix(".bmp")?
+            .parse()
+            .ok()?;
+
+        (1..=INTERNAL_TOON_COUNT)
+            .contains(&number)
+            .then(|| number - 1)
+    }
+
+    /// A stand-in for a built-in toon texture, so renderers need not ship MMD's files.
+    ///
+    /// The rows of an RGBA gradient from the lit top to the shaded bottom of the texture, meant
+    /// to be uploaded as a 1 pixel wide image. The colors approximate the originals. `None` for
+    /// texture references and out of range indices.
+    pub fn internal_gradient(&self) -> Option<[[u8; 4]; TOON_GRADIENT_LEN]> {
+        let [r, g, b] = *INTERNAL_TOON_SHADES.get(usize::from(self.internal()?))?;
+
+        // white down to the middle, then a short blend into the shade
+        let edge = TOON_GRADIENT_LEN / 2;
+        let blend = 4;
+
+        Some(std::array::from_fn(|row| {
+            let t = (row.saturating_sub(edge) as f32 / blend as f32).min(1.0);
+            let mix = |shade: u8| (255.0 + (f32::from(shade) - 255.0) * t).round() as u8;
+
+            [mix(r), mix(g), mix(b), 255]
+        }))
+    }
+
+    pub fn parse(reader: &mut impl Read, index_size: u8) -> Result<Self> {
+        let mut toon_ref = [0; 1];
+
+        reader.read_exact(&mut toon_ref)?;
+
+        match toon_ref[0] {
+            0 => {
+                let index = TextureIndex::parse(reader, index_size.try_into()?)?;
+
+                Ok(Toon::Texture(index))
+            }
+            1 => {
+                let mut internal = [0; 1];
+
+                reader.read_exact(&mut internal)?;
+
+                Ok(Toon::Internal(internal[0]))
+            }
+            _ => Err(Error::InvalidToonReference),
+        }
+    }
+
+    pub fn write(&self, writer: &mut impl Write, index_size: u8) -> Result<()> {
+        match self {
+            Toon::Texture(index) => {
+                write_u8(writer, 0)?;
+                index.write(writer, index_size.try_into()?)?;
+            }
+            Toon::Internal(internal) => {
+                write_u8(writer, 1)?;
+                write_u8(writer, *internal)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How the environment texture of a material is sampled and combined with its color.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EnvironmentBlend {
+    /// The environment texture is not used.
     None,
+    /// A sphere map multiplied with the color, `.sph` files.
     Multiply,
+    /// A sphere map added to the color, `.spa` files.
     Add,
-    Additional(Vec4),
+    /// A sub-texture: drawn like a second color texture, multiplied with the color, at the first
+    /// additional UV of the vertices rather than a sphere map.
+    Additional,
 }
 
-#[derive(Debug)]
-struct Name {
-    local: PmxText,
-    universal: PmxText,
+/// Where the environment texture is sampled, see [`EnvironmentBlend::source`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EnvironmentSource {
+    /// Not sampled.
+    None,
+    /// At the view space normal mapped to `0..1`, with V pointing down.
+    SphereMap,
+    /// At the X and Y of an additional UV channel.
+    AdditionalUv(UvChannel),
+}
+
+impl EnvironmentBlend {
+    /// Where the mode samples the environment texture.
+    pub fn source(self) -> EnvironmentSource {
+        match self {
+            EnvironmentBlend::None => EnvironmentSource::None,
+            EnvironmentBlend::Multiply | EnvironmentBlend::Add => EnvironmentSource::SphereMap,
+            EnvironmentBlend::Additional => EnvironmentSource::AdditionalUv(UvChannel::Uv1),
+        }
+    }
+
+    /// The sphere map texture coordinates for a view space normal, `None` if the mode does not
+    /// sample a sphere map.
+    pub fn sphere_map_uv(self, view_normal: Vec3) -> Option<[f32; 2]> {
+        let [x, y, _]: [f32; 3] = to_array(view_normal);
+
+        (self.source() == EnvironmentSource::SphereMap).then_some([x * 0.5 + 0.5, -y * 0.5 + 0.5])
+    }
+
+    /// Combines a color with a sample of the environment texture as MMD does: multiplied or
+    /// added for the RGB channels, the alphas are multiplied in every mode but `None`.
+    pub fn apply(self, color: Vec4, sample: Vec4) -> Vec4 {
+        let [r, g, b, a]: [f32; 4] = to_array(color);
+        let [sr, sg, sb, sa]: [f32; 4] = to_array(sample);
+
+        match self {
+            EnvironmentBlend::None => color,
+            EnvironmentBlend::Multiply | EnvironmentBlend::Additional => {
+                from_array([r * sr, g * sg, b * sb, a * sa])
+            }
+            EnvironmentBlend::Add => from_array([r + sr, g + sg, b + sb, a * sa]),
+        }
+    }
+}
+
+impl TryFrom<u8> for EnvironmentBlend {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Multiply),
+            2 => Ok(Self::Add),
+            3 => Ok(Self::Additional),
+            _ => Err(Error::InvalidEnvironmentBlend),
+        }
+    }
+}
+
+impl From<&EnvironmentBlend> for u8 {
+    fn from(value: &EnvironmentBlend) -> Self {
+        match value {
+            EnvironmentBlend::None => 0,
+            EnvironmentBlend::Multiply => 1,
+            EnvironmentBlend::Add => 2,
+            EnvironmentBlend::Additional => 3,
+        }
+    }
 }
 
 impl Material {
-    pub fn parse(reader: &mut impl Read, index_size: u8, encoding: TextEncoding) -> Result<Self> {
-        let name = {
-            let local = PmxText::from_bytes(reader, encoding)?;
-            let universal = PmxText::from_bytes(reader, encoding)?;
-            Name { local, universal }
-        };
+    /// Creates an untextured white material that casts and receives shadows.
+    ///
+    /// The surface count is left at 0, it is set when the material is added to a
+    /// [`PmxBuilder`](crate::builder::PmxBuilder).
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: Name::new(name, "", TextEncoding::UTF16LE),
+            diffuse: from_array([1.0, 1.0, 1.0, 1.0]),
+            specular: from_array([0.0; 3]),
+            specular_strength: 0.0,
+            ambient: from_array([0.5; 3]),
+            flags: MaterialFlags::from_raw(
+                MaterialFlags::GROUND_SHADOW
+                    | MaterialFlags::DRAW_SHADOW
+                    | MaterialFlags::RECEIVE_SHADOW,
+            ),
+            edge_color: from_array([0.0, 0.0, 0.0, 1.0]),
+            edge_scale: 1.0,
+            tex_idx: TextureIndex::nil(),
+            env_idx: TextureIndex::nil(),
+            env_blend: EnvironmentBlend::None,
+            toon: Toon::Texture(TextureIndex::nil()),
+            meta: PmxText::new("", TextEncoding::UTF16LE),
+            surface_count: 0,
+        }
+    }
+
+    pub fn set_diffuse(&mut self, diffuse: Vec4) {
+        self.diffuse = diffuse;
+    }
+
+    pub fn set_specular(&mut self, specular: Vec3, strength: f32) {
+        self.specular = specular;
+        self.specular_strength = strength;
+    }
+
+    pub fn set_ambient(&mut self, ambient: Vec3) {
+        self.ambient = ambient;
+    }
+
+    pub fn set_flags(&mut self, flags: MaterialFlags) {
+        self.flags = flags;
+    }
+
+    pub fn set_texture_index(&mut self, index: TextureIndex) {
+        self.tex_idx = index;
+    }
+
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn diffuse(&self) -> Vec4 {
+        self.diffuse
+    }
+
+    pub fn specular(&self) -> Vec3 {
+        self.specular
+    }
+
+    pub fn specular_strength(&self) -> f32 {
+        self.specular_strength
+    }
+
+    pub fn ambient(&self) -> Vec3 {
+        self.ambient
+    }
+
+    pub fn flags(&self) -> MaterialFlags {
+        self.flags
+    }
+
+    pub fn edge_color(&self) -> Vec4 {
+        self.edge_color
+    }
+
+    pub fn edge_scale(&self) -> f32 {
+        self.edge_scale
+    }
+
+    pub fn texture_index(&self) -> &TextureIndex {
+        &self.tex_idx
+    }
+
+    pub fn environment_index(&self) -> &TextureIndex {
+        &self.env_idx
+    }
+
+    pub fn environment_blend(&self) -> &EnvironmentBlend {
+        &self.env_blend
+    }
+
+    /// The environment texture and how it is used, `None` if the blend mode is
+    /// [`EnvironmentBlend::None`] or there is no texture.
+    pub fn environment(&self) -> Option<(TextureIndex, EnvironmentBlend)> {
+        (self.env_blend != EnvironmentBlend::None && !self.env_idx.is_nil())
+            .then_some((self.env_idx, self.env_blend))
+    }
+
+    pub fn toon(&self) -> &Toon {
+        &self.toon
+    }
+
+    /// Free-form memo text.
+    pub fn meta(&self) -> &PmxText {
+        &self.meta
+    }
+
+    /// Amount of surfaces (not triangles) this material covers, materials cover the surfaces in order.
+    pub fn surface_count(&self) -> i32 {
+        self.surface_count
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let name = field("name", || Ok(Name::parse(reader, encoding, context)?))?;
+
+        let diffuse: Vec4 = field("diffuse", || Ok(vec_from_bytes!(Vec4, reader)))?;
+        let specular: Vec3 = field("specular", || Ok(vec_from_bytes!(Vec3, reader)))?;
+
+        let specular_strength = field("specular_strength", || Ok(read_f32(reader)?))?;
+
+        let ambient: Vec3 = field("ambient", || Ok(vec_from_bytes!(Vec3, reader)))?;
+
+        let flags = field("flags", || MaterialFlags::parse(reader))?;
+
+        let edge_color: Vec4 = field("edge_color", || Ok(vec_from_bytes!(Vec4, reader)))?;
+
+        let edge_scale = field("edge_scale", || Ok(read_f32(reader)?))?;
+
+        let tex_idx = field("tex_idx", || {
+            Ok(TextureIndex::parse(reader, index_size.try_into()?)?)
+        })?;
+
+        let env_idx = field("env_idx", || {
+            Ok(TextureIndex::parse(reader, index_size.try_into()?)?)
+        })?;
+
+        let env_blend = field("env_blend", || read_u8(reader)?.try_into())?;
+
+        let toon = field("toon", || Toon::parse(reader, index_size))?;
+
+        let meta = field("meta", || {
+            Ok(PmxText::from_bytes_with(reader, encoding, context)?)
+        })?;
+
+        let surface_count = field("surface_count", || Ok(read_i32(reader)?))?;
+
+        Ok(Self {
+            name,
+            diffuse,
+            specular,
+            specular_strength,
+            ambient,
+            flags,
+            edge_color,
+            edge_scale,
+            tex_idx,
+            env_idx,
+            env_blend,
+            toon,
+            meta,
+            surface_count,
+        })
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        self.name.write(writer, encoding)?;
+
+        vec_to_bytes!(Vec4, self.diffuse, writer);
+        vec_to_bytes!(Vec3, self.specular, writer);
+
+        write_f32(writer, self.specular_strength)?;
+
+        vec_to_bytes!(Vec3, self.ambient, writer);
+
+        self.flags.write(writer)?;
+
+        vec_to_bytes!(Vec4, self.edge_color, writer);
+
+        write_f32(writer, self.edge_scale)?;
+
+        self.tex_idx.write(writer, index_size.try_into()?)?;
+
+        self.env_idx.write(writer, index_size.try_into()?)?;
+
+        write_u8(writer, (&self.env_blend).into())?;
+
+        self.toon.write(writer, index_size)?;
+
+        self.meta.write(writer, encoding)?;
 
-        let diffuse: Vec4 = vec_from_bytes!(Vec4, reader);
-        let specular: Vec3 = vec_from_bytes!(Vec3, reader);
+        write_i32(writer, self.surface_count)?;
 
-        unimplemented!();
+        Ok(())
     }
 }
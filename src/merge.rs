@@ -0,0 +1,501 @@
+//! Combining two models into one.
+//!
+//! [`Pmx::merge`] appends every section of another model to this one, shifting the indices of
+//! the appended elements past the existing ones. Optionally textures with the same path and bones
+//! with the same name are shared instead of appended, which is what attaching an accessory to a
+//! model wants: the accessory's bones named like the model's bones follow them.
+//!
+//! The appended materials keep their own surfaces, which go right after the surfaces the
+//! existing materials cover. Material morph offsets of the other model that target every material
+//! are split into one offset per material of that model, so they keep leaving the existing
+//! materials alone. The special display frames, `Root` and the expressions, are combined by name,
+//! every other frame is appended.
+//!
+//! The header takes the newer version, the larger additional UV count with vertices padded with
+//! zeroes, and index sizes large enough for the combined sections.
+
+use std::collections::HashMap;
+
+use crate::{
+    bone::Tail,
+    display_frame::FrameEntry,
+    material::Toon,
+    morph::{MaterialOffset, Offsets},
+    pmx::Pmx,
+    soft_body::SoftBodies,
+    types::{
+        BoneIndex, IndexSize, MaterialIndex, MorphIndex, RigidBodyIndex, TextureIndex, VertexIndex,
+        from_array,
+    },
+};
+
+/// Settings for [`Pmx::merge`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeOptions {
+    pub(crate) share_textures: bool,
+    pub(crate) share_bones: bool,
+}
+
+impl MergeOptions {
+    /// Options that append every element.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn share_textures(&self) -> bool {
+        self.share_textures
+    }
+
+    /// Whether textures with a path already in the model reuse that texture.
+    pub fn set_share_textures(&mut self, share_textures: bool) {
+        self.share_textures = share_textures;
+    }
+
+    pub fn share_bones(&self) -> bool {
+        self.share_bones
+    }
+
+    /// Whether bones with a local name already in the model are replaced by that bone, the first
+    /// one if several share the name.
+    pub fn set_share_bones(&mut self, share_bones: bool) {
+        self.share_bones = share_bones;
+    }
+}
+
+/// Where the indices of the other model's elements go.
+struct Table {
+    map: Vec<i32>,
+    /// The length of the combined section.
+    total: usize,
+}
+
+impl Table {
+    /// Every element appended after `base` existing ones.
+    fn appended(base: usize, len: usize) -> Self {
+        Self {
+            map: (base..base + len).map(|i| i as i32).collect(),
+            total: base + len,
+        }
+    }
+
+    /// Elements whose key is already among `existing` map to it, the others are appended.
+    /// Returns which elements are appended along with the table.
+    fn shared<'a>(
+        existing: impl Iterator<Item = &'a str>,
+        keys: impl Iterator<Item = &'a str>,
+    ) -> (Self, Vec<bool>) {
+        let mut known = HashMap::new();
+        let mut total = 0;
+        for (index, key) in existing.enumerate() {
+            known.entry(key).or_insert(index);
+            total += 1;
+        }
+
+        let mut appended = Vec::new();
+        let map = keys
+            .map(|key| match known.get(key) {
+                Some(&index) => {
+                    appended.push(false);
+                    index as i32
+                }
+                None => {
+                    appended.push(true);
+                    total += 1;
+                    total as i32 - 1
+                }
+            })
+            .collect();
+
+        (Self { map, total }, appended)
+    }
+
+    fn get(&self, value: i32) -> i32 {
+        match usize::try_from(value) {
+            Err(_) => value,
+            Ok(i) if i < self.map.len() => self.map[i],
+            // references past the end stay past it
+            Ok(i) => (i - self.map.len() + self.total) as i32,
+        }
+    }
+
+    fn vertex(&self, index: &mut VertexIndex) {
+        *index = VertexIndex::new(self.get(index.value()));
+    }
+
+    fn texture(&self, index: &mut TextureIndex) {
+        *index = TextureIndex::new(self.get(index.value()));
+    }
+
+    fn material(&self, index: &mut MaterialIndex) {
+        *index = MaterialIndex::new(self.get(index.value()));
+    }
+
+    fn bone(&self, index: &mut BoneIndex) {
+        *index = BoneIndex::new(self.get(index.value()));
+    }
+
+    fn morph(&self, index: &mut MorphIndex) {
+        *index = MorphIndex::new(self.get(index.value()));
+    }
+
+    fn rigid_body(&self, index: &mut RigidBodyIndex) {
+        *index = RigidBodyIndex::new(self.get(index.value()));
+    }
+}
+
+impl Pmx {
+    /// Appends `other` to the model, see the [module docs](crate::merge).
+    pub fn merge(&mut self, mut other: Pmx, options: &MergeOptions) {
+        let vertices = Table::appended(self.vertices.inner.len(), other.vertices.inner.len());
+        let other_materials = other.materials.inner.len();
+        let materials = Table::appended(self.materials.inner.len(), other_materials);
+        let morphs = Table::appended(self.morphs.inner.len(), other.morphs.inner.len());
+        let rigid_bodies = Table::appended(
+            self.rigid_bodies.inner.len(),
+            other.rigid_bodies.inner.len(),
+        );
+
+        let (textures, new_textures) = if options.share_textures {
+            Table::shared(
+                self.textures.inner.iter().map(|t| t.path.as_str()),
+                other.textures.inner.iter().map(|t| t.path.as_str()),
+            )
+        } else {
+            let table = Table::appended(self.textures.inner.len(), other.textures.inner.len());
+            (table, vec![true; other.textures.inner.len()])
+        };
+        let (bones, new_bones) = if options.share_bones {
+            Table::shared(
+                self.bones.inner.iter().map(|b| b.name.local.as_str()),
+                other.bones.inner.iter().map(|b| b.name.local.as_str()),
+            )
+        } else {
+            let table = Table::appended(self.bones.inner.len(), other.bones.inner.len());
+            (table, vec![true; other.bones.inner.len()])
+        };
+
+        let additional =
+            (self.header.globals.vec4_additional).max(other.header.globals.vec4_additional);
+        let pad = |extra: &mut Option<Vec<_>>| {
+            if additional > 0 {
+                let extra = extra.get_or_insert_with(Vec::new);
+                extra.resize(additional as usize, from_array([0.0; 4]));
+            }
+        };
+        self.vertices
+            .inner
+            .iter_mut()
+            .for_each(|v| pad(&mut v.extra_vec4));
+        for mut vertex in other.vertices.inner {
+            pad(&mut vertex.extra_vec4);
+            for bone in vertex.weight_deform.bone_indices_mut() {
+                bones.bone(bone);
+            }
+            self.vertices.inner.push(vertex);
+        }
+
+        // the new surfaces go after the ones the existing materials cover
+        let covered: usize = (self.materials.inner.iter())
+            .map(|material| material.surface_count.max(0) as usize)
+            .sum();
+        let covered = covered.min(self.surfaces.inner.len());
+        let surfaces = other.surfaces.inner.into_iter().map(|mut surface| {
+            vertices.vertex(&mut surface.index);
+            surface
+        });
+        self.surfaces.inner.splice(covered..covered, surfaces);
+
+        let textures_kept = other.textures.inner.into_iter().zip(new_textures);
+        (self.textures.inner)
+            .extend(textures_kept.filter_map(|(texture, new)| new.then_some(texture)));
+
+        for mut material in other.materials.inner {
+            textures.texture(&mut material.tex_idx);
+            textures.texture(&mut material.env_idx);
+            if let Toon::Texture(index) = &mut material.toon {
+                textures.texture(index);
+            }
+            self.materials.inner.push(material);
+        }
+
+        for (mut bone, new) in other.bones.inner.into_iter().zip(new_bones) {
+            if !new {
+                continue;
+            }
+
+            bones.bone(&mut bone.parent);
+            if let Tail::Bone(tail) = &mut bone.tail {
+                bones.bone(tail);
+            }
+            if let Some(inherit) = &mut bone.inherit {
+                bones.bone(&mut inherit.parent);
+            }
+            if let Some(ik) = &mut bone.ik {
+                bones.bone(&mut ik.target);
+                ik.links
+                    .iter_mut()
+                    .for_each(|link| bones.bone(&mut link.bone));
+            }
+            self.bones.inner.push(bone);
+        }
+
+        for mut morph in other.morphs.inner {
+            match &mut morph.offsets {
+                Offsets::Group(offsets) => {
+                    offsets.iter_mut().for_each(|o| morphs.morph(&mut o.morph))
+                }
+                Offsets::Flip(offsets) => {
+                    offsets.iter_mut().for_each(|o| morphs.morph(&mut o.morph))
+                }
+                Offsets::Vertex(offsets) => offsets
+                    .iter_mut()
+                    .for_each(|o| vertices.vertex(&mut o.vertex)),
+                Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => offsets
+                    .iter_mut()
+                    .for_each(|o| vertices.vertex(&mut o.vertex)),
+                Offsets::Bone(offsets) => offsets.iter_mut().for_each(|o| bones.bone(&mut o.bone)),
+                Offsets::Impulse(offsets) => offsets
+                    .iter_mut()
+                    .for_each(|o| rigid_bodies.rigid_body(&mut o.rigid_body)),
+                Offsets::Material(offsets) => {
+                    *offsets = std::mem::take(offsets)
+                        .into_iter()
+                        .flat_map(|offset| split_material_offset(offset, other_materials))
+                        .map(|mut offset| {
+                            materials.material(&mut offset.material);
+                            offset
+                        })
+                        .collect();
+                }
+            }
+            self.morphs.inner.push(morph);
+        }
+
+        for mut frame in other.display_frames.inner {
+            for entry in &mut frame.entries {
+                match entry {
+                    FrameEntry::Bone(bone) => bones.bone(bone),
+                    FrameEntry::Morph(morph) => morphs.morph(morph),
+                }
+            }
+
+            let special = (self.display_frames.inner.iter_mut()).find(|f| {
+                f.special && frame.special && f.name.local.as_str() == frame.name.local.as_str()
+            });
+            match special {
+                Some(existing) => {
+                    for entry in frame.entries {
+                        if !existing.entries.iter().any(|e| same_entry(e, &entry)) {
+                            existing.entries.push(entry);
+                        }
+                    }
+                }
+                None => self.display_frames.inner.push(frame),
+            }
+        }
+
+        for mut rigid_body in other.rigid_bodies.inner {
+            bones.bone(&mut rigid_body.bone);
+            self.rigid_bodies.inner.push(rigid_body);
+        }
+
+        for mut joint in other.joints.inner {
+            rigid_bodies.rigid_body(&mut joint.rigid_body_a);
+            rigid_bodies.rigid_body(&mut joint.rigid_body_b);
+            self.joints.inner.push(joint);
+        }
+
+        if let Some(soft_bodies) = other.soft_bodies.take() {
+            let existing = self.soft_bodies.get_or_insert_with(|| SoftBodies {
+                len: 0,
+                inner: Vec::new(),
+            });
+            for mut soft_body in soft_bodies.inner {
+                materials.material(&mut soft_body.material);
+                for anchor in &mut soft_body.anchors {
+                    rigid_bodies.rigid_body(&mut anchor.rigid_body);
+                    vertices.vertex(&mut anchor.vertex);
+                }
+                soft_body
+                    .pinned_vertices
+                    .iter_mut()
+                    .for_each(|v| vertices.vertex(v));
+                existing.inner.push(soft_body);
+            }
+            existing.len = existing.inner.len();
+        }
+
+        self.vertices.size = self.vertices.inner.len();
+        self.surfaces.len = self.surfaces.inner.len();
+        self.textures.len = self.textures.inner.len();
+        self.materials.len = self.materials.inner.len();
+        self.bones.len = self.bones.inner.len();
+        self.morphs.len = self.morphs.inner.len();
+        self.display_frames.len = self.display_frames.inner.len();
+        self.rigid_bodies.len = self.rigid_bodies.inner.len();
+        self.joints.len = self.joints.inner.len();
+
+        if other.header.version > self.header.version {
+            self.header.version = other.header.version;
+            self.header.raw_version = other.header.version.as_f32();
+        }
+
+        let globals = &mut self.header.globals;
+        globals.vec4_additional = additional;
+        let grow = |size: &mut u8, count: usize, signed: bool| {
+            *size = (*size).max(IndexSize::smallest_for(count, signed));
+        };
+        grow(&mut globals.vert_idx_size, self.vertices.inner.len(), false);
+        grow(&mut globals.tex_idx_size, self.textures.inner.len(), true);
+        grow(
+            &mut globals.material_idx_size,
+            self.materials.inner.len(),
+            true,
+        );
+        grow(&mut globals.bone_idx_size, self.bones.inner.len(), true);
+        grow(&mut globals.morph_idx_size, self.morphs.inner.len(), true);
+        grow(
+            &mut globals.rb_idx_size,
+            self.rigid_bodies.inner.len(),
+            true,
+        );
+    }
+}
+
+/// `offset`, or one copy for each of `count` materials if it targets every material.
+fn split_material_offset(offset: MaterialOffset, count: usize) -> Vec<MaterialOffset> {
+    if !offset.material.is_nil() {
+        return vec![offset];
+    }
+
+    (0..count)
+        .map(|material| MaterialOffset {
+            material: MaterialIndex::new(material as i32),
+            ..offset
+        })
+        .collect()
+}
+
+fn same_entry(a: &FrameEntry, b: &FrameEntry) -> bool {
+    match (a, b) {
+        (FrameEntry::Bone(a), FrameEntry::Bone(b)) => a.value() == b.value(),
+        (FrameEntry::Morph(a), FrameEntry::Morph(b)) => a.value() == b.value(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bone::Bone,
+        builder::PmxBuilder,
+        material::Material,
+        morph::{BoneOffset, GroupOffset, Morph, Panel, VertexOffset},
+        types::{Name, TextEncoding},
+        vertex::{Vertex, WeightDeform},
+    };
+
+    /// A model with a triangle weighted to its last bone and the given morphs, written and parsed
+    /// again so the indices are the parsed kind.
+    fn model(bones: &[(&str, i32)], morphs: Vec<(&str, Offsets)>) -> Pmx {
+        let last = BoneIndex::new(bones.len() as i32 - 1);
+        let vertex = |x: f32| {
+            Vertex::new(
+                from_array([x, 0.0, 0.0]),
+                from_array([0.0, 0.0, 1.0]),
+                from_array([0.0; 2]),
+                WeightDeform::Bdef1 { index: last },
+            )
+        };
+
+        let mut builder = PmxBuilder::new()
+            .add_vertices([0.0, 1.0, 2.0].map(vertex))
+            .add_material(Material::new("body"), [[0, 1, 2]]);
+        for &(name, parent) in bones {
+            let mut bone = Bone::new(name, from_array([0.0; 3]));
+            bone.set_parent(BoneIndex::new(parent));
+            builder = builder.add_bone(bone);
+        }
+
+        let mut pmx = builder.build().unwrap();
+        pmx.morphs = (morphs.into_iter())
+            .map(|(name, offsets)| Morph {
+                name: Name::new(name, name, TextEncoding::UTF16LE),
+                panel: Panel::Other,
+                offsets,
+            })
+            .collect::<Vec<_>>()
+            .into();
+
+        let mut bytes = Vec::new();
+        pmx.write_to(&mut bytes).unwrap();
+        Pmx::parse(&mut &bytes[..]).unwrap()
+    }
+
+    fn vertex_offset(vertex: i32) -> VertexOffset {
+        VertexOffset {
+            vertex: VertexIndex::new(vertex),
+            translation: from_array([0.0, 0.0, 1.0]),
+        }
+    }
+
+    #[test]
+    fn merge_remaps_parents_weights_and_morphs() {
+        let mut pmx = model(
+            &[("センター", -1), ("頭", 0)],
+            vec![("base", Offsets::Vertex(vec![vertex_offset(0)]))],
+        );
+        let hat = model(
+            &[("頭", -1), ("hat", 0)],
+            vec![
+                ("tilt", Offsets::Vertex(vec![vertex_offset(2)])),
+                (
+                    "lift",
+                    Offsets::Bone(vec![BoneOffset {
+                        bone: BoneIndex::new(1),
+                        translation: from_array([0.0, 1.0, 0.0]),
+                        rotation: from_array([0.0, 0.0, 0.0, 1.0]),
+                    }]),
+                ),
+                (
+                    "both",
+                    Offsets::Group(vec![GroupOffset {
+                        morph: MorphIndex::new(0),
+                        weight: 1.0,
+                    }]),
+                ),
+            ],
+        );
+
+        let mut options = MergeOptions::new();
+        options.set_share_bones(true);
+        pmx.merge(hat, &options);
+
+        let bones = &pmx.bones.inner;
+        assert_eq!(bones.len(), 3);
+        assert_eq!(bones[2].parent, BoneIndex::new(1));
+
+        let weights: Vec<_> = (pmx.vertices.inner.iter())
+            .map(|v| v.weight_deform.bone_indices()[0])
+            .collect();
+        assert_eq!(weights, [1, 1, 1, 2, 2, 2].map(BoneIndex::new));
+
+        let surfaces: Vec<_> = pmx.surfaces.inner.iter().map(|s| s.index).collect();
+        assert_eq!(surfaces, [0, 1, 2, 3, 4, 5].map(VertexIndex::new));
+
+        let morphs = &pmx.morphs.inner;
+        let Offsets::Vertex(offsets) = &morphs[1].offsets else {
+            panic!("not a vertex morph");
+        };
+        assert_eq!(offsets[0].vertex, VertexIndex::new(5));
+        let Offsets::Bone(offsets) = &morphs[2].offsets else {
+            panic!("not a bone morph");
+        };
+        assert_eq!(offsets[0].bone, BoneIndex::new(2));
+        let Offsets::Group(offsets) = &morphs[3].offsets else {
+            panic!("not a group morph");
+        };
+        assert_eq!(offsets[0].morph, MorphIndex::new(1));
+    }
+}
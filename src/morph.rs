@@ -0,0 +1,743 @@
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+use crate::{
+    display_frame::FrameEntry,
+    pmx::{Globals, Pmx},
+    types::{
+        BoneIndex, IndexSize, MaterialIndex, MorphIndex, Name, ParseContext, RigidBodyIndex, Vec3,
+        Vec4, VertexIndex, read_f32, read_i32, read_u8, vec_from_bytes, vec_to_bytes, write_count,
+        write_f32, write_u8,
+    },
+    vertex::UvChannel,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Negative size encountered where positive expected")]
+    NegativeSize,
+    #[error(transparent)]
+    Type(#[from] crate::types::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid morph panel encountered")]
+    InvalidPanel,
+    #[error("Invalid morph type encountered")]
+    InvalidMorphType,
+    #[error("Invalid material morph operation encountered")]
+    InvalidMaterialOperation,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// How deep group and flip morphs may nest, a group of plain morphs being 1 deep. Morphs nested
+/// deeper are not evaluated, and [`Pmx::validate`] reports the groups reaching them.
+pub const MAX_MORPH_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub struct Morphs {
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<Morph>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(Morphs, Morph, len);
+
+impl From<Vec<Morph>> for Morphs {
+    fn from(inner: Vec<Morph>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
+}
+
+impl Morphs {
+    pub fn len(&self) -> usize {
+        let len = self.inner.len();
+        debug_assert!(self.len == len);
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn morphs(&self) -> &[Morph] {
+        &self.inner
+    }
+
+    pub fn parse(reader: &mut impl Read, globals: &Globals, context: ParseContext) -> Result<Self> {
+        let size = read_i32(reader)?;
+
+        if size.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // name, panel, type and offset count
+        let size = context.check_count(size as usize, 14)?;
+
+        let mut inner_vec = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let morph = Morph::parse(reader, globals, context)?;
+            inner_vec.push(morph);
+        }
+
+        Ok(Self {
+            len: size,
+            inner: inner_vec,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, globals: &Globals) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for morph in &self.inner {
+            morph.write(writer, globals)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The panel (category) a morph is shown under in MMD's facial operation panel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Panel {
+    /// Reserved for system use, not shown in any panel.
+    Hidden,
+    Eyebrow,
+    Eye,
+    Mouth,
+    Other,
+}
+
+impl Panel {
+    /// The panels MMD shows, in the order of their panel bytes.
+    pub const SHOWN: [Panel; 4] = [Panel::Eyebrow, Panel::Eye, Panel::Mouth, Panel::Other];
+}
+
+impl TryFrom<u8> for Panel {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Hidden),
+            1 => Ok(Self::Eyebrow),
+            2 => Ok(Self::Eye),
+            3 => Ok(Self::Mouth),
+            4 => Ok(Self::Other),
+            _ => Err(Error::InvalidPanel),
+        }
+    }
+}
+
+impl From<Panel> for u8 {
+    fn from(value: Panel) -> Self {
+        match value {
+            Panel::Hidden => 0,
+            Panel::Eyebrow => 1,
+            Panel::Eye => 2,
+            Panel::Mouth => 3,
+            Panel::Other => 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Morph {
+    pub(crate) name: Name,
+    pub(crate) panel: Panel,
+    pub(crate) offsets: Offsets,
+}
+
+impl Morph {
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn panel(&self) -> Panel {
+        self.panel
+    }
+
+    pub fn offsets(&self) -> &Offsets {
+        &self.offsets
+    }
+
+    pub fn parse(reader: &mut impl Read, globals: &Globals, context: ParseContext) -> Result<Self> {
+        let name = Name::parse(reader, globals.encoding, context)?;
+
+        let panel = read_u8(reader)?.try_into()?;
+
+        let typ = read_u8(reader)?;
+
+        let offsets = Offsets::parse(reader, typ, globals, context)?;
+
+        Ok(Self {
+            name,
+            panel,
+            offsets,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, globals: &Globals) -> Result<()> {
+        self.name.write(writer, globals.encoding)?;
+
+        write_u8(writer, self.panel.into())?;
+
+        write_u8(writer, self.offsets.typ())?;
+
+        self.offsets.write(writer, globals)?;
+
+        Ok(())
+    }
+}
+
+/// The offsets of a morph, the variant is decided by the morph type.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Offsets {
+    // ver 2.0
+    Group(Vec<GroupOffset>),
+    // ver 2.0
+    Vertex(Vec<VertexOffset>),
+    // ver 2.0
+    Bone(Vec<BoneOffset>),
+    // ver 2.0
+    Uv(Vec<UvOffset>),
+    /// Offsets for one of the additional UV channels, `0` being UV1 and `3` being UV4.
+    // ver 2.0
+    AdditionalUv(u8, Vec<UvOffset>),
+    // ver 2.0
+    Material(Vec<MaterialOffset>),
+    // ver 2.1
+    Flip(Vec<FlipOffset>),
+    // ver 2.1
+    Impulse(Vec<ImpulseOffset>),
+}
+
+impl Offsets {
+    pub fn parse(
+        reader: &mut impl Read,
+        typ: u8,
+        globals: &Globals,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let count = read_i32(reader)?;
+
+        if count.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // the smallest offsets are group and flip offsets with 1 byte indices
+        let count = context.check_count(count as usize, 5)?;
+
+        let vertex_size: IndexSize = globals.vert_idx_size.try_into()?;
+        let bone_size: IndexSize = globals.bone_idx_size.try_into()?;
+        let material_size: IndexSize = globals.material_idx_size.try_into()?;
+        let morph_size: IndexSize = globals.morph_idx_size.try_into()?;
+        let rb_size: IndexSize = globals.rb_idx_size.try_into()?;
+
+        let offsets = match typ {
+            0 => Offsets::Group(parse_n(reader, count, |r| {
+                GroupOffset::parse(r, morph_size)
+            })?),
+            1 => Offsets::Vertex(parse_n(reader, count, |r| {
+                VertexOffset::parse(r, vertex_size)
+            })?),
+            2 => Offsets::Bone(parse_n(reader, count, |r| BoneOffset::parse(r, bone_size))?),
+            3 => Offsets::Uv(parse_n(reader, count, |r| UvOffset::parse(r, vertex_size))?),
+            4..=7 => Offsets::AdditionalUv(
+                typ - 4,
+                parse_n(reader, count, |r| UvOffset::parse(r, vertex_size))?,
+            ),
+            8 => Offsets::Material(parse_n(reader, count, |r| {
+                MaterialOffset::parse(r, material_size)
+            })?),
+            9 => Offsets::Flip(parse_n(reader, count, |r| {
+                FlipOffset::parse(r, morph_size)
+            })?),
+            10 => Offsets::Impulse(parse_n(reader, count, |r| {
+                ImpulseOffset::parse(r, rb_size)
+            })?),
+            _ => Err(Error::InvalidMorphType)?,
+        };
+
+        Ok(offsets)
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Offsets::Group(v) => v.len(),
+            Offsets::Vertex(v) => v.len(),
+            Offsets::Bone(v) => v.len(),
+            Offsets::Uv(v) | Offsets::AdditionalUv(_, v) => v.len(),
+            Offsets::Material(v) => v.len(),
+            Offsets::Flip(v) => v.len(),
+            Offsets::Impulse(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The morphs referred to by group and flip offsets, nothing for other morphs.
+    pub fn referenced_morphs(&self) -> impl Iterator<Item = &MorphIndex> {
+        let (group, flip) = match self {
+            Offsets::Group(offsets) => (&offsets[..], &[][..]),
+            Offsets::Flip(offsets) => (&[][..], &offsets[..]),
+            _ => (&[][..], &[][..]),
+        };
+
+        (group.iter().map(|o| &o.morph)).chain(flip.iter().map(|o| &o.morph))
+    }
+
+    /// The additional UV channel of additional UV morphs.
+    pub fn uv_channel(&self) -> Option<UvChannel> {
+        match self {
+            Offsets::AdditionalUv(channel, _) => UvChannel::from_index(*channel),
+            _ => None,
+        }
+    }
+
+    /// The morph type byte as stored in the file.
+    pub fn typ(&self) -> u8 {
+        match self {
+            Offsets::Group(_) => 0,
+            Offsets::Vertex(_) => 1,
+            Offsets::Bone(_) => 2,
+            Offsets::Uv(_) => 3,
+            Offsets::AdditionalUv(channel, _) => 4 + channel,
+            Offsets::Material(_) => 8,
+            Offsets::Flip(_) => 9,
+            Offsets::Impulse(_) => 10,
+        }
+    }
+
+    /// Writes the offset count followed by the offsets, the type byte is written by the morph.
+    pub fn write(&self, writer: &mut impl Write, globals: &Globals) -> Result<()> {
+        write_count(writer, self.len())?;
+
+        let vertex_size: IndexSize = globals.vert_idx_size.try_into()?;
+        let bone_size: IndexSize = globals.bone_idx_size.try_into()?;
+        let material_size: IndexSize = globals.material_idx_size.try_into()?;
+        let morph_size: IndexSize = globals.morph_idx_size.try_into()?;
+        let rb_size: IndexSize = globals.rb_idx_size.try_into()?;
+
+        match self {
+            Offsets::Group(v) => v.iter().try_for_each(|o| o.write(writer, morph_size))?,
+            Offsets::Vertex(v) => v.iter().try_for_each(|o| o.write(writer, vertex_size))?,
+            Offsets::Bone(v) => v.iter().try_for_each(|o| o.write(writer, bone_size))?,
+            Offsets::Uv(v) | Offsets::AdditionalUv(_, v) => {
+                v.iter().try_for_each(|o| o.write(writer, vertex_size))?
+            }
+            Offsets::Material(v) => v.iter().try_for_each(|o| o.write(writer, material_size))?,
+            Offsets::Flip(v) => v.iter().try_for_each(|o| o.write(writer, morph_size))?,
+            Offsets::Impulse(v) => v.iter().try_for_each(|o| o.write(writer, rb_size))?,
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_n<R: Read, T>(
+    reader: &mut R,
+    count: usize,
+    mut parse: impl FnMut(&mut R) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut v = Vec::with_capacity(count);
+    for _ in 0..count {
+        v.push(parse(reader)?);
+    }
+    Ok(v)
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupOffset {
+    pub(crate) morph: MorphIndex,
+    pub(crate) weight: f32,
+}
+
+impl GroupOffset {
+    pub fn morph(&self) -> &MorphIndex {
+        &self.morph
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+        let morph = MorphIndex::parse(reader, size)?;
+        let weight = read_f32(reader)?;
+
+        Ok(Self { morph, weight })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.morph.write(writer, size)?;
+        write_f32(writer, self.weight)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VertexOffset {
+    pub(crate) vertex: VertexIndex,
+    pub(crate) translation: Vec3,
+}
+
+impl VertexOffset {
+    pub fn vertex(&self) -> &VertexIndex {
+        &self.vertex
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+        let vertex = VertexIndex::parse(reader, size)?;
+        let translation: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        Ok(Self {
+            vertex,
+            translation,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.vertex.write(writer, size)?;
+        vec_to_bytes!(Vec3, self.translation, writer);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoneOffset {
+    pub(crate) bone: BoneIndex,
+    pub(crate) translation: Vec3,
+    /// Rotation quaternion (XYZW).
+    pub(crate) rotation: Vec4,
+}
+
+impl BoneOffset {
+    pub fn bone(&self) -> &BoneIndex {
+        &self.bone
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    pub fn rotation(&self) -> Vec4 {
+        self.rotation
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+        let bone = BoneIndex::parse(reader, size)?;
+        let translation: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation: Vec4 = vec_from_bytes!(Vec4, reader);
+
+        Ok(Self {
+            bone,
+            translation,
+            rotation,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.bone.write(writer, size)?;
+        vec_to_bytes!(Vec3, self.translation, writer);
+        vec_to_bytes!(Vec4, self.rotation, writer);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UvOffset {
+    pub(crate) vertex: VertexIndex,
+    /// Only the first two components are used for the base UV channel.
+    pub(crate) offset: Vec4,
+}
+
+impl UvOffset {
+    pub fn vertex(&self) -> &VertexIndex {
+        &self.vertex
+    }
+
+    pub fn offset(&self) -> Vec4 {
+        self.offset
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+        let vertex = VertexIndex::parse(reader, size)?;
+        let offset: Vec4 = vec_from_bytes!(Vec4, reader);
+
+        Ok(Self { vertex, offset })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.vertex.write(writer, size)?;
+        vec_to_bytes!(Vec4, self.offset, writer);
+
+        Ok(())
+    }
+}
+
+/// How a material morph combines its values with the material's.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MaterialOperation {
+    Multiply,
+    Add,
+}
+
+impl TryFrom<u8> for MaterialOperation {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Multiply),
+            1 => Ok(Self::Add),
+            _ => Err(Error::InvalidMaterialOperation),
+        }
+    }
+}
+
+impl From<MaterialOperation> for u8 {
+    fn from(value: MaterialOperation) -> Self {
+        match value {
+            MaterialOperation::Multiply => 0,
+            MaterialOperation::Add => 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaterialOffset {
+    /// A nil index means the offset applies to every material.
+    pub(crate) material: MaterialIndex,
+    pub(crate) operation: MaterialOperation,
+    pub(crate) diffuse: Vec4,
+    pub(crate) specular: Vec3,
+    pub(crate) specular_strength: f32,
+    pub(crate) ambient: Vec3,
+    pub(crate) edge_color: Vec4,
+    pub(crate) edge_scale: f32,
+    pub(crate) texture_tint: Vec4,
+    pub(crate) environment_tint: Vec4,
+    pub(crate) toon_tint: Vec4,
+}
+
+impl MaterialOffset {
+    pub fn material(&self) -> &MaterialIndex {
+        &self.material
+    }
+
+    pub fn operation(&self) -> MaterialOperation {
+        self.operation
+    }
+
+    pub fn diffuse(&self) -> Vec4 {
+        self.diffuse
+    }
+
+    pub fn specular(&self) -> Vec3 {
+        self.specular
+    }
+
+    pub fn specular_strength(&self) -> f32 {
+        self.specular_strength
+    }
+
+    pub fn ambient(&self) -> Vec3 {
+        self.ambient
+    }
+
+    pub fn edge_color(&self) -> Vec4 {
+        self.edge_color
+    }
+
+    pub fn edge_scale(&self) -> f32 {
+        self.edge_scale
+    }
+
+    pub fn texture_tint(&self) -> Vec4 {
+        self.texture_tint
+    }
+
+    pub fn environment_tint(&self) -> Vec4 {
+        self.environment_tint
+    }
+
+    pub fn toon_tint(&self) -> Vec4 {
+        self.toon_tint
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+        let material = MaterialIndex::parse(reader, size)?;
+        let operation = read_u8(reader)?.try_into()?;
+        let diffuse: Vec4 = vec_from_bytes!(Vec4, reader);
+        let specular: Vec3 = vec_from_bytes!(Vec3, reader);
+        let specular_strength = read_f32(reader)?;
+        let ambient: Vec3 = vec_from_bytes!(Vec3, reader);
+        let edge_color: Vec4 = vec_from_bytes!(Vec4, reader);
+        let edge_scale = read_f32(reader)?;
+        let texture_tint: Vec4 = vec_from_bytes!(Vec4, reader);
+        let environment_tint: Vec4 = vec_from_bytes!(Vec4, reader);
+        let toon_tint: Vec4 = vec_from_bytes!(Vec4, reader);
+
+        Ok(Self {
+            material,
+            operation,
+            diffuse,
+            specular,
+            specular_strength,
+            ambient,
+            edge_color,
+            edge_scale,
+            texture_tint,
+            environment_tint,
+            toon_tint,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.material.write(writer, size)?;
+        write_u8(writer, self.operation.into())?;
+        vec_to_bytes!(Vec4, self.diffuse, writer);
+        vec_to_bytes!(Vec3, self.specular, writer);
+        write_f32(writer, self.specular_strength)?;
+        vec_to_bytes!(Vec3, self.ambient, writer);
+        vec_to_bytes!(Vec4, self.edge_color, writer);
+        write_f32(writer, self.edge_scale)?;
+        vec_to_bytes!(Vec4, self.texture_tint, writer);
+        vec_to_bytes!(Vec4, self.environment_tint, writer);
+        vec_to_bytes!(Vec4, self.toon_tint, writer);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlipOffset {
+    pub(crate) morph: MorphIndex,
+    pub(crate) weight: f32,
+}
+
+impl FlipOffset {
+    pub fn morph(&self) -> &MorphIndex {
+        &self.morph
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+        let morph = MorphIndex::parse(reader, size)?;
+        let weight = read_f32(reader)?;
+
+        Ok(Self { morph, weight })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.morph.write(writer, size)?;
+        write_f32(writer, self.weight)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImpulseOffset {
+    pub(crate) rigid_body: RigidBodyIndex,
+    /// Whether velocity and torque are in the rigid body's local space.
+    pub(crate) local: bool,
+    pub(crate) velocity: Vec3,
+    pub(crate) torque: Vec3,
+}
+
+impl ImpulseOffset {
+    pub fn rigid_body(&self) -> &RigidBodyIndex {
+        &self.rigid_body
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    pub fn torque(&self) -> Vec3 {
+        self.torque
+    }
+
+    pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+        let rigid_body = RigidBodyIndex::parse(reader, size)?;
+        let local = read_u8(reader)? != 0;
+        let velocity: Vec3 = vec_from_bytes!(Vec3, reader);
+        let torque: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        Ok(Self {
+            rigid_body,
+            local,
+            velocity,
+            torque,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        self.rigid_body.write(writer, size)?;
+        write_u8(writer, self.local as u8)?;
+        vec_to_bytes!(Vec3, self.velocity, writer);
+        vec_to_bytes!(Vec3, self.torque, writer);
+
+        Ok(())
+    }
+}
+
+impl Pmx {
+    /// The morphs of each panel MMD shows, in the order of [`Panel::SHOWN`].
+    ///
+    /// Within a panel the morphs are in the order of the display frames. Like MMD, morphs in no
+    /// display frame are left out, as are morphs of the hidden panel.
+    pub fn morphs_by_panel(&self) -> [(Panel, Vec<MorphIndex>); 4] {
+        let mut panels = Panel::SHOWN.map(|panel| (panel, Vec::new()));
+
+        let entries = self.display_frames.inner.iter().flat_map(|f| &f.entries);
+        for entry in entries {
+            let FrameEntry::Morph(index) = entry else {
+                continue;
+            };
+            let Some(morph) = index.get(&self.morphs.inner) else {
+                continue;
+            };
+
+            if let Some((_, morphs)) = panels.iter_mut().find(|(p, _)| *p == morph.panel)
+                && !morphs.contains(index)
+            {
+                morphs.push(*index);
+            }
+        }
+
+        panels
+    }
+}
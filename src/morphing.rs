@@ -0,0 +1,619 @@
+//! Evaluating morphs.
+//!
+//! [`Pmx::apply_morphs`] takes a set of morph weights and returns the vertex data, material
+//! parameters and bone pose they produce, leaving the model itself untouched, and
+//! [`Pmx::effective_material`] only the parameters of one material. Group and flip morphs are
+//! expanded into the morphs they refer to first, a group that ends up containing itself is only
+//! expanded once and morphs nested deeper than [`MAX_MORPH_DEPTH`] are skipped.
+//!
+//! Impulse morphs act on the physics simulation, their offsets are passed on as [`Impulse`]s
+//! scaled by the weight of the morph, which the physics scene of the `rapier` feature applies to
+//! its bodies.
+//!
+//! [`Pmx::bake_morph`] evaluates a single morph the same way and writes the result into the model
+//! itself. Bone morphs are baked by skinning the mesh with their pose and moving the bones, rigid
+//! bodies and joints along, so the posed shape becomes the new bind pose. The offsets of other
+//! morphs are kept as they are, vertex offsets of rotated parts point the old way. Texture, sphere
+//! and toon tints have no counterpart in the material and are not baked.
+
+use glam::{EulerRot, Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+
+use crate::{
+    bone::Tail,
+    material::Material,
+    morph::{MAX_MORPH_DEPTH, MaterialOffset, MaterialOperation, Offsets},
+    pmx::Pmx,
+    skin::{self, Pose},
+    types::MorphIndex,
+    vertex::WeightDeform,
+};
+
+/// The result of [`Pmx::apply_morphs`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Morphed {
+    pub(crate) positions: Vec<Vec3>,
+    pub(crate) uvs: Vec<Vec2>,
+    pub(crate) additional_uvs: Vec<Vec<Vec4>>,
+    pub(crate) materials: Vec<MaterialState>,
+    pub(crate) pose: Pose,
+    pub(crate) impulses: Vec<Impulse>,
+}
+
+impl Morphed {
+    /// The position of each vertex.
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// The UV of each vertex.
+    pub fn uvs(&self) -> &[Vec2] {
+        &self.uvs
+    }
+
+    /// The additional vec4s of each vertex.
+    pub fn additional_uvs(&self) -> &[Vec<Vec4>] {
+        &self.additional_uvs
+    }
+
+    /// The parameters of each material.
+    pub fn materials(&self) -> &[MaterialState] {
+        &self.materials
+    }
+
+    /// The bone translations and rotations of the bone morphs.
+    pub fn pose(&self) -> &Pose {
+        &self.pose
+    }
+
+    pub fn into_pose(self) -> Pose {
+        self.pose
+    }
+
+    /// The impulses of the impulse morphs, in morph order.
+    pub fn impulses(&self) -> &[Impulse] {
+        &self.impulses
+    }
+}
+
+/// A push given to a rigid body by an impulse morph, already scaled by the morph's weight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Impulse {
+    pub(crate) rigid_body: usize,
+    pub(crate) local: bool,
+    pub(crate) velocity: Vec3,
+    pub(crate) torque: Vec3,
+}
+
+impl Impulse {
+    /// The index of the rigid body.
+    pub fn rigid_body(&self) -> usize {
+        self.rigid_body
+    }
+
+    /// Whether the velocity and torque are in the space of the rigid body rather than the
+    /// model's.
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+
+    /// The change in velocity.
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    /// The angular impulse.
+    pub fn torque(&self) -> Vec3 {
+        self.torque
+    }
+}
+
+/// The parameters of a material after material morphs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialState {
+    pub(crate) diffuse: Vec4,
+    pub(crate) specular: Vec3,
+    pub(crate) specular_strength: f32,
+    pub(crate) ambient: Vec3,
+    pub(crate) edge_color: Vec4,
+    pub(crate) edge_scale: f32,
+    pub(crate) texture_tint: Tint,
+    pub(crate) environment_tint: Tint,
+    pub(crate) toon_tint: Tint,
+}
+
+impl MaterialState {
+    pub fn diffuse(&self) -> Vec4 {
+        self.diffuse
+    }
+
+    pub fn specular(&self) -> Vec3 {
+        self.specular
+    }
+
+    pub fn specular_strength(&self) -> f32 {
+        self.specular_strength
+    }
+
+    pub fn ambient(&self) -> Vec3 {
+        self.ambient
+    }
+
+    pub fn edge_color(&self) -> Vec4 {
+        self.edge_color
+    }
+
+    pub fn edge_scale(&self) -> f32 {
+        self.edge_scale
+    }
+
+    pub fn texture_tint(&self) -> Tint {
+        self.texture_tint
+    }
+
+    pub fn environment_tint(&self) -> Tint {
+        self.environment_tint
+    }
+
+    pub fn toon_tint(&self) -> Tint {
+        self.toon_tint
+    }
+}
+
+/// How a texture's colour is changed by material morphs, first multiplied and then added to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tint {
+    pub(crate) multiply: Vec4,
+    pub(crate) add: Vec4,
+}
+
+impl Tint {
+    pub fn multiply(&self) -> Vec4 {
+        self.multiply
+    }
+
+    pub fn add(&self) -> Vec4 {
+        self.add
+    }
+
+    /// Tints a colour sampled from the texture.
+    pub fn apply(&self, color: Vec4) -> Vec4 {
+        color * self.multiply + self.add
+    }
+}
+
+/// Every value a material offset can change, used for both the multiplied and added parts.
+#[derive(Clone, Copy)]
+struct MaterialValues {
+    diffuse: Vec4,
+    specular: Vec3,
+    specular_strength: f32,
+    ambient: Vec3,
+    edge_color: Vec4,
+    edge_scale: f32,
+    texture: Vec4,
+    environment: Vec4,
+    toon: Vec4,
+}
+
+impl MaterialValues {
+    fn splat(value: f32) -> Self {
+        Self {
+            diffuse: Vec4::splat(value),
+            specular: Vec3::splat(value),
+            specular_strength: value,
+            ambient: Vec3::splat(value),
+            edge_color: Vec4::splat(value),
+            edge_scale: value,
+            texture: Vec4::splat(value),
+            environment: Vec4::splat(value),
+            toon: Vec4::splat(value),
+        }
+    }
+
+    fn from_offset(offset: &MaterialOffset) -> Self {
+        Self {
+            diffuse: offset.diffuse,
+            specular: offset.specular,
+            specular_strength: offset.specular_strength,
+            ambient: offset.ambient,
+            edge_color: offset.edge_color,
+            edge_scale: offset.edge_scale,
+            texture: offset.texture_tint,
+            environment: offset.environment_tint,
+            toon: offset.toon_tint,
+        }
+    }
+
+    /// Applies `f` to each pair of fields, with the components of the vectors as separate calls.
+    fn zip_with(self, other: Self, f: impl Fn(f32, f32) -> f32) -> Self {
+        let v3 = |a: Vec3, b: Vec3| Vec3::from_array([0, 1, 2].map(|i| f(a[i], b[i])));
+        let v4 = |a: Vec4, b: Vec4| Vec4::from_array([0, 1, 2, 3].map(|i| f(a[i], b[i])));
+
+        Self {
+            diffuse: v4(self.diffuse, other.diffuse),
+            specular: v3(self.specular, other.specular),
+            specular_strength: f(self.specular_strength, other.specular_strength),
+            ambient: v3(self.ambient, other.ambient),
+            edge_color: v4(self.edge_color, other.edge_color),
+            edge_scale: f(self.edge_scale, other.edge_scale),
+            texture: v4(self.texture, other.texture),
+            environment: v4(self.environment, other.environment),
+            toon: v4(self.toon, other.toon),
+        }
+    }
+}
+
+/// The multiplied and added parts of the material morphs on one material.
+#[derive(Clone, Copy)]
+struct MaterialMorph {
+    multiply: MaterialValues,
+    add: MaterialValues,
+}
+
+impl MaterialMorph {
+    fn apply(&mut self, offset: &MaterialOffset, weight: f32) {
+        let values = MaterialValues::from_offset(offset);
+
+        match offset.operation {
+            // blends from leaving the value as it is towards multiplying it by the offset
+            MaterialOperation::Multiply => {
+                self.multiply = self
+                    .multiply
+                    .zip_with(values, |m, v| m * (1.0 + (v - 1.0) * weight));
+            }
+            MaterialOperation::Add => {
+                self.add = self.add.zip_with(values, |a, v| a + v * weight);
+            }
+        }
+    }
+
+    fn state(&self, material: &Material) -> MaterialState {
+        let (m, a) = (&self.multiply, &self.add);
+
+        MaterialState {
+            diffuse: material.diffuse * m.diffuse + a.diffuse,
+            specular: material.specular * m.specular + a.specular,
+            specular_strength: material.specular_strength * m.specular_strength
+                + a.specular_strength,
+            ambient: material.ambient * m.ambient + a.ambient,
+            edge_color: material.edge_color * m.edge_color + a.edge_color,
+            edge_scale: material.edge_scale * m.edge_scale + a.edge_scale,
+            texture_tint: Tint {
+                multiply: m.texture,
+                add: a.texture,
+            },
+            environment_tint: Tint {
+                multiply: m.environment,
+                add: a.environment,
+            },
+            toon_tint: Tint {
+                multiply: m.toon,
+                add: a.toon,
+            },
+        }
+    }
+}
+
+impl Pmx {
+    /// Evaluates the given morphs at the given weights, see the [module docs](self).
+    ///
+    /// A morph listed more than once, directly or through groups, gets the sum of its weights.
+    /// Indices that do not refer to a morph are ignored.
+    pub fn apply_morphs(&self, weights: &[(MorphIndex, f32)]) -> Morphed {
+        let totals = self.morph_totals(weights);
+
+        let vertices = &self.vertices.inner;
+        let mut positions: Vec<Vec3> = vertices.iter().map(|v| v.pos).collect();
+        let mut uvs: Vec<Vec2> = vertices.iter().map(|v| v.uv).collect();
+        let mut additional_uvs: Vec<Vec<Vec4>> = vertices
+            .iter()
+            .map(|v| v.additional_vec4s().to_vec())
+            .collect();
+
+        let unchanged = MaterialMorph {
+            multiply: MaterialValues::splat(1.0),
+            add: MaterialValues::splat(0.0),
+        };
+        let mut material_morphs = vec![unchanged; self.materials.inner.len()];
+
+        let mut pose = Pose::new();
+        let mut impulses = Vec::new();
+
+        for (morph, &weight) in self.morphs.inner.iter().zip(&totals) {
+            if weight == 0.0 {
+                continue;
+            }
+
+            match &morph.offsets {
+                Offsets::Vertex(offsets) => {
+                    for offset in offsets {
+                        if let Some(position) =
+                            offset.vertex.as_usize().and_then(|i| positions.get_mut(i))
+                        {
+                            *position += offset.translation * weight;
+                        }
+                    }
+                }
+                Offsets::Uv(offsets) => {
+                    for offset in offsets {
+                        if let Some(uv) = offset.vertex.as_usize().and_then(|i| uvs.get_mut(i)) {
+                            *uv += offset.offset.truncate().truncate() * weight;
+                        }
+                    }
+                }
+                Offsets::AdditionalUv(channel, offsets) => {
+                    for offset in offsets {
+                        if let Some(uv) = offset
+                            .vertex
+                            .as_usize()
+                            .and_then(|i| additional_uvs.get_mut(i))
+                            .and_then(|uvs| uvs.get_mut(*channel as usize))
+                        {
+                            *uv += offset.offset * weight;
+                        }
+                    }
+                }
+                Offsets::Bone(offsets) => {
+                    let bones = self.bones.inner.len();
+                    for offset in offsets {
+                        let Some(bone) = offset.bone.as_usize().filter(|&b| b < bones) else {
+                            continue;
+                        };
+
+                        let rotation = Quat::from_vec4(offset.rotation).normalize();
+                        let rotation = skin::scale_rotation(rotation, weight);
+
+                        pose.set_translation(
+                            bone,
+                            pose.translation(bone) + offset.translation * weight,
+                        );
+                        pose.set_rotation(bone, pose.rotation(bone) * rotation);
+                    }
+                }
+                Offsets::Material(offsets) => {
+                    for offset in offsets {
+                        // nil applies to every material
+                        match offset.material.as_usize() {
+                            Some(material) => {
+                                if let Some(state) = material_morphs.get_mut(material) {
+                                    state.apply(offset, weight);
+                                }
+                            }
+                            None => material_morphs
+                                .iter_mut()
+                                .for_each(|state| state.apply(offset, weight)),
+                        }
+                    }
+                }
+                Offsets::Impulse(offsets) => {
+                    let bodies = self.rigid_bodies.inner.len();
+                    impulses.extend(offsets.iter().filter_map(|offset| {
+                        Some(Impulse {
+                            rigid_body: offset.rigid_body.as_usize().filter(|&b| b < bodies)?,
+                            local: offset.local,
+                            velocity: offset.velocity * weight,
+                            torque: offset.torque * weight,
+                        })
+                    }));
+                }
+                Offsets::Group(_) | Offsets::Flip(_) => {}
+            }
+        }
+
+        let materials = self
+            .materials
+            .inner
+            .iter()
+            .zip(&material_morphs)
+            .map(|(material, morph)| morph.state(material))
+            .collect();
+
+        Morphed {
+            positions,
+            uvs,
+            additional_uvs,
+            materials,
+            pose,
+            impulses,
+        }
+    }
+
+    /// The parameters of one material after the material morphs among `weights`, evaluated like
+    /// [`apply_morphs`](Pmx::apply_morphs) does without touching the vertices or bones. `None` if
+    /// there is no such material.
+    ///
+    /// Textures are not changed by morphs, the tints in the result say how to change the colours
+    /// sampled from them.
+    pub fn effective_material(
+        &self,
+        index: usize,
+        weights: &[(MorphIndex, f32)],
+    ) -> Option<MaterialState> {
+        let material = self.materials.inner.get(index)?;
+        let totals = self.morph_totals(weights);
+
+        let mut state = MaterialMorph {
+            multiply: MaterialValues::splat(1.0),
+            add: MaterialValues::splat(0.0),
+        };
+        for (morph, &weight) in self.morphs.inner.iter().zip(&totals) {
+            if let Offsets::Material(offsets) = &morph.offsets
+                && weight != 0.0
+            {
+                // nil applies to every material
+                offsets
+                    .iter()
+                    .filter(|o| o.material.as_usize().is_none_or(|m| m == index))
+                    .for_each(|offset| state.apply(offset, weight));
+            }
+        }
+
+        Some(state.state(material))
+    }
+
+    /// The total weight of every morph, with groups and flips expanded.
+    fn morph_totals(&self, weights: &[(MorphIndex, f32)]) -> Vec<f32> {
+        let mut totals = vec![0.0; self.morphs.inner.len()];
+        let mut expanding = Vec::new();
+
+        for &(morph, weight) in weights {
+            self.expand_morph(morph, weight, &mut expanding, &mut totals);
+        }
+
+        totals
+    }
+
+    /// Applies `morph` at `weight` to the model permanently, see the [module docs](self).
+    ///
+    /// With `remove` the morph is removed afterwards like [`remove_morph`](Pmx::remove_morph)
+    /// does. Returns false if there is no such morph.
+    pub fn bake_morph(&mut self, morph: MorphIndex, weight: f32, remove: bool) -> bool {
+        let Some(index) = morph.as_usize().filter(|&i| i < self.morphs.inner.len()) else {
+            return false;
+        };
+
+        let morphed = self.apply_morphs(&[(morph, weight)]);
+
+        let vertices = self.vertices.inner.iter_mut().zip(morphed.positions);
+        let uvs = morphed.uvs.into_iter().zip(morphed.additional_uvs);
+        for ((vertex, position), (uv, additional)) in vertices.zip(uvs) {
+            vertex.pos = position;
+            vertex.uv = uv;
+            if let Some(extra) = &mut vertex.extra_vec4 {
+                *extra = additional;
+            }
+        }
+
+        for (material, state) in self.materials.inner.iter_mut().zip(morphed.materials) {
+            material.diffuse = state.diffuse;
+            material.specular = state.specular;
+            material.specular_strength = state.specular_strength;
+            material.ambient = state.ambient;
+            material.edge_color = state.edge_color;
+            material.edge_scale = state.edge_scale;
+        }
+
+        if !morphed.pose.is_empty() {
+            self.bake_pose(&morphed.pose);
+        }
+
+        if remove {
+            self.remove_morph(index);
+        }
+
+        true
+    }
+
+    /// Makes `pose` the bind pose, moving the vertices, bones and physics along.
+    fn bake_pose(&mut self, pose: &Pose) {
+        let skeleton = self.skeleton();
+        let world = skeleton.pose_transforms(pose);
+        let matrices = skeleton.skinning_matrices(pose);
+        let positions = self.skin(pose);
+        let matrix = |bone: i32| {
+            (usize::try_from(bone).ok())
+                .and_then(|bone| matrices.get(bone))
+                .copied()
+                .unwrap_or(Mat4::IDENTITY)
+        };
+
+        for (vertex, position) in self.vertices.inner.iter_mut().zip(positions) {
+            // the weighted blend of the bone matrices, for what is not a position
+            let (indices, weights) = vertex.weight_deform.bone_influences();
+            let total: f32 = weights.iter().sum();
+            let blend = if total > 0.0 {
+                (indices.into_iter().zip(weights)).fold(Mat4::ZERO, |sum, (bone, w)| {
+                    sum + matrix(bone) * (w / total)
+                })
+            } else {
+                Mat4::IDENTITY
+            };
+
+            vertex.pos = position;
+            vertex.normal = (Mat3::from_mat4(blend) * vertex.normal).normalize_or(vertex.normal);
+            if let WeightDeform::Sdef { c, r0, r1, .. } = &mut vertex.weight_deform {
+                *c = blend.transform_point3(*c);
+                *r0 = blend.transform_point3(*r0);
+                *r1 = blend.transform_point3(*r1);
+            }
+        }
+
+        for (index, bone) in self.bones.inner.iter_mut().enumerate() {
+            let rotation = Mat3::from_mat4(matrix(index as i32));
+            bone.position = world[index].transform_point3(Vec3::ZERO);
+
+            if let Tail::Position(offset) = &mut bone.tail {
+                *offset = rotation * *offset;
+            }
+            if let Some(axis) = &mut bone.fixed_axis {
+                *axis = rotation * *axis;
+            }
+            if let Some(axes) = &mut bone.local_axes {
+                axes.x = rotation * axes.x;
+                axes.z = rotation * axes.z;
+            }
+        }
+
+        // bodies and joints follow the bone they are attached to
+        let frame = |matrix: Mat4, position: &mut Vec3, rotation: &mut Vec3| {
+            let (_, turn, _) = matrix.to_scale_rotation_translation();
+            let old = Quat::from_euler(EulerRot::YXZ, rotation.y, rotation.x, rotation.z);
+            let (y, x, z) = (turn * old).to_euler(EulerRot::YXZ);
+
+            *position = matrix.transform_point3(*position);
+            *rotation = Vec3::new(x, y, z);
+        };
+
+        for body in &mut self.rigid_bodies.inner {
+            frame(
+                matrix(body.bone.value()),
+                &mut body.position,
+                &mut body.rotation,
+            );
+        }
+
+        for joint in &mut self.joints.inner {
+            let bone = (joint.rigid_body_a.get(&self.rigid_bodies.inner))
+                .map_or(-1, |body| body.bone.value());
+            frame(matrix(bone), &mut joint.position, &mut joint.rotation);
+        }
+    }
+
+    /// Adds `weight` to the total of `morph`, or to the morphs it refers to for group and flip
+    /// morphs. `expanding` holds the groups currently being expanded.
+    fn expand_morph(
+        &self,
+        morph: MorphIndex,
+        weight: f32,
+        expanding: &mut Vec<usize>,
+        totals: &mut [f32],
+    ) {
+        let Some(index) = morph.as_usize().filter(|&i| i < totals.len()) else {
+            return;
+        };
+        if weight == 0.0 || expanding.len() > MAX_MORPH_DEPTH || expanding.contains(&index) {
+            return;
+        }
+
+        expanding.push(index);
+
+        match &self.morphs.inner[index].offsets {
+            Offsets::Group(offsets) => {
+                for offset in offsets {
+                    self.expand_morph(offset.morph, weight * offset.weight, expanding, totals);
+                }
+            }
+            // the weight picks one of the offsets, which is applied at its own weight
+            Offsets::Flip(offsets) => {
+                if !offsets.is_empty() && weight > 0.0 {
+                    let pick = ((weight * offsets.len() as f32) as usize).min(offsets.len() - 1);
+                    let offset = &offsets[pick];
+                    self.expand_morph(offset.morph, offset.weight, expanding, totals);
+                }
+            }
+            _ => totals[index] += weight,
+        }
+
+        expanding.pop();
+    }
+}
@@ -0,0 +1,94 @@
+//! Looking up bones and morphs by name.
+//!
+//! Motions, poses and scripts refer to bones and morphs by name. [`Pmx::bone_by_name`] and
+//! [`Pmx::morph_by_name`] search the model once, [`Pmx::name_index`] builds hash maps for many
+//! lookups. The index borrows the model, so it can not go stale while the model changes.
+//!
+//! Both local and universal names are matched, local names first, so a bone whose universal name
+//! is another bone's local name is not found by it. Of several elements with the same name the
+//! first one is found, as MMD does. Empty names never match.
+
+use std::collections::HashMap;
+
+use crate::{
+    pmx::Pmx,
+    types::{BoneIndex, MorphIndex, Name},
+};
+
+/// Name to index maps of the bones and morphs of a model, see [`Pmx::name_index`].
+#[derive(Debug, Clone)]
+pub struct NameIndex<'a> {
+    bones: Names<'a>,
+    morphs: Names<'a>,
+}
+
+impl NameIndex<'_> {
+    /// The bone with the local or universal name.
+    pub fn bone(&self, name: &str) -> Option<BoneIndex> {
+        self.bones.get(name).map(|i| BoneIndex::new(i as i32))
+    }
+
+    /// The morph with the local or universal name.
+    pub fn morph(&self, name: &str) -> Option<MorphIndex> {
+        self.morphs.get(name).map(|i| MorphIndex::new(i as i32))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Names<'a> {
+    local: HashMap<&'a str, usize>,
+    universal: HashMap<&'a str, usize>,
+}
+
+impl<'a> Names<'a> {
+    fn new(names: impl Iterator<Item = &'a Name>) -> Self {
+        let mut local = HashMap::new();
+        let mut universal = HashMap::new();
+
+        for (i, name) in names.enumerate() {
+            for (map, name) in [(&mut local, &name.local), (&mut universal, &name.universal)] {
+                if !name.as_str().is_empty() {
+                    map.entry(name.as_str()).or_insert(i);
+                }
+            }
+        }
+
+        Self { local, universal }
+    }
+
+    fn get(&self, name: &str) -> Option<usize> {
+        (self.local.get(name))
+            .or_else(|| self.universal.get(name))
+            .copied()
+    }
+}
+
+impl Pmx {
+    /// Builds the name maps of the bones and morphs, see the [module docs](crate::names).
+    pub fn name_index(&self) -> NameIndex<'_> {
+        NameIndex {
+            bones: Names::new(self.bones.inner.iter().map(|bone| &bone.name)),
+            morphs: Names::new(self.morphs.inner.iter().map(|morph| &morph.name)),
+        }
+    }
+
+    /// The bone with the local or universal name, see the [module docs](crate::names).
+    pub fn bone_by_name(&self, name: &str) -> Option<BoneIndex> {
+        find(self.bones.inner.iter().map(|bone| &bone.name), name).map(|i| BoneIndex::new(i as i32))
+    }
+
+    /// The morph with the local or universal name, see the [module docs](crate::names).
+    pub fn morph_by_name(&self, name: &str) -> Option<MorphIndex> {
+        find(self.morphs.inner.iter().map(|morph| &morph.name), name)
+            .map(|i| MorphIndex::new(i as i32))
+    }
+}
+
+fn find<'a>(names: impl Iterator<Item = &'a Name> + Clone, name: &str) -> Option<usize> {
+    if name.is_empty() {
+        return None;
+    }
+
+    (names.clone().position(|n| n.local.as_str() == name))
+        .or_else(|| names.clone().position(|n| n.universal.as_str() == name))
+}
@@ -0,0 +1,158 @@
+//! Rebuilding vertex normals from the triangles.
+//!
+//! A vertex has a single normal, so it is always smoothed over every triangle it is a corner of.
+//! Models split vertices where their UVs or normals differ, the smoothing angle decides across
+//! which of those splits the normals are smoothed. The triangles of other vertices at exactly the
+//! same position are included when their normal is within the smoothing angle of the vertex's own
+//! triangles, so UV seams on a curved surface get smooth and creases modeled as split vertices stay
+//! hard.
+//!
+//! Triangles are weighted by their area. Vertices that no triangle in scope refers to, or whose
+//! triangles are degenerate, keep their normal.
+
+use std::collections::HashMap;
+
+use crate::{
+    pmx::Pmx,
+    types::{from_array, to_array},
+};
+
+/// Which triangles [`Pmx::recompute_normals`] smooths over, and which vertices it updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalScope {
+    /// Every vertex, smoothed over the triangles of every material.
+    #[default]
+    Mesh,
+    /// Every vertex, smoothed across splits only over triangles of the materials it belongs to,
+    /// keeping the edges between materials hard.
+    PerMaterial,
+    /// Only the vertices of the material at this index, from its own triangles.
+    Material(usize),
+}
+
+impl Pmx {
+    /// Rebuilds the vertex normals, see the [module docs](crate::normals).
+    ///
+    /// `smoothing_angle` is in radians, `0` keeps every split hard and `π` smooths all of them.
+    pub fn recompute_normals(&mut self, smoothing_angle: f32, scope: NormalScope) {
+        let vertex_count = self.vertices.inner.len();
+        let positions: Vec<[f32; 3]> = (self.vertices.inner.iter())
+            .map(|vertex| to_array(vertex.pos))
+            .collect();
+
+        // the triangles in scope with their area weighted normal and smoothing group
+        let mut triangles = Vec::new();
+        let mut start = 0;
+        for (material, m) in self.materials.inner.iter().enumerate() {
+            let surfaces = &self.surfaces.inner;
+            let count = (m.surface_count.max(0) as usize).min(surfaces.len() - start);
+            let range = &surfaces[start..start + count];
+            start += count;
+
+            let group = match scope {
+                NormalScope::Mesh => 0,
+                NormalScope::PerMaterial => material,
+                NormalScope::Material(index) if index == material => 0,
+                NormalScope::Material(_) => continue,
+            };
+
+            for triangle in range.chunks_exact(3) {
+                let corner = |i: usize| {
+                    (triangle[i].index.as_usize()).filter(|&vertex| vertex < vertex_count)
+                };
+                let (Some(a), Some(b), Some(c)) = (corner(0), corner(1), corner(2)) else {
+                    continue;
+                };
+
+                let [pa, pb, pc] = [a, b, c].map(|vertex| positions[vertex]);
+                let normal = cross(sub(pb, pa), sub(pc, pa));
+                triangles.push(([a, b, c], normal, group));
+            }
+        }
+
+        // the triangles each vertex is a corner of
+        let mut corners: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+        for (index, (vertices, _, _)) in triangles.iter().enumerate() {
+            for &vertex in vertices {
+                if corners[vertex].last() != Some(&index) {
+                    corners[vertex].push(index);
+                }
+            }
+        }
+
+        // the vertices at each position, with -0.0 and 0.0 counting as the same
+        let mut welded: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (vertex, position) in positions.iter().enumerate() {
+            if !corners[vertex].is_empty() {
+                let key = position.map(|p| (p + 0.0).to_bits());
+                welded.entry(key).or_default().push(vertex);
+            }
+        }
+
+        let threshold = smoothing_angle.clamp(0.0, std::f32::consts::PI).cos();
+
+        for (vertex, own) in corners.iter().enumerate() {
+            if own.is_empty() {
+                continue;
+            }
+
+            let mut normal = own
+                .iter()
+                .fold([0.0; 3], |sum, &triangle| add(sum, triangles[triangle].1));
+            let Some(direction) = normalize(normal) else {
+                continue;
+            };
+
+            let key = positions[vertex].map(|p| (p + 0.0).to_bits());
+            for &other in welded.get(&key).into_iter().flatten() {
+                if other == vertex {
+                    continue;
+                }
+
+                for &triangle in &corners[other] {
+                    let (vertices, face, group) = triangles[triangle];
+                    // triangles the vertex is a corner of are already counted
+                    let shared = vertices.contains(&vertex);
+                    let same_group = own.iter().any(|&t| triangles[t].2 == group);
+
+                    if !shared
+                        && same_group
+                        && normalize(face).is_some_and(|face| dot(face, direction) >= threshold)
+                    {
+                        normal = add(normal, face);
+                    }
+                }
+            }
+
+            if let Some(normal) = normalize(normal) {
+                self.vertices.inner[vertex].normal = from_array(normal);
+            }
+        }
+    }
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    std::array::from_fn(|i| a[i] + b[i])
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    std::array::from_fn(|i| a[i] - b[i])
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// The unit vector along `v`, `None` for a zero or non-finite vector.
+fn normalize(v: [f32; 3]) -> Option<[f32; 3]> {
+    let len = dot(v, v).sqrt();
+    (len > f32::EPSILON && len.is_finite()).then(|| v.map(|c| c / len))
+}
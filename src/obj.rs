@@ -0,0 +1,150 @@
+//! Wavefront `.obj` / `.mtl` export.
+//!
+//! Only the static mesh is written: positions, normals and UVs, one group per material and a
+//! material library with the diffuse colour and texture of each material. Bones, morphs and
+//! physics have no OBJ counterpart and are dropped, which makes this useful for quickly looking
+//! at a model in tools that do not understand PMX.
+//!
+//! OBJ is right-handed, so the Z axis is mirrored and the winding of every face reversed, and V
+//! is flipped since OBJ places the UV origin at the bottom left.
+
+use std::{
+    collections::HashSet,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{pmx::Pmx, types::to_array};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl Pmx {
+    /// Writes the mesh to `path` and its materials to a `.mtl` file next to it with the same
+    /// stem.
+    pub fn export_obj(&self, path: &Path) -> Result<()> {
+        let mtl_path = path.with_extension("mtl");
+        let mtl_name = mtl_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut obj = BufWriter::new(std::fs::File::create(path)?);
+        let mut mtl = BufWriter::new(std::fs::File::create(&mtl_path)?);
+
+        self.write_obj(&mut obj, &mut mtl, &mtl_name)?;
+
+        obj.flush()?;
+        mtl.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the mesh and the material library to separate writers, `mtl_name` is the file
+    /// name the `.obj` refers to the library by.
+    pub fn write_obj(
+        &self,
+        obj: &mut impl Write,
+        mtl: &mut impl Write,
+        mtl_name: &str,
+    ) -> Result<()> {
+        let names = material_names(self);
+
+        writeln!(obj, "# {}", self.header.name.local.as_str())?;
+        writeln!(obj, "mtllib {mtl_name}")?;
+
+        for vertex in &self.vertices.inner {
+            let [x, y, z]: [f32; 3] = to_array(vertex.pos);
+            writeln!(obj, "v {x} {y} {}", 0.0 - z)?;
+        }
+        for vertex in &self.vertices.inner {
+            let [u, v]: [f32; 2] = to_array(vertex.uv);
+            writeln!(obj, "vt {u} {}", 1.0 - v)?;
+        }
+        for vertex in &self.vertices.inner {
+            let [x, y, z]: [f32; 3] = to_array(vertex.normal);
+            writeln!(obj, "vn {x} {y} {}", 0.0 - z)?;
+        }
+
+        let surfaces = &self.surfaces.inner;
+        let mut start = 0;
+
+        for (material, name) in self.materials.inner.iter().zip(&names) {
+            let count = (material.surface_count.max(0) as usize).min(surfaces.len() - start);
+            let range = &surfaces[start..start + count];
+            start += count;
+
+            writeln!(obj, "g {name}")?;
+            writeln!(obj, "usemtl {name}")?;
+
+            for tri in range.chunks_exact(3) {
+                // OBJ indices are 1-based
+                let [a, b, c] = [&tri[0], &tri[2], &tri[1]].map(|s| i64::from(s.index.value()) + 1);
+                writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+            }
+        }
+
+        for (material, name) in self.materials.inner.iter().zip(&names) {
+            let [r, g, b, a]: [f32; 4] = to_array(material.diffuse);
+            let [sr, sg, sb]: [f32; 3] = to_array(material.specular);
+            let [ar, ag, ab]: [f32; 3] = to_array(material.ambient);
+
+            writeln!(mtl, "newmtl {name}")?;
+            writeln!(mtl, "Kd {r} {g} {b}")?;
+            writeln!(mtl, "Ka {ar} {ag} {ab}")?;
+            writeln!(mtl, "Ks {sr} {sg} {sb}")?;
+            writeln!(mtl, "Ns {}", material.specular_strength)?;
+            writeln!(mtl, "d {a}")?;
+
+            if let Some(texture) = material
+                .tex_idx
+                .as_usize()
+                .and_then(|index| self.textures.inner.get(index))
+            {
+                // PMX paths use Windows separators
+                writeln!(mtl, "map_Kd {}", texture.path.as_str().replace('\\', "/"))?;
+            }
+
+            writeln!(mtl)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Material names usable as OBJ identifiers: whitespace is replaced and duplicates get the
+/// material index appended, since `usemtl` looks materials up by name.
+fn material_names(pmx: &Pmx) -> Vec<String> {
+    let mut seen = HashSet::new();
+
+    pmx.materials
+        .inner
+        .iter()
+        .enumerate()
+        .map(|(i, material)| {
+            let name: String = material
+                .name
+                .local
+                .as_str()
+                .chars()
+                .map(|c| if c.is_whitespace() { '_' } else { c })
+                .collect();
+
+            let name = if name.is_empty() || !seen.insert(name.clone()) {
+                format!("{name}_{i}")
+            } else {
+                name
+            };
+            seen.insert(name.clone());
+
+            name
+        })
+        .collect()
+}
@@ -0,0 +1,419 @@
+//! Building a [rapier](https://rapier.rs) physics scene from the rigid bodies and joints of a model.
+//!
+//! Every PMX rigid body becomes a rapier rigid body with a single collider, and every joint an
+//! impulse joint between the bodies it connects. Bodies following their bone are kinematic, the
+//! others dynamic, and the user data of each body, collider and joint holds its PMX index. The
+//! scene moves the kinematic bodies along with an animated [`Pose`] and writes the simulated ones
+//! back into it, see [`PhysicsScene::follow_bones`], and takes the pushes of impulse morphs with
+//! [`PhysicsScene::apply_impulses`].
+//!
+//! The scene stays in MMD's units and axes. MMD's gravity is usually taken as 9.8 units per
+//! second squared times 10, down along -Y. All joint types are built as 6-DOF spring joints like
+//! the PMX 2.0 `Spring6Dof`, which is what virtually every model uses.
+
+use glam::{Mat4, Quat};
+use rapier3d::{
+    math::{Isometry, Real, Vector},
+    na::{Quaternion, Translation3, UnitQuaternion},
+    prelude::{
+        ColliderBuilder, ColliderSet, GenericJointBuilder, Group, ImpulseJointHandle,
+        ImpulseJointSet, InteractionGroups, JointAxesMask, JointAxis, RigidBodyBuilder,
+        RigidBodyHandle, RigidBodySet,
+    },
+};
+
+use crate::{
+    joint::Joint,
+    morphing::Impulse,
+    pmx::Pmx,
+    rigid_body::{PhysicsMode, RigidBody, Shape},
+    skeleton::Skeleton,
+    skin::Pose,
+    types::Vec3,
+};
+
+/// The rapier sets built from a model, see the [module docs](self).
+pub struct PhysicsScene {
+    rigid_bodies: RigidBodySet,
+    colliders: ColliderSet,
+    joints: ImpulseJointSet,
+    body_handles: Vec<RigidBodyHandle>,
+    joint_handles: Vec<Option<ImpulseJointHandle>>,
+    links: Vec<BoneLink>,
+}
+
+/// How a body is tied to its bone.
+#[derive(Clone, Copy)]
+struct BoneLink {
+    bone: Option<usize>,
+    mode: PhysicsMode,
+    /// The transform of the body in the space of the bone, in the bind pose.
+    offset: Isometry<Real>,
+}
+
+impl PhysicsScene {
+    pub fn from_pmx(pmx: &Pmx) -> Self {
+        let mut rigid_bodies = RigidBodySet::new();
+        let mut colliders = ColliderSet::new();
+        let mut joints = ImpulseJointSet::new();
+
+        let mut body_handles = Vec::with_capacity(pmx.rigid_bodies.inner.len());
+        let mut links = Vec::with_capacity(pmx.rigid_bodies.inner.len());
+        for (index, body) in pmx.rigid_bodies.inner.iter().enumerate() {
+            let handle = rigid_bodies.insert(rigid_body(body, index));
+            colliders.insert_with_parent(collider(body, index), handle, &mut rigid_bodies);
+            body_handles.push(handle);
+
+            let bone = body.bone.as_usize().filter(|&b| b < pmx.bones.inner.len());
+            // bones carry no rotation in the bind pose, only their position
+            let bone_position =
+                bone.map_or(Vector::zeros(), |b| vector(pmx.bones.inner[b].position));
+            links.push(BoneLink {
+                bone,
+                mode: body.physics_mode,
+                offset: Translation3::from(-bone_position) * isometry(body.position, body.rotation),
+            });
+        }
+
+        let joint_handles = pmx
+            .joints
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(index, joint)| {
+                let a = joint.rigid_body_a.as_usize()?;
+                let b = joint.rigid_body_b.as_usize()?;
+                let (&handle_a, &handle_b) = (body_handles.get(a)?, body_handles.get(b)?);
+
+                let frame_a = isometry(
+                    pmx.rigid_bodies.inner[a].position,
+                    pmx.rigid_bodies.inner[a].rotation,
+                );
+                let frame_b = isometry(
+                    pmx.rigid_bodies.inner[b].position,
+                    pmx.rigid_bodies.inner[b].rotation,
+                );
+
+                Some(joints.insert(
+                    handle_a,
+                    handle_b,
+                    generic_joint(joint, index, frame_a, frame_b),
+                    true,
+                ))
+            })
+            .collect();
+
+        Self {
+            rigid_bodies,
+            colliders,
+            joints,
+            body_handles,
+            joint_handles,
+            links,
+        }
+    }
+
+    pub fn rigid_bodies(&self) -> &RigidBodySet {
+        &self.rigid_bodies
+    }
+
+    pub fn colliders(&self) -> &ColliderSet {
+        &self.colliders
+    }
+
+    pub fn joints(&self) -> &ImpulseJointSet {
+        &self.joints
+    }
+
+    pub fn rigid_bodies_mut(&mut self) -> &mut RigidBodySet {
+        &mut self.rigid_bodies
+    }
+
+    pub fn colliders_mut(&mut self) -> &mut ColliderSet {
+        &mut self.colliders
+    }
+
+    pub fn joints_mut(&mut self) -> &mut ImpulseJointSet {
+        &mut self.joints
+    }
+
+    /// The handle of the body built from a PMX rigid body.
+    pub fn body_handle(&self, rigid_body: usize) -> Option<RigidBodyHandle> {
+        self.body_handles.get(rigid_body).copied()
+    }
+
+    /// The handle of the joint built from a PMX joint, `None` also for joints that do not
+    /// connect two existing rigid bodies, which are skipped.
+    pub fn joint_handle(&self, joint: usize) -> Option<ImpulseJointHandle> {
+        self.joint_handles.get(joint).copied().flatten()
+    }
+
+    /// The sets, for handing them to a rapier pipeline that owns them.
+    pub fn into_sets(self) -> (RigidBodySet, ColliderSet, ImpulseJointSet) {
+        (self.rigid_bodies, self.colliders, self.joints)
+    }
+}
+
+/// Keeping the simulation and the bones in step, each frame:
+/// 1. [`follow_bones`](PhysicsScene::follow_bones) with the animated pose, and
+///    [`apply_impulses`](PhysicsScene::apply_impulses) with the impulses of the morphs
+/// 2. step the rapier pipeline
+/// 3. [`drive_bones`](PhysicsScene::drive_bones) to write the simulated bodies back into the pose
+///
+/// before skinning with the pose. [`reset`](PhysicsScene::reset) puts everything back in place
+/// for the first frame, or after jumping around in the animation.
+impl PhysicsScene {
+    /// Moves the bodies that follow their bone to where the bone is in `pose`, reached over the
+    /// next step.
+    pub fn follow_bones(&mut self, skeleton: &Skeleton, pose: &Pose) {
+        let world = skeleton.pose_transforms(pose);
+
+        for (link, &handle) in self.links.iter().zip(&self.body_handles) {
+            if link.mode != PhysicsMode::FollowBone {
+                continue;
+            }
+
+            if let Some(bone) = link.bone
+                && let Some(body) = self.rigid_bodies.get_mut(handle)
+            {
+                body.set_next_kinematic_position(to_isometry(world[bone]) * link.offset);
+            }
+        }
+    }
+
+    /// Places every body where its bone is in `pose` and stops it moving.
+    pub fn reset(&mut self, skeleton: &Skeleton, pose: &Pose) {
+        let world = skeleton.pose_transforms(pose);
+
+        for (link, &handle) in self.links.iter().zip(&self.body_handles) {
+            if let Some(bone) = link.bone
+                && let Some(body) = self.rigid_bodies.get_mut(handle)
+            {
+                body.set_position(to_isometry(world[bone]) * link.offset, true);
+                body.set_linvel(Vector::zeros(), true);
+                body.set_angvel(Vector::zeros(), true);
+            }
+        }
+    }
+
+    /// Gives the simulated bodies the pushes of impulse morphs, from
+    /// [`Morphed::impulses`](crate::morphing::Morphed::impulses).
+    ///
+    /// The velocity of an impulse is added to the body's, the torque is applied as an angular
+    /// impulse, both rotated along with the body if they are local. Impulses act once, models
+    /// usually key the morph on for a single frame. Bodies following their bone are not pushed.
+    pub fn apply_impulses(&mut self, impulses: &[Impulse]) {
+        for impulse in impulses {
+            let Some(link) = self.links.get(impulse.rigid_body) else {
+                continue;
+            };
+            if link.mode == PhysicsMode::FollowBone {
+                continue;
+            }
+
+            if let Some(body) = self
+                .rigid_bodies
+                .get_mut(self.body_handles[impulse.rigid_body])
+            {
+                let rotation = if impulse.local {
+                    body.position().rotation
+                } else {
+                    UnitQuaternion::identity()
+                };
+
+                body.set_linvel(body.linvel() + rotation * vector(impulse.velocity), true);
+                body.apply_torque_impulse(rotation * vector(impulse.torque), true);
+            }
+        }
+    }
+
+    /// Writes the simulated bodies into the bones they drive.
+    ///
+    /// Bodies in [`PhysicsMode::Physics`] set both the rotation and the position of their bone,
+    /// ones in [`PhysicsMode::PhysicsBone`] only the rotation, keeping the bone at its animated
+    /// offset from its parent. Bones not driven by a body keep their animated transform relative
+    /// to their parent, so they move along with a simulated parent.
+    pub fn drive_bones(&self, skeleton: &Skeleton, pose: &mut Pose) {
+        let animated = skeleton.pose_transforms(pose);
+        let bind = skeleton.local_transforms();
+
+        let mut driven: Vec<Option<(Mat4, PhysicsMode)>> = vec![None; skeleton.len()];
+        for (link, &handle) in self.links.iter().zip(&self.body_handles) {
+            if link.mode == PhysicsMode::FollowBone {
+                continue;
+            }
+
+            if let Some(bone) = link.bone
+                && let Some(body) = self.rigid_bodies.get(handle)
+            {
+                let world = to_mat4(body.position() * link.offset.inverse());
+                driven[bone] = Some((world, link.mode));
+            }
+        }
+
+        let mut world = animated.clone();
+
+        for bone in skeleton.depth_first() {
+            let parent = skeleton.parent(bone);
+            let parent_world = parent.map_or(Mat4::IDENTITY, |p| world[p]);
+            // where the bone ends up if it keeps its animated place relative to its parent
+            let follow = match parent {
+                Some(p) => parent_world * animated[p].inverse() * animated[bone],
+                None => animated[bone],
+            };
+
+            world[bone] = match driven[bone] {
+                Some((mut target, mode)) => {
+                    if mode == PhysicsMode::PhysicsBone {
+                        target.w_axis = follow.w_axis;
+                    }
+
+                    let local = (parent_world * bind[bone]).inverse() * target;
+                    let (_, rotation, translation) = local.to_scale_rotation_translation();
+                    pose.set_rotation(bone, rotation);
+                    pose.set_translation(bone, translation);
+
+                    target
+                }
+                None => follow,
+            };
+        }
+    }
+}
+
+fn to_isometry(matrix: Mat4) -> Isometry<Real> {
+    let (_, rotation, translation) = matrix.to_scale_rotation_translation();
+    let rotation = Quaternion::new(rotation.w, rotation.x, rotation.y, rotation.z);
+
+    Isometry::from_parts(
+        Translation3::from(vector(translation)),
+        UnitQuaternion::from_quaternion(rotation),
+    )
+}
+
+fn to_mat4(isometry: Isometry<Real>) -> Mat4 {
+    let rotation = isometry.rotation;
+    let translation = isometry.translation.vector;
+
+    Mat4::from_rotation_translation(
+        Quat::from_xyzw(rotation.i, rotation.j, rotation.k, rotation.w),
+        Vec3::new(translation.x, translation.y, translation.z),
+    )
+}
+
+impl Pmx {
+    /// Builds a rapier scene from the physics of the model, see [`PhysicsScene`].
+    pub fn physics_scene(&self) -> PhysicsScene {
+        PhysicsScene::from_pmx(self)
+    }
+}
+
+fn vector(v: Vec3) -> Vector<Real> {
+    Vector::new(v[0], v[1], v[2])
+}
+
+/// The transform of a position and MMD Euler angles, which rotate around Y, then X, then Z.
+fn isometry(position: Vec3, rotation: Vec3) -> Isometry<Real> {
+    let rotation = UnitQuaternion::from_axis_angle(&Vector::y_axis(), rotation[1])
+        * UnitQuaternion::from_axis_angle(&Vector::x_axis(), rotation[0])
+        * UnitQuaternion::from_axis_angle(&Vector::z_axis(), rotation[2]);
+
+    Isometry::from_parts(Translation3::from(vector(position)), rotation)
+}
+
+fn rigid_body(body: &RigidBody, index: usize) -> RigidBodyBuilder {
+    let builder = match body.physics_mode {
+        PhysicsMode::FollowBone => RigidBodyBuilder::kinematic_position_based(),
+        PhysicsMode::Physics | PhysicsMode::PhysicsBone => RigidBodyBuilder::dynamic(),
+    };
+
+    builder
+        .position(isometry(body.position, body.rotation))
+        .linear_damping(body.linear_damping)
+        .angular_damping(body.angular_damping)
+        .user_data(index as u128)
+}
+
+fn collider(body: &RigidBody, index: usize) -> ColliderBuilder {
+    let size = body.size;
+    let builder = match body.shape {
+        Shape::Sphere => ColliderBuilder::ball(size[0]),
+        Shape::Box => ColliderBuilder::cuboid(size[0], size[1], size[2]),
+        // the height is the distance between the centers of the caps
+        Shape::Capsule => ColliderBuilder::capsule_y(size[1] * 0.5, size[0]),
+    };
+
+    // the mask has a bit set for every group the body collides with, like rapier's filter
+    let groups = InteractionGroups::new(
+        Group::from_bits_truncate(u32::from(body.group.bit())),
+        Group::from_bits_truncate(u32::from(body.non_collision_mask.raw())),
+    );
+
+    let builder = builder
+        .collision_groups(groups)
+        .restitution(body.repulsion)
+        .friction(body.friction)
+        .user_data(index as u128);
+
+    // massless bodies keep rapier's default density rather than becoming infinitely light
+    if body.mass > 0.0 {
+        builder.mass(body.mass)
+    } else {
+        builder
+    }
+}
+
+/// A joint at the joint's transform, limited per axis like Bullet's 6-DOF spring constraint:
+/// equal limits lock the axis, a minimum above the maximum leaves it free.
+fn generic_joint(
+    joint: &Joint,
+    index: usize,
+    frame_a: Isometry<Real>,
+    frame_b: Isometry<Real>,
+) -> GenericJointBuilder {
+    let frame = isometry(joint.position, joint.rotation);
+
+    let mut locked = JointAxesMask::empty();
+    let mut builder = GenericJointBuilder::new(JointAxesMask::empty())
+        .local_frame1(frame_a.inverse() * frame)
+        .local_frame2(frame_b.inverse() * frame)
+        .user_data(index as u128);
+
+    let axes = [
+        (JointAxis::LinX, JointAxesMask::LIN_X),
+        (JointAxis::LinY, JointAxesMask::LIN_Y),
+        (JointAxis::LinZ, JointAxesMask::LIN_Z),
+        (JointAxis::AngX, JointAxesMask::ANG_X),
+        (JointAxis::AngY, JointAxesMask::ANG_Y),
+        (JointAxis::AngZ, JointAxesMask::ANG_Z),
+    ];
+
+    for (i, (axis, mask)) in axes.into_iter().enumerate() {
+        let (min, max, spring) = if i < 3 {
+            (
+                joint.position_min,
+                joint.position_max,
+                joint.position_spring,
+            )
+        } else {
+            (
+                joint.rotation_min,
+                joint.rotation_max,
+                joint.rotation_spring,
+            )
+        };
+        let (min, max, spring) = (min[i % 3], max[i % 3], spring[i % 3]);
+
+        if min == max {
+            locked |= mask;
+        } else if min < max {
+            builder = builder.limits(axis, [min, max]);
+        }
+
+        if spring != 0.0 {
+            builder = builder.motor_position(axis, 0.0, spring, 0.0);
+        }
+    }
+
+    builder.locked_axes(locked)
+}
@@ -0,0 +1,190 @@
+//! Chains of simulated rigid bodies.
+//!
+//! Hair strands, skirt columns and other swinging parts are built as chains of rigid bodies
+//! moved by physics, each held to the one before by a joint and hanging from a body that follows
+//! a bone. [`Pmx::rigid_body_chains`] recovers the chains: the parent of a simulated body is the
+//! other body of the first joint holding it, MMD putting the child body second, or else the body
+//! of its bone's nearest ancestor that has one. Chains start at bodies whose parent is missing or
+//! follows its bone, and run through the first simulated child of each body, further children
+//! starting chains of their own.
+//!
+//! Simulated bodies no joint connects to fall away from the model, they are listed as dangling.
+
+use std::fmt;
+
+use crate::{pmx::Pmx, rigid_body::PhysicsMode};
+
+/// Rigid bodies each hanging from the one before, see the [module docs](crate::physics_chains).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RigidBodyChain {
+    pub(crate) parent: Option<usize>,
+    pub(crate) bodies: Vec<usize>,
+}
+
+impl RigidBodyChain {
+    /// The first body of the chain.
+    pub fn root(&self) -> usize {
+        self.bodies[0]
+    }
+
+    /// The body the chain hangs from: one following its bone, or a simulated one for chains
+    /// branching off another. `None` for chains held by nothing.
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    /// The bodies from the root down.
+    pub fn bodies(&self) -> &[usize] {
+        &self.bodies
+    }
+
+    pub fn len(&self) -> usize {
+        self.bodies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bodies.is_empty()
+    }
+}
+
+/// The chains of simulated rigid bodies of a model, see [`Pmx::rigid_body_chains`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RigidBodyChains {
+    pub(crate) chains: Vec<RigidBodyChain>,
+    pub(crate) dangling: Vec<usize>,
+}
+
+impl RigidBodyChains {
+    /// The chains, ordered by their root.
+    pub fn chains(&self) -> &[RigidBodyChain] {
+        &self.chains
+    }
+
+    /// The simulated bodies no joint connects to anything, in index order.
+    pub fn dangling(&self) -> &[usize] {
+        &self.dangling
+    }
+
+    /// The chain holding `body`, `None` if it follows its bone.
+    pub fn chain_of(&self, body: usize) -> Option<&RigidBodyChain> {
+        self.chains
+            .iter()
+            .find(|chain| chain.bodies.contains(&body))
+    }
+}
+
+impl fmt::Display for RigidBodyChains {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chain in &self.chains {
+            let bodies: Vec<String> = chain.bodies.iter().map(|b| b.to_string()).collect();
+            match chain.parent {
+                Some(parent) => write!(f, "rigid body {parent} -> ")?,
+                None => write!(f, "unheld -> ")?,
+            }
+            writeln!(f, "{} (length {})", bodies.join(" -> "), chain.len())?;
+        }
+        for body in &self.dangling {
+            writeln!(f, "rigid body {body} is dangling")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pmx {
+    /// Groups the simulated rigid bodies into chains, see the [module docs](crate::physics_chains).
+    pub fn rigid_body_chains(&self) -> RigidBodyChains {
+        let bodies = &self.rigid_bodies.inner;
+        let simulated: Vec<bool> = (bodies.iter())
+            .map(|body| body.physics_mode != PhysicsMode::FollowBone)
+            .collect();
+        let valid = |index: usize| (index < bodies.len()).then_some(index);
+
+        let mut jointed = vec![false; bodies.len()];
+        let mut held_by: Vec<Option<usize>> = vec![None; bodies.len()];
+        for joint in &self.joints.inner {
+            let a = joint.rigid_body_a.as_usize().and_then(valid);
+            let b = joint.rigid_body_b.as_usize().and_then(valid);
+            if let (Some(a), Some(b)) = (a, b)
+                && a != b
+            {
+                jointed[a] = true;
+                jointed[b] = true;
+                held_by[b].get_or_insert(a);
+            }
+        }
+
+        let bone_count = self.bones.inner.len();
+        let mut bone_bodies: Vec<Option<usize>> = vec![None; bone_count];
+        for (i, body) in bodies.iter().enumerate() {
+            if let Some(bone) = body.bone.as_usize().filter(|&b| b < bone_count) {
+                bone_bodies[bone].get_or_insert(i);
+            }
+        }
+
+        let skeleton = self.skeleton();
+        let parents: Vec<Option<usize>> = (0..bodies.len())
+            .map(|i| {
+                held_by[i].or_else(|| {
+                    let bone = bodies[i].bone.as_usize().filter(|&b| b < bone_count)?;
+                    skeleton
+                        .ancestors(bone)
+                        .find_map(|ancestor| bone_bodies[ancestor])
+                        .filter(|&parent| parent != i)
+                })
+            })
+            .collect();
+
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); bodies.len()];
+        for (i, parent) in parents.iter().enumerate() {
+            if let Some(parent) = *parent
+                && simulated[i]
+            {
+                children[parent].push(i);
+            }
+        }
+
+        let mut roots: Vec<usize> = (0..bodies.len())
+            .filter(|&i| simulated[i] && parents[i].is_none_or(|p| !simulated[p]))
+            .rev()
+            .collect();
+        let mut visited = vec![false; bodies.len()];
+        let mut chains = Vec::new();
+        // bodies whose parents loop back to them have no root, the first one left starts a chain
+        while let Some(root) =
+            (roots.pop()).or_else(|| (0..bodies.len()).find(|&i| simulated[i] && !visited[i]))
+        {
+            if visited[root] {
+                continue;
+            }
+
+            let mut chain = vec![root];
+            visited[root] = true;
+            let mut current = root;
+            loop {
+                let mut next = children[current].iter().filter(|&&c| !visited[c]);
+                let Some(&first) = next.next() else {
+                    break;
+                };
+
+                // branches start chains of their own
+                roots.extend(next.copied());
+                visited[first] = true;
+                chain.push(first);
+                current = first;
+            }
+
+            chains.push(RigidBodyChain {
+                parent: parents[root],
+                bodies: chain,
+            });
+        }
+        chains.sort_by_key(|chain| chain.bodies[0]);
+
+        let dangling = (0..bodies.len())
+            .filter(|&i| simulated[i] && !jointed[i])
+            .collect();
+
+        RigidBodyChains { chains, dangling }
+    }
+}
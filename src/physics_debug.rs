@@ -0,0 +1,295 @@
+//! Meshes showing the rigid bodies and joints.
+//!
+//! [`Pmx::physics_debug_meshes`] builds a mesh for every rigid body in the shape it collides with,
+//! and a small cube at every joint, placed in model space at the bind pose, much like PMX Editor
+//! draws the physics setup. Each mesh has triangles to draw it solid and line segments to draw it
+//! as a wireframe, both indexing the same positions.
+//!
+//! Spheres and capsules are split into [`SEGMENTS`] around the Y axis and [`RINGS`] from pole to
+//! pole, capsule caps getting half the rings each. The rotations are applied around Y, then X,
+//! then Z, as in the physics simulation. Triangles are wound counterclockwise seen from outside.
+
+use std::f32::consts::{PI, TAU};
+
+use crate::{
+    pmx::Pmx,
+    rigid_body::{PhysicsMode, Shape},
+    types::{Vec3, from_array, to_array},
+};
+
+/// The number of vertices around the Y axis of spheres and capsules.
+pub const SEGMENTS: usize = 16;
+/// The number of bands from pole to pole of spheres and capsules.
+pub const RINGS: usize = 8;
+/// Half the edge length of the cubes drawn at joints.
+pub const JOINT_SIZE: f32 = 0.2;
+
+/// What a debug mesh shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugSource {
+    /// A rigid body, by index, and how it moves, which viewers usually color by.
+    RigidBody(usize, PhysicsMode),
+    /// A joint, by index.
+    Joint(usize),
+}
+
+/// A mesh of a rigid body or joint, see [`Pmx::physics_debug_meshes`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugMesh {
+    pub(crate) source: DebugSource,
+    pub(crate) positions: Vec<Vec3>,
+    pub(crate) normals: Vec<Vec3>,
+    pub(crate) triangles: Vec<[u32; 3]>,
+    pub(crate) lines: Vec<[u32; 2]>,
+}
+
+impl DebugMesh {
+    pub fn source(&self) -> DebugSource {
+        self.source
+    }
+
+    /// The positions in model space.
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// The normals, one per position.
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    /// The triangles of the solid mesh.
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    /// The line segments of the wireframe.
+    pub fn lines(&self) -> &[[u32; 2]] {
+        &self.lines
+    }
+}
+
+impl Pmx {
+    /// Builds a mesh for every rigid body and joint, see the [module docs](crate::physics_debug).
+    ///
+    /// The rigid bodies come first, then the joints, each in section order.
+    pub fn physics_debug_meshes(&self) -> Vec<DebugMesh> {
+        let bodies = self.rigid_bodies.inner.iter().enumerate().map(|(i, body)| {
+            let [x, y, z]: [f32; 3] = to_array(body.size);
+            let geometry = match body.shape {
+                Shape::Sphere => lathe(&sphere_profile(x, 0.0)),
+                Shape::Box => cuboid([x, y, z]),
+                Shape::Capsule => lathe(&sphere_profile(x, y * 0.5)),
+            };
+
+            geometry.place(
+                DebugSource::RigidBody(i, body.physics_mode),
+                to_array(body.position),
+                to_array(body.rotation),
+            )
+        });
+
+        let joints = self.joints.inner.iter().enumerate().map(|(i, joint)| {
+            cuboid([JOINT_SIZE; 3]).place(
+                DebugSource::Joint(i),
+                to_array(joint.position),
+                to_array(joint.rotation),
+            )
+        });
+
+        bodies.chain(joints).collect()
+    }
+}
+
+/// A mesh around the origin.
+#[derive(Default)]
+struct Geometry {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    triangles: Vec<[u32; 3]>,
+    lines: Vec<[u32; 2]>,
+}
+
+impl Geometry {
+    fn vertex(&mut self, position: [f32; 3], normal: [f32; 3]) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        (self.positions.len() - 1) as u32
+    }
+
+    /// Rotates the mesh and moves it to `position`.
+    fn place(self, source: DebugSource, position: [f32; 3], rotation: [f32; 3]) -> DebugMesh {
+        let rotate = rotation_matrix(rotation);
+        let apply = |v: [f32; 3]| -> [f32; 3] {
+            std::array::from_fn(|row| (0..3).map(|col| rotate[row][col] * v[col]).sum())
+        };
+
+        DebugMesh {
+            source,
+            positions: (self.positions.into_iter())
+                .map(|p| {
+                    let p = apply(p);
+                    from_array([p[0] + position[0], p[1] + position[1], p[2] + position[2]])
+                })
+                .collect(),
+            normals: (self.normals.into_iter())
+                .map(|n| from_array(apply(n)))
+                .collect(),
+            triangles: self.triangles,
+            lines: self.lines,
+        }
+    }
+}
+
+/// The rotation around Y, then X, then Z.
+fn rotation_matrix([x, y, z]: [f32; 3]) -> [[f32; 3]; 3] {
+    let (sx, cx) = x.sin_cos();
+    let (sy, cy) = y.sin_cos();
+    let (sz, cz) = z.sin_cos();
+
+    // Ry * Rx * Rz
+    [
+        [cy * cz + sy * sx * sz, sy * sx * cz - cy * sz, sy * cx],
+        [cx * sz, cx * cz, -sx],
+        [cy * sx * sz - sy * cz, sy * sz + cy * sx * cz, cy * cx],
+    ]
+}
+
+/// Points from the top pole to the bottom one as radius, height and the angle of the normal from
+/// the top, of a sphere whose halves are `offset` above and below the origin.
+fn sphere_profile(radius: f32, offset: f32) -> Vec<(f32, f32, f32)> {
+    let half = RINGS / 2;
+    let mut profile = Vec::new();
+
+    for ring in 0..=half {
+        let angle = PI * ring as f32 / RINGS as f32;
+        profile.push((radius * angle.sin(), offset + radius * angle.cos(), angle));
+    }
+    for ring in half..=RINGS {
+        let angle = PI * ring as f32 / RINGS as f32;
+        profile.push((radius * angle.sin(), -offset + radius * angle.cos(), angle));
+    }
+
+    // a sphere has no cylinder between its halves
+    if offset == 0.0 {
+        profile.remove(half);
+    }
+    profile
+}
+
+/// Turns the profile around the Y axis, the first and last points being the poles.
+fn lathe(profile: &[(f32, f32, f32)]) -> Geometry {
+    let mut geometry = Geometry::default();
+    let around = |i: usize| TAU * (i % SEGMENTS) as f32 / SEGMENTS as f32;
+
+    let (_, top_height, _) = profile[0];
+    let top = geometry.vertex([0.0, top_height, 0.0], [0.0, 1.0, 0.0]);
+
+    let rings: Vec<u32> = profile[1..profile.len() - 1]
+        .iter()
+        .map(|&(radius, height, angle)| {
+            let first = geometry.positions.len() as u32;
+            for segment in 0..SEGMENTS {
+                let (sin, cos) = around(segment).sin_cos();
+                geometry.vertex(
+                    [radius * cos, height, -radius * sin],
+                    [angle.sin() * cos, angle.cos(), -angle.sin() * sin],
+                );
+            }
+            first
+        })
+        .collect();
+
+    let (_, bottom_height, _) = profile[profile.len() - 1];
+    let bottom = geometry.vertex([0.0, bottom_height, 0.0], [0.0, -1.0, 0.0]);
+
+    let at = |ring: u32, segment: usize| ring + (segment % SEGMENTS) as u32;
+    for segment in 0..SEGMENTS {
+        let first = rings[0];
+        let last = rings[rings.len() - 1];
+        geometry
+            .triangles
+            .push([top, at(first, segment), at(first, segment + 1)]);
+        geometry
+            .triangles
+            .push([bottom, at(last, segment + 1), at(last, segment)]);
+        geometry.lines.push([top, at(first, segment)]);
+        geometry.lines.push([at(last, segment), bottom]);
+
+        for pair in rings.windows(2) {
+            let (upper, lower) = (pair[0], pair[1]);
+            geometry.triangles.push([
+                at(upper, segment),
+                at(lower, segment),
+                at(lower, segment + 1),
+            ]);
+            geometry.triangles.push([
+                at(upper, segment),
+                at(lower, segment + 1),
+                at(upper, segment + 1),
+            ]);
+            geometry
+                .lines
+                .push([at(upper, segment), at(lower, segment)]);
+        }
+
+        for &ring in &rings {
+            geometry
+                .lines
+                .push([at(ring, segment), at(ring, segment + 1)]);
+        }
+    }
+
+    geometry
+}
+
+/// A box with the half extents, with its own vertices per face for flat shading and the corners
+/// again for the wireframe.
+fn cuboid(half: [f32; 3]) -> Geometry {
+    let mut geometry = Geometry::default();
+
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for sign in [1.0, -1.0] {
+            let mut normal = [0.0; 3];
+            normal[axis] = sign;
+
+            let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(a, b)| {
+                let mut corner = [0.0; 3];
+                corner[axis] = sign * half[axis];
+                corner[u] = a * half[u];
+                corner[v] = b * half[v];
+                geometry.vertex(corner, normal)
+            });
+
+            // the corners go counterclockwise around the positive axis
+            let [a, b, c, d] = corners;
+            if sign > 0.0 {
+                geometry.triangles.extend([[a, b, c], [a, c, d]]);
+            } else {
+                geometry.triangles.extend([[a, c, b], [a, d, c]]);
+            }
+        }
+    }
+
+    let corner = |i: usize| std::array::from_fn(|axis| if i >> axis & 1 == 1 { 1.0 } else { -1.0 });
+    let first = geometry.positions.len() as u32;
+    for i in 0..8 {
+        let direction: [f32; 3] = corner(i);
+        let length = 3.0f32.sqrt();
+        geometry.vertex(
+            std::array::from_fn(|axis| direction[axis] * half[axis]),
+            direction.map(|d| d / length),
+        );
+    }
+    // corners one bit apart share an edge
+    for i in 0..8u32 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                geometry.lines.push([first + i, first + (i | bit)]);
+            }
+        }
+    }
+
+    geometry
+}
@@ -0,0 +1,1445 @@
+//! The legacy PMD model format, the predecessor of PMX used by MMD before version 7.
+//!
+//! All indices are fixed 2-byte unsigned integers (except the rigid bodies of joints) and all text is
+//! Shift-JIS in fixed-size fields. The English names, toon textures and physics sections were added
+//! later as extensions at the end of the file, older files simply end before them.
+
+use std::{
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{
+    bone::{self, Bone as PmxBone, BoneFlags, IkAngleLimit, IkLink, Inherit, Tail},
+    display_frame::{DisplayFrame, FrameEntry},
+    joint::{self, JointType},
+    material::{EnvironmentBlend, Material as PmxMaterial, MaterialFlags, Toon},
+    morph::{self, Panel},
+    pmx::{self, Comment, Globals, ModelName, Pmx, Version},
+    rigid_body::{self, PhysicsMode, Shape},
+    surface::Surface,
+    texture::Texture,
+    types::{
+        BoneIndex, IndexSize, MorphIndex, Name, PmxText, RigidBodyIndex, TextEncoding,
+        TextureIndex, Vec2, Vec3, VertexIndex, from_array, read_f32, read_shift_jis, read_u8,
+        read_u16, read_u32, to_array, vec_from_bytes,
+    },
+    vertex::{Vertex as PmxVertex, WeightDeform},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("File had an invalid tag, did you input the correct file?")]
+    InvalidTag,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid bone kind encountered")]
+    InvalidBoneKind,
+    #[error("Morph error: {0}")]
+    Morph(#[from] morph::Error),
+    #[error("Rigid body error: {0}")]
+    RigidBody(#[from] rigid_body::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Nil value of the 2-byte bone indices.
+pub const NIL: u16 = u16::MAX;
+
+const NAME_LEN: usize = 20;
+const COMMENT_LEN: usize = 256;
+const BONE_GROUP_NAME_LEN: usize = 50;
+const TOON_TEXTURE_LEN: usize = 100;
+const TOON_TEXTURE_COUNT: usize = 10;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Header {
+    pub(crate) version: f32,
+    pub(crate) name: String,
+    pub(crate) comment: String,
+}
+
+impl Header {
+    pub fn version(&self) -> f32 {
+        self.version
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let mut tag = [0; 3];
+        reader.read_exact(&mut tag)?;
+
+        if &tag != b"Pmd" {
+            Err(Error::InvalidTag)?
+        }
+
+        let version = read_f32(reader)?;
+
+        let name = read_shift_jis(reader, NAME_LEN)?;
+        let comment = read_shift_jis(reader, COMMENT_LEN)?;
+
+        Ok(Self {
+            version,
+            name,
+            comment,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vertex {
+    pub(crate) position: Vec3,
+    pub(crate) normal: Vec3,
+    pub(crate) uv: Vec2,
+    pub(crate) bones: [u16; 2],
+    /// Weight of the first bone in percent, the second bone gets the rest.
+    pub(crate) weight: u8,
+    pub(crate) edge: bool,
+}
+
+impl Vertex {
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    pub fn uv(&self) -> Vec2 {
+        self.uv
+    }
+
+    pub fn bones(&self) -> [u16; 2] {
+        self.bones
+    }
+
+    pub fn weight(&self) -> u8 {
+        self.weight
+    }
+
+    /// Whether the vertex gets an edge (outline), stored inverted in the file.
+    pub fn edge(&self) -> bool {
+        self.edge
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let position: Vec3 = vec_from_bytes!(Vec3, reader);
+        let normal: Vec3 = vec_from_bytes!(Vec3, reader);
+        let uv: Vec2 = vec_from_bytes!(Vec2, reader);
+
+        let bones = [read_u16(reader)?, read_u16(reader)?];
+
+        let weight = read_u8(reader)?;
+
+        let edge = read_u8(reader)? == 0;
+
+        Ok(Self {
+            position,
+            normal,
+            uv,
+            bones,
+            weight,
+            edge,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Material {
+    /// RGBA.
+    pub(crate) diffuse: [f32; 4],
+    pub(crate) specular_strength: f32,
+    pub(crate) specular: Vec3,
+    pub(crate) ambient: Vec3,
+    /// 0-based index into the toon texture list, `None` for no toon.
+    pub(crate) toon: Option<u8>,
+    pub(crate) edge: bool,
+    /// The number of surface indices (not triangles) the material covers.
+    pub(crate) surface_count: u32,
+    /// The raw texture field, `texture*sphere` when both a texture and a sphere map are set.
+    pub(crate) texture: String,
+}
+
+impl Material {
+    pub fn diffuse(&self) -> [f32; 4] {
+        self.diffuse
+    }
+
+    pub fn specular_strength(&self) -> f32 {
+        self.specular_strength
+    }
+
+    pub fn specular(&self) -> Vec3 {
+        self.specular
+    }
+
+    pub fn ambient(&self) -> Vec3 {
+        self.ambient
+    }
+
+    pub fn toon(&self) -> Option<u8> {
+        self.toon
+    }
+
+    pub fn edge(&self) -> bool {
+        self.edge
+    }
+
+    pub fn surface_count(&self) -> u32 {
+        self.surface_count
+    }
+
+    pub fn raw_texture(&self) -> &str {
+        &self.texture
+    }
+
+    /// The color texture, without the sphere map part of the texture field.
+    pub fn texture(&self) -> Option<&str> {
+        self.texture_parts().find(|path| !is_sphere_map(path))
+    }
+
+    /// The sphere map, either after a `*` in the texture field or as its only `.sph`/`.spa` file.
+    pub fn sphere(&self) -> Option<&str> {
+        self.texture_parts().find(|path| is_sphere_map(path))
+    }
+
+    fn texture_parts(&self) -> impl Iterator<Item = &str> {
+        self.texture.split('*').filter(|path| !path.is_empty())
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let diffuse = [
+            read_f32(reader)?,
+            read_f32(reader)?,
+            read_f32(reader)?,
+            read_f32(reader)?,
+        ];
+
+        let specular_strength = read_f32(reader)?;
+
+        let specular: Vec3 = vec_from_bytes!(Vec3, reader);
+        let ambient: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        let toon = match read_u8(reader)? {
+            u8::MAX => None,
+            toon => Some(toon),
+        };
+
+        let edge = read_u8(reader)? != 0;
+
+        let surface_count = read_u32(reader)?;
+
+        let texture = read_shift_jis(reader, NAME_LEN)?;
+
+        Ok(Self {
+            diffuse,
+            specular_strength,
+            specular,
+            ambient,
+            toon,
+            edge,
+            surface_count,
+            texture,
+        })
+    }
+}
+
+/// Sphere maps are told apart from color textures by extension, `.spa` is additive.
+pub(crate) fn is_sphere_map(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".sph") || path.ends_with(".spa")
+}
+
+/// PMD bones have a single kind instead of PMX's flags.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BoneKind {
+    Rotate,
+    RotateMove,
+    Ik,
+    Unknown,
+    /// Rotated by an IK chain, `ik_parent` is the IK bone.
+    IkAffected,
+    /// Copies the rotation of `ik_parent`.
+    RotateAffected,
+    IkTarget,
+    Invisible,
+    Twist,
+    /// Rotates along with `ik_parent`, the ratio in percent is stored in `tail`.
+    RotateFollow,
+}
+
+impl TryFrom<u8> for BoneKind {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Rotate),
+            1 => Ok(Self::RotateMove),
+            2 => Ok(Self::Ik),
+            3 => Ok(Self::Unknown),
+            4 => Ok(Self::IkAffected),
+            5 => Ok(Self::RotateAffected),
+            6 => Ok(Self::IkTarget),
+            7 => Ok(Self::Invisible),
+            8 => Ok(Self::Twist),
+            9 => Ok(Self::RotateFollow),
+            _ => Err(Error::InvalidBoneKind),
+        }
+    }
+}
+
+impl From<BoneKind> for u8 {
+    fn from(value: BoneKind) -> Self {
+        match value {
+            BoneKind::Rotate => 0,
+            BoneKind::RotateMove => 1,
+            BoneKind::Ik => 2,
+            BoneKind::Unknown => 3,
+            BoneKind::IkAffected => 4,
+            BoneKind::RotateAffected => 5,
+            BoneKind::IkTarget => 6,
+            BoneKind::Invisible => 7,
+            BoneKind::Twist => 8,
+            BoneKind::RotateFollow => 9,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bone {
+    pub(crate) name: String,
+    /// `NIL` for root bones.
+    pub(crate) parent: u16,
+    /// The bone the tail points at, `NIL` (or 0 in some files) for none.
+    pub(crate) tail: u16,
+    pub(crate) kind: BoneKind,
+    /// Meaning depends on `kind`, 0 when unused.
+    pub(crate) ik_parent: u16,
+    pub(crate) position: Vec3,
+}
+
+impl Bone {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parent(&self) -> u16 {
+        self.parent
+    }
+
+    pub fn tail(&self) -> u16 {
+        self.tail
+    }
+
+    pub fn kind(&self) -> BoneKind {
+        self.kind
+    }
+
+    pub fn ik_parent(&self) -> u16 {
+        self.ik_parent
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let name = read_shift_jis(reader, NAME_LEN)?;
+
+        let parent = read_u16(reader)?;
+        let tail = read_u16(reader)?;
+
+        let kind = read_u8(reader)?.try_into()?;
+
+        let ik_parent = read_u16(reader)?;
+
+        let position: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        Ok(Self {
+            name,
+            parent,
+            tail,
+            kind,
+            ik_parent,
+            position,
+        })
+    }
+}
+
+/// An IK chain, stored separately from the bones unlike in PMX.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ik {
+    /// The IK bone that is moved by the animation.
+    pub(crate) bone: u16,
+    /// The end effector that is moved towards `bone`.
+    pub(crate) target: u16,
+    pub(crate) loop_count: u16,
+    /// Maximum rotation per iteration, in units of 4 radians.
+    pub(crate) limit_angle: f32,
+    /// The chain from the effector upwards.
+    pub(crate) links: Vec<u16>,
+}
+
+impl Ik {
+    pub fn bone(&self) -> u16 {
+        self.bone
+    }
+
+    pub fn target(&self) -> u16 {
+        self.target
+    }
+
+    pub fn loop_count(&self) -> u16 {
+        self.loop_count
+    }
+
+    pub fn limit_angle(&self) -> f32 {
+        self.limit_angle
+    }
+
+    pub fn links(&self) -> &[u16] {
+        &self.links
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let bone = read_u16(reader)?;
+        let target = read_u16(reader)?;
+
+        let link_count = read_u8(reader)?;
+
+        let loop_count = read_u16(reader)?;
+
+        let limit_angle = read_f32(reader)?;
+
+        let links = (0..link_count)
+            .map(|_| read_u16(reader))
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(Self {
+            bone,
+            target,
+            loop_count,
+            limit_angle,
+            links,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MorphVertex {
+    /// For the base morph an index into the vertices, for every other morph an index into the
+    /// vertices of the base morph.
+    pub(crate) index: u32,
+    /// For the base morph the absolute position, for every other morph an offset.
+    pub(crate) position: Vec3,
+}
+
+impl MorphVertex {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+}
+
+/// A vertex morph (called a skin in PMD).
+///
+/// The first morph is the base morph on the `Hidden` panel, it lists every vertex used by any morph.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Morph {
+    pub(crate) name: String,
+    pub(crate) panel: Panel,
+    pub(crate) vertices: Vec<MorphVertex>,
+}
+
+impl Morph {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn panel(&self) -> Panel {
+        self.panel
+    }
+
+    pub fn vertices(&self) -> &[MorphVertex] {
+        &self.vertices
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let name = read_shift_jis(reader, NAME_LEN)?;
+
+        let count = read_u32(reader)? as usize;
+
+        let panel = read_u8(reader)?.try_into()?;
+
+        let mut vertices = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let index = read_u32(reader)?;
+            let position: Vec3 = vec_from_bytes!(Vec3, reader);
+
+            vertices.push(MorphVertex { index, position });
+        }
+
+        Ok(Self {
+            name,
+            panel,
+            vertices,
+        })
+    }
+}
+
+/// Assigns a bone to one of the bone groups shown in MMD's bone panel.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoneDisplay {
+    pub(crate) bone: u16,
+    /// 1-based index into the bone groups.
+    pub(crate) group: u8,
+}
+
+impl BoneDisplay {
+    pub fn bone(&self) -> u16 {
+        self.bone
+    }
+
+    pub fn group(&self) -> u8 {
+        self.group
+    }
+}
+
+/// The English name extension.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct English {
+    pub(crate) name: String,
+    pub(crate) comment: String,
+    /// One per bone.
+    pub(crate) bones: Vec<String>,
+    /// One per morph, except the base morph.
+    pub(crate) morphs: Vec<String>,
+    /// One per bone group.
+    pub(crate) bone_groups: Vec<String>,
+}
+
+impl English {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    pub fn bones(&self) -> &[String] {
+        &self.bones
+    }
+
+    pub fn morphs(&self) -> &[String] {
+        &self.morphs
+    }
+
+    pub fn bone_groups(&self) -> &[String] {
+        &self.bone_groups
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        bone_count: usize,
+        morph_count: usize,
+        bone_group_count: usize,
+    ) -> Result<Self> {
+        let name = read_shift_jis(reader, NAME_LEN)?;
+        let comment = read_shift_jis(reader, COMMENT_LEN)?;
+
+        let bones = read_names(reader, bone_count, NAME_LEN)?;
+        let morphs = read_names(reader, morph_count.saturating_sub(1), NAME_LEN)?;
+        let bone_groups = read_names(reader, bone_group_count, BONE_GROUP_NAME_LEN)?;
+
+        Ok(Self {
+            name,
+            comment,
+            bones,
+            morphs,
+            bone_groups,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RigidBody {
+    pub(crate) name: String,
+    /// `NIL` for bodies that are not attached to a bone.
+    pub(crate) bone: u16,
+    pub(crate) group: u8,
+    pub(crate) non_collision_mask: u16,
+    pub(crate) shape: Shape,
+    pub(crate) size: Vec3,
+    /// Relative to the bone, unlike PMX where it is in model space.
+    pub(crate) position: Vec3,
+    /// Euler angles in radians.
+    pub(crate) rotation: Vec3,
+    pub(crate) mass: f32,
+    pub(crate) linear_damping: f32,
+    pub(crate) angular_damping: f32,
+    pub(crate) repulsion: f32,
+    pub(crate) friction: f32,
+    pub(crate) physics_mode: PhysicsMode,
+}
+
+impl RigidBody {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn bone(&self) -> u16 {
+        self.bone
+    }
+
+    pub fn group(&self) -> u8 {
+        self.group
+    }
+
+    pub fn non_collision_mask(&self) -> u16 {
+        self.non_collision_mask
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.size
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Vec3 {
+        self.rotation
+    }
+
+    pub fn mass(&self) -> f32 {
+        self.mass
+    }
+
+    pub fn linear_damping(&self) -> f32 {
+        self.linear_damping
+    }
+
+    pub fn angular_damping(&self) -> f32 {
+        self.angular_damping
+    }
+
+    pub fn repulsion(&self) -> f32 {
+        self.repulsion
+    }
+
+    pub fn friction(&self) -> f32 {
+        self.friction
+    }
+
+    pub fn physics_mode(&self) -> PhysicsMode {
+        self.physics_mode
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let name = read_shift_jis(reader, NAME_LEN)?;
+
+        let bone = read_u16(reader)?;
+
+        let group = read_u8(reader)?;
+
+        let non_collision_mask = read_u16(reader)?;
+
+        let shape = read_u8(reader)?.try_into()?;
+
+        let size: Vec3 = vec_from_bytes!(Vec3, reader);
+        let position: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        let mass = read_f32(reader)?;
+        let linear_damping = read_f32(reader)?;
+        let angular_damping = read_f32(reader)?;
+        let repulsion = read_f32(reader)?;
+        let friction = read_f32(reader)?;
+
+        let physics_mode = read_u8(reader)?.try_into()?;
+
+        Ok(Self {
+            name,
+            bone,
+            group,
+            non_collision_mask,
+            shape,
+            size,
+            position,
+            rotation,
+            mass,
+            linear_damping,
+            angular_damping,
+            repulsion,
+            friction,
+            physics_mode,
+        })
+    }
+}
+
+/// A 6DOF spring joint, the only joint type PMD has.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joint {
+    pub(crate) name: String,
+    pub(crate) rigid_body_a: u32,
+    pub(crate) rigid_body_b: u32,
+    pub(crate) position: Vec3,
+    /// Euler angles in radians.
+    pub(crate) rotation: Vec3,
+    pub(crate) position_min: Vec3,
+    pub(crate) position_max: Vec3,
+    pub(crate) rotation_min: Vec3,
+    pub(crate) rotation_max: Vec3,
+    pub(crate) position_spring: Vec3,
+    pub(crate) rotation_spring: Vec3,
+}
+
+impl Joint {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn rigid_body_a(&self) -> u32 {
+        self.rigid_body_a
+    }
+
+    pub fn rigid_body_b(&self) -> u32 {
+        self.rigid_body_b
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Vec3 {
+        self.rotation
+    }
+
+    pub fn position_min(&self) -> Vec3 {
+        self.position_min
+    }
+
+    pub fn position_max(&self) -> Vec3 {
+        self.position_max
+    }
+
+    pub fn rotation_min(&self) -> Vec3 {
+        self.rotation_min
+    }
+
+    pub fn rotation_max(&self) -> Vec3 {
+        self.rotation_max
+    }
+
+    pub fn position_spring(&self) -> Vec3 {
+        self.position_spring
+    }
+
+    pub fn rotation_spring(&self) -> Vec3 {
+        self.rotation_spring
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let name = read_shift_jis(reader, NAME_LEN)?;
+
+        let rigid_body_a = read_u32(reader)?;
+        let rigid_body_b = read_u32(reader)?;
+
+        let position: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation: Vec3 = vec_from_bytes!(Vec3, reader);
+        let position_min: Vec3 = vec_from_bytes!(Vec3, reader);
+        let position_max: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation_min: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation_max: Vec3 = vec_from_bytes!(Vec3, reader);
+        let position_spring: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation_spring: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        Ok(Self {
+            name,
+            rigid_body_a,
+            rigid_body_b,
+            position,
+            rotation,
+            position_min,
+            position_max,
+            rotation_min,
+            rotation_max,
+            position_spring,
+            rotation_spring,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pmd {
+    pub(crate) header: Header,
+    pub(crate) vertices: Vec<Vertex>,
+    /// Vertex indices, every 3 form a triangle.
+    pub(crate) surfaces: Vec<u16>,
+    pub(crate) materials: Vec<Material>,
+    pub(crate) bones: Vec<Bone>,
+    pub(crate) iks: Vec<Ik>,
+    pub(crate) morphs: Vec<Morph>,
+    /// Morphs shown in the facial panel, in display order.
+    pub(crate) morph_display: Vec<u16>,
+    pub(crate) bone_groups: Vec<String>,
+    pub(crate) bone_display: Vec<BoneDisplay>,
+    pub(crate) english: Option<English>,
+    /// The ten toon texture file names materials refer to, MMD's `toon01.bmp`-`toon10.bmp` when absent.
+    pub(crate) toon_textures: Option<Vec<String>>,
+    pub(crate) rigid_bodies: Vec<RigidBody>,
+    pub(crate) joints: Vec<Joint>,
+}
+
+impl Pmd {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn surfaces(&self) -> &[u16] {
+        &self.surfaces
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn bones(&self) -> &[Bone] {
+        &self.bones
+    }
+
+    pub fn iks(&self) -> &[Ik] {
+        &self.iks
+    }
+
+    pub fn morphs(&self) -> &[Morph] {
+        &self.morphs
+    }
+
+    pub fn morph_display(&self) -> &[u16] {
+        &self.morph_display
+    }
+
+    pub fn bone_groups(&self) -> &[String] {
+        &self.bone_groups
+    }
+
+    pub fn bone_display(&self) -> &[BoneDisplay] {
+        &self.bone_display
+    }
+
+    pub fn english(&self) -> Option<&English> {
+        self.english.as_ref()
+    }
+
+    pub fn toon_textures(&self) -> Option<&[String]> {
+        self.toon_textures.as_deref()
+    }
+
+    pub fn rigid_bodies(&self) -> &[RigidBody] {
+        &self.rigid_bodies
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let fh = std::fs::File::open(path)?;
+
+        let mut reader = BufReader::new(fh);
+
+        Self::parse(&mut reader)
+    }
+
+    /// Parses a PMD model from any reader.
+    ///
+    /// The extension sections (English names, toon textures, physics) are optional, a file that
+    /// ends before them parses with them empty.
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let header = Header::parse(reader)?;
+
+        let count = read_u32(reader)? as usize;
+        let vertices = parse_list(reader, count, Vertex::parse)?;
+
+        let count = read_u32(reader)? as usize;
+        let surfaces = parse_list(reader, count, |r| Ok(read_u16(r)?))?;
+
+        let count = read_u32(reader)? as usize;
+        let materials = parse_list(reader, count, Material::parse)?;
+
+        let count = read_u16(reader)? as usize;
+        let bones = parse_list(reader, count, Bone::parse)?;
+
+        let count = read_u16(reader)? as usize;
+        let iks = parse_list(reader, count, Ik::parse)?;
+
+        let count = read_u16(reader)? as usize;
+        let morphs = parse_list(reader, count, Morph::parse)?;
+
+        let count = read_u8(reader)? as usize;
+        let morph_display = parse_list(reader, count, |r| Ok(read_u16(r)?))?;
+
+        let count = read_u8(reader)? as usize;
+        let bone_groups = read_names(reader, count, BONE_GROUP_NAME_LEN)?;
+
+        let count = read_u32(reader)? as usize;
+        let bone_display = parse_list(reader, count, |r| {
+            Ok(BoneDisplay {
+                bone: read_u16(r)?,
+                group: read_u8(r)?,
+            })
+        })?;
+
+        let mut pmd = Self {
+            header,
+            vertices,
+            surfaces,
+            materials,
+            bones,
+            iks,
+            morphs,
+            morph_display,
+            bone_groups,
+            bone_display,
+            english: None,
+            toon_textures: None,
+            rigid_bodies: Vec::new(),
+            joints: Vec::new(),
+        };
+
+        let Some(has_english) = read_optional(reader, read_u8)? else {
+            return Ok(pmd);
+        };
+
+        if has_english != 0 {
+            pmd.english = Some(English::parse(
+                reader,
+                pmd.bones.len(),
+                pmd.morphs.len(),
+                pmd.bone_groups.len(),
+            )?);
+        }
+
+        let Some(first_toon) = read_optional(reader, |r| read_shift_jis(r, TOON_TEXTURE_LEN))?
+        else {
+            return Ok(pmd);
+        };
+
+        let mut toon_textures = vec![first_toon];
+        toon_textures.extend(read_names(
+            reader,
+            TOON_TEXTURE_COUNT - 1,
+            TOON_TEXTURE_LEN,
+        )?);
+        pmd.toon_textures = Some(toon_textures);
+
+        let Some(count) = read_optional(reader, read_u32)? else {
+            return Ok(pmd);
+        };
+        pmd.rigid_bodies = parse_list(reader, count as usize, RigidBody::parse)?;
+
+        let count = read_u32(reader)? as usize;
+        pmd.joints = parse_list(reader, count, Joint::parse)?;
+
+        Ok(pmd)
+    }
+}
+
+fn parse_list<R: Read, T>(
+    reader: &mut R,
+    count: usize,
+    parse: impl Fn(&mut R) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut items = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        items.push(parse(reader)?);
+    }
+
+    Ok(items)
+}
+
+fn read_names(reader: &mut impl Read, count: usize, len: usize) -> Result<Vec<String>> {
+    (0..count)
+        .map(|_| Ok(read_shift_jis(reader, len)?))
+        .collect()
+}
+
+/// Reads the first field of an optional extension, EOF right before it means the extension is absent.
+fn read_optional<R: Read, T>(
+    reader: &mut R,
+    read: impl Fn(&mut R) -> std::io::Result<T>,
+) -> Result<Option<T>> {
+    match read(reader) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e)?,
+    }
+}
+
+impl Pmx {
+    /// Converts a PMD model into an equivalent PMX 2.0 model.
+    ///
+    /// PMD behaviour that PMX expresses explicitly is spelled out the way PMX Editor does it: IK chain
+    /// links named `ひざ` (knee) get MMD's implicit knee limits, rigid body positions are made
+    /// absolute, sphere maps become environment textures and custom toon textures become texture
+    /// references. Names PMD does not have (materials) are generated.
+    pub fn from_pmd(pmd: &Pmd) -> Self {
+        let encoding = TextEncoding::UTF16LE;
+        let text = |text: &str| PmxText::new(text, encoding);
+        let name = |local: &str, universal: Option<&String>| Name {
+            local: text(local),
+            universal: text(universal.map_or("", String::as_str)),
+        };
+
+        let english = pmd.english.as_ref();
+        let bone_index = |bone: u16| BoneIndex::new(if bone == NIL { -1 } else { bone as i32 });
+        // the base morph is folded into the others, so every morph index after it shifts down by
+        // one, models without it keep their morphs as they are
+        let base = (pmd.morphs.first()).filter(|morph| morph.panel == Panel::Hidden);
+        let skipped = usize::from(base.is_some());
+        let morph_index = |morph: u16| MorphIndex::new(morph as i32 - skipped as i32);
+
+        let vertices: Vec<PmxVertex> = pmd
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let [a, b] = vertex.bones;
+
+                let weight_deform = match vertex.weight {
+                    _ if a == b => WeightDeform::Bdef1 {
+                        index: bone_index(a),
+                    },
+                    100.. => WeightDeform::Bdef1 {
+                        index: bone_index(a),
+                    },
+                    0 => WeightDeform::Bdef1 {
+                        index: bone_index(b),
+                    },
+                    weight => {
+                        let weight = weight as f32 / 100.0;
+                        WeightDeform::Bdef2 {
+                            indices: [bone_index(a), bone_index(b)],
+                            weights: [weight, 1.0 - weight],
+                        }
+                    }
+                };
+
+                PmxVertex {
+                    pos: vertex.position,
+                    normal: vertex.normal,
+                    uv: vertex.uv,
+                    extra_vec4: None,
+                    weight_deform,
+                    edge_scale: if vertex.edge { 1.0 } else { 0.0 },
+                }
+            })
+            .collect();
+
+        let surfaces: Vec<Surface> = pmd
+            .surfaces
+            .iter()
+            .map(|&index| Surface {
+                index: VertexIndex::new(index as i32),
+            })
+            .collect();
+
+        let mut textures: Vec<String> = Vec::new();
+        let mut texture_index = |path: Option<&str>| match path {
+            None => TextureIndex::nil(),
+            Some(path) => {
+                let index = textures.iter().position(|t| t == path).unwrap_or_else(|| {
+                    textures.push(path.to_string());
+                    textures.len() - 1
+                });
+                TextureIndex::new(index as i32)
+            }
+        };
+
+        let materials: Vec<PmxMaterial> = pmd
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(i, material)| {
+                let [r, g, b, alpha] = material.diffuse;
+
+                let mut flags = MaterialFlags::GROUND_SHADOW
+                    | MaterialFlags::DRAW_SHADOW
+                    | MaterialFlags::RECEIVE_SHADOW;
+                // PMD draws translucent materials double sided
+                if alpha < 1.0 {
+                    flags |= MaterialFlags::NO_CULL;
+                }
+                // an alpha of exactly 0.98 is MMD's magic value for disabling self shadows
+                if alpha == 0.98 {
+                    flags &= !(MaterialFlags::DRAW_SHADOW | MaterialFlags::RECEIVE_SHADOW);
+                }
+                if material.edge {
+                    flags |= MaterialFlags::EDGE;
+                }
+
+                let sphere = material.sphere();
+                let env_blend = match sphere {
+                    Some(path) if path.to_ascii_lowercase().ends_with(".spa") => {
+                        EnvironmentBlend::Add
+                    }
+                    Some(_) => EnvironmentBlend::Multiply,
+                    None => EnvironmentBlend::None,
+                };
+
+                let toon = match material.toon {
+                    None => Toon::Texture(TextureIndex::nil()),
+                    Some(toon) => {
+                        let path = pmd
+                            .toon_textures
+                            .as_ref()
+                            .and_then(|toons| toons.get(toon as usize));
+
+                        match path {
+                            Some(path) if *path != Toon::internal_file_name(toon) => {
+                                Toon::Texture(texture_index(Some(path)))
+                            }
+                            _ => Toon::Internal(toon),
+                        }
+                    }
+                };
+
+                PmxMaterial {
+                    name: Name {
+                        local: text(&format!("材質{}", i + 1)),
+                        universal: text(&format!("Material{}", i + 1)),
+                    },
+                    diffuse: from_array([r, g, b, alpha]),
+                    specular: material.specular,
+                    specular_strength: material.specular_strength,
+                    ambient: material.ambient,
+                    flags: MaterialFlags::from_raw(flags),
+                    edge_color: from_array([0.0, 0.0, 0.0, 1.0]),
+                    edge_scale: 1.0,
+                    tex_idx: texture_index(material.texture()),
+                    env_idx: texture_index(sphere),
+                    env_blend,
+                    toon,
+                    meta: text(""),
+                    surface_count: material.surface_count as i32,
+                }
+            })
+            .collect();
+
+        let textures: Vec<Texture> = textures
+            .iter()
+            .map(|path| Texture { path: text(path) })
+            .collect();
+
+        let mut bones: Vec<PmxBone> = pmd
+            .bones
+            .iter()
+            .enumerate()
+            .map(|(i, bone)| {
+                use BoneKind::*;
+
+                let mut flags = BoneFlags::ROTATABLE;
+                if !matches!(bone.kind, IkTarget | Invisible | RotateAffected) {
+                    flags |= BoneFlags::VISIBLE | BoneFlags::ENABLED;
+                }
+                if matches!(bone.kind, RotateMove | Ik) {
+                    flags |= BoneFlags::TRANSLATABLE;
+                }
+
+                let bone_position =
+                    |index: u16| pmd.bones.get(index as usize).map(|tail| tail.position);
+
+                // rotate-follow bones reuse the tail field for their ratio
+                let has_tail = bone.kind != RotateFollow && bone.tail != NIL && bone.tail != 0;
+
+                let tail = if has_tail {
+                    flags |= BoneFlags::INDEXED_TAIL;
+                    Tail::Bone(bone_index(bone.tail))
+                } else {
+                    Tail::Position(from_array([0.0; 3]))
+                };
+
+                let inherit = match bone.kind {
+                    RotateAffected => Some(Inherit {
+                        parent: bone_index(bone.ik_parent),
+                        weight: 1.0,
+                    }),
+                    RotateFollow => Some(Inherit {
+                        parent: bone_index(bone.ik_parent),
+                        weight: bone.tail as f32 / 100.0,
+                    }),
+                    _ => None,
+                };
+                if inherit.is_some() {
+                    flags |= BoneFlags::INHERIT_ROTATION;
+                }
+
+                let fixed_axis = match (bone.kind, has_tail) {
+                    (Twist, true) => bone_position(bone.tail).map(|tail| {
+                        let [x, y, z]: [f32; 3] = to_array(tail);
+                        let [px, py, pz]: [f32; 3] = to_array(bone.position);
+                        let axis = [x - px, y - py, z - pz];
+                        let length = axis.iter().map(|c| c * c).sum::<f32>().sqrt();
+                        let axis = if length > 0.0 {
+                            axis.map(|c| c / length)
+                        } else {
+                            axis
+                        };
+                        Vec3::from(axis)
+                    }),
+                    _ => None,
+                };
+                if fixed_axis.is_some() {
+                    flags |= BoneFlags::FIXED_AXIS;
+                }
+
+                PmxBone {
+                    name: name(&bone.name, english.and_then(|e| e.bones.get(i))),
+                    position: bone.position,
+                    parent: bone_index(bone.parent),
+                    layer: 0,
+                    flags: BoneFlags::from_raw(flags),
+                    tail,
+                    inherit,
+                    fixed_axis,
+                    local_axes: None,
+                    external_parent: None,
+                    ik: None,
+                }
+            })
+            .collect();
+
+        for ik in &pmd.iks {
+            let Some(bone) = bones.get_mut(ik.bone as usize) else {
+                continue;
+            };
+
+            let links = ik
+                .links
+                .iter()
+                .map(|&link| {
+                    let is_knee = pmd
+                        .bones
+                        .get(link as usize)
+                        .is_some_and(|bone| bone.name.contains("ひざ"));
+
+                    IkLink {
+                        bone: bone_index(link),
+                        limits: is_knee.then(|| IkAngleLimit {
+                            min: from_array([-std::f32::consts::PI, 0.0, 0.0]),
+                            max: from_array([-0.5_f32.to_radians(), 0.0, 0.0]),
+                        }),
+                    }
+                })
+                .collect();
+
+            bone.flags = BoneFlags::from_raw(bone.flags.raw() | BoneFlags::IK);
+            bone.ik = Some(bone::Ik {
+                target: bone_index(ik.target),
+                loop_count: ik.loop_count as i32,
+                limit_angle: ik.limit_angle * 4.0,
+                links,
+            });
+        }
+
+        let morphs: Vec<morph::Morph> = pmd
+            .morphs
+            .iter()
+            .enumerate()
+            .skip(skipped)
+            .map(|(i, morph)| {
+                let offsets = morph
+                    .vertices
+                    .iter()
+                    .filter_map(|offset| {
+                        // without a base morph the offsets index the vertices directly
+                        let vertex = match base {
+                            Some(base) => base.vertices.get(offset.index as usize)?.index,
+                            None => offset.index,
+                        };
+
+                        Some(morph::VertexOffset {
+                            vertex: VertexIndex::new(vertex as i32),
+                            translation: offset.position,
+                        })
+                    })
+                    .collect();
+
+                morph::Morph {
+                    name: name(
+                        &morph.name,
+                        english.and_then(|e| e.morphs.get(i.checked_sub(1)?)),
+                    ),
+                    panel: morph.panel,
+                    offsets: morph::Offsets::Vertex(offsets),
+                }
+            })
+            .collect();
+
+        let mut display_frames = vec![
+            DisplayFrame {
+                name: name("Root", Some(&"Root".to_string())),
+                special: true,
+                entries: if pmd.bones.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![FrameEntry::Bone(BoneIndex::new(0))]
+                },
+            },
+            DisplayFrame {
+                name: name("表情", Some(&"Exp".to_string())),
+                special: true,
+                entries: pmd
+                    .morph_display
+                    .iter()
+                    .map(|&morph| FrameEntry::Morph(morph_index(morph)))
+                    .collect(),
+            },
+        ];
+
+        display_frames.extend(pmd.bone_groups.iter().enumerate().map(|(i, group)| {
+            DisplayFrame {
+                name: name(group, english.and_then(|e| e.bone_groups.get(i))),
+                special: false,
+                entries: pmd
+                    .bone_display
+                    .iter()
+                    .filter(|display| display.group as usize == i + 1)
+                    .map(|display| FrameEntry::Bone(bone_index(display.bone)))
+                    .collect(),
+            }
+        }));
+
+        let rigid_bodies: Vec<rigid_body::RigidBody> = pmd
+            .rigid_bodies
+            .iter()
+            .map(|body| {
+                // bodies without a bone are placed relative to the first bone
+                let origin = pmd
+                    .bones
+                    .get(if body.bone == NIL {
+                        0
+                    } else {
+                        body.bone as usize
+                    })
+                    .map_or([0.0; 3], |bone| to_array(bone.position));
+                let [x, y, z]: [f32; 3] = to_array(body.position);
+
+                rigid_body::RigidBody {
+                    name: name(&body.name, None),
+                    bone: bone_index(body.bone),
+                    group: rigid_body::CollisionGroup::from_raw(body.group),
+                    non_collision_mask: rigid_body::CollisionMask::from_raw(
+                        body.non_collision_mask,
+                    ),
+                    shape: body.shape,
+                    size: body.size,
+                    position: from_array([x + origin[0], y + origin[1], z + origin[2]]),
+                    rotation: body.rotation,
+                    mass: body.mass,
+                    linear_damping: body.linear_damping,
+                    angular_damping: body.angular_damping,
+                    repulsion: body.repulsion,
+                    friction: body.friction,
+                    physics_mode: body.physics_mode,
+                }
+            })
+            .collect();
+
+        let joints: Vec<joint::Joint> = pmd
+            .joints
+            .iter()
+            .map(|joint| joint::Joint {
+                name: name(&joint.name, None),
+                typ: JointType::Spring6Dof,
+                rigid_body_a: RigidBodyIndex::new(joint.rigid_body_a as i32),
+                rigid_body_b: RigidBodyIndex::new(joint.rigid_body_b as i32),
+                position: joint.position,
+                rotation: joint.rotation,
+                position_min: joint.position_min,
+                position_max: joint.position_max,
+                rotation_min: joint.rotation_min,
+                rotation_max: joint.rotation_max,
+                position_spring: joint.position_spring,
+                rotation_spring: joint.rotation_spring,
+            })
+            .collect();
+
+        let header = pmx::Header {
+            tag: *b"PMX ",
+            version: Version::V2_0,
+            raw_version: Version::V2_0.as_f32(),
+            globals: Globals {
+                encoding,
+                vec4_additional: 0,
+                vert_idx_size: IndexSize::smallest_for(vertices.len(), false),
+                tex_idx_size: IndexSize::smallest_for(textures.len(), true),
+                material_idx_size: IndexSize::smallest_for(materials.len(), true),
+                bone_idx_size: IndexSize::smallest_for(bones.len(), true),
+                morph_idx_size: IndexSize::smallest_for(morphs.len(), true),
+                rb_idx_size: IndexSize::smallest_for(rigid_bodies.len(), true),
+                additional: None,
+            },
+            name: ModelName {
+                local: text(&pmd.header.name),
+                universal: text(english.map_or("", |e| e.name.as_str())),
+            },
+            comment: Comment {
+                local: text(&pmd.header.comment),
+                universal: text(english.map_or("", |e| e.comment.as_str())),
+            },
+        };
+
+        Self {
+            header,
+            vertices: vertices.into(),
+            surfaces: surfaces.into(),
+            textures: textures.into(),
+            materials: materials.into(),
+            bones: bones.into(),
+            morphs: morphs.into(),
+            display_frames: display_frames.into(),
+            rigid_bodies: rigid_bodies.into(),
+            joints: joints.into(),
+            soft_bodies: None,
+            trailing: None,
+        }
+    }
+}
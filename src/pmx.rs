@@ -1,16 +1,18 @@
 use core::fmt;
 
 use std::{
-    io::{BufReader, Read},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
 use thiserror::Error;
 
 use crate::{
-    surface, texture,
-    types::{self, PmxText, TextEncoding},
-    vertex,
+    bone, display_frame, joint, material, morph, rigid_body, soft_body, surface, texture,
+    types::{self, ParseContext, PmxText, TextDecoding, TextEncoding, write_f32, write_u8},
+    validate,
+    vertex::{self, UvChannel},
+    visit::Section,
 };
 
 #[derive(Debug, Error)]
@@ -29,15 +31,65 @@ pub enum Error {
     Surface(#[from] surface::Error),
     #[error("Texture error: {0}")]
     Texture(#[from] texture::Error),
+    #[error("Material error: {0}")]
+    Material(#[from] material::Error),
+    #[error("Bone error: {0}")]
+    Bone(#[from] bone::Error),
+    #[error("Morph error: {0}")]
+    Morph(#[from] morph::Error),
+    #[error("Display frame error: {0}")]
+    DisplayFrame(#[from] display_frame::Error),
+    #[error("Rigid body error: {0}")]
+    RigidBody(#[from] rigid_body::Error),
+    #[error("Joint error: {0}")]
+    Joint(#[from] joint::Error),
+    #[error("Soft body error: {0}")]
+    SoftBody(#[from] soft_body::Error),
+    #[error("Negative size encountered where positive expected")]
+    NegativeSize,
+    #[error("Unsupported PMX version {0}")]
+    UnsupportedVersion(f32),
+    #[error("{feature} is not supported in PMX {version}")]
+    VersionMismatch {
+        version: Version,
+        feature: &'static str,
+    },
+    #[error("{source} in field {field}")]
+    Field {
+        field: &'static str,
+        source: Box<Error>,
+    },
+    #[error("{source} at byte {offset:#X} while reading {location}")]
+    At {
+        offset: u64,
+        /// Where in the model the error happened, e.g. `Material[12].tex_idx`.
+        location: String,
+        source: Box<Error>,
+    },
+    #[error("{len} bytes of trailing data at byte {offset:#X}")]
+    TrailingData { offset: u64, len: u64 },
+    #[error("Model failed validation\n{0}")]
+    Invalid(validate::ValidationReport),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pmx {
-    header: Header,
-    vertices: vertex::Vertices,
-    surfaces: surface::Surfaces,
-    textures: texture::Textures,
+    pub(crate) header: Header,
+    pub(crate) vertices: vertex::Vertices,
+    pub(crate) surfaces: surface::Surfaces,
+    pub(crate) textures: texture::Textures,
+    pub(crate) materials: material::Materials,
+    pub(crate) bones: bone::Bones,
+    pub(crate) morphs: morph::Morphs,
+    pub(crate) display_frames: display_frame::DisplayFrames,
+    pub(crate) rigid_bodies: rigid_body::RigidBodies,
+    pub(crate) joints: joint::Joints,
+    /// Only present in PMX 2.1 files.
+    pub(crate) soft_bodies: Option<soft_body::SoftBodies>,
+    /// Bytes found after the last known section, see [`TrailingMode`].
+    pub(crate) trailing: Option<TrailingData>,
 }
 
 impl fmt::Debug for Pmx {
@@ -56,120 +108,1196 @@ impl fmt::Debug for Pmx {
                 self.surfaces.len()
             ))
             .field("textures", &self.textures)
+            .field("materials", &format!(
+                "<truncated, print the field separately if you want to see raw contents> (size: {})",
+                self.materials.len()
+            ))
+            .field("bones", &format!(
+                "<truncated, print the field separately if you want to see raw contents> (size: {})",
+                self.bones.len()
+            ))
+            .field("morphs", &format!(
+                "<truncated, print the field separately if you want to see raw contents> (size: {})",
+                self.morphs.len()
+            ))
+            .field("display_frames", &self.display_frames)
+            .field("rigid_bodies", &format!(
+                "<truncated, print the field separately if you want to see raw contents> (size: {})",
+                self.rigid_bodies.len()
+            ))
+            .field("joints", &format!(
+                "<truncated, print the field separately if you want to see raw contents> (size: {})",
+                self.joints.len()
+            ))
+            .field("soft_bodies", &self.soft_bodies)
+            .field(
+                "trailing",
+                &self.trailing.as_ref().map(|trailing| trailing.len),
+            )
             .finish()
     }
 }
 
 impl Pmx {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    pub fn vertices(&self) -> &vertex::Vertices {
+        &self.vertices
+    }
+
+    /// The vertices can be changed in place, see [`Pmx::remove_bone`] and friends for edits
+    /// that affect other sections.
+    pub fn vertices_mut(&mut self) -> &mut [vertex::Vertex] {
+        &mut self.vertices.inner
+    }
+
+    pub fn surfaces(&self) -> &surface::Surfaces {
+        &self.surfaces
+    }
+
+    pub fn textures(&self) -> &texture::Textures {
+        &self.textures
+    }
+
+    pub fn materials(&self) -> &material::Materials {
+        &self.materials
+    }
+
+    /// Materials can be changed in place, their surface counts stay as they are since they have to
+    /// add up to the surface section.
+    pub fn materials_mut(&mut self) -> &mut [material::Material] {
+        &mut self.materials.inner
+    }
+
+    pub fn bones(&self) -> &bone::Bones {
+        &self.bones
+    }
+
+    pub fn morphs(&self) -> &morph::Morphs {
+        &self.morphs
+    }
+
+    pub fn display_frames(&self) -> &display_frame::DisplayFrames {
+        &self.display_frames
+    }
+
+    pub fn rigid_bodies(&self) -> &rigid_body::RigidBodies {
+        &self.rigid_bodies
+    }
+
+    pub fn joints(&self) -> &joint::Joints {
+        &self.joints
+    }
+
+    /// The soft body section, `None` for files older than PMX 2.1.
+    pub fn soft_bodies(&self) -> Option<&soft_body::SoftBodies> {
+        self.soft_bodies.as_ref()
+    }
+
+    /// Bytes after the last known section, only kept in preserving mode.
+    pub fn trailing(&self) -> Option<&[u8]> {
+        self.trailing.as_ref()?.bytes.as_deref()
+    }
+
+    /// Where the bytes after the last known section were found and how many there were, `None` if
+    /// the file ended right after it or trailing data was not looked for.
+    pub fn trailing_data(&self) -> Option<&TrailingData> {
+        self.trailing.as_ref()
+    }
+
     pub fn open(path: &Path) -> Result<Self> {
+        Self::open_with(path, &ParseOptions::default())
+    }
+
+    /// Opens a PMX file with the given options.
+    pub fn open_with(path: &Path, options: &ParseOptions) -> Result<Self> {
         let fh = std::fs::File::open(path)?;
 
+        let len = fh.metadata()?.len();
+
         let mut reader = BufReader::new(fh);
 
-        let header = Header::parse(&mut reader)?;
+        Self::parse_inner(&mut reader, options, Some(len))
+    }
+
+    /// Opens a PMX file keeping everything needed to write it back bit-for-bit.
+    ///
+    /// On top of what `open` keeps, any bytes after the last known section are stored and re-emitted
+    /// by the writer, so an unmodified model written with `save` is identical to the input file.
+    pub fn open_preserving(path: &Path) -> Result<Self> {
+        let mut options = ParseOptions::default();
+        options.set_trailing(TrailingMode::Preserve);
+
+        Self::open_with(path, &options)
+    }
+
+    /// Parses a PMX model from any reader.
+    ///
+    /// Prefer passing a buffered reader, as the parser does a lot of small reads.
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Self::parse_with(reader, &ParseOptions::default())
+    }
+
+    /// Parses a PMX model from any reader with the given options.
+    ///
+    /// Element counts are only checked against `max_elements`, use `parse_seekable` to also check
+    /// them against the length of the stream.
+    pub fn parse_with(reader: &mut impl Read, options: &ParseOptions) -> Result<Self> {
+        Self::parse_inner(reader, options, None)
+    }
+
+    /// Parses a PMX model from a seekable reader, rejecting element counts that could not possibly
+    /// fit in what is left of the stream before allocating anything for them.
+    pub fn parse_seekable(reader: &mut (impl Read + Seek), options: &ParseOptions) -> Result<Self> {
+        let start = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        Self::parse_inner(reader, options, Some(len.saturating_sub(start)))
+    }
+
+    /// Parses a PMX model from any reader in preserving mode, see `open_preserving`.
+    ///
+    /// The reader is consumed until EOF.
+    pub fn parse_preserving(reader: &mut impl Read) -> Result<Self> {
+        let mut options = ParseOptions::default();
+        options.set_trailing(TrailingMode::Preserve);
+
+        Self::parse_with(reader, &options)
+    }
+
+    /// Opens a PMX file, keeping whatever was parsed before an error instead of discarding it.
+    ///
+    /// Only a broken header fails outright, see [`Recovered`].
+    pub fn open_recovering(path: &Path, options: &ParseOptions) -> Result<Recovered> {
+        let fh = std::fs::File::open(path)?;
+
+        let len = fh.metadata()?.len();
+
+        let mut reader = BufReader::new(fh);
+
+        Self::parse_recovering_inner(&mut reader, options, Some(len))
+    }
+
+    /// Parses a PMX model from any reader in recovering mode, see `open_recovering`.
+    pub fn parse_recovering(reader: &mut impl Read, options: &ParseOptions) -> Result<Recovered> {
+        Self::parse_recovering_inner(reader, options, None)
+    }
+
+    fn parse_inner(
+        reader: &mut impl Read,
+        options: &ParseOptions,
+        len: Option<u64>,
+    ) -> Result<Self> {
+        let recovered = Self::parse_recovering_inner(reader, options, len)?;
+
+        match recovered.error {
+            Some(error) => Err(error),
+            None => Ok(recovered.pmx),
+        }
+    }
+
+    fn parse_recovering_inner(
+        reader: &mut impl Read,
+        options: &ParseOptions,
+        len: Option<u64>,
+    ) -> Result<Recovered> {
+        let reader = &mut OffsetReader {
+            inner: reader,
+            offset: 0,
+        };
+
+        let context = options.context(len);
+
+        let header =
+            Header::parse_with(reader, context).map_err(|e| locate(reader.offset, "Header", e))?;
+
+        let context = options.context(len.map(|len| len.saturating_sub(reader.offset)));
+
+        let mut pmx = Pmx {
+            header,
+            vertices: Vec::new().into(),
+            surfaces: Vec::new().into(),
+            textures: Vec::new().into(),
+            materials: Vec::new().into(),
+            bones: Vec::new().into(),
+            morphs: Vec::new().into(),
+            display_frames: Vec::new().into(),
+            rigid_bodies: Vec::new().into(),
+            joints: Vec::new().into(),
+            soft_bodies: None,
+            trailing: None,
+        };
+
+        if let Err((section, error)) = pmx.parse_sections(reader, options, context) {
+            return Ok(Recovered {
+                pmx,
+                failed_section: Some(section),
+                error: Some(error),
+            });
+        }
+
+        // skipping the physics sections leaves the reader in the middle of the file
+        if options.physics
+            && let Err(error) = pmx.read_trailing(reader, options.trailing)
+        {
+            return Ok(Recovered {
+                pmx,
+                failed_section: None,
+                error: Some(error),
+            });
+        }
+
+        let error = if options.validate {
+            let report = pmx.validate();
+
+            (!report.is_valid()).then_some(Error::Invalid(report))
+        } else {
+            None
+        };
+
+        Ok(Recovered {
+            pmx,
+            failed_section: None,
+            error,
+        })
+    }
+
+    /// Handles whatever follows the last section according to `mode`.
+    fn read_trailing(
+        &mut self,
+        reader: &mut OffsetReader<impl Read>,
+        mode: TrailingMode,
+    ) -> Result<()> {
+        let offset = reader.offset;
+
+        let (len, bytes) = match mode {
+            TrailingMode::Ignore => return Ok(()),
+            TrailingMode::Detect | TrailingMode::Reject => {
+                (std::io::copy(reader, &mut std::io::sink())?, None)
+            }
+            TrailingMode::Preserve => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                (bytes.len() as u64, Some(bytes))
+            }
+        };
+
+        if len == 0 {
+            return Ok(());
+        }
+
+        if mode == TrailingMode::Reject {
+            Err(Error::TrailingData { offset, len })?
+        }
+
+        self.trailing = Some(TrailingData { offset, len, bytes });
+
+        Ok(())
+    }
+
+    /// Parses the sections after the header into the model one by one, so everything before a
+    /// failing section is kept.
+    fn parse_sections(
+        &mut self,
+        reader: &mut OffsetReader<impl Read>,
+        options: &ParseOptions,
+        context: ParseContext,
+    ) -> std::result::Result<(), (Section, Error)> {
+        let version = self.header.version;
+        let globals = &self.header.globals;
 
         let vertices = vertex::Vertices::parse(
-            &mut reader,
-            header.globals.vec4_additional,
-            header.globals.bone_idx_size,
-        )?;
+            reader,
+            globals.vec4_additional,
+            globals.bone_idx_size,
+            context,
+        )
+        .map_err(|e| (Section::Vertices, locate(reader.offset, "Vertex", e.into())))?;
+
+        check_vertices(version, &vertices).map_err(|e| (Section::Vertices, e))?;
+
+        self.vertices = vertices;
+
+        self.surfaces =
+            surface::Surfaces::parse(reader, globals.vert_idx_size, context).map_err(|e| {
+                (
+                    Section::Surfaces,
+                    locate(reader.offset, "Surface", e.into()),
+                )
+            })?;
+
+        self.textures =
+            texture::Textures::parse(reader, globals.encoding, context).map_err(|e| {
+                (
+                    Section::Textures,
+                    locate(reader.offset, "Texture", e.into()),
+                )
+            })?;
+
+        self.materials =
+            material::Materials::parse(reader, globals.tex_idx_size, globals.encoding, context)
+                .map_err(|e| {
+                    (
+                        Section::Materials,
+                        locate(reader.offset, "Material", e.into()),
+                    )
+                })?;
+
+        self.bones = bone::Bones::parse(reader, globals.bone_idx_size, globals.encoding, context)
+            .map_err(|e| (Section::Bones, locate(reader.offset, "Bone", e.into())))?;
+
+        let morphs = morph::Morphs::parse(reader, globals, context)
+            .map_err(|e| (Section::Morphs, locate(reader.offset, "Morph", e.into())))?;
+
+        check_morphs(version, &morphs).map_err(|e| (Section::Morphs, e))?;
+
+        self.morphs = morphs;
+
+        self.display_frames = display_frame::DisplayFrames::parse(reader, globals, context)
+            .map_err(|e| {
+                let error = locate(reader.offset, "DisplayFrame", e.into());
+                (Section::DisplayFrames, error)
+            })?;
+
+        // the soft body section was added in 2.1, older files simply end after the joints
+        let has_soft_bodies = version >= Version::V2_1;
+
+        if !options.physics {
+            if has_soft_bodies {
+                self.soft_bodies = Some(Vec::new().into());
+            }
+
+            return Ok(());
+        }
+
+        self.rigid_bodies = rigid_body::RigidBodies::parse(
+            reader,
+            globals.bone_idx_size,
+            globals.encoding,
+            context,
+        )
+        .map_err(|e| {
+            (
+                Section::RigidBodies,
+                locate(reader.offset, "RigidBody", e.into()),
+            )
+        })?;
+
+        let joints = joint::Joints::parse(reader, globals.rb_idx_size, globals.encoding, context)
+            .map_err(|e| (Section::Joints, locate(reader.offset, "Joint", e.into())))?;
+
+        check_joints(version, &joints).map_err(|e| (Section::Joints, e))?;
+
+        self.joints = joints;
+
+        if has_soft_bodies {
+            let soft_bodies =
+                soft_body::SoftBodies::parse(reader, globals, context).map_err(|e| {
+                    (
+                        Section::SoftBodies,
+                        locate(reader.offset, "SoftBody", e.into()),
+                    )
+                })?;
+
+            self.soft_bodies = Some(soft_bodies);
+        }
+
+        Ok(())
+    }
+
+    /// Opens a PMX file and decodes its sections in parallel.
+    #[cfg(feature = "parallel")]
+    pub fn open_parallel(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+
+        Self::parse_parallel(&bytes)
+    }
+
+    /// Parses a PMX model from memory, decoding the sections on separate threads.
+    ///
+    /// The section boundaries are found with a quick scan first, which skips over the records
+    /// without decoding them.
+    #[cfg(feature = "parallel")]
+    pub fn parse_parallel(bytes: &[u8]) -> Result<Self> {
+        use crate::lazy::scan;
+
+        let mut cursor = std::io::Cursor::new(bytes);
+
+        let header = Header::parse(&mut cursor)?;
+
+        let offsets = scan(&mut cursor, &header)?;
+
+        let section = |section: Section| &bytes[offsets[&section] as usize..];
+
+        let globals = &header.globals;
 
-        let surfaces = surface::Surfaces::parse(&mut reader, header.globals.vert_idx_size)?;
+        let mut vertices = None;
+        let mut surfaces = None;
+        let mut textures = None;
+        let mut materials = None;
+        let mut bones = None;
+        let mut morphs = None;
+        let mut display_frames = None;
+        let mut rigid_bodies = None;
+        let mut joints = None;
+        let mut soft_bodies = None;
 
-        let textures = texture::Textures::parse(&mut reader, header.globals.encoding)?;
+        rayon::scope(|s| {
+            s.spawn(|_| {
+                vertices = Some(vertex::Vertices::parse(
+                    &mut section(Section::Vertices),
+                    globals.vec4_additional,
+                    globals.bone_idx_size,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                surfaces = Some(surface::Surfaces::parse(
+                    &mut section(Section::Surfaces),
+                    globals.vert_idx_size,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                textures = Some(texture::Textures::parse(
+                    &mut section(Section::Textures),
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                materials = Some(material::Materials::parse(
+                    &mut section(Section::Materials),
+                    globals.tex_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                bones = Some(bone::Bones::parse(
+                    &mut section(Section::Bones),
+                    globals.bone_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                morphs = Some(morph::Morphs::parse(
+                    &mut section(Section::Morphs),
+                    globals,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                display_frames = Some(display_frame::DisplayFrames::parse(
+                    &mut section(Section::DisplayFrames),
+                    globals,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                rigid_bodies = Some(rigid_body::RigidBodies::parse(
+                    &mut section(Section::RigidBodies),
+                    globals.bone_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            s.spawn(|_| {
+                joints = Some(joint::Joints::parse(
+                    &mut section(Section::Joints),
+                    globals.rb_idx_size,
+                    globals.encoding,
+                    ParseContext::UNCHECKED,
+                ))
+            });
+            if offsets.contains_key(&Section::SoftBodies) {
+                s.spawn(|_| {
+                    soft_bodies = Some(soft_body::SoftBodies::parse(
+                        &mut section(Section::SoftBodies),
+                        globals,
+                        ParseContext::UNCHECKED,
+                    ))
+                });
+            }
+        });
+
+        // every task has run by the time the scope returns
+        let vertices = vertices.expect("vertices decoded")?;
+        let morphs = morphs.expect("morphs decoded")?;
+        let joints = joints.expect("joints decoded")?;
+
+        check_vertices(header.version, &vertices)?;
+        check_morphs(header.version, &morphs)?;
+        check_joints(header.version, &joints)?;
 
         Ok(Pmx {
-            header,
             vertices,
-            surfaces,
-            textures,
+            surfaces: surfaces.expect("surfaces decoded")?,
+            textures: textures.expect("textures decoded")?,
+            materials: materials.expect("materials decoded")?,
+            bones: bones.expect("bones decoded")?,
+            morphs,
+            display_frames: display_frames.expect("display frames decoded")?,
+            rigid_bodies: rigid_bodies.expect("rigid bodies decoded")?,
+            joints,
+            soft_bodies: soft_bodies.transpose()?,
+            trailing: None,
+            header,
         })
     }
+
+    /// Writes the model to a `.pmx` file at the given path, creating or truncating it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let fh = std::fs::File::create(path)?;
+
+        let mut writer = BufWriter::new(fh);
+
+        self.write_to(&mut writer)?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the model to any writer.
+    ///
+    /// Section counts are taken from the current contents, text is encoded and indices are emitted
+    /// according to the header globals.
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        self.write_with_textures(writer, &self.textures)
+    }
+
+    /// Writes the model with `textures` in place of its texture section.
+    pub(crate) fn write_with_textures(
+        &self,
+        writer: &mut impl Write,
+        textures: &texture::Textures,
+    ) -> Result<()> {
+        let globals = &self.header.globals;
+        let version = self.header.version;
+
+        check_vertices(version, &self.vertices)?;
+        check_morphs(version, &self.morphs)?;
+        check_joints(version, &self.joints)?;
+
+        if self.soft_bodies.is_some() && version < Version::V2_1 {
+            Err(Error::VersionMismatch {
+                version,
+                feature: "Soft bodies",
+            })?
+        }
+
+        self.header.write(writer)?;
+
+        self.vertices
+            .write(writer, globals.vec4_additional, globals.bone_idx_size)?;
+
+        self.surfaces.write(writer, globals.vert_idx_size)?;
+
+        textures.write(writer, globals.encoding)?;
+
+        self.materials
+            .write(writer, globals.tex_idx_size, globals.encoding)?;
+
+        self.bones
+            .write(writer, globals.bone_idx_size, globals.encoding)?;
+
+        self.morphs.write(writer, globals)?;
+
+        self.display_frames.write(writer, globals)?;
+
+        self.rigid_bodies
+            .write(writer, globals.bone_idx_size, globals.encoding)?;
+
+        self.joints
+            .write(writer, globals.rb_idx_size, globals.encoding)?;
+
+        if let Some(soft_bodies) = &self.soft_bodies {
+            soft_bodies.write(writer, globals)?;
+        }
+
+        if let Some(trailing) = self.trailing() {
+            writer.write_all(trailing)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings for parsing a model.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// The largest element count accepted for a section or a list inside an element.
+    pub(crate) max_elements: usize,
+    pub(crate) text_decoding: TextDecoding,
+    /// Whether texts keep the bytes they were read from, see [`PmxText::raw_bytes`].
+    pub(crate) retain_raw_text: bool,
+    /// Whether the rigid body, joint and soft body sections are parsed.
+    pub(crate) physics: bool,
+    /// Whether the model is checked with [`Pmx::validate`] after parsing.
+    pub(crate) validate: bool,
+    pub(crate) trailing: TrailingMode,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            // far beyond what MMD can handle, while keeping the allocations for a corrupt count
+            // in the low gigabytes
+            max_elements: 1 << 24,
+            text_decoding: TextDecoding::Strict,
+            retain_raw_text: true,
+            physics: true,
+            validate: false,
+            trailing: TrailingMode::Detect,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_elements(&self) -> usize {
+        self.max_elements
+    }
+
+    pub fn text_decoding(&self) -> TextDecoding {
+        self.text_decoding
+    }
+
+    pub fn retain_raw_text(&self) -> bool {
+        self.retain_raw_text
+    }
+
+    pub fn physics(&self) -> bool {
+        self.physics
+    }
+
+    pub fn validate(&self) -> bool {
+        self.validate
+    }
+
+    pub fn trailing(&self) -> TrailingMode {
+        self.trailing
+    }
+
+    pub fn set_max_elements(&mut self, max_elements: usize) {
+        self.max_elements = max_elements;
+    }
+
+    pub fn set_text_decoding(&mut self, text_decoding: TextDecoding) {
+        self.text_decoding = text_decoding;
+    }
+
+    /// Dropping the raw bytes saves memory, texts are then re-encoded when the model is written.
+    pub fn set_retain_raw_text(&mut self, retain_raw_text: bool) {
+        self.retain_raw_text = retain_raw_text;
+    }
+
+    /// When disabled, parsing stops after the display frames and the physics sections are left
+    /// empty, which is all a renderer needs.
+    pub fn set_physics(&mut self, physics: bool) {
+        self.physics = physics;
+    }
+
+    /// When enabled, a model with broken references fails to load with [`Error::Invalid`].
+    pub fn set_validate(&mut self, validate: bool) {
+        self.validate = validate;
+    }
+
+    pub fn set_trailing(&mut self, trailing: TrailingMode) {
+        self.trailing = trailing;
+    }
+
+    /// The context handed to the sections with `remaining` bytes left in the stream.
+    pub(crate) fn context(&self, remaining: Option<u64>) -> ParseContext {
+        ParseContext {
+            max_elements: self.max_elements,
+            remaining,
+            text_decoding: self.text_decoding,
+            retain_raw_text: self.retain_raw_text,
+        }
+    }
+}
+
+/// What happens to bytes after the last known section.
+///
+/// Some exporters append proprietary data there, which the format knows nothing about.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TrailingMode {
+    /// Stop reading after the last section, leaving the rest of the stream untouched.
+    Ignore,
+    /// Read the stream to its end and record where the trailing bytes start and how many there
+    /// are, see [`Pmx::trailing_data`].
+    #[default]
+    Detect,
+    /// Like [`TrailingMode::Detect`], also keeping the bytes so the writer re-emits them.
+    Preserve,
+    /// Fail the parse with [`Error::TrailingData`] if there are any.
+    Reject,
+}
+
+/// Bytes found after the last known section of a file.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrailingData {
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+    /// Only kept with [`TrailingMode::Preserve`].
+    pub(crate) bytes: Option<Vec<u8>>,
+}
+
+impl TrailingData {
+    /// The offset of the first trailing byte in the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes themselves, only kept with [`TrailingMode::Preserve`].
+    pub fn bytes(&self) -> Option<&[u8]> {
+        self.bytes.as_deref()
+    }
+}
+
+/// A model parsed in recovering mode, see [`Pmx::open_recovering`].
+///
+/// When a section fails to parse, it and every section after it are left empty while the ones
+/// before it are intact.
+#[derive(Debug)]
+pub struct Recovered {
+    pub(crate) pmx: Pmx,
+    pub(crate) failed_section: Option<Section>,
+    pub(crate) error: Option<Error>,
+}
+
+impl Recovered {
+    pub fn pmx(&self) -> &Pmx {
+        &self.pmx
+    }
+
+    pub fn into_pmx(self) -> Pmx {
+        self.pmx
+    }
+
+    /// Returns true if the whole file was parsed without errors.
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The section that failed to parse, `None` if every section was parsed and the error, if
+    /// any, came from validation or reading trailing data.
+    pub fn failed_section(&self) -> Option<Section> {
+        self.failed_section
+    }
+
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_parts(self) -> (Pmx, Option<Error>) {
+        (self.pmx, self.error)
+    }
+}
+
+/// Counts the bytes read through it, so parse errors can point at where in the file they happened.
+struct OffsetReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+/// Attaches the byte offset and the element and field the error was attributed to, rendered like
+/// `Material[12].tex_idx`.
+fn locate(offset: u64, section: &str, error: Error) -> Error {
+    let (index, field, source) = match error {
+        Error::VertexError(e) => {
+            let (index, field, e) = e.split_context();
+            (index, field, Error::VertexError(e))
+        }
+        Error::Surface(e) => {
+            let (index, field, e) = e.split_context();
+            (index, field, Error::Surface(e))
+        }
+        Error::Texture(e) => {
+            let (index, field, e) = e.split_context();
+            (index, field, Error::Texture(e))
+        }
+        Error::Material(e) => {
+            let (index, field, e) = e.split_context();
+            (index, field, Error::Material(e))
+        }
+        Error::Field { field, source } => (None, Some(field), *source),
+        e => (None, None, e),
+    };
+
+    let mut location = section.to_owned();
+    if let Some(index) = index {
+        location.push_str(&format!("[{index}]"));
+    }
+    if let Some(field) = field {
+        location.push('.');
+        location.push_str(field);
+    }
+
+    Error::At {
+        offset,
+        location,
+        source: Box::new(source),
+    }
+}
+
+/// Attributes an error to a header field.
+fn field<T>(name: &'static str, parse: impl FnOnce() -> Result<T>) -> Result<T> {
+    parse().map_err(|source| Error::Field {
+        field: name,
+        source: Box::new(source),
+    })
+}
+
+/// Rejects features that were introduced after the file's version.
+pub(crate) fn check_vertices(version: Version, vertices: &vertex::Vertices) -> Result<()> {
+    for vertex in vertices.vertices() {
+        check_vertex(version, vertex)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_morphs(version: Version, morphs: &morph::Morphs) -> Result<()> {
+    for morph in morphs.morphs() {
+        check_morph(version, morph)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_joints(version: Version, joints: &joint::Joints) -> Result<()> {
+    for joint in joints.joints() {
+        check_joint(version, joint)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_vertex(version: Version, vertex: &vertex::Vertex) -> Result<()> {
+    if version < Version::V2_1
+        && matches!(vertex.weight_deform(), vertex::WeightDeform::Qdef { .. })
+    {
+        Err(Error::VersionMismatch {
+            version,
+            feature: "QDEF weight deform",
+        })?
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_morph(version: Version, morph: &morph::Morph) -> Result<()> {
+    let feature = match morph.offsets() {
+        morph::Offsets::Flip(_) => "Flip morphs",
+        morph::Offsets::Impulse(_) => "Impulse morphs",
+        _ => return Ok(()),
+    };
+
+    if version < Version::V2_1 {
+        Err(Error::VersionMismatch { version, feature })?
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_joint(version: Version, joint: &joint::Joint) -> Result<()> {
+    if version < Version::V2_1 && joint.joint_type() != joint::JointType::Spring6Dof {
+        Err(Error::VersionMismatch {
+            version,
+            feature: "Joint types other than spring 6DOF",
+        })?
+    }
+
+    Ok(())
+}
+
+/// The PMX format version.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Version {
+    V2_0,
+    /// Adds QDEF weights, vertex/flip/impulse morphs, more joint types and soft bodies.
+    V2_1,
+}
+
+impl Version {
+    pub fn as_f32(&self) -> f32 {
+        match self {
+            Version::V2_0 => 2.0,
+            Version::V2_1 => 2.1,
+        }
+    }
+}
+
+impl TryFrom<f32> for Version {
+    type Error = Error;
+
+    fn try_from(value: f32) -> Result<Self> {
+        // compare in tenths so slightly off floats written by other tools are still accepted
+        match (value * 10.0).round() as i32 {
+            20 => Ok(Self::V2_0),
+            21 => Ok(Self::V2_1),
+            _ => Err(Error::UnsupportedVersion(value)),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::V2_0 => write!(f, "2.0"),
+            Version::V2_1 => write!(f, "2.1"),
+        }
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
-    version: f32,
-    globals: Globals,
-    name: ModelName,
-    comment: Comment,
+    /// The file magic, kept since the 4th byte is not always a space in the wild.
+    pub(crate) tag: [u8; 4],
+    pub(crate) version: Version,
+    /// The version float as stored, written back as-is.
+    pub(crate) raw_version: f32,
+    pub(crate) globals: Globals,
+    pub(crate) name: ModelName,
+    pub(crate) comment: Comment,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModelName {
     pub local: PmxText,
     pub universal: PmxText,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Comment {
     pub local: PmxText,
     pub universal: PmxText,
 }
 
 impl Header {
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn raw_version(&self) -> f32 {
+        self.raw_version
+    }
+
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+
+    /// Only the additional globals can be changed, the others are tied to the model's contents.
+    pub fn globals_mut(&mut self) -> &mut Globals {
+        &mut self.globals
+    }
+
+    pub fn name(&self) -> &ModelName {
+        &self.name
+    }
+
+    pub fn comment(&self) -> &Comment {
+        &self.comment
+    }
+
     pub fn parse(r: &mut impl Read) -> Result<Self> {
+        Self::parse_with(r, ParseContext::UNCHECKED)
+    }
+
+    /// Parses the header, decoding the name and comment as the context says.
+    pub fn parse_with(r: &mut impl Read, context: ParseContext) -> Result<Self> {
         // 4 bytes since there's a space after
-        let mut tag = [0; 4];
+        let tag = field("tag", || {
+            let mut tag = [0; 4];
 
-        r.read_exact(&mut tag)?;
+            r.read_exact(&mut tag)?;
 
-        if &tag[..3] != b"PMX" {
-            Err(Error::InvalidTag)?
-        }
+            if &tag[..3] != b"PMX" {
+                Err(Error::InvalidTag)?
+            }
 
-        let mut ver = [0; 4];
+            Ok(tag)
+        })?;
 
-        r.read_exact(&mut ver)?;
+        let (raw_version, version) = field("version", || {
+            let mut ver = [0; 4];
 
-        let version = f32::from_le_bytes(ver);
+            r.read_exact(&mut ver)?;
 
-        let globals = Globals::parse(r)?;
+            let raw_version = f32::from_le_bytes(ver);
 
-        let text_encoding = globals.encoding;
+            Ok((raw_version, raw_version.try_into()?))
+        })?;
 
-        let local_name = PmxText::from_bytes(r, text_encoding)?;
+        let globals = field("globals", || Globals::parse(r))?;
 
-        let universal_name = PmxText::from_bytes(r, text_encoding)?;
+        let text_encoding = globals.encoding;
 
-        let name = ModelName {
-            local: local_name,
-            universal: universal_name,
-        };
+        let name = field("name", || {
+            let local = PmxText::from_bytes_with(r, text_encoding, context)?;
+            let universal = PmxText::from_bytes_with(r, text_encoding, context)?;
 
-        let local_comment = PmxText::from_bytes(r, text_encoding)?;
+            Ok(ModelName { local, universal })
+        })?;
 
-        let universal_comment = PmxText::from_bytes(r, text_encoding)?;
+        let comment = field("comment", || {
+            let local = PmxText::from_bytes_with(r, text_encoding, context)?;
+            let universal = PmxText::from_bytes_with(r, text_encoding, context)?;
 
-        let comment = Comment {
-            local: local_comment,
-            universal: universal_comment,
-        };
+            Ok(Comment { local, universal })
+        })?;
 
         Ok(Self {
+            tag,
             version,
+            raw_version,
             globals,
             name,
             comment,
         })
     }
+
+    pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        w.write_all(&self.tag)?;
+
+        write_f32(w, self.raw_version)?;
+
+        self.globals.write(w)?;
+
+        let text_encoding = self.globals.encoding;
+
+        self.name.local.write(w, text_encoding)?;
+
+        self.name.universal.write(w, text_encoding)?;
+
+        self.comment.local.write(w, text_encoding)?;
+
+        self.comment.universal.write(w, text_encoding)?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Globals {
-    encoding: TextEncoding,
-    vec4_additional: u8,
-    vert_idx_size: u8,
-    tex_idx_size: u8,
-    material_idx_size: u8,
-    bone_idx_size: u8,
-    morph_idx_size: u8,
-    rb_idx_size: u8,
-    /// Store additional fields here that we don't know the specific purpose of right now.
-    additional: Option<Vec<u8>>,
+    pub(crate) encoding: TextEncoding,
+    pub(crate) vec4_additional: u8,
+    pub(crate) vert_idx_size: u8,
+    pub(crate) tex_idx_size: u8,
+    pub(crate) material_idx_size: u8,
+    pub(crate) bone_idx_size: u8,
+    pub(crate) morph_idx_size: u8,
+    pub(crate) rb_idx_size: u8,
+    /// Globals past the first 8, kept as-is and written back unchanged, see [`GlobalsExtension`].
+    pub(crate) additional: Option<Vec<u8>>,
 }
 
 impl Globals {
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Sets the encoding the model is written with, text stored in another encoding is
+    /// re-encoded on write.
+    pub fn set_encoding(&mut self, encoding: TextEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Amount of additional vec4s per vertex, 0-4.
+    pub fn additional_vec4_count(&self) -> u8 {
+        self.vec4_additional
+    }
+
+    /// The additional UV channels the vertices have, one per additional vec4.
+    pub fn additional_uv_channels(&self) -> &'static [UvChannel] {
+        &UvChannel::ALL[..(self.vec4_additional as usize).min(4)]
+    }
+
+    pub fn vertex_index_size(&self) -> u8 {
+        self.vert_idx_size
+    }
+
+    pub fn texture_index_size(&self) -> u8 {
+        self.tex_idx_size
+    }
+
+    pub fn material_index_size(&self) -> u8 {
+        self.material_idx_size
+    }
+
+    pub fn bone_index_size(&self) -> u8 {
+        self.bone_idx_size
+    }
+
+    pub fn morph_index_size(&self) -> u8 {
+        self.morph_idx_size
+    }
+
+    pub fn rigid_body_index_size(&self) -> u8 {
+        self.rb_idx_size
+    }
+
+    /// Globals past the first 8, their meaning is unknown.
+    pub fn additional(&self) -> Option<&[u8]> {
+        self.additional.as_deref()
+    }
+
+    /// Replaces the globals past the first 8, at most 247 fit in the header.
+    pub fn set_additional(&mut self, additional: Option<Vec<u8>>) {
+        self.additional = additional.filter(|additional| !additional.is_empty());
+    }
+
+    /// Reads a value from the additional globals through the given interpretation, `None` if they
+    /// do not carry it.
+    pub fn extension<E: GlobalsExtension>(&self) -> Option<E::Value> {
+        E::decode(self.additional.as_deref().unwrap_or_default())
+    }
+
+    /// Stores a value into the additional globals through the given interpretation.
+    pub fn set_extension<E: GlobalsExtension>(&mut self, value: &E::Value) {
+        let mut additional = self.additional.take().unwrap_or_default();
+
+        E::encode(value, &mut additional);
+
+        self.set_additional(Some(additional));
+    }
+
     pub fn parse(r: &mut impl Read) -> Result<Self> {
         let mut global_count = [0; 1];
 
@@ -202,4 +1330,49 @@ impl Globals {
             additional,
         })
     }
+
+    pub fn write(&self, w: &mut impl Write) -> Result<()> {
+        let additional = self.additional.as_deref().unwrap_or_default();
+
+        let global_count =
+            u8::try_from(8 + additional.len()).map_err(|_| Error::InvalidGlobalCount)?;
+
+        write_u8(w, global_count)?;
+
+        let encoding = match self.encoding {
+            TextEncoding::UTF16LE => 0,
+            TextEncoding::UTF8 => 1,
+        };
+
+        w.write_all(&[
+            encoding,
+            self.vec4_additional,
+            self.vert_idx_size,
+            self.tex_idx_size,
+            self.material_idx_size,
+            self.bone_idx_size,
+            self.morph_idx_size,
+            self.rb_idx_size,
+        ])?;
+
+        w.write_all(additional)?;
+
+        Ok(())
+    }
+}
+
+/// An interpretation of the globals past the first 8.
+///
+/// The format leaves room for more globals but defines none, implementing this lets spec revisions
+/// or vendor extensions give them a meaning through [`Globals::extension`] and
+/// [`Globals::set_extension`] without changes to the crate. Bytes an extension does not touch are
+/// written back unchanged.
+pub trait GlobalsExtension {
+    type Value;
+
+    /// Decodes the value from the additional globals, `None` if they do not carry it.
+    fn decode(additional: &[u8]) -> Option<Self::Value>;
+
+    /// Encodes the value into the additional globals, growing them as needed.
+    fn encode(value: &Self::Value, additional: &mut Vec<u8>);
 }
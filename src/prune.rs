@@ -0,0 +1,385 @@
+//! Removing elements nothing refers to.
+//!
+//! [`Pmx::prune`] drops the vertices no surface uses and the textures no material uses, and with
+//! [`PruneOptions::set_bones`] the bones that can not affect the mesh. The surviving elements
+//! shift down to fill the gaps and every reference to them is updated, the returned [`Remap`]
+//! records where each element went so references from outside the model, like motions or
+//! materials of a renderer, can be updated the same way.
+//!
+//! A bone is kept if it has an IK solver, or if a vertex weight, another bone's parent, inherit
+//! parent, IK target or IK link, a bone morph or a rigid body refers to it. Tails and display frame
+//! entries do not count, tails pointing at a removed bone become nil and its display frame entries
+//! are dropped. Removing a bone can leave its parent unused, so they are removed until every
+//! remaining bone is used.
+
+use crate::{
+    material::Toon,
+    morph::Offsets,
+    pmx::Pmx,
+    types::{BoneIndex, TextureIndex, VertexIndex},
+};
+
+/// Settings for [`Pmx::prune`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PruneOptions {
+    pub(crate) bones: bool,
+}
+
+impl PruneOptions {
+    /// Options that only prune vertices and textures.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bones(&self) -> bool {
+        self.bones
+    }
+
+    /// Whether unused bones are pruned too, see the [module docs](crate::prune).
+    pub fn set_bones(&mut self, bones: bool) {
+        self.bones = bones;
+    }
+}
+
+/// Where the elements went, indexed by their old index, `None` for removed elements.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Remap {
+    pub(crate) vertices: Vec<Option<usize>>,
+    pub(crate) textures: Vec<Option<usize>>,
+    pub(crate) bones: Vec<Option<usize>>,
+}
+
+impl Remap {
+    pub fn vertices(&self) -> &[Option<usize>] {
+        &self.vertices
+    }
+
+    pub fn textures(&self) -> &[Option<usize>] {
+        &self.textures
+    }
+
+    pub fn bones(&self) -> &[Option<usize>] {
+        &self.bones
+    }
+
+    /// The new index of the vertex at `index`, `None` if it was removed or did not exist.
+    pub fn vertex(&self, index: usize) -> Option<usize> {
+        self.vertices.get(index).copied().flatten()
+    }
+
+    pub fn texture(&self, index: usize) -> Option<usize> {
+        self.textures.get(index).copied().flatten()
+    }
+
+    pub fn bone(&self, index: usize) -> Option<usize> {
+        self.bones.get(index).copied().flatten()
+    }
+
+    /// The number of vertices, textures and bones removed.
+    pub fn removed(&self) -> (usize, usize, usize) {
+        let count = |remap: &[Option<usize>]| remap.iter().filter(|i| i.is_none()).count();
+        (
+            count(&self.vertices),
+            count(&self.textures),
+            count(&self.bones),
+        )
+    }
+}
+
+impl Pmx {
+    /// Removes unused elements, see the [module docs](crate::prune).
+    pub fn prune(&mut self, options: &PruneOptions) -> Remap {
+        let vertices = self.prune_vertices();
+        let textures = self.prune_textures();
+        let bones = if options.bones {
+            self.prune_bones()
+        } else {
+            (0..self.bones.inner.len()).map(Some).collect()
+        };
+
+        Remap {
+            vertices,
+            textures,
+            bones,
+        }
+    }
+
+    fn prune_vertices(&mut self) -> Vec<Option<usize>> {
+        let count = self.vertices.inner.len();
+        let mut used = vec![false; count];
+        for surface in &self.surfaces.inner {
+            if let Some(vertex) = surface.index.as_usize().filter(|&i| i < count) {
+                used[vertex] = true;
+            }
+        }
+
+        let remap = retain(&mut self.vertices.inner, &used);
+        self.vertices.size = self.vertices.inner.len();
+        let removed = (count - self.vertices.inner.len()) as i32;
+
+        // references past the end stay past it
+        let map = |index: &VertexIndex| -> Option<VertexIndex> {
+            match index.as_usize() {
+                Some(i) if i < count => remap[i].map(|i| VertexIndex::new(i as i32)),
+                Some(_) => Some(VertexIndex::new(index.value() - removed)),
+                None => Some(*index),
+            }
+        };
+
+        for surface in &mut self.surfaces.inner {
+            // only vertices no surface refers to are removed
+            surface.index = map(&surface.index).unwrap();
+        }
+
+        for morph in &mut self.morphs.inner {
+            match &mut morph.offsets {
+                Offsets::Vertex(offsets) => {
+                    offsets.retain_mut(|o| map(&o.vertex).map(|vertex| o.vertex = vertex).is_some())
+                }
+                Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => {
+                    offsets.retain_mut(|o| map(&o.vertex).map(|vertex| o.vertex = vertex).is_some())
+                }
+                _ => {}
+            }
+        }
+
+        for soft_body in self.soft_bodies.iter_mut().flat_map(|s| &mut s.inner) {
+            soft_body.anchors.retain_mut(|anchor| {
+                map(&anchor.vertex)
+                    .map(|vertex| anchor.vertex = vertex)
+                    .is_some()
+            });
+            soft_body
+                .pinned_vertices
+                .retain_mut(|pinned| map(pinned).map(|vertex| *pinned = vertex).is_some());
+        }
+
+        remap
+    }
+
+    fn prune_textures(&mut self) -> Vec<Option<usize>> {
+        let count = self.textures.inner.len();
+        let mut used = vec![false; count];
+        for material in &self.materials.inner {
+            let toon = match &material.toon {
+                Toon::Texture(index) => Some(index),
+                Toon::Internal(_) => None,
+            };
+            let indices = [&material.tex_idx, &material.env_idx]
+                .into_iter()
+                .chain(toon);
+            for texture in indices.filter_map(|index| index.as_usize()) {
+                if texture < count {
+                    used[texture] = true;
+                }
+            }
+        }
+
+        let remap = retain(&mut self.textures.inner, &used);
+        self.textures.len = self.textures.inner.len();
+        let removed = (count - self.textures.inner.len()) as i32;
+
+        let map = |index: &mut TextureIndex| {
+            *index = match index.as_usize() {
+                Some(i) if i < count => TextureIndex::new(remap[i].unwrap() as i32),
+                Some(_) => TextureIndex::new(index.value() - removed),
+                None => *index,
+            };
+        };
+
+        for material in &mut self.materials.inner {
+            map(&mut material.tex_idx);
+            map(&mut material.env_idx);
+            if let Toon::Texture(index) = &mut material.toon {
+                map(index);
+            }
+        }
+
+        remap
+    }
+
+    fn prune_bones(&mut self) -> Vec<Option<usize>> {
+        // the original index of each remaining bone
+        let count = self.bones.inner.len();
+        let mut original: Vec<usize> = (0..count).collect();
+
+        loop {
+            let used = self.used_bones();
+            if used.iter().all(|&used| used) {
+                break;
+            }
+
+            // from the back so the indices of the other unused bones stay valid
+            for index in (0..used.len()).rev().filter(|&i| !used[i]) {
+                self.remove_bone(index);
+                original.remove(index);
+            }
+        }
+
+        let mut remap = vec![None; count];
+        for (new, &old) in original.iter().enumerate() {
+            remap[old] = Some(new);
+        }
+        remap
+    }
+
+    /// Which bones something besides a tail or display frame refers to.
+    fn used_bones(&self) -> Vec<bool> {
+        let count = self.bones.inner.len();
+        let mut used = vec![false; count];
+        let mut mark = |index: &BoneIndex| {
+            if let Some(bone) = index.as_usize().filter(|&i| i < count) {
+                used[bone] = true;
+            }
+        };
+
+        for vertex in &self.vertices.inner {
+            vertex
+                .weight_deform
+                .bone_indices()
+                .iter()
+                .for_each(&mut mark);
+        }
+
+        for bone in &self.bones.inner {
+            mark(&bone.parent);
+            if let Some(inherit) = &bone.inherit {
+                mark(&inherit.parent);
+            }
+            if let Some(ik) = &bone.ik {
+                mark(&ik.target);
+                ik.links.iter().for_each(|link| mark(&link.bone));
+            }
+        }
+
+        for morph in &self.morphs.inner {
+            if let Offsets::Bone(offsets) = &morph.offsets {
+                offsets.iter().for_each(|o| mark(&o.bone));
+            }
+        }
+
+        for rigid_body in &self.rigid_bodies.inner {
+            mark(&rigid_body.bone);
+        }
+
+        for (bone, used) in self.bones.inner.iter().zip(&mut used) {
+            *used |= bone.ik.is_some();
+        }
+
+        used
+    }
+}
+
+/// Keeps the items marked as used, returning the new index of each old one.
+fn retain<T>(items: &mut Vec<T>, used: &[bool]) -> Vec<Option<usize>> {
+    let mut kept = 0;
+    let remap = used
+        .iter()
+        .map(|&used| {
+            used.then(|| {
+                kept += 1;
+                kept - 1
+            })
+        })
+        .collect();
+
+    let mut index = 0;
+    items.retain(|_| {
+        index += 1;
+        used[index - 1]
+    });
+
+    remap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bone::Bone,
+        builder::PmxBuilder,
+        material::Material,
+        morph::{BoneOffset, Morph, Panel, VertexOffset},
+        types::{Name, TextEncoding, from_array},
+        vertex::{Vertex, WeightDeform},
+    };
+
+    #[test]
+    fn prune_remaps_surfaces_weights_and_morphs() {
+        let vertex = |x: f32| {
+            Vertex::new(
+                from_array([x, 0.0, 0.0]),
+                from_array([0.0, 0.0, 1.0]),
+                from_array([0.0; 2]),
+                WeightDeform::Bdef1 {
+                    index: BoneIndex::new(2),
+                },
+            )
+        };
+        let bone = |name: &str, parent: i32| {
+            let mut bone = Bone::new(name, from_array([0.0; 3]));
+            bone.set_parent(BoneIndex::new(parent));
+            bone
+        };
+        let mut pmx = PmxBuilder::new()
+            .add_bone(bone("root", -1))
+            .add_bone(bone("unused", 0))
+            .add_bone(bone("body", 0))
+            .add_vertices([0.0, 1.0, 2.0, 3.0].map(vertex))
+            .add_material(Material::new("body"), [[1, 2, 3]])
+            .build()
+            .unwrap();
+
+        let morph = |name: &str, offsets| Morph {
+            name: Name::new(name, name, TextEncoding::UTF16LE),
+            panel: Panel::Other,
+            offsets,
+        };
+        let vertex_offset = |vertex: i32| VertexOffset {
+            vertex: VertexIndex::new(vertex),
+            translation: from_array([0.0, 0.0, 1.0]),
+        };
+        let bone_offset = BoneOffset {
+            bone: BoneIndex::new(2),
+            translation: from_array([0.0, 1.0, 0.0]),
+            rotation: from_array([0.0, 0.0, 0.0, 1.0]),
+        };
+        pmx.morphs = vec![
+            morph(
+                "push",
+                Offsets::Vertex(vec![vertex_offset(0), vertex_offset(2)]),
+            ),
+            morph("lift", Offsets::Bone(vec![bone_offset])),
+        ]
+        .into();
+
+        let mut bytes = Vec::new();
+        pmx.write_to(&mut bytes).unwrap();
+        let mut pmx = Pmx::parse(&mut &bytes[..]).unwrap();
+
+        let mut options = PruneOptions::new();
+        options.set_bones(true);
+        let remap = pmx.prune(&options);
+        assert_eq!(remap.vertices(), [None, Some(0), Some(1), Some(2)]);
+        assert_eq!(remap.bones(), [Some(0), None, Some(1)]);
+
+        let surfaces: Vec<_> = pmx.surfaces.inner.iter().map(|s| s.index).collect();
+        assert_eq!(surfaces, [0, 1, 2].map(VertexIndex::new));
+
+        assert_eq!(pmx.bones.inner[1].parent, BoneIndex::new(0));
+        for vertex in &pmx.vertices.inner {
+            assert_eq!(vertex.weight_deform.bone_indices(), [BoneIndex::new(1)]);
+        }
+
+        let Offsets::Vertex(offsets) = &pmx.morphs.inner[0].offsets else {
+            panic!("not a vertex morph");
+        };
+        let targets: Vec<_> = offsets.iter().map(|o| o.vertex).collect();
+        assert_eq!(targets, [VertexIndex::new(1)]);
+
+        let Offsets::Bone(offsets) = &pmx.morphs.inner[1].offsets else {
+            panic!("not a bone morph");
+        };
+        assert_eq!(offsets[0].bone, BoneIndex::new(1));
+    }
+}
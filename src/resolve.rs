@@ -0,0 +1,118 @@
+//! Resolution of indices into the elements they refer to.
+
+use crate::{
+    bone::Bone,
+    material::{Material, Toon},
+    morph::Morph,
+    pmx::Pmx,
+    rigid_body::RigidBody,
+    texture::Texture,
+    types::{BoneIndex, MaterialIndex, MorphIndex, RigidBodyIndex, TextureIndex, VertexIndex},
+    vertex::Vertex,
+};
+
+/// An index that can be resolved against a [`Pmx`] into the element it refers to.
+pub trait Resolve {
+    type Target;
+
+    /// Returns the referenced element, or `None` if the index is nil or out of bounds.
+    fn resolve<'a>(&self, pmx: &'a Pmx) -> Option<&'a Self::Target>;
+}
+
+macro_rules! impl_resolve {
+    ($index:ty, $target:ty, $section:ident, $items:ident) => {
+        impl Resolve for $index {
+            type Target = $target;
+
+            fn resolve<'a>(&self, pmx: &'a Pmx) -> Option<&'a Self::Target> {
+                self.get(pmx.$section().$items())
+            }
+        }
+    };
+}
+
+impl_resolve!(VertexIndex, Vertex, vertices, vertices);
+impl_resolve!(TextureIndex, Texture, textures, textures);
+impl_resolve!(MaterialIndex, Material, materials, materials);
+impl_resolve!(BoneIndex, Bone, bones, bones);
+impl_resolve!(MorphIndex, Morph, morphs, morphs);
+impl_resolve!(RigidBodyIndex, RigidBody, rigid_bodies, rigid_bodies);
+
+impl Pmx {
+    /// Resolves any typed index into the element it refers to.
+    ///
+    /// Returns `None` if the index is nil or out of bounds.
+    pub fn resolve<I: Resolve>(&self, index: I) -> Option<&I::Target> {
+        index.resolve(self)
+    }
+
+    pub fn vertex(&self, index: VertexIndex) -> Option<&Vertex> {
+        self.resolve(index)
+    }
+
+    pub fn texture(&self, index: TextureIndex) -> Option<&Texture> {
+        self.resolve(index)
+    }
+
+    pub fn material(&self, index: MaterialIndex) -> Option<&Material> {
+        self.resolve(index)
+    }
+
+    pub fn bone(&self, index: BoneIndex) -> Option<&Bone> {
+        self.resolve(index)
+    }
+
+    pub fn morph(&self, index: MorphIndex) -> Option<&Morph> {
+        self.resolve(index)
+    }
+
+    pub fn rigid_body(&self, index: RigidBodyIndex) -> Option<&RigidBody> {
+        self.resolve(index)
+    }
+
+    /// The main texture of a material.
+    pub fn texture_of(&self, material: &Material) -> Option<&Texture> {
+        self.resolve(*material.texture_index())
+    }
+
+    /// The environment (sphere map) texture of a material.
+    pub fn environment_texture_of(&self, material: &Material) -> Option<&Texture> {
+        self.resolve(*material.environment_index())
+    }
+
+    /// The toon texture of a material, `None` for internal toon references.
+    pub fn toon_texture_of(&self, material: &Material) -> Option<&Texture> {
+        match material.toon() {
+            Toon::Texture(index) => self.resolve(*index),
+            Toon::Internal(_) => None,
+        }
+    }
+
+    /// The built-in toon texture of a material, also for texture references to a bare name like
+    /// `toon01.bmp`.
+    pub fn internal_toon_of(&self, material: &Material) -> Option<u8> {
+        match material.toon() {
+            Toon::Internal(index) => Some(*index),
+            Toon::Texture(index) => self
+                .resolve(*index)
+                .and_then(|texture| Toon::internal_index_of(texture.path().as_str())),
+        }
+    }
+
+    /// The bones influencing a vertex.
+    ///
+    /// Nil indices are skipped, out of bounds indices yield `None`.
+    pub fn bones_of<'a>(&'a self, vertex: &'a Vertex) -> impl Iterator<Item = Option<&'a Bone>> {
+        vertex
+            .weight_deform()
+            .bone_indices()
+            .iter()
+            .filter(|index| !index.is_nil())
+            .map(|index| self.resolve(*index))
+    }
+
+    /// The parent of a bone, `None` for root bones.
+    pub fn parent_of(&self, bone: &Bone) -> Option<&Bone> {
+        self.resolve(*bone.parent())
+    }
+}
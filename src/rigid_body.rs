@@ -0,0 +1,437 @@
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+use crate::types::{
+    BoneIndex, IndexSize, Name, ParseContext, TextEncoding, Vec3, read_f32, read_i32, read_u8,
+    read_u16, vec_from_bytes, vec_to_bytes, write_count, write_f32, write_u8, write_u16,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Negative size encountered where positive expected")]
+    NegativeSize,
+    #[error(transparent)]
+    Type(#[from] crate::types::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid rigid body shape encountered")]
+    InvalidShape,
+    #[error("Invalid physics mode encountered")]
+    InvalidPhysicsMode,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct RigidBodies {
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<RigidBody>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(RigidBodies, RigidBody, len);
+
+impl From<Vec<RigidBody>> for RigidBodies {
+    fn from(inner: Vec<RigidBody>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
+}
+
+impl RigidBodies {
+    pub fn len(&self) -> usize {
+        let len = self.inner.len();
+        debug_assert!(self.len == len);
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn rigid_bodies(&self) -> &[RigidBody] {
+        &self.inner
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size = read_i32(reader)?;
+
+        if size.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // name, bone index, shape and physics parameters
+        let size = context.check_count(size as usize, 69 + index_size as u64)?;
+
+        let mut inner_vec = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let rb = RigidBody::parse(reader, index_size, encoding, context)?;
+            inner_vec.push(rb);
+        }
+
+        Ok(Self {
+            len: size,
+            inner: inner_vec,
+        })
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for rb in &self.inner {
+            rb.write(writer, index_size, encoding)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Shape {
+    Sphere,
+    Box,
+    Capsule,
+}
+
+impl TryFrom<u8> for Shape {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Sphere),
+            1 => Ok(Self::Box),
+            2 => Ok(Self::Capsule),
+            _ => Err(Error::InvalidShape),
+        }
+    }
+}
+
+impl From<Shape> for u8 {
+    fn from(value: Shape) -> Self {
+        match value {
+            Shape::Sphere => 0,
+            Shape::Box => 1,
+            Shape::Capsule => 2,
+        }
+    }
+}
+
+/// How the rigid body interacts with its related bone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PhysicsMode {
+    /// The rigid body follows the bone (kinematic).
+    FollowBone,
+    /// The bone follows the simulated rigid body.
+    Physics,
+    /// Like `Physics`, but the bone's position stays aligned to its parent, only rotation is simulated.
+    PhysicsBone,
+}
+
+impl TryFrom<u8> for PhysicsMode {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::FollowBone),
+            1 => Ok(Self::Physics),
+            2 => Ok(Self::PhysicsBone),
+            _ => Err(Error::InvalidPhysicsMode),
+        }
+    }
+}
+
+impl From<PhysicsMode> for u8 {
+    fn from(value: PhysicsMode) -> Self {
+        match value {
+            PhysicsMode::FollowBone => 0,
+            PhysicsMode::Physics => 1,
+            PhysicsMode::PhysicsBone => 2,
+        }
+    }
+}
+
+/// The number of collision groups.
+pub const COLLISION_GROUPS: u8 = 16;
+
+/// The collision group of a rigid body, 0-15.
+///
+/// Files may hold larger values, which are kept as read and taken modulo 16 for collisions, as
+/// MMD does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct CollisionGroup {
+    pub(crate) raw: u8,
+}
+
+impl CollisionGroup {
+    /// The group, or `None` if it is 16 or above.
+    pub fn new(group: u8) -> Option<Self> {
+        (group < COLLISION_GROUPS).then_some(Self { raw: group })
+    }
+
+    pub fn from_raw(raw: u8) -> Self {
+        Self { raw }
+    }
+
+    pub fn raw(&self) -> u8 {
+        self.raw
+    }
+
+    /// The group number collisions use.
+    pub fn value(&self) -> u8 {
+        self.raw % COLLISION_GROUPS
+    }
+
+    /// Returns false if the raw value is out of the 0-15 range.
+    pub fn is_valid(&self) -> bool {
+        self.raw < COLLISION_GROUPS
+    }
+
+    /// The bit of the group in a [`CollisionMask`].
+    pub fn bit(&self) -> u16 {
+        1 << self.value()
+    }
+
+    /// All 16 groups, in order.
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..COLLISION_GROUPS).map(Self::from_raw)
+    }
+}
+
+/// The groups a rigid body collides with.
+///
+/// Bit `n` set means the body collides with the bodies of group `n`, a clear bit means it passes
+/// through them. Models usually have all bits set, clearing the ones of the groups to ignore, and
+/// so does the default.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct CollisionMask {
+    pub(crate) raw: u16,
+}
+
+impl Default for CollisionMask {
+    fn default() -> Self {
+        Self { raw: u16::MAX }
+    }
+}
+
+impl CollisionMask {
+    pub fn from_raw(raw: u16) -> Self {
+        Self { raw }
+    }
+
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+
+    /// Returns true if bodies of `group` are collided with.
+    pub fn collides_with(&self, group: CollisionGroup) -> bool {
+        self.raw & group.bit() != 0
+    }
+
+    pub fn set_collides_with(&mut self, group: CollisionGroup, collides: bool) {
+        if collides {
+            self.raw |= group.bit();
+        } else {
+            self.raw &= !group.bit();
+        }
+    }
+
+    /// The groups passed through, in order.
+    pub fn non_colliding_groups(&self) -> impl Iterator<Item = CollisionGroup> + '_ {
+        CollisionGroup::all().filter(|&group| !self.collides_with(group))
+    }
+
+    /// The bits of the groups collided with, which is the raw mask.
+    pub fn collision_bits(&self) -> u16 {
+        self.raw
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RigidBody {
+    pub(crate) name: Name,
+    pub(crate) bone: BoneIndex,
+    pub(crate) group: CollisionGroup,
+    /// Bit `n` set means this body collides with group `n`.
+    pub(crate) non_collision_mask: CollisionMask,
+    pub(crate) shape: Shape,
+    /// Meaning depends on the shape: sphere uses x as radius, box uses xyz as half extents,
+    /// capsule uses x as radius and y as height.
+    pub(crate) size: Vec3,
+    pub(crate) position: Vec3,
+    /// Euler angles in radians.
+    pub(crate) rotation: Vec3,
+    pub(crate) mass: f32,
+    pub(crate) linear_damping: f32,
+    pub(crate) angular_damping: f32,
+    pub(crate) repulsion: f32,
+    pub(crate) friction: f32,
+    pub(crate) physics_mode: PhysicsMode,
+}
+
+impl RigidBody {
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn bone(&self) -> &BoneIndex {
+        &self.bone
+    }
+
+    pub fn group(&self) -> CollisionGroup {
+        self.group
+    }
+
+    pub fn non_collision_mask(&self) -> CollisionMask {
+        self.non_collision_mask
+    }
+
+    /// Returns true if the two bodies collide, which needs each to collide with the other's group.
+    pub fn collides_with(&self, other: &RigidBody) -> bool {
+        self.non_collision_mask.collides_with(other.group)
+            && other.non_collision_mask.collides_with(self.group)
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.size
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Vec3 {
+        self.rotation
+    }
+
+    pub fn mass(&self) -> f32 {
+        self.mass
+    }
+
+    pub fn linear_damping(&self) -> f32 {
+        self.linear_damping
+    }
+
+    pub fn angular_damping(&self) -> f32 {
+        self.angular_damping
+    }
+
+    pub fn repulsion(&self) -> f32 {
+        self.repulsion
+    }
+
+    pub fn friction(&self) -> f32 {
+        self.friction
+    }
+
+    pub fn physics_mode(&self) -> PhysicsMode {
+        self.physics_mode
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        index_size: u8,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size: IndexSize = index_size.try_into()?;
+
+        let name = Name::parse(reader, encoding, context)?;
+
+        let bone = BoneIndex::parse(reader, size)?;
+
+        let group = CollisionGroup::from_raw(read_u8(reader)?);
+
+        let non_collision_mask = CollisionMask::from_raw(read_u16(reader)?);
+
+        let shape = read_u8(reader)?.try_into()?;
+
+        let shape_size: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        let position: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        let rotation: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        let mass = read_f32(reader)?;
+        let linear_damping = read_f32(reader)?;
+        let angular_damping = read_f32(reader)?;
+        let repulsion = read_f32(reader)?;
+        let friction = read_f32(reader)?;
+
+        let physics_mode = read_u8(reader)?.try_into()?;
+
+        Ok(Self {
+            name,
+            bone,
+            group,
+            non_collision_mask,
+            shape,
+            size: shape_size,
+            position,
+            rotation,
+            mass,
+            linear_damping,
+            angular_damping,
+            repulsion,
+            friction,
+            physics_mode,
+        })
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        index_size: u8,
+        encoding: TextEncoding,
+    ) -> Result<()> {
+        self.name.write(writer, encoding)?;
+
+        self.bone.write(writer, index_size.try_into()?)?;
+
+        write_u8(writer, self.group.raw)?;
+
+        write_u16(writer, self.non_collision_mask.raw)?;
+
+        write_u8(writer, self.shape.into())?;
+
+        vec_to_bytes!(Vec3, self.size, writer);
+
+        vec_to_bytes!(Vec3, self.position, writer);
+
+        vec_to_bytes!(Vec3, self.rotation, writer);
+
+        write_f32(writer, self.mass)?;
+        write_f32(writer, self.linear_damping)?;
+        write_f32(writer, self.angular_damping)?;
+        write_f32(writer, self.repulsion)?;
+        write_f32(writer, self.friction)?;
+
+        write_u8(writer, self.physics_mode.into())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+//! The bone hierarchy as a graph.
+//!
+//! Bones only store the index of their parent. [`Skeleton`] builds the reverse links once, so
+//! walking the hierarchy in either direction does not have to rescan every bone. Parent indices
+//! that are out of bounds are treated as nil, and bones whose parent chain loops are never
+//! reached from a root, see [`Skeleton::cycles`].
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+#[cfg(feature = "math_glam")]
+use glam::Mat4;
+
+use crate::{bone::Bone, pmx::Pmx};
+
+/// The parent/child links of the bones of a model, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct Skeleton<'a> {
+    pub(crate) bones: &'a [Bone],
+    parents: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    roots: Vec<usize>,
+    /// Whether following the parents of a bone ends at a root.
+    rooted: Vec<bool>,
+    cycles: Vec<Vec<usize>>,
+}
+
+impl<'a> Skeleton<'a> {
+    pub fn new(bones: &'a [Bone]) -> Self {
+        let parents: Vec<Option<usize>> = bones
+            .iter()
+            .map(|bone| bone.parent.as_usize().filter(|&p| p < bones.len()))
+            .collect();
+
+        let mut children = vec![Vec::new(); bones.len()];
+        let mut roots = Vec::new();
+        for (bone, parent) in parents.iter().enumerate() {
+            match parent {
+                Some(parent) => children[*parent].push(bone),
+                None => roots.push(bone),
+            }
+        }
+
+        let (rooted, cycles) = find_cycles(&parents);
+
+        Self {
+            bones,
+            parents,
+            children,
+            roots,
+            rooted,
+            cycles,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bones.is_empty()
+    }
+
+    pub fn bone(&self, bone: usize) -> Option<&'a Bone> {
+        self.bones.get(bone)
+    }
+
+    /// The parent of a bone, `None` for roots and bones that do not exist.
+    pub fn parent(&self, bone: usize) -> Option<usize> {
+        self.parents.get(bone).copied().flatten()
+    }
+
+    /// The bones whose parent is `bone`, in index order.
+    pub fn children(&self, bone: usize) -> &[usize] {
+        self.children.get(bone).map_or(&[], Vec::as_slice)
+    }
+
+    /// The bones without a parent, in index order.
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// The parent of `bone`, its parent and so on up to the root.
+    ///
+    /// Stops before repeating a bone if the chain loops.
+    pub fn ancestors(&self, bone: usize) -> Ancestors<'_> {
+        // only chains running into a cycle need to remember where they have been
+        let visited = (!self.is_rooted(bone)).then(|| vec![false; self.parents.len()]);
+
+        Ancestors {
+            parents: &self.parents,
+            current: bone,
+            visited,
+        }
+    }
+
+    /// The number of ancestors of `bone`, 0 for roots.
+    pub fn depth(&self, bone: usize) -> usize {
+        self.ancestors(bone).count()
+    }
+
+    /// Returns true if following the parents of `bone` ends at a root, false if it runs into a
+    /// cycle.
+    pub fn is_rooted(&self, bone: usize) -> bool {
+        self.rooted.get(bone).copied().unwrap_or(false)
+    }
+
+    /// Every bone reachable from a root, parents before their children.
+    ///
+    /// Roots and children are visited in index order, bones that are part of or lead into a
+    /// cycle are skipped.
+    pub fn depth_first(&self) -> DepthFirst<'_> {
+        DepthFirst {
+            children: &self.children,
+            stack: self.roots.iter().rev().copied().collect(),
+        }
+    }
+
+    /// The order MMD transforms bones in: by deform layer, bones deformed after physics after all
+    /// others, and in index order within a layer.
+    ///
+    /// Parents are not guaranteed to come before their children, models are expected to put
+    /// them on the right layers. [`evaluation_order`](Skeleton::evaluation_order) sorts them
+    /// within a layer.
+    pub fn deform_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.len()).collect();
+        order.sort_by_key(|&bone| {
+            let bone = &self.bones[bone];
+            (bone.flags.physics_after_deform(), bone.layer)
+        });
+
+        order
+    }
+
+    /// The order to evaluate bones in, like [`deform_order`](Skeleton::deform_order) but with
+    /// each bone after its parent and inherit parent, see [`EvaluationOrder`].
+    pub fn evaluation_order(&self) -> EvaluationOrder {
+        let len = self.len();
+        let key = |bone: usize| {
+            let bone = &self.bones[bone];
+            (bone.flags.physics_after_deform(), bone.layer)
+        };
+
+        let dependencies: Vec<Vec<(usize, Dependency)>> = (0..len)
+            .map(|bone| {
+                let inherit = (self.bones[bone].inherit.as_ref())
+                    .and_then(|inherit| inherit.parent.as_usize())
+                    .filter(|&p| p < len);
+                let mut dependencies = Vec::new();
+                if let Some(parent) = self.parents[bone] {
+                    dependencies.push((parent, Dependency::Parent));
+                }
+                if let Some(inherit) = inherit {
+                    dependencies.push((inherit, Dependency::Inherit));
+                }
+                dependencies
+            })
+            .collect();
+
+        let mut late = Vec::new();
+        let mut before_physics = Vec::new();
+        let mut after_physics = Vec::new();
+        let mut placed = vec![false; len];
+
+        for group in self.deform_order().chunk_by(|&a, &b| key(a) == key(b)) {
+            let in_group = |bone: usize| key(bone) == key(group[0]);
+            let mut waiting = vec![0; len];
+            let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); len];
+
+            for &bone in group {
+                for &(on, dependency) in &dependencies[bone] {
+                    if in_group(on) {
+                        waiting[bone] += 1;
+                        dependents[on].push(bone);
+                    } else if !placed[on] {
+                        late.push(LateDependency {
+                            bone,
+                            on,
+                            dependency,
+                        });
+                    }
+                }
+            }
+
+            let mut ready: BinaryHeap<Reverse<usize>> = (group.iter())
+                .filter(|&&bone| waiting[bone] == 0)
+                .map(|&bone| Reverse(bone))
+                .collect();
+            let order = if key(group[0]).0 {
+                &mut after_physics
+            } else {
+                &mut before_physics
+            };
+
+            for _ in 0..group.len() {
+                // a cycle leaves every remaining bone waiting, the lowest index goes first
+                let bone = match ready.pop() {
+                    Some(Reverse(bone)) => bone,
+                    None => *(group.iter())
+                        .filter(|&&bone| !placed[bone])
+                        .min()
+                        .expect("group has unplaced bones"),
+                };
+                placed[bone] = true;
+                order.push(bone);
+                for &dependent in &dependents[bone] {
+                    waiting[dependent] -= 1;
+                    if waiting[dependent] == 0 && !placed[dependent] {
+                        ready.push(Reverse(dependent));
+                    }
+                }
+            }
+        }
+
+        EvaluationOrder {
+            before_physics,
+            after_physics,
+            late,
+            cycles: dependency_cycles(&dependencies),
+        }
+    }
+
+    /// Every loop in the parent chains, each starting at its lowest index and listed in the
+    /// order parents are followed.
+    pub fn cycles(&self) -> &[Vec<usize>] {
+        &self.cycles
+    }
+
+    pub fn has_cycles(&self) -> bool {
+        !self.cycles.is_empty()
+    }
+}
+
+/// Transforms of the bind pose, the pose the model is modelled in.
+///
+/// PMX bones carry no rotation of their own, so in the bind pose each bone is only offset from
+/// its parent, and the world transform of a bone is the translation to its position.
+#[cfg(feature = "math_glam")]
+impl Skeleton<'_> {
+    /// The transform of each bone relative to its parent, roots relative to the model origin.
+    ///
+    /// Bones in or leading into a cycle are treated as roots.
+    pub fn local_transforms(&self) -> Vec<Mat4> {
+        (0..self.len())
+            .map(|bone| {
+                let position = self.bones[bone].position;
+                let parent = self.parent(bone).filter(|_| self.is_rooted(bone));
+
+                match parent {
+                    Some(parent) => Mat4::from_translation(position - self.bones[parent].position),
+                    None => Mat4::from_translation(position),
+                }
+            })
+            .collect()
+    }
+
+    /// The transform of each bone in model space, the local transforms composed down the
+    /// hierarchy.
+    pub fn world_transforms(&self) -> Vec<Mat4> {
+        let local = self.local_transforms();
+        let mut world = local.clone();
+
+        // the depth-first order visits parents before their children, bones it skips are roots
+        for bone in self.depth_first() {
+            if let Some(parent) = self.parent(bone) {
+                world[bone] = world[parent] * local[bone];
+            }
+        }
+
+        world
+    }
+
+    /// The inverse of each world transform, taking model space vertices into the space of the
+    /// bone for skinning.
+    pub fn inverse_bind_matrices(&self) -> Vec<Mat4> {
+        self.world_transforms().iter().map(Mat4::inverse).collect()
+    }
+}
+
+/// Follows the parents of every bone once, recording which chains end at a root and the loops
+/// found along the way.
+fn find_cycles(parents: &[Option<usize>]) -> (Vec<bool>, Vec<Vec<usize>>) {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Unvisited,
+        OnPath,
+        Done,
+    }
+
+    let mut state = vec![State::Unvisited; parents.len()];
+    let mut rooted = vec![false; parents.len()];
+    let mut cycles = Vec::new();
+
+    for start in 0..parents.len() {
+        let mut path = Vec::new();
+        let mut current = Some(start);
+
+        let ends_at_root = loop {
+            let Some(bone) = current else {
+                break true;
+            };
+
+            match state[bone] {
+                State::Done => break rooted[bone],
+                State::OnPath => {
+                    let from = path.iter().position(|&b| b == bone).unwrap_or_default();
+                    let mut cycle = path[from..].to_vec();
+
+                    let lowest = cycle
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|&(_, b)| *b)
+                        .map_or(0, |(i, _)| i);
+                    cycle.rotate_left(lowest);
+                    cycles.push(cycle);
+
+                    break false;
+                }
+                State::Unvisited => {
+                    state[bone] = State::OnPath;
+                    path.push(bone);
+                    current = parents[bone];
+                }
+            }
+        };
+
+        for bone in path {
+            state[bone] = State::Done;
+            rooted[bone] = ends_at_root;
+        }
+    }
+
+    (rooted, cycles)
+}
+
+/// What a bone needs evaluated before it, see [`LateDependency`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dependency {
+    /// The parent, whose transform the bone's is relative to.
+    Parent,
+    /// The inherit parent, whose rotation or translation the bone takes on.
+    Inherit,
+}
+
+/// A bone evaluated before a bone it depends on, as that one is on a later deform layer or
+/// deformed after physics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LateDependency {
+    pub(crate) bone: usize,
+    pub(crate) on: usize,
+    pub(crate) dependency: Dependency,
+}
+
+impl LateDependency {
+    pub fn bone(&self) -> usize {
+        self.bone
+    }
+
+    /// The bone depended on.
+    pub fn on(&self) -> usize {
+        self.on
+    }
+
+    pub fn dependency(&self) -> Dependency {
+        self.dependency
+    }
+}
+
+/// The order to evaluate bones in, see [`Skeleton::evaluation_order`].
+///
+/// Bones are evaluated by deform layer like MMD does, the ones deformed after physics after the
+/// simulation has run. Within a layer a bone comes after its parent and inherit parent, and
+/// otherwise in index order. A bone depending on one on a later layer can not be moved behind
+/// it, that would change how the model deforms in MMD, so it is only reported. So are the
+/// bones that depend on themselves through their parents and inherit parents, which are
+/// evaluated in index order when nothing else is left in their layer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvaluationOrder {
+    pub(crate) before_physics: Vec<usize>,
+    pub(crate) after_physics: Vec<usize>,
+    pub(crate) late: Vec<LateDependency>,
+    pub(crate) cycles: Vec<Vec<usize>>,
+}
+
+impl EvaluationOrder {
+    /// Every bone, in the order to evaluate them.
+    pub fn order(&self) -> impl Iterator<Item = usize> + '_ {
+        self.before_physics
+            .iter()
+            .chain(&self.after_physics)
+            .copied()
+    }
+
+    /// The bones evaluated before the physics simulation.
+    pub fn before_physics(&self) -> &[usize] {
+        &self.before_physics
+    }
+
+    /// The bones deformed after physics, evaluated after the simulation.
+    pub fn after_physics(&self) -> &[usize] {
+        &self.after_physics
+    }
+
+    /// The bones evaluated before a bone they depend on, in evaluation order.
+    pub fn late_dependencies(&self) -> &[LateDependency] {
+        &self.late
+    }
+
+    /// Every loop of parents and inherit parents, each starting at its lowest index and listed
+    /// in the order the dependencies are followed.
+    pub fn cycles(&self) -> &[Vec<usize>] {
+        &self.cycles
+    }
+
+    pub fn has_cycles(&self) -> bool {
+        !self.cycles.is_empty()
+    }
+}
+
+/// The loops of the dependency graph. Bones left over after taking away every bone whose
+/// dependencies can all be met are on a loop or depend on one, following the dependencies of a
+/// left over bone ends up going around a loop.
+fn dependency_cycles(dependencies: &[Vec<(usize, Dependency)>]) -> Vec<Vec<usize>> {
+    let len = dependencies.len();
+    let mut waiting: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); len];
+    for (bone, dependencies) in dependencies.iter().enumerate() {
+        for &(on, _) in dependencies {
+            dependents[on].push(bone);
+        }
+    }
+
+    let mut ready: Vec<usize> = (0..len).filter(|&bone| waiting[bone] == 0).collect();
+    while let Some(bone) = ready.pop() {
+        for &dependent in &dependents[bone] {
+            waiting[dependent] -= 1;
+            if waiting[dependent] == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+
+    let mut on_cycle = vec![false; len];
+    let mut cycles = Vec::new();
+    for start in (0..len).filter(|&bone| waiting[bone] > 0) {
+        let mut path = vec![start];
+        let mut current = start;
+
+        loop {
+            if on_cycle[current] {
+                break;
+            }
+            // a left over bone always has a left over dependency
+            let Some(&(next, _)) = (dependencies[current].iter()).find(|&&(on, _)| waiting[on] > 0)
+            else {
+                break;
+            };
+
+            if let Some(from) = path.iter().position(|&b| b == next) {
+                let mut cycle = path[from..].to_vec();
+                let lowest = (cycle.iter().enumerate())
+                    .min_by_key(|&(_, b)| *b)
+                    .map_or(0, |(i, _)| i);
+                cycle.rotate_left(lowest);
+                cycle.iter().for_each(|&bone| on_cycle[bone] = true);
+                cycles.push(cycle);
+                break;
+            }
+
+            path.push(next);
+            current = next;
+        }
+    }
+
+    cycles.sort();
+    cycles
+}
+
+/// Iterator returned by [`Skeleton::ancestors`].
+#[derive(Debug, Clone)]
+pub struct Ancestors<'s> {
+    parents: &'s [Option<usize>],
+    current: usize,
+    visited: Option<Vec<bool>>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let parent = self.parents.get(self.current).copied().flatten()?;
+
+        if let Some(visited) = &mut self.visited {
+            visited[self.current] = true;
+            if visited[parent] {
+                return None;
+            }
+        }
+
+        self.current = parent;
+
+        Some(parent)
+    }
+}
+
+/// Iterator returned by [`Skeleton::depth_first`].
+#[derive(Debug, Clone)]
+pub struct DepthFirst<'s> {
+    children: &'s [Vec<usize>],
+    stack: Vec<usize>,
+}
+
+impl Iterator for DepthFirst<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let bone = self.stack.pop()?;
+        self.stack.extend(self.children[bone].iter().rev());
+
+        Some(bone)
+    }
+}
+
+impl Pmx {
+    /// Builds the bone hierarchy, see [`Skeleton`].
+    pub fn skeleton(&self) -> Skeleton<'_> {
+        Skeleton::new(&self.bones.inner)
+    }
+}
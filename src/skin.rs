@@ -0,0 +1,268 @@
+//! Deforming the mesh on the CPU.
+//!
+//! A [`Pose`] holds a rotation and translation for each bone, relative to the bind pose.
+//! [`Pmx::skin`] moves every vertex along with the bones it is weighted to, using the blending
+//! its weight deform type asks for: linear for BDEF, spherical for SDEF and dual quaternion for
+//! QDEF.
+//!
+//! Bones that inherit the rotation or translation of another bone follow it on top of their own
+//! pose, and bones are transformed in [deform order](Skeleton::deform_order) like MMD does.
+//! There is no physics simulation, bones driven by rigid bodies keep their pose. IK chains are
+//! solved into the pose beforehand with [`Skeleton::solve_ik`].
+
+use glam::{Mat3, Mat4, Quat, Vec3};
+
+use crate::{
+    pmx::Pmx,
+    skeleton::Skeleton,
+    types::BoneIndex,
+    vertex::{SdefParams, Vertex, WeightDeform},
+};
+
+/// Per-bone rotations and translations relative to the bind pose.
+///
+/// Bones without an entry stay in the bind pose.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pose {
+    bones: Vec<BonePose>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct BonePose {
+    rotation: Quat,
+    translation: Vec3,
+}
+
+impl Pose {
+    /// The bind pose.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether no bone has an entry, leaving every bone in the bind pose.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bones.is_empty()
+    }
+
+    pub fn rotation(&self, bone: usize) -> Quat {
+        self.bones.get(bone).map_or(Quat::IDENTITY, |b| b.rotation)
+    }
+
+    pub fn translation(&self, bone: usize) -> Vec3 {
+        self.bones.get(bone).map_or(Vec3::ZERO, |b| b.translation)
+    }
+
+    /// Sets the rotation of a bone around its own position, in its parent's space.
+    pub fn set_rotation(&mut self, bone: usize, rotation: Quat) {
+        self.entry(bone).rotation = rotation;
+    }
+
+    /// Sets how far a bone is moved from its bind position, in its parent's space.
+    pub fn set_translation(&mut self, bone: usize, translation: Vec3) {
+        self.entry(bone).translation = translation;
+    }
+
+    fn entry(&mut self, bone: usize) -> &mut BonePose {
+        if bone >= self.bones.len() {
+            self.bones.resize(bone + 1, BonePose::default());
+        }
+
+        &mut self.bones[bone]
+    }
+}
+
+impl Skeleton<'_> {
+    /// The model space transform of each bone with `pose` applied.
+    ///
+    /// A bone inheriting from another adds the other bone's rotation and translation, scaled by
+    /// the inherit weight, to its own. Local inheritance takes only the other bone's pose, global
+    /// inheritance its pose including what it inherited itself.
+    pub fn pose_transforms(&self, pose: &Pose) -> Vec<Mat4> {
+        let bind = self.local_transforms();
+
+        let mut rotations: Vec<Quat> = (0..self.len()).map(|b| pose.rotation(b)).collect();
+        let mut translations: Vec<Vec3> = (0..self.len()).map(|b| pose.translation(b)).collect();
+        // bones whose parent comes later in the deform order see its bind pose
+        let mut world = self.world_transforms();
+
+        for bone in self.deform_order() {
+            let flags = self.bones[bone].flags;
+
+            if let Some(inherit) = &self.bones[bone].inherit
+                && let Some(source) = inherit.parent.as_usize().filter(|&s| s < self.len())
+            {
+                let local = flags.inherit_local();
+
+                if flags.inherit_rotation() {
+                    let rotation = if local {
+                        pose.rotation(source)
+                    } else {
+                        rotations[source]
+                    };
+                    rotations[bone] *= scale_rotation(rotation, inherit.weight);
+                }
+                if flags.inherit_translation() {
+                    let translation = if local {
+                        pose.translation(source)
+                    } else {
+                        translations[source]
+                    };
+                    translations[bone] += translation * inherit.weight;
+                }
+            }
+
+            let local =
+                bind[bone] * Mat4::from_rotation_translation(rotations[bone], translations[bone]);
+
+            world[bone] = match self.parent(bone).filter(|_| self.is_rooted(bone)) {
+                Some(parent) => world[parent] * local,
+                None => local,
+            };
+        }
+
+        world
+    }
+
+    /// The matrices taking bind pose vertices to their place in `pose`, one per bone.
+    pub fn skinning_matrices(&self, pose: &Pose) -> Vec<Mat4> {
+        self.pose_transforms(pose)
+            .into_iter()
+            .zip(self.inverse_bind_matrices())
+            .map(|(world, inverse_bind)| world * inverse_bind)
+            .collect()
+    }
+}
+
+/// The rotation `weight` times as far as `rotation`, negative weights rotate the other way.
+pub(crate) fn scale_rotation(rotation: Quat, weight: f32) -> Quat {
+    // the shorter way around, so half of a rotation by 350° is not 175°
+    let rotation = if rotation.w < 0.0 {
+        -rotation
+    } else {
+        rotation
+    };
+    let (axis, angle) = rotation.to_axis_angle();
+
+    Quat::from_axis_angle(axis, angle * weight)
+}
+
+impl Pmx {
+    /// The positions of every vertex with `pose` applied.
+    ///
+    /// Weights referring to bones that do not exist leave their share of the vertex in place.
+    pub fn skin(&self, pose: &Pose) -> Vec<Vec3> {
+        let matrices = self.skeleton().skinning_matrices(pose);
+
+        self.vertices
+            .inner
+            .iter()
+            .map(|vertex| skin_vertex(vertex, &matrices))
+            .collect()
+    }
+}
+
+fn skin_vertex(vertex: &Vertex, matrices: &[Mat4]) -> Vec3 {
+    let matrix = |index: BoneIndex| {
+        index
+            .as_usize()
+            .and_then(|index| matrices.get(index))
+            .copied()
+            .unwrap_or(Mat4::IDENTITY)
+    };
+    let position = vertex.pos;
+
+    match &vertex.weight_deform {
+        WeightDeform::Bdef1 { index } => matrix(*index).transform_point3(position),
+        WeightDeform::Bdef2 { indices, weights } => linear(position, indices, weights, matrix),
+        WeightDeform::Bdef4 { indices, weights } => linear(position, indices, weights, matrix),
+        WeightDeform::Sdef {
+            indices,
+            weights,
+            c,
+            r0,
+            r1,
+        } => {
+            let params = SdefParams {
+                c: *c,
+                r0: *r0,
+                r1: *r1,
+            };
+            spherical(position, indices.map(matrix), *weights, &params)
+        }
+        WeightDeform::Qdef { indices, weights } => {
+            dual_quaternion(position, indices.map(matrix), *weights)
+        }
+    }
+}
+
+fn linear(
+    position: Vec3,
+    indices: &[BoneIndex],
+    weights: &[f32],
+    matrix: impl Fn(BoneIndex) -> Mat4,
+) -> Vec3 {
+    indices
+        .iter()
+        .zip(weights)
+        .map(|(index, &weight)| matrix(*index).transform_point3(position) * weight)
+        .sum()
+}
+
+/// Rotates around the SDEF center with the blended bone rotation, so joints bend without the
+/// volume loss of linear blending.
+fn spherical(position: Vec3, matrices: [Mat4; 2], weights: [f32; 2], params: &SdefParams) -> Vec3 {
+    let [w0, w1] = weights;
+    let SdefParams { c, r0, r1 } = *params;
+
+    // the reference points are corrected so their weighted average lies on the center
+    let rw = r0 * w0 + r1 * w1;
+    let r0 = c + r0 - rw;
+    let r1 = c + r1 - rw;
+    let cr0 = (c + r0) * 0.5;
+    let cr1 = (c + r1) * 0.5;
+
+    let q0 = Quat::from_mat4(&matrices[0]);
+    let q1 = Quat::from_mat4(&matrices[1]);
+    let rotation = Mat3::from_quat(q0.slerp(q1, w1));
+
+    rotation * (position - c)
+        + matrices[0].transform_point3(cr0) * w0
+        + matrices[1].transform_point3(cr1) * w1
+}
+
+/// Blends the bone transforms as dual quaternions, which keeps twisting bones from collapsing
+/// the mesh.
+fn dual_quaternion(position: Vec3, matrices: [Mat4; 4], weights: [f32; 4]) -> Vec3 {
+    let mut real = Quat::from_xyzw(0.0, 0.0, 0.0, 0.0);
+    let mut dual = Quat::from_xyzw(0.0, 0.0, 0.0, 0.0);
+    let mut first: Option<Quat> = None;
+
+    for (matrix, weight) in matrices.iter().zip(weights) {
+        if weight == 0.0 {
+            continue;
+        }
+
+        let (_, mut rotation, translation) = matrix.to_scale_rotation_translation();
+
+        // q and -q are the same rotation, blending has to pick the same hemisphere for all
+        let reference = *first.get_or_insert(rotation);
+        if reference.dot(rotation) < 0.0 {
+            rotation = -rotation;
+        }
+
+        let translation = Quat::from_xyzw(translation.x, translation.y, translation.z, 0.0);
+        real += rotation * weight;
+        dual += translation * rotation * (0.5 * weight);
+    }
+
+    let length = real.length();
+    if length == 0.0 {
+        return position;
+    }
+
+    let real = real / length;
+    let dual = dual / length;
+    let translation = dual * real.conjugate() * 2.0;
+
+    real * position + Vec3::new(translation.x, translation.y, translation.z)
+}
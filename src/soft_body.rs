@@ -0,0 +1,715 @@
+use std::io::{Read, Write};
+
+use thiserror::Error;
+
+use crate::{
+    pmx::Globals,
+    types::{
+        IndexSize, MaterialIndex, Name, ParseContext, RigidBodyIndex, VertexIndex, read_f32,
+        read_i32, read_u8, read_u16, write_count, write_f32, write_i32, write_u8, write_u16,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Negative size encountered where positive expected")]
+    NegativeSize,
+    #[error(transparent)]
+    Type(#[from] crate::types::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid soft body shape encountered")]
+    InvalidShape,
+    #[error("Invalid aerodynamics model encountered")]
+    InvalidAeroModel,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The soft body section, only present in PMX 2.1 files.
+#[derive(Debug)]
+pub struct SoftBodies {
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<SoftBody>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(SoftBodies, SoftBody, len);
+
+impl From<Vec<SoftBody>> for SoftBodies {
+    fn from(inner: Vec<SoftBody>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
+}
+
+impl SoftBodies {
+    pub fn len(&self) -> usize {
+        let len = self.inner.len();
+        debug_assert!(self.len == len);
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn soft_bodies(&self) -> &[SoftBody] {
+        &self.inner
+    }
+
+    pub fn parse(reader: &mut impl Read, globals: &Globals, context: ParseContext) -> Result<Self> {
+        let size = read_i32(reader)?;
+
+        if size.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // everything but the anchors and pinned vertices
+        let size = context.check_count(size as usize, 142)?;
+
+        let mut inner_vec = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let soft_body = SoftBody::parse(reader, globals, context)?;
+            inner_vec.push(soft_body);
+        }
+
+        Ok(Self {
+            len: size,
+            inner: inner_vec,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, globals: &Globals) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for soft_body in &self.inner {
+            soft_body.write(writer, globals)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Shape {
+    TriMesh,
+    Rope,
+}
+
+impl TryFrom<u8> for Shape {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::TriMesh),
+            1 => Ok(Self::Rope),
+            _ => Err(Error::InvalidShape),
+        }
+    }
+}
+
+impl From<Shape> for u8 {
+    fn from(value: Shape) -> Self {
+        match value {
+            Shape::TriMesh => 0,
+            Shape::Rope => 1,
+        }
+    }
+}
+
+/// Soft body construction flags.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoftBodyFlags {
+    pub(crate) raw: u8,
+}
+
+impl SoftBodyFlags {
+    pub fn from_raw(raw: u8) -> Self {
+        Self { raw }
+    }
+
+    pub fn raw(&self) -> u8 {
+        self.raw
+    }
+
+    /// Generate bending constraints, using `b_link_distance`.
+    pub const B_LINK: u8 = 0x01;
+    /// Generate clusters, using `cluster_count`.
+    pub const CLUSTER_CREATION: u8 = 0x02;
+    pub const LINK_CROSSING: u8 = 0x04;
+
+    /// Returns true if every bit of `flag` is set.
+    pub fn contains(&self, flag: u8) -> bool {
+        self.raw & flag == flag
+    }
+
+    pub fn b_link(&self) -> bool {
+        self.contains(Self::B_LINK)
+    }
+
+    pub fn cluster_creation(&self) -> bool {
+        self.contains(Self::CLUSTER_CREATION)
+    }
+
+    pub fn link_crossing(&self) -> bool {
+        self.contains(Self::LINK_CROSSING)
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            raw: read_u8(reader)?,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_u8(writer, self.raw)?;
+
+        Ok(())
+    }
+}
+
+/// Bullet's `btSoftBody::eAeroModel`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AeroModel {
+    VertexPoint,
+    VertexTwoSided,
+    VertexOneSided,
+    FaceTwoSided,
+    FaceOneSided,
+}
+
+impl TryFrom<i32> for AeroModel {
+    type Error = Error;
+
+    fn try_from(value: i32) -> Result<Self> {
+        match value {
+            0 => Ok(Self::VertexPoint),
+            1 => Ok(Self::VertexTwoSided),
+            2 => Ok(Self::VertexOneSided),
+            3 => Ok(Self::FaceTwoSided),
+            4 => Ok(Self::FaceOneSided),
+            _ => Err(Error::InvalidAeroModel),
+        }
+    }
+}
+
+impl From<AeroModel> for i32 {
+    fn from(value: AeroModel) -> Self {
+        match value {
+            AeroModel::VertexPoint => 0,
+            AeroModel::VertexTwoSided => 1,
+            AeroModel::VertexOneSided => 2,
+            AeroModel::FaceTwoSided => 3,
+            AeroModel::FaceOneSided => 4,
+        }
+    }
+}
+
+/// Bullet soft body configuration coefficients, named after their `btSoftBody::Config` counterparts.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Config {
+    /// kVCF
+    pub(crate) velocity_correction: f32,
+    /// kDP
+    pub(crate) damping: f32,
+    /// kDG
+    pub(crate) drag: f32,
+    /// kLF
+    pub(crate) lift: f32,
+    /// kPR
+    pub(crate) pressure: f32,
+    /// kVC
+    pub(crate) volume_conservation: f32,
+    /// kDF
+    pub(crate) dynamic_friction: f32,
+    /// kMT
+    pub(crate) pose_matching: f32,
+    /// kCHR
+    pub(crate) rigid_contact_hardness: f32,
+    /// kKHR
+    pub(crate) kinetic_contact_hardness: f32,
+    /// kSHR
+    pub(crate) soft_contact_hardness: f32,
+    /// kAHR
+    pub(crate) anchor_hardness: f32,
+}
+
+impl Config {
+    pub fn velocity_correction(&self) -> f32 {
+        self.velocity_correction
+    }
+
+    pub fn damping(&self) -> f32 {
+        self.damping
+    }
+
+    pub fn drag(&self) -> f32 {
+        self.drag
+    }
+
+    pub fn lift(&self) -> f32 {
+        self.lift
+    }
+
+    pub fn pressure(&self) -> f32 {
+        self.pressure
+    }
+
+    pub fn volume_conservation(&self) -> f32 {
+        self.volume_conservation
+    }
+
+    pub fn dynamic_friction(&self) -> f32 {
+        self.dynamic_friction
+    }
+
+    pub fn pose_matching(&self) -> f32 {
+        self.pose_matching
+    }
+
+    pub fn rigid_contact_hardness(&self) -> f32 {
+        self.rigid_contact_hardness
+    }
+
+    pub fn kinetic_contact_hardness(&self) -> f32 {
+        self.kinetic_contact_hardness
+    }
+
+    pub fn soft_contact_hardness(&self) -> f32 {
+        self.soft_contact_hardness
+    }
+
+    pub fn anchor_hardness(&self) -> f32 {
+        self.anchor_hardness
+    }
+}
+
+/// Cluster related coefficients.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClusterConfig {
+    /// kSRHR_CL
+    pub(crate) soft_rigid_hardness: f32,
+    /// kSKHR_CL
+    pub(crate) soft_kinetic_hardness: f32,
+    /// kSSHR_CL
+    pub(crate) soft_soft_hardness: f32,
+    /// kSR_SPLT_CL
+    pub(crate) soft_rigid_impulse_split: f32,
+    /// kSK_SPLT_CL
+    pub(crate) soft_kinetic_impulse_split: f32,
+    /// kSS_SPLT_CL
+    pub(crate) soft_soft_impulse_split: f32,
+}
+
+impl ClusterConfig {
+    pub fn soft_rigid_hardness(&self) -> f32 {
+        self.soft_rigid_hardness
+    }
+
+    pub fn soft_kinetic_hardness(&self) -> f32 {
+        self.soft_kinetic_hardness
+    }
+
+    pub fn soft_soft_hardness(&self) -> f32 {
+        self.soft_soft_hardness
+    }
+
+    pub fn soft_rigid_impulse_split(&self) -> f32 {
+        self.soft_rigid_impulse_split
+    }
+
+    pub fn soft_kinetic_impulse_split(&self) -> f32 {
+        self.soft_kinetic_impulse_split
+    }
+
+    pub fn soft_soft_impulse_split(&self) -> f32 {
+        self.soft_soft_impulse_split
+    }
+}
+
+/// Solver iteration counts.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Iterations {
+    pub(crate) velocity: i32,
+    pub(crate) position: i32,
+    pub(crate) drift: i32,
+    pub(crate) cluster: i32,
+}
+
+impl Iterations {
+    pub fn velocity(&self) -> i32 {
+        self.velocity
+    }
+
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    pub fn drift(&self) -> i32 {
+        self.drift
+    }
+
+    pub fn cluster(&self) -> i32 {
+        self.cluster
+    }
+}
+
+/// Soft body material stiffness coefficients.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaterialConfig {
+    /// kLST
+    pub(crate) linear_stiffness: f32,
+    /// kAST
+    pub(crate) angular_stiffness: f32,
+    /// kVST
+    pub(crate) volume_stiffness: f32,
+}
+
+impl MaterialConfig {
+    pub fn linear_stiffness(&self) -> f32 {
+        self.linear_stiffness
+    }
+
+    pub fn angular_stiffness(&self) -> f32 {
+        self.angular_stiffness
+    }
+
+    pub fn volume_stiffness(&self) -> f32 {
+        self.volume_stiffness
+    }
+}
+
+/// Attaches a soft body vertex to a rigid body.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Anchor {
+    pub(crate) rigid_body: RigidBodyIndex,
+    pub(crate) vertex: VertexIndex,
+    pub(crate) near_mode: bool,
+}
+
+impl Anchor {
+    pub fn rigid_body(&self) -> &RigidBodyIndex {
+        &self.rigid_body
+    }
+
+    pub fn vertex(&self) -> &VertexIndex {
+        &self.vertex
+    }
+
+    pub fn is_near_mode(&self) -> bool {
+        self.near_mode
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoftBody {
+    pub(crate) name: Name,
+    pub(crate) shape: Shape,
+    pub(crate) material: MaterialIndex,
+    pub(crate) group: u8,
+    pub(crate) non_collision_mask: u16,
+    pub(crate) flags: SoftBodyFlags,
+    pub(crate) b_link_distance: i32,
+    pub(crate) cluster_count: i32,
+    pub(crate) total_mass: f32,
+    pub(crate) collision_margin: f32,
+    pub(crate) aero_model: AeroModel,
+    pub(crate) config: Config,
+    pub(crate) cluster: ClusterConfig,
+    pub(crate) iterations: Iterations,
+    pub(crate) material_config: MaterialConfig,
+    pub(crate) anchors: Vec<Anchor>,
+    pub(crate) pinned_vertices: Vec<VertexIndex>,
+}
+
+impl SoftBody {
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    pub fn material(&self) -> &MaterialIndex {
+        &self.material
+    }
+
+    pub fn group(&self) -> u8 {
+        self.group
+    }
+
+    pub fn non_collision_mask(&self) -> u16 {
+        self.non_collision_mask
+    }
+
+    pub fn flags(&self) -> SoftBodyFlags {
+        self.flags
+    }
+
+    pub fn b_link_distance(&self) -> i32 {
+        self.b_link_distance
+    }
+
+    pub fn cluster_count(&self) -> i32 {
+        self.cluster_count
+    }
+
+    pub fn total_mass(&self) -> f32 {
+        self.total_mass
+    }
+
+    pub fn collision_margin(&self) -> f32 {
+        self.collision_margin
+    }
+
+    pub fn aero_model(&self) -> AeroModel {
+        self.aero_model
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn cluster(&self) -> &ClusterConfig {
+        &self.cluster
+    }
+
+    pub fn iterations(&self) -> &Iterations {
+        &self.iterations
+    }
+
+    pub fn material_config(&self) -> &MaterialConfig {
+        &self.material_config
+    }
+
+    pub fn anchors(&self) -> &[Anchor] {
+        &self.anchors
+    }
+
+    pub fn pinned_vertices(&self) -> &[VertexIndex] {
+        &self.pinned_vertices
+    }
+
+    pub fn parse(reader: &mut impl Read, globals: &Globals, context: ParseContext) -> Result<Self> {
+        let material_size: IndexSize = globals.material_idx_size.try_into()?;
+        let rb_size: IndexSize = globals.rb_idx_size.try_into()?;
+        let vertex_size: IndexSize = globals.vert_idx_size.try_into()?;
+
+        let name = Name::parse(reader, globals.encoding, context)?;
+
+        let shape = read_u8(reader)?.try_into()?;
+
+        let material = MaterialIndex::parse(reader, material_size)?;
+
+        let group = read_u8(reader)?;
+
+        let non_collision_mask = read_u16(reader)?;
+
+        let flags = SoftBodyFlags::parse(reader)?;
+
+        let b_link_distance = read_i32(reader)?;
+        let cluster_count = read_i32(reader)?;
+        let total_mass = read_f32(reader)?;
+        let collision_margin = read_f32(reader)?;
+
+        let aero_model = read_i32(reader)?.try_into()?;
+
+        let config = Config {
+            velocity_correction: read_f32(reader)?,
+            damping: read_f32(reader)?,
+            drag: read_f32(reader)?,
+            lift: read_f32(reader)?,
+            pressure: read_f32(reader)?,
+            volume_conservation: read_f32(reader)?,
+            dynamic_friction: read_f32(reader)?,
+            pose_matching: read_f32(reader)?,
+            rigid_contact_hardness: read_f32(reader)?,
+            kinetic_contact_hardness: read_f32(reader)?,
+            soft_contact_hardness: read_f32(reader)?,
+            anchor_hardness: read_f32(reader)?,
+        };
+
+        let cluster = ClusterConfig {
+            soft_rigid_hardness: read_f32(reader)?,
+            soft_kinetic_hardness: read_f32(reader)?,
+            soft_soft_hardness: read_f32(reader)?,
+            soft_rigid_impulse_split: read_f32(reader)?,
+            soft_kinetic_impulse_split: read_f32(reader)?,
+            soft_soft_impulse_split: read_f32(reader)?,
+        };
+
+        let iterations = Iterations {
+            velocity: read_i32(reader)?,
+            position: read_i32(reader)?,
+            drift: read_i32(reader)?,
+            cluster: read_i32(reader)?,
+        };
+
+        let material_config = MaterialConfig {
+            linear_stiffness: read_f32(reader)?,
+            angular_stiffness: read_f32(reader)?,
+            volume_stiffness: read_f32(reader)?,
+        };
+
+        let anchor_count = read_i32(reader)?;
+
+        if anchor_count.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        // two indices and the near mode flag
+        let anchor_count = context.check_count(anchor_count as usize, 3)?;
+
+        let mut anchors = Vec::with_capacity(anchor_count);
+
+        for _ in 0..anchor_count {
+            let rigid_body = RigidBodyIndex::parse(reader, rb_size)?;
+            let vertex = VertexIndex::parse(reader, vertex_size)?;
+            let near_mode = read_u8(reader)? != 0;
+
+            anchors.push(Anchor {
+                rigid_body,
+                vertex,
+                near_mode,
+            });
+        }
+
+        let pin_count = read_i32(reader)?;
+
+        if pin_count.is_negative() {
+            Err(Error::NegativeSize)?
+        }
+
+        let pin_count = context.check_count(pin_count as usize, 1)?;
+
+        let mut pinned_vertices = Vec::with_capacity(pin_count);
+
+        for _ in 0..pin_count {
+            pinned_vertices.push(VertexIndex::parse(reader, vertex_size)?);
+        }
+
+        Ok(Self {
+            name,
+            shape,
+            material,
+            group,
+            non_collision_mask,
+            flags,
+            b_link_distance,
+            cluster_count,
+            total_mass,
+            collision_margin,
+            aero_model,
+            config,
+            cluster,
+            iterations,
+            material_config,
+            anchors,
+            pinned_vertices,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, globals: &Globals) -> Result<()> {
+        let material_size: IndexSize = globals.material_idx_size.try_into()?;
+        let rb_size: IndexSize = globals.rb_idx_size.try_into()?;
+        let vertex_size: IndexSize = globals.vert_idx_size.try_into()?;
+
+        self.name.write(writer, globals.encoding)?;
+
+        write_u8(writer, self.shape.into())?;
+
+        self.material.write(writer, material_size)?;
+
+        write_u8(writer, self.group)?;
+
+        write_u16(writer, self.non_collision_mask)?;
+
+        self.flags.write(writer)?;
+
+        write_i32(writer, self.b_link_distance)?;
+        write_i32(writer, self.cluster_count)?;
+        write_f32(writer, self.total_mass)?;
+        write_f32(writer, self.collision_margin)?;
+
+        write_i32(writer, self.aero_model.into())?;
+
+        let config = &self.config;
+        for value in [
+            config.velocity_correction,
+            config.damping,
+            config.drag,
+            config.lift,
+            config.pressure,
+            config.volume_conservation,
+            config.dynamic_friction,
+            config.pose_matching,
+            config.rigid_contact_hardness,
+            config.kinetic_contact_hardness,
+            config.soft_contact_hardness,
+            config.anchor_hardness,
+        ] {
+            write_f32(writer, value)?;
+        }
+
+        let cluster = &self.cluster;
+        for value in [
+            cluster.soft_rigid_hardness,
+            cluster.soft_kinetic_hardness,
+            cluster.soft_soft_hardness,
+            cluster.soft_rigid_impulse_split,
+            cluster.soft_kinetic_impulse_split,
+            cluster.soft_soft_impulse_split,
+        ] {
+            write_f32(writer, value)?;
+        }
+
+        let iterations = &self.iterations;
+        for value in [
+            iterations.velocity,
+            iterations.position,
+            iterations.drift,
+            iterations.cluster,
+        ] {
+            write_i32(writer, value)?;
+        }
+
+        let material_config = &self.material_config;
+        for value in [
+            material_config.linear_stiffness,
+            material_config.angular_stiffness,
+            material_config.volume_stiffness,
+        ] {
+            write_f32(writer, value)?;
+        }
+
+        write_count(writer, self.anchors.len())?;
+
+        for anchor in &self.anchors {
+            anchor.rigid_body.write(writer, rb_size)?;
+            anchor.vertex.write(writer, vertex_size)?;
+            write_u8(writer, anchor.near_mode as u8)?;
+        }
+
+        write_count(writer, self.pinned_vertices.len())?;
+
+        for vertex in &self.pinned_vertices {
+            vertex.write(writer, vertex_size)?;
+        }
+
+        Ok(())
+    }
+}
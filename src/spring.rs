@@ -0,0 +1,318 @@
+//! Spring bones approximating the rigid body physics of a model.
+//!
+//! Renderers without a physics engine, like most web viewers and VRM runtimes, animate hair and
+//! skirts with spring bones instead: chains of bones that lag behind and swing back into place.
+//! [`SpringBones`] derives them from the rigid bodies, each chain of bones driven by simulated
+//! bodies becomes a [`SpringChain`] and bodies following their bone become colliders.
+//!
+//! The parameters are a heuristic, there is no exact mapping between the two models. Values are
+//! in MMD units and axes.
+
+use crate::{
+    joint::Joint,
+    pmx::Pmx,
+    rigid_body::{PhysicsMode, RigidBody, Shape},
+    types::{Vec3, from_array, to_array},
+};
+
+/// A rotation spring this strong adds half of the maximum spring stiffness.
+const HALF_SPRING: f32 = 100.0;
+
+/// The spring chains and colliders of a model, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpringBones {
+    pub(crate) chains: Vec<SpringChain>,
+    pub(crate) colliders: Vec<SpringCollider>,
+}
+
+/// Bones moved by springs, each a child of the one before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpringChain {
+    /// The name of the rigid body of the first bone.
+    pub(crate) name: String,
+    pub(crate) joints: Vec<SpringJoint>,
+}
+
+/// The spring parameters of one bone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpringJoint {
+    pub(crate) bone: usize,
+    /// How strongly the bone returns to its rest direction, 0 for none.
+    pub(crate) stiffness: f32,
+    /// How much of its velocity the bone loses, from 0 to 1.
+    pub(crate) drag: f32,
+    pub(crate) hit_radius: f32,
+}
+
+/// A shape attached to a bone that springs cannot pass through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpringCollider {
+    pub(crate) bone: usize,
+    /// The center of the sphere or of the first cap, relative to the bone's position.
+    pub(crate) offset: Vec3,
+    pub(crate) shape: ColliderShape,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColliderShape {
+    Sphere {
+        radius: f32,
+    },
+    /// A capsule from the offset to `tail`, also relative to the bone's position.
+    Capsule {
+        radius: f32,
+        tail: Vec3,
+    },
+}
+
+impl SpringBones {
+    /// Derives spring bones from the rigid bodies and joints of a model.
+    ///
+    /// A bone driven by more than one body uses the first. Stiffness comes from the joint holding
+    /// a body, the tighter its rotation limits and the stronger its rotation spring the stiffer,
+    /// bones without a joint get a stiffness of 1. Drag is the average of the linear and angular
+    /// damping. Box colliders have no spring bone equivalent and are skipped.
+    pub fn from_pmx(pmx: &Pmx) -> Self {
+        let bones = &pmx.bones.inner;
+
+        let mut dynamic: Vec<Option<usize>> = vec![None; bones.len()];
+        let mut colliders = Vec::new();
+
+        for (index, body) in pmx.rigid_bodies.inner.iter().enumerate() {
+            let Some(bone) = body.bone.as_usize().filter(|&b| b < bones.len()) else {
+                continue;
+            };
+
+            match body.physics_mode {
+                PhysicsMode::FollowBone => {
+                    if let Some(collider) = collider(bone, bones[bone].position, body) {
+                        colliders.push(collider);
+                    }
+                }
+                PhysicsMode::Physics | PhysicsMode::PhysicsBone => {
+                    dynamic[bone].get_or_insert(index);
+                }
+            }
+        }
+
+        let skeleton = pmx.skeleton();
+
+        let mut roots: Vec<usize> = (0..bones.len())
+            .filter(|&b| {
+                dynamic[b].is_some() && skeleton.parent(b).is_none_or(|p| dynamic[p].is_none())
+            })
+            .collect();
+
+        let mut chains: Vec<Vec<usize>> = Vec::new();
+        while let Some(root) = roots.pop() {
+            let mut chain = vec![root];
+            let mut current = root;
+
+            loop {
+                let mut next = skeleton
+                    .children(current)
+                    .iter()
+                    .filter(|&&c| dynamic[c].is_some());
+                let Some(&first) = next.next() else {
+                    break;
+                };
+
+                // branches start chains of their own
+                roots.extend(next.copied());
+
+                if chain.contains(&first) {
+                    break;
+                }
+
+                chain.push(first);
+                current = first;
+            }
+
+            chains.push(chain);
+        }
+        chains.sort();
+
+        let chains = chains
+            .into_iter()
+            .map(|chain| {
+                let joints: Vec<SpringJoint> = chain
+                    .iter()
+                    .filter_map(|&bone| {
+                        let index = dynamic[bone]?;
+                        let body = &pmx.rigid_bodies.inner[index];
+                        let joint = pmx.joints.inner.iter().find(|joint| holds(joint, index));
+
+                        Some(SpringJoint {
+                            bone,
+                            stiffness: joint.map_or(1.0, stiffness),
+                            drag: ((body.linear_damping + body.angular_damping) / 2.0)
+                                .clamp(0.0, 1.0),
+                            hit_radius: radius(body),
+                        })
+                    })
+                    .collect();
+                let name = dynamic[chain[0]]
+                    .map(|index| {
+                        pmx.rigid_bodies.inner[index]
+                            .name
+                            .local
+                            .as_str()
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+
+                SpringChain { name, joints }
+            })
+            .collect();
+
+        Self { chains, colliders }
+    }
+
+    /// The chains, ordered by the index of their first bone.
+    pub fn chains(&self) -> &[SpringChain] {
+        &self.chains
+    }
+
+    pub fn colliders(&self) -> &[SpringCollider] {
+        &self.colliders
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty() && self.colliders.is_empty()
+    }
+}
+
+impl SpringChain {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn joints(&self) -> &[SpringJoint] {
+        &self.joints
+    }
+}
+
+impl SpringJoint {
+    pub fn bone(&self) -> usize {
+        self.bone
+    }
+
+    pub fn stiffness(&self) -> f32 {
+        self.stiffness
+    }
+
+    pub fn drag(&self) -> f32 {
+        self.drag
+    }
+
+    pub fn hit_radius(&self) -> f32 {
+        self.hit_radius
+    }
+}
+
+impl SpringCollider {
+    pub fn bone(&self) -> usize {
+        self.bone
+    }
+
+    pub fn offset(&self) -> Vec3 {
+        self.offset
+    }
+
+    pub fn shape(&self) -> ColliderShape {
+        self.shape
+    }
+}
+
+impl Pmx {
+    /// Approximates the physics of the model with spring bones, see [`SpringBones`].
+    pub fn spring_bones(&self) -> SpringBones {
+        SpringBones::from_pmx(self)
+    }
+}
+
+/// Returns true if `joint` attaches rigid body `body` to another, MMD puts the child body second.
+fn holds(joint: &Joint, body: usize) -> bool {
+    joint.rigid_body_b.as_usize() == Some(body)
+}
+
+/// Between 0 and 3: up to 2 for rotation limits closing in on a fixed joint, and up to 1 for the
+/// rotation spring.
+fn stiffness(joint: &Joint) -> f32 {
+    let min: [f32; 3] = to_array(joint.rotation_min);
+    let max: [f32; 3] = to_array(joint.rotation_max);
+    let spring: [f32; 3] = to_array(joint.rotation_spring);
+
+    // a minimum above the maximum leaves the axis free
+    let range: f32 = min
+        .iter()
+        .zip(&max)
+        .map(|(min, max)| {
+            if min > max {
+                std::f32::consts::PI
+            } else {
+                (max - min).min(std::f32::consts::PI)
+            }
+        })
+        .sum::<f32>()
+        / 3.0;
+    let tightness = 1.0 - range / std::f32::consts::PI;
+
+    let spring = spring.iter().map(|s| s.abs()).sum::<f32>() / 3.0;
+
+    2.0 * tightness + spring / (spring + HALF_SPRING)
+}
+
+/// The radius of a sphere or capsule, and of the sphere fitting inside a box.
+fn radius(body: &RigidBody) -> f32 {
+    let [x, y, z]: [f32; 3] = to_array(body.size);
+
+    match body.shape {
+        Shape::Sphere | Shape::Capsule => x,
+        Shape::Box => x.min(y).min(z),
+    }
+}
+
+fn collider(bone: usize, bone_position: Vec3, body: &RigidBody) -> Option<SpringCollider> {
+    let [bx, by, bz]: [f32; 3] = to_array(bone_position);
+    let [px, py, pz]: [f32; 3] = to_array(body.position);
+    let center = [px - bx, py - by, pz - bz];
+
+    let (offset, shape) = match body.shape {
+        Shape::Box => return None,
+        Shape::Sphere => (
+            center,
+            ColliderShape::Sphere {
+                radius: radius(body),
+            },
+        ),
+        Shape::Capsule => {
+            // capsules extend along their local Y axis, rotated in Y, X, Z order
+            let [rx, ry, rz]: [f32; 3] = to_array(body.rotation);
+            let axis = [
+                -rz.sin() * ry.cos() + rz.cos() * rx.sin() * ry.sin(),
+                rz.cos() * rx.cos(),
+                rz.sin() * ry.sin() + rz.cos() * rx.sin() * ry.cos(),
+            ];
+            let [_, height, _]: [f32; 3] = to_array(body.size);
+            let half = height / 2.0;
+
+            let start = [0, 1, 2].map(|i| center[i] - axis[i] * half);
+            let tail = [0, 1, 2].map(|i| center[i] + axis[i] * half);
+
+            (
+                start,
+                ColliderShape::Capsule {
+                    radius: radius(body),
+                    tail: from_array(tail),
+                },
+            )
+        }
+    };
+
+    Some(SpringCollider {
+        bone,
+        offset: from_array(offset),
+        shape,
+    })
+}
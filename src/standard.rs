@@ -0,0 +1,455 @@
+//! Checking a skeleton against the standard MMD bone set.
+//!
+//! Motions address bones by name, so a motion made for one model only plays on another if both
+//! name and connect their bones the same way. [`Pmx::check_standard_bones`] compares the bones of
+//! a model against a built-in table of the standard bones of the default models and the
+//! semi-standard (準標準) ones added by the usual plugins, and reports the bones missing, the ones
+//! named with the wrong width of letters and digits, and the ones attached to a different parent.
+//!
+//! A bone can have several acceptable parents, the optional semi-standard bones in between come
+//! first. The expected parent is the first of those the model has, bones none of them exist for
+//! are not checked.
+//!
+//! [`Pmx::add_standard_bones`] creates the simple semi-standard bones that only need a place in
+//! the hierarchy, 操作中心, 全ての親, グルーブ and 腰. Display frames are left alone.
+
+use std::fmt;
+
+use crate::{
+    bone::{Bone, BoneFlags},
+    pmx::Pmx,
+    types::{BoneIndex, Name, from_array, to_array},
+};
+
+/// A bone of the standard set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StandardBone {
+    pub(crate) name: String,
+    pub(crate) parents: Vec<String>,
+    pub(crate) semi_standard: bool,
+}
+
+impl StandardBone {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The acceptable parents, in order of preference. Empty for root bones.
+    pub fn parents(&self) -> &[String] {
+        &self.parents
+    }
+
+    /// Whether the bone is semi-standard, optional bones most models do not have.
+    pub fn semi_standard(&self) -> bool {
+        self.semi_standard
+    }
+}
+
+/// Bones in the middle of the body: name, parents and whether they are semi-standard.
+const CENTER: &[(&str, &[&str], bool)] = &[
+    ("操作中心", &[], true),
+    ("全ての親", &[], true),
+    ("センター", &["全ての親"], false),
+    ("グルーブ", &["センター"], true),
+    ("腰", &["グルーブ", "センター"], true),
+    ("上半身", &["腰", "グルーブ", "センター"], false),
+    ("上半身2", &["上半身"], true),
+    ("首", &["上半身2", "上半身"], false),
+    ("頭", &["首"], false),
+    ("両目", &["頭"], false),
+    ("下半身", &["腰", "グルーブ", "センター"], false),
+];
+
+/// Bones on both sides, without the 左 or 右 prefix. Parents starting with `*` are on the same
+/// side.
+const SIDES: &[(&str, &[&str], bool)] = &[
+    ("目", &["*頭", "頭"], false),
+    ("肩P", &["上半身2", "上半身"], true),
+    ("肩", &["*肩P", "上半身2", "上半身"], false),
+    ("肩C", &["*肩"], true),
+    ("腕", &["*肩C", "*肩"], false),
+    ("腕捩", &["*腕"], true),
+    ("ひじ", &["*腕捩", "*腕"], false),
+    ("手捩", &["*ひじ"], true),
+    ("手首", &["*手捩", "*ひじ"], false),
+    ("ダミー", &["*手首"], true),
+    ("親指０", &["*手首"], true),
+    ("親指１", &["*親指０", "*手首"], false),
+    ("親指２", &["*親指１"], false),
+    ("人指１", &["*手首"], false),
+    ("人指２", &["*人指１"], false),
+    ("人指３", &["*人指２"], false),
+    ("中指１", &["*手首"], false),
+    ("中指２", &["*中指１"], false),
+    ("中指３", &["*中指２"], false),
+    ("薬指１", &["*手首"], false),
+    ("薬指２", &["*薬指１"], false),
+    ("薬指３", &["*薬指２"], false),
+    ("小指１", &["*手首"], false),
+    ("小指２", &["*小指１"], false),
+    ("小指３", &["*小指２"], false),
+    ("腰キャンセル", &["下半身"], true),
+    ("足", &["*腰キャンセル", "下半身"], false),
+    ("ひざ", &["*足"], false),
+    ("足首", &["*ひざ"], false),
+    ("つま先", &["*足首"], false),
+    ("足IK親", &["全ての親"], true),
+    ("足ＩＫ", &["*足IK親", "全ての親"], false),
+    ("つま先ＩＫ", &["*足ＩＫ"], false),
+    ("足D", &["*腰キャンセル", "下半身"], true),
+    ("ひざD", &["*足D"], true),
+    ("足首D", &["*ひざD"], true),
+    ("足先EX", &["*足首D"], true),
+];
+
+/// The standard and semi-standard bones, in the order they usually appear in a model.
+pub fn standard_bones() -> Vec<StandardBone> {
+    let mut bones: Vec<StandardBone> = CENTER
+        .iter()
+        .map(|&(name, parents, semi_standard)| StandardBone {
+            name: name.to_owned(),
+            parents: parents.iter().map(|&p| p.to_owned()).collect(),
+            semi_standard,
+        })
+        .collect();
+
+    for side in ["左", "右"] {
+        bones.extend(SIDES.iter().map(|&(name, parents, semi_standard)| {
+            StandardBone {
+                name: format!("{side}{name}"),
+                parents: parents
+                    .iter()
+                    .map(|p| match p.strip_prefix('*') {
+                        Some(p) => format!("{side}{p}"),
+                        None => p.to_string(),
+                    })
+                    .collect(),
+                semi_standard,
+            }
+        }));
+    }
+
+    bones
+}
+
+/// A difference between a model's bones and the standard set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StandardIssue {
+    /// No bone has the name, not even with different widths.
+    Missing { name: String, semi_standard: bool },
+    /// A bone has the name with different widths, like `左足IK` for `左足ＩＫ`.
+    Misnamed {
+        bone: usize,
+        name: String,
+        expected: String,
+    },
+    /// A bone is attached to another parent than the expected one, `None` for no parent.
+    Misparented {
+        bone: usize,
+        name: String,
+        parent: Option<String>,
+        expected: Option<String>,
+    },
+}
+
+impl fmt::Display for StandardIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_root = |name: &Option<String>| name.clone().unwrap_or_else(|| "no parent".into());
+
+        match self {
+            StandardIssue::Missing {
+                name,
+                semi_standard: false,
+            } => write!(f, "missing {name}"),
+            StandardIssue::Missing {
+                name,
+                semi_standard: true,
+            } => write!(f, "missing semi-standard {name}"),
+            StandardIssue::Misnamed {
+                bone,
+                name,
+                expected,
+            } => write!(f, "bone {bone}: {name} should be named {expected}"),
+            StandardIssue::Misparented {
+                bone,
+                name,
+                parent,
+                expected,
+            } => write!(
+                f,
+                "bone {bone}: {name} is attached to {}, expected {}",
+                or_root(parent),
+                or_root(expected)
+            ),
+        }
+    }
+}
+
+/// The issues found by [`Pmx::check_standard_bones`], in the order of the standard bone table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StandardReport {
+    pub(crate) issues: Vec<StandardIssue>,
+}
+
+impl StandardReport {
+    pub fn issues(&self) -> &[StandardIssue] {
+        &self.issues
+    }
+
+    /// The issues, without the semi-standard bones that are missing.
+    pub fn standard_issues(&self) -> impl Iterator<Item = &StandardIssue> {
+        self.issues.iter().filter(|issue| {
+            !matches!(
+                issue,
+                StandardIssue::Missing {
+                    semi_standard: true,
+                    ..
+                }
+            )
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for StandardReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{issue}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pmx {
+    /// Compares the bones against the standard set, see the [module docs](crate::standard).
+    pub fn check_standard_bones(&self) -> StandardReport {
+        let table = standard_bones();
+        let mut issues = Vec::new();
+
+        // where each standard bone is, by its exact name or else with widths folded
+        let found: Vec<Option<usize>> = table
+            .iter()
+            .map(|standard| {
+                self.find_bone(&standard.name).or_else(|| {
+                    let folded = fold(&standard.name);
+                    (self.bones.inner.iter()).position(|b| fold(b.name.local.as_str()) == folded)
+                })
+            })
+            .collect();
+        let index_of = |name: &str| {
+            let i = table.iter().position(|standard| standard.name == name)?;
+            found[i]
+        };
+        let name_of = |index: usize| self.bones.inner[index].name.local.as_str().to_owned();
+
+        for (standard, &index) in table.iter().zip(&found) {
+            let Some(index) = index else {
+                issues.push(StandardIssue::Missing {
+                    name: standard.name.clone(),
+                    semi_standard: standard.semi_standard,
+                });
+                continue;
+            };
+
+            let name = name_of(index);
+            if name != standard.name {
+                issues.push(StandardIssue::Misnamed {
+                    bone: index,
+                    name: name.clone(),
+                    expected: standard.name.clone(),
+                });
+            }
+
+            let expected = if standard.parents.is_empty() {
+                None
+            } else {
+                match standard.parents.iter().find_map(|p| index_of(p)) {
+                    Some(parent) => Some(parent),
+                    None => continue,
+                }
+            };
+            let parent = self.bones.inner[index]
+                .parent
+                .as_usize()
+                .filter(|&i| i < self.bones.inner.len());
+            if parent != expected {
+                issues.push(StandardIssue::Misparented {
+                    bone: index,
+                    name,
+                    parent: parent.map(name_of),
+                    expected: expected.map(name_of),
+                });
+            }
+        }
+
+        StandardReport { issues }
+    }
+
+    /// Creates the missing 操作中心, 全ての親, グルーブ and 腰 bones, see the
+    /// [module docs](crate::standard).
+    ///
+    /// - 操作中心 goes first, at the origin.
+    /// - 全ての親 goes after it, at the origin, and becomes the parent of every other root bone.
+    /// - グルーブ goes after センター, at its position, and takes over its children and weights.
+    /// - 腰 goes after グルーブ or センター, at the position of 下半身, and becomes the parent of
+    ///   上半身 and 下半身.
+    ///
+    /// グルーブ is only created if the model has センター, 腰 only if it has センター and
+    /// 上半身 or 下半身. The new bones are added to no display frame. Returns the names of the
+    /// bones created.
+    pub fn add_standard_bones(&mut self) -> Vec<String> {
+        let mut created = Vec::new();
+        let translatable = BoneFlags::ROTATABLE
+            | BoneFlags::TRANSLATABLE
+            | BoneFlags::VISIBLE
+            | BoneFlags::ENABLED;
+
+        if self.find_bone("操作中心").is_none() {
+            let bone = self.standard_bone("操作中心", "view cnt", [0.0; 3], translatable);
+            self.insert_bone(0, bone);
+            created.push("操作中心".to_owned());
+        }
+
+        if self.find_bone("全ての親").is_none() {
+            let view = self.find_bone("操作中心");
+            let at = usize::from(view == Some(0));
+            let bone = self.standard_bone("全ての親", "mother", [0.0; 3], translatable);
+            let index = self.insert_bone(at, bone);
+
+            for (i, bone) in self.bones.inner.iter_mut().enumerate() {
+                if i != index && Some(i) != view && bone.parent.is_nil() {
+                    bone.parent = BoneIndex::new(index as i32);
+                }
+            }
+            created.push("全ての親".to_owned());
+        }
+
+        if self.find_bone("グルーブ").is_none()
+            && let Some(center) = self.find_bone("センター")
+        {
+            let position = to_array(self.bones.inner[center].position);
+            let mut bone = self.standard_bone("グルーブ", "groove", position, translatable);
+            bone.parent = BoneIndex::new(center as i32);
+            let index = self.insert_bone(center + 1, bone);
+            let (from, to) = (Some(center), BoneIndex::new(index as i32));
+
+            for (i, bone) in self.bones.inner.iter_mut().enumerate() {
+                if i != index && bone.parent.as_usize() == from {
+                    bone.parent = to;
+                }
+            }
+            for vertex in &mut self.vertices.inner {
+                for bone in vertex.weight_deform.bone_indices_mut() {
+                    if bone.as_usize() == from {
+                        *bone = to;
+                    }
+                }
+            }
+            created.push("グルーブ".to_owned());
+        }
+
+        let upper = self.find_bone("上半身");
+        let lower = self.find_bone("下半身");
+        let parent = self
+            .find_bone("グルーブ")
+            .or_else(|| self.find_bone("センター"));
+        if self.find_bone("腰").is_none()
+            && let Some(parent) = parent
+            && let Some(body) = lower.or(upper)
+        {
+            let position = to_array(self.bones.inner[body].position);
+            let flags = BoneFlags::ROTATABLE | BoneFlags::VISIBLE | BoneFlags::ENABLED;
+            let mut bone = self.standard_bone("腰", "waist", position, flags);
+            bone.parent = BoneIndex::new(parent as i32);
+            let index = self.insert_bone(parent + 1, bone);
+
+            for name in ["上半身", "下半身"] {
+                if let Some(body) = self.find_bone(name) {
+                    self.bones.inner[body].parent = BoneIndex::new(index as i32);
+                }
+            }
+            created.push("腰".to_owned());
+        }
+
+        created
+    }
+
+    fn find_bone(&self, name: &str) -> Option<usize> {
+        (self.bones.inner.iter()).position(|bone| bone.name.local.as_str() == name)
+    }
+
+    /// A bone named in the model's encoding.
+    fn standard_bone(&self, local: &str, universal: &str, position: [f32; 3], flags: u16) -> Bone {
+        let mut bone = Bone::new(local, from_array(position));
+        bone.name = Name::new(local, universal, self.header.globals.encoding);
+        bone.set_flags(BoneFlags::from_raw(flags));
+        bone
+    }
+}
+
+/// The name with full width ASCII folded to half width and surrounding whitespace removed.
+pub(crate) fn fold(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c {
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::PmxBuilder,
+        vertex::{Vertex, WeightDeform},
+    };
+
+    #[test]
+    fn groove_takes_over_center_of_parsed_model() {
+        let bone = |name: &str, parent: i32| {
+            let mut bone = Bone::new(name, from_array([0.0; 3]));
+            bone.set_parent(BoneIndex::new(parent));
+            bone
+        };
+        let vertex = Vertex::new(
+            from_array([0.0; 3]),
+            from_array([0.0, 1.0, 0.0]),
+            from_array([0.0; 2]),
+            WeightDeform::Bdef1 {
+                index: BoneIndex::new(2),
+            },
+        );
+        let built = PmxBuilder::new()
+            .add_bone(bone("操作中心", -1))
+            .add_bone(bone("全ての親", -1))
+            .add_bone(bone("センター", 1))
+            .add_bone(bone("腰", 2))
+            .add_bone(bone("下半身", 3))
+            .add_vertex(vertex)
+            .build()
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        built.write_to(&mut bytes).unwrap();
+        let mut pmx = Pmx::parse(&mut &bytes[..]).unwrap();
+
+        assert_eq!(pmx.add_standard_bones(), ["グルーブ"]);
+        assert_eq!(pmx.find_bone("グルーブ"), Some(3));
+
+        let bones = &pmx.bones.inner;
+        assert_eq!(bones[2].parent, BoneIndex::new(1));
+        assert_eq!(bones[3].parent, BoneIndex::new(2));
+        assert_eq!(bones[4].parent, BoneIndex::new(3));
+        assert_eq!(bones[5].parent, BoneIndex::new(4));
+        assert_eq!(
+            pmx.vertices.inner[0].weight_deform.bone_indices(),
+            [BoneIndex::new(3)]
+        );
+    }
+}
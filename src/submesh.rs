@@ -0,0 +1,105 @@
+//! Splitting the mesh by material.
+//!
+//! Materials do not store where their triangles are, each one covers the next
+//! [`surface_count`](crate::material::Material::surface_count) surfaces after the ones of the
+//! materials before it. [`Pmx::submeshes`] works the ranges out once, and
+//! [`Submesh::compact`] gathers the vertices a submesh uses so it can be drawn or exported on its
+//! own, with indices into that subset.
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::pmx::Pmx;
+
+/// The surfaces drawn with one material.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submesh {
+    pub(crate) material: usize,
+    pub(crate) surfaces: Range<usize>,
+}
+
+impl Submesh {
+    /// The index of the material.
+    pub fn material(&self) -> usize {
+        self.material
+    }
+
+    /// The range of the surface section covered, three surfaces per triangle.
+    pub fn surfaces(&self) -> Range<usize> {
+        self.surfaces.clone()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.surfaces.len() / 3
+    }
+
+    /// The vertices this submesh uses and its triangles indexing into them.
+    ///
+    /// Vertices are in the order the triangles first use them. Triangles with a nil or out of
+    /// range corner are dropped, as is a trailing partial triangle.
+    pub fn compact(&self, pmx: &Pmx) -> CompactSubmesh {
+        let vertex_count = pmx.vertices.inner.len();
+        let surfaces = pmx.surfaces.inner.get(self.surfaces.clone()).unwrap_or_default();
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::with_capacity(surfaces.len());
+        let mut remap = HashMap::new();
+
+        for triangle in surfaces.chunks_exact(3) {
+            let corner =
+                |i: usize| (triangle[i].index.as_usize()).filter(|&vertex| vertex < vertex_count);
+            let (Some(a), Some(b), Some(c)) = (corner(0), corner(1), corner(2)) else {
+                continue;
+            };
+
+            for vertex in [a, b, c] {
+                let index = *remap.entry(vertex).or_insert_with(|| {
+                    vertices.push(vertex);
+                    vertices.len() as u32 - 1
+                });
+                indices.push(index);
+            }
+        }
+
+        CompactSubmesh { vertices, indices }
+    }
+}
+
+/// A submesh with its own vertex subset, see [`Submesh::compact`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactSubmesh {
+    pub(crate) vertices: Vec<usize>,
+    pub(crate) indices: Vec<u32>,
+}
+
+impl CompactSubmesh {
+    /// The indices of the used vertices in the model's vertex section.
+    pub fn vertices(&self) -> &[usize] {
+        &self.vertices
+    }
+
+    /// The triangles, indexing into [`vertices`](CompactSubmesh::vertices).
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+}
+
+impl Pmx {
+    /// The submesh of every material, in material order.
+    ///
+    /// Materials without surfaces get an empty range, materials reaching past the last surface
+    /// are cut off.
+    pub fn submeshes(&self) -> Vec<Submesh> {
+        let total = self.surfaces.inner.len();
+        let mut start = 0;
+
+        (self.materials.inner.iter().enumerate())
+            .map(|(material, m)| {
+                let end = (start + m.surface_count.max(0) as usize).min(total);
+                let surfaces = start..end;
+                start = end;
+
+                Submesh { material, surfaces }
+            })
+            .collect()
+    }
+}
@@ -1,8 +1,8 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use thiserror::Error;
 
-use crate::types::Index;
+use crate::types::{ParseContext, VertexIndex, read_i32, write_count};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -12,14 +12,35 @@ pub enum Error {
     Type(#[from] crate::types::Error),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("{source} in element {index}")]
+    Element { index: usize, source: Box<Error> },
+    #[error("{source} in field {field}")]
+    Field {
+        field: &'static str,
+        source: Box<Error>,
+    },
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+crate::types::error_context!();
+
 #[derive(Debug)]
 pub struct Surfaces {
-    len: usize,
-    inner: Vec<Surface>,
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<Surface>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(Surfaces, Surface, len);
+
+impl From<Vec<Surface>> for Surfaces {
+    fn from(inner: Vec<Surface>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
 }
 
 impl Surfaces {
@@ -29,23 +50,42 @@ impl Surfaces {
         len
     }
 
-    pub fn parse(reader: &mut impl Read, index_size: u8) -> Result<Self> {
-        let mut size_bytes = [0; 4];
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        reader.read_exact(&mut size_bytes)?;
+    pub fn surfaces(&self) -> &[Surface] {
+        &self.inner
+    }
 
-        let size = i32::from_le_bytes(size_bytes);
+    /// The vertex indices as a flat index buffer.
+    pub fn as_u32_indices(&self) -> Vec<u32> {
+        self.inner.iter().map(|s| s.index.value() as u32).collect()
+    }
 
-        if size.is_negative() {
-            Err(Error::NegativeSize)?
-        }
+    /// Iterates over the triangles, ignoring a trailing incomplete one.
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.inner
+            .chunks_exact(3)
+            .map(|tri| std::array::from_fn(|i| tri[i].index.value() as u32))
+    }
 
-        let size = size as usize;
+    pub fn parse(reader: &mut impl Read, index_size: u8, context: ParseContext) -> Result<Self> {
+        let size = field("count", || {
+            let size = read_i32(reader)?;
+
+            if size.is_negative() {
+                Err(Error::NegativeSize)?
+            }
+
+            // a single vertex index
+            Ok(context.check_count(size as usize, index_size as u64)?)
+        })?;
 
         let mut inner_vec = Vec::with_capacity(size);
 
-        for _ in 0..size {
-            let surf = Surface::parse(reader, index_size)?;
+        for i in 0..size {
+            let surf = element(i, Surface::parse(reader, index_size))?;
             inner_vec.push(surf);
         }
 
@@ -54,17 +94,41 @@ impl Surfaces {
             inner: inner_vec,
         })
     }
+
+    pub fn write(&self, writer: &mut impl Write, index_size: u8) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for surf in &self.inner {
+            surf.write(writer, index_size)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Surface {
-    index: Index,
+    pub(crate) index: VertexIndex,
 }
 
 impl Surface {
+    /// Index into the vertex section, every 3 consecutive surfaces form a triangle.
+    pub fn index(&self) -> &VertexIndex {
+        &self.index
+    }
+
     pub fn parse(reader: &mut impl Read, index_size: u8) -> Result<Self> {
-        let index = Index::parse(reader, index_size.try_into()?, false)?;
+        let index = field("index", || {
+            Ok(VertexIndex::parse(reader, index_size.try_into()?)?)
+        })?;
 
         Ok(Self { index })
     }
+
+    pub fn write(&self, writer: &mut impl Write, index_size: u8) -> Result<()> {
+        self.index.write(writer, index_size.try_into()?)?;
+
+        Ok(())
+    }
 }
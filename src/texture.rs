@@ -1,8 +1,12 @@
-use std::io::Read;
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
 
 use thiserror::Error;
 
-use crate::types::{PmxText, TextEncoding};
+use crate::types::{ParseContext, PmxText, TextEncoding, read_i32, write_count};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -12,14 +16,35 @@ pub enum Error {
     Type(#[from] crate::types::Error),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("{source} in element {index}")]
+    Element { index: usize, source: Box<Error> },
+    #[error("{source} in field {field}")]
+    Field {
+        field: &'static str,
+        source: Box<Error>,
+    },
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+crate::types::error_context!();
+
 #[derive(Debug)]
 pub struct Textures {
-    len: usize,
-    inner: Vec<Texture>,
+    pub(crate) len: usize,
+    pub(crate) inner: Vec<Texture>,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(Textures, Texture, len);
+
+impl From<Vec<Texture>> for Textures {
+    fn from(inner: Vec<Texture>) -> Self {
+        Self {
+            len: inner.len(),
+            inner,
+        }
+    }
 }
 
 impl Textures {
@@ -29,23 +54,34 @@ impl Textures {
         len
     }
 
-    pub fn parse(reader: &mut impl Read, encoding: TextEncoding) -> Result<Self> {
-        let mut size_bytes = [0; 4];
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-        reader.read_exact(&mut size_bytes)?;
+    pub fn textures(&self) -> &[Texture] {
+        &self.inner
+    }
 
-        let size = i32::from_le_bytes(size_bytes);
+    pub fn parse(
+        reader: &mut impl Read,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size = field("count", || {
+            let size = read_i32(reader)?;
 
-        if size.is_negative() {
-            Err(Error::NegativeSize)?
-        }
+            if size.is_negative() {
+                Err(Error::NegativeSize)?
+            }
 
-        let size = size as usize;
+            // the length of the path
+            Ok(context.check_count(size as usize, 4)?)
+        })?;
 
         let mut inner_vec = Vec::with_capacity(size);
 
-        for _ in 0..size {
-            let tex = Texture::parse(reader, encoding)?;
+        for i in 0..size {
+            let tex = element(i, Texture::parse(reader, encoding, context))?;
             inner_vec.push(tex);
         }
 
@@ -54,17 +90,89 @@ impl Textures {
             inner: inner_vec,
         })
     }
+
+    pub fn write(&self, writer: &mut impl Write, encoding: TextEncoding) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for tex in &self.inner {
+            tex.write(writer, encoding)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Texture {
-    path: PmxText,
+    pub(crate) path: PmxText,
 }
 
 impl Texture {
-    pub fn parse(reader: &mut impl Read, encoding: TextEncoding) -> Result<Self> {
-        let path = PmxText::from_bytes(reader, encoding)?;
+    /// Path of the texture relative to the model file.
+    pub fn path(&self) -> &PmxText {
+        &self.path
+    }
+
+    /// Finds the file of the texture in the directory of the model, see [`resolve_path`].
+    pub fn resolve(&self, model_dir: &Path) -> Option<PathBuf> {
+        resolve_path(model_dir, self.path.as_str())
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let path = field("path", || {
+            Ok(PmxText::from_bytes_with(reader, encoding, context)?)
+        })?;
 
         Ok(Self { path })
     }
+
+    pub fn write(&self, writer: &mut impl Write, encoding: TextEncoding) -> Result<()> {
+        self.path.write(writer, encoding)?;
+
+        Ok(())
+    }
+}
+
+/// Finds the file a texture path refers to, `None` if there is none.
+///
+/// Texture paths are written on Windows, so backslashes are treated as separators and each
+/// component that does not exist as written is looked up case-insensitively among the entries of
+/// its directory.
+pub fn resolve_path(model_dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = normalize_path(path);
+    let mut resolved = model_dir.to_path_buf();
+
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => resolved.push(".."),
+            Component::Normal(name) => {
+                let exact = resolved.join(name);
+                if exact.exists() {
+                    resolved = exact;
+                    continue;
+                }
+
+                let name = name.to_string_lossy().to_lowercase();
+                let entry = fs::read_dir(&resolved)
+                    .ok()?
+                    .flatten()
+                    .find(|entry| entry.file_name().to_string_lossy().to_lowercase() == name)?;
+                resolved.push(entry.file_name());
+            }
+        }
+    }
+
+    resolved.is_file().then_some(resolved)
+}
+
+/// Replaces the Windows separators of a PMX path.
+pub(crate) fn normalize_path(path: &str) -> PathBuf {
+    PathBuf::from(path.trim().replace('\\', "/"))
 }
@@ -0,0 +1,329 @@
+//! Rendering a preview image of a model without a GPU.
+//!
+//! [`Pmx::thumbnail`] rasterizes the model in its bind pose on the CPU, looking at its front with
+//! an orthographic camera framed to the visible materials. Every material is drawn in its diffuse
+//! color, lit MMD's way, ambient plus diffuse times the light, and shaded with its toon, built-in
+//! toons through their [fallback gradients](Toon::internal_gradient) and toon textures as the
+//! first built-in one. Textures and edges are not drawn.
+//!
+//! Materials with an alpha of `0`, which MMD hides, are skipped. Translucent ones are blended over
+//! what was drawn before them without hiding what is drawn after, in material order like MMD.
+//! The image is rendered at [`SUPERSAMPLING`] times its size and scaled down to smooth the edges.
+
+use ::image::{Rgba, RgbaImage};
+use glam::{Quat, Vec2, Vec3};
+
+use crate::{
+    material::{Material, TOON_GRADIENT_LEN, Toon},
+    pmx::Pmx,
+};
+
+/// The samples per pixel along each axis.
+pub const SUPERSAMPLING: u32 = 2;
+
+/// MMD's default light color, the same gray in every channel.
+const LIGHT_COLOR: f32 = 154.0 / 255.0;
+
+/// The empty border around the model, as a fraction of the image size.
+const MARGIN: f32 = 0.05;
+
+/// Settings for [`Pmx::thumbnail`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThumbnailOptions {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) background: [u8; 4],
+    pub(crate) yaw: f32,
+}
+
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        Self {
+            width: 256,
+            height: 256,
+            background: [0; 4],
+            yaw: 0.0,
+        }
+    }
+}
+
+impl ThumbnailOptions {
+    /// A 256x256 front view on a transparent background.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn background(&self) -> [u8; 4] {
+        self.background
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    /// The size of the image in pixels, at least 1 on each axis.
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+    }
+
+    /// The RGBA color of the pixels the model does not cover.
+    pub fn set_background(&mut self, background: [u8; 4]) {
+        self.background = background;
+    }
+
+    /// Turns the model around its vertical axis, in radians, positive turning its front to the
+    /// right of the image.
+    pub fn set_yaw(&mut self, yaw: f32) {
+        self.yaw = yaw;
+    }
+}
+
+impl Pmx {
+    /// Renders a preview of the model, see the [module docs](crate::thumbnail).
+    pub fn thumbnail(&self, options: &ThumbnailOptions) -> RgbaImage {
+        let width = options.width.max(1) * SUPERSAMPLING;
+        let height = options.height.max(1) * SUPERSAMPLING;
+        let mut canvas = Canvas::new(width, height, options.background);
+
+        let rotation = Quat::from_rotation_y(-options.yaw);
+        let positions: Vec<Vec3> = (self.vertices.inner.iter())
+            .map(|vertex| rotation * vertex.pos)
+            .collect();
+        let normals: Vec<Vec3> = (self.vertices.inner.iter())
+            .map(|vertex| rotation * vertex.normal)
+            .collect();
+
+        let materials = self.visible_triangles();
+
+        // frames the model in the image, keeping its aspect ratio
+        let (mut min, mut max) = (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY));
+        for (_, triangles) in &materials {
+            for &vertex in triangles.as_flattened() {
+                min = min.min(positions[vertex].truncate());
+                max = max.max(positions[vertex].truncate());
+            }
+        }
+        if min.x > max.x {
+            return canvas.resolve();
+        }
+
+        let size = Vec2::new(width as f32, height as f32);
+        let extent = (max - min).max(Vec2::splat(f32::EPSILON));
+        let scale = (size * (1.0 - 2.0 * MARGIN) / extent).min_element();
+        let center = (min + max) / 2.0;
+        // the camera looks down +Z from the front of the model, image rows grow downwards
+        let project = |position: Vec3| {
+            let offset = (position.truncate() - center) * scale;
+            Vec3::new(size.x / 2.0 + offset.x, size.y / 2.0 - offset.y, position.z)
+        };
+
+        for (material, triangles) in materials {
+            let shader = Shader::new(material);
+
+            for triangle in triangles {
+                let corners = triangle.map(|vertex| Corner {
+                    screen: project(positions[vertex]),
+                    position: positions[vertex],
+                    normal: normals[vertex],
+                });
+                canvas.triangle(&corners, &shader);
+            }
+        }
+
+        canvas.resolve()
+    }
+
+    /// The materials MMD draws with their triangles, in drawing order.
+    fn visible_triangles(&self) -> Vec<(&Material, Vec<[usize; 3]>)> {
+        let vertex_count = self.vertices.inner.len();
+        let surfaces = &self.surfaces.inner;
+        let mut materials = Vec::new();
+        let mut start = 0;
+
+        for material in &self.materials.inner {
+            let count = (material.surface_count.max(0) as usize).min(surfaces.len() - start);
+            let range = &surfaces[start..start + count];
+            start += count;
+
+            if material.diffuse.w <= 0.0 {
+                continue;
+            }
+
+            let triangles = (range.chunks_exact(3))
+                .filter_map(|triangle| {
+                    let corner = |i: usize| {
+                        (triangle[i].index.as_usize()).filter(|&vertex| vertex < vertex_count)
+                    };
+                    Some([corner(0)?, corner(1)?, corner(2)?])
+                })
+                .collect();
+            materials.push((material, triangles));
+        }
+
+        materials
+    }
+}
+
+/// A triangle corner, in image space and in model space.
+struct Corner {
+    /// The pixel position, with the model depth as `z`.
+    screen: Vec3,
+    position: Vec3,
+    normal: Vec3,
+}
+
+/// The toon shading of a material.
+struct Shader {
+    /// Ambient plus diffuse times the light.
+    base: Vec3,
+    alpha: f32,
+    toon: [[u8; 4]; TOON_GRADIENT_LEN],
+}
+
+impl Shader {
+    /// Where the light shines from, MMD's default light direction reversed.
+    const TO_LIGHT: Vec3 = Vec3::new(0.5, 1.0, -0.5);
+
+    fn new(material: &Material) -> Self {
+        let toon = (material.toon.internal_gradient())
+            .or_else(|| Toon::Internal(0).internal_gradient())
+            .unwrap_or([[255; 4]; TOON_GRADIENT_LEN]);
+
+        Self {
+            base: (material.ambient + material.diffuse.truncate() * LIGHT_COLOR).min(Vec3::ONE),
+            alpha: material.diffuse.w.min(1.0),
+            toon,
+        }
+    }
+
+    /// The color of a point facing `normal`, which faces the camera.
+    fn shade(&self, normal: Vec3) -> Vec3 {
+        let lambert = normal.dot(Self::TO_LIGHT.normalize());
+        // the toon is sampled from the lit top at full light to the shaded bottom facing away
+        let row = ((0.5 - lambert * 0.5) * (TOON_GRADIENT_LEN - 1) as f32).round() as usize;
+        let [r, g, b, _] = self.toon[row.min(TOON_GRADIENT_LEN - 1)];
+
+        self.base * Vec3::new(f32::from(r), f32::from(g), f32::from(b)) / 255.0
+    }
+}
+
+/// The supersampled image being drawn.
+struct Canvas {
+    width: u32,
+    height: u32,
+    color: Vec<Vec3>,
+    alpha: Vec<f32>,
+    depth: Vec<f32>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, background: [u8; 4]) -> Self {
+        let len = (width * height) as usize;
+        let [r, g, b, a] = background.map(|channel| f32::from(channel) / 255.0);
+
+        Self {
+            width,
+            height,
+            color: vec![Vec3::new(r, g, b); len],
+            alpha: vec![a; len],
+            depth: vec![f32::INFINITY; len],
+        }
+    }
+
+    fn triangle(&mut self, [a, b, c]: &[Corner; 3], shader: &Shader) {
+        let edge = |from: Vec3, to: Vec3, point: Vec2| {
+            (to.x - from.x) * (point.y - from.y) - (to.y - from.y) * (point.x - from.x)
+        };
+        let area = edge(a.screen, b.screen, c.screen.truncate());
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+
+        // stands in for missing vertex normals
+        let face = (b.position - a.position)
+            .cross(c.position - a.position)
+            .normalize_or_zero();
+
+        let min = a.screen.min(b.screen).min(c.screen).truncate().floor();
+        let max = a.screen.max(b.screen).max(c.screen).truncate().ceil();
+        let (x0, y0) = (min.x.max(0.0) as u32, min.y.max(0.0) as u32);
+        let (x1, y1) = (
+            (max.x as u32).min(self.width),
+            (max.y as u32).min(self.height),
+        );
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let point = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                // the barycentric weights, positive inside whichever way the triangle winds
+                let weights = Vec3::new(
+                    edge(b.screen, c.screen, point),
+                    edge(c.screen, a.screen, point),
+                    edge(a.screen, b.screen, point),
+                ) / area;
+                if weights.min_element() < 0.0 {
+                    continue;
+                }
+
+                let pixel = (y * self.width + x) as usize;
+                let depth = weights.dot(Vec3::new(a.screen.z, b.screen.z, c.screen.z));
+                if depth >= self.depth[pixel] {
+                    continue;
+                }
+
+                let normal = (a.normal * weights.x + b.normal * weights.y + c.normal * weights.z)
+                    .try_normalize()
+                    .unwrap_or(face);
+                // MMD draws both sides of most materials, back faces are lit from the front
+                let normal = if normal.z > 0.0 { -normal } else { normal };
+
+                self.blend(pixel, shader.shade(normal), shader.alpha, depth);
+            }
+        }
+    }
+
+    fn blend(&mut self, pixel: usize, color: Vec3, alpha: f32, depth: f32) {
+        if alpha >= 1.0 {
+            self.color[pixel] = color;
+            self.alpha[pixel] = 1.0;
+            self.depth[pixel] = depth;
+            return;
+        }
+
+        let behind = self.alpha[pixel] * (1.0 - alpha);
+        let total = alpha + behind;
+        self.color[pixel] = (color * alpha + self.color[pixel] * behind) / total;
+        self.alpha[pixel] = total;
+    }
+
+    /// Averages the samples of each pixel, weighting colors by their coverage.
+    fn resolve(&self) -> RgbaImage {
+        let (width, height) = (self.width / SUPERSAMPLING, self.height / SUPERSAMPLING);
+        let samples = (SUPERSAMPLING * SUPERSAMPLING) as f32;
+
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (mut color, mut alpha) = (Vec3::ZERO, 0.0);
+            for sy in 0..SUPERSAMPLING {
+                for sx in 0..SUPERSAMPLING {
+                    let pixel =
+                        ((y * SUPERSAMPLING + sy) * self.width + x * SUPERSAMPLING + sx) as usize;
+                    color += self.color[pixel] * self.alpha[pixel];
+                    alpha += self.alpha[pixel];
+                }
+            }
+
+            let color = if alpha > 0.0 { color / alpha } else { color };
+            let [r, g, b] = color.to_array().map(|c| (c * 255.0).round() as u8);
+            Rgba([r, g, b, (alpha / samples * 255.0).round() as u8])
+        })
+    }
+}
@@ -0,0 +1,187 @@
+//! Baking a transform into a model.
+//!
+//! [`Pmx::bake_transform`] applies an affine transform to every position, offset and direction of
+//! the model, the usual use being [`Pmx::scale`] from MMD units to meters. Positions and SDEF
+//! parameters are transformed as points, offsets as vectors, normals with the inverse transpose
+//! and directions are normalized again. Transforms that mirror reverse the winding of the
+//! triangles so faces keep pointing outwards.
+//!
+//! Rigid bodies and joints have a rotated local frame, their rotation is composed with the
+//! rotation of the transform and their sizes and translation limits are scaled by how much the
+//! transform stretches each of their local axes. A box scaled along a direction that is not one of
+//! its axes can not stay a box, so those end up approximated. Mirrored frames have their local Z
+//! mirrored as well, like [`convert_coordinates`](Pmx::convert_coordinates) does.
+//!
+//! IK angle limits are per world axis and kept as they are, they only stay exact for transforms
+//! without rotation.
+
+use glam::{EulerRot, Mat3, Mat4, Quat, Vec3, Vec4};
+
+use crate::{bone::Tail, morph::Offsets, pmx::Pmx, rigid_body::Shape, vertex::WeightDeform};
+
+/// The iterations of the polar decomposition in [`orthogonal_part`].
+const POLAR_ITERATIONS: usize = 16;
+
+/// Mirrors Z in the local frames of rigid bodies and joints.
+const LOCAL_MIRROR: Mat3 = Mat3::from_diagonal(Vec3::new(1.0, 1.0, -1.0));
+
+impl Pmx {
+    /// Scales the model uniformly around the origin, `0.08` converts MMD units to meters.
+    pub fn scale(&mut self, factor: f32) {
+        self.bake_transform(Mat4::from_scale(Vec3::splat(factor)));
+    }
+
+    /// Applies `transform` to the model, see the [module docs](crate::transform).
+    pub fn bake_transform(&mut self, transform: Mat4) {
+        let linear = Mat3::from_mat4(transform);
+        let normals = linear.inverse().transpose();
+        let mirrored = linear.determinant() < 0.0;
+        let orthogonal = orthogonal_part(linear);
+        let average_scale = linear.determinant().abs().cbrt();
+        // rotation vectors like torques turn the other way in a mirrored space
+        let axial = if mirrored { -1.0 } else { 1.0 };
+
+        let point = |p: Vec3| transform.transform_point3(p);
+        let direction = |d: Vec3| (linear * d).normalize_or(d);
+
+        // a local frame given as MMD Euler angles, its new angles and the stretch of each axis
+        let frame = |euler: Vec3| {
+            let matrix =
+                Mat3::from_quat(Quat::from_euler(EulerRot::YXZ, euler.y, euler.x, euler.z));
+            let stretch =
+                Vec3::from_array(std::array::from_fn(|i| (linear * matrix.col(i)).length()));
+
+            let mut rotated = orthogonal * matrix;
+            if mirrored {
+                rotated *= LOCAL_MIRROR;
+            }
+            let (y, x, z) = Quat::from_mat3(&rotated).to_euler(EulerRot::YXZ);
+
+            (Vec3::new(x, y, z), stretch)
+        };
+
+        for vertex in &mut self.vertices.inner {
+            vertex.pos = point(vertex.pos);
+            vertex.normal = (normals * vertex.normal).normalize_or(vertex.normal);
+
+            if let WeightDeform::Sdef { c, r0, r1, .. } = &mut vertex.weight_deform {
+                *c = point(*c);
+                *r0 = point(*r0);
+                *r1 = point(*r1);
+            }
+        }
+
+        if mirrored {
+            for triangle in self.surfaces.inner.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        for bone in &mut self.bones.inner {
+            bone.position = point(bone.position);
+
+            if let Tail::Position(offset) = &mut bone.tail {
+                *offset = linear * *offset;
+            }
+            if let Some(axis) = &mut bone.fixed_axis {
+                *axis = direction(*axis);
+            }
+            if let Some(axes) = &mut bone.local_axes {
+                axes.x = direction(axes.x);
+                axes.z = direction(axes.z);
+            }
+        }
+
+        for morph in &mut self.morphs.inner {
+            match &mut morph.offsets {
+                Offsets::Vertex(offsets) => {
+                    for offset in offsets {
+                        offset.translation = linear * offset.translation;
+                    }
+                }
+                Offsets::Bone(offsets) => {
+                    for offset in offsets {
+                        offset.translation = linear * offset.translation;
+
+                        let r = offset.rotation;
+                        let matrix = Mat3::from_quat(Quat::from_xyzw(r.x, r.y, r.z, r.w));
+                        let rotated = orthogonal * matrix * orthogonal.transpose();
+                        offset.rotation = Vec4::from(Quat::from_mat3(&rotated));
+                    }
+                }
+                Offsets::Impulse(offsets) => {
+                    for offset in offsets {
+                        if offset.local {
+                            // the frame of the body is only mirrored along its own Z
+                            let local = if mirrored {
+                                LOCAL_MIRROR
+                            } else {
+                                Mat3::IDENTITY
+                            };
+                            offset.velocity = local * offset.velocity * average_scale;
+                            offset.torque = local * offset.torque * axial;
+                        } else {
+                            offset.velocity = linear * offset.velocity;
+                            offset.torque = orthogonal * offset.torque * axial;
+                        }
+                    }
+                }
+                Offsets::Group(_)
+                | Offsets::Uv(_)
+                | Offsets::AdditionalUv(..)
+                | Offsets::Material(_)
+                | Offsets::Flip(_) => {}
+            }
+        }
+
+        for body in &mut self.rigid_bodies.inner {
+            let (rotation, stretch) = frame(body.rotation);
+            body.position = point(body.position);
+            body.rotation = rotation;
+
+            body.size = match body.shape {
+                Shape::Sphere => body.size * stretch.element_sum() / 3.0,
+                // the radius across X and Z, the height along Y
+                Shape::Capsule => {
+                    let radius = (stretch.x + stretch.z) / 2.0;
+                    Vec3::new(body.size.x * radius, body.size.y * stretch.y, body.size.z)
+                }
+                Shape::Box => body.size * stretch,
+            };
+        }
+
+        for joint in &mut self.joints.inner {
+            let (rotation, stretch) = frame(joint.rotation);
+            joint.position = point(joint.position);
+            joint.rotation = rotation;
+
+            let (mut min, mut max) = (joint.position_min * stretch, joint.position_max * stretch);
+            if mirrored {
+                (min.z, max.z) = (-max.z, -min.z);
+
+                let (rmin, rmax) = (joint.rotation_min, joint.rotation_max);
+                joint.rotation_min = Vec3::new(-rmax.x, -rmax.y, rmin.z);
+                joint.rotation_max = Vec3::new(-rmin.x, -rmin.y, rmax.z);
+            }
+            (joint.position_min, joint.position_max) = (min, max);
+        }
+    }
+}
+
+/// The rotation, or rotation and mirroring, closest to `linear`, the orthogonal factor of its
+/// polar decomposition.
+///
+/// Averaging a matrix with its inverse transpose converges to it quickly for the well-conditioned
+/// transforms models get.
+fn orthogonal_part(linear: Mat3) -> Mat3 {
+    let mut orthogonal = linear;
+    for _ in 0..POLAR_ITERATIONS {
+        let inverse = orthogonal.inverse().transpose();
+        if !inverse.is_finite() {
+            return Mat3::IDENTITY;
+        }
+        orthogonal = (orthogonal + inverse) * 0.5;
+    }
+
+    orthogonal
+}
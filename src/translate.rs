@@ -0,0 +1,197 @@
+//! Translating standard Japanese bone and morph names to English.
+//!
+//! Most models only fill in the local names, English tools then show every bone and morph without
+//! a name or with one they can not display. [`Pmx::fill_universal_names`] fills the empty universal
+//! names of the bones and morphs from a built-in dictionary of the standard and semi-standard bone
+//! names and the usual facial morph names, using the English names MMD and PMX Editor use for them.
+//! Names are matched with full width letters and digits folded, so `左足IK` and `左足ＩＫ` both
+//! translate.
+//!
+//! Bones on either side are translated with a `_L` or `_R` suffix, `左腕` becomes `arm_L`.
+
+use crate::{pmx::Pmx, standard::fold, types::PmxText};
+
+/// Bones in the middle of the body.
+const BONES: &[(&str, &str)] = &[
+    ("操作中心", "view cnt"),
+    ("全ての親", "master"),
+    ("センター", "center"),
+    ("グルーブ", "groove"),
+    ("腰", "waist"),
+    ("上半身", "upper body"),
+    ("上半身2", "upper body2"),
+    ("上半身3", "upper body3"),
+    ("首", "neck"),
+    ("頭", "head"),
+    ("両目", "eyes"),
+    ("下半身", "lower body"),
+    ("舌１", "tongue1"),
+    ("舌２", "tongue2"),
+    ("舌３", "tongue3"),
+];
+
+/// Bones on both sides, without the 左 or 右 prefix and the `_L` or `_R` suffix.
+const SIDE_BONES: &[(&str, &str)] = &[
+    ("目", "eye"),
+    ("肩P", "shoulderP"),
+    ("肩", "shoulder"),
+    ("肩C", "shoulderC"),
+    ("腕", "arm"),
+    ("腕捩", "arm twist"),
+    ("腕捩1", "arm twist1"),
+    ("腕捩2", "arm twist2"),
+    ("腕捩3", "arm twist3"),
+    ("ひじ", "elbow"),
+    ("手捩", "wrist twist"),
+    ("手捩1", "wrist twist1"),
+    ("手捩2", "wrist twist2"),
+    ("手捩3", "wrist twist3"),
+    ("手首", "wrist"),
+    ("ダミー", "dummy"),
+    ("親指０", "thumb0"),
+    ("親指１", "thumb1"),
+    ("親指２", "thumb2"),
+    ("人指１", "fore1"),
+    ("人指２", "fore2"),
+    ("人指３", "fore3"),
+    ("中指１", "middle1"),
+    ("中指２", "middle2"),
+    ("中指３", "middle3"),
+    ("薬指１", "third1"),
+    ("薬指２", "third2"),
+    ("薬指３", "third3"),
+    ("小指１", "little1"),
+    ("小指２", "little2"),
+    ("小指３", "little3"),
+    ("腰キャンセル", "waist cancel"),
+    ("足", "leg"),
+    ("ひざ", "knee"),
+    ("足首", "ankle"),
+    ("つま先", "toe"),
+    ("足IK親", "leg IKP"),
+    ("足ＩＫ", "leg IK"),
+    ("つま先ＩＫ", "toe IK"),
+    ("足D", "leg D"),
+    ("ひざD", "knee D"),
+    ("足首D", "ankle D"),
+    ("足先EX", "toe EX"),
+];
+
+/// Facial morphs.
+const MORPHS: &[(&str, &str)] = &[
+    // eyes
+    ("まばたき", "blink"),
+    ("笑い", "smile"),
+    ("ウィンク", "wink"),
+    ("ウィンク右", "wink R"),
+    ("ウィンク２", "wink 2"),
+    ("ｳｨﾝｸ２右", "wink 2 R"),
+    ("なごみ", "calm"),
+    ("はぅ", "close><"),
+    ("びっくり", "surprised"),
+    ("じと目", "stare"),
+    ("ｷﾘｯ", "serious eyes"),
+    ("はちゅ目", "round eyes"),
+    ("瞳小", "pupil small"),
+    ("瞳大", "pupil big"),
+    ("ハイライト消", "no highlight"),
+    ("星目", "star eyes"),
+    ("はぁと", "heart eyes"),
+    // mouth
+    ("あ", "a"),
+    ("い", "i"),
+    ("う", "u"),
+    ("え", "e"),
+    ("お", "o"),
+    ("あ２", "a 2"),
+    ("ん", "n"),
+    ("▲", "mouth ▲"),
+    ("∧", "mouth ∧"),
+    ("□", "mouth □"),
+    ("ワ", "mouth wa"),
+    ("ω", "mouth ω"),
+    ("ω□", "mouth ω□"),
+    ("はんっ", "huh"),
+    ("えー", "eh"),
+    ("にやり", "grin"),
+    ("にっこり", "smile mouth"),
+    ("ぺろっ", "tongue out"),
+    ("てへぺろ", "tehepero"),
+    ("口角上げ", "mouth corner up"),
+    ("口角下げ", "mouth corner down"),
+    ("口横広げ", "mouth wide"),
+    ("歯無し上", "no upper teeth"),
+    ("歯無し下", "no lower teeth"),
+    // eyebrows
+    ("真面目", "serious"),
+    ("困る", "troubled"),
+    ("にこり", "cheerful"),
+    ("怒り", "angry"),
+    ("上", "brow up"),
+    ("下", "brow down"),
+    ("前", "brow forward"),
+    // other
+    ("照れ", "blush"),
+    ("涙", "tears"),
+    ("青ざめ", "pale"),
+    ("がーん", "shocked"),
+];
+
+/// The English name of a standard bone, see the [module docs](crate::translate).
+pub fn translate_bone(name: &str) -> Option<String> {
+    let name = fold(name);
+    if let Some(english) = lookup(BONES, &name) {
+        return Some(english.to_owned());
+    }
+
+    let (suffix, base) = match name.strip_prefix('左') {
+        Some(base) => ("_L", base),
+        None => ("_R", name.strip_prefix('右')?),
+    };
+    lookup(SIDE_BONES, base).map(|english| format!("{english}{suffix}"))
+}
+
+/// The English name of a common facial morph, see the [module docs](crate::translate).
+pub fn translate_morph(name: &str) -> Option<&'static str> {
+    lookup(MORPHS, &fold(name))
+}
+
+fn lookup(table: &[(&str, &'static str)], folded: &str) -> Option<&'static str> {
+    (table.iter())
+        .find(|(japanese, _)| fold(japanese) == folded)
+        .map(|&(_, english)| english)
+}
+
+impl Pmx {
+    /// Fills the empty universal names of the bones and morphs with the English names of the
+    /// dictionary, see the [module docs](crate::translate).
+    ///
+    /// Names the dictionary does not know stay empty. Returns the number of names filled.
+    pub fn fill_universal_names(&mut self) -> usize {
+        let mut filled = 0;
+        let mut fill = |universal: &mut PmxText, english: Option<String>| {
+            if let Some(english) = english {
+                *universal = PmxText::new(english, universal.encoding);
+                filled += 1;
+            }
+        };
+
+        for bone in &mut self.bones.inner {
+            if bone.name.universal.as_str().trim().is_empty() {
+                fill(
+                    &mut bone.name.universal,
+                    translate_bone(bone.name.local.as_str()),
+                );
+            }
+        }
+
+        for morph in &mut self.morphs.inner {
+            if morph.name.universal.as_str().trim().is_empty() {
+                let english = translate_morph(morph.name.local.as_str());
+                fill(&mut morph.name.universal, english.map(str::to_owned));
+            }
+        }
+
+        filled
+    }
+}
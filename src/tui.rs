@@ -0,0 +1,433 @@
+//! A terminal browser for the sections of a parsed model, with [ratatui](https://docs.rs/ratatui).
+//!
+//! [`run`] shows the header, the bone tree, the materials and the morphs in tabs, a list of the
+//! items on the left and the fields of the selected one on the right. Bones are listed parents
+//! before their children and indented by depth, bones caught in a parent cycle come last.
+//!
+//! Typing `/` filters the lists by a case-insensitive substring of the Japanese or English name.
+//! Morph offsets are cut to the first [`OFFSET_PREVIEW`] since vertex morphs hold thousands.
+
+use std::{fmt::Debug, io};
+
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Tabs, Wrap},
+};
+
+use crate::{
+    material::Toon,
+    morph::{Morph, Offsets},
+    pmx::Pmx,
+    types::{Name, TextureIndex},
+};
+
+/// The number of offsets of a morph shown.
+pub const OFFSET_PREVIEW: usize = 32;
+
+/// The lines moved by page up and page down.
+const PAGE: usize = 16;
+
+const HELP: &str = "q quit  tab/←→ section  ↑↓/jk select  J/K scroll  / search  esc clear";
+
+/// Browses `pmx` in the terminal until the user quits, see the [module docs](self).
+///
+/// Takes over the terminal and restores it before returning, also when drawing fails.
+pub fn run(pmx: &Pmx) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = Inspector::new(pmx).run(&mut terminal);
+    ratatui::restore();
+
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    Header,
+    Bones,
+    Materials,
+    Morphs,
+}
+
+impl Tab {
+    const ALL: [Tab; 4] = [Tab::Header, Tab::Bones, Tab::Materials, Tab::Morphs];
+
+    fn title(self) -> &'static str {
+        match self {
+            Tab::Header => "Header",
+            Tab::Bones => "Bones",
+            Tab::Materials => "Materials",
+            Tab::Morphs => "Morphs",
+        }
+    }
+
+    fn position(self) -> usize {
+        Tab::ALL.iter().position(|&tab| tab == self).unwrap_or(0)
+    }
+}
+
+/// An item of a list, the index into its section and its depth in the bone tree.
+struct Row {
+    index: usize,
+    depth: usize,
+    label: String,
+    /// The lowercased names searched.
+    key: String,
+}
+
+impl Row {
+    fn new(index: usize, depth: usize, name: &Name) -> Self {
+        let label = if name.universal.is_empty() {
+            format!("{index:>4} {}", name.local)
+        } else {
+            format!("{index:>4} {} ({})", name.local, name.universal)
+        };
+
+        Self {
+            index,
+            depth,
+            label,
+            key: format!("{}\n{}", name.local, name.universal).to_lowercase(),
+        }
+    }
+}
+
+struct Inspector<'a> {
+    pmx: &'a Pmx,
+    tab: Tab,
+    bones: Vec<Row>,
+    materials: Vec<Row>,
+    morphs: Vec<Row>,
+    /// The rows of the current tab matching the query.
+    visible: Vec<usize>,
+    list: ListState,
+    query: String,
+    searching: bool,
+    scroll: u16,
+}
+
+impl<'a> Inspector<'a> {
+    fn new(pmx: &'a Pmx) -> Self {
+        let skeleton = pmx.skeleton();
+        let bones = &pmx.bones.inner;
+        let unrooted = (0..bones.len()).filter(|&bone| !skeleton.is_rooted(bone));
+        let bones = skeleton
+            .depth_first()
+            .map(|bone| Row::new(bone, skeleton.depth(bone), &bones[bone].name))
+            .chain(unrooted.map(|bone| Row::new(bone, 0, &bones[bone].name)))
+            .collect();
+
+        let materials = (pmx.materials.inner.iter().enumerate())
+            .map(|(index, material)| Row::new(index, 0, &material.name))
+            .collect();
+        let morphs = (pmx.morphs.inner.iter().enumerate())
+            .map(|(index, morph)| Row::new(index, 0, &morph.name))
+            .collect();
+
+        Self {
+            pmx,
+            tab: Tab::Header,
+            bones,
+            materials,
+            morphs,
+            visible: Vec::new(),
+            list: ListState::default(),
+            query: String::new(),
+            searching: false,
+            scroll: 0,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        self.filter();
+
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && !self.handle(key)
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    fn rows(&self) -> &[Row] {
+        match self.tab {
+            Tab::Header => &[],
+            Tab::Bones => &self.bones,
+            Tab::Materials => &self.materials,
+            Tab::Morphs => &self.morphs,
+        }
+    }
+
+    /// The section index of the selected item.
+    fn selected(&self) -> Option<usize> {
+        let row = *self.visible.get(self.list.selected()?)?;
+        Some(self.rows()[row].index)
+    }
+
+    /// Recomputes the visible rows after the query or the tab changed, keeping the selected item
+    /// if it still matches.
+    fn filter(&mut self) {
+        let selected = self
+            .list
+            .selected()
+            .and_then(|i| self.visible.get(i).copied());
+        let query = self.query.to_lowercase();
+
+        self.visible = (self.rows().iter().enumerate())
+            .filter(|(_, row)| row.key.contains(&query))
+            .map(|(i, _)| i)
+            .collect();
+
+        let position = selected.and_then(|row| self.visible.iter().position(|&i| i == row));
+        self.list
+            .select(position.or((!self.visible.is_empty()).then_some(0)));
+        self.scroll = 0;
+    }
+
+    fn switch(&mut self, tab: Tab) {
+        if tab != self.tab {
+            self.tab = tab;
+            self.list.select(None);
+            self.filter();
+        }
+    }
+
+    fn step(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+
+        let current = self.list.selected().unwrap_or(0);
+        let last = self.visible.len() - 1;
+        self.list
+            .select(Some(current.saturating_add_signed(delta).min(last)));
+        self.scroll = 0;
+    }
+
+    /// Handles a key press, returns false to quit.
+    fn handle(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+
+        if self.searching {
+            match key.code {
+                KeyCode::Enter => self.searching = false,
+                KeyCode::Esc => {
+                    self.searching = false;
+                    self.query.clear();
+                    self.filter();
+                }
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.filter();
+                }
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.filter();
+                }
+                _ => {}
+            }
+            return true;
+        }
+
+        let tab = self.tab.position();
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Esc if self.query.is_empty() => return false,
+            KeyCode::Esc => {
+                self.query.clear();
+                self.filter();
+            }
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Tab | KeyCode::Right => self.switch(Tab::ALL[(tab + 1) % Tab::ALL.len()]),
+            KeyCode::BackTab | KeyCode::Left => {
+                self.switch(Tab::ALL[(tab + Tab::ALL.len() - 1) % Tab::ALL.len()])
+            }
+            KeyCode::Char(c @ '1'..='4') => self.switch(Tab::ALL[c as usize - '1' as usize]),
+            KeyCode::Down | KeyCode::Char('j') => self.step(1),
+            KeyCode::Up | KeyCode::Char('k') => self.step(-1),
+            KeyCode::PageDown => self.step(PAGE as isize),
+            KeyCode::PageUp => self.step(-(PAGE as isize)),
+            KeyCode::Home | KeyCode::Char('g') => self.step(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.step(isize::MAX),
+            KeyCode::Char('J') => self.scroll = self.scroll.saturating_add(1),
+            KeyCode::Char('K') => self.scroll = self.scroll.saturating_sub(1),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [tabs, body, status] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let titles = Tab::ALL.iter().map(|tab| tab.title());
+        frame.render_widget(
+            Tabs::new(titles)
+                .select(self.tab.position())
+                .highlight_style(Style::new().bold().reversed()),
+            tabs,
+        );
+
+        if self.tab == Tab::Header {
+            self.draw_details(frame, body, "Header", self.header());
+        } else {
+            let [list, details] =
+                Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                    .areas(body);
+            self.draw_list(frame, list);
+
+            let text = self.selected().map(|index| match self.tab {
+                Tab::Header => unreachable!(),
+                Tab::Bones => self.bone(index),
+                Tab::Materials => self.material(index),
+                Tab::Morphs => self.morph(&self.pmx.morphs.inner[index]),
+            });
+            self.draw_details(frame, details, "Details", text.unwrap_or_default());
+        }
+
+        let status_line = if self.searching {
+            Line::from(format!("/{}", self.query))
+        } else if !self.query.is_empty() {
+            Line::from(format!("filter: {}  (esc to clear)  {HELP}", self.query))
+        } else {
+            Line::from(HELP)
+        };
+        frame.render_widget(Paragraph::new(status_line).dim(), status);
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self.rows();
+        let items: Vec<ListItem> = (self.visible.iter())
+            .map(|&i| {
+                let row = &rows[i];
+                ListItem::new(format!("{}{}", "  ".repeat(row.depth), row.label))
+            })
+            .collect();
+        let title = format!(
+            "{} ({}/{})",
+            self.tab.title(),
+            self.visible.len(),
+            rows.len()
+        );
+
+        let list = List::new(items)
+            .block(Block::new().borders(Borders::ALL).title(title))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, area, &mut self.list);
+    }
+
+    fn draw_details(&self, frame: &mut Frame, area: Rect, title: &str, text: String) {
+        let details = Paragraph::new(text)
+            .block(Block::new().borders(Borders::ALL).title(title))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        frame.render_widget(details, area);
+    }
+
+    fn header(&self) -> String {
+        let pmx = self.pmx;
+        let mut text = format!(
+            "vertices:     {}\nfaces:        {}\ntextures:     {}\nmaterials:    {}\n\
+             bones:        {}\nmorphs:       {}\nframes:       {}\nrigid bodies: {}\n\
+             joints:       {}\n",
+            pmx.vertices.inner.len(),
+            pmx.surfaces.inner.len() / 3,
+            pmx.textures.inner.len(),
+            pmx.materials.inner.len(),
+            pmx.bones.inner.len(),
+            pmx.morphs.inner.len(),
+            pmx.display_frames.inner.len(),
+            pmx.rigid_bodies.inner.len(),
+            pmx.joints.inner.len(),
+        );
+        if let Some(soft_bodies) = &pmx.soft_bodies {
+            text += &format!("soft bodies:  {}\n", soft_bodies.inner.len());
+        }
+
+        text + &format!("\n{:#?}", pmx.header)
+    }
+
+    fn bone(&self, index: usize) -> String {
+        let bones = &self.pmx.bones.inner;
+        let skeleton = self.pmx.skeleton();
+        let name = |bone: usize| format!("{bone} {}", bones[bone].name.local);
+
+        let parent = skeleton.parent(index).map_or("none".into(), name);
+        let children: Vec<String> = skeleton.children(index).iter().map(|&c| name(c)).collect();
+
+        format!(
+            "parent:   {parent}\nchildren: {}\n\n{:#?}",
+            children.join(", "),
+            bones[index]
+        )
+    }
+
+    fn material(&self, index: usize) -> String {
+        let material = &self.pmx.materials.inner[index];
+        let texture = |index: &TextureIndex| {
+            index
+                .get(&self.pmx.textures.inner)
+                .map_or("none".into(), |texture| texture.path.to_string())
+        };
+
+        let toon = match (material.toon.internal(), material.toon.texture_index()) {
+            (Some(internal), _) => Toon::internal_file_name(internal),
+            (None, Some(index)) => texture(&index),
+            (None, None) => "none".into(),
+        };
+
+        format!(
+            "texture:  {}\nsphere:   {}\ntoon:     {toon}\n\n{material:#?}",
+            texture(&material.tex_idx),
+            texture(&material.env_idx),
+        )
+    }
+
+    fn morph(&self, morph: &Morph) -> String {
+        let (kind, offsets): (String, String) = match &morph.offsets {
+            Offsets::Group(offsets) => ("group".into(), preview(offsets)),
+            Offsets::Vertex(offsets) => ("vertex".into(), preview(offsets)),
+            Offsets::Bone(offsets) => ("bone".into(), preview(offsets)),
+            Offsets::Uv(offsets) => ("uv".into(), preview(offsets)),
+            Offsets::AdditionalUv(channel, offsets) => {
+                (format!("uv{}", channel + 1), preview(offsets))
+            }
+            Offsets::Material(offsets) => ("material".into(), preview(offsets)),
+            Offsets::Flip(offsets) => ("flip".into(), preview(offsets)),
+            Offsets::Impulse(offsets) => ("impulse".into(), preview(offsets)),
+        };
+
+        format!(
+            "name:    {}\nenglish: {}\npanel:   {:?}\nkind:    {kind}\noffsets: {}\n\n{offsets}",
+            morph.name.local,
+            morph.name.universal,
+            morph.panel,
+            morph.offsets.len(),
+        )
+    }
+}
+
+/// The first [`OFFSET_PREVIEW`] offsets, noting how many were left out.
+fn preview<T: Debug>(offsets: &[T]) -> String {
+    let shown = &offsets[..offsets.len().min(OFFSET_PREVIEW)];
+    let mut text = format!("{shown:#?}");
+    if offsets.len() > shown.len() {
+        text += &format!("\n… {} more", offsets.len() - shown.len());
+    }
+
+    text
+}
@@ -1,9 +1,12 @@
 use core::fmt;
-use std::io::Read;
+use std::{
+    hash::{Hash, Hasher},
+    io::{Read, Write},
+};
 
 use thiserror::Error;
 
-use crate::util::from_utf16le;
+use crate::util::{decode_shift_jis, encode_shift_jis, from_utf16le, from_utf16le_lossy};
 
 // PMX Types
 // Name	Size (bytes)	Structure	Notes
@@ -29,53 +32,23 @@ pub enum Error {
     FromUtf8(#[from] std::str::Utf8Error),
     #[error("Index size mismatch")]
     IndexSizeMismatch,
+    #[error("Index value {0} does not fit in the declared index size")]
+    IndexOutOfRange(i32),
+    #[error("Text is too long to be written")]
+    TextTooLong,
+    #[error("Element count {count} exceeds the limit of {limit}")]
+    TooManyElements { count: usize, limit: usize },
+    #[error("Element count {count} cannot fit in the {remaining} bytes left in the file")]
+    CountPastEnd { count: usize, remaining: u64 },
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
-/// A bitflag structure used in various parts of the PMX format.
-/// 8 flags per byte. 0 = off, 1 = on.
-// TODO(mate): consider using bitflags crate
-#[derive(Debug)]
-pub struct Flag {
-    raw: u8,
-}
-
-impl Flag {
-    /// Get the state of a specific bit in the flag.
-    ///
-    /// Note that this uses 0-based indexing and only supports bits 0-7.
-    ///
-    /// If `bit` is out of range, this will panic in debug builds, and wraps in release builds.
-    /// You could try the `try_get_state` method if you want to be sure that the bit is valid.
-    pub fn get_state(&self, bit: u8) -> bool {
-        debug_assert!(bit < 8, "Bit index must be 0-7, got {}", bit);
-
-        (self.raw & (1 << bit)) != 0
-    }
-
-    /// Get the state of a specific bit in the flag.
-    ///
-    /// Returns None if the bit is out of range (not 0-7).
-    pub fn try_get_state(&self, bit: u8) -> Option<bool> {
-        if bit >= 8 {
-            return None;
-        }
-
-        Some((self.raw & (1 << bit)) != 0)
-    }
-
-    pub fn parse(reader: &mut impl Read) -> Result<Self> {
-        let mut bytes = [0; 1];
-        reader.read_exact(&mut bytes)?;
-        Ok(Self { raw: bytes[0] })
-    }
-}
-
 /// The text encoding used in the PMX file.
 ///
 /// Defined in the PMX file header.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TextEncoding {
     UTF16LE,
     UTF8,
@@ -93,13 +66,26 @@ impl TryFrom<u8> for TextEncoding {
     }
 }
 
+/// How text that is not valid in the file's encoding is handled.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum TextDecoding {
+    /// Fail the parse.
+    #[default]
+    Strict,
+    /// Replace the invalid sequences with U+FFFD, the raw bytes are kept so the text is still
+    /// written back unchanged.
+    Lossy,
+}
+
 /// A PMX text string, encoded in either UTF18LE or UTF8, specified by the file's global variables.
 pub struct PmxText {
-    // TODO(mate): keep the rawy bytes for now, but maybe we can drop them later
-    raw_bytes: Vec<u8>,
+    /// `None` if the bytes were dropped while parsing, the text is then re-encoded on write.
+    pub(crate) raw_bytes: Option<Vec<u8>>,
     // TODO(mate): this is also sort of useless as its in the file header and always the same for every text anyways
-    encoding: TextEncoding,
-    decoded: String,
+    pub(crate) encoding: TextEncoding,
+    pub(crate) decoded: String,
+    /// Whether invalid sequences were replaced while decoding.
+    pub(crate) lossy: bool,
 }
 
 impl fmt::Debug for PmxText {
@@ -117,13 +103,100 @@ impl fmt::Display for PmxText {
     }
 }
 
+/// Serialized as a plain string, deserialized text is UTF-8 and gets re-encoded on write if needed.
+#[cfg(feature = "serde")]
+impl serde::Serialize for PmxText {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.decoded)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PmxText {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        Ok(Self::new(
+            String::deserialize(deserializer)?,
+            TextEncoding::UTF8,
+        ))
+    }
+}
+
+impl AsRef<str> for PmxText {
+    fn as_ref(&self) -> &str {
+        &self.decoded
+    }
+}
+
 impl PmxText {
-    /// Reads a PMX text string from the given reader and an encoding.
+    /// Creates a text, encoding it right away with the given encoding.
+    pub fn new(text: impl Into<String>, encoding: TextEncoding) -> Self {
+        let decoded = text.into();
+
+        Self {
+            raw_bytes: Some(encode(&decoded, encoding)),
+            encoding,
+            decoded,
+            lossy: false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.decoded
+    }
+
+    pub fn into_string(self) -> String {
+        self.decoded
+    }
+
+    /// The bytes as stored in the file, without the length prefix.
     ///
-    /// Returns an error if the length is negative or if there was an IO error.
+    /// `None` if the model was parsed without retaining them.
+    pub fn raw_bytes(&self) -> Option<&[u8]> {
+        self.raw_bytes.as_deref()
+    }
+
+    /// Returns true if the raw bytes were not valid in their encoding and got decoded with
+    /// replacement characters, see [`TextDecoding::Lossy`].
+    ///
+    /// The raw bytes of such a text are always kept, so writing it back with the same encoding
+    /// reproduces the original bytes.
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+
+    /// The encoding the raw bytes are stored in.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Length of the decoded string in bytes.
+    pub fn len(&self) -> usize {
+        self.decoded.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoded.is_empty()
+    }
+
+    /// Reads a PMX text string from the given reader and an encoding.
     ///
-    /// Decoding the string is lazy and done when `try_into_string` is called.
+    /// Returns an error if the length is negative, the text is not valid in the encoding or if
+    /// there was an IO error.
     pub fn from_bytes(reader: &mut impl Read, encoding: TextEncoding) -> Result<Self> {
+        Self::from_bytes_with(reader, encoding, ParseContext::UNCHECKED)
+    }
+
+    /// Reads a PMX text string, decoding and keeping the raw bytes as the context says.
+    pub fn from_bytes_with(
+        reader: &mut impl Read,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
         let mut len = [0; 4];
 
         reader.read_exact(&mut len)?;
@@ -137,35 +210,142 @@ impl PmxText {
 
         let len = len as usize;
 
-        let mut raw_bytes = vec![0; len];
+        // grows with the bytes actually read instead of trusting the length up front
+        let mut raw_bytes = Vec::new();
 
-        reader.read_exact(&mut raw_bytes)?;
+        reader.take(len as u64).read_to_end(&mut raw_bytes)?;
 
-        let decoded = match encoding {
-            TextEncoding::UTF8 => {
-                // convert to &str first to validate UTF-8
-                // so if it's invalid we have not cloned yet
-                let str = str::from_utf8(&raw_bytes)?;
-                str.to_string()
+        if raw_bytes.len() != len {
+            Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?
+        }
+
+        let strict = match encoding {
+            TextEncoding::UTF8 => str::from_utf8(&raw_bytes)
+                .map(str::to_string)
+                .map_err(Error::from),
+            TextEncoding::UTF16LE => from_utf16le(&raw_bytes).map_err(Error::from),
+        };
+
+        let (decoded, lossy) = match (strict, context.text_decoding) {
+            (Ok(decoded), _) => (decoded, false),
+            (Err(error), TextDecoding::Strict) => Err(error)?,
+            (Err(_), TextDecoding::Lossy) => {
+                let decoded = match encoding {
+                    TextEncoding::UTF8 => String::from_utf8_lossy(&raw_bytes).into_owned(),
+                    TextEncoding::UTF16LE => from_utf16le_lossy(&raw_bytes),
+                };
+
+                (decoded, true)
             }
-            TextEncoding::UTF16LE => from_utf16le(&raw_bytes)?,
         };
 
+        // the raw bytes of a lossy text are all that is left of the original
+        let keep_raw = context.retain_raw_text || lossy;
+
         Ok(Self {
-            raw_bytes,
+            raw_bytes: keep_raw.then_some(raw_bytes),
             encoding,
             decoded,
+            lossy,
         })
     }
+
+    /// Writes the text with its length prefix, encoded with the given encoding.
+    ///
+    /// If the encoding matches the one the text was read with, the original bytes are written as-is,
+    /// otherwise the text is re-encoded from the decoded string.
+    pub fn write(&self, writer: &mut impl Write, encoding: TextEncoding) -> Result<()> {
+        let encoded;
+
+        let bytes = match &self.raw_bytes {
+            Some(raw_bytes) if encoding == self.encoding => raw_bytes,
+            _ => {
+                encoded = encode(&self.decoded, encoding);
+                &encoded
+            }
+        };
+
+        let len = i32::try_from(bytes.len()).map_err(|_| Error::TextTooLong)?;
+
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(bytes)?;
+
+        Ok(())
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+fn encode(text: &str, encoding: TextEncoding) -> Vec<u8> {
+    match encoding {
+        TextEncoding::UTF8 => text.as_bytes().to_vec(),
+        TextEncoding::UTF16LE => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+    }
+}
+
+/// A pair of local (usually Japanese) and universal (usually English) names.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Name {
+    pub local: PmxText,
+    pub universal: PmxText,
+}
+
+impl Name {
+    pub fn new(
+        local: impl Into<String>,
+        universal: impl Into<String>,
+        encoding: TextEncoding,
+    ) -> Self {
+        Self {
+            local: PmxText::new(local, encoding),
+            universal: PmxText::new(universal, encoding),
+        }
+    }
+
+    pub fn parse(
+        reader: &mut impl Read,
+        encoding: TextEncoding,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let local = PmxText::from_bytes_with(reader, encoding, context)?;
+        let universal = PmxText::from_bytes_with(reader, encoding, context)?;
+
+        Ok(Self { local, universal })
+    }
+
+    pub fn write(&self, writer: &mut impl Write, encoding: TextEncoding) -> Result<()> {
+        self.local.write(writer, encoding)?;
+        self.universal.write(writer, encoding)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum IndexSize {
     Size1([u8; 1]),
     Size2([u8; 2]),
     Size4([u8; 4]),
 }
 
+impl IndexSize {
+    /// The smallest index size in bytes that can address `count` elements.
+    ///
+    /// Signed indices lose half their range to negative values, -1 being nil.
+    pub fn smallest_for(count: usize, signed: bool) -> u8 {
+        let (max1, max2) = if signed {
+            (i8::MAX as usize + 1, i16::MAX as usize + 1)
+        } else {
+            (u8::MAX as usize + 1, u16::MAX as usize + 1)
+        };
+
+        match count {
+            c if c <= max1 => 1,
+            c if c <= max2 => 2,
+            _ => 4,
+        }
+    }
+}
+
 impl TryFrom<u8> for IndexSize {
     type Error = Error;
 
@@ -179,14 +359,47 @@ impl TryFrom<u8> for IndexSize {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct Index {
-    size: IndexSize,
-    sign: bool,
-    value: i32,
+    pub(crate) size: IndexSize,
+    pub(crate) sign: bool,
+    pub(crate) value: i32,
+}
+
+// a parsed index keeps the bytes it was read from, indices are equal when they refer to the same
+// element however they were made
+impl PartialEq for Index {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl Eq for Index {}
+
+impl Hash for Index {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+    }
 }
 
 impl Index {
+    /// Creates a 4 byte index, the size it is written with is decided by the globals anyway.
+    pub fn new(value: i32, sign: bool) -> Self {
+        Self {
+            size: IndexSize::Size4([0; 4]),
+            sign,
+            value,
+        }
+    }
+
+    pub fn size(&self) -> IndexSize {
+        self.size
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.sign
+    }
+
     pub fn parse(reader: &mut impl Read, mut size: IndexSize, sign: bool) -> Result<Self> {
         // read data into the index
         match &mut size {
@@ -216,21 +429,279 @@ impl Index {
         Ok(Self { size, value, sign })
     }
 
+    /// The raw value of the index, `-1` means nil.
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+
     pub fn is_nil(&self) -> bool {
         self.value == -1
     }
+
+    /// The index as a `usize`, or `None` if the index is nil (or otherwise negative).
+    pub fn as_usize(&self) -> Option<usize> {
+        usize::try_from(self.value).ok()
+    }
+
+    /// Looks up the element this index refers to in the given slice.
+    ///
+    /// Returns `None` if the index is nil or out of bounds.
+    pub fn get<'a, T>(&self, items: &'a [T]) -> Option<&'a T> {
+        items.get(self.as_usize()?)
+    }
+
+    /// Mutable version of `get`.
+    pub fn get_mut<'a, T>(&self, items: &'a mut [T]) -> Option<&'a mut T> {
+        items.get_mut(self.as_usize()?)
+    }
+
+    /// Writes the index with the given size and signedness.
+    ///
+    /// Returns an error if the value does not fit, e.g. 300 into a signed 1 byte index.
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize, sign: bool) -> Result<()> {
+        let value = self.value;
+        let out_of_range = || Error::IndexOutOfRange(value);
+
+        match size {
+            IndexSize::Size1(_) => {
+                let byte = if sign {
+                    i8::try_from(value).map_err(|_| out_of_range())? as u8
+                } else {
+                    u8::try_from(value).map_err(|_| out_of_range())?
+                };
+                writer.write_all(&[byte])?;
+            }
+            IndexSize::Size2(_) => {
+                let bytes = if sign {
+                    i16::try_from(value)
+                        .map_err(|_| out_of_range())?
+                        .to_le_bytes()
+                } else {
+                    u16::try_from(value)
+                        .map_err(|_| out_of_range())?
+                        .to_le_bytes()
+                };
+                writer.write_all(&bytes)?;
+            }
+            IndexSize::Size4(_) => writer.write_all(&value.to_le_bytes())?,
+        }
+
+        Ok(())
+    }
 }
 
-#[cfg(not(feature = "math_glam"))]
+/// Serialized as its value, the size is only known from the globals when writing anyway.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Index {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.value)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Index {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        Ok(Self::new(i32::deserialize(deserializer)?, true))
+    }
+}
+
+/// Declares an index newtype that can only be used to look up elements of a single section.
+///
+/// The signedness is fixed per section, as defined by the spec.
+macro_rules! typed_index {
+    ($(#[$meta:meta])* $name:ident, $target:ty, $sign:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub struct $name(pub(crate) Index);
+
+        impl $name {
+            pub fn new(value: i32) -> Self {
+                Self(Index::new(value, $sign))
+            }
+
+            /// The nil index, referring to nothing.
+            pub fn nil() -> Self {
+                Self::new(-1)
+            }
+
+            pub fn parse(reader: &mut impl Read, size: IndexSize) -> Result<Self> {
+                Ok(Self(Index::parse(reader, size, $sign)?))
+            }
+
+            pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+                self.0.write(writer, size, $sign)
+            }
+
+            /// The untyped index.
+            pub fn raw(&self) -> &Index {
+                &self.0
+            }
+
+            /// The raw value of the index, `-1` means nil.
+            pub fn value(&self) -> i32 {
+                self.0.value()
+            }
+
+            pub fn is_nil(&self) -> bool {
+                self.0.is_nil()
+            }
+
+            /// The index as a `usize`, or `None` if the index is nil (or otherwise negative).
+            pub fn as_usize(&self) -> Option<usize> {
+                self.0.as_usize()
+            }
+
+            /// Looks up the element this index refers to.
+            ///
+            /// Returns `None` if the index is nil or out of bounds.
+            pub fn get<'a>(&self, items: &'a [$target]) -> Option<&'a $target> {
+                self.0.get(items)
+            }
+
+            /// Mutable version of `get`.
+            pub fn get_mut<'a>(&self, items: &'a mut [$target]) -> Option<&'a mut $target> {
+                self.0.get_mut(items)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                self.0.serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> std::result::Result<Self, D::Error> {
+                Ok(Self::new(i32::deserialize(deserializer)?))
+            }
+        }
+
+        impl From<Index> for $name {
+            fn from(index: Index) -> Self {
+                Self(index)
+            }
+        }
+
+        impl From<$name> for Index {
+            fn from(index: $name) -> Self {
+                index.0
+            }
+        }
+    };
+}
+
+typed_index!(
+    /// Index into the vertex section. Unsigned for 1 and 2 byte sizes.
+    VertexIndex,
+    crate::vertex::Vertex,
+    false
+);
+typed_index!(
+    /// Index into the texture section.
+    TextureIndex,
+    crate::texture::Texture,
+    true
+);
+typed_index!(
+    /// Index into the material section.
+    MaterialIndex,
+    crate::material::Material,
+    true
+);
+typed_index!(
+    /// Index into the bone section.
+    BoneIndex,
+    crate::bone::Bone,
+    true
+);
+typed_index!(
+    /// Index into the morph section.
+    MorphIndex,
+    crate::morph::Morph,
+    true
+);
+typed_index!(
+    /// Index into the rigid body section.
+    RigidBodyIndex,
+    crate::rigid_body::RigidBody,
+    true
+);
+
+#[cfg(not(any(feature = "math_glam", feature = "math_nalgebra")))]
 pub type Vec2 = [f32; 2];
-#[cfg(not(feature = "math_glam"))]
+#[cfg(not(any(feature = "math_glam", feature = "math_nalgebra")))]
 pub type Vec3 = [f32; 3];
-#[cfg(not(feature = "math_glam"))]
+#[cfg(not(any(feature = "math_glam", feature = "math_nalgebra")))]
 pub type Vec4 = [f32; 4];
 
 #[cfg(feature = "math_glam")]
 pub use glam::{Vec2, Vec3, Vec4};
 
+// glam takes precedence when both are enabled, the glam-only modules rely on it
+#[cfg(all(feature = "math_nalgebra", not(feature = "math_glam")))]
+pub type Vec2 = nalgebra::Vector2<f32>;
+#[cfg(all(feature = "math_nalgebra", not(feature = "math_glam")))]
+pub type Vec3 = nalgebra::Vector3<f32>;
+#[cfg(all(feature = "math_nalgebra", not(feature = "math_glam")))]
+pub type Vec4 = nalgebra::Vector4<f32>;
+
+/// Converts a vector of the math backend into an array, which is a no-op with the array backend.
+pub(crate) fn to_array<const N: usize>(vector: impl Into<[f32; N]>) -> [f32; N] {
+    vector.into()
+}
+
+/// Converts an array into a vector of the math backend, which is a no-op with the array backend.
+pub(crate) fn from_array<T: From<[f32; N]>, const N: usize>(array: [f32; N]) -> T {
+    T::from(array)
+}
+
+// every backend converts from and into the mint types with `From`, the arrays through mint
+// itself and glam and nalgebra through their own mint features
+#[cfg(feature = "mint")]
+pub use mint;
+
+/// Implements serde for a section container as a plain sequence of its elements.
+#[cfg(feature = "serde")]
+macro_rules! serde_section {
+    ($section:ty, $item:ty, $len:ident) => {
+        impl serde::Serialize for $section {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                self.inner.serialize(serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $section {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> std::result::Result<Self, D::Error> {
+                let inner = Vec::<$item>::deserialize(deserializer)?;
+
+                Ok(Self {
+                    $len: inner.len(),
+                    inner,
+                })
+            }
+        }
+    };
+}
+#[cfg(feature = "serde")]
+pub(crate) use serde_section;
+
 macro_rules! vec_from_bytes {
     ($t:ty,$reader:ident) => {{
         const SIZE: usize = std::mem::size_of::<$t>();
@@ -247,3 +718,207 @@ macro_rules! vec_from_bytes {
     }};
 }
 pub(super) use vec_from_bytes;
+
+macro_rules! vec_to_bytes {
+    ($t:ty,$value:expr,$writer:ident) => {{
+        const COUNT: usize = std::mem::size_of::<$t>() / 4;
+
+        let floats: [f32; COUNT] = $value.into();
+
+        for float in floats {
+            $writer.write_all(&float.to_le_bytes())?;
+        }
+    }};
+}
+pub(super) use vec_to_bytes;
+
+/// Adds the helpers for attributing errors to elements and fields to a section module.
+///
+/// The module's `Error` needs `Element { index: usize, source: Box<Error> }` and
+/// `Field { field: &'static str, source: Box<Error> }` variants.
+macro_rules! error_context {
+    () => {
+        impl Error {
+            /// Separates the element index and field an error was attributed to from the error itself.
+            pub(crate) fn split_context(self) -> (Option<usize>, Option<&'static str>, Self) {
+                match self {
+                    Error::Element { index, source } => {
+                        let (_, field, source) = source.split_context();
+                        (Some(index), field, source)
+                    }
+                    Error::Field { field, source } => {
+                        let (index, _, source) = source.split_context();
+                        (index, Some(field), source)
+                    }
+                    error => (None, None, error),
+                }
+            }
+        }
+
+        /// Runs `parse`, attributing any error to the field `name`.
+        fn field<T>(name: &'static str, parse: impl FnOnce() -> Result<T>) -> Result<T> {
+            parse().map_err(|error| Error::Field {
+                field: name,
+                source: Box::new(error),
+            })
+        }
+
+        /// Attributes an error to the element at `index` of the section.
+        fn element<T>(index: usize, result: Result<T>) -> Result<T> {
+            result.map_err(|error| Error::Element {
+                index,
+                source: Box::new(error),
+            })
+        }
+    };
+}
+pub(crate) use error_context;
+
+/// The settings of a parse that reach down into the sections, derived from the parse options.
+///
+/// Element counts are checked before anything is allocated for them, so a corrupt count fails the
+/// parse instead of exhausting memory.
+#[derive(Debug, Clone, Copy)]
+pub struct ParseContext {
+    /// The largest count accepted for a single list.
+    pub(crate) max_elements: usize,
+    /// Bytes left in the stream when the context was set up, if known, which bounds every list read
+    /// after that point.
+    pub(crate) remaining: Option<u64>,
+    pub(crate) text_decoding: TextDecoding,
+    /// Whether texts keep the bytes they were read from.
+    pub(crate) retain_raw_text: bool,
+}
+
+impl ParseContext {
+    /// No bounds and strict text decoding, for data whose counts have been checked before.
+    pub const UNCHECKED: Self = Self {
+        max_elements: usize::MAX,
+        remaining: None,
+        text_decoding: TextDecoding::Strict,
+        retain_raw_text: true,
+    };
+
+    /// Checks the count of a list whose elements take up at least `min_size` bytes each.
+    pub(crate) fn check_count(self, count: usize, min_size: u64) -> Result<usize> {
+        if count > self.max_elements {
+            Err(Error::TooManyElements {
+                count,
+                limit: self.max_elements,
+            })?
+        }
+
+        if let Some(remaining) = self.remaining
+            && (count as u64).saturating_mul(min_size) > remaining
+        {
+            Err(Error::CountPastEnd { count, remaining })?
+        }
+
+        Ok(count)
+    }
+}
+
+/// Reads a single byte from the reader.
+pub(crate) fn read_u8(reader: &mut impl Read) -> std::io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+/// Reads a little-endian `u16` from the reader.
+pub(crate) fn read_u16(reader: &mut impl Read) -> std::io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+/// Reads a little-endian `i32` from the reader.
+pub(crate) fn read_i32(reader: &mut impl Read) -> std::io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+/// Reads a little-endian `u32` from the reader.
+pub(crate) fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Reads a little-endian `f32` from the reader.
+pub(crate) fn read_f32(reader: &mut impl Read) -> std::io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+/// Reads a fixed-size Shift-JIS text field, as used by the MMD formats other than PMX.
+pub(crate) fn read_shift_jis(reader: &mut impl Read, len: usize) -> std::io::Result<String> {
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(decode_shift_jis(&bytes))
+}
+
+/// Writes a single byte to the writer.
+pub(crate) fn write_u8(writer: &mut impl Write, value: u8) -> std::io::Result<()> {
+    writer.write_all(&[value])
+}
+
+/// Writes a little-endian `u16` to the writer.
+pub(crate) fn write_u16(writer: &mut impl Write, value: u16) -> std::io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Writes a little-endian `i32` to the writer.
+pub(crate) fn write_i32(writer: &mut impl Write, value: i32) -> std::io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Writes a little-endian `u32` to the writer.
+pub(crate) fn write_u32(writer: &mut impl Write, value: u32) -> std::io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Writes a little-endian `f32` to the writer.
+pub(crate) fn write_f32(writer: &mut impl Write, value: f32) -> std::io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Writes a fixed-size Shift-JIS text field, see `read_shift_jis`.
+pub(crate) fn write_shift_jis(
+    writer: &mut impl Write,
+    text: &str,
+    len: usize,
+) -> std::io::Result<()> {
+    writer.write_all(&encode_shift_jis(text, len))
+}
+
+/// Writes a section element count, which is stored as an `i32` in the file.
+pub(crate) fn write_count(writer: &mut impl Write, count: usize) -> std::io::Result<()> {
+    let count = i32::try_from(count).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "element count does not fit in an i32",
+        )
+    })?;
+
+    write_i32(writer, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsed_index_equals_new() {
+        let parsed = BoneIndex::parse(&mut &[3u8][..], IndexSize::Size1([0])).unwrap();
+        let nil = BoneIndex::parse(&mut &[0xFF, 0xFF][..], IndexSize::Size2([0; 2])).unwrap();
+
+        assert_eq!(parsed, BoneIndex::new(3));
+        assert_eq!(nil, BoneIndex::nil());
+
+        let set: std::collections::HashSet<_> = [parsed, BoneIndex::new(3)].into();
+        assert_eq!(set.len(), 1);
+    }
+}
@@ -34,3 +34,78 @@ pub(crate) fn from_utf16le(v: &[u8]) -> Result<String> {
 
     Ok(res)
 }
+
+/// Like `from_utf16le`, but replaces unpaired surrogates and a dangling odd byte with U+FFFD.
+pub(crate) fn from_utf16le_lossy(v: &[u8]) -> String {
+    let (chunks, rest) = v.as_chunks::<2>();
+
+    let mut text: String = char::decode_utf16(chunks.iter().copied().map(u16::from_le_bytes))
+        .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+
+    if !rest.is_empty() {
+        text.push(char::REPLACEMENT_CHARACTER);
+    }
+
+    text
+}
+
+/// Decodes a NUL terminated, fixed-size Shift-JIS field as used by the MMD formats.
+///
+/// Everything after the first NUL is padding and gets ignored. Tools cut names at the field size even
+/// in the middle of a double-byte character, a dangling lead byte at the end is dropped so the result
+/// matches `decode_shift_jis(&encode_shift_jis(name, len))` for the untruncated name. Other invalid
+/// sequences are replaced instead of rejected.
+pub(crate) fn decode_shift_jis(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let bytes = &bytes[..end];
+
+    let (text, _) = encoding_rs::SHIFT_JIS.decode_without_bom_handling(bytes);
+
+    let mut text = text.into_owned();
+
+    if text.ends_with(char::REPLACEMENT_CHARACTER)
+        && matches!(bytes.last(), Some(0x81..=0x9F | 0xE0..=0xFC))
+    {
+        text.pop();
+    }
+
+    text
+}
+
+/// Decodes a whole Shift-JIS text file, invalid sequences are replaced.
+pub(crate) fn decode_shift_jis_text(bytes: &[u8]) -> String {
+    let (text, _) = encoding_rs::SHIFT_JIS.decode_without_bom_handling(bytes);
+
+    text.into_owned()
+}
+
+/// Encodes text as Shift-JIS, characters without a Shift-JIS mapping are written as `?`.
+pub(crate) fn encode_shift_jis_text(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut buf = [0; 4];
+
+    for c in text.chars() {
+        let (encoded, _, unmappable) = encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut buf));
+
+        if unmappable {
+            bytes.push(b'?');
+        } else {
+            bytes.extend_from_slice(&encoded);
+        }
+    }
+
+    bytes
+}
+
+/// Encodes text into a fixed-size Shift-JIS field, padded with NULs.
+///
+/// Text that does not fit is cut at exactly `len` bytes like MMD does, even in the middle of a
+/// double-byte character, since motions match bones and morphs by the truncated bytes.
+pub(crate) fn encode_shift_jis(text: &str, len: usize) -> Vec<u8> {
+    let mut bytes = encode_shift_jis_text(text);
+
+    bytes.resize(len, 0);
+
+    bytes
+}
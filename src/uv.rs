@@ -0,0 +1,44 @@
+//! Texture coordinate conventions.
+//!
+//! PMX stores UVs with the origin at the top left of the texture, like DirectX. OpenGL puts the
+//! origin at the bottom left, [`Pmx::flip_v`] converts between the two for every place a V
+//! coordinate is stored, so UV morphs keep moving the texture the same way.
+
+use crate::{
+    morph::Offsets,
+    pmx::Pmx,
+    types::{from_array, to_array},
+};
+
+impl Pmx {
+    /// Flips the V coordinate of the UVs between the top left and bottom left texture origin.
+    ///
+    /// Maps `v` to `1 - v` in the vertex UVs and the second component of the additional UVs, and
+    /// negates the V component of UV morph offsets. The other components of the additional UVs are
+    /// left alone, models use them for arbitrary data. Flipping twice restores the model.
+    pub fn flip_v(&mut self) {
+        let flip = |v: f32| 1.0 - v;
+
+        for vertex in &mut self.vertices.inner {
+            let [u, v]: [f32; 2] = to_array(vertex.uv);
+            vertex.uv = from_array([u, flip(v)]);
+
+            for additional in vertex.extra_vec4.iter_mut().flatten() {
+                let [x, y, z, w]: [f32; 4] = to_array(*additional);
+                *additional = from_array([x, flip(y), z, w]);
+            }
+        }
+
+        for morph in &mut self.morphs.inner {
+            let (Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets)) = &mut morph.offsets
+            else {
+                continue;
+            };
+
+            for offset in offsets {
+                let [x, y, z, w]: [f32; 4] = to_array(offset.offset);
+                offset.offset = from_array([x, -y, z, w]);
+            }
+        }
+    }
+}
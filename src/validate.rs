@@ -0,0 +1,488 @@
+//! Consistency checks between the sections of a model.
+//!
+//! Parsing only checks that a file is well-formed, an index pointing past the end of the section
+//! it refers to is read just fine and only causes trouble once something resolves it.
+//! [`Pmx::validate`] walks every reference in the model and reports the broken ones, along with
+//! group and flip morphs that refer back to themselves or nest deeper than [`MAX_MORPH_DEPTH`],
+//! sub-texture materials in models without additional UVs, and rigid bodies in collision groups
+//! that do not exist.
+
+use core::fmt;
+use std::collections::VecDeque;
+
+use crate::{
+    bone::Tail,
+    display_frame::FrameEntry,
+    material::{EnvironmentBlend, Toon},
+    morph::{MAX_MORPH_DEPTH, Offsets},
+    pmx::Pmx,
+    types::{BoneIndex, MaterialIndex, MorphIndex, RigidBodyIndex, TextureIndex, VertexIndex},
+    visit::Section,
+};
+
+/// A single problem found by [`Pmx::validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub(crate) section: Section,
+    pub(crate) index: Option<usize>,
+    pub(crate) kind: ViolationKind,
+}
+
+impl Violation {
+    /// The section of the offending element.
+    pub fn section(&self) -> Section {
+        self.section
+    }
+
+    /// The index of the offending element within its section, `None` for problems concerning the
+    /// section as a whole.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    pub fn kind(&self) -> &ViolationKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "{} {index}: {}", self.section, self.kind),
+            None => write!(f, "{}: {}", self.section, self.kind),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationKind {
+    /// An index that is out of bounds for the section it refers to.
+    InvalidReference {
+        /// The field holding the index.
+        field: &'static str,
+        target: Section,
+        value: i32,
+    },
+    /// The surface counts of the materials do not add up to the size of the surface section.
+    SurfaceCountMismatch { materials: i64, surfaces: usize },
+    /// A group or flip morph that refers back to itself, directly or through other groups.
+    MorphCycle {
+        /// The morphs on the way, starting and ending with the offending one.
+        cycle: Vec<usize>,
+    },
+    /// Group and flip morphs nested deeper than [`MAX_MORPH_DEPTH`].
+    MorphNestingTooDeep { depth: usize },
+    /// A material drawing its environment texture as a sub-texture, at the first additional UV,
+    /// in a model whose vertices have none.
+    SubTextureWithoutAdditionalUv,
+    /// A rigid body in a collision group above 15.
+    CollisionGroupOutOfRange { group: u8 },
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViolationKind::InvalidReference {
+                field,
+                target,
+                value,
+            } => write!(
+                f,
+                "{field} refers to {target} {value}, which does not exist"
+            ),
+            ViolationKind::SurfaceCountMismatch {
+                materials,
+                surfaces,
+            } => write!(
+                f,
+                "materials cover {materials} surface indices, but there are {surfaces}"
+            ),
+            ViolationKind::MorphCycle { cycle } => {
+                let cycle: Vec<String> = cycle.iter().map(|i| i.to_string()).collect();
+                write!(
+                    f,
+                    "refers back to itself through morphs {}",
+                    cycle.join(" -> ")
+                )
+            }
+            ViolationKind::MorphNestingTooDeep { depth } => write!(
+                f,
+                "nests morphs {depth} deep, only {MAX_MORPH_DEPTH} are evaluated"
+            ),
+            ViolationKind::SubTextureWithoutAdditionalUv => write!(
+                f,
+                "samples its sub-texture at the first additional UV, which the vertices do not have"
+            ),
+            ViolationKind::CollisionGroupOutOfRange { group } => {
+                write!(f, "is in collision group {group}, only 0-15 exist")
+            }
+        }
+    }
+}
+
+/// Every problem found by [`Pmx::validate`], in section order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub(crate) violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    /// Returns true if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for violation in &self.violations {
+            writeln!(f, "{violation}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects the violations of one model.
+struct Checker<'a> {
+    pmx: &'a Pmx,
+    violations: Vec<Violation>,
+}
+
+impl Checker<'_> {
+    fn len(&self, section: Section) -> usize {
+        let pmx = self.pmx;
+
+        match section {
+            Section::Vertices => pmx.vertices.inner.len(),
+            Section::Surfaces => pmx.surfaces.inner.len(),
+            Section::Textures => pmx.textures.inner.len(),
+            Section::Materials => pmx.materials.inner.len(),
+            Section::Bones => pmx.bones.inner.len(),
+            Section::Morphs => pmx.morphs.inner.len(),
+            Section::DisplayFrames => pmx.display_frames.inner.len(),
+            Section::RigidBodies => pmx.rigid_bodies.inner.len(),
+            Section::Joints => pmx.joints.inner.len(),
+            Section::SoftBodies => pmx.soft_bodies.as_ref().map_or(0, |s| s.inner.len()),
+        }
+    }
+
+    /// Records a violation if `value` is out of bounds for `target`, nil passes if `nil_allowed`.
+    fn reference(
+        &mut self,
+        (section, index): (Section, usize),
+        field: &'static str,
+        target: Section,
+        value: i32,
+        nil_allowed: bool,
+    ) {
+        if value == -1 && nil_allowed {
+            return;
+        }
+
+        if usize::try_from(value).is_ok_and(|value| value < self.len(target)) {
+            return;
+        }
+
+        self.violations.push(Violation {
+            section,
+            index: Some(index),
+            kind: ViolationKind::InvalidReference {
+                field,
+                target,
+                value,
+            },
+        });
+    }
+
+    fn vertex(&mut self, at: (Section, usize), field: &'static str, index: &VertexIndex) {
+        self.reference(at, field, Section::Vertices, index.value(), false);
+    }
+
+    fn texture(&mut self, at: (Section, usize), field: &'static str, index: &TextureIndex) {
+        self.reference(at, field, Section::Textures, index.value(), true);
+    }
+
+    fn material(&mut self, at: (Section, usize), field: &'static str, index: &MaterialIndex) {
+        self.reference(at, field, Section::Materials, index.value(), true);
+    }
+
+    fn bone(&mut self, at: (Section, usize), field: &'static str, index: &BoneIndex) {
+        self.reference(at, field, Section::Bones, index.value(), true);
+    }
+
+    fn morph(&mut self, at: (Section, usize), field: &'static str, index: &MorphIndex) {
+        self.reference(at, field, Section::Morphs, index.value(), true);
+    }
+
+    fn rigid_body(&mut self, at: (Section, usize), field: &'static str, index: &RigidBodyIndex) {
+        self.reference(at, field, Section::RigidBodies, index.value(), true);
+    }
+}
+
+impl Pmx {
+    /// Checks every index in the model against the bounds of the section it refers to, that the
+    /// materials cover exactly the surface section and that group morphs nest properly.
+    ///
+    /// Nil indices are accepted wherever the format gives them a meaning, references to vertices
+    /// must always be valid.
+    pub fn validate(&self) -> ValidationReport {
+        let mut checker = Checker {
+            pmx: self,
+            violations: Vec::new(),
+        };
+
+        for (i, vertex) in self.vertices.inner.iter().enumerate() {
+            for bone in vertex.weight_deform.bone_indices() {
+                checker.bone((Section::Vertices, i), "weight bone", bone);
+            }
+        }
+
+        for (i, surface) in self.surfaces.inner.iter().enumerate() {
+            checker.vertex((Section::Surfaces, i), "vertex", &surface.index);
+        }
+
+        let mut covered: i64 = 0;
+        for (i, material) in self.materials.inner.iter().enumerate() {
+            let at = (Section::Materials, i);
+
+            checker.texture(at, "texture", &material.tex_idx);
+            checker.texture(at, "environment texture", &material.env_idx);
+            if material.env_blend == EnvironmentBlend::Additional
+                && self.header.globals.vec4_additional == 0
+            {
+                checker.violations.push(Violation {
+                    section: Section::Materials,
+                    index: Some(i),
+                    kind: ViolationKind::SubTextureWithoutAdditionalUv,
+                });
+            }
+            if let Toon::Texture(index) = &material.toon {
+                checker.texture(at, "toon texture", index);
+            }
+
+            covered += i64::from(material.surface_count);
+        }
+
+        if covered != self.surfaces.inner.len() as i64 {
+            checker.violations.push(Violation {
+                section: Section::Materials,
+                index: None,
+                kind: ViolationKind::SurfaceCountMismatch {
+                    materials: covered,
+                    surfaces: self.surfaces.inner.len(),
+                },
+            });
+        }
+
+        for (i, bone) in self.bones.inner.iter().enumerate() {
+            let at = (Section::Bones, i);
+
+            checker.bone(at, "parent", &bone.parent);
+            if let Tail::Bone(tail) = &bone.tail {
+                checker.bone(at, "tail", tail);
+            }
+            if let Some(inherit) = &bone.inherit {
+                checker.bone(at, "inherit parent", &inherit.parent);
+            }
+            if let Some(ik) = &bone.ik {
+                checker.bone(at, "IK target", &ik.target);
+                for link in &ik.links {
+                    checker.bone(at, "IK link", &link.bone);
+                }
+            }
+        }
+
+        let (cycles, depths) = self.morph_nesting();
+        for (i, morph) in self.morphs.inner.iter().enumerate() {
+            let at = (Section::Morphs, i);
+
+            let nesting = match (&cycles[i], depths[i]) {
+                (Some(cycle), _) => Some(ViolationKind::MorphCycle {
+                    cycle: cycle.clone(),
+                }),
+                (None, depth) if depth > MAX_MORPH_DEPTH => {
+                    Some(ViolationKind::MorphNestingTooDeep { depth })
+                }
+                _ => None,
+            };
+            if let Some(kind) = nesting {
+                checker.violations.push(Violation {
+                    section: Section::Morphs,
+                    index: Some(i),
+                    kind,
+                });
+            }
+
+            match &morph.offsets {
+                Offsets::Group(offsets) => offsets
+                    .iter()
+                    .for_each(|o| checker.morph(at, "group offset", &o.morph)),
+                Offsets::Vertex(offsets) => offsets
+                    .iter()
+                    .for_each(|o| checker.vertex(at, "vertex offset", &o.vertex)),
+                Offsets::Bone(offsets) => offsets
+                    .iter()
+                    .for_each(|o| checker.bone(at, "bone offset", &o.bone)),
+                Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => offsets
+                    .iter()
+                    .for_each(|o| checker.vertex(at, "UV offset", &o.vertex)),
+                Offsets::Material(offsets) => offsets
+                    .iter()
+                    .for_each(|o| checker.material(at, "material offset", &o.material)),
+                Offsets::Flip(offsets) => offsets
+                    .iter()
+                    .for_each(|o| checker.morph(at, "flip offset", &o.morph)),
+                Offsets::Impulse(offsets) => offsets
+                    .iter()
+                    .for_each(|o| checker.rigid_body(at, "impulse offset", &o.rigid_body)),
+            }
+        }
+
+        for (i, frame) in self.display_frames.inner.iter().enumerate() {
+            let at = (Section::DisplayFrames, i);
+
+            for entry in &frame.entries {
+                match entry {
+                    FrameEntry::Bone(bone) => checker.bone(at, "bone entry", bone),
+                    FrameEntry::Morph(morph) => checker.morph(at, "morph entry", morph),
+                }
+            }
+        }
+
+        for (i, rigid_body) in self.rigid_bodies.inner.iter().enumerate() {
+            checker.bone((Section::RigidBodies, i), "bone", &rigid_body.bone);
+
+            if !rigid_body.group.is_valid() {
+                checker.violations.push(Violation {
+                    section: Section::RigidBodies,
+                    index: Some(i),
+                    kind: ViolationKind::CollisionGroupOutOfRange {
+                        group: rigid_body.group.raw,
+                    },
+                });
+            }
+        }
+
+        for (i, joint) in self.joints.inner.iter().enumerate() {
+            let at = (Section::Joints, i);
+
+            checker.rigid_body(at, "rigid body A", &joint.rigid_body_a);
+            checker.rigid_body(at, "rigid body B", &joint.rigid_body_b);
+        }
+
+        let soft_bodies = self.soft_bodies.as_ref().map_or(&[][..], |s| &s.inner);
+        for (i, soft_body) in soft_bodies.iter().enumerate() {
+            let at = (Section::SoftBodies, i);
+
+            checker.material(at, "material", &soft_body.material);
+            for anchor in &soft_body.anchors {
+                checker.rigid_body(at, "anchor rigid body", &anchor.rigid_body);
+                checker.vertex(at, "anchor vertex", &anchor.vertex);
+            }
+            for vertex in &soft_body.pinned_vertices {
+                checker.vertex(at, "pinned vertex", vertex);
+            }
+        }
+
+        ValidationReport {
+            violations: checker.violations,
+        }
+    }
+
+    /// For every morph the cycle of group and flip morphs leading back to it, if any, and how deep
+    /// the morphs below it nest. Cycles do not count towards the depth.
+    ///
+    /// Both are worked out without recursion, so deep nesting can not overflow the stack.
+    fn morph_nesting(&self) -> (Vec<Option<Vec<usize>>>, Vec<usize>) {
+        let morphs = &self.morphs.inner;
+        let count = morphs.len();
+        let children: Vec<Vec<usize>> = morphs
+            .iter()
+            .map(|morph| {
+                let mut children: Vec<usize> = (morph.offsets.referenced_morphs())
+                    .filter_map(|index| index.as_usize().filter(|&i| i < count))
+                    .collect();
+                children.sort_unstable();
+                children.dedup();
+                children
+            })
+            .collect();
+
+        // a morph is on a cycle if a search from its children finds it again
+        let cycles: Vec<Option<Vec<usize>>> = (0..count)
+            .map(|start| {
+                let mut previous = vec![None; count];
+                let mut queue: VecDeque<usize> = VecDeque::new();
+                for &child in &children[start] {
+                    if previous[child].is_none() {
+                        previous[child] = Some(start);
+                        queue.push_back(child);
+                    }
+                }
+
+                while let Some(morph) = queue.pop_front() {
+                    if morph == start {
+                        let mut cycle = vec![start];
+                        let mut at = previous[start].unwrap();
+                        while at != start {
+                            cycle.push(at);
+                            at = previous[at].unwrap();
+                        }
+                        cycle.push(start);
+                        cycle.reverse();
+                        return Some(cycle);
+                    }
+                    for &child in &children[morph] {
+                        if previous[child].is_none() {
+                            previous[child] = Some(morph);
+                            queue.push_back(child);
+                        }
+                    }
+                }
+
+                None
+            })
+            .collect();
+
+        // the morphs off the cycles form a graph without cycles, a plain morph is 0 deep and a group
+        // one deeper than its deepest member
+        let nests = |i: usize| matches!(morphs[i].offsets, Offsets::Group(_) | Offsets::Flip(_));
+        let mut depths: Vec<Option<usize>> = vec![None; count];
+        for start in 0..count {
+            if cycles[start].is_some() || depths[start].is_some() {
+                continue;
+            }
+
+            let mut stack = vec![(start, 0)];
+            while let Some((morph, next)) = stack.last_mut() {
+                let morph = *morph;
+                match children[morph].get(*next) {
+                    Some(&child) => {
+                        *next += 1;
+                        if cycles[child].is_none() && depths[child].is_none() {
+                            stack.push((child, 0));
+                        }
+                    }
+                    None => {
+                        let deepest = (children[morph].iter())
+                            .filter_map(|&child| depths[child])
+                            .max()
+                            .unwrap_or(0);
+                        depths[morph] = Some(if nests(morph) { deepest + 1 } else { 0 });
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        (
+            cycles,
+            depths.into_iter().map(Option::unwrap_or_default).collect(),
+        )
+    }
+}
@@ -1,8 +1,11 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 use thiserror::Error;
 
-use crate::types::{Index, IndexSize, Vec2, Vec3, Vec4, vec_from_bytes};
+use crate::types::{
+    BoneIndex, IndexSize, ParseContext, Vec2, Vec3, Vec4, read_f32, read_i32, read_u8,
+    vec_from_bytes, vec_to_bytes, write_count, write_f32, write_u8,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -16,14 +19,37 @@ pub enum Error {
     InvalidWeightDeformType,
     #[error(transparent)]
     Type(#[from] crate::types::Error),
+    #[error("Vertex has a different amount of additional vec4s than declared in the globals")]
+    AdditionalVec4Mismatch,
+    #[error("{source} in element {index}")]
+    Element { index: usize, source: Box<Error> },
+    #[error("{source} in field {field}")]
+    Field {
+        field: &'static str,
+        source: Box<Error>,
+    },
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+crate::types::error_context!();
+
 #[derive(Debug)]
 pub struct Vertices {
-    inner: Vec<Vertex>,
-    size: usize,
+    pub(crate) inner: Vec<Vertex>,
+    pub(crate) size: usize,
+}
+
+#[cfg(feature = "serde")]
+crate::types::serde_section!(Vertices, Vertex, size);
+
+impl From<Vec<Vertex>> for Vertices {
+    fn from(inner: Vec<Vertex>) -> Self {
+        Self {
+            size: inner.len(),
+            inner,
+        }
+    }
 }
 
 impl Vertices {
@@ -33,27 +59,35 @@ impl Vertices {
         len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn vertices(&self) -> &[Vertex] {
         &self.inner
     }
 
-    pub fn parse(reader: &mut impl Read, extra_vec4_count: u8, index_size: u8) -> Result<Self> {
-        let mut size = [0; 4];
-
-        reader.read_exact(&mut size)?;
-
-        let size = i32::from_le_bytes(size);
+    pub fn parse(
+        reader: &mut impl Read,
+        extra_vec4_count: u8,
+        index_size: u8,
+        context: ParseContext,
+    ) -> Result<Self> {
+        let size = field("count", || {
+            let size = read_i32(reader)?;
 
-        if size.is_negative() {
-            Err(Error::NegativeSize)?
-        }
+            if size.is_negative() {
+                Err(Error::NegativeSize)?
+            }
 
-        let size = size as usize;
+            // position, normal, UV, a BDEF1 weight and edge scale
+            Ok(context.check_count(size as usize, 38 + 16 * extra_vec4_count as u64)?)
+        })?;
 
         let mut inner_vec = Vec::with_capacity(size);
 
-        for _ in 0..size {
-            let vert = Vertex::parse(reader, extra_vec4_count, index_size)?;
+        for i in 0..size {
+            let vert = element(i, Vertex::parse(reader, extra_vec4_count, index_size))?;
             inner_vec.push(vert);
         }
 
@@ -62,49 +96,171 @@ impl Vertices {
             size,
         })
     }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        extra_vec4_count: u8,
+        index_size: u8,
+    ) -> Result<()> {
+        write_count(writer, self.inner.len())?;
+
+        for vert in &self.inner {
+            vert.write(writer, extra_vec4_count, index_size)?;
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+/// One of the additional UV channels, which the format stores as the additional vec4s of every
+/// vertex. Their meaning is up to the shaders using them, all four components are free.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UvChannel {
+    Uv1,
+    Uv2,
+    Uv3,
+    Uv4,
+}
+
+impl UvChannel {
+    pub const ALL: [UvChannel; 4] = [
+        UvChannel::Uv1,
+        UvChannel::Uv2,
+        UvChannel::Uv3,
+        UvChannel::Uv4,
+    ];
+
+    /// The channel at `index` among the additional vec4s, `0` being UV1 as in additional UV
+    /// morphs.
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    /// The index of the channel among the additional vec4s.
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vertex {
-    pos: Vec3,
-    normal: Vec3,
-    uv: Vec2,
-    extra_vec4: Option<Vec<Vec4>>,
-    weight_deform: WeightDeform,
-    edge_scale: f32,
+    pub(crate) pos: Vec3,
+    pub(crate) normal: Vec3,
+    pub(crate) uv: Vec2,
+    pub(crate) extra_vec4: Option<Vec<Vec4>>,
+    pub(crate) weight_deform: WeightDeform,
+    pub(crate) edge_scale: f32,
 }
 
 impl Vertex {
+    /// Creates a vertex without additional vec4s and with an edge scale of 1.
+    pub fn new(position: Vec3, normal: Vec3, uv: Vec2, weight_deform: WeightDeform) -> Self {
+        Self {
+            pos: position,
+            normal,
+            uv,
+            extra_vec4: None,
+            weight_deform,
+            edge_scale: 1.0,
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.pos
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    pub fn uv(&self) -> Vec2 {
+        self.uv
+    }
+
+    /// The additional vec4s, as many as declared in the globals.
+    pub fn additional_vec4s(&self) -> &[Vec4] {
+        self.extra_vec4.as_deref().unwrap_or_default()
+    }
+
+    /// The UV of an additional channel, `None` if the model has fewer channels.
+    pub fn additional_uv(&self, channel: UvChannel) -> Option<Vec4> {
+        self.additional_vec4s()
+            .get(channel.index() as usize)
+            .copied()
+    }
+
+    pub fn weight_deform(&self) -> &WeightDeform {
+        &self.weight_deform
+    }
+
+    pub fn edge_scale(&self) -> f32 {
+        self.edge_scale
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.pos = position;
+    }
+
+    pub fn set_normal(&mut self, normal: Vec3) {
+        self.normal = normal;
+    }
+
+    pub fn set_uv(&mut self, uv: Vec2) {
+        self.uv = uv;
+    }
+
+    /// Sets the UV of an additional channel. Does nothing and returns false if the model has fewer
+    /// channels, the channel count is declared in the globals.
+    pub fn set_additional_uv(&mut self, channel: UvChannel, uv: Vec4) -> bool {
+        let slot = (self.extra_vec4.as_mut()).and_then(|v| v.get_mut(channel.index() as usize));
+        match slot {
+            Some(slot) => {
+                *slot = uv;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_weight_deform(&mut self, weight_deform: WeightDeform) {
+        self.weight_deform = weight_deform;
+    }
+
+    pub fn set_edge_scale(&mut self, edge_scale: f32) {
+        self.edge_scale = edge_scale;
+    }
+
     pub fn parse(reader: &mut impl Read, extra_vec4_count: u8, index_size: u8) -> Result<Self> {
-        let pos = vec_from_bytes!(Vec3, reader);
+        let pos = field("pos", || Ok(vec_from_bytes!(Vec3, reader)))?;
+
+        let normal = field("normal", || Ok(vec_from_bytes!(Vec3, reader)))?;
 
-        let normal = vec_from_bytes!(Vec3, reader);
+        let uv = field("uv", || Ok(vec_from_bytes!(Vec2, reader)))?;
 
-        let uv = vec_from_bytes!(Vec2, reader);
+        let vec4s = field("extra_vec4", || {
+            if extra_vec4_count == 0 {
+                return Ok(None);
+            }
 
-        let vec4s = if extra_vec4_count != 0 {
             let mut v = Vec::with_capacity(extra_vec4_count as _);
             for _ in 0..extra_vec4_count {
                 v.push(vec_from_bytes!(Vec4, reader));
             }
-            Some(v)
-        } else {
-            None
-        };
-
-        let mut weight_deform_type = [0; 1];
+            Ok(Some(v))
+        })?;
 
-        reader.read_exact(&mut weight_deform_type)?;
+        let weight_deform = field("weight_deform", || {
+            let weight_deform_type = read_u8(reader)?;
 
-        let size: IndexSize = index_size.try_into()?;
-
-        let weight_deform = WeightDeform::parse(reader, weight_deform_type[0], size, true)?;
-
-        let mut edge_scale = [0; 4];
+            let size: IndexSize = index_size.try_into()?;
 
-        reader.read_exact(&mut edge_scale)?;
+            WeightDeform::parse(reader, weight_deform_type, size)
+        })?;
 
-        let edge_scale = f32::from_le_bytes(edge_scale);
+        let edge_scale = field("edge_scale", || Ok(read_f32(reader)?))?;
 
         Ok(Self {
             pos,
@@ -115,29 +271,61 @@ impl Vertex {
             edge_scale,
         })
     }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        extra_vec4_count: u8,
+        index_size: u8,
+    ) -> Result<()> {
+        vec_to_bytes!(Vec3, self.pos, writer);
+
+        vec_to_bytes!(Vec3, self.normal, writer);
+
+        vec_to_bytes!(Vec2, self.uv, writer);
+
+        let vec4s = self.extra_vec4.as_deref().unwrap_or_default();
+
+        if vec4s.len() != extra_vec4_count as usize {
+            Err(Error::AdditionalVec4Mismatch)?
+        }
+
+        for vec4 in vec4s {
+            vec_to_bytes!(Vec4, *vec4, writer);
+        }
+
+        let size: IndexSize = index_size.try_into()?;
+
+        self.weight_deform.write(writer, size)?;
+
+        write_f32(writer, self.edge_scale)?;
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum WeightDeform {
     // ver 2.0
     Bdef1 {
-        index: Index,
+        index: BoneIndex,
     },
     // ver 2.0
     Bdef2 {
-        indices: [Index; 2],
+        indices: [BoneIndex; 2],
         // Only 1 actual weight is stored in the file, the other is calculated from it
         weights: [f32; 2],
     },
     // ver 2.0
     Bdef4 {
-        indices: [Index; 4],
+        indices: [BoneIndex; 4],
         weights: [f32; 4],
     },
     /// Spherical deform blending
     // ver 2.0
     Sdef {
-        indices: [Index; 2],
+        indices: [BoneIndex; 2],
         // Only 1 actual weight is stored in the file, the other is calculated from it
         weights: [f32; 2],
         // these fields are unsure?
@@ -149,28 +337,23 @@ pub enum WeightDeform {
     // unsure if this is correct also
     // ver 2.1
     Qdef {
-        indices: [Index; 4],
+        indices: [BoneIndex; 4],
         weights: [f32; 4],
     },
 }
 
 impl WeightDeform {
-    pub fn parse(
-        reader: &mut impl Read,
-        typ: u8,
-        size: IndexSize,
-        index_sign: bool,
-    ) -> Result<Self> {
+    pub fn parse(reader: &mut impl Read, typ: u8, size: IndexSize) -> Result<Self> {
         match typ {
             0 => {
-                let index = Index::parse(reader, size, index_sign)?;
+                let index = BoneIndex::parse(reader, size)?;
 
                 Ok(WeightDeform::Bdef1 { index })
             }
             1 => {
                 let indices = [
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
                 ];
 
                 let mut weights = [0.0; 2];
@@ -189,10 +372,10 @@ impl WeightDeform {
             }
             2 => {
                 let indices = [
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
                 ];
 
                 let mut weights = [0.0; 4];
@@ -211,8 +394,8 @@ impl WeightDeform {
             }
             3 => {
                 let indices = [
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
                 ];
 
                 let mut weights = [0.0; 2];
@@ -251,10 +434,10 @@ impl WeightDeform {
             }
             4 => {
                 let indices = [
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
-                    Index::parse(reader, size, index_sign)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
+                    BoneIndex::parse(reader, size)?,
                 ];
 
                 let mut weights = [0.0; 4];
@@ -275,3 +458,159 @@ impl WeightDeform {
         }
     }
 }
+
+/// The extra SDEF parameters, see [`WeightDeform::sdef_params`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdefParams {
+    pub c: Vec3,
+    pub r0: Vec3,
+    pub r1: Vec3,
+}
+
+impl WeightDeform {
+    /// The bone indices and weights spread over 4 slots.
+    ///
+    /// Unused slots have index -1 and weight 0. SDEF is treated like BDEF2 and QDEF like BDEF4,
+    /// use `sdef_params` to get the spherical deform parameters.
+    pub fn bone_influences(&self) -> ([i32; 4], [f32; 4]) {
+        match self {
+            WeightDeform::Bdef1 { index } => ([index.value(), -1, -1, -1], [1.0, 0.0, 0.0, 0.0]),
+            WeightDeform::Bdef2 { indices, weights }
+            | WeightDeform::Sdef {
+                indices, weights, ..
+            } => (
+                [indices[0].value(), indices[1].value(), -1, -1],
+                [weights[0], weights[1], 0.0, 0.0],
+            ),
+            WeightDeform::Bdef4 { indices, weights } | WeightDeform::Qdef { indices, weights } => {
+                (indices.map(|index| index.value()), *weights)
+            }
+        }
+    }
+
+    /// The SDEF parameters, `None` for every other deform type.
+    pub fn sdef_params(&self) -> Option<SdefParams> {
+        match self {
+            WeightDeform::Sdef { c, r0, r1, .. } => Some(SdefParams {
+                c: *c,
+                r0: *r0,
+                r1: *r1,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The bone indices referenced by this deform, including nil ones.
+    pub fn bone_indices(&self) -> &[BoneIndex] {
+        match self {
+            WeightDeform::Bdef1 { index } => std::slice::from_ref(index),
+            WeightDeform::Bdef2 { indices, .. } | WeightDeform::Sdef { indices, .. } => indices,
+            WeightDeform::Bdef4 { indices, .. } | WeightDeform::Qdef { indices, .. } => indices,
+        }
+    }
+
+    pub(crate) fn bone_indices_mut(&mut self) -> &mut [BoneIndex] {
+        match self {
+            WeightDeform::Bdef1 { index } => std::slice::from_mut(index),
+            WeightDeform::Bdef2 { indices, .. } | WeightDeform::Sdef { indices, .. } => indices,
+            WeightDeform::Bdef4 { indices, .. } | WeightDeform::Qdef { indices, .. } => indices,
+        }
+    }
+
+    /// Four joints with normalized weights, for formats without SDEF or QDEF, which are blended
+    /// linearly instead.
+    ///
+    /// Nil and out of range bones get weight 0, a vertex left without weight is bound fully to
+    /// bone 0.
+    pub fn skin_weights(&self, bone_count: usize) -> ([u16; 4], [f32; 4]) {
+        let (indices, weights): (&[BoneIndex], Vec<f32>) = match self {
+            WeightDeform::Bdef1 { index } => (std::slice::from_ref(index), vec![1.0]),
+            WeightDeform::Bdef2 { indices, weights }
+            | WeightDeform::Sdef {
+                indices, weights, ..
+            } => (indices.as_slice(), vec![weights[0], 1.0 - weights[0]]),
+            WeightDeform::Bdef4 { indices, weights } | WeightDeform::Qdef { indices, weights } => {
+                (indices.as_slice(), weights.to_vec())
+            }
+        };
+
+        let mut joints = [0; 4];
+        let mut out = [0.0; 4];
+
+        for (slot, (index, weight)) in indices.iter().zip(weights).enumerate() {
+            if let Some(index) = index.as_usize().filter(|&i| i < bone_count)
+                && weight > 0.0
+            {
+                joints[slot] = index as u16;
+                out[slot] = weight;
+            }
+        }
+
+        let sum: f32 = out.iter().sum();
+        if sum > 0.0 {
+            out = out.map(|w| w / sum);
+        } else {
+            out = [1.0, 0.0, 0.0, 0.0];
+        }
+
+        (joints, out)
+    }
+
+    /// The weight deform type byte as stored in the file.
+    pub fn typ(&self) -> u8 {
+        match self {
+            WeightDeform::Bdef1 { .. } => 0,
+            WeightDeform::Bdef2 { .. } => 1,
+            WeightDeform::Bdef4 { .. } => 2,
+            WeightDeform::Sdef { .. } => 3,
+            WeightDeform::Qdef { .. } => 4,
+        }
+    }
+
+    /// Writes the type byte followed by the deform data.
+    pub fn write(&self, writer: &mut impl Write, size: IndexSize) -> Result<()> {
+        write_u8(writer, self.typ())?;
+
+        match self {
+            WeightDeform::Bdef1 { index } => index.write(writer, size)?,
+            WeightDeform::Bdef2 { indices, weights } => {
+                for index in indices {
+                    index.write(writer, size)?;
+                }
+
+                // the 2nd weight is implicit
+                write_f32(writer, weights[0])?;
+            }
+            WeightDeform::Bdef4 { indices, weights } | WeightDeform::Qdef { indices, weights } => {
+                for index in indices {
+                    index.write(writer, size)?;
+                }
+
+                for weight in weights {
+                    write_f32(writer, *weight)?;
+                }
+            }
+            WeightDeform::Sdef {
+                indices,
+                weights,
+                c,
+                r0,
+                r1,
+            } => {
+                for index in indices {
+                    index.write(writer, size)?;
+                }
+
+                // the 2nd weight is implicit
+                write_f32(writer, weights[0])?;
+
+                vec_to_bytes!(Vec3, *c, writer);
+                vec_to_bytes!(Vec3, *r0, writer);
+                vec_to_bytes!(Vec3, *r1, writer);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+//! Streaming parsing, handing every element to a [`Visitor`] instead of collecting them.
+//!
+//! Nothing is kept in memory besides the element currently being parsed, which makes this suitable
+//! for models too large to comfortably hold in full.
+
+use core::fmt;
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use crate::{
+    bone::Bone,
+    display_frame::DisplayFrame,
+    joint::Joint,
+    material::Material,
+    morph::Morph,
+    pmx::{Error, Header, ParseOptions, Result, Version, check_joint, check_morph, check_vertex},
+    rigid_body::RigidBody,
+    soft_body::SoftBody,
+    surface::Surface,
+    texture::Texture,
+    types::read_i32,
+    vertex::Vertex,
+};
+
+/// The sections of a PMX file, in file order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Section {
+    Vertices,
+    Surfaces,
+    Textures,
+    Materials,
+    Bones,
+    Morphs,
+    DisplayFrames,
+    RigidBodies,
+    Joints,
+    /// Only present in 2.1 files.
+    SoftBodies,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Section::Vertices => "vertex",
+            Section::Surfaces => "surface",
+            Section::Textures => "texture",
+            Section::Materials => "material",
+            Section::Bones => "bone",
+            Section::Morphs => "morph",
+            Section::DisplayFrames => "display frame",
+            Section::RigidBodies => "rigid body",
+            Section::Joints => "joint",
+            Section::SoftBodies => "soft body",
+        })
+    }
+}
+
+/// Receives the elements of a PMX file as they are parsed.
+///
+/// Every method defaults to doing nothing, so only the interesting ones have to be implemented.
+/// Elements are handed over by value along with their index in their section.
+#[allow(unused_variables)]
+pub trait Visitor {
+    fn on_header(&mut self, header: &Header) {}
+
+    /// Called before the elements of a section, with the number of elements that follow.
+    fn on_section(&mut self, section: Section, count: usize) {}
+
+    fn on_vertex(&mut self, index: usize, vertex: Vertex) {}
+
+    fn on_surface(&mut self, index: usize, surface: Surface) {}
+
+    fn on_texture(&mut self, index: usize, texture: Texture) {}
+
+    fn on_material(&mut self, index: usize, material: Material) {}
+
+    fn on_bone(&mut self, index: usize, bone: Bone) {}
+
+    fn on_morph(&mut self, index: usize, morph: Morph) {}
+
+    fn on_display_frame(&mut self, index: usize, display_frame: DisplayFrame) {}
+
+    fn on_rigid_body(&mut self, index: usize, rigid_body: RigidBody) {}
+
+    fn on_joint(&mut self, index: usize, joint: Joint) {}
+
+    fn on_soft_body(&mut self, index: usize, soft_body: SoftBody) {}
+}
+
+/// Opens a `.pmx` file and streams it into the visitor.
+pub fn visit_file(path: &Path, visitor: &mut impl Visitor) -> Result<()> {
+    let fh = File::open(path)?;
+
+    let mut reader = BufReader::new(fh);
+
+    visit(&mut reader, visitor)
+}
+
+/// Streams a PMX model from the reader into the visitor, section by section.
+pub fn visit(reader: &mut impl Read, visitor: &mut impl Visitor) -> Result<()> {
+    let header = Header::parse(reader)?;
+
+    visitor.on_header(&header);
+
+    let version = header.version();
+    let globals = header.globals();
+
+    // elements are handed off one by one, only the lists inside them need bounding
+    let context = ParseOptions::default().context(None);
+
+    let count = section(reader, visitor, Section::Vertices)?;
+    for i in 0..count {
+        let vertex = Vertex::parse(reader, globals.vec4_additional, globals.bone_idx_size)?;
+        check_vertex(version, &vertex)?;
+        visitor.on_vertex(i, vertex);
+    }
+
+    let count = section(reader, visitor, Section::Surfaces)?;
+    for i in 0..count {
+        visitor.on_surface(i, Surface::parse(reader, globals.vert_idx_size)?);
+    }
+
+    let count = section(reader, visitor, Section::Textures)?;
+    for i in 0..count {
+        visitor.on_texture(i, Texture::parse(reader, globals.encoding, context)?);
+    }
+
+    let count = section(reader, visitor, Section::Materials)?;
+    for i in 0..count {
+        let material = Material::parse(reader, globals.tex_idx_size, globals.encoding, context)?;
+        visitor.on_material(i, material);
+    }
+
+    let count = section(reader, visitor, Section::Bones)?;
+    for i in 0..count {
+        visitor.on_bone(
+            i,
+            Bone::parse(reader, globals.bone_idx_size, globals.encoding, context)?,
+        );
+    }
+
+    let count = section(reader, visitor, Section::Morphs)?;
+    for i in 0..count {
+        let morph = Morph::parse(reader, globals, context)?;
+        check_morph(version, &morph)?;
+        visitor.on_morph(i, morph);
+    }
+
+    let count = section(reader, visitor, Section::DisplayFrames)?;
+    for i in 0..count {
+        visitor.on_display_frame(i, DisplayFrame::parse(reader, globals, context)?);
+    }
+
+    let count = section(reader, visitor, Section::RigidBodies)?;
+    for i in 0..count {
+        let rigid_body =
+            RigidBody::parse(reader, globals.bone_idx_size, globals.encoding, context)?;
+        visitor.on_rigid_body(i, rigid_body);
+    }
+
+    let count = section(reader, visitor, Section::Joints)?;
+    for i in 0..count {
+        let joint = Joint::parse(reader, globals.rb_idx_size, globals.encoding, context)?;
+        check_joint(version, &joint)?;
+        visitor.on_joint(i, joint);
+    }
+
+    if version >= Version::V2_1 {
+        let count = section(reader, visitor, Section::SoftBodies)?;
+        for i in 0..count {
+            visitor.on_soft_body(i, SoftBody::parse(reader, globals, context)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a section's element count and announces the section to the visitor.
+fn section(reader: &mut impl Read, visitor: &mut impl Visitor, section: Section) -> Result<usize> {
+    let count = read_i32(reader)?;
+
+    if count.is_negative() {
+        Err(Error::NegativeSize)?
+    }
+
+    let count = count as usize;
+
+    visitor.on_section(section, count);
+
+    Ok(count)
+}
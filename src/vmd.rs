@@ -0,0 +1,1081 @@
+//! MikuMikuDance motion (`.vmd`) files.
+//!
+//! A motion is a flat list of keyframes per section, in no particular order. Bone and morph frames
+//! refer to their targets by (Shift-JIS) name, so a motion can be applied to any model that uses the
+//! same names.
+
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::types::{
+    Vec3, Vec4, read_f32, read_shift_jis, read_u8, read_u32, vec_from_bytes, vec_to_bytes,
+    write_f32, write_shift_jis, write_u8, write_u32,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("File had an invalid signature, did you input the correct file?")]
+    InvalidSignature,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid shadow mode encountered")]
+    InvalidShadowMode,
+    #[error("Too many keyframes in a section to be written")]
+    TooManyFrames,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+const SIGNATURE_LEN: usize = 30;
+pub(crate) const BONE_NAME_LEN: usize = 15;
+pub(crate) const MORPH_NAME_LEN: usize = 15;
+const IK_NAME_LEN: usize = 20;
+
+/// The revision of the file format, it only changes the size of the model name field.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Version {
+    /// `Vocaloid Motion Data file`, written by very early MMD versions, 10 byte model names.
+    Legacy,
+    /// `Vocaloid Motion Data 0002`, 20 byte model names.
+    V2,
+}
+
+impl Version {
+    pub(crate) fn signature(self) -> &'static [u8] {
+        match self {
+            Self::Legacy => b"Vocaloid Motion Data file",
+            Self::V2 => b"Vocaloid Motion Data 0002",
+        }
+    }
+
+    pub(crate) fn model_name_len(self) -> usize {
+        match self {
+            Self::Legacy => 10,
+            Self::V2 => 20,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Header {
+    pub(crate) version: Version,
+    /// The model the motion was recorded on, camera motions use `カメラ・照明`.
+    pub(crate) model_name: String,
+}
+
+impl Header {
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    pub fn set_model_name(&mut self, model_name: impl Into<String>) {
+        self.model_name = model_name.into();
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let mut signature = [0; SIGNATURE_LEN];
+        reader.read_exact(&mut signature)?;
+
+        let version = [Version::V2, Version::Legacy]
+            .into_iter()
+            .find(|version| signature.starts_with(version.signature()))
+            .ok_or(Error::InvalidSignature)?;
+
+        let model_name = read_shift_jis(reader, version.model_name_len())?;
+
+        Ok(Self {
+            version,
+            model_name,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        let mut signature = [0; SIGNATURE_LEN];
+        let tag = self.version.signature();
+        signature[..tag.len()].copy_from_slice(tag);
+
+        writer.write_all(&signature)?;
+
+        write_shift_jis(writer, &self.model_name, self.version.model_name_len())?;
+
+        Ok(())
+    }
+}
+
+/// A cubic bezier easing curve going from (0, 0) to (127, 127).
+///
+/// The two control points are stored, both coordinates are in `0..=127`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bezier {
+    pub x1: u8,
+    pub y1: u8,
+    pub x2: u8,
+    pub y2: u8,
+}
+
+impl Bezier {
+    /// The curve MMD uses for new keyframes, equivalent to linear interpolation.
+    pub const LINEAR: Self = Self {
+        x1: 20,
+        y1: 20,
+        x2: 107,
+        y2: 107,
+    };
+
+    /// The eased progress at `x`, both running from 0 to 1.
+    pub fn evaluate(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        if self.x1 == self.y1 && self.x2 == self.y2 {
+            return x;
+        }
+
+        let [x1, y1, x2, y2] = [self.x1, self.y1, self.x2, self.y2].map(|v| f32::from(v) / 127.0);
+        let curve = |t: f32, p1: f32, p2: f32| {
+            let s = 1.0 - t;
+            3.0 * s * s * t * p1 + 3.0 * s * t * t * p2 + t * t * t
+        };
+
+        // the control points lie inside the unit square, so x only grows with t and bisection
+        // finds the one t that lands on it
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..24 {
+            let t = (low + high) * 0.5;
+            if curve(t, x1, x2) < x {
+                low = t;
+            } else {
+                high = t;
+            }
+        }
+
+        curve((low + high) * 0.5, y1, y2)
+    }
+}
+
+/// Interpolation curves of a bone keyframe, used for the segment ending at that keyframe.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoneInterpolation {
+    pub x: Bezier,
+    pub y: Bezier,
+    pub z: Bezier,
+    pub rotation: Bezier,
+}
+
+impl BoneInterpolation {
+    pub const LINEAR: Self = Self {
+        x: Bezier::LINEAR,
+        y: Bezier::LINEAR,
+        z: Bezier::LINEAR,
+        rotation: Bezier::LINEAR,
+    };
+
+    /// Builds the 64 byte block MMD writes: the 16 parameter bytes, followed by three copies
+    /// shifted left by one byte each and padded with `01 00 00`.
+    pub(crate) fn to_bytes(self) -> [u8; 64] {
+        let curves = [self.x, self.y, self.z, self.rotation];
+
+        let mut row = [0; 16];
+        for (channel, curve) in curves.iter().enumerate() {
+            row[channel] = curve.x1;
+            row[4 + channel] = curve.y1;
+            row[8 + channel] = curve.x2;
+            row[12 + channel] = curve.y2;
+        }
+
+        let mut bytes = [0; 64];
+        for shift in 0..4 {
+            let start = shift * 16;
+            bytes[start..start + 16 - shift].copy_from_slice(&row[shift..]);
+            if shift > 0 {
+                bytes[start + 16 - shift] = 1;
+            }
+        }
+
+        bytes
+    }
+}
+
+/// Interpolation curves of a camera keyframe, used for the segment ending at that keyframe.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraInterpolation {
+    pub x: Bezier,
+    pub y: Bezier,
+    pub z: Bezier,
+    pub rotation: Bezier,
+    pub distance: Bezier,
+    pub fov: Bezier,
+}
+
+impl CameraInterpolation {
+    pub const LINEAR: Self = Self {
+        x: Bezier::LINEAR,
+        y: Bezier::LINEAR,
+        z: Bezier::LINEAR,
+        rotation: Bezier::LINEAR,
+        distance: Bezier::LINEAR,
+        fov: Bezier::LINEAR,
+    };
+
+    pub(crate) fn to_bytes(self) -> [u8; 24] {
+        let curves = [
+            self.x,
+            self.y,
+            self.z,
+            self.rotation,
+            self.distance,
+            self.fov,
+        ];
+
+        let mut bytes = [0; 24];
+        for (chunk, curve) in bytes.chunks_exact_mut(4).zip(curves) {
+            chunk.copy_from_slice(&[curve.x1, curve.x2, curve.y1, curve.y2]);
+        }
+
+        bytes
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoneFrame {
+    pub(crate) bone: String,
+    pub(crate) frame: u32,
+    /// Relative to the bind pose of the bone.
+    pub(crate) translation: Vec3,
+    /// Quaternion in xyzw order.
+    pub(crate) rotation: Vec4,
+    /// The raw 64 byte interpolation block.
+    ///
+    /// Only the first 16 bytes carry information, the other rows are shifted copies of it that MMD
+    /// writes for compatibility.
+    #[cfg_attr(feature = "serde", serde(with = "serde_bytes_64"))]
+    pub(crate) interpolation: [u8; 64],
+}
+
+impl BoneFrame {
+    /// Creates a keyframe with linear interpolation.
+    pub fn new(bone: impl Into<String>, frame: u32, translation: Vec3, rotation: Vec4) -> Self {
+        Self {
+            bone: bone.into(),
+            frame,
+            translation,
+            rotation,
+            interpolation: BoneInterpolation::LINEAR.to_bytes(),
+        }
+    }
+
+    pub fn bone(&self) -> &str {
+        &self.bone
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    pub fn rotation(&self) -> Vec4 {
+        self.rotation
+    }
+
+    pub fn interpolation(&self) -> BoneInterpolation {
+        // the first row holds x1 for x, y, z, rotation, then y1, x2 and y2 in the same order
+        let curve = |channel: usize| Bezier {
+            x1: self.interpolation[channel],
+            y1: self.interpolation[4 + channel],
+            x2: self.interpolation[8 + channel],
+            y2: self.interpolation[12 + channel],
+        };
+
+        BoneInterpolation {
+            x: curve(0),
+            y: curve(1),
+            z: curve(2),
+            rotation: curve(3),
+        }
+    }
+
+    pub fn raw_interpolation(&self) -> &[u8; 64] {
+        &self.interpolation
+    }
+
+    pub fn set_bone(&mut self, bone: impl Into<String>) {
+        self.bone = bone.into();
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    pub fn set_translation(&mut self, translation: Vec3) {
+        self.translation = translation;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Vec4) {
+        self.rotation = rotation;
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: BoneInterpolation) {
+        self.interpolation = interpolation.to_bytes();
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let bone = read_shift_jis(reader, BONE_NAME_LEN)?;
+
+        let frame = read_u32(reader)?;
+
+        let translation: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation: Vec4 = vec_from_bytes!(Vec4, reader);
+
+        let mut interpolation = [0; 64];
+        reader.read_exact(&mut interpolation)?;
+
+        Ok(Self {
+            bone,
+            frame,
+            translation,
+            rotation,
+            interpolation,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_shift_jis(writer, &self.bone, BONE_NAME_LEN)?;
+
+        write_u32(writer, self.frame)?;
+
+        vec_to_bytes!(Vec3, self.translation, writer);
+        vec_to_bytes!(Vec4, self.rotation, writer);
+
+        writer.write_all(&self.interpolation)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MorphFrame {
+    pub(crate) morph: String,
+    pub(crate) frame: u32,
+    pub(crate) weight: f32,
+}
+
+impl MorphFrame {
+    pub fn new(morph: impl Into<String>, frame: u32, weight: f32) -> Self {
+        Self {
+            morph: morph.into(),
+            frame,
+            weight,
+        }
+    }
+
+    pub fn morph(&self) -> &str {
+        &self.morph
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    pub fn set_morph(&mut self, morph: impl Into<String>) {
+        self.morph = morph.into();
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let morph = read_shift_jis(reader, MORPH_NAME_LEN)?;
+
+        let frame = read_u32(reader)?;
+
+        let weight = read_f32(reader)?;
+
+        Ok(Self {
+            morph,
+            frame,
+            weight,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_shift_jis(writer, &self.morph, MORPH_NAME_LEN)?;
+
+        write_u32(writer, self.frame)?;
+
+        write_f32(writer, self.weight)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraFrame {
+    pub(crate) frame: u32,
+    /// Distance of the camera from the target, negative values are in front of it.
+    pub(crate) distance: f32,
+    /// The point the camera looks at.
+    pub(crate) target: Vec3,
+    /// Euler angles in radians.
+    pub(crate) rotation: Vec3,
+    /// Stored as x1, x2, y1, y2 for x, y, z, rotation, distance and fov in that order.
+    pub(crate) interpolation: [u8; 24],
+    /// Vertical field of view in degrees.
+    pub(crate) fov: u32,
+    pub(crate) perspective: bool,
+}
+
+impl CameraFrame {
+    /// Creates a perspective keyframe with linear interpolation.
+    pub fn new(frame: u32, distance: f32, target: Vec3, rotation: Vec3, fov: u32) -> Self {
+        Self {
+            frame,
+            distance,
+            target,
+            rotation,
+            interpolation: CameraInterpolation::LINEAR.to_bytes(),
+            fov,
+            perspective: true,
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn target(&self) -> Vec3 {
+        self.target
+    }
+
+    pub fn rotation(&self) -> Vec3 {
+        self.rotation
+    }
+
+    pub fn interpolation(&self) -> CameraInterpolation {
+        let curve = |channel: usize| {
+            let [x1, x2, y1, y2] = self.interpolation[channel * 4..channel * 4 + 4]
+                .try_into()
+                .expect("slice is 4 bytes long");
+
+            Bezier { x1, y1, x2, y2 }
+        };
+
+        CameraInterpolation {
+            x: curve(0),
+            y: curve(1),
+            z: curve(2),
+            rotation: curve(3),
+            distance: curve(4),
+            fov: curve(5),
+        }
+    }
+
+    pub fn raw_interpolation(&self) -> &[u8; 24] {
+        &self.interpolation
+    }
+
+    pub fn fov(&self) -> u32 {
+        self.fov
+    }
+
+    /// Whether perspective projection is on, orthographic projection is used otherwise.
+    pub fn perspective(&self) -> bool {
+        self.perspective
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    pub fn set_distance(&mut self, distance: f32) {
+        self.distance = distance;
+    }
+
+    pub fn set_target(&mut self, target: Vec3) {
+        self.target = target;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Vec3) {
+        self.rotation = rotation;
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: CameraInterpolation) {
+        self.interpolation = interpolation.to_bytes();
+    }
+
+    pub fn set_fov(&mut self, fov: u32) {
+        self.fov = fov;
+    }
+
+    pub fn set_perspective(&mut self, perspective: bool) {
+        self.perspective = perspective;
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let frame = read_u32(reader)?;
+
+        let distance = read_f32(reader)?;
+
+        let target: Vec3 = vec_from_bytes!(Vec3, reader);
+        let rotation: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        let mut interpolation = [0; 24];
+        reader.read_exact(&mut interpolation)?;
+
+        let fov = read_u32(reader)?;
+
+        // stored inverted, 0 means perspective is on
+        let perspective = read_u8(reader)? == 0;
+
+        Ok(Self {
+            frame,
+            distance,
+            target,
+            rotation,
+            interpolation,
+            fov,
+            perspective,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_u32(writer, self.frame)?;
+
+        write_f32(writer, self.distance)?;
+
+        vec_to_bytes!(Vec3, self.target, writer);
+        vec_to_bytes!(Vec3, self.rotation, writer);
+
+        writer.write_all(&self.interpolation)?;
+
+        write_u32(writer, self.fov)?;
+
+        write_u8(writer, u8::from(!self.perspective))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LightFrame {
+    pub(crate) frame: u32,
+    /// RGB in `0.0..=1.0`.
+    pub(crate) color: Vec3,
+    /// Direction the light comes from.
+    pub(crate) direction: Vec3,
+}
+
+impl LightFrame {
+    pub fn new(frame: u32, color: Vec3, direction: Vec3) -> Self {
+        Self {
+            frame,
+            color,
+            direction,
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn color(&self) -> Vec3 {
+        self.color
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    pub fn set_color(&mut self, color: Vec3) {
+        self.color = color;
+    }
+
+    pub fn set_direction(&mut self, direction: Vec3) {
+        self.direction = direction;
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let frame = read_u32(reader)?;
+
+        let color: Vec3 = vec_from_bytes!(Vec3, reader);
+        let direction: Vec3 = vec_from_bytes!(Vec3, reader);
+
+        Ok(Self {
+            frame,
+            color,
+            direction,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_u32(writer, self.frame)?;
+
+        vec_to_bytes!(Vec3, self.color, writer);
+        vec_to_bytes!(Vec3, self.direction, writer);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ShadowMode {
+    Off,
+    Mode1,
+    Mode2,
+}
+
+impl TryFrom<u8> for ShadowMode {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Off),
+            1 => Ok(Self::Mode1),
+            2 => Ok(Self::Mode2),
+            _ => Err(Error::InvalidShadowMode),
+        }
+    }
+}
+
+impl From<ShadowMode> for u8 {
+    fn from(value: ShadowMode) -> Self {
+        match value {
+            ShadowMode::Off => 0,
+            ShadowMode::Mode1 => 1,
+            ShadowMode::Mode2 => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShadowFrame {
+    pub(crate) frame: u32,
+    pub(crate) mode: ShadowMode,
+    /// The value as stored, MMD shows it as `(0.1 - distance) * 100000` in the UI.
+    pub(crate) distance: f32,
+}
+
+impl ShadowFrame {
+    pub fn new(frame: u32, mode: ShadowMode, distance: f32) -> Self {
+        Self {
+            frame,
+            mode,
+            distance,
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn mode(&self) -> ShadowMode {
+        self.mode
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    pub fn set_mode(&mut self, mode: ShadowMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_distance(&mut self, distance: f32) {
+        self.distance = distance;
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let frame = read_u32(reader)?;
+
+        let mode = read_u8(reader)?.try_into()?;
+
+        let distance = read_f32(reader)?;
+
+        Ok(Self {
+            frame,
+            mode,
+            distance,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_u32(writer, self.frame)?;
+
+        write_u8(writer, self.mode.into())?;
+
+        write_f32(writer, self.distance)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IkState {
+    pub(crate) bone: String,
+    pub(crate) enabled: bool,
+}
+
+impl IkState {
+    pub fn new(bone: impl Into<String>, enabled: bool) -> Self {
+        Self {
+            bone: bone.into(),
+            enabled,
+        }
+    }
+
+    pub fn bone(&self) -> &str {
+        &self.bone
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// Model visibility and the on/off state of IK bones, keyed by bone name.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IkFrame {
+    pub(crate) frame: u32,
+    pub(crate) visible: bool,
+    pub(crate) iks: Vec<IkState>,
+}
+
+impl IkFrame {
+    pub fn new(frame: u32, visible: bool, iks: Vec<IkState>) -> Self {
+        Self {
+            frame,
+            visible,
+            iks,
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn iks(&self) -> &[IkState] {
+        &self.iks
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn iks_mut(&mut self) -> &mut Vec<IkState> {
+        &mut self.iks
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let frame = read_u32(reader)?;
+
+        let visible = read_u8(reader)? != 0;
+
+        let count = read_u32(reader)? as usize;
+
+        let mut iks = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let bone = read_shift_jis(reader, IK_NAME_LEN)?;
+            let enabled = read_u8(reader)? != 0;
+
+            iks.push(IkState { bone, enabled });
+        }
+
+        Ok(Self {
+            frame,
+            visible,
+            iks,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        write_u32(writer, self.frame)?;
+
+        write_u8(writer, self.visible.into())?;
+
+        write_len(writer, self.iks.len())?;
+
+        for ik in &self.iks {
+            write_shift_jis(writer, &ik.bone, IK_NAME_LEN)?;
+            write_u8(writer, ik.enabled.into())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vmd {
+    pub(crate) header: Header,
+    pub(crate) bone_frames: Vec<BoneFrame>,
+    pub(crate) morph_frames: Vec<MorphFrame>,
+    pub(crate) camera_frames: Vec<CameraFrame>,
+    pub(crate) light_frames: Vec<LightFrame>,
+    pub(crate) shadow_frames: Vec<ShadowFrame>,
+    pub(crate) ik_frames: Vec<IkFrame>,
+}
+
+impl Vmd {
+    /// Creates an empty motion for the given model.
+    pub fn new(model_name: impl Into<String>) -> Self {
+        Self {
+            header: Header {
+                version: Version::V2,
+                model_name: model_name.into(),
+            },
+            bone_frames: Vec::new(),
+            morph_frames: Vec::new(),
+            camera_frames: Vec::new(),
+            light_frames: Vec::new(),
+            shadow_frames: Vec::new(),
+            ik_frames: Vec::new(),
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn bone_frames(&self) -> &[BoneFrame] {
+        &self.bone_frames
+    }
+
+    pub fn morph_frames(&self) -> &[MorphFrame] {
+        &self.morph_frames
+    }
+
+    pub fn camera_frames(&self) -> &[CameraFrame] {
+        &self.camera_frames
+    }
+
+    pub fn light_frames(&self) -> &[LightFrame] {
+        &self.light_frames
+    }
+
+    pub fn shadow_frames(&self) -> &[ShadowFrame] {
+        &self.shadow_frames
+    }
+
+    pub fn ik_frames(&self) -> &[IkFrame] {
+        &self.ik_frames
+    }
+
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    pub fn bone_frames_mut(&mut self) -> &mut Vec<BoneFrame> {
+        &mut self.bone_frames
+    }
+
+    pub fn morph_frames_mut(&mut self) -> &mut Vec<MorphFrame> {
+        &mut self.morph_frames
+    }
+
+    pub fn camera_frames_mut(&mut self) -> &mut Vec<CameraFrame> {
+        &mut self.camera_frames
+    }
+
+    pub fn light_frames_mut(&mut self) -> &mut Vec<LightFrame> {
+        &mut self.light_frames
+    }
+
+    pub fn shadow_frames_mut(&mut self) -> &mut Vec<ShadowFrame> {
+        &mut self.shadow_frames
+    }
+
+    pub fn ik_frames_mut(&mut self) -> &mut Vec<IkFrame> {
+        &mut self.ik_frames
+    }
+
+    /// The last frame any keyframe is placed on.
+    pub fn last_frame(&self) -> u32 {
+        let frames = self
+            .bone_frames
+            .iter()
+            .map(|f| f.frame)
+            .chain(self.morph_frames.iter().map(|f| f.frame))
+            .chain(self.camera_frames.iter().map(|f| f.frame))
+            .chain(self.light_frames.iter().map(|f| f.frame))
+            .chain(self.shadow_frames.iter().map(|f| f.frame))
+            .chain(self.ik_frames.iter().map(|f| f.frame));
+
+        frames.max().unwrap_or(0)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let fh = std::fs::File::open(path)?;
+
+        let mut reader = BufReader::new(fh);
+
+        Self::parse(&mut reader)
+    }
+
+    /// Parses a motion from any reader.
+    ///
+    /// Files written by older tools end after the bone, morph or camera section, the missing sections
+    /// are treated as empty.
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let header = Header::parse(reader)?;
+
+        let bone_frames = parse_section(reader, BoneFrame::parse)?;
+        let morph_frames = parse_section(reader, MorphFrame::parse)?;
+        let camera_frames = parse_section(reader, CameraFrame::parse)?;
+        let light_frames = parse_section(reader, LightFrame::parse)?;
+        let shadow_frames = parse_section(reader, ShadowFrame::parse)?;
+        let ik_frames = parse_section(reader, IkFrame::parse)?;
+
+        Ok(Self {
+            header,
+            bone_frames,
+            morph_frames,
+            camera_frames,
+            light_frames,
+            shadow_frames,
+            ik_frames,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let fh = std::fs::File::create(path)?;
+
+        let mut writer = BufWriter::new(fh);
+
+        self.write_to(&mut writer)?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the motion to any writer.
+    ///
+    /// All sections are always written, empty ones as a zero count, which every MMD version since
+    /// the introduction of the sections accepts.
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        self.header.write(writer)?;
+
+        write_section(writer, &self.bone_frames, BoneFrame::write)?;
+        write_section(writer, &self.morph_frames, MorphFrame::write)?;
+        write_section(writer, &self.camera_frames, CameraFrame::write)?;
+        write_section(writer, &self.light_frames, LightFrame::write)?;
+        write_section(writer, &self.shadow_frames, ShadowFrame::write)?;
+        write_section(writer, &self.ik_frames, IkFrame::write)?;
+
+        Ok(())
+    }
+}
+
+/// Parses a section, a missing section count at the end of the file means the section is empty.
+fn parse_section<R: Read, T>(
+    reader: &mut R,
+    parse: impl Fn(&mut R) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut count = [0; 4];
+
+    match reader.read_exact(&mut count) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(Vec::new()),
+        Err(e) => Err(e)?,
+    }
+
+    let count = u32::from_le_bytes(count) as usize;
+
+    let mut frames = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        frames.push(parse(reader)?);
+    }
+
+    Ok(frames)
+}
+
+fn write_section<W: Write, T>(
+    writer: &mut W,
+    frames: &[T],
+    write: impl Fn(&T, &mut W) -> Result<()>,
+) -> Result<()> {
+    write_len(writer, frames.len())?;
+
+    for frame in frames {
+        write(frame, writer)?;
+    }
+
+    Ok(())
+}
+
+fn write_len(writer: &mut impl Write, len: usize) -> Result<()> {
+    let len = u32::try_from(len).map_err(|_| Error::TooManyFrames)?;
+
+    write_u32(writer, len)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+mod serde_bytes_64 {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 64], serializer: S) -> Result<S::Ok, S::Error> {
+        bytes.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 64], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+
+        bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| serde::de::Error::invalid_length(bytes.len(), &"64 bytes"))
+    }
+}
@@ -0,0 +1,395 @@
+//! MikuMikuDance pose (`.vpd`) files.
+//!
+//! A pose is a Shift-JIS text file holding a single frame of bone transforms and morph weights,
+//! keyed by name:
+//!
+//! ```text
+//! Vocaloid Pose Data file
+//!
+//! miku.osm;       // parent file name
+//! 1;              // bone count
+//!
+//! Bone0{センター
+//!   0.000000,1.000000,0.000000;            // translation x,y,z
+//!   0.000000,0.000000,0.000000,1.000000;   // quaternion x,y,z,w
+//! }
+//!
+//! Morph0{まばたき
+//!   0.500000;
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{
+    pmx::Pmx,
+    types::{BoneIndex, MorphIndex, Vec3, Vec4, from_array, to_array},
+    util::{decode_shift_jis_text, encode_shift_jis_text},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("File had an invalid signature, did you input the correct file?")]
+    InvalidSignature,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed pose file, expected {0}")]
+    Malformed(&'static str),
+    #[error("Invalid number \"{0}\"")]
+    InvalidNumber(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+const SIGNATURE: &str = "Vocaloid Pose Data file";
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BonePose {
+    pub(crate) bone: String,
+    /// Relative to the bind pose of the bone.
+    pub(crate) translation: Vec3,
+    /// Quaternion in xyzw order.
+    pub(crate) rotation: Vec4,
+}
+
+impl BonePose {
+    pub fn new(bone: impl Into<String>, translation: Vec3, rotation: Vec4) -> Self {
+        Self {
+            bone: bone.into(),
+            translation,
+            rotation,
+        }
+    }
+
+    pub fn bone(&self) -> &str {
+        &self.bone
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    pub fn rotation(&self) -> Vec4 {
+        self.rotation
+    }
+
+    pub fn set_bone(&mut self, bone: impl Into<String>) {
+        self.bone = bone.into();
+    }
+
+    pub fn set_translation(&mut self, translation: Vec3) {
+        self.translation = translation;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Vec4) {
+        self.rotation = rotation;
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MorphPose {
+    pub(crate) morph: String,
+    pub(crate) weight: f32,
+}
+
+impl MorphPose {
+    pub fn new(morph: impl Into<String>, weight: f32) -> Self {
+        Self {
+            morph: morph.into(),
+            weight,
+        }
+    }
+
+    pub fn morph(&self) -> &str {
+        &self.morph
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    pub fn set_morph(&mut self, morph: impl Into<String>) {
+        self.morph = morph.into();
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vpd {
+    /// The file the pose was saved from, usually `<model>.osm`.
+    pub(crate) parent: String,
+    pub(crate) bones: Vec<BonePose>,
+    pub(crate) morphs: Vec<MorphPose>,
+}
+
+impl Vpd {
+    pub fn new(parent: impl Into<String>) -> Self {
+        Self {
+            parent: parent.into(),
+            bones: Vec::new(),
+            morphs: Vec::new(),
+        }
+    }
+
+    /// The rest pose of a model, every bone with an identity transform and every morph at 0.
+    pub fn rest_pose(pmx: &Pmx) -> Self {
+        let name = pmx.header.name.local.as_str();
+
+        Self {
+            parent: format!("{name}.osm"),
+            bones: pmx
+                .bones
+                .inner
+                .iter()
+                .map(|bone| {
+                    BonePose::new(
+                        bone.name.local.as_str(),
+                        from_array([0.0; 3]),
+                        from_array([0.0, 0.0, 0.0, 1.0]),
+                    )
+                })
+                .collect(),
+            morphs: pmx
+                .morphs
+                .inner
+                .iter()
+                .map(|morph| MorphPose::new(morph.name.local.as_str(), 0.0))
+                .collect(),
+        }
+    }
+
+    pub fn parent(&self) -> &str {
+        &self.parent
+    }
+
+    pub fn bones(&self) -> &[BonePose] {
+        &self.bones
+    }
+
+    pub fn morphs(&self) -> &[MorphPose] {
+        &self.morphs
+    }
+
+    pub fn set_parent(&mut self, parent: impl Into<String>) {
+        self.parent = parent.into();
+    }
+
+    pub fn bones_mut(&mut self) -> &mut Vec<BonePose> {
+        &mut self.bones
+    }
+
+    pub fn morphs_mut(&mut self) -> &mut Vec<MorphPose> {
+        &mut self.morphs
+    }
+
+    /// Pairs the bone poses with the bones of a model by local name.
+    ///
+    /// Poses for bones the model does not have are skipped, as MMD does when loading a pose.
+    pub fn resolve_bones<'a>(
+        &'a self,
+        pmx: &Pmx,
+    ) -> impl Iterator<Item = (BoneIndex, &'a BonePose)> + 'a {
+        let indices: HashMap<String, i32> = pmx
+            .bones
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(i, bone)| (bone.name.local.as_str().to_string(), i as i32))
+            .collect();
+
+        self.bones.iter().filter_map(move |pose| {
+            let index = *indices.get(&pose.bone)?;
+            Some((BoneIndex::new(index), pose))
+        })
+    }
+
+    /// Pairs the morph weights with the morphs of a model by local name, unknown morphs are skipped.
+    pub fn resolve_morphs<'a>(
+        &'a self,
+        pmx: &Pmx,
+    ) -> impl Iterator<Item = (MorphIndex, &'a MorphPose)> + 'a {
+        let indices: HashMap<String, i32> = pmx
+            .morphs
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(i, morph)| (morph.name.local.as_str().to_string(), i as i32))
+            .collect();
+
+        self.morphs.iter().filter_map(move |pose| {
+            let index = *indices.get(&pose.morph)?;
+            Some((MorphIndex::new(index), pose))
+        })
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+
+        Self::parse(&mut bytes.as_slice())
+    }
+
+    /// Parses a pose from any reader, the reader is consumed until EOF.
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let text = decode_shift_jis_text(&bytes);
+
+        // comments run until the end of the line and can appear after any statement
+        let text: String = text
+            .lines()
+            .map(|line| line.split_once("//").map_or(line, |(code, _)| code))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let text = text.trim_start_matches('\u{feff}').trim_start();
+
+        let mut rest = text
+            .strip_prefix(SIGNATURE)
+            .ok_or(Error::InvalidSignature)?;
+
+        let parent = take_until(&mut rest, ';', "the parent file name")?
+            .trim()
+            .to_string();
+
+        // the declared bone count is not needed, the blocks are self-delimiting
+        take_until(&mut rest, ';', "the bone count")?;
+
+        let mut bones = Vec::new();
+        let mut morphs = Vec::new();
+
+        loop {
+            rest = rest.trim_start();
+
+            if rest.is_empty() {
+                break;
+            }
+
+            let kind = take_until(&mut rest, '{', "a bone or morph block")?.trim();
+            let name = take_until(&mut rest, '\n', "a name")?.trim().to_string();
+
+            if kind.starts_with("Bone") {
+                let [x, y, z] = parse_floats(take_until(&mut rest, ';', "a translation")?)?;
+                let [qx, qy, qz, qw] = parse_floats(take_until(&mut rest, ';', "a rotation")?)?;
+
+                bones.push(BonePose::new(
+                    name,
+                    from_array([x, y, z]),
+                    from_array([qx, qy, qz, qw]),
+                ));
+            } else if kind.starts_with("Morph") {
+                let [weight] = parse_floats(take_until(&mut rest, ';', "a morph weight")?)?;
+
+                morphs.push(MorphPose::new(name, weight));
+            } else {
+                Err(Error::Malformed("a bone or morph block"))?
+            }
+
+            let end = take_until(&mut rest, '}', "the end of a block")?;
+
+            if !end.trim().is_empty() {
+                Err(Error::Malformed("the end of a block"))?
+            }
+        }
+
+        Ok(Self {
+            parent,
+            bones,
+            morphs,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let fh = std::fs::File::create(path)?;
+
+        let mut writer = BufWriter::new(fh);
+
+        self.write_to(&mut writer)?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the pose in the layout MMD uses, with CRLF line endings.
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        let mut text = String::new();
+
+        text.push_str(SIGNATURE);
+        text.push_str("\r\n\r\n");
+        text.push_str(&format!("{};\t\t// 親ファイル名\r\n", self.parent));
+        text.push_str(&format!(
+            "{};\t\t\t\t// 総ポーズボーン数\r\n\r\n",
+            self.bones.len()
+        ));
+
+        for (i, bone) in self.bones.iter().enumerate() {
+            let [x, y, z]: [f32; 3] = to_array(bone.translation);
+            let [qx, qy, qz, qw]: [f32; 4] = to_array(bone.rotation);
+
+            text.push_str(&format!("Bone{i}{{{}\r\n", bone.bone));
+            text.push_str(&format!(
+                "  {x:.6},{y:.6},{z:.6};\t\t\t\t// trans x,y,z\r\n"
+            ));
+            text.push_str(&format!(
+                "  {qx:.6},{qy:.6},{qz:.6},{qw:.6};\t\t// Quaternion x,y,z,w\r\n"
+            ));
+            text.push_str("}\r\n\r\n");
+        }
+
+        for (i, morph) in self.morphs.iter().enumerate() {
+            text.push_str(&format!("Morph{i}{{{}\r\n", morph.morph));
+            text.push_str(&format!("  {:.6};\r\n", morph.weight));
+            text.push_str("}\r\n\r\n");
+        }
+
+        writer.write_all(&encode_shift_jis_text(&text))?;
+
+        Ok(())
+    }
+}
+
+/// Splits off everything up to `delimiter`, consuming the delimiter itself.
+fn take_until<'a>(rest: &mut &'a str, delimiter: char, expected: &'static str) -> Result<&'a str> {
+    let (head, tail) = rest
+        .split_once(delimiter)
+        .ok_or(Error::Malformed(expected))?;
+
+    *rest = tail;
+
+    Ok(head)
+}
+
+fn parse_floats<const N: usize>(text: &str) -> Result<[f32; N]> {
+    let mut floats = [0.0; N];
+    let mut parts = text.split(',');
+
+    for float in &mut floats {
+        let part = parts
+            .next()
+            .ok_or(Error::Malformed("more components"))?
+            .trim();
+
+        *float = part
+            .parse()
+            .map_err(|_| Error::InvalidNumber(part.to_string()))?;
+    }
+
+    if parts.next().is_some() {
+        Err(Error::Malformed("fewer components"))?
+    }
+
+    Ok(floats)
+}
@@ -0,0 +1,630 @@
+//! VRM export, glTF with the VRM 0.x (`VRM`) or 1.0 (`VRMC_vrm`, `VRMC_springBone`) extensions.
+//!
+//! The humanoid is mapped from the standard MMD bone names, preferring the deform (`D`) bones of
+//! semi-standard rigs for the legs. Facial morphs become blend shapes / expressions, using the
+//! presets for the standard MMD names (`あ`, `まばたき`, `笑い`, ...). Physics rigid body chains
+//! are approximated with spring bones and bone-following spheres and capsules become colliders.
+//!
+//! The rest pose is exported as is, MMD models are usually modeled in an A-pose while VRM
+//! importers expect a T-pose, so arms may need to be re-posed on import.
+
+use std::collections::HashMap;
+
+use serde_json::{Value, json};
+use thiserror::Error;
+
+use crate::{
+    gltf::{self, ExportOptions, Gltf},
+    morph::{Offsets, Panel},
+    pmx::Pmx,
+    spring::ColliderShape,
+    types::{Vec3, from_array, to_array},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Gltf(#[from] gltf::Error),
+    #[error("The model has no bone for the required humanoid bone {vrm} (expected {mmd})")]
+    MissingHumanBone { vrm: String, mmd: String },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VrmVersion {
+    V0,
+    V1,
+}
+
+#[derive(Debug, Clone)]
+pub struct VrmOptions {
+    pub(crate) version: VrmVersion,
+    pub(crate) authors: Vec<String>,
+    pub(crate) export: ExportOptions,
+}
+
+impl Default for VrmOptions {
+    fn default() -> Self {
+        Self {
+            version: VrmVersion::V1,
+            authors: Vec::new(),
+            export: ExportOptions::default(),
+        }
+    }
+}
+
+impl VrmOptions {
+    pub fn new(version: VrmVersion) -> Self {
+        Self {
+            version,
+            ..Self::default()
+        }
+    }
+
+    pub fn version(&self) -> VrmVersion {
+        self.version
+    }
+
+    pub fn authors(&self) -> &[String] {
+        &self.authors
+    }
+
+    pub fn export(&self) -> &ExportOptions {
+        &self.export
+    }
+
+    pub fn set_version(&mut self, version: VrmVersion) {
+        self.version = version;
+    }
+
+    pub fn set_authors(&mut self, authors: Vec<String>) {
+        self.authors = authors;
+    }
+
+    /// The options of the underlying glTF export.
+    pub fn export_mut(&mut self) -> &mut ExportOptions {
+        &mut self.export
+    }
+}
+
+/// A humanoid bone, with the MMD bone names to look for in order of preference.
+struct HumanBone {
+    vrm1: String,
+    vrm0: String,
+    candidates: Vec<String>,
+    required_v0: bool,
+    required_v1: bool,
+}
+
+fn human_bones() -> Vec<HumanBone> {
+    let bone = |vrm: &str, candidates: &[&str], v0: bool, v1: bool| HumanBone {
+        vrm1: vrm.to_string(),
+        vrm0: vrm.to_string(),
+        candidates: candidates.iter().map(|c| c.to_string()).collect(),
+        required_v0: v0,
+        required_v1: v1,
+    };
+
+    let mut bones = vec![
+        bone("hips", &["腰", "グルーブ", "センター"], true, true),
+        bone("spine", &["上半身"], true, true),
+        bone("chest", &["上半身2"], true, false),
+        bone("upperChest", &["上半身3"], false, false),
+        bone("neck", &["首"], true, false),
+        bone("head", &["頭"], true, true),
+    ];
+
+    for (side, jp) in [("left", "左"), ("right", "右")] {
+        let named = |suffixes: &[&str]| -> Vec<String> {
+            suffixes.iter().map(|s| format!("{jp}{s}")).collect()
+        };
+        let limb = |vrm: &str, suffixes: &[&str], required: bool| HumanBone {
+            vrm1: format!("{side}{vrm}"),
+            vrm0: format!("{side}{vrm}"),
+            candidates: named(suffixes),
+            required_v0: required,
+            required_v1: required,
+        };
+
+        bones.extend([
+            limb("Eye", &["目"], false),
+            limb("UpperLeg", &["足D", "足"], true),
+            limb("LowerLeg", &["ひざD", "ひざ"], true),
+            limb("Foot", &["足首D", "足首"], true),
+            limb("Toes", &["足先EX", "つま先"], false),
+            limb("Shoulder", &["肩"], false),
+            limb("UpperArm", &["腕"], true),
+            limb("LowerArm", &["ひじ"], true),
+            limb("Hand", &["手首"], true),
+        ]);
+
+        // VRM 1.0 added the thumb metacarpal and dropped the intermediate
+        for (slot, (vrm1, vrm0)) in [
+            ("Metacarpal", "Proximal"),
+            ("Proximal", "Intermediate"),
+            ("Distal", "Distal"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            bones.push(HumanBone {
+                vrm1: format!("{side}Thumb{vrm1}"),
+                vrm0: format!("{side}Thumb{vrm0}"),
+                candidates: named(&[
+                    ["親指０", "親指１", "親指２"][slot],
+                    ["親指0", "親指1", "親指2"][slot],
+                ]),
+                required_v0: false,
+                required_v1: false,
+            });
+        }
+
+        for (finger, jp_finger) in [
+            ("Index", "人指"),
+            ("Middle", "中指"),
+            ("Ring", "薬指"),
+            ("Little", "小指"),
+        ] {
+            for (segment, (full, half)) in [
+                ("Proximal", ("１", "1")),
+                ("Intermediate", ("２", "2")),
+                ("Distal", ("３", "3")),
+            ] {
+                bones.push(limb(
+                    &format!("{finger}{segment}"),
+                    &[&format!("{jp_finger}{full}"), &format!("{jp_finger}{half}")],
+                    false,
+                ));
+            }
+        }
+    }
+
+    bones
+}
+
+/// VRM 0.x and 1.0 preset names for a standard MMD facial morph.
+struct Preset {
+    mmd: &'static str,
+    vrm0: &'static str,
+    vrm1: &'static str,
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        mmd: "あ",
+        vrm0: "a",
+        vrm1: "aa",
+    },
+    Preset {
+        mmd: "い",
+        vrm0: "i",
+        vrm1: "ih",
+    },
+    Preset {
+        mmd: "う",
+        vrm0: "u",
+        vrm1: "ou",
+    },
+    Preset {
+        mmd: "え",
+        vrm0: "e",
+        vrm1: "ee",
+    },
+    Preset {
+        mmd: "お",
+        vrm0: "o",
+        vrm1: "oh",
+    },
+    Preset {
+        mmd: "まばたき",
+        vrm0: "blink",
+        vrm1: "blink",
+    },
+    Preset {
+        mmd: "ウィンク",
+        vrm0: "blink_l",
+        vrm1: "blinkLeft",
+    },
+    Preset {
+        mmd: "ウィンク右",
+        vrm0: "blink_r",
+        vrm1: "blinkRight",
+    },
+    Preset {
+        mmd: "笑い",
+        vrm0: "joy",
+        vrm1: "happy",
+    },
+    Preset {
+        mmd: "怒り",
+        vrm0: "angry",
+        vrm1: "angry",
+    },
+    Preset {
+        mmd: "困る",
+        vrm0: "sorrow",
+        vrm1: "sad",
+    },
+    Preset {
+        mmd: "にこり",
+        vrm0: "fun",
+        vrm1: "relaxed",
+    },
+];
+
+/// A facial morph as morph target weights.
+struct Expression {
+    name: String,
+    preset: Option<&'static Preset>,
+    binds: Vec<(usize, f32)>,
+}
+
+impl Gltf {
+    /// Exports a model as VRM.
+    ///
+    /// Spring bones are an approximation of the rigid body physics, see [`crate::spring`].
+    pub fn vrm_from_pmx(pmx: &Pmx, options: &VrmOptions) -> Result<Self> {
+        let mut export = options.export.clone();
+        export.mirror_x = options.version == VrmVersion::V0;
+
+        let mut gltf = Gltf::from_pmx(pmx, &export)?;
+
+        let bones = &pmx.bones.inner;
+        let scale = export.scale;
+        let mirror = |v: Vec3| -> [f32; 3] {
+            let [x, y, z]: [f32; 3] = to_array(v);
+            match options.version {
+                VrmVersion::V0 => [-x * scale, y * scale, z * scale],
+                VrmVersion::V1 => [x * scale, y * scale, -z * scale],
+            }
+        };
+
+        // the exporter writes one node per bone followed by the mesh node
+        let mesh_node = bones.len();
+
+        let by_name: HashMap<&str, usize> = bones
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, bone)| (bone.name.local.as_str(), i))
+            .collect();
+
+        // humanoid
+
+        let mut humanoid = Vec::new();
+        for human in human_bones() {
+            let node = human
+                .candidates
+                .iter()
+                .find_map(|name| by_name.get(name.as_str()).copied());
+
+            let required = match options.version {
+                VrmVersion::V0 => human.required_v0,
+                VrmVersion::V1 => human.required_v1,
+            };
+
+            match node {
+                Some(node) => humanoid.push((human, node)),
+                None if required => Err(Error::MissingHumanBone {
+                    vrm: human.vrm1,
+                    mmd: human.candidates.join(" / "),
+                })?,
+                None => {}
+            }
+        }
+
+        let head = humanoid
+            .iter()
+            .find(|(human, _)| human.vrm1 == "head")
+            .map(|(_, node)| *node);
+
+        // expressions, indexed by morph target like the exporter assigns them
+
+        let mut targets: HashMap<usize, usize> = HashMap::new();
+        for (i, morph) in pmx.morphs.inner.iter().enumerate() {
+            if gltf::is_morph_target(&morph.offsets, &pmx.header.globals) {
+                targets.insert(i, targets.len());
+            }
+        }
+
+        let mut expressions: Vec<Expression> = Vec::new();
+
+        for (i, morph) in pmx.morphs.inner.iter().enumerate() {
+            // hidden and "other" morphs are not facial expressions
+            if !matches!(morph.panel, Panel::Eyebrow | Panel::Eye | Panel::Mouth) {
+                continue;
+            }
+
+            let binds: Vec<(usize, f32)> = match (&morph.offsets, targets.get(&i)) {
+                (_, Some(&target)) => vec![(target, 1.0)],
+                (Offsets::Group(offsets), None) => offsets
+                    .iter()
+                    .filter_map(|offset| {
+                        let target = targets.get(&offset.morph.as_usize()?)?;
+                        Some((*target, offset.weight))
+                    })
+                    .collect(),
+                _ => continue,
+            };
+
+            if binds.is_empty() {
+                continue;
+            }
+
+            let name = morph.name.local.as_str();
+            let mut preset = PRESETS.iter().find(|preset| preset.mmd == name);
+
+            // the first morph wins when several share a preset
+            if let Some(taken) = preset
+                && expressions
+                    .iter()
+                    .any(|e| e.preset.is_some_and(|p| std::ptr::eq(p, taken)))
+            {
+                preset = None;
+            }
+
+            expressions.push(Expression {
+                name: name.to_string(),
+                preset,
+                binds,
+            });
+        }
+
+        let springs = pmx.spring_bones();
+        let scaled = |length: f32| length * scale;
+
+        let json = &mut gltf.json;
+        let model_name = pmx.header.name.local.as_str().to_string();
+        let authors = if options.authors.is_empty() {
+            vec!["unknown".to_string()]
+        } else {
+            options.authors.clone()
+        };
+
+        match options.version {
+            VrmVersion::V0 => {
+                let human_bones: Vec<Value> = humanoid
+                    .iter()
+                    .map(|(human, node)| {
+                        json!({ "bone": human.vrm0, "node": node, "useDefaultValues": true })
+                    })
+                    .collect();
+
+                let blend_shape_groups: Vec<Value> = expressions
+                    .iter()
+                    .map(|expression| {
+                        json!({
+                            "name": expression.name,
+                            "presetName": expression.preset.map_or("unknown", |p| p.vrm0),
+                            "binds": expression.binds
+                                .iter()
+                                .map(|(index, weight)| {
+                                    json!({ "mesh": 0, "index": index, "weight": weight * 100.0 })
+                                })
+                                .collect::<Vec<_>>(),
+                            "materialValues": [],
+                            "isBinary": false,
+                        })
+                    })
+                    .collect();
+
+                // one collider group per bone, VRM 0.x only has spheres
+                let mut groups: Vec<(usize, Vec<Value>)> = Vec::new();
+                for collider in springs.colliders() {
+                    let (center, size) = match collider.shape() {
+                        ColliderShape::Sphere { radius } => (collider.offset(), radius),
+                        ColliderShape::Capsule { radius, tail } => {
+                            let [ox, oy, oz]: [f32; 3] = to_array(collider.offset());
+                            let [tx, ty, tz]: [f32; 3] = to_array(tail);
+                            let center: Vec3 =
+                                from_array([(ox + tx) / 2.0, (oy + ty) / 2.0, (oz + tz) / 2.0]);
+                            (center, radius)
+                        }
+                    };
+                    let center = mirror(center);
+                    let json = json!({
+                        "offset": { "x": center[0], "y": center[1], "z": center[2] },
+                        "radius": scaled(size),
+                    });
+
+                    let bone = collider.bone();
+                    match groups.iter_mut().find(|(b, _)| *b == bone) {
+                        Some((_, colliders)) => colliders.push(json),
+                        None => groups.push((bone, vec![json])),
+                    }
+                }
+
+                let collider_groups: Vec<Value> = groups
+                    .iter()
+                    .map(|(node, colliders)| json!({ "node": node, "colliders": colliders }))
+                    .collect();
+                let all_groups: Vec<usize> = (0..collider_groups.len()).collect();
+
+                // VRM 0.x has one set of parameters per chain, taken from its first bone
+                let bone_groups: Vec<Value> = springs
+                    .chains()
+                    .iter()
+                    .filter_map(|chain| {
+                        let joint = chain.joints().first()?;
+                        Some(json!({
+                            "comment": chain.name(),
+                            "stiffiness": joint.stiffness(),
+                            "gravityPower": 0.0,
+                            "gravityDir": { "x": 0.0, "y": -1.0, "z": 0.0 },
+                            "dragForce": joint.drag(),
+                            "center": -1,
+                            "hitRadius": scaled(joint.hit_radius()),
+                            "bones": [joint.bone()],
+                            "colliderGroups": all_groups,
+                        }))
+                    })
+                    .collect();
+
+                let material_properties: Vec<Value> = json["materials"]
+                    .as_array()
+                    .map_or(&[][..], Vec::as_slice)
+                    .iter()
+                    .map(|material| {
+                        json!({
+                            "name": material["name"],
+                            "shader": "VRM_USE_GLTFSHADER",
+                            "renderQueue": 2000,
+                            "floatProperties": {},
+                            "vectorProperties": {},
+                            "textureProperties": {},
+                            "keywordMap": {},
+                            "tagMap": {},
+                        })
+                    })
+                    .collect();
+
+                json["extensionsUsed"] = json!(["VRM"]);
+                json["extensions"] = json!({
+                    "VRM": {
+                        "exporterVersion": "sermmde",
+                        "specVersion": "0.0",
+                        "meta": {
+                            "title": model_name,
+                            "version": "",
+                            "author": authors.join(", "),
+                            "contactInformation": "",
+                            "reference": "",
+                            "allowedUserName": "OnlyAuthor",
+                            "violentUssageName": "Disallow",
+                            "sexualUssageName": "Disallow",
+                            "commercialUssageName": "Disallow",
+                            "otherPermissionUrl": "",
+                            "licenseName": "Other",
+                            "otherLicenseUrl": "",
+                        },
+                        "humanoid": { "humanBones": human_bones },
+                        "firstPerson": {
+                            "firstPersonBone": head.map_or(-1, |head| head as i64),
+                            "firstPersonBoneOffset": { "x": 0.0, "y": 0.06, "z": 0.0 },
+                            "meshAnnotations": [],
+                            "lookAtTypeName": "Bone",
+                        },
+                        "blendShapeMaster": { "blendShapeGroups": blend_shape_groups },
+                        "secondaryAnimation": {
+                            "boneGroups": bone_groups,
+                            "colliderGroups": collider_groups,
+                        },
+                        "materialProperties": material_properties,
+                    }
+                });
+            }
+            VrmVersion::V1 => {
+                let human_bones: serde_json::Map<String, Value> = humanoid
+                    .iter()
+                    .map(|(human, node)| (human.vrm1.clone(), json!({ "node": node })))
+                    .collect();
+
+                let mut preset = serde_json::Map::new();
+                let mut custom = serde_json::Map::new();
+                for expression in &expressions {
+                    let binds = json!({
+                        "morphTargetBinds": expression.binds
+                            .iter()
+                            .map(|(index, weight)| {
+                                json!({ "node": mesh_node, "index": index, "weight": weight })
+                            })
+                            .collect::<Vec<_>>(),
+                        "isBinary": false,
+                    });
+
+                    match expression.preset {
+                        Some(p) => preset.insert(p.vrm1.to_string(), binds),
+                        None => custom.insert(expression.name.clone(), binds),
+                    };
+                }
+
+                let colliders_json: Vec<Value> = springs
+                    .colliders()
+                    .iter()
+                    .map(|collider| {
+                        let offset = mirror(collider.offset());
+                        let shape = match collider.shape() {
+                            ColliderShape::Sphere { radius: size } => json!({
+                                "sphere": { "offset": offset, "radius": scaled(size) }
+                            }),
+                            ColliderShape::Capsule { radius: size, tail } => json!({
+                                "capsule": {
+                                    "offset": offset,
+                                    "radius": scaled(size),
+                                    "tail": mirror(tail),
+                                }
+                            }),
+                        };
+                        json!({ "node": collider.bone(), "shape": shape })
+                    })
+                    .collect();
+
+                let collider_groups = if colliders_json.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![json!({
+                        "name": "colliders",
+                        "colliders": (0..colliders_json.len()).collect::<Vec<_>>(),
+                    })]
+                };
+
+                let springs: Vec<Value> = springs
+                    .chains()
+                    .iter()
+                    .map(|chain| {
+                        let joints: Vec<Value> = chain
+                            .joints()
+                            .iter()
+                            .map(|joint| {
+                                json!({
+                                    "node": joint.bone(),
+                                    "hitRadius": scaled(joint.hit_radius()),
+                                    "stiffness": joint.stiffness(),
+                                    "gravityPower": 0.0,
+                                    "gravityDir": [0.0, -1.0, 0.0],
+                                    "dragForce": joint.drag(),
+                                })
+                            })
+                            .collect();
+
+                        let mut spring = json!({ "name": chain.name(), "joints": joints });
+                        if !collider_groups.is_empty() {
+                            spring["colliderGroups"] = json!([0]);
+                        }
+                        spring
+                    })
+                    .collect();
+
+                let mut first_person = json!({ "meshAnnotations": [] });
+                if head.is_some() {
+                    first_person["meshAnnotations"] =
+                        json!([{ "node": mesh_node, "type": "auto" }]);
+                }
+
+                json["extensionsUsed"] = json!(["VRMC_vrm", "VRMC_springBone"]);
+                json["extensions"] = json!({
+                    "VRMC_vrm": {
+                        "specVersion": "1.0",
+                        "meta": {
+                            "name": model_name,
+                            "authors": authors,
+                            "licenseUrl": "https://vrm.dev/licenses/1.0/",
+                        },
+                        "humanoid": { "humanBones": human_bones },
+                        "firstPerson": first_person,
+                        "expressions": { "preset": preset, "custom": custom },
+                    },
+                    "VRMC_springBone": {
+                        "specVersion": "1.0",
+                        "colliders": colliders_json,
+                        "colliderGroups": collider_groups,
+                        "springs": springs,
+                    },
+                });
+            }
+        }
+
+        Ok(gltf)
+    }
+}
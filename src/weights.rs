@@ -0,0 +1,176 @@
+//! Simplifying weight deforms for engines with simpler skinning.
+//!
+//! Many engines only implement linear blend skinning. SDEF and QDEF vertices already carry the
+//! bones and weights linear blending needs, [`Pmx::flatten_weights`] keeps those and drops the
+//! blending mode, SDEF becoming BDEF2 and QDEF becoming BDEF4. Joints bent far deform with the
+//! usual candy wrapper and volume loss of linear blending afterwards.
+//!
+//! Importers also tend to expect weights that sum to 1 spread over a fixed number of bones.
+//! [`Pmx::normalize_weights`] merges influences of the same bone, drops the ones below a
+//! threshold and the smallest ones past the limit, and scales the rest to sum to 1. Vertices that
+//! already satisfy all of that are left untouched.
+
+use crate::{
+    pmx::Pmx,
+    types::BoneIndex,
+    vertex::{SdefParams, WeightDeform},
+};
+
+/// How far the weights of a vertex may sum from 1 before [`Pmx::normalize_weights`] rescales them.
+pub const SUM_TOLERANCE: f32 = 1e-5;
+
+/// Settings for [`Pmx::normalize_weights`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeightOptions {
+    pub(crate) threshold: f32,
+    pub(crate) max_influences: usize,
+}
+
+impl Default for WeightOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 0.0,
+            max_influences: 4,
+        }
+    }
+}
+
+impl WeightOptions {
+    /// Options keeping every nonzero weight, up to 4 bones per vertex.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Weights below this are dropped, before normalizing. The largest weight of a vertex is
+    /// always kept.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    pub fn max_influences(&self) -> usize {
+        self.max_influences
+    }
+
+    /// The most bones a vertex is weighted to, clamped to `1..=4`.
+    pub fn set_max_influences(&mut self, max_influences: usize) {
+        self.max_influences = max_influences.clamp(1, 4);
+    }
+}
+
+impl Pmx {
+    /// Converts SDEF vertices to BDEF2 and QDEF vertices to BDEF4, see the
+    /// [module docs](crate::weights).
+    ///
+    /// Returns the SDEF parameters of every converted SDEF vertex along with its index, to keep as
+    /// side data for a renderer that implements spherical deform on its own.
+    pub fn flatten_weights(&mut self) -> Vec<(usize, SdefParams)> {
+        let mut sdef = Vec::new();
+
+        for (index, vertex) in self.vertices.inner.iter_mut().enumerate() {
+            vertex.weight_deform = match vertex.weight_deform {
+                WeightDeform::Sdef {
+                    indices,
+                    weights,
+                    c,
+                    r0,
+                    r1,
+                } => {
+                    sdef.push((index, SdefParams { c, r0, r1 }));
+                    WeightDeform::Bdef2 { indices, weights }
+                }
+                WeightDeform::Qdef { indices, weights } => WeightDeform::Bdef4 { indices, weights },
+                _ => continue,
+            };
+        }
+
+        sdef
+    }
+
+    /// Normalizes the weights of every vertex, see the [module docs](crate::weights).
+    ///
+    /// SDEF and QDEF vertices keep their type where their bones still fit it, SDEF vertices left
+    /// with a single bone become BDEF1. BDEF vertices get the smallest BDEF type that fits.
+    /// Returns the number of vertices changed.
+    pub fn normalize_weights(&mut self, options: &WeightOptions) -> usize {
+        let max_influences = options.max_influences.clamp(1, 4);
+        let mut modified = 0;
+
+        for vertex in &mut self.vertices.inner {
+            let (indices, weights) = vertex.weight_deform.bone_influences();
+
+            // unused slots and nil bones carry no weight
+            let mut influences: Vec<(i32, f32)> = Vec::with_capacity(4);
+            let mut merged = false;
+            for (index, weight) in indices.into_iter().zip(weights) {
+                if index < 0 || weight <= 0.0 {
+                    continue;
+                }
+                match influences.iter_mut().find(|(bone, _)| *bone == index) {
+                    Some((_, sum)) => {
+                        *sum += weight;
+                        merged = true;
+                    }
+                    None => influences.push((index, weight)),
+                }
+            }
+            if influences.is_empty() {
+                continue;
+            }
+
+            // the heaviest bones, kept in their original order so SDEF parameters stay with
+            // their bone
+            let original = influences.len();
+            let mut order: Vec<usize> = (0..original).collect();
+            order.sort_by(|&a, &b| influences[b].1.total_cmp(&influences[a].1));
+            let heaviest = order[0];
+            order.retain(|&i| i == heaviest || influences[i].1 >= options.threshold);
+            order.truncate(max_influences);
+            order.sort_unstable();
+            let mut influences: Vec<(i32, f32)> = order.iter().map(|&i| influences[i]).collect();
+
+            let sum: f32 = influences.iter().map(|(_, weight)| weight).sum();
+            if influences.len() == original && !merged && (sum - 1.0).abs() <= SUM_TOLERANCE {
+                continue;
+            }
+            influences.iter_mut().for_each(|(_, weight)| *weight /= sum);
+
+            vertex.weight_deform = rebuild(&vertex.weight_deform, &influences);
+            modified += 1;
+        }
+
+        modified
+    }
+}
+
+/// A weight deform of the same kind as `deform` with the given influences, at most 4 of them.
+fn rebuild(deform: &WeightDeform, influences: &[(i32, f32)]) -> WeightDeform {
+    let bone = |slot: usize| BoneIndex::new(influences.get(slot).map_or(-1, |&(index, _)| index));
+    let weight = |slot: usize| influences.get(slot).map_or(0.0, |&(_, weight)| weight);
+
+    match (deform, influences.len()) {
+        (WeightDeform::Sdef { c, r0, r1, .. }, 2) => WeightDeform::Sdef {
+            indices: [bone(0), bone(1)],
+            weights: [weight(0), weight(1)],
+            c: *c,
+            r0: *r0,
+            r1: *r1,
+        },
+        (WeightDeform::Qdef { .. }, _) => WeightDeform::Qdef {
+            indices: std::array::from_fn(bone),
+            weights: std::array::from_fn(weight),
+        },
+        (_, 1) => WeightDeform::Bdef1 { index: bone(0) },
+        (_, 2) => WeightDeform::Bdef2 {
+            indices: [bone(0), bone(1)],
+            weights: [weight(0), weight(1)],
+        },
+        _ => WeightDeform::Bdef4 {
+            indices: std::array::from_fn(bone),
+            weights: std::array::from_fn(weight),
+        },
+    }
+}
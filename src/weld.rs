@@ -0,0 +1,290 @@
+//! Merging duplicate vertices.
+//!
+//! Export pipelines often split every vertex per triangle or per material, [`Pmx::weld_vertices`]
+//! merges them back. Vertices are merged when their positions are within the given distance and,
+//! if asked for, their UVs and normals match within [`ATTRIBUTE_EPSILON`]. Everything else that
+//! changes how a vertex deforms has to be equal for it to be merged, the bone weights, SDEF
+//! parameters, edge scale, additional UVs and its offsets in every vertex and UV morph, so welding
+//! never changes how the model moves.
+//!
+//! Each vertex is compared against the first vertex of each group rather than any member, so
+//! groups do not drift further than the distance. The first vertex of a group is kept with its UV
+//! and normal, the surfaces, morph offsets and soft bodies referring to the others are redirected
+//! to it. Triangles that collapse are kept, the surface counts of the materials stay valid.
+
+use std::collections::HashMap;
+
+use crate::{
+    morph::Offsets,
+    pmx::Pmx,
+    types::{VertexIndex, to_array},
+    vertex::{Vertex, WeightDeform},
+};
+
+/// The largest difference between two UV or normal components that still counts as equal.
+pub const ATTRIBUTE_EPSILON: f32 = 1e-5;
+
+impl Pmx {
+    /// Merges duplicate vertices, see the [module docs](crate::weld).
+    ///
+    /// `position_epsilon` is the largest distance between merged vertices, `0` only merges
+    /// vertices at exactly the same position. `compare_uv` and `compare_normal` keep vertices with
+    /// different UVs or normals apart, merging across UV seams changes the texturing. Returns the
+    /// number of vertices removed.
+    pub fn weld_vertices(
+        &mut self,
+        position_epsilon: f32,
+        compare_uv: bool,
+        compare_normal: bool,
+    ) -> usize {
+        let vertices = &self.vertices.inner;
+        let offsets = self.morph_offsets_by_vertex();
+        let epsilon = position_epsilon.max(0.0);
+
+        // the kept vertices by grid cell, a cell as large as the distance so matches are always
+        // in a neighboring one
+        let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let cell = |position: [f32; 3]| -> [i64; 3] {
+            if epsilon > 0.0 {
+                position.map(|p| (p / epsilon).floor() as i64)
+            } else {
+                position.map(|p| i64::from((p + 0.0).to_bits()))
+            }
+        };
+        let neighbors = if epsilon > 0.0 { -1..=1 } else { 0..=0 };
+
+        let mut remap = Vec::with_capacity(vertices.len());
+        let mut kept = Vec::new();
+
+        for (index, vertex) in vertices.iter().enumerate() {
+            let position: [f32; 3] = to_array(vertex.pos);
+            let [x, y, z] = cell(position);
+
+            let mut found = None;
+            'search: for dx in neighbors.clone() {
+                for dy in neighbors.clone() {
+                    for dz in neighbors.clone() {
+                        let candidates = grid.get(&[x + dx, y + dy, z + dz]);
+                        for &candidate in candidates.into_iter().flatten() {
+                            let other = &vertices[candidate];
+                            let other_position: [f32; 3] = to_array(other.pos);
+
+                            if distance(position, other_position) <= epsilon
+                                && (!compare_uv || close(to_array(vertex.uv), to_array(other.uv)))
+                                && (!compare_normal
+                                    || close(to_array(vertex.normal), to_array(other.normal)))
+                                && same_deform(vertex, other)
+                                && offsets.get(&index) == offsets.get(&candidate)
+                            {
+                                found = Some(candidate);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+
+            match found {
+                Some(candidate) => remap.push(remap[candidate]),
+                None => {
+                    remap.push(kept.len());
+                    kept.push(index);
+                    grid.entry([x, y, z]).or_default().push(index);
+                }
+            }
+        }
+
+        let removed = vertices.len() - kept.len();
+        if removed == 0 {
+            return 0;
+        }
+
+        let mut old = std::mem::take(&mut self.vertices.inner)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        self.vertices.inner = kept.iter().map(|&i| old[i].take().unwrap()).collect();
+        self.vertices.size = self.vertices.inner.len();
+
+        // references past the end stay past it
+        let map = |index: &VertexIndex| -> VertexIndex {
+            match index.as_usize() {
+                Some(i) if i < remap.len() => VertexIndex::new(remap[i] as i32),
+                Some(_) => VertexIndex::new(index.value() - removed as i32),
+                None => *index,
+            }
+        };
+
+        for surface in &mut self.surfaces.inner {
+            surface.index = map(&surface.index);
+        }
+
+        for morph in &mut self.morphs.inner {
+            match &mut morph.offsets {
+                Offsets::Vertex(offsets) => {
+                    offsets.iter_mut().for_each(|o| o.vertex = map(&o.vertex));
+                    dedup(offsets, |o| o.vertex.value());
+                }
+                Offsets::Uv(offsets) | Offsets::AdditionalUv(_, offsets) => {
+                    offsets.iter_mut().for_each(|o| o.vertex = map(&o.vertex));
+                    dedup(offsets, |o| o.vertex.value());
+                }
+                _ => {}
+            }
+        }
+
+        for soft_body in self.soft_bodies.iter_mut().flat_map(|s| &mut s.inner) {
+            for anchor in &mut soft_body.anchors {
+                anchor.vertex = map(&anchor.vertex);
+            }
+            soft_body
+                .pinned_vertices
+                .iter_mut()
+                .for_each(|v| *v = map(v));
+            dedup(&mut soft_body.pinned_vertices, |v| v.value());
+        }
+
+        removed
+    }
+
+    /// The offsets of every vertex in the vertex and UV morphs, as `(morph, channel, offset)`
+    /// sorted so equal sets compare equal.
+    fn morph_offsets_by_vertex(&self) -> HashMap<usize, Vec<(usize, u8, [u32; 4])>> {
+        let mut offsets: HashMap<usize, Vec<(usize, u8, [u32; 4])>> = HashMap::new();
+        let mut push = |vertex: &VertexIndex, entry| {
+            if let Some(vertex) = vertex.as_usize() {
+                offsets.entry(vertex).or_default().push(entry);
+            }
+        };
+
+        for (index, morph) in self.morphs.inner.iter().enumerate() {
+            match &morph.offsets {
+                Offsets::Vertex(list) => {
+                    for offset in list {
+                        let [x, y, z]: [f32; 3] = to_array(offset.translation);
+                        push(&offset.vertex, (index, 0, [x, y, z, 0.0].map(f32::to_bits)));
+                    }
+                }
+                Offsets::Uv(list) => {
+                    for offset in list {
+                        let offset_bits = Into::<[f32; 4]>::into(offset.offset).map(f32::to_bits);
+                        push(&offset.vertex, (index, 1, offset_bits));
+                    }
+                }
+                Offsets::AdditionalUv(channel, list) => {
+                    for offset in list {
+                        let offset_bits = Into::<[f32; 4]>::into(offset.offset).map(f32::to_bits);
+                        push(&offset.vertex, (index, 2 + channel, offset_bits));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        offsets.values_mut().for_each(|list| list.sort_unstable());
+        offsets
+    }
+}
+
+/// Whether two vertices deform the same, their UV and normal aside.
+fn same_deform(a: &Vertex, b: &Vertex) -> bool {
+    std::mem::discriminant(&a.weight_deform) == std::mem::discriminant(&b.weight_deform)
+        && a.weight_deform.bone_influences() == b.weight_deform.bone_influences()
+        && sdef(&a.weight_deform) == sdef(&b.weight_deform)
+        && a.edge_scale == b.edge_scale
+        && additional(a) == additional(b)
+}
+
+fn sdef(deform: &WeightDeform) -> Option<[[f32; 3]; 3]> {
+    let params = deform.sdef_params()?;
+    Some([to_array(params.c), to_array(params.r0), to_array(params.r1)])
+}
+
+fn additional(vertex: &Vertex) -> Vec<[f32; 4]> {
+    vertex
+        .extra_vec4
+        .iter()
+        .flatten()
+        .map(|&v| to_array(v))
+        .collect()
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn close<const N: usize>(a: [f32; N], b: [f32; N]) -> bool {
+    a.iter()
+        .zip(&b)
+        .all(|(a, b)| (a - b).abs() <= ATTRIBUTE_EPSILON)
+}
+
+/// Keeps the first of the items with the same key.
+fn dedup<T>(items: &mut Vec<T>, key: impl Fn(&T) -> i32) {
+    let mut seen = std::collections::HashSet::new();
+    items.retain(|item| seen.insert(key(item)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bone::Bone,
+        builder::PmxBuilder,
+        material::Material,
+        morph::{Morph, Panel, VertexOffset},
+        types::{BoneIndex, Name, TextEncoding, from_array},
+    };
+
+    #[test]
+    fn weld_remaps_surfaces_and_morphs() {
+        let vertex = |position: [f32; 3]| {
+            Vertex::new(
+                from_array(position),
+                from_array([0.0, 0.0, 1.0]),
+                from_array([0.0; 2]),
+                WeightDeform::Bdef1 {
+                    index: BoneIndex::new(0),
+                },
+            )
+        };
+        let mut pmx = PmxBuilder::new()
+            .add_bone(Bone::new("root", from_array([0.0; 3])))
+            .add_vertices([
+                vertex([0.0, 0.0, 0.0]),
+                vertex([1.0, 0.0, 0.0]),
+                vertex([0.0, 0.0, 0.0]),
+                vertex([0.0, 1.0, 0.0]),
+            ])
+            .add_material(Material::new("body"), [[0, 1, 3], [2, 1, 3]])
+            .build()
+            .unwrap();
+
+        let offset = |vertex: i32| VertexOffset {
+            vertex: VertexIndex::new(vertex),
+            translation: from_array([0.0, 0.0, 1.0]),
+        };
+        pmx.morphs = vec![Morph {
+            name: Name::new("push", "push", TextEncoding::UTF16LE),
+            panel: Panel::Other,
+            offsets: Offsets::Vertex(vec![offset(1), offset(3)]),
+        }]
+        .into();
+
+        let mut bytes = Vec::new();
+        pmx.write_to(&mut bytes).unwrap();
+        let mut pmx = Pmx::parse(&mut &bytes[..]).unwrap();
+
+        assert_eq!(pmx.weld_vertices(0.0, true, true), 1);
+        assert_eq!(pmx.vertices.inner.len(), 3);
+
+        let surfaces: Vec<_> = pmx.surfaces.inner.iter().map(|s| s.index).collect();
+        assert_eq!(surfaces, [0, 1, 2, 0, 1, 2].map(VertexIndex::new));
+
+        let Offsets::Vertex(offsets) = &pmx.morphs.inner[0].offsets else {
+            panic!("not a vertex morph");
+        };
+        let targets: Vec<_> = offsets.iter().map(|o| o.vertex).collect();
+        assert_eq!(targets, [VertexIndex::new(1), VertexIndex::new(2)]);
+    }
+}
@@ -0,0 +1,175 @@
+//! Creating [wgpu](https://wgpu.rs) buffers for a model.
+//!
+//! [`ModelBuffers`] uploads the [packed vertices](PackedVertex) and the index data and keeps the
+//! [draw range](DrawRange) of each material, so drawing a model is a loop over
+//! [`ModelBuffers::draws`] with `draw_indexed`. Both buffers can also be bound as storage buffers,
+//! for skinning in a compute shader. [`BoneBuffer`] holds one matrix per bone, typically the
+//! [skinning matrices](crate::skeleton::Skeleton::skinning_matrices) of the current pose.
+
+use ::wgpu::{
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages,
+    Device, IndexFormat, Queue, ShaderStages, VertexAttribute, VertexBufferLayout, VertexStepMode,
+    util::{BufferInitDescriptor, DeviceExt},
+    vertex_attr_array,
+};
+use glam::Mat4;
+
+use crate::{
+    gpu::{self, DrawRange, PackedVertex},
+    pmx::Pmx,
+};
+
+impl PackedVertex {
+    /// Position, normal, UV, bone indices and bone weights at shader locations 0 to 4.
+    pub const ATTRIBUTES: [VertexAttribute; 5] = vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x2,
+        3 => Uint16x4,
+        4 => Float32x4,
+    ];
+
+    /// The layout of a vertex buffer holding packed vertices.
+    pub fn layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<Self>() as u64,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// The vertex and index buffers of a model, see the [module docs](self).
+#[derive(Debug)]
+pub struct ModelBuffers {
+    vertices: Buffer,
+    indices: Buffer,
+    index_count: u32,
+    draws: Vec<DrawRange>,
+}
+
+impl ModelBuffers {
+    pub fn new(device: &Device, pmx: &Pmx) -> Result<Self, gpu::Error> {
+        let vertices = pmx.pack_vertices()?;
+        let indices = pmx.index_data();
+        let name = pmx.header.name.local.as_str();
+
+        let vertex_label = format!("{name} vertices");
+        let vertices = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&vertex_label),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: BufferUsages::VERTEX | BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        let index_label = format!("{name} indices");
+        let index_count = indices.len() as u32;
+        let indices = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&index_label),
+            contents: bytemuck::cast_slice(&indices),
+            usage: BufferUsages::INDEX | BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        Ok(Self {
+            vertices,
+            indices,
+            index_count,
+            draws: pmx.draw_ranges(),
+        })
+    }
+
+    /// The packed vertices, laid out as [`PackedVertex::layout`].
+    pub fn vertices(&self) -> &Buffer {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &Buffer {
+        &self.indices
+    }
+
+    pub fn index_format(&self) -> IndexFormat {
+        IndexFormat::Uint32
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    /// The index range of each material, in material order.
+    pub fn draws(&self) -> &[DrawRange] {
+        &self.draws
+    }
+}
+
+/// A storage buffer of bone matrices, as an `array<mat4x4<f32>>` in WGSL.
+#[derive(Debug)]
+pub struct BoneBuffer {
+    buffer: Buffer,
+    len: usize,
+}
+
+impl BoneBuffer {
+    /// Creates a buffer for `len` bones, all set to the identity.
+    ///
+    /// Room for at least one matrix is made, as wgpu cannot bind an empty buffer.
+    pub fn new(device: &Device, len: usize) -> Self {
+        let identities = vec![Mat4::IDENTITY.to_cols_array(); len.max(1)];
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("bone matrices"),
+            contents: bytemuck::cast_slice(&identities),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        Self { buffer, len }
+    }
+
+    /// Creates a buffer for every bone of `pmx`.
+    pub fn for_model(device: &Device, pmx: &Pmx) -> Self {
+        Self::new(device, pmx.bones.inner.len())
+    }
+
+    /// Uploads the matrices, ones past the length of the buffer are ignored.
+    pub fn write(&self, queue: &Queue, matrices: &[Mat4]) {
+        let columns: Vec<[f32; 16]> = matrices
+            .iter()
+            .take(self.len)
+            .map(Mat4::to_cols_array)
+            .collect();
+
+        if !columns.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&columns));
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// The number of bones.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The whole buffer, for a bind group entry.
+    pub fn binding(&self) -> BindingResource<'_> {
+        self.buffer.as_entire_binding()
+    }
+
+    /// The bind group layout entry for the buffer, read-only in the given stages.
+    pub fn layout_entry(binding: u32, visibility: ShaderStages) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
@@ -0,0 +1,1026 @@
+//! DirectX `.x` files, the format MMD uses for accessories (stages, props, effects).
+//!
+//! Both the text and the binary encoding are supported, the compressed variants are not. Only the
+//! parts MMD uses are interpreted: meshes with their normals, texture coordinates and materials,
+//! optionally nested in frames whose transforms are applied. Everything else (animations, skinning,
+//! templates) is skipped.
+
+use std::{collections::HashMap, io::Read, path::Path};
+
+use thiserror::Error;
+
+use crate::{
+    bone::{Bone, BoneFlags, Tail},
+    display_frame::{DisplayFrame, FrameEntry},
+    material::{EnvironmentBlend, Material as PmxMaterial, MaterialFlags, Toon},
+    pmd::is_sphere_map,
+    pmx::{Comment, Globals, Header, ModelName, Pmx, Version},
+    surface::Surface,
+    texture::Texture,
+    types::{
+        BoneIndex, IndexSize, Name, PmxText, TextEncoding, TextureIndex, Vec2, Vec3, Vec4,
+        VertexIndex, from_array, to_array,
+    },
+    util::decode_shift_jis_text,
+    vertex::{Vertex, WeightDeform},
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("File had an invalid header, did you input the correct file?")]
+    InvalidHeader,
+    #[error("Compressed .x files are not supported")]
+    Compressed,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Unexpected end of file")]
+    UnexpectedEnd,
+    #[error("Unknown binary token {0}")]
+    UnknownToken(u16),
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Text(String),
+    Number(f64),
+    Guid,
+    OpenBrace,
+    CloseBrace,
+    Template,
+}
+
+/// Tokenizes the text encoding, separators carry no information for the templates we read and are
+/// dropped.
+fn tokenize_text(bytes: &[u8]) -> Result<Vec<Token>> {
+    let text = decode_shift_jis_text(bytes);
+    let mut chars = text.chars().peekable();
+    let mut tokens = Vec::new();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() || c == ';' || c == ',' => {
+                chars.next();
+            }
+            '/' | '#' => {
+                // `//` and `#` comments run until the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '{' => {
+                chars.next();
+                tokens.push(Token::OpenBrace);
+            }
+            '}' => {
+                chars.next();
+                tokens.push(Token::CloseBrace);
+            }
+            '"' => {
+                chars.next();
+                let string: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                tokens.push(Token::Text(string));
+            }
+            '<' => {
+                for c in chars.by_ref() {
+                    if c == '>' {
+                        break;
+                    }
+                }
+                tokens.push(Token::Guid);
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.' | '#') {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // exporters occasionally write `1.#QNAN` and similar, those become NaN
+                tokens.push(Token::Number(number.parse().unwrap_or(f64::NAN)));
+            }
+            _ => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '{' | '}' | ';' | ',' | '"' | '<') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                if name == "template" {
+                    tokens.push(Token::Template);
+                } else {
+                    tokens.push(Token::Name(name));
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Tokenizes the binary encoding, lists are flattened into single numbers.
+fn tokenize_binary(mut bytes: &[u8], double: bool) -> Result<Vec<Token>> {
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if bytes.len() < len {
+            Err(Error::UnexpectedEnd)?
+        }
+        let (head, tail) = bytes.split_at(len);
+        *bytes = tail;
+        Ok(head)
+    }
+
+    fn u16_at(bytes: &mut &[u8]) -> Result<u16> {
+        Ok(u16::from_le_bytes(take(bytes, 2)?.try_into().unwrap()))
+    }
+
+    fn u32_at(bytes: &mut &[u8]) -> Result<u32> {
+        Ok(u32::from_le_bytes(take(bytes, 4)?.try_into().unwrap()))
+    }
+
+    let mut tokens = Vec::new();
+
+    while !bytes.is_empty() {
+        match u16_at(&mut bytes)? {
+            // name
+            1 => {
+                let len = u32_at(&mut bytes)? as usize;
+                tokens.push(Token::Name(decode_shift_jis_text(take(&mut bytes, len)?)));
+            }
+            // string, followed by its terminating separator token
+            2 => {
+                let len = u32_at(&mut bytes)? as usize;
+                tokens.push(Token::Text(decode_shift_jis_text(take(&mut bytes, len)?)));
+                u16_at(&mut bytes)?;
+            }
+            // integer
+            3 => tokens.push(Token::Number(u32_at(&mut bytes)? as f64)),
+            // guid
+            5 => {
+                take(&mut bytes, 16)?;
+                tokens.push(Token::Guid);
+            }
+            // integer list
+            6 => {
+                let count = u32_at(&mut bytes)?;
+                for _ in 0..count {
+                    tokens.push(Token::Number(u32_at(&mut bytes)? as f64));
+                }
+            }
+            // float list
+            7 => {
+                let count = u32_at(&mut bytes)?;
+                for _ in 0..count {
+                    let float = if double {
+                        f64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap())
+                    } else {
+                        f32::from_le_bytes(take(&mut bytes, 4)?.try_into().unwrap()) as f64
+                    };
+                    tokens.push(Token::Number(float));
+                }
+            }
+            10 => tokens.push(Token::OpenBrace),
+            11 => tokens.push(Token::CloseBrace),
+            31 => tokens.push(Token::Template),
+            // punctuation and the primitive type keywords used in templates
+            12..=20 | 40..=53 => {}
+            token => Err(Error::UnknownToken(token))?,
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug)]
+enum Child {
+    Object(Object),
+    Reference(String),
+}
+
+/// A data object, its members are flattened into `data` in declaration order.
+#[derive(Debug)]
+struct Object {
+    template: String,
+    name: Option<String>,
+    data: Vec<Value>,
+    children: Vec<Child>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.position + offset)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.peek(0).cloned().ok_or(Error::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn objects(&mut self) -> Result<Vec<Object>> {
+        let mut objects = Vec::new();
+
+        while let Some(token) = self.peek(0) {
+            match token {
+                Token::Template => self.skip_template()?,
+                Token::Name(_) => objects.push(self.object()?),
+                _ => Err(Error::Malformed("top level object"))?,
+            }
+        }
+
+        Ok(objects)
+    }
+
+    fn skip_template(&mut self) -> Result<()> {
+        self.next()?;
+
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::OpenBrace => depth += 1,
+                Token::CloseBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Object> {
+        let Token::Name(template) = self.next()? else {
+            Err(Error::Malformed("object header"))?
+        };
+
+        let name = match self.next()? {
+            Token::Name(name) => {
+                if self.next()? != Token::OpenBrace {
+                    Err(Error::Malformed("object header"))?
+                }
+                Some(name)
+            }
+            Token::OpenBrace => None,
+            _ => Err(Error::Malformed("object header"))?,
+        };
+
+        let mut object = Object {
+            template,
+            name,
+            data: Vec::new(),
+            children: Vec::new(),
+        };
+
+        loop {
+            match self.peek(0).ok_or(Error::UnexpectedEnd)? {
+                Token::CloseBrace => {
+                    self.next()?;
+                    return Ok(object);
+                }
+                Token::Number(number) => {
+                    object.data.push(Value::Number(*number));
+                    self.next()?;
+                }
+                Token::Text(text) => {
+                    object.data.push(Value::Text(text.clone()));
+                    self.next()?;
+                }
+                Token::Guid => {
+                    self.next()?;
+                }
+                Token::OpenBrace => {
+                    // `{ Name }`, a reference to an object declared elsewhere
+                    self.next()?;
+                    let mut name = None;
+                    loop {
+                        match self.next()? {
+                            Token::CloseBrace => break,
+                            Token::Name(n) => name = Some(n),
+                            _ => {}
+                        }
+                    }
+                    if let Some(name) = name {
+                        object.children.push(Child::Reference(name));
+                    }
+                }
+                Token::Name(name) => {
+                    let is_object = matches!(
+                        (self.peek(1), self.peek(2)),
+                        (Some(Token::OpenBrace), _)
+                            | (Some(Token::Name(_)), Some(Token::OpenBrace))
+                    );
+
+                    if is_object {
+                        let child = self.object()?;
+                        object.children.push(Child::Object(child));
+                    } else {
+                        object.data.push(Value::Text(name.clone()));
+                        self.next()?;
+                    }
+                }
+                Token::Template => self.skip_template()?,
+            }
+        }
+    }
+}
+
+/// Reads the flattened members of an object in order.
+struct Cursor<'a> {
+    data: &'a [Value],
+    what: &'static str,
+}
+
+impl Cursor<'_> {
+    fn number(&mut self) -> Result<f64> {
+        match self.data.split_first() {
+            Some((Value::Number(number), rest)) => {
+                self.data = rest;
+                Ok(*number)
+            }
+            _ => Err(Error::Malformed(self.what)),
+        }
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        Ok(self.number()? as usize)
+    }
+
+    fn float(&mut self) -> Result<f32> {
+        Ok(self.number()? as f32)
+    }
+
+    fn text(&mut self) -> Result<String> {
+        match self.data.split_first() {
+            Some((Value::Text(text), rest)) => {
+                self.data = rest;
+                Ok(text.clone())
+            }
+            _ => Err(Error::Malformed(self.what)),
+        }
+    }
+
+    fn vec2(&mut self) -> Result<Vec2> {
+        Ok(from_array([self.float()?, self.float()?]))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3> {
+        Ok(from_array([self.float()?, self.float()?, self.float()?]))
+    }
+
+    fn vec4(&mut self) -> Result<Vec4> {
+        let vector = [self.float()?, self.float()?, self.float()?, self.float()?];
+        Ok(from_array(vector))
+    }
+
+    fn faces(&mut self) -> Result<Vec<Vec<u32>>> {
+        let count = self.count()?;
+
+        (0..count)
+            .map(|_| {
+                let corners = self.count()?;
+                (0..corners).map(|_| Ok(self.number()? as u32)).collect()
+            })
+            .collect()
+    }
+}
+
+fn cursor<'a>(object: &'a Object, what: &'static str) -> Cursor<'a> {
+    Cursor {
+        data: &object.data,
+        what,
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Material {
+    pub(crate) name: Option<String>,
+    /// RGBA.
+    pub(crate) diffuse: Vec4,
+    pub(crate) power: f32,
+    pub(crate) specular: Vec3,
+    pub(crate) emissive: Vec3,
+    /// The raw texture file name, `texture*sphere` when both a texture and a sphere map are set.
+    pub(crate) texture: Option<String>,
+}
+
+impl Material {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn diffuse(&self) -> Vec4 {
+        self.diffuse
+    }
+
+    pub fn power(&self) -> f32 {
+        self.power
+    }
+
+    pub fn specular(&self) -> Vec3 {
+        self.specular
+    }
+
+    pub fn emissive(&self) -> Vec3 {
+        self.emissive
+    }
+
+    pub fn raw_texture(&self) -> Option<&str> {
+        self.texture.as_deref()
+    }
+
+    /// The color texture, without the sphere map part of the texture name.
+    pub fn texture(&self) -> Option<&str> {
+        self.texture_parts().find(|path| !is_sphere_map(path))
+    }
+
+    pub fn sphere(&self) -> Option<&str> {
+        self.texture_parts().find(|path| is_sphere_map(path))
+    }
+
+    fn texture_parts(&self) -> impl Iterator<Item = &str> {
+        self.texture
+            .iter()
+            .flat_map(|texture| texture.split('*'))
+            .filter(|path| !path.is_empty())
+    }
+
+    fn from_object(object: &Object) -> Result<Self> {
+        let mut data = cursor(object, "material");
+
+        let diffuse = data.vec4()?;
+        let power = data.float()?;
+        let specular = data.vec3()?;
+        let emissive = data.vec3()?;
+
+        let texture = object
+            .children
+            .iter()
+            .find_map(|child| match child {
+                Child::Object(o) if o.template == "TextureFilename" => Some(o),
+                _ => None,
+            })
+            .map(|o| cursor(o, "texture file name").text())
+            .transpose()?;
+
+        Ok(Self {
+            name: object.name.clone(),
+            diffuse,
+            power,
+            specular,
+            emissive,
+            texture,
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mesh {
+    pub(crate) name: Option<String>,
+    /// Positions with the transforms of enclosing frames applied.
+    pub(crate) positions: Vec<Vec3>,
+    /// Polygons as indices into `positions`, not necessarily triangles.
+    pub(crate) faces: Vec<Vec<u32>>,
+    pub(crate) normals: Vec<Vec3>,
+    /// Polygons as indices into `normals`, matching `faces` one to one.
+    pub(crate) normal_faces: Vec<Vec<u32>>,
+    /// One per position, empty when the mesh has no texture coordinates.
+    pub(crate) uvs: Vec<Vec2>,
+    pub(crate) materials: Vec<Material>,
+    /// One index into `materials` per face, empty when the mesh has no material list.
+    pub(crate) face_materials: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    pub fn faces(&self) -> &[Vec<u32>] {
+        &self.faces
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn normal_faces(&self) -> &[Vec<u32>] {
+        &self.normal_faces
+    }
+
+    pub fn uvs(&self) -> &[Vec2] {
+        &self.uvs
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn face_materials(&self) -> &[u32] {
+        &self.face_materials
+    }
+
+    fn from_object(
+        object: &Object,
+        transform: &Matrix,
+        shared: &HashMap<&str, &Object>,
+    ) -> Result<Self> {
+        let mut data = cursor(object, "mesh");
+
+        let count = data.count()?;
+        let positions = (0..count)
+            .map(|_| Ok(transform.point(data.vec3()?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let faces = data.faces()?;
+
+        let mut mesh = Self {
+            name: object.name.clone(),
+            positions,
+            faces,
+            normals: Vec::new(),
+            normal_faces: Vec::new(),
+            uvs: Vec::new(),
+            materials: Vec::new(),
+            face_materials: Vec::new(),
+        };
+
+        for child in &object.children {
+            let Child::Object(child) = child else {
+                continue;
+            };
+
+            match child.template.as_str() {
+                "MeshNormals" => {
+                    let mut data = cursor(child, "mesh normals");
+
+                    let count = data.count()?;
+                    mesh.normals = (0..count)
+                        .map(|_| Ok(transform.normal(data.vec3()?)))
+                        .collect::<Result<_>>()?;
+
+                    mesh.normal_faces = data.faces()?;
+                }
+                "MeshTextureCoords" => {
+                    let mut data = cursor(child, "texture coordinates");
+
+                    let count = data.count()?;
+                    mesh.uvs = (0..count).map(|_| data.vec2()).collect::<Result<_>>()?;
+                }
+                "MeshMaterialList" => {
+                    let mut data = cursor(child, "material list");
+
+                    let _material_count = data.count()?;
+                    let count = data.count()?;
+                    mesh.face_materials = (0..count)
+                        .map(|_| Ok(data.number()? as u32))
+                        .collect::<Result<_>>()?;
+
+                    for material in &child.children {
+                        let material = match material {
+                            Child::Object(material) => material,
+                            Child::Reference(name) => shared
+                                .get(name.as_str())
+                                .ok_or(Error::Malformed("material reference"))?,
+                        };
+
+                        mesh.materials.push(Material::from_object(material)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(mesh)
+    }
+}
+
+/// A row-major DirectX matrix, points are row vectors multiplied from the left.
+#[derive(Clone)]
+struct Matrix([f32; 16]);
+
+impl Matrix {
+    const IDENTITY: Self = Self([
+        1.0, 0.0, 0.0, 0.0, //
+        0.0, 1.0, 0.0, 0.0, //
+        0.0, 0.0, 1.0, 0.0, //
+        0.0, 0.0, 0.0, 1.0,
+    ]);
+
+    fn then(&self, parent: &Self) -> Self {
+        let (a, b) = (&self.0, &parent.0);
+        Self(std::array::from_fn(|i| {
+            let (row, col) = (i / 4, i % 4);
+            (0..4).map(|k| a[row * 4 + k] * b[k * 4 + col]).sum()
+        }))
+    }
+
+    fn point(&self, point: Vec3) -> Vec3 {
+        let [x, y, z]: [f32; 3] = to_array(point);
+        let m = &self.0;
+        from_array([
+            x * m[0] + y * m[4] + z * m[8] + m[12],
+            x * m[1] + y * m[5] + z * m[9] + m[13],
+            x * m[2] + y * m[6] + z * m[10] + m[14],
+        ])
+    }
+
+    fn normal(&self, normal: Vec3) -> Vec3 {
+        let [x, y, z]: [f32; 3] = to_array(normal);
+        let m = &self.0;
+        let n = [
+            x * m[0] + y * m[4] + z * m[8],
+            x * m[1] + y * m[5] + z * m[9],
+            x * m[2] + y * m[6] + z * m[10],
+        ];
+        let length = n.iter().map(|c| c * c).sum::<f32>().sqrt();
+        if length > 0.0 {
+            from_array(n.map(|c| c / length))
+        } else {
+            from_array(n)
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XFile {
+    pub(crate) meshes: Vec<Mesh>,
+}
+
+impl XFile {
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+
+        Self::parse(&mut bytes.as_slice())
+    }
+
+    /// Parses a `.x` file from any reader, the reader is consumed until EOF.
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        // xof 0302txt 0032
+        if bytes.len() < 16 || &bytes[..4] != b"xof " {
+            Err(Error::InvalidHeader)?
+        }
+
+        let double = match &bytes[12..16] {
+            b"0032" => false,
+            b"0064" => true,
+            _ => Err(Error::InvalidHeader)?,
+        };
+
+        let tokens = match &bytes[8..12] {
+            b"txt " => tokenize_text(&bytes[16..])?,
+            b"bin " => tokenize_binary(&bytes[16..], double)?,
+            b"tzip" | b"bzip" => Err(Error::Compressed)?,
+            _ => Err(Error::InvalidHeader)?,
+        };
+
+        let objects = Parser {
+            tokens,
+            position: 0,
+        }
+        .objects()?;
+
+        // top level objects can be referenced by name from anywhere
+        let shared: HashMap<&str, &Object> = objects
+            .iter()
+            .filter_map(|object| Some((object.name.as_deref()?, object)))
+            .collect();
+
+        let mut meshes = Vec::new();
+        for object in &objects {
+            collect_meshes(object, &Matrix::IDENTITY, &shared, &mut meshes)?;
+        }
+
+        Ok(Self { meshes })
+    }
+
+    /// Converts the accessory into a static PMX model.
+    ///
+    /// All meshes are merged, polygons are triangulated as fans and every vertex is bound to a single
+    /// root bone. Material textures are split into color and sphere textures like for PMD models.
+    pub fn to_pmx(&self, name: &str) -> Pmx {
+        let encoding = TextEncoding::UTF16LE;
+        let text = |text: &str| PmxText::new(text, encoding);
+
+        let mut vertices = Vec::new();
+        let mut surfaces = Vec::new();
+        let mut materials = Vec::new();
+        let mut textures: Vec<String> = Vec::new();
+
+        let mut texture_index = |path: Option<&str>| match path {
+            None => TextureIndex::nil(),
+            Some(path) => {
+                let index = textures.iter().position(|t| t == path).unwrap_or_else(|| {
+                    textures.push(path.to_string());
+                    textures.len() - 1
+                });
+                TextureIndex::new(index as i32)
+            }
+        };
+
+        for mesh in &self.meshes {
+            // corners are split into separate vertices only where their normals differ
+            let mut corner_vertices: HashMap<(u32, Option<u32>), i32> = HashMap::new();
+
+            let default_material = Material {
+                name: None,
+                diffuse: from_array([1.0, 1.0, 1.0, 1.0]),
+                power: 0.0,
+                specular: from_array([0.0; 3]),
+                emissive: from_array([0.5; 3]),
+                texture: None,
+            };
+            let mesh_materials: Vec<&Material> = if mesh.materials.is_empty() {
+                vec![&default_material]
+            } else {
+                mesh.materials.iter().collect()
+            };
+
+            for (material_index, material) in mesh_materials.iter().enumerate() {
+                let surface_start = surfaces.len();
+
+                for (face_index, face) in mesh.faces.iter().enumerate() {
+                    let face_material = mesh.face_materials.get(face_index).copied().unwrap_or(0);
+                    if face_material as usize != material_index {
+                        continue;
+                    }
+
+                    let normal_face = mesh
+                        .normal_faces
+                        .get(face_index)
+                        .filter(|normals| normals.len() == face.len());
+
+                    let mut corner = |k: usize| {
+                        let position = face[k];
+                        let normal = normal_face.map(|normals| normals[k]);
+
+                        *corner_vertices
+                            .entry((position, normal))
+                            .or_insert_with(|| {
+                                let pos = mesh
+                                    .positions
+                                    .get(position as usize)
+                                    .copied()
+                                    .unwrap_or_default();
+                                let normal = normal
+                                    .and_then(|normal| mesh.normals.get(normal as usize))
+                                    .copied()
+                                    .unwrap_or_else(|| face_normal(mesh, face));
+
+                                vertices.push(Vertex {
+                                    pos,
+                                    normal,
+                                    uv: mesh
+                                        .uvs
+                                        .get(position as usize)
+                                        .copied()
+                                        .unwrap_or_default(),
+                                    extra_vec4: None,
+                                    weight_deform: WeightDeform::Bdef1 {
+                                        index: BoneIndex::new(0),
+                                    },
+                                    edge_scale: 1.0,
+                                });
+
+                                vertices.len() as i32 - 1
+                            })
+                    };
+
+                    for k in 1..face.len().saturating_sub(1) {
+                        for corner_index in [0, k, k + 1] {
+                            surfaces.push(Surface {
+                                index: VertexIndex::new(corner(corner_index)),
+                            });
+                        }
+                    }
+                }
+
+                let sphere = material.sphere();
+                let env_blend = match sphere {
+                    Some(path) if path.to_ascii_lowercase().ends_with(".spa") => {
+                        EnvironmentBlend::Add
+                    }
+                    Some(_) => EnvironmentBlend::Multiply,
+                    None => EnvironmentBlend::None,
+                };
+
+                let index = materials.len() + 1;
+                let material_name = material
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("材質{index}"));
+
+                materials.push(PmxMaterial {
+                    name: Name {
+                        local: text(&material_name),
+                        universal: text(""),
+                    },
+                    diffuse: material.diffuse,
+                    specular: material.specular,
+                    specular_strength: material.power,
+                    ambient: material.emissive,
+                    flags: MaterialFlags::from_raw(
+                        MaterialFlags::GROUND_SHADOW
+                            | MaterialFlags::DRAW_SHADOW
+                            | MaterialFlags::RECEIVE_SHADOW,
+                    ),
+                    edge_color: from_array([0.0, 0.0, 0.0, 1.0]),
+                    edge_scale: 1.0,
+                    tex_idx: texture_index(material.texture()),
+                    env_idx: texture_index(sphere),
+                    env_blend,
+                    toon: Toon::Texture(TextureIndex::nil()),
+                    meta: text(""),
+                    surface_count: (surfaces.len() - surface_start) as i32,
+                });
+            }
+        }
+
+        let textures: Vec<Texture> = textures
+            .iter()
+            .map(|path| Texture { path: text(path) })
+            .collect();
+
+        let bones = vec![Bone {
+            name: Name {
+                local: text("センター"),
+                universal: text("center"),
+            },
+            position: from_array([0.0; 3]),
+            parent: BoneIndex::nil(),
+            layer: 0,
+            flags: BoneFlags::from_raw(
+                BoneFlags::ROTATABLE
+                    | BoneFlags::TRANSLATABLE
+                    | BoneFlags::VISIBLE
+                    | BoneFlags::ENABLED,
+            ),
+            tail: Tail::Position(from_array([0.0; 3])),
+            inherit: None,
+            fixed_axis: None,
+            local_axes: None,
+            external_parent: None,
+            ik: None,
+        }];
+
+        let display_frames = vec![
+            DisplayFrame {
+                name: Name {
+                    local: text("Root"),
+                    universal: text("Root"),
+                },
+                special: true,
+                entries: vec![FrameEntry::Bone(BoneIndex::new(0))],
+            },
+            DisplayFrame {
+                name: Name {
+                    local: text("表情"),
+                    universal: text("Exp"),
+                },
+                special: true,
+                entries: Vec::new(),
+            },
+        ];
+
+        let header = Header {
+            tag: *b"PMX ",
+            version: Version::V2_0,
+            raw_version: Version::V2_0.as_f32(),
+            globals: Globals {
+                encoding,
+                vec4_additional: 0,
+                vert_idx_size: IndexSize::smallest_for(vertices.len(), false),
+                tex_idx_size: IndexSize::smallest_for(textures.len(), true),
+                material_idx_size: IndexSize::smallest_for(materials.len(), true),
+                bone_idx_size: 1,
+                morph_idx_size: 1,
+                rb_idx_size: 1,
+                additional: None,
+            },
+            name: ModelName {
+                local: text(name),
+                universal: text(""),
+            },
+            comment: Comment {
+                local: text(""),
+                universal: text(""),
+            },
+        };
+
+        Pmx {
+            header,
+            vertices: vertices.into(),
+            surfaces: surfaces.into(),
+            textures: textures.into(),
+            materials: materials.into(),
+            bones: bones.into(),
+            morphs: Vec::new().into(),
+            display_frames: display_frames.into(),
+            rigid_bodies: Vec::new().into(),
+            joints: Vec::new().into(),
+            soft_bodies: None,
+            trailing: None,
+        }
+    }
+}
+
+fn collect_meshes(
+    object: &Object,
+    transform: &Matrix,
+    shared: &HashMap<&str, &Object>,
+    meshes: &mut Vec<Mesh>,
+) -> Result<()> {
+    match object.template.as_str() {
+        "Mesh" => meshes.push(Mesh::from_object(object, transform, shared)?),
+        "Frame" => {
+            let local = object
+                .children
+                .iter()
+                .find_map(|child| match child {
+                    Child::Object(o) if o.template == "FrameTransformMatrix" => Some(o),
+                    _ => None,
+                })
+                .map(|o| {
+                    let mut data = cursor(o, "frame transform");
+                    let mut matrix = [0.0; 16];
+                    for value in &mut matrix {
+                        *value = data.float()?;
+                    }
+                    Ok::<_, Error>(Matrix(matrix))
+                })
+                .transpose()?
+                .unwrap_or(Matrix::IDENTITY);
+
+            let transform = local.then(transform);
+
+            for child in &object.children {
+                if let Child::Object(child) = child {
+                    collect_meshes(child, &transform, shared, meshes)?;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Flat normal of a polygon, for meshes without normals.
+fn face_normal(mesh: &Mesh, face: &[u32]) -> Vec3 {
+    let point = |k: usize| -> [f32; 3] {
+        face.get(k)
+            .and_then(|&i| mesh.positions.get(i as usize))
+            .map_or([0.0; 3], |&p| to_array(p))
+    };
+
+    let (a, b, c) = (point(0), point(1), point(2));
+    let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+
+    let length = n.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if length > 0.0 {
+        from_array(n.map(|c| c / length))
+    } else {
+        from_array(n)
+    }
+}